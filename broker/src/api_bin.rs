@@ -2,24 +2,178 @@ use std::{
     net::SocketAddrV4,
     sync::Arc,
     thread::{self, JoinHandle},
+    time::Duration,
 };
 
 use crate::App;
 use log::info;
 use processing_thread_pool::ProcessingThreadPool;
-use pulsar_rust_net::sockets::buffer_pool::BufferPool;
+use pulsar_rust_net::{
+    data_types::ContractVersionNumber,
+    sockets::{
+        buffer_pool::{BufferPool, DEFAULT_MAX_BUFFER_CAPACITY, DEFAULT_MAX_POOLED_BUFFERS},
+        tcp_channel::DEFAULT_MAX_MESSAGE_SIZE,
+        tls::ConnectionSecurity,
+    },
+};
 
 mod connection;
+mod connection_quota;
 mod connection_thread;
+mod delivery_thread;
 mod listener_thread;
 mod processing_thread;
 mod processing_thread_pool;
+mod push_consumers;
 mod router_thread;
 mod server;
+mod watchdog;
+
+/// The lowest version of the API contracts that the binary API supports
+pub const MIN_CONTRACT_VERSION: ContractVersionNumber = 1;
+
+/// The highest version of the API contracts that the binary API supports
+pub const MAX_CONTRACT_VERSION: ContractVersionNumber = 2;
+
+/// Limits on the requests that the binary API will process. Size limits are checked after
+/// the request is deserialized, and oversize requests are rejected
+#[derive(Clone, Copy)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct RequestLimits {
+    /// The maximum total length of the message key, headers, attribute names and attribute values
+    pub max_publish_bytes: usize,
+
+    /// The maximum length of the message ref key in an ack or get message request
+    pub max_ack_bytes: usize,
+
+    /// The maximum length of the message ref key in a nack request
+    pub max_nack_bytes: usize,
+
+    /// The maximum total length of the message ref key and reason in a quarantine request
+    pub max_quarantine_bytes: usize,
+
+    /// The maximum length of the group name in a request to join a consumer group
+    pub max_join_group_bytes: usize,
+
+    /// The maximum number of messages in a batch publish request. Each message in the batch
+    /// is also subject to the `max_publish_bytes` limit
+    pub max_publish_batch_count: usize,
+
+    /// How long to wait for a request to be processed before abandoning it and returning a
+    /// timeout error to the client. Requests are processed without a timeout when this is None
+    pub processing_timeout: Option<Duration>,
+
+    /// How long to keep processing requests that were already received after the app begins
+    /// draining. The stop signal is set when there are no requests left, or when this elapses
+    pub drain_timeout: Duration,
+
+    /// The highest version of the API contracts that clients can negotiate. Lowering this
+    /// keeps clients on an older version, for example while a cluster is being upgraded
+    pub max_contract_version: ContractVersionNumber,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        Self {
+            max_publish_bytes: 512,
+            max_ack_bytes: 64,
+            max_nack_bytes: 64,
+            max_quarantine_bytes: 320,
+            max_join_group_bytes: 64,
+            max_publish_batch_count: 1000,
+            processing_timeout: Some(Duration::from_secs(10)),
+            drain_timeout: Duration::from_secs(30),
+            max_contract_version: MAX_CONTRACT_VERSION,
+        }
+    }
+}
+
+/// Limits on the connections that the binary API accepts from clients
+#[derive(Clone, Copy)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct ConnectionLimits {
+    /// The number of pending connections the operating system will queue before the
+    /// listener accepts them. Connections beyond this are refused by the operating system
+    pub listen_backlog: i32,
+
+    /// The maximum number of open connections from any one IP address. Connections from
+    /// an IP address that already has this many open connections are closed immediately
+    pub max_connections_per_ip: usize,
+
+    /// The maximum length of a serialized request or response. A client that sends a longer
+    /// request is disconnected, and responses that are longer are not sent
+    pub max_message_size: usize,
+
+    /// The maximum number of buffers of each size that are kept for reuse. Buffers returned
+    /// to a full pool are dropped, so that a burst of traffic does not hold on to memory
+    pub max_pooled_buffers: usize,
+
+    /// Buffers with more capacity than this are dropped instead of being kept for reuse
+    pub max_pooled_buffer_capacity: usize,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            listen_backlog: 128,
+            max_connections_per_ip: 64,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            max_pooled_buffers: DEFAULT_MAX_POOLED_BUFFERS,
+            max_pooled_buffer_capacity: DEFAULT_MAX_BUFFER_CAPACITY,
+        }
+    }
+}
 
 pub fn serve(app: &Arc<App>, addr: SocketAddrV4) -> JoinHandle<()> {
-    let buffer_pool = Arc::new(BufferPool::new());
-    let server_thread = ProcessingThreadPool::new(&app.stop_signal, &buffer_pool, &app, addr);
+    serve_with_limits(app, addr, RequestLimits::default())
+}
+
+pub fn serve_with_limits(
+    app: &Arc<App>,
+    addr: SocketAddrV4,
+    request_limits: RequestLimits,
+) -> JoinHandle<()> {
+    serve_with_connection_limits(app, addr, request_limits, ConnectionLimits::default())
+}
+
+pub fn serve_with_connection_limits(
+    app: &Arc<App>,
+    addr: SocketAddrV4,
+    request_limits: RequestLimits,
+    connection_limits: ConnectionLimits,
+) -> JoinHandle<()> {
+    serve_with_security(
+        app,
+        addr,
+        request_limits,
+        connection_limits,
+        ConnectionSecurity::Plain,
+    )
+}
+
+/// Serves the binary API with connections that are optionally encrypted with TLS. Clients
+/// must connect with matching security, otherwise their connection is closed
+pub fn serve_with_security(
+    app: &Arc<App>,
+    addr: SocketAddrV4,
+    request_limits: RequestLimits,
+    connection_limits: ConnectionLimits,
+    security: ConnectionSecurity,
+) -> JoinHandle<()> {
+    let buffer_pool = Arc::new(BufferPool::with_limits(
+        connection_limits.max_pooled_buffers,
+        connection_limits.max_pooled_buffer_capacity,
+    ));
+    app.metrics.internals().register_buffer_pool(&buffer_pool);
+    let server_thread = ProcessingThreadPool::new(
+        &app.stop_signal,
+        &buffer_pool,
+        &app,
+        addr,
+        request_limits,
+        connection_limits,
+        security,
+    );
     info!("Binary API listening on {addr}");
     thread::Builder::new()
         .name(String::from("bin-api-thread-pool"))
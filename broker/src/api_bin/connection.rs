@@ -1,48 +1,72 @@
 use std::{
     collections::HashMap,
-    net::TcpStream,
+    net::IpAddr,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU16, Ordering},
         mpsc::{channel, SendError, Sender},
         Arc, RwLock,
     },
     thread,
 };
 
+use crate::observability::internals::{ConnectionQueues, Internals};
 use log::info;
-use pulsar_rust_net::sockets::buffer_pool::BufferPool;
+use pulsar_rust_net::{
+    data_types::ContractVersionNumber,
+    sockets::{buffer_pool::BufferPool, tls::ChannelStream},
+};
 
 use super::{
+    connection_quota::ConnectionQuota,
     connection_thread::ConnectionThread,
-    server::{ConnectionId, ServerMessage},
+    server::{ConnectionId, RequestMessage, ServerMessage},
+    MIN_CONTRACT_VERSION,
 };
 
 pub(crate) struct Connection {
     sender: Sender<ServerMessage>,
+    queues: Arc<ConnectionQueues>,
     stop_signal: Arc<AtomicBool>,
+
+    /// The version of the API contracts that the client negotiated on this connection
+    contract_version: AtomicU16,
+
+    /// The maximum length of a message sent or received on this connection
+    max_message_size: usize,
 }
 
 impl Connection {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         buffer_pool: &Arc<BufferPool>,
+        internals: &Arc<Internals>,
         connections: &Arc<RwLock<HashMap<ConnectionId, Connection>>>,
-        request_sender: Sender<ServerMessage>,
+        connection_quota: &Arc<ConnectionQuota>,
+        request_sender: Sender<RequestMessage>,
         connection_id: ConnectionId,
-        stream: TcpStream,
+        stream: ChannelStream,
+        peer_ip: IpAddr,
+        max_message_size: usize,
     ) -> Self {
         info!("Connection: Created {connection_id}");
 
         let stop_signal = Arc::new(AtomicBool::new(false));
         let (response_sender, response_receiver) = channel::<ServerMessage>();
+        let queues = Arc::new(ConnectionQueues::default());
 
         let thread = ConnectionThread::new(
             response_receiver,
             request_sender.clone(),
             stream,
             &buffer_pool,
+            internals,
+            &queues,
             &stop_signal,
             &connections,
+            &connection_quota,
             connection_id,
+            peer_ip,
+            max_message_size,
         );
         thread::Builder::new()
             .name(String::from("bin-api-connection"))
@@ -51,10 +75,25 @@ impl Connection {
 
         Self {
             sender: response_sender,
+            queues,
             stop_signal: stop_signal.clone(),
+            contract_version: AtomicU16::new(MIN_CONTRACT_VERSION),
+            max_message_size,
         }
     }
 
+    pub(crate) fn contract_version(self: &Self) -> ContractVersionNumber {
+        self.contract_version.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_contract_version(self: &Self, version: ContractVersionNumber) {
+        self.contract_version.store(version, Ordering::Relaxed);
+    }
+
+    pub(crate) fn max_message_size(self: &Self) -> usize {
+        self.max_message_size
+    }
+
     pub(crate) fn stop(self: &Self) {
         self.stop_signal.store(true, Ordering::Relaxed);
     }
@@ -63,7 +102,14 @@ impl Connection {
         self: &Self,
         message: ServerMessage,
     ) -> Result<(), SendError<ServerMessage>> {
-        self.sender.send(message)
+        // Counted in before sending, because the connection thread can write the message
+        // and count it out before the send returns
+        self.queues.sent();
+        let result = self.sender.send(message);
+        if result.is_err() {
+            self.queues.written();
+        }
+        result
     }
 }
 
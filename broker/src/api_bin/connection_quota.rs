@@ -0,0 +1,90 @@
+/*
+Counts the open binary API connections from each peer IP address, so that a single client
+host can not exhaust the broker's threads by opening a large number of connections. The
+listener acquires a slot before it creates a connection, and the connection thread
+releases the slot when the connection closes.
+*/
+
+use std::{collections::HashMap, net::IpAddr, sync::Mutex};
+
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub(crate) struct ConnectionQuota {
+    max_connections_per_ip: usize,
+    counts: Mutex<HashMap<IpAddr, usize>>,
+}
+
+impl ConnectionQuota {
+    pub(crate) fn new(max_connections_per_ip: usize) -> Self {
+        Self {
+            max_connections_per_ip,
+            counts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts a new connection from the IP address and returns true, or returns false
+    /// without counting it if the IP address already has its quota of connections
+    pub(crate) fn try_acquire(self: &Self, ip: IpAddr) -> bool {
+        let mut counts = self.counts.lock().unwrap();
+        let count = counts.entry(ip).or_insert(0);
+        if *count >= self.max_connections_per_ip {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+
+    /// Stops counting a connection from the IP address after the connection was closed
+    pub(crate) fn release(self: &Self, ip: IpAddr) {
+        let mut counts = self.counts.lock().unwrap();
+        if let Some(count) = counts.get_mut(&ip) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&ip);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    fn count(self: &Self, ip: IpAddr) -> usize {
+        *self.counts.lock().unwrap().get(&ip).unwrap_or(&0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConnectionQuota;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    #[test]
+    fn should_limit_connections_from_each_ip() {
+        let quota = ConnectionQuota::new(3);
+        let busy_ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let other_ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+
+        for _ in 0..3 {
+            assert!(quota.try_acquire(busy_ip));
+        }
+        for _ in 0..10 {
+            assert!(!quota.try_acquire(busy_ip));
+        }
+        assert_eq!(quota.count(busy_ip), 3);
+
+        // Another IP address is unaffected by the one that is over its quota
+        for _ in 0..3 {
+            assert!(quota.try_acquire(other_ip));
+        }
+        assert!(!quota.try_acquire(other_ip));
+
+        // Closing a connection makes room for one more
+        quota.release(busy_ip);
+        assert_eq!(quota.count(busy_ip), 2);
+        assert!(quota.try_acquire(busy_ip));
+        assert!(!quota.try_acquire(busy_ip));
+
+        for _ in 0..3 {
+            quota.release(other_ip);
+        }
+        assert_eq!(quota.count(other_ip), 0);
+        assert_eq!(quota.count(busy_ip), 3);
+    }
+}
@@ -1,7 +1,7 @@
 use log::{info, warn};
 use std::{
     collections::HashMap,
-    net::TcpStream,
+    net::IpAddr,
     sync::{
         atomic::{AtomicBool, Ordering},
         mpsc::{channel, Receiver, Sender, TryRecvError},
@@ -11,11 +11,18 @@ use std::{
     time::{Duration, Instant},
 };
 
-use pulsar_rust_net::sockets::{buffer_pool::BufferPool, tcp_channel::TcpChannel};
+use pulsar_rust_net::sockets::{
+    buffer_pool::BufferPool,
+    tcp_channel::{ReceivedMessage, TcpChannel},
+    tls::ChannelStream,
+};
+
+use crate::observability::internals::{ConnectionQueues, Internals, THREAD_CONNECTION};
 
 use super::{
     connection::Connection,
-    server::{ConnectionId, ServerMessage},
+    connection_quota::ConnectionQuota,
+    server::{ConnectionId, RequestMessage, ServerMessage},
 };
 
 #[cfg(debug_assertions)]
@@ -28,36 +35,54 @@ const IDLE_SLEEP_DURATION: Duration = Duration::from_millis(10);
 pub(crate) struct ConnectionThread {
     connection_id: ConnectionId,
     response_receiver: Receiver<ServerMessage>,
-    request_sender: Sender<ServerMessage>,
-    tcp_request_receiver: Receiver<Vec<u8>>,
+    request_sender: Sender<RequestMessage>,
+    tcp_request_receiver: Receiver<ReceivedMessage>,
     tcp_response_sender: Sender<Vec<u8>>,
     _tcp_channel: TcpChannel,
     buffer_pool: Arc<BufferPool>,
+    internals: Arc<Internals>,
+    queues: Arc<ConnectionQueues>,
     stop_signal: Arc<AtomicBool>,
     connections: Arc<RwLock<HashMap<ConnectionId, Connection>>>,
+    connection_quota: Arc<ConnectionQuota>,
+    peer_ip: IpAddr,
     last_message_instant: Instant,
 }
 
 impl ConnectionThread {
     pub(super) fn new(
         receiver: Receiver<ServerMessage>,
-        sender: Sender<ServerMessage>,
-        stream: TcpStream,
+        sender: Sender<RequestMessage>,
+        stream: ChannelStream,
         buffer_pool: &Arc<BufferPool>,
+        internals: &Arc<Internals>,
+        queues: &Arc<ConnectionQueues>,
         stop_signal: &Arc<AtomicBool>,
         connections: &Arc<RwLock<HashMap<ConnectionId, Connection>>>,
+        connection_quota: &Arc<ConnectionQuota>,
         connection_id: ConnectionId,
+        peer_ip: IpAddr,
+        max_message_size: usize,
     ) -> Self {
         let (tcp_response_sender, tcp_receiver) = channel();
         let (tcp_sender, tcp_request_receiver) = channel();
 
-        stream.set_nonblocking(true).unwrap();
-        stream
+        let tcp_stream = stream.tcp_stream();
+        tcp_stream.set_nonblocking(true).unwrap();
+        tcp_stream
             .set_read_timeout(Some(Duration::from_millis(5)))
             .unwrap();
 
-        let tcp_channel =
-            TcpChannel::new(tcp_receiver, tcp_sender, stream, buffer_pool, stop_signal);
+        let tcp_channel = TcpChannel::new(
+            tcp_receiver,
+            tcp_sender,
+            stream,
+            buffer_pool,
+            stop_signal,
+            max_message_size,
+        );
+
+        internals.register_connection(connection_id, queues);
 
         Self {
             response_receiver: receiver,
@@ -66,8 +91,12 @@ impl ConnectionThread {
             tcp_response_sender,
             _tcp_channel: tcp_channel,
             buffer_pool: buffer_pool.clone(),
+            internals: internals.clone(),
+            queues: queues.clone(),
             stop_signal: stop_signal.clone(),
             connections: connections.clone(),
+            connection_quota: connection_quota.clone(),
+            peer_ip,
             connection_id,
             last_message_instant: Instant::now(),
         }
@@ -75,6 +104,7 @@ impl ConnectionThread {
 
     pub(crate) fn run(mut self: Self) {
         info!("ConnectionThread: Started");
+        let _running = self.internals.thread_started(THREAD_CONNECTION);
         while !self.stop_signal.load(Ordering::Relaxed) {
             self.try_send();
             self.try_receive();
@@ -84,6 +114,8 @@ impl ConnectionThread {
             .write()
             .unwrap()
             .remove(&self.connection_id);
+        self.connection_quota.release(self.peer_ip);
+        self.internals.deregister_connection(&self.queues);
         info!("ConnectionThread: Stopped");
     }
 
@@ -91,6 +123,8 @@ impl ConnectionThread {
         match self.response_receiver.try_recv() {
             Ok(message) => {
                 self.last_message_instant = Instant::now();
+                self.queues.written();
+                self.queues.responded();
                 #[cfg(debug_assertions)]
                 debug!("ConnectionThread: Received response from channel: {message:?}");
                 match self.tcp_response_sender.send(message.body) {
@@ -114,15 +148,17 @@ impl ConnectionThread {
                 self.last_message_instant = Instant::now();
                 #[cfg(debug_assertions)]
                 debug!("ConnectionThread: Received request from Tcp: {message:?}");
-                match self.request_sender.send(ServerMessage {
-                    body: message,
-                    connection_id: self.connection_id,
-                }) {
-                    Ok(_) => (),
-                    Err(err) => {
-                        self.fatal(&"Request sender channel disconnected");
-                        self.buffer_pool.reuse(err.0.body);
-                    }
+                self.queues.received();
+                if self
+                    .request_sender
+                    .send(ServerMessage {
+                        body: message,
+                        connection_id: self.connection_id,
+                    })
+                    .is_err()
+                {
+                    self.queues.responded();
+                    self.fatal(&"Request sender channel disconnected");
                 }
             }
             Err(e) => match e {
@@ -0,0 +1,235 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Arc, RwLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::{
+    connection::Connection,
+    push_consumers::{PushConsumer, PushConsumers},
+    server::{ConnectionId, ServerMessage},
+};
+use crate::{
+    observability::{internals::THREAD_DELIVERY, Metrics},
+    services::sub_service::SubError,
+    App, RunState,
+};
+use log::{error, info, warn};
+use pulsar_rust_net::{
+    bin_serialization::{BrokerResponse, ContractSerializer, ResponsePayload},
+    contracts::v1,
+    data_types::{CreditCount, MessageCount},
+    error_codes::ERROR_CODE_GENERAL_FAILURE,
+    sockets::buffer_pool::BufferPool,
+};
+
+const IDLE_LIMIT_DURATION: Duration = Duration::from_millis(50);
+const IDLE_SLEEP_DURATION: Duration = Duration::from_millis(10);
+
+/// Pushes messages to the consumers that registered with a subscribe request, for as long as
+/// they have credits. Messages are pushed through the same channel as the responses of the
+/// processing threads, so they are routed to the consumer's connection in the same way
+pub(crate) struct DeliveryThread {
+    app: Arc<App>,
+    stop_signal: Arc<AtomicBool>,
+    serializer: ContractSerializer,
+    sender: Arc<Sender<ServerMessage>>,
+    push_consumers: Arc<PushConsumers>,
+    connections: Arc<RwLock<HashMap<ConnectionId, Connection>>>,
+    last_delivery_instant: Instant,
+}
+
+impl DeliveryThread {
+    pub(crate) fn new(
+        app: &Arc<App>,
+        buffer_pool: &Arc<BufferPool>,
+        stop_signal: &Arc<AtomicBool>,
+        sender: &Arc<Sender<ServerMessage>>,
+        push_consumers: &Arc<PushConsumers>,
+        connections: &Arc<RwLock<HashMap<ConnectionId, Connection>>>,
+    ) -> Self {
+        Self {
+            app: app.clone(),
+            stop_signal: stop_signal.clone(),
+            serializer: ContractSerializer::new(buffer_pool),
+            sender: sender.clone(),
+            push_consumers: push_consumers.clone(),
+            connections: connections.clone(),
+            last_delivery_instant: Instant::now(),
+        }
+    }
+
+    pub(crate) fn run(mut self: Self) {
+        info!("DeliveryThread: Started");
+        let internals = self.app.metrics.internals().clone();
+        let _running = internals.thread_started(THREAD_DELIVERY);
+        while !self.stop_signal.load(Ordering::Relaxed) {
+            // Nothing new is pushed while draining, so that the connections can become idle
+            if self.app.run_state() == RunState::Running {
+                self.deliver();
+            }
+            self.sleep_if_idle();
+        }
+        info!("DeliveryThread: Stopped");
+    }
+
+    fn deliver(self: &mut Self) {
+        for consumer in self.push_consumers.all() {
+            if !self
+                .connections
+                .read()
+                .unwrap()
+                .contains_key(&consumer.connection_id)
+            {
+                self.disconnect(&consumer);
+                continue;
+            }
+
+            let credits = consumer.take_credits(MessageCount::MAX as CreditCount);
+            if credits == 0 {
+                continue;
+            }
+
+            let mut delivered = Vec::new();
+            let consumed = self.app.sub_service.consume_max_messages(
+                consumer.topic_id,
+                consumer.subscription_id,
+                Some(consumer.consumer_id),
+                credits as MessageCount,
+            );
+            let payload = match consumed {
+                Ok(messages) => {
+                    let unused = credits - messages.messages.len() as CreditCount;
+                    if unused > 0 {
+                        consumer.grant_credits(unused);
+                    }
+                    if messages.messages.is_empty() {
+                        continue;
+                    }
+                    delivered = messages
+                        .messages
+                        .iter()
+                        .map(|message| message.subscribed_message.message_ref_key.clone())
+                        .collect();
+                    self.app.metrics.count(
+                        Metrics::METRIC_BIN_DELIVERY_COUNT,
+                        messages.messages.len() as f64,
+                    );
+                    v1::responses::Response::success(v1::responses::ConsumeResult::from(&messages))
+                }
+                Err(err) => {
+                    // The consumer can not be delivered to, so the subscriber is told why and
+                    // nothing more is pushed to it
+                    self.push_consumers.remove(&consumer);
+                    match err {
+                        SubError::WrongNode(node) => v1::responses::Response::incorrect_node(
+                            &format!(
+                                "This node is not the owner of the partition, subscribe on {} instead",
+                                node.ip_address()
+                            ),
+                            node.pubsub_authority(),
+                        ),
+                        SubError::TopicNotFound => {
+                            v1::responses::Response::warning("Unknown topic ID")
+                        }
+                        SubError::SubscriptionNotFound => {
+                            v1::responses::Response::warning("Unknown subscription ID")
+                        }
+                        _ => v1::responses::Response::error(
+                            "Failed to deliver messages",
+                            ERROR_CODE_GENERAL_FAILURE,
+                        ),
+                    }
+                }
+            };
+            if !self.send(&consumer, payload) {
+                self.undeliver(&consumer, delivered);
+            }
+        }
+    }
+
+    /// Returns false if the delivery could not be serialized, in which case nothing was sent
+    fn send(
+        self: &mut Self,
+        consumer: &Arc<PushConsumer>,
+        payload: v1::responses::Response<v1::responses::ConsumeResult>,
+    ) -> bool {
+        self.last_delivery_instant = Instant::now();
+        let delivery =
+            BrokerResponse::new(consumer.request_id, ResponsePayload::V1Delivery(payload));
+        let body = match self
+            .serializer
+            .serialize_response_with_compression(&delivery, consumer.compression)
+        {
+            Ok(body) => body,
+            Err(err) => {
+                self.app
+                    .metrics
+                    .incr(Metrics::METRIC_BIN_SERIALIZE_ERROR_COUNT);
+                error!(
+                    "Failed to serialize delivery to consumer {} on {} connection. {:?}",
+                    consumer.consumer_id, consumer.connection_id, err
+                );
+                return false;
+            }
+        };
+        let message = ServerMessage {
+            body,
+            connection_id: consumer.connection_id,
+        };
+        if let Err(_) = self.sender.send(message) {
+            self.fatal("Failed to send delivery to the router");
+        }
+        true
+    }
+
+    /// The messages were consumed from the subscription but never sent, so they are nacked to
+    /// make them available for redelivery, and the credits that they used are given back
+    fn undeliver(self: &Self, consumer: &Arc<PushConsumer>, message_ref_keys: Vec<String>) {
+        let credits = message_ref_keys.len() as CreditCount;
+        for message_ref_key in message_ref_keys {
+            let _ = self.app.sub_service.nack(
+                message_ref_key,
+                consumer.subscription_id,
+                consumer.consumer_id,
+            );
+        }
+        if credits > 0 {
+            consumer.grant_credits(credits);
+        }
+    }
+
+    /// The connection that the consumer subscribed on was closed, so the consumer is
+    /// disconnected from the subscription and its unacked messages are redelivered
+    fn disconnect(self: &Self, consumer: &Arc<PushConsumer>) {
+        info!(
+            "DeliveryThread: Connection {} closed, disconnecting consumer {}",
+            consumer.connection_id, consumer.consumer_id
+        );
+        self.push_consumers.remove(consumer);
+        let _ = self.app.sub_service.disconnect_consumer(
+            consumer.topic_id,
+            consumer.subscription_id,
+            consumer.consumer_id,
+        );
+    }
+
+    fn sleep_if_idle(self: &Self) {
+        let idle_duration = self.last_delivery_instant.elapsed();
+        if idle_duration > IDLE_LIMIT_DURATION {
+            thread::sleep(IDLE_SLEEP_DURATION);
+        } else {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn fatal(self: &Self, msg: &str) {
+        warn!("DeliveryThread: {}", msg);
+        self.stop_signal.store(true, Ordering::Relaxed);
+    }
+}
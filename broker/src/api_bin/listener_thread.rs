@@ -1,8 +1,8 @@
 use std::{
     collections::HashMap,
-    net::{TcpListener, TcpStream},
+    net::{SocketAddr, TcpListener, TcpStream},
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU8, Ordering},
         mpsc::{Receiver, Sender},
         Arc, RwLock,
     },
@@ -11,34 +11,49 @@ use std::{
 
 use super::{
     connection::Connection,
+    connection_quota::ConnectionQuota,
     router_thread::RouterThread,
-    server::{ConnectionId, ServerMessage},
+    server::{ConnectionId, RequestMessage, ServerMessage},
+};
+use crate::{
+    observability::internals::{Internals, THREAD_LISTENER},
+    RunState,
 };
 use log::{info, warn};
-use pulsar_rust_net::sockets::buffer_pool::BufferPool;
+use pulsar_rust_net::sockets::{buffer_pool::BufferPool, tls::ConnectionSecurity};
 
 /// A thread that owns a Tcp listener, accepts connections to a listener and spawns a thread to handle
 /// each client that connects.
 pub(crate) struct ListenerThread {
-    request_sender: Sender<ServerMessage>,
+    request_sender: Sender<RequestMessage>,
     listener: TcpListener,
     buffer_pool: Arc<BufferPool>,
+    internals: Arc<Internals>,
+    run_state: Arc<AtomicU8>,
     stop_signal: Arc<AtomicBool>,
     next_connection_id: ConnectionId,
     connections: Arc<RwLock<HashMap<ConnectionId, Connection>>>,
+    connection_quota: Arc<ConnectionQuota>,
+    max_message_size: usize,
+    security: ConnectionSecurity,
 }
 
 impl ListenerThread {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         response_receiver: Receiver<ServerMessage>,
-        request_sender: Sender<ServerMessage>,
+        request_sender: Sender<RequestMessage>,
         listener: TcpListener,
+        connections: &Arc<RwLock<HashMap<ConnectionId, Connection>>>,
         buffer_pool: &Arc<BufferPool>,
+        internals: &Arc<Internals>,
+        run_state: &Arc<AtomicU8>,
         stop_signal: &Arc<AtomicBool>,
+        max_connections_per_ip: usize,
+        max_message_size: usize,
+        security: ConnectionSecurity,
     ) -> Self {
-        let connections = Arc::new(RwLock::new(HashMap::new()));
-
-        let router = RouterThread::new(response_receiver, stop_signal, &connections);
+        let router = RouterThread::new(response_receiver, internals, stop_signal, connections);
         thread::Builder::new()
             .name(String::from("bin-api-router"))
             .spawn(move || router.run())
@@ -48,19 +63,25 @@ impl ListenerThread {
             request_sender,
             listener,
             buffer_pool: buffer_pool.clone(),
+            internals: internals.clone(),
+            run_state: run_state.clone(),
             stop_signal: stop_signal.clone(),
             next_connection_id: 1,
-            connections,
+            connections: connections.clone(),
+            connection_quota: Arc::new(ConnectionQuota::new(max_connections_per_ip)),
+            max_message_size,
+            security,
         }
     }
 
     /// This method owns Self so that when this function exits the data will be dropped
     pub(crate) fn run(mut self: Self) {
         info!("ListenerThread: Started");
+        let _running = self.internals.thread_started(THREAD_LISTENER);
 
         while !self.stop_signal.load(Ordering::Relaxed) {
             match self.listener.accept() {
-                Ok((stream, _address)) => self.handle_connection(stream),
+                Ok((stream, address)) => self.handle_connection(stream, address),
                 Err(e) => self.fatal(&format!("{e}")),
             }
         }
@@ -74,17 +95,45 @@ impl ListenerThread {
         info!("ListenerThread: Stopped");
     }
 
-    fn handle_connection(self: &mut Self, stream: TcpStream) {
+    fn handle_connection(self: &mut Self, stream: TcpStream, address: SocketAddr) {
+        if RunState::from(self.run_state.load(Ordering::Relaxed)) != RunState::Running {
+            // Dropping the stream closes the connection
+            info!("ListenerThread: Rejected connection from {address} while draining");
+            return;
+        }
+
+        if !self.connection_quota.try_acquire(address.ip()) {
+            // Dropping the stream closes the connection
+            warn!(
+                "ListenerThread: Rejected connection from {} which has too many open connections",
+                address.ip()
+            );
+            return;
+        }
+
+        let stream = match self.security.wrap(stream) {
+            Ok(stream) => stream,
+            Err(err) => {
+                warn!("ListenerThread: Failed to secure connection from {address}. {err}");
+                self.connection_quota.release(address.ip());
+                return;
+            }
+        };
+
         let connection_id = self.next_connection_id;
         self.next_connection_id += 1;
 
         info!("ListenerThread: A client connected. Id={connection_id}");
         let connection = Connection::new(
             &self.buffer_pool,
+            &self.internals,
             &self.connections,
+            &self.connection_quota,
             self.request_sender.clone(),
             connection_id,
             stream,
+            address.ip(),
+            self.max_message_size,
         );
 
         self.connections
@@ -1,24 +1,47 @@
-use core::time::Duration;
-
 use std::{
+    collections::HashMap,
     sync::{
-        atomic::{AtomicBool, Ordering},
-        mpsc::{Receiver, Sender, TryRecvError},
-        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex, RwLock,
     },
-    thread,
-    time::Instant,
+    time::Duration,
 };
 
-use super::server::ServerMessage;
-use crate::App;
+use super::{
+    connection::Connection,
+    push_consumers::{PushConsumer, PushConsumers},
+    server::{ConnectionId, RequestMessage, ServerMessage},
+    watchdog::RequestSlot,
+};
+use crate::{
+    api_bin::{RequestLimits, MIN_CONTRACT_VERSION},
+    model::messages::PublishedMessage,
+    observability::{
+        internals::{self, THREAD_PROCESSING},
+        Metrics,
+    },
+    services::{
+        pub_service::PubError,
+        sub_service::{ResponseFrame, SubError},
+    },
+    App,
+};
 use log::{error, info, warn};
 use pulsar_rust_net::{
-    bin_serialization::{BrokerResponse, ContractSerializer, RequestPayload, ResponsePayload},
-    contracts::v1::{self, responses::MessageRef},
+    bin_serialization::{
+        BrokerResponse, CompressionScheme, ContractSerializer, RequestId, RequestPayload,
+        ResponsePayload,
+    },
+    contracts::v1::{
+        self,
+        requests::{MessageHeaders, NegotiateVersion, PublishAckLevel},
+    },
+    data_types::{ContractVersionNumber, ErrorCode},
     error_codes::{
-        ERROR_CODE_BACKLOG_FULL, ERROR_CODE_GENERAL_FAILURE, ERROR_CODE_INCORRECT_NODE,
-        ERROR_CODE_NO_COMPATIBLE_VERSION,
+        ERROR_CODE_BACKLOG_FULL, ERROR_CODE_DUPLICATE_SEQUENCE, ERROR_CODE_GENERAL_FAILURE,
+        ERROR_CODE_NO_COMPATIBLE_VERSION, ERROR_CODE_REQUEST_TOO_LARGE, ERROR_CODE_TIMEOUT,
+        ERROR_CODE_TOO_MANY_CONSUMERS,
     },
     sockets::buffer_pool::BufferPool,
 };
@@ -26,6 +49,9 @@ use pulsar_rust_net::{
 #[cfg(debug_assertions)]
 use log::debug;
 
+/// How long the thread waits for a request before checking the stop signal again
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
 /// Receives requests from a mpsc channel, and processes the request to produce a reply,
 /// postig the reply into another mpsc channel.
 pub(crate) struct ProcessingThread {
@@ -33,116 +59,222 @@ pub(crate) struct ProcessingThread {
     stop_signal: Arc<AtomicBool>,
     serializer: ContractSerializer,
     sender: Arc<Sender<ServerMessage>>,
-    receiver: Receiver<ServerMessage>,
-    last_message_instant: Instant,
+    receiver: Arc<Mutex<Receiver<RequestMessage>>>,
+    queue_depth: Arc<AtomicUsize>,
+    push_consumers: Arc<PushConsumers>,
+    connections: Arc<RwLock<HashMap<ConnectionId, Connection>>>,
+    request_limits: RequestLimits,
+    request_slot: Option<Arc<RequestSlot>>,
+    abandoned: bool,
+}
+
+/// Identifies the connection and request that a request came from, so that messages can be
+/// pushed to consumers that subscribe on the same connection
+#[derive(Clone, Copy)]
+struct RequestOrigin {
+    connection_id: ConnectionId,
+    request_id: RequestId,
+    compression: CompressionScheme,
 }
 
 impl ProcessingThread {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         app: &Arc<App>,
         buffer_pool: &Arc<BufferPool>,
         stop_signal: &Arc<AtomicBool>,
         sender: &Arc<Sender<ServerMessage>>,
-        receiver: Receiver<ServerMessage>,
+        receiver: &Arc<Mutex<Receiver<RequestMessage>>>,
+        queue_depth: &Arc<AtomicUsize>,
+        push_consumers: &Arc<PushConsumers>,
+        connections: &Arc<RwLock<HashMap<ConnectionId, Connection>>>,
+        request_limits: RequestLimits,
+        request_slot: Option<&Arc<RequestSlot>>,
     ) -> Self {
         Self {
             app: app.clone(),
             stop_signal: stop_signal.clone(),
             serializer: ContractSerializer::new(buffer_pool),
             sender: sender.clone(),
-            receiver,
-            last_message_instant: Instant::now(),
+            receiver: receiver.clone(),
+            queue_depth: queue_depth.clone(),
+            push_consumers: push_consumers.clone(),
+            connections: connections.clone(),
+            request_limits,
+            request_slot: request_slot.cloned(),
+            abandoned: false,
         }
     }
 
     pub(crate) fn run(mut self: Self) {
         info!("ProcessingThread: Started");
-        while !self.stop_signal.load(Ordering::Relaxed) {
+        let internals = self.app.metrics.internals().clone();
+        let _running = internals.thread_started(THREAD_PROCESSING);
+        while !self.stop_signal.load(Ordering::Relaxed) && !self.abandoned {
             self.try_process();
-            self.sleep_if_idle();
         }
         info!("ProcessingThread: Stopped");
     }
 
     fn try_process(self: &mut Self) {
-        match self.receiver.try_recv() {
+        // The receiver is shared with the thread that replaces this one if a request is
+        // abandoned, so it is only locked while waiting for a request
+        let received = self
+            .receiver
+            .lock()
+            .unwrap()
+            .recv_timeout(STOP_CHECK_INTERVAL);
+        match received {
             Ok(request_message) => {
-                self.last_message_instant = Instant::now();
+                internals::decrement(&self.queue_depth);
                 #[cfg(debug_assertions)]
                 debug!(
                     "ProcessingThread: processing request {request_message:?} from {}",
                     request_message.connection_id
                 );
 
+                // Responses are compressed the same way as the request, so that each client
+                // receives the compression that it negotiated
+                let compression = self.serializer.request_compression(&request_message.body);
+
                 match self.serializer.deserialize_request(request_message.body) {
                     Ok(request) => {
                         #[cfg(debug_assertions)]
                         debug!(
-                            "ProcessingThread: Received {:?} from connection {}",
-                            request, request_message.connection_id
+                            "ProcessingThread: Received {:?} from session {} on connection {}",
+                            request, request.session_id, request_message.connection_id
                         );
                         let request_id = request.request_id;
+                        let origin = RequestOrigin {
+                            connection_id: request_message.connection_id,
+                            request_id,
+                            compression,
+                        };
+                        if let Some(request_slot) = &self.request_slot {
+                            request_slot.start(
+                                request_message.connection_id,
+                                request_id,
+                                compression,
+                                error_response(
+                                    &request.payload,
+                                    "Request was abandoned because it took too long to process",
+                                    ERROR_CODE_TIMEOUT,
+                                ),
+                            );
+                        }
+                        let mut has_deferred_publish = false;
                         let response_payload = match request.payload {
+                            ref payload if self.is_oversize(payload) => oversize_response(payload),
                             RequestPayload::NegotiateVersion(negotiate_version) => {
-                                if negotiate_version.min_version == 1 {
-                                    ResponsePayload::NegotiateVersion(
-                                        v1::responses::Response::success(
-                                            v1::responses::NegotiateVersionResult { version: 1 },
-                                        ),
-                                    )
-                                } else {
-                                    ResponsePayload::NegotiateVersion(
+                                match self.choose_version(&negotiate_version) {
+                                    Some(version) => {
+                                        self.set_contract_version(origin, version);
+                                        ResponsePayload::NegotiateVersion(
+                                            v1::responses::Response::success(
+                                                v1::responses::NegotiateVersionResult {
+                                                    version,
+                                                    compression: CompressionScheme::choose(
+                                                        &negotiate_version.compression,
+                                                    ),
+                                                },
+                                            ),
+                                        )
+                                    }
+                                    None => ResponsePayload::NegotiateVersion(
                                         v1::responses::Response::error(
-                                            "Maximum supported API version is 1",
+                                            &format!(
+                                                "Supported API versions are {MIN_CONTRACT_VERSION} to {}",
+                                                self.request_limits.max_contract_version
+                                            ),
                                             ERROR_CODE_NO_COMPATIBLE_VERSION,
                                         ),
-                                    )
+                                    ),
                                 }
                             }
                             RequestPayload::V1Publish(v1_publish) => {
-                                let publish_message = v1_publish.into();
-                                match self.app.pub_service.publish_message(publish_message) {
-                                    Ok(message_ref) => {
-                                        ResponsePayload::V1Publish(v1::responses::Response::success(
-                                            v1::responses::PublishResult {
-                                                message_ref: message_ref.into(),
-                                            },
-                                        ))
-                                    }
-                                    Err(err) => match err {
-                                        crate::services::pub_service::PubError::Error(msg) =>
-                                            ResponsePayload::V1Publish(v1::responses::Response::error(&msg, ERROR_CODE_GENERAL_FAILURE)),
-                                        crate::services::pub_service::PubError::TopicNotFound =>
-                                            ResponsePayload::V1Publish(v1::responses::Response::warning("Unknown topic ID")),
-                                        crate::services::pub_service::PubError::PartitionNotFound =>
-                                            ResponsePayload::V1Publish(v1::responses::Response::warning("Unknown partition ID")),
-                                        crate::services::pub_service::PubError::NodeNotFound =>
-                                            ResponsePayload::V1Publish(v1::responses::Response::warning("Unknown node for this partition")),
-                                        crate::services::pub_service::PubError::WrongNode(entity_ref) =>
-                                            ResponsePayload::V1Publish(v1::responses::Response::error(&format!("This node is not the owner of the partition, publish to {} instead", entity_ref.ip_address()), ERROR_CODE_INCORRECT_NODE)),
-                                        crate::services::pub_service::PubError::BacklogCapacityExceeded =>
-                                            ResponsePayload::V1Publish(v1::responses::Response::error("Backlog capacity exceeded", ERROR_CODE_BACKLOG_FULL)),
-                                        crate::services::pub_service::PubError::NoSubscribers =>
-                                            ResponsePayload::V1Publish(v1::responses::Response::warning("No subscribers to this topic")),
-                                    }
+                                has_deferred_publish =
+                                    v1_publish.ack_level == PublishAckLevel::None;
+                                ResponsePayload::V1Publish(
+                                    self.publish(v1_publish.ack_level, v1_publish.into()),
+                                )
+                            }
+                            RequestPayload::V2Publish(v2_publish) => {
+                                if self.contract_version(origin) < 2 {
+                                    ResponsePayload::V1Publish(v1::responses::Response::error(
+                                        "Version 2 publish requests need version 2 of the API to be negotiated",
+                                        ERROR_CODE_NO_COMPATIBLE_VERSION,
+                                    ))
+                                } else {
+                                    has_deferred_publish =
+                                        v2_publish.ack_level == PublishAckLevel::None;
+                                    ResponsePayload::V1Publish(
+                                        self.publish(v2_publish.ack_level, v2_publish.into()),
+                                    )
                                 }
                             }
+                            RequestPayload::V1PublishBatch(v1_publish_batch) => {
+                                has_deferred_publish =
+                                    v1_publish_batch.messages.iter().any(|v1_publish| {
+                                        v1_publish.ack_level == PublishAckLevel::None
+                                    });
+                                let results = v1_publish_batch
+                                    .messages
+                                    .into_iter()
+                                    .map(|v1_publish| {
+                                        self.publish(v1_publish.ack_level, v1_publish.into())
+                                    })
+                                    .collect();
+                                ResponsePayload::V1PublishBatch(v1::responses::Response::success(
+                                    v1::responses::PublishBatchResult { results },
+                                ))
+                            }
                             RequestPayload::V1Consume(v1_consume) => {
                                 let topic_id = v1_consume.topic_id;
                                 let subscription_id = v1_consume.subscription_id;
                                 let consumer_id = v1_consume.consumer_id;
                                 let max_messages = v1_consume.max_messages;
-                                match self.app.sub_service.consume_max_messages(
-                                    topic_id,
-                                    subscription_id,
-                                    consumer_id,
-                                    max_messages,
-                                ) {
+                                let max_bytes = v1_consume.max_bytes;
+                                let frame = self.response_frame(origin);
+                                let consumed = if v1_consume.ack_previous {
+                                    self.app.sub_service.consume_and_ack_previous(
+                                        topic_id,
+                                        subscription_id,
+                                        consumer_id,
+                                        max_messages,
+                                        max_bytes,
+                                        frame,
+                                    )
+                                } else {
+                                    self.app.sub_service.consume_into_frame(
+                                        topic_id,
+                                        subscription_id,
+                                        consumer_id,
+                                        max_messages,
+                                        max_bytes,
+                                        frame,
+                                    )
+                                };
+                                match consumed {
                                     Ok(messages) => ResponsePayload::V1Consume(
                                         v1::responses::Response::success(
                                             v1::responses::ConsumeResult::from(&messages),
                                         ),
                                     ),
+                                    Err(SubError::WrongNode(node)) => ResponsePayload::V1Consume(
+                                        v1::responses::Response::incorrect_node(
+                                            &format!(
+                                                "This node is not the owner of the partition, consume from {} instead",
+                                                node.ip_address()
+                                            ),
+                                            node.pubsub_authority(),
+                                        ),
+                                    ),
+                                    Err(SubError::TooManyConsumers) => {
+                                        ResponsePayload::V1Consume(v1::responses::Response::error(
+                                            "The subscription already has the maximum number of consumers",
+                                            ERROR_CODE_TOO_MANY_CONSUMERS,
+                                        ))
+                                    }
                                     Err(_) => {
                                         ResponsePayload::V1Consume(v1::responses::Response::error(
                                             "Failed to allocate consumer id",
@@ -151,6 +283,30 @@ impl ProcessingThread {
                                     }
                                 }
                             }
+                            RequestPayload::V1Subscribe(v1_subscribe) => {
+                                ResponsePayload::V1Subscribe(self.subscribe(origin, v1_subscribe))
+                            }
+                            RequestPayload::V1Ping(ping) => pong(ping),
+                            RequestPayload::V1Flow(v1_flow) => {
+                                match self.push_consumers.grant_credits(
+                                    origin.connection_id,
+                                    v1_flow.topic_id,
+                                    v1_flow.subscription_id,
+                                    v1_flow.consumer_id,
+                                    v1_flow.credits,
+                                ) {
+                                    Some(credits) => {
+                                        ResponsePayload::V1Flow(v1::responses::Response::success(
+                                            v1::responses::FlowResult { credits },
+                                        ))
+                                    }
+                                    None => {
+                                        ResponsePayload::V1Flow(v1::responses::Response::warning(
+                                            "Consumer is not subscribed on this connection",
+                                        ))
+                                    }
+                                }
+                            }
                             RequestPayload::V1Ack(v1_ack) => {
                                 let message_ack_key = v1_ack.message_ref_key;
                                 let subscription_id = v1_ack.subscription_id;
@@ -169,6 +325,15 @@ impl ProcessingThread {
                                             "Message was already acknowledged",
                                         )
                                     }),
+                                    Err(SubError::WrongNode(node)) => ResponsePayload::V1Ack(
+                                        v1::responses::Response::incorrect_node(
+                                            &format!(
+                                                "This node is not the owner of the partition, ack on {} instead",
+                                                node.ip_address()
+                                            ),
+                                            node.pubsub_authority(),
+                                        ),
+                                    ),
                                     Err(_) => {
                                         ResponsePayload::V1Ack(v1::responses::Response::error(
                                             "Failed to ack message",
@@ -195,6 +360,15 @@ impl ProcessingThread {
                                             "Message was already acknowledged",
                                         )
                                     }),
+                                    Err(SubError::WrongNode(node)) => ResponsePayload::V1Nack(
+                                        v1::responses::Response::incorrect_node(
+                                            &format!(
+                                                "This node is not the owner of the partition, nack on {} instead",
+                                                node.ip_address()
+                                            ),
+                                            node.pubsub_authority(),
+                                        ),
+                                    ),
                                     Err(_) => {
                                         ResponsePayload::V1Nack(v1::responses::Response::error(
                                             "Failed to nack message",
@@ -203,17 +377,242 @@ impl ProcessingThread {
                                     }
                                 }
                             }
+                            RequestPayload::V1Quarantine(v1_quarantine) => {
+                                match self.app.sub_service.quarantine(
+                                    v1_quarantine.message_ref_key,
+                                    v1_quarantine.subscription_id,
+                                    v1_quarantine.consumer_id,
+                                    &v1_quarantine.reason,
+                                ) {
+                                    Ok(success) => ResponsePayload::V1Quarantine(if success {
+                                        v1::responses::Response::success(
+                                            v1::responses::QuarantineResult { success: true },
+                                        )
+                                    } else {
+                                        v1::responses::Response::warning(
+                                            "Message was already acknowledged",
+                                        )
+                                    }),
+                                    Err(SubError::WrongNode(node)) => {
+                                        ResponsePayload::V1Quarantine(
+                                            v1::responses::Response::incorrect_node(
+                                                &format!(
+                                                    "This node is not the owner of the partition, quarantine on {} instead",
+                                                    node.ip_address()
+                                                ),
+                                                node.pubsub_authority(),
+                                            ),
+                                        )
+                                    }
+                                    Err(_) => ResponsePayload::V1Quarantine(
+                                        v1::responses::Response::error(
+                                            "Failed to quarantine message",
+                                            ERROR_CODE_GENERAL_FAILURE,
+                                        ),
+                                    ),
+                                }
+                            }
+                            RequestPayload::V1JoinGroup(v1_join_group) => {
+                                match self.app.sub_service.join_group(
+                                    v1_join_group.topic_id,
+                                    v1_join_group.subscription_id,
+                                    &v1_join_group.group_name,
+                                ) {
+                                    Ok(member) => ResponsePayload::V1JoinGroup(
+                                        v1::responses::Response::success(
+                                            v1::responses::JoinGroupResult {
+                                                subscription_id: member.subscription_id,
+                                                consumer_id: member.consumer_id,
+                                            },
+                                        ),
+                                    ),
+                                    Err(SubError::WrongNode(node)) => ResponsePayload::V1JoinGroup(
+                                        v1::responses::Response::incorrect_node(
+                                            &format!(
+                                                "This node is not the owner of the partition, join on {} instead",
+                                                node.ip_address()
+                                            ),
+                                            node.pubsub_authority(),
+                                        ),
+                                    ),
+                                    Err(SubError::TooManyConsumers) => {
+                                        ResponsePayload::V1JoinGroup(
+                                            v1::responses::Response::error(
+                                                "The subscription already has the maximum number of consumers",
+                                                ERROR_CODE_TOO_MANY_CONSUMERS,
+                                            ),
+                                        )
+                                    }
+                                    Err(_) => ResponsePayload::V1JoinGroup(
+                                        v1::responses::Response::error(
+                                            "Failed to join consumer group",
+                                            ERROR_CODE_GENERAL_FAILURE,
+                                        ),
+                                    ),
+                                }
+                            }
+                            RequestPayload::V1LeaveGroup(v1_leave_group) => {
+                                match self.app.sub_service.leave_group(
+                                    v1_leave_group.topic_id,
+                                    v1_leave_group.subscription_id,
+                                    v1_leave_group.consumer_id,
+                                ) {
+                                    Ok(success) => ResponsePayload::V1LeaveGroup(if success {
+                                        v1::responses::Response::success(
+                                            v1::responses::LeaveGroupResult { success: true },
+                                        )
+                                    } else {
+                                        v1::responses::Response::warning(
+                                            "Consumer is not a member of a group",
+                                        )
+                                    }),
+                                    Err(_) => ResponsePayload::V1LeaveGroup(
+                                        v1::responses::Response::error(
+                                            "Failed to leave consumer group",
+                                            ERROR_CODE_GENERAL_FAILURE,
+                                        ),
+                                    ),
+                                }
+                            }
+                            RequestPayload::V1DisconnectConsumer(v1_disconnect_consumer) => {
+                                match self.app.sub_service.disconnect_consumer(
+                                    v1_disconnect_consumer.topic_id,
+                                    v1_disconnect_consumer.subscription_id,
+                                    v1_disconnect_consumer.consumer_id,
+                                ) {
+                                    Ok(()) => ResponsePayload::V1DisconnectConsumer(
+                                        v1::responses::Response::success(
+                                            v1::responses::DisconnectConsumerResult {
+                                                success: true,
+                                            },
+                                        ),
+                                    ),
+                                    Err(_) => ResponsePayload::V1DisconnectConsumer(
+                                        v1::responses::Response::error(
+                                            "Failed to disconnect consumer",
+                                            ERROR_CODE_GENERAL_FAILURE,
+                                        ),
+                                    ),
+                                }
+                            }
+                            RequestPayload::V1GetMessage(v1_get_message) => {
+                                match self
+                                    .app
+                                    .sub_service
+                                    .get_message(&v1_get_message.message_ref_key)
+                                {
+                                    Ok(message) => ResponsePayload::V1GetMessage(
+                                        v1::responses::Response::success(
+                                            v1::responses::Message::from(&message),
+                                        ),
+                                    ),
+                                    Err(SubError::MessageNotFound) => {
+                                        ResponsePayload::V1GetMessage(
+                                            v1::responses::Response::warning(
+                                                "No message found with this message id. The message may have been acked by all subscriptions",
+                                            ),
+                                        )
+                                    }
+                                    Err(SubError::WrongNode(node)) => {
+                                        ResponsePayload::V1GetMessage(
+                                            v1::responses::Response::incorrect_node(
+                                                &format!(
+                                                    "This node is not the owner of the partition, get the message from {} instead",
+                                                    node.ip_address()
+                                                ),
+                                                node.pubsub_authority(),
+                                            ),
+                                        )
+                                    }
+                                    Err(_) => ResponsePayload::V1GetMessage(
+                                        v1::responses::Response::error(
+                                            "Failed to get message",
+                                            ERROR_CODE_GENERAL_FAILURE,
+                                        ),
+                                    ),
+                                }
+                            }
+                            RequestPayload::V1GetPartitions(v1_get_partitions) => {
+                                match self
+                                    .app
+                                    .admin_service
+                                    .topic_by_id(v1_get_partitions.topic_id)
+                                {
+                                    Some(topic) => ResponsePayload::V1GetPartitions(
+                                        v1::responses::Response::success(
+                                            v1::responses::PartitionList::from(topic.partitions()),
+                                        ),
+                                    ),
+                                    None => ResponsePayload::V1GetPartitions(
+                                        v1::responses::Response::warning("Unknown topic ID"),
+                                    ),
+                                }
+                            }
+                            RequestPayload::V1ListTopics(_) => ResponsePayload::V1ListTopics(
+                                v1::responses::Response::success(v1::responses::TopicList::from(
+                                    self.app.admin_service.all_topics(),
+                                )),
+                            ),
+                            RequestPayload::V1GetTopicPartitionMap(get_map) => {
+                                match self.app.admin_service.topic_by_id(get_map.topic_id) {
+                                    Some(topic) => {
+                                        let mut map =
+                                            v1::responses::TopicPartitionMap::from(&topic);
+                                        map.nodes = self
+                                            .app
+                                            .admin_service
+                                            .all_nodes()
+                                            .values()
+                                            .iter()
+                                            .map(|node| v1::responses::NodeDetail::from(node))
+                                            .collect();
+                                        ResponsePayload::V1GetTopicPartitionMap(
+                                            v1::responses::Response::success(map),
+                                        )
+                                    }
+                                    None => ResponsePayload::V1GetTopicPartitionMap(
+                                        v1::responses::Response::warning("Unknown topic ID"),
+                                    ),
+                                }
+                            }
                         };
+
+                        // The watchdog already responded with a timeout error if it abandoned
+                        // the request, and started another thread to take over from this one
+                        if let Some(request_slot) = &self.request_slot {
+                            if !request_slot.finish() {
+                                warn!(
+                                    "ProcessingThread: Discarded the response to abandoned request {} from connection {}",
+                                    request_id, request_message.connection_id
+                                );
+                                if has_deferred_publish {
+                                    self.app.pub_service.flush_publishes();
+                                }
+                                self.abandoned = true;
+                                return;
+                            }
+                        }
+
                         let serialization_response =
                             BrokerResponse::new(request_id, response_payload);
+                        let body = match self.serializer.serialize_response_with_compression(
+                            &serialization_response,
+                            compression,
+                        ) {
+                            Ok(body) => body,
+                            Err(err) => {
+                                self.app
+                                    .metrics
+                                    .incr(Metrics::METRIC_BIN_SERIALIZE_ERROR_COUNT);
+                                error!(
+                                    "Failed to serialize response to {} on {} connection. {:?}",
+                                    request_id, request_message.connection_id, err
+                                );
+                                return;
+                            }
+                        };
                         let response_message = ServerMessage {
-                            body: self
-                                .serializer
-                                .serialize_response(&serialization_response)
-                                .expect(&format!(
-                                    "Failed to serialize response to {} on {} connection",
-                                    request_id, request_message.connection_id
-                                )),
+                            body,
                             connection_id: request_message.connection_id,
                         };
                         if let Err(_) = self.sender.send(response_message) {
@@ -222,8 +621,17 @@ impl ProcessingThread {
                                 request_id, request_message.connection_id
                             ));
                         }
+
+                        // The publisher has been told that the message was published, so now
+                        // the message can be logged and added to subscriptions
+                        if has_deferred_publish {
+                            self.app.pub_service.flush_publishes();
+                        }
                     }
                     Err(err) => {
+                        self.app
+                            .metrics
+                            .incr(Metrics::METRIC_BIN_DESERIALIZE_ERROR_COUNT);
                         error!(
                             "Failed to deserialize request from connection {}. {:?}",
                             request_message.connection_id, err
@@ -232,21 +640,298 @@ impl ProcessingThread {
                 };
             }
             Err(e) => match e {
-                TryRecvError::Empty => thread::sleep(Duration::from_millis(1)),
-                TryRecvError::Disconnected => self.fatal("Receive channel disconnected"),
+                RecvTimeoutError::Timeout => {}
+                RecvTimeoutError::Disconnected => self.fatal("Receive channel disconnected"),
             },
         }
     }
 
-    fn sleep_if_idle(self: &Self) {
-        let idle_duration = self.last_message_instant.elapsed();
-        if idle_duration > Duration::from_millis(50) {
-            thread::sleep(Duration::from_millis(10));
+    /// Allocates a consumer if the request does not have one, and registers it to have messages
+    /// pushed to it on the connection that the request came from
+    fn subscribe(
+        self: &Self,
+        origin: RequestOrigin,
+        v1_subscribe: v1::requests::Subscribe,
+    ) -> v1::responses::Response<v1::responses::SubscribeResult> {
+        let topic_id = v1_subscribe.topic_id;
+        let subscription_id = v1_subscribe.subscription_id;
+        match self.app.sub_service.consume_max_messages(
+            topic_id,
+            subscription_id,
+            v1_subscribe.consumer_id,
+            0,
+        ) {
+            Ok(consumed) => {
+                self.push_consumers.subscribe(PushConsumer::new(
+                    origin.connection_id,
+                    origin.request_id,
+                    origin.compression,
+                    topic_id,
+                    subscription_id,
+                    consumed.consumer_id,
+                    v1_subscribe.credits,
+                ));
+                v1::responses::Response::success(v1::responses::SubscribeResult {
+                    consumer_id: consumed.consumer_id,
+                })
+            }
+            Err(SubError::WrongNode(node)) => v1::responses::Response::incorrect_node(
+                &format!(
+                    "This node is not the owner of the partition, subscribe on {} instead",
+                    node.ip_address()
+                ),
+                node.pubsub_authority(),
+            ),
+            Err(SubError::TooManyConsumers) => v1::responses::Response::error(
+                "The subscription already has the maximum number of consumers",
+                ERROR_CODE_TOO_MANY_CONSUMERS,
+            ),
+            Err(SubError::TopicNotFound) => v1::responses::Response::warning("Unknown topic ID"),
+            Err(SubError::SubscriptionNotFound) => {
+                v1::responses::Response::warning("Unknown subscription ID")
+            }
+            Err(_) => v1::responses::Response::error(
+                "Failed to allocate consumer id",
+                ERROR_CODE_GENERAL_FAILURE,
+            ),
         }
     }
 
+    /// Chooses the highest version of the API contracts that both the client and this broker
+    /// support, or None if the ranges do not overlap
+    fn choose_version(
+        self: &Self,
+        negotiate_version: &NegotiateVersion,
+    ) -> Option<ContractVersionNumber> {
+        let min_version = negotiate_version.min_version.max(MIN_CONTRACT_VERSION);
+        let max_version = negotiate_version
+            .max_version
+            .min(self.request_limits.max_contract_version);
+        if min_version <= max_version {
+            Some(max_version)
+        } else {
+            None
+        }
+    }
+
+    /// The version of the API contracts that was negotiated on the connection a request came from
+    fn contract_version(self: &Self, origin: RequestOrigin) -> ContractVersionNumber {
+        self.connections
+            .read()
+            .unwrap()
+            .get(&origin.connection_id)
+            .map_or(MIN_CONTRACT_VERSION, Connection::contract_version)
+    }
+
+    /// Remembers the version of the API contracts that was negotiated on a connection, so that
+    /// later requests on the connection can be checked against it
+    fn set_contract_version(self: &Self, origin: RequestOrigin, version: ContractVersionNumber) {
+        if let Some(connection) = self.connections.read().unwrap().get(&origin.connection_id) {
+            connection.set_contract_version(version);
+        }
+    }
+
+    /// The frame that a consume response is sent to the consumer in, so that the batch can be
+    /// sized to fit the connection
+    fn response_frame(self: &Self, origin: RequestOrigin) -> Option<ResponseFrame> {
+        self.connections
+            .read()
+            .unwrap()
+            .get(&origin.connection_id)
+            .map(|connection| ResponseFrame {
+                max_size: connection.max_message_size(),
+                compression: origin.compression,
+            })
+    }
+
+    /// Publishes one message, returning the response to send back to the publisher
+    fn publish(
+        self: &Self,
+        ack_level: PublishAckLevel,
+        publish_message: PublishedMessage,
+    ) -> v1::responses::Response<v1::responses::PublishResult> {
+        match self
+            .app
+            .pub_service
+            .publish_message_with_ack_level(publish_message, ack_level)
+        {
+            Ok(message_ref) => v1::responses::Response::success(
+                v1::responses::PublishResult::from(&self.app.pub_service.receipt(message_ref)),
+            ),
+            Err(err) => match err {
+                PubError::Error(msg) => {
+                    v1::responses::Response::error(&msg, ERROR_CODE_GENERAL_FAILURE)
+                }
+                PubError::TopicNotFound => v1::responses::Response::warning("Unknown topic ID"),
+                PubError::PartitionNotFound => {
+                    v1::responses::Response::warning("Unknown partition ID")
+                }
+                PubError::NodeNotFound => {
+                    v1::responses::Response::warning("Unknown node for this partition")
+                }
+                PubError::WrongNode(entity_ref) => v1::responses::Response::incorrect_node(
+                    &format!(
+                        "This node is not the owner of the partition, publish to {} instead",
+                        entity_ref.ip_address()
+                    ),
+                    entity_ref.pubsub_authority(),
+                ),
+                PubError::BacklogCapacityExceeded => v1::responses::Response::error(
+                    "Backlog capacity exceeded",
+                    ERROR_CODE_BACKLOG_FULL,
+                ),
+                PubError::NoSubscribers => {
+                    v1::responses::Response::warning("No subscribers to this topic")
+                }
+                PubError::NoLedger => v1::responses::Response::error(
+                    "No ledger is available for this partition",
+                    ERROR_CODE_GENERAL_FAILURE,
+                ),
+                PubError::DuplicateSequenceNumber => v1::responses::Response::error(
+                    "This sequence number was already published by this producer",
+                    ERROR_CODE_DUPLICATE_SEQUENCE,
+                ),
+            },
+        }
+    }
+
+    /// Returns true if the variable length content of a request exceeds the limit for its type
+    fn is_oversize(self: &Self, payload: &RequestPayload) -> bool {
+        match payload {
+            RequestPayload::NegotiateVersion(_) => false,
+            RequestPayload::V1Publish(publish) => {
+                self.is_oversize_publish(&publish.key, &publish.attributes, &publish.headers)
+            }
+            RequestPayload::V2Publish(publish) => {
+                self.is_oversize_publish(&publish.key, &publish.attributes, &publish.headers)
+            }
+            RequestPayload::V1PublishBatch(publish_batch) => {
+                publish_batch.messages.len() > self.request_limits.max_publish_batch_count
+                    || publish_batch.messages.iter().any(|publish| {
+                        self.is_oversize_publish(
+                            &publish.key,
+                            &publish.attributes,
+                            &publish.headers,
+                        )
+                    })
+            }
+            RequestPayload::V1Consume(_) => false,
+            RequestPayload::V1Subscribe(_) => false,
+            RequestPayload::V1Flow(_) => false,
+            RequestPayload::V1Ping(_) => false,
+            RequestPayload::V1Ack(ack) => {
+                ack.message_ref_key.len() > self.request_limits.max_ack_bytes
+            }
+            RequestPayload::V1Nack(nack) => {
+                nack.message_ref_key.len() > self.request_limits.max_nack_bytes
+            }
+            RequestPayload::V1Quarantine(quarantine) => {
+                quarantine.message_ref_key.len() + quarantine.reason.len()
+                    > self.request_limits.max_quarantine_bytes
+            }
+            RequestPayload::V1JoinGroup(join_group) => {
+                join_group.group_name.len() > self.request_limits.max_join_group_bytes
+            }
+            RequestPayload::V1LeaveGroup(_) => false,
+            RequestPayload::V1DisconnectConsumer(_) => false,
+            RequestPayload::V1GetMessage(get_message) => {
+                get_message.message_ref_key.len() > self.request_limits.max_ack_bytes
+            }
+            RequestPayload::V1GetPartitions(_) => false,
+            RequestPayload::V1ListTopics(_) => false,
+            RequestPayload::V1GetTopicPartitionMap(_) => false,
+        }
+    }
+
+    /// Returns true if the variable length content of a message exceeds the publish limit
+    fn is_oversize_publish(
+        self: &Self,
+        key: &str,
+        attributes: &HashMap<String, String>,
+        headers: &MessageHeaders,
+    ) -> bool {
+        let attributes_size: usize = attributes
+            .iter()
+            .map(|(name, value)| name.len() + value.len())
+            .sum();
+        key.len() + attributes_size + headers.byte_count() > self.request_limits.max_publish_bytes
+    }
+
     fn fatal(self: &Self, msg: &str) {
         warn!("ProcessingThread: {}", msg);
         self.stop_signal.store(true, Ordering::Relaxed);
     }
 }
+
+/// Answers a ping without touching any of the services, so that it only checks the connection
+fn pong(ping: v1::requests::Ping) -> ResponsePayload {
+    ResponsePayload::V1Pong(v1::responses::Response::success(v1::responses::Pong {
+        timestamp: ping.timestamp,
+    }))
+}
+
+/// Constructs an error response of the right type for a request that is too large
+fn oversize_response(payload: &RequestPayload) -> ResponsePayload {
+    error_response(
+        payload,
+        "Request exceeds the maximum size for this type of request",
+        ERROR_CODE_REQUEST_TOO_LARGE,
+    )
+}
+
+/// Constructs an error response of the right type for a request
+fn error_response(payload: &RequestPayload, msg: &str, error_code: ErrorCode) -> ResponsePayload {
+    match payload {
+        RequestPayload::NegotiateVersion(_) => {
+            ResponsePayload::NegotiateVersion(v1::responses::Response::error(msg, error_code))
+        }
+        RequestPayload::V1Publish(_) | RequestPayload::V2Publish(_) => {
+            ResponsePayload::V1Publish(v1::responses::Response::error(msg, error_code))
+        }
+        RequestPayload::V1PublishBatch(_) => {
+            ResponsePayload::V1PublishBatch(v1::responses::Response::error(msg, error_code))
+        }
+        RequestPayload::V1Consume(_) => {
+            ResponsePayload::V1Consume(v1::responses::Response::error(msg, error_code))
+        }
+        RequestPayload::V1Subscribe(_) => {
+            ResponsePayload::V1Subscribe(v1::responses::Response::error(msg, error_code))
+        }
+        RequestPayload::V1Flow(_) => {
+            ResponsePayload::V1Flow(v1::responses::Response::error(msg, error_code))
+        }
+        RequestPayload::V1Ping(_) => {
+            ResponsePayload::V1Pong(v1::responses::Response::error(msg, error_code))
+        }
+        RequestPayload::V1Ack(_) => {
+            ResponsePayload::V1Ack(v1::responses::Response::error(msg, error_code))
+        }
+        RequestPayload::V1Nack(_) => {
+            ResponsePayload::V1Nack(v1::responses::Response::error(msg, error_code))
+        }
+        RequestPayload::V1Quarantine(_) => {
+            ResponsePayload::V1Quarantine(v1::responses::Response::error(msg, error_code))
+        }
+        RequestPayload::V1JoinGroup(_) => {
+            ResponsePayload::V1JoinGroup(v1::responses::Response::error(msg, error_code))
+        }
+        RequestPayload::V1LeaveGroup(_) => {
+            ResponsePayload::V1LeaveGroup(v1::responses::Response::error(msg, error_code))
+        }
+        RequestPayload::V1DisconnectConsumer(_) => {
+            ResponsePayload::V1DisconnectConsumer(v1::responses::Response::error(msg, error_code))
+        }
+        RequestPayload::V1GetMessage(_) => {
+            ResponsePayload::V1GetMessage(v1::responses::Response::error(msg, error_code))
+        }
+        RequestPayload::V1GetPartitions(_) => {
+            ResponsePayload::V1GetPartitions(v1::responses::Response::error(msg, error_code))
+        }
+        RequestPayload::V1ListTopics(_) => {
+            ResponsePayload::V1ListTopics(v1::responses::Response::error(msg, error_code))
+        }
+        RequestPayload::V1GetTopicPartitionMap(_) => {
+            ResponsePayload::V1GetTopicPartitionMap(v1::responses::Response::error(msg, error_code))
+        }
+    }
+}
@@ -3,21 +3,28 @@ use core::time::Duration;
 use std::{
     net::SocketAddrV4,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicUsize, Ordering},
         mpsc::{channel, Sender, TryRecvError},
-        Arc,
+        Arc, Mutex,
     },
     thread::{self, available_parallelism},
     time::Instant,
 };
 
-use crate::App;
+use crate::{
+    api_bin::{ConnectionLimits, RequestLimits},
+    observability::internals::{self, THREAD_PROCESSING_POOL},
+    App, RunState,
+};
 use log::{info, warn};
-use pulsar_rust_net::sockets::buffer_pool::BufferPool;
+use pulsar_rust_net::sockets::{buffer_pool::BufferPool, tls::ConnectionSecurity};
 
 use super::{
+    delivery_thread::DeliveryThread,
     processing_thread::ProcessingThread,
-    server::{Server, ServerMessage},
+    push_consumers::PushConsumers,
+    server::{RequestMessage, Server},
+    watchdog::{RequestSlot, Watchdog},
 };
 
 const IDLE_LIMIT_DURATION: Duration = Duration::from_millis(50);
@@ -28,8 +35,12 @@ pub(crate) struct ProcessingThreadPool {
     app: Arc<App>,
     authority: String,
     buffer_pool: Arc<BufferPool>,
+    request_limits: RequestLimits,
+    connection_limits: ConnectionLimits,
+    security: ConnectionSecurity,
     last_message_instant: Instant,
     next_thread_index: usize,
+    drain_deadline: Option<Instant>,
 }
 
 /// Owns a server, Receives requests from a server's rx channel and distributes them to a pool of
@@ -40,25 +51,44 @@ impl ProcessingThreadPool {
         buffer_pool: &Arc<BufferPool>,
         app: &Arc<App>,
         addr: SocketAddrV4,
+        request_limits: RequestLimits,
+        connection_limits: ConnectionLimits,
+        security: ConnectionSecurity,
     ) -> Self {
         Self {
             stop_signal: stop_signal.clone(),
             app: app.clone(),
             authority: format!("{}:{}", addr.ip(), addr.port()),
             buffer_pool: buffer_pool.clone(),
+            request_limits,
+            connection_limits,
+            security,
             last_message_instant: Instant::now(),
             next_thread_index: 0,
+            drain_deadline: None,
         }
     }
 
     pub(crate) fn run(mut self: Self) {
         info!("ProcessingThreadPool: Started");
-
-        let server = Server::new(&self.buffer_pool, &self.authority);
-        let request_senders = self.create_threads(&server.sender());
+        let internals = self.app.metrics.internals().clone();
+        let _running = internals.thread_started(THREAD_PROCESSING_POOL);
+
+        let server = Server::new(
+            &self.buffer_pool,
+            &internals,
+            &self.app.run_state,
+            &self.authority,
+            self.connection_limits,
+            self.security.clone(),
+        );
+        let push_consumers = Arc::new(PushConsumers::new());
+        let request_senders = self.create_threads(&server, &push_consumers);
+        self.create_delivery_thread(&server, &push_consumers);
 
         while !self.stop_signal.load(Ordering::Relaxed) {
             self.try_process(&server, &request_senders);
+            self.stop_if_drained();
             self.sleep_if_idle();
         }
 
@@ -67,35 +97,104 @@ impl ProcessingThreadPool {
 
     fn create_threads(
         self: &Self,
-        response_sender: &Arc<Sender<ServerMessage>>,
-    ) -> Vec<Sender<ServerMessage>> {
+        server: &Server,
+        push_consumers: &Arc<PushConsumers>,
+    ) -> Vec<(Sender<RequestMessage>, Arc<AtomicUsize>)> {
+        let response_sender = &server.sender();
         let cpus = available_parallelism()
             .expect("ProcessingThreadPool: Can't get number of CPUs")
             .get();
-        let mut request_senders: Vec<Sender<ServerMessage>> = Vec::with_capacity(cpus);
-
-        for _ in 0..cpus {
-            let (request_sender, request_receiver) = channel::<ServerMessage>();
-            request_senders.push(request_sender);
+        let mut request_senders: Vec<(Sender<RequestMessage>, Arc<AtomicUsize>)> =
+            Vec::with_capacity(cpus);
 
-            let processing_thread = ProcessingThread::new(
+        // When requests have a processing timeout, the processing threads are started by a
+        // watchdog so that it can replace any thread that it abandons
+        let mut watchdog = self.request_limits.processing_timeout.map(|timeout| {
+            Watchdog::new(
                 &self.app,
                 &self.buffer_pool,
                 &self.stop_signal,
                 response_sender,
-                request_receiver,
-            );
-            thread::spawn(move || processing_thread.run());
+                timeout,
+            )
+        });
+
+        for _ in 0..cpus {
+            let (request_sender, request_receiver) = channel::<RequestMessage>();
+            let request_receiver = Arc::new(Mutex::new(request_receiver));
+            let queue_depth = self.app.metrics.internals().register_processing_queue();
+            request_senders.push((request_sender, queue_depth.clone()));
+
+            let app = self.app.clone();
+            let buffer_pool = self.buffer_pool.clone();
+            let stop_signal = self.stop_signal.clone();
+            let response_sender = response_sender.clone();
+            let push_consumers = push_consumers.clone();
+            let connections = server.connections().clone();
+            let request_limits = self.request_limits;
+            let start_thread = move |request_slot: Option<&Arc<RequestSlot>>| {
+                let processing_thread = ProcessingThread::new(
+                    &app,
+                    &buffer_pool,
+                    &stop_signal,
+                    &response_sender,
+                    &request_receiver,
+                    &queue_depth,
+                    &push_consumers,
+                    &connections,
+                    request_limits,
+                    request_slot,
+                );
+                thread::spawn(move || processing_thread.run());
+            };
+            match &mut watchdog {
+                Some(watchdog) => watchdog.watch(Box::new(move |request_slot| {
+                    start_thread(Some(request_slot))
+                })),
+                None => start_thread(None),
+            }
+        }
+
+        if let Some(watchdog) = watchdog {
+            thread::Builder::new()
+                .name(String::from("bin-api-watchdog"))
+                .spawn(move || watchdog.run())
+                .unwrap();
         }
 
         request_senders
     }
 
-    fn try_process(self: &mut Self, server: &Server, request_senders: &Vec<Sender<ServerMessage>>) {
+    fn create_delivery_thread(self: &Self, server: &Server, push_consumers: &Arc<PushConsumers>) {
+        let delivery_thread = DeliveryThread::new(
+            &self.app,
+            &self.buffer_pool,
+            &self.stop_signal,
+            &server.sender(),
+            push_consumers,
+            server.connections(),
+        );
+        thread::Builder::new()
+            .name(String::from("bin-api-delivery"))
+            .spawn(move || delivery_thread.run())
+            .unwrap();
+    }
+
+    fn try_process(
+        self: &mut Self,
+        server: &Server,
+        request_senders: &Vec<(Sender<RequestMessage>, Arc<AtomicUsize>)>,
+    ) {
         match server.try_recv() {
             Ok(message) => {
                 self.last_message_instant = Instant::now();
-                request_senders[self.next_thread_index].send(message).ok();
+                let (request_sender, queue_depth) = &request_senders[self.next_thread_index];
+                // Counted in before sending, because the processing thread can take the
+                // message and count it out before the send returns
+                internals::increment(queue_depth);
+                if request_sender.send(message).is_err() {
+                    internals::decrement(queue_depth);
+                }
                 self.next_thread_index = if self.next_thread_index == 0 {
                     request_senders.len() - 1
                 } else {
@@ -109,6 +208,25 @@ impl ProcessingThreadPool {
         }
     }
 
+    /// Once the app begins draining, requests that were already received continue to be
+    /// processed. The app is stopped when they have all been responded to, or when the drain
+    /// timeout elapses
+    fn stop_if_drained(self: &mut Self) {
+        if self.app.run_state() != RunState::Draining {
+            return;
+        }
+        let drain_deadline = *self
+            .drain_deadline
+            .get_or_insert_with(|| Instant::now() + self.request_limits.drain_timeout);
+        if self.app.metrics.internals().is_idle() {
+            info!("ProcessingThreadPool: Drained");
+            self.app.stop();
+        } else if Instant::now() >= drain_deadline {
+            warn!("ProcessingThreadPool: Stopping before all requests were drained");
+            self.app.stop();
+        }
+    }
+
     fn sleep_if_idle(self: &Self) {
         let idle_duration = self.last_message_instant.elapsed();
         if idle_duration > IDLE_LIMIT_DURATION {
@@ -0,0 +1,202 @@
+/*
+Consumers that register with a subscribe request have messages pushed to them as they become
+available, instead of polling with consume requests. Each consumer has a number of credits,
+and each message that is pushed to it uses one. Credits are taken before messages are
+consumed from the subscription, and any that were not used are given back afterwards, so a
+consumer is never sent more messages than it has credits for, even when more credits are
+granted while a delivery is in progress.
+*/
+
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc, RwLock,
+};
+
+use pulsar_rust_net::{
+    bin_serialization::{CompressionScheme, RequestId},
+    data_types::{ConsumerId, CreditCount, SubscriptionId, TopicId},
+};
+
+use super::server::ConnectionId;
+
+pub(crate) struct PushConsumer {
+    pub connection_id: ConnectionId,
+
+    /// Messages are pushed with the request id of the subscribe request, so that the client
+    /// can route them to the subscriber
+    pub request_id: RequestId,
+
+    /// Messages are pushed with the compression that the subscribe request was sent with
+    pub compression: CompressionScheme,
+
+    pub topic_id: TopicId,
+    pub subscription_id: SubscriptionId,
+    pub consumer_id: ConsumerId,
+    credits: AtomicU32,
+}
+
+impl PushConsumer {
+    pub(crate) fn new(
+        connection_id: ConnectionId,
+        request_id: RequestId,
+        compression: CompressionScheme,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+        credits: CreditCount,
+    ) -> Self {
+        Self {
+            connection_id,
+            request_id,
+            compression,
+            topic_id,
+            subscription_id,
+            consumer_id,
+            credits: AtomicU32::new(credits),
+        }
+    }
+
+    /// Takes up to `max_credits` credits from the consumer, returning the number taken
+    pub(crate) fn take_credits(self: &Self, max_credits: CreditCount) -> CreditCount {
+        match self
+            .credits
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |credits| {
+                if credits == 0 {
+                    None
+                } else {
+                    Some(credits - credits.min(max_credits))
+                }
+            }) {
+            Ok(credits) => credits.min(max_credits),
+            Err(_) => 0,
+        }
+    }
+
+    /// Adds credits to the consumer, returning the number of credits that it has now
+    pub(crate) fn grant_credits(self: &Self, credits: CreditCount) -> CreditCount {
+        let previous = self
+            .credits
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                Some(current.saturating_add(credits))
+            })
+            .unwrap();
+        previous.saturating_add(credits)
+    }
+}
+
+/// The push consumers of every connection. This is shared by the processing threads, which
+/// register consumers and grant them credits, and the delivery thread that pushes messages
+pub(crate) struct PushConsumers {
+    consumers: RwLock<Vec<Arc<PushConsumer>>>,
+}
+
+impl PushConsumers {
+    pub(crate) fn new() -> Self {
+        Self {
+            consumers: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Registers a push consumer, replacing any earlier registration of the same consumer
+    /// on the same connection
+    pub(crate) fn subscribe(self: &Self, consumer: PushConsumer) {
+        let mut consumers = self.consumers.write().unwrap();
+        consumers.retain(|registered| {
+            !(registered.connection_id == consumer.connection_id
+                && registered.topic_id == consumer.topic_id
+                && registered.subscription_id == consumer.subscription_id
+                && registered.consumer_id == consumer.consumer_id)
+        });
+        consumers.push(Arc::new(consumer));
+    }
+
+    /// Grants credits to a consumer that was registered on this connection. Returns the
+    /// number of credits that the consumer has now, or None if it is not registered
+    pub(crate) fn grant_credits(
+        self: &Self,
+        connection_id: ConnectionId,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+        credits: CreditCount,
+    ) -> Option<CreditCount> {
+        self.consumers
+            .read()
+            .unwrap()
+            .iter()
+            .find(|consumer| {
+                consumer.connection_id == connection_id
+                    && consumer.topic_id == topic_id
+                    && consumer.subscription_id == subscription_id
+                    && consumer.consumer_id == consumer_id
+            })
+            .map(|consumer| consumer.grant_credits(credits))
+    }
+
+    pub(crate) fn all(self: &Self) -> Vec<Arc<PushConsumer>> {
+        self.consumers.read().unwrap().clone()
+    }
+
+    pub(crate) fn remove(self: &Self, consumer: &Arc<PushConsumer>) {
+        self.consumers
+            .write()
+            .unwrap()
+            .retain(|registered| !Arc::ptr_eq(registered, consumer));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PushConsumer, PushConsumers};
+    use pulsar_rust_net::bin_serialization::CompressionScheme;
+    use std::{sync::Arc, thread};
+
+    fn push_consumer(credits: u32) -> PushConsumer {
+        PushConsumer::new(1, 10, CompressionScheme::None, 2, 3, 4, credits)
+    }
+
+    #[test]
+    fn should_not_take_more_credits_than_the_consumer_has() {
+        let consumer = push_consumer(5);
+        assert_eq!(consumer.take_credits(3), 3);
+        assert_eq!(consumer.take_credits(3), 2);
+        assert_eq!(consumer.take_credits(3), 0);
+        assert_eq!(consumer.grant_credits(4), 4);
+        assert_eq!(consumer.take_credits(10), 4);
+    }
+
+    #[test]
+    fn should_take_each_credit_once_across_threads() {
+        let consumer = Arc::new(push_consumer(10_000));
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let consumer = consumer.clone();
+                thread::spawn(move || {
+                    let mut taken = 0;
+                    loop {
+                        match consumer.take_credits(7) {
+                            0 => break taken,
+                            credits => taken += credits,
+                        }
+                    }
+                })
+            })
+            .collect();
+        let taken: u32 = threads.into_iter().map(|t| t.join().unwrap()).sum();
+        assert_eq!(taken, 10_000);
+        assert_eq!(consumer.take_credits(1), 0);
+    }
+
+    #[test]
+    fn should_only_grant_credits_on_the_subscribing_connection() {
+        let consumers = PushConsumers::new();
+        consumers.subscribe(push_consumer(1));
+        assert_eq!(consumers.grant_credits(1, 2, 3, 4, 5), Some(6));
+        assert_eq!(consumers.grant_credits(2, 2, 3, 4, 5), None);
+
+        // Subscribing again replaces the consumer along with its credits
+        consumers.subscribe(push_consumer(2));
+        assert_eq!(consumers.all().len(), 1);
+        assert_eq!(consumers.all()[0].take_credits(10), 2);
+    }
+}
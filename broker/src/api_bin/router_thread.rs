@@ -13,6 +13,7 @@ use super::{
     connection::Connection,
     server::{ConnectionId, ServerMessage},
 };
+use crate::observability::internals::{Internals, THREAD_ROUTER};
 use log::{info, warn};
 
 #[cfg(debug_assertions)]
@@ -24,6 +25,7 @@ const IDLE_SLEEP_DURATION: Duration = Duration::from_millis(10);
 pub(crate) struct RouterThread {
     connections: Arc<RwLock<HashMap<ConnectionId, Connection>>>,
     receiver: Receiver<ServerMessage>,
+    internals: Arc<Internals>,
     stop_signal: Arc<AtomicBool>,
     last_message_instant: Instant,
 }
@@ -31,12 +33,14 @@ pub(crate) struct RouterThread {
 impl RouterThread {
     pub(crate) fn new(
         receiver: Receiver<ServerMessage>,
+        internals: &Arc<Internals>,
         stop_signal: &Arc<AtomicBool>,
         connections: &Arc<RwLock<HashMap<ConnectionId, Connection>>>,
     ) -> Self {
         Self {
             receiver,
             connections: connections.clone(),
+            internals: internals.clone(),
             stop_signal: stop_signal.clone(),
             last_message_instant: Instant::now(),
         }
@@ -44,6 +48,7 @@ impl RouterThread {
 
     pub(crate) fn run(mut self: Self) {
         info!("Router: Started");
+        let _running = self.internals.thread_started(THREAD_ROUTER);
         while !self.stop_signal.load(Ordering::Relaxed) {
             self.try_route();
             self.sleep_if_idle();
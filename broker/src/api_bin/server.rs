@@ -1,12 +1,20 @@
-use crate::api_bin::listener_thread::ListenerThread;
+use crate::{
+    api_bin::{connection::Connection, listener_thread::ListenerThread, ConnectionLimits},
+    observability::internals::Internals,
+};
 use log::info;
-use pulsar_rust_net::sockets::buffer_pool::BufferPool;
+use pulsar_rust_net::sockets::{
+    buffer_pool::BufferPool, tcp_channel::ReceivedMessage, tls::ConnectionSecurity,
+};
+use socket2::{Domain, Protocol, Socket, Type};
 use std::{
-    net::{TcpListener, TcpStream},
+    collections::HashMap,
+    io,
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU8, Ordering},
         mpsc::{channel, Receiver, Sender, TryRecvError},
-        Arc,
+        Arc, RwLock,
     },
     thread::{self},
 };
@@ -14,15 +22,20 @@ use std::{
 pub(crate) type ConnectionId = u32;
 
 #[derive(Debug)]
-pub(crate) struct ServerMessage {
+pub(crate) struct ServerMessage<T = Vec<u8>> {
     pub connection_id: ConnectionId,
-    pub body: Vec<u8>,
+    pub body: T,
 }
 
+/// A request that was received from a client. The body shares the buffer that the request
+/// was received into, rather than being copied out of it
+pub(crate) type RequestMessage = ServerMessage<ReceivedMessage>;
+
 pub(crate) struct Server {
     stop_signal: Arc<AtomicBool>,
     sender: Arc<Sender<ServerMessage>>,
-    receiver: Receiver<ServerMessage>,
+    receiver: Receiver<RequestMessage>,
+    connections: Arc<RwLock<HashMap<ConnectionId, Connection>>>,
     port: u16,
 }
 
@@ -33,18 +46,37 @@ pub(crate) struct Server {
 /// connection id in each server message. It is important to copy the connection id into responses so that
 /// they go to the right client.
 impl Server {
-    pub(crate) fn new(buffer_pool: &Arc<BufferPool>, authority: &str) -> Self {
-        let listener = TcpListener::bind(authority)
+    pub(crate) fn new(
+        buffer_pool: &Arc<BufferPool>,
+        internals: &Arc<Internals>,
+        run_state: &Arc<AtomicU8>,
+        authority: &str,
+        connection_limits: ConnectionLimits,
+        security: ConnectionSecurity,
+    ) -> Self {
+        let listener = Self::bind(authority, connection_limits.listen_backlog)
             .expect(&format!("Server: Failed to listen on {authority}"));
         info!("Server: Constructed for {authority}");
         let stop_signal = Arc::new(AtomicBool::new(false));
         let port = listener.local_addr().unwrap().port();
 
         let (tx_sender, tx_receiver) = channel::<ServerMessage>();
-        let (rx_sender, rx_receiver) = channel::<ServerMessage>();
+        let (rx_sender, rx_receiver) = channel::<RequestMessage>();
+        let connections = Arc::new(RwLock::new(HashMap::new()));
 
-        let thread =
-            ListenerThread::new(tx_receiver, rx_sender, listener, &buffer_pool, &stop_signal);
+        let thread = ListenerThread::new(
+            tx_receiver,
+            rx_sender,
+            listener,
+            &connections,
+            &buffer_pool,
+            internals,
+            run_state,
+            &stop_signal,
+            connection_limits.max_connections_per_ip,
+            connection_limits.max_message_size,
+            security,
+        );
         thread::Builder::new()
             .name(String::from("bin-api-listener"))
             .spawn(move || thread.run())
@@ -54,17 +86,37 @@ impl Server {
             stop_signal,
             sender: Arc::new(tx_sender),
             receiver: rx_receiver,
+            connections,
             port,
         }
     }
 
-    pub(crate) fn try_recv(self: &Self) -> Result<ServerMessage, TryRecvError> {
+    /// The standard library always listens with a fixed backlog, so the socket is created
+    /// with socket2 to make the backlog configurable
+    fn bind(authority: &str, backlog: i32) -> io::Result<TcpListener> {
+        let addr: SocketAddr = authority
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, authority.to_owned()))?;
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        socket.set_reuse_address(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(backlog)?;
+        Ok(socket.into())
+    }
+
+    pub(crate) fn try_recv(self: &Self) -> Result<RequestMessage, TryRecvError> {
         self.receiver.try_recv()
     }
 
     pub(crate) fn sender(&self) -> Arc<Sender<ServerMessage>> {
         self.sender.clone()
     }
+
+    /// The connections that are currently open, keyed by connection id
+    pub(crate) fn connections(&self) -> &Arc<RwLock<HashMap<ConnectionId, Connection>>> {
+        &self.connections
+    }
 }
 
 impl Drop for Server {
@@ -0,0 +1,508 @@
+/*
+Watches the requests that the processing threads are working on, so that a single stuck
+request can not stall every connection that shares a processing thread. A thread can not be
+cancelled, so a request that takes longer than the timeout is abandoned rather than stopped:
+the watchdog sends a timeout error to the client in place of the response, and starts a new
+processing thread to handle the requests that follow. The abandoned thread keeps running until
+the request completes, then discards its response and exits. When a lock is stuck, each new
+thread can get stuck on it in turn, so the number of abandoned threads that are still running
+is limited. Once the limit is reached, slow requests are left to finish instead.
+*/
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::server::{ConnectionId, ServerMessage};
+use crate::{
+    observability::{internals::THREAD_WATCHDOG, Metrics},
+    App,
+};
+use log::{error, info, warn};
+use pulsar_rust_net::{
+    bin_serialization::{
+        BrokerResponse, CompressionScheme, ContractSerializer, RequestId, ResponsePayload,
+    },
+    sockets::buffer_pool::BufferPool,
+};
+
+/// The longest time between checks for requests that have taken too long
+const MAX_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The number of abandoned threads that can still be running for each processing thread
+/// before requests stop being abandoned
+const MAX_ABANDONED_THREADS: usize = 4;
+
+/// Starts a processing thread that reports the request it is working on into a slot
+type StartThread = Box<dyn Fn(&Arc<RequestSlot>) + Send>;
+
+/// A request that a processing thread is working on
+pub(crate) struct InFlightRequest {
+    connection_id: ConnectionId,
+    request_id: RequestId,
+    compression: CompressionScheme,
+    timeout_response: ResponsePayload,
+    started: Instant,
+}
+
+/// Where a processing thread records the request that it is working on. Whichever of the
+/// processing thread and the watchdog takes the request out of the slot first gets to respond
+pub(crate) struct RequestSlot {
+    in_flight: Mutex<Option<InFlightRequest>>,
+}
+
+impl RequestSlot {
+    pub(crate) fn new() -> Self {
+        Self {
+            in_flight: Mutex::new(None),
+        }
+    }
+
+    /// Records that a request is being processed, along with the response to send if the
+    /// request is abandoned
+    pub(crate) fn start(
+        self: &Self,
+        connection_id: ConnectionId,
+        request_id: RequestId,
+        compression: CompressionScheme,
+        timeout_response: ResponsePayload,
+    ) {
+        *self.in_flight.lock().unwrap() = Some(InFlightRequest {
+            connection_id,
+            request_id,
+            compression,
+            timeout_response,
+            started: Instant::now(),
+        });
+    }
+
+    /// Records that processing of the request finished. Returns false if the request was
+    /// abandoned by the watchdog, in which case the response must not be sent
+    pub(crate) fn finish(self: &Self) -> bool {
+        self.in_flight.lock().unwrap().take().is_some()
+    }
+
+    /// Takes the request out of the slot if it has been processing for longer than the timeout
+    fn take_expired(self: &Self, timeout: Duration) -> Option<InFlightRequest> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        match &*in_flight {
+            Some(request) if request.started.elapsed() >= timeout => in_flight.take(),
+            _ => None,
+        }
+    }
+
+    /// True if the request in the slot has been processing for longer than the timeout
+    fn is_expired(self: &Self, timeout: Duration) -> bool {
+        match &*self.in_flight.lock().unwrap() {
+            Some(request) => request.started.elapsed() >= timeout,
+            None => false,
+        }
+    }
+}
+
+struct Watched {
+    slot: Arc<RequestSlot>,
+    start_thread: StartThread,
+
+    /// The slots of threads that were abandoned. A thread drops its slot when it exits, so
+    /// the threads that are still running are the ones whose slot is still shared
+    abandoned: Vec<Arc<RequestSlot>>,
+
+    /// Set when the limit on abandoned threads was reported, so that it is reported once
+    limit_reported: bool,
+}
+
+pub(crate) struct Watchdog {
+    app: Arc<App>,
+    stop_signal: Arc<AtomicBool>,
+    serializer: ContractSerializer,
+    sender: Arc<Sender<ServerMessage>>,
+    timeout: Duration,
+    watched: Vec<Watched>,
+}
+
+impl Watchdog {
+    pub(crate) fn new(
+        app: &Arc<App>,
+        buffer_pool: &Arc<BufferPool>,
+        stop_signal: &Arc<AtomicBool>,
+        sender: &Arc<Sender<ServerMessage>>,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            app: app.clone(),
+            stop_signal: stop_signal.clone(),
+            serializer: ContractSerializer::new(buffer_pool),
+            sender: sender.clone(),
+            timeout,
+            watched: Vec::new(),
+        }
+    }
+
+    /// Starts a processing thread that is watched by this watchdog. The function is called
+    /// again to start a replacement each time a request on the thread is abandoned
+    pub(crate) fn watch(self: &mut Self, start_thread: StartThread) {
+        let slot = Arc::new(RequestSlot::new());
+        start_thread(&slot);
+        self.watched.push(Watched {
+            slot,
+            start_thread,
+            abandoned: Vec::new(),
+            limit_reported: false,
+        });
+    }
+
+    pub(crate) fn run(mut self: Self) {
+        info!("Watchdog: Started");
+        let internals = self.app.metrics.internals().clone();
+        let _running = internals.thread_started(THREAD_WATCHDOG);
+        let check_interval = MAX_CHECK_INTERVAL.min(self.timeout / 4);
+        while !self.stop_signal.load(Ordering::Relaxed) {
+            thread::sleep(check_interval);
+            self.abandon_expired();
+        }
+        info!("Watchdog: Stopped");
+    }
+
+    fn abandon_expired(self: &mut Self) {
+        for watched in self.watched.iter_mut() {
+            watched.abandoned.retain(|slot| Arc::strong_count(slot) > 1);
+            if watched.abandoned.len() >= MAX_ABANDONED_THREADS {
+                if !watched.limit_reported && watched.slot.is_expired(self.timeout) {
+                    watched.limit_reported = true;
+                    self.app
+                        .metrics
+                        .incr(Metrics::METRIC_BIN_ABANDON_LIMIT_COUNT);
+                    error!(
+                        "Watchdog: {} abandoned processing threads are still running, so slow requests are no longer abandoned",
+                        watched.abandoned.len()
+                    );
+                }
+                continue;
+            }
+            watched.limit_reported = false;
+
+            if let Some(request) = watched.slot.take_expired(self.timeout) {
+                self.app
+                    .metrics
+                    .incr(Metrics::METRIC_BIN_REQUEST_TIMEOUT_COUNT);
+                warn!(
+                    "Watchdog: Request {} from connection {} was not processed within {:?}",
+                    request.request_id, request.connection_id, self.timeout
+                );
+
+                // The abandoned thread keeps the old slot, so that it finds it empty when
+                // the request eventually completes
+                let abandoned = std::mem::replace(&mut watched.slot, Arc::new(RequestSlot::new()));
+                watched.abandoned.push(abandoned);
+                (watched.start_thread)(&watched.slot);
+
+                let response = BrokerResponse::new(request.request_id, request.timeout_response);
+                match self
+                    .serializer
+                    .serialize_response_with_compression(&response, request.compression)
+                {
+                    Ok(body) => {
+                        let response_message = ServerMessage {
+                            body,
+                            connection_id: request.connection_id,
+                        };
+                        if self.sender.send(response_message).is_err() {
+                            warn!("Watchdog: Failed to send timeout response");
+                        }
+                    }
+                    Err(err) => {
+                        self.app
+                            .metrics
+                            .incr(Metrics::METRIC_BIN_SERIALIZE_ERROR_COUNT);
+                        error!(
+                            "Failed to serialize timeout response to {} on {} connection. {:?}",
+                            request.request_id, request.connection_id, err
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RequestSlot, Watchdog, MAX_ABANDONED_THREADS};
+    use crate::{
+        api_bin::{
+            connection::Connection,
+            processing_thread::ProcessingThread,
+            push_consumers::PushConsumers,
+            server::{ConnectionId, RequestMessage, ServerMessage},
+            RequestLimits,
+        },
+        observability::Metrics,
+        test_support::ClusterBuilder,
+        App,
+    };
+    use pulsar_rust_net::{
+        bin_serialization::{
+            BrokerResponse, CompressionScheme, ContractSerializer, Request, RequestPayload,
+            ResponsePayload,
+        },
+        contracts::v1::{
+            requests,
+            responses::{RequestOutcome, Response},
+        },
+        error_codes::ERROR_CODE_TIMEOUT,
+        sockets::{buffer_pool::BufferPool, tcp_channel::ReceivedMessage},
+    };
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            mpsc::{channel, Receiver, Sender},
+            Arc, Mutex, RwLock,
+        },
+        thread,
+        time::Duration,
+    };
+
+    const CONNECTION_ID: ConnectionId = 1;
+
+    /// A processing thread that is watched by a watchdog, fed and drained through the same
+    /// channels that the server uses
+    struct Pipeline {
+        app: Arc<App>,
+        stop_signal: Arc<AtomicBool>,
+        serializer: ContractSerializer,
+        requests: Sender<RequestMessage>,
+        responses: Receiver<ServerMessage>,
+        connections: Arc<RwLock<HashMap<ConnectionId, Connection>>>,
+    }
+
+    impl Pipeline {
+        fn start(timeout: Duration) -> Self {
+            let app = ClusterBuilder::new("10.0.0.1")
+                .topic("topic1", 1)
+                .subscription("subscription1", false)
+                .build()
+                .app();
+            let buffer_pool = Arc::new(BufferPool::new());
+            let stop_signal = Arc::new(AtomicBool::new(false));
+            let (request_sender, request_receiver) = channel::<RequestMessage>();
+            let request_receiver = Arc::new(Mutex::new(request_receiver));
+            let (response_sender, response_receiver) = channel::<ServerMessage>();
+            let response_sender = Arc::new(response_sender);
+            let connections = Arc::new(RwLock::new(HashMap::new()));
+
+            let mut watchdog =
+                Watchdog::new(&app, &buffer_pool, &stop_signal, &response_sender, timeout);
+            {
+                let app = app.clone();
+                let buffer_pool = buffer_pool.clone();
+                let stop_signal = stop_signal.clone();
+                let connections = connections.clone();
+                let queue_depth = Arc::new(AtomicUsize::new(0));
+                let push_consumers = Arc::new(PushConsumers::new());
+                watchdog.watch(Box::new(move |request_slot| {
+                    let processing_thread = ProcessingThread::new(
+                        &app,
+                        &buffer_pool,
+                        &stop_signal,
+                        &response_sender,
+                        &request_receiver,
+                        &queue_depth,
+                        &push_consumers,
+                        &connections,
+                        RequestLimits::default(),
+                        Some(request_slot),
+                    );
+                    thread::spawn(move || processing_thread.run());
+                }));
+            }
+            thread::spawn(move || watchdog.run());
+
+            Self {
+                app,
+                stop_signal,
+                serializer: ContractSerializer::new(&buffer_pool),
+                requests: request_sender,
+                responses: response_receiver,
+                connections,
+            }
+        }
+
+        fn send(self: &Self, request_id: u32, payload: RequestPayload) {
+            let body = self
+                .serializer
+                .serialize_request(&Request::new(request_id, payload))
+                .unwrap();
+            self.requests
+                .send(ServerMessage {
+                    connection_id: CONNECTION_ID,
+                    body: ReceivedMessage::from(body),
+                })
+                .unwrap();
+        }
+
+        fn consume(self: &Self, request_id: u32) {
+            self.send(
+                request_id,
+                RequestPayload::V1Consume(requests::Consume {
+                    topic_id: 1,
+                    subscription_id: 1,
+                    consumer_id: None,
+                    max_messages: 1,
+                    ack_previous: false,
+                    max_bytes: None,
+                }),
+            );
+        }
+
+        fn ping(self: &Self, request_id: u32) {
+            self.send(
+                request_id,
+                RequestPayload::V1Ping(requests::Ping { timestamp: 1 }),
+            );
+        }
+
+        fn receive(self: &Self, timeout: Duration) -> Option<BrokerResponse> {
+            let message = self.responses.recv_timeout(timeout).ok()?;
+            assert_eq!(message.connection_id, CONNECTION_ID);
+            Some(self.serializer.deserialize_response(message.body).unwrap())
+        }
+    }
+
+    impl Drop for Pipeline {
+        fn drop(self: &mut Self) {
+            self.stop_signal.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn assert_timed_out(response: BrokerResponse, request_id: u32) {
+        assert_eq!(response.request_id, request_id);
+        match response.payload {
+            ResponsePayload::V1Consume(Response {
+                outcome: RequestOutcome::Error(_, code),
+                ..
+            }) => assert_eq!(code, ERROR_CODE_TIMEOUT),
+            _ => panic!("Expected a timeout response to the consume request"),
+        }
+    }
+
+    #[test]
+    fn should_respond_to_a_stuck_request_and_process_the_next_one() {
+        let pipeline = Pipeline::start(Duration::from_millis(100));
+
+        // Consume requests read the connections, so they are stuck while this is held
+        let connections = pipeline.connections.write().unwrap();
+        pipeline.consume(1);
+        pipeline.ping(2);
+
+        let response = pipeline.receive(Duration::from_secs(5)).unwrap();
+        assert_timed_out(response, 1);
+
+        let response = pipeline.receive(Duration::from_secs(5)).unwrap();
+        assert_eq!(response.request_id, 2);
+        assert!(matches!(
+            response.payload,
+            ResponsePayload::V1Pong(Response {
+                outcome: RequestOutcome::Success,
+                ..
+            })
+        ));
+
+        // The abandoned request discards its response when it eventually completes
+        drop(connections);
+        assert!(pipeline.receive(Duration::from_millis(200)).is_none());
+        assert_eq!(
+            pipeline
+                .app
+                .metrics
+                .unsent_count(Metrics::METRIC_BIN_REQUEST_TIMEOUT_COUNT),
+            1.0
+        );
+    }
+
+    #[test]
+    fn should_stop_abandoning_requests_when_too_many_threads_are_stuck() {
+        let pipeline = Pipeline::start(Duration::from_millis(100));
+
+        let connections = pipeline.connections.write().unwrap();
+        for request_id in 1..=MAX_ABANDONED_THREADS as u32 {
+            pipeline.consume(request_id);
+            let response = pipeline.receive(Duration::from_secs(5)).unwrap();
+            assert_timed_out(response, request_id);
+        }
+
+        // Every abandoned thread is still stuck, so the next stuck request is left to finish
+        let request_id = MAX_ABANDONED_THREADS as u32 + 1;
+        pipeline.consume(request_id);
+        assert!(pipeline.receive(Duration::from_millis(500)).is_none());
+        assert_eq!(
+            pipeline
+                .app
+                .metrics
+                .unsent_count(Metrics::METRIC_BIN_ABANDON_LIMIT_COUNT),
+            1.0
+        );
+
+        drop(connections);
+        let response = pipeline.receive(Duration::from_secs(5)).unwrap();
+        assert_eq!(response.request_id, request_id);
+        assert!(matches!(response.payload, ResponsePayload::V1Consume(_)));
+        assert_eq!(
+            pipeline
+                .app
+                .metrics
+                .unsent_count(Metrics::METRIC_BIN_REQUEST_TIMEOUT_COUNT),
+            MAX_ABANDONED_THREADS as f64
+        );
+    }
+
+    fn start(slot: &RequestSlot, request_id: u32) {
+        slot.start(
+            1,
+            request_id,
+            CompressionScheme::None,
+            ResponsePayload::V1Pong(Response::error("Timed out", ERROR_CODE_TIMEOUT)),
+        );
+    }
+
+    #[test]
+    fn should_abandon_slow_requests_and_continue() {
+        let timeout = Duration::from_millis(100);
+        let slot = RequestSlot::new();
+
+        start(&slot, 1);
+        assert!(slot.take_expired(timeout).is_none());
+        assert!(slot.finish());
+
+        start(&slot, 2);
+        thread::sleep(Duration::from_millis(150));
+        let abandoned = slot.take_expired(timeout).unwrap();
+        assert_eq!(abandoned.request_id, 2);
+
+        // The slow request finishes after it was abandoned, so its response is discarded
+        assert!(!slot.finish());
+
+        // Requests after the slow one are processed as normal
+        start(&slot, 3);
+        assert!(slot.finish());
+        assert!(slot.take_expired(timeout).is_none());
+    }
+
+    #[test]
+    fn should_not_abandon_a_finished_request() {
+        let timeout = Duration::from_millis(10);
+        let slot = RequestSlot::new();
+
+        start(&slot, 1);
+        assert!(slot.finish());
+        thread::sleep(Duration::from_millis(20));
+        assert!(slot.take_expired(timeout).is_none());
+    }
+}
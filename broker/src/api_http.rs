@@ -13,7 +13,7 @@ use std::{
     net::SocketAddrV4,
     sync::{atomic::Ordering, Arc},
 };
-use warp::{Filter, Rejection, Reply};
+use warp::{header, Filter, Rejection, Reply};
 
 mod admin; // CRUD operations on nodes, topics, subscriptions and partitions
 mod assets; // Serving static assets like css files
@@ -33,6 +33,22 @@ fn with_app<'a>(app: &Arc<App>) -> impl Filter<Extract = (Arc<App>,), Error = In
     })
 }
 
+/// This warp filter extracts the first media type from the Accept header, so that handlers
+/// can choose the format of the response
+fn with_accept(
+    default_accept: &'static str,
+) -> impl Filter<Extract = (String,), Error = warp::Rejection> + Clone {
+    header::optional::<String>("accept").map(move |accept: Option<String>| {
+        String::from(
+            accept
+                .unwrap_or(String::from(default_accept))
+                .split([',', ';'])
+                .next()
+                .unwrap_or(default_accept),
+        )
+    })
+}
+
 #[rustfmt::skip]
 pub fn routes(app: &Arc<App>) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
         publisher::routes(app)
@@ -1,14 +1,28 @@
 use super::with_app;
-use crate::{observability::Metrics, App};
+use crate::{
+    model::messages::MessageRef,
+    observability::Metrics,
+    services::{
+        admin_service::{AdminError, CreateError, DeleteError, ReassignError},
+        sub_service::SubError,
+    },
+    App,
+};
 use pulsar_rust_net::{
-    contracts::v1::responses::{
-        LedgerDetail, LedgerList, Message, NodeDetail, NodeList, PartitionDetail, PartitionList,
-        Response, TopicDetail, TopicList,
+    contracts::v1::{
+        requests::{CreatePartition, CreateSubscription, CreateTopic},
+        responses::{
+            AckResult, CreatePartitionResult, CreateSubscriptionResult, CreateTopicResult,
+            DeleteSubscriptionResult, DeleteTopicResult, LedgerDetail, LedgerList, Message,
+            NodeDetail, NodeList, PartitionDetail, PartitionList, Response, SubscriptionRepair,
+            SubscriptionSeek, TopicDetail, TopicList,
+        },
     },
-    data_types::{LedgerId, MessageId, NodeId, PartitionId, TopicId},
+    data_types::{LedgerId, MessageId, NodeId, PartitionId, SubscriptionId, Timestamp, TopicId},
+    error_codes::ERROR_CODE_GENERAL_FAILURE,
 };
 use std::sync::Arc;
-use warp::{get, path, reply, Filter, Rejection, Reply};
+use warp::{body, delete, get, path, post, reply, Filter, Rejection, Reply};
 
 async fn get_node_by_id(node_id: NodeId, app: Arc<App>) -> Result<impl Reply, Rejection> {
     app.metrics.incr(Metrics::METRIC_HTTP_ADMIN_COUNT);
@@ -133,6 +147,205 @@ async fn get_ledger_messages_by_id(
     }
 }
 
+async fn force_ack_message(
+    topic_id: TopicId,
+    partition_id: PartitionId,
+    ledger_id: LedgerId,
+    message_id: MessageId,
+    subscription_id: SubscriptionId,
+    app: Arc<App>,
+) -> Result<impl Reply, Rejection> {
+    app.metrics.incr(Metrics::METRIC_HTTP_ADMIN_COUNT);
+    let message_ref = MessageRef {
+        topic_id,
+        partition_id,
+        ledger_id,
+        message_id,
+    };
+    let response = match app.admin_service.force_ack(message_ref, subscription_id) {
+        Ok(true) => Response::success(AckResult { success: true }),
+        Ok(false) => {
+            Response::warning("No message found with this id, maybe this was acked already")
+        }
+        Err(AdminError::TopicNotFound) => Response::warning("No topic found with this id"),
+        Err(AdminError::SubscriptionNotFound) => {
+            Response::warning("No subscription found with this id")
+        }
+        Err(AdminError::PartitionNotFound) => Response::warning("No partition found with this id"),
+        Err(AdminError::LedgerNotFound) => Response::warning("No ledger found with this id"),
+        Err(AdminError::TopicIsEphemeral) => Response::warning("This topic is ephemeral"),
+    };
+    Ok(reply::json(&response))
+}
+
+async fn repair_subscription(
+    topic_id: TopicId,
+    subscription_id: SubscriptionId,
+    app: Arc<App>,
+) -> Result<impl Reply, Rejection> {
+    app.metrics.incr(Metrics::METRIC_HTTP_ADMIN_COUNT);
+    let response = match app
+        .admin_service
+        .repair_subscription(topic_id, subscription_id)
+    {
+        Ok(repair) => Response::success(SubscriptionRepair::from(&repair)),
+        Err(AdminError::TopicNotFound) => Response::warning("No topic found with this id"),
+        Err(AdminError::SubscriptionNotFound) => {
+            Response::warning("No subscription found with this id")
+        }
+        Err(AdminError::PartitionNotFound) => Response::warning("No partition found with this id"),
+        Err(AdminError::LedgerNotFound) => Response::warning("No ledger found with this id"),
+        Err(AdminError::TopicIsEphemeral) => {
+            Response::warning("Ephemeral topics have no event log to repair from")
+        }
+    };
+    Ok(reply::json(&response))
+}
+
+async fn seek_subscription(
+    topic_id: TopicId,
+    subscription_id: SubscriptionId,
+    timestamp: Timestamp,
+    app: Arc<App>,
+) -> Result<impl Reply, Rejection> {
+    app.metrics.incr(Metrics::METRIC_HTTP_ADMIN_COUNT);
+    let response = match app.sub_service.seek(topic_id, subscription_id, timestamp) {
+        Ok(seek) => Response::success(SubscriptionSeek::from(&seek)),
+        Err(SubError::TopicNotFound) => Response::warning("No topic found with this id"),
+        Err(SubError::SubscriptionNotFound) => {
+            Response::warning("No subscription found with this id")
+        }
+        Err(SubError::Error(msg)) => Response::warning(&msg),
+        Err(_) => Response::error("Failed to seek", ERROR_CODE_GENERAL_FAILURE),
+    };
+    Ok(reply::json(&response))
+}
+
+async fn reassign_partition(
+    topic_id: TopicId,
+    partition_id: PartitionId,
+    node_id: NodeId,
+    app: Arc<App>,
+) -> Result<impl Reply, Rejection> {
+    app.metrics.incr(Metrics::METRIC_HTTP_ADMIN_COUNT);
+    let response = match app
+        .admin_service
+        .reassign_partition(topic_id, partition_id, node_id)
+    {
+        Ok(partition) => Response::success(PartitionDetail::from(&partition)),
+        Err(ReassignError::TopicNotFound) => Response::warning("No topic found with this id"),
+        Err(ReassignError::PartitionNotFound) => {
+            Response::warning("No partition found with this id")
+        }
+        Err(ReassignError::NodeNotFound) => Response::warning("No node found with this id"),
+        Err(ReassignError::AlreadyOwned) => {
+            Response::warning("The partition is already owned by this node")
+        }
+        Err(ReassignError::PersistenceFailure(msg)) => {
+            Response::error(&msg, ERROR_CODE_GENERAL_FAILURE)
+        }
+    };
+    Ok(reply::json(&response))
+}
+
+fn create_error<T>(err: CreateError, name_in_use: &str) -> Response<T> {
+    match err {
+        CreateError::TopicNotFound => Response::warning("No topic found with this id"),
+        CreateError::NodeNotFound => Response::warning("No node found with this id"),
+        CreateError::NameInUse => Response::warning(name_in_use),
+        CreateError::PersistenceFailure(msg) => Response::error(&msg, ERROR_CODE_GENERAL_FAILURE),
+    }
+}
+
+async fn create_topic(request: CreateTopic, app: Arc<App>) -> Result<impl Reply, Rejection> {
+    app.metrics.incr(Metrics::METRIC_HTTP_ADMIN_COUNT);
+    let response = match app.admin_service.create_topic(&request.name) {
+        Ok(topic) => Response::success(CreateTopicResult {
+            topic_id: topic.topic_id(),
+        }),
+        Err(err) => create_error(err, "There is already a topic with this name"),
+    };
+    Ok(reply::json(&response))
+}
+
+async fn create_partition(
+    topic_id: TopicId,
+    request: CreatePartition,
+    app: Arc<App>,
+) -> Result<impl Reply, Rejection> {
+    app.metrics.incr(Metrics::METRIC_HTTP_ADMIN_COUNT);
+    let response = match app
+        .admin_service
+        .create_partition(topic_id, request.node_id)
+    {
+        Ok(partition) => Response::success(CreatePartitionResult {
+            topic_id,
+            partition_id: partition.partition_id(),
+            node_id: partition.node_id(),
+        }),
+        Err(err) => create_error(err, "This partition already exists"),
+    };
+    Ok(reply::json(&response))
+}
+
+async fn create_subscription(
+    topic_id: TopicId,
+    request: CreateSubscription,
+    app: Arc<App>,
+) -> Result<impl Reply, Rejection> {
+    app.metrics.incr(Metrics::METRIC_HTTP_ADMIN_COUNT);
+    let response = match app.admin_service.create_subscription(
+        topic_id,
+        &request.name,
+        request.has_key_affinity,
+    ) {
+        Ok(subscription) => Response::success(CreateSubscriptionResult {
+            topic_id,
+            subscription_id: subscription.subscription_id(),
+        }),
+        Err(err) => create_error(err, "There is already a subscription with this name"),
+    };
+    Ok(reply::json(&response))
+}
+
+fn delete_error<T>(err: DeleteError) -> Response<T> {
+    match err {
+        DeleteError::TopicNotFound => Response::warning("No topic found with this id"),
+        DeleteError::SubscriptionNotFound => {
+            Response::warning("No subscription found with this id")
+        }
+        DeleteError::PersistenceFailure(msg) => Response::error(&msg, ERROR_CODE_GENERAL_FAILURE),
+    }
+}
+
+async fn delete_topic(topic_id: TopicId, app: Arc<App>) -> Result<impl Reply, Rejection> {
+    app.metrics.incr(Metrics::METRIC_HTTP_ADMIN_COUNT);
+    let response = match app.admin_service.delete_topic(topic_id) {
+        Ok(()) => Response::success(DeleteTopicResult { topic_id }),
+        Err(err) => delete_error(err),
+    };
+    Ok(reply::json(&response))
+}
+
+async fn delete_subscription(
+    topic_id: TopicId,
+    subscription_id: SubscriptionId,
+    app: Arc<App>,
+) -> Result<impl Reply, Rejection> {
+    app.metrics.incr(Metrics::METRIC_HTTP_ADMIN_COUNT);
+    let response = match app
+        .admin_service
+        .delete_subscription(topic_id, subscription_id)
+    {
+        Ok(()) => Response::success(DeleteSubscriptionResult {
+            topic_id,
+            subscription_id,
+        }),
+        Err(err) => delete_error(err),
+    };
+    Ok(reply::json(&response))
+}
+
 #[rustfmt::skip]
 pub fn routes(app: &Arc<App>) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
     path!("v1" / "admin" / "nodes")
@@ -165,4 +378,31 @@ pub fn routes(app: &Arc<App>) -> impl Filter<Extract = impl Reply, Error = Rejec
     .or(path!("v1" / "admin" / "topic" / TopicId / "partition" / PartitionId / "ledger" / LedgerId / "message" / MessageId)
         .and(get()).and(with_app(app))
         .and_then(get_message_by_id))
+    .or(path!("v1" / "admin" / "topic" / TopicId / "partition" / PartitionId / "ledger" / LedgerId / "message" / MessageId / "subscription" / SubscriptionId / "ack")
+        .and(post()).and(with_app(app))
+        .and_then(force_ack_message))
+    .or(path!("v1" / "admin" / "topic" / TopicId / "subscription" / SubscriptionId / "repair")
+        .and(post()).and(with_app(app))
+        .and_then(repair_subscription))
+    .or(path!("v1" / "admin" / "topic" / TopicId / "subscription" / SubscriptionId / "seek" / Timestamp)
+        .and(post()).and(with_app(app))
+        .and_then(seek_subscription))
+    .or(path!("v1" / "admin" / "topic" / TopicId / "partition" / PartitionId / "reassign" / NodeId)
+        .and(post()).and(with_app(app))
+        .and_then(reassign_partition))
+    .or(path!("v1" / "admin" / "topic")
+        .and(post()).and(body::content_length_limit(512)).and(body::json()).and(with_app(app))
+        .and_then(create_topic))
+    .or(path!("v1" / "admin" / "topic" / TopicId / "partition")
+        .and(post()).and(body::content_length_limit(512)).and(body::json()).and(with_app(app))
+        .and_then(create_partition))
+    .or(path!("v1" / "admin" / "topic" / TopicId / "subscription")
+        .and(post()).and(body::content_length_limit(512)).and(body::json()).and(with_app(app))
+        .and_then(create_subscription))
+    .or(path!("v1" / "admin" / "topic" / TopicId)
+        .and(delete()).and(with_app(app))
+        .and_then(delete_topic))
+    .or(path!("v1" / "admin" / "topic" / TopicId / "subscription" / SubscriptionId)
+        .and(delete()).and(with_app(app))
+        .and_then(delete_subscription))
 }
@@ -1,7 +1,9 @@
 use crate::formatting::html_builder::{HtmlBuilder, ToHtml};
 use pulsar_rust_net::contracts::v1::responses::{
-    AckLogEntry, DropConsumerLogEntry, KeyAffinityLogEntry, LogEntry, LogEntryDetail,
-    LogEntrySummary, Message, MessageRef, NackLogEntry, NewConsumerLogEntry, PublishLogEntry,
+    AckLogEntry, AdminAckLogEntry, DeadLetterLogEntry, DropConsumerLogEntry, ExpiryLogEntry,
+    KeyAffinityLogEntry, LogEntry, LogEntryDetail, LogEntrySummary, Message, MessageRef,
+    NackLogEntry, NewConsumerLogEntry, PartitionReassignedLogEntry, PublishLogEntry,
+    QuarantineLogEntry,
 };
 
 impl<T> ToHtml<T> for Vec<LogEntry> {
@@ -119,6 +121,22 @@ impl<T> ToHtml<T> for AckLogEntry {
     }
 }
 
+impl<T> ToHtml<T> for AdminAckLogEntry {
+    fn to_html(self: &Self, w: &HtmlBuilder<T>) {
+        w.div(self, "admin-ack", |w, _: &T, ack| {
+            w.div(ack, "subscription-id", |w, _: &T, ack| {
+                w.span(ack, "label subscription-id__label", |w, _, _| {
+                    w.text("Subscription");
+                });
+                w.span(ack, "field subscription-id__id", |w, _, ack| {
+                    w.text(&ack.subscription_id.to_string());
+                });
+            });
+            ack.message_ref.to_html(w);
+        });
+    }
+}
+
 impl<T> ToHtml<T> for NackLogEntry {
     fn to_html(self: &Self, w: &HtmlBuilder<T>) {
         w.div(self, "nack", |w, _: &T, nack| {
@@ -143,6 +161,84 @@ impl<T> ToHtml<T> for NackLogEntry {
     }
 }
 
+impl<T> ToHtml<T> for QuarantineLogEntry {
+    fn to_html(self: &Self, w: &HtmlBuilder<T>) {
+        w.div(self, "quarantine", |w, _: &T, q| {
+            w.div(q, "subscription-id", |w, _: &T, q| {
+                w.span(q, "label subscription-id__label", |w, _, _| {
+                    w.text("Subscription");
+                });
+                w.span(q, "field subscription-id__id", |w, _, q| {
+                    w.text(&q.subscription_id.to_string());
+                });
+            });
+            w.div(q, "consumer-id", |w, _: &T, q| {
+                w.span(q, "label consumer-id__label", |w, _, _| {
+                    w.text("Consumer");
+                });
+                w.span(q, "field consumer-id__id", |w, _, q| {
+                    w.text(&q.consumer_id.to_string());
+                });
+            });
+            w.div(q, "reason", |w, _: &T, q| {
+                w.span(q, "label reason__label", |w, _, _| {
+                    w.text("Reason");
+                });
+                w.span(q, "field reason__text", |w, _, q| {
+                    w.text(&q.reason);
+                });
+            });
+            q.message_ref.to_html(w);
+        });
+    }
+}
+
+impl<T> ToHtml<T> for ExpiryLogEntry {
+    fn to_html(self: &Self, w: &HtmlBuilder<T>) {
+        w.div(self, "expiry", |w, _: &T, expiry| {
+            w.div(expiry, "subscription-id", |w, _: &T, expiry| {
+                w.span(expiry, "label subscription-id__label", |w, _, _| {
+                    w.text("Subscription");
+                });
+                w.span(expiry, "field subscription-id__id", |w, _, expiry| {
+                    w.text(&expiry.subscription_id.to_string());
+                });
+            });
+            expiry.message_ref.to_html(w);
+        });
+    }
+}
+
+impl<T> ToHtml<T> for DeadLetterLogEntry {
+    fn to_html(self: &Self, w: &HtmlBuilder<T>) {
+        w.div(self, "dead-letter", |w, _: &T, dl| {
+            w.div(dl, "subscription-id", |w, _: &T, dl| {
+                w.span(dl, "label subscription-id__label", |w, _, _| {
+                    w.text("Subscription");
+                });
+                w.span(dl, "field subscription-id__id", |w, _, dl| {
+                    w.text(&dl.subscription_id.to_string());
+                });
+            });
+            w.div(dl, "consumer-id", |w, _: &T, dl| {
+                w.span(dl, "label consumer-id__label", |w, _, _| {
+                    w.text("Consumer");
+                });
+                w.span(dl, "field consumer-id__id", |w, _, dl| {
+                    w.text(&dl.consumer_id.to_string());
+                });
+            });
+            dl.message_ref.to_html(w);
+            w.div(dl, "dead-letter-ref", |w, _: &T, dl| {
+                w.span(dl, "label dead-letter-ref__label", |w, _, _| {
+                    w.text("Dead letter");
+                });
+                dl.dead_letter_ref.to_html(w);
+            });
+        });
+    }
+}
+
 impl<T> ToHtml<T> for NewConsumerLogEntry {
     fn to_html(self: &Self, w: &HtmlBuilder<T>) {
         w.div(self, "new-consumer", |w, _: &T, c| {
@@ -244,15 +340,67 @@ impl<T> ToHtml<T> for KeyAffinityLogEntry {
     }
 }
 
+impl<T> ToHtml<T> for PartitionReassignedLogEntry {
+    fn to_html(self: &Self, w: &HtmlBuilder<T>) {
+        w.div(self, "partition-reassigned", |w, _: &T, p| {
+            w.div(p, "topic-id", |w, _: &T, p| {
+                w.span(p, "label topic-id__label", |w, _, _| {
+                    w.text("Topic");
+                });
+                w.span(p, "field topic-id__id", |w, _, p| {
+                    w.text(&p.topic_id.to_string());
+                });
+            });
+            w.div(p, "partition-id", |w, _: &T, p| {
+                w.span(p, "label partition-id__label", |w, _, _| {
+                    w.text("Partition");
+                });
+                w.span(p, "field partition-id__id", |w, _, p| {
+                    w.text(&p.partition_id.to_string());
+                });
+            });
+            w.div(p, "ledger-id", |w, _: &T, p| {
+                w.span(p, "label ledger-id__label", |w, _, _| {
+                    w.text("Ledger");
+                });
+                w.span(p, "field ledger-id__id", |w, _, p| {
+                    w.text(&p.ledger_id.to_string());
+                });
+            });
+            w.div(p, "from-node-id", |w, _: &T, p| {
+                w.span(p, "label from-node-id__label", |w, _, _| {
+                    w.text("From node");
+                });
+                w.span(p, "field from-node-id__id", |w, _, p| {
+                    w.text(&p.from_node_id.to_string());
+                });
+            });
+            w.div(p, "to-node-id", |w, _: &T, p| {
+                w.span(p, "label to-node-id__label", |w, _, _| {
+                    w.text("To node");
+                });
+                w.span(p, "field to-node-id__id", |w, _, p| {
+                    w.text(&p.to_node_id.to_string());
+                });
+            });
+        });
+    }
+}
+
 impl<T> ToHtml<T> for LogEntryDetail {
     fn to_html(self: &Self, w: &HtmlBuilder<T>) {
         match self {
             LogEntryDetail::Publish(entry) => entry.to_html(w),
             LogEntryDetail::Ack(entry) => entry.to_html(w),
             LogEntryDetail::Nack(entry) => entry.to_html(w),
+            LogEntryDetail::AdminAck(entry) => entry.to_html(w),
             LogEntryDetail::NewConsumer(entry) => entry.to_html(w),
             LogEntryDetail::DropConsumer(entry) => entry.to_html(w),
             LogEntryDetail::KeyAffinity(entry) => entry.to_html(w),
+            LogEntryDetail::Quarantine(entry) => entry.to_html(w),
+            LogEntryDetail::Expiry(entry) => entry.to_html(w),
+            LogEntryDetail::DeadLetter(entry) => entry.to_html(w),
+            LogEntryDetail::PartitionReassigned(entry) => entry.to_html(w),
         }
     }
 }
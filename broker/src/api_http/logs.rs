@@ -1,27 +1,42 @@
-use super::with_app;
+use super::{with_accept, with_app};
 use crate::{
     formatting::html_builder::{HtmlBuilder, ToHtml},
-    persistence::{event_logger::EventQueryOptions, PersistenceLayer},
+    persistence::{event_logger::EventQueryOptions, log_entries, PersistenceLayer},
     App,
 };
+use log::warn;
 use pulsar_rust_net::{
     contracts::v1::responses::{LogEntry, LogEntrySummary},
-    data_types::{LedgerId, MessageId, PartitionId, TopicId},
+    data_types::{LedgerId, MessageId, PartitionId, Timestamp, TopicId},
     display::JoinableToString,
 };
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::{
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+use tokio::{sync::broadcast::error::RecvError, time};
 use warp::{
-    get, header,
+    get,
     http::Response,
+    hyper::{body::Bytes, Body},
     path, query,
     reply::{self},
     Filter, Rejection, Reply,
 };
 
+const DEFAULT_ACCEPT: &str = "text/plain";
+const DEFAULT_STREAM_ACCEPT: &str = "application/x-ndjson";
+
+/// How often a stream that has no new events checks whether the broker is stopping
+const STREAM_STOP_INTERVAL: Duration = Duration::from_millis(100);
+
 #[derive(Serialize, Deserialize)]
 struct LogParams {
     limit: Option<usize>,
+    offset: Option<usize>,
+    from_timestamp: Option<Timestamp>,
+    to_timestamp: Option<Timestamp>,
     detailed: Option<bool>,
     exact: Option<bool>,
 }
@@ -30,26 +45,15 @@ fn with_params() -> impl Filter<Extract = (LogParams,), Error = warp::Rejection>
     query::<LogParams>()
 }
 
-fn with_accept() -> impl Filter<Extract = (String,), Error = warp::Rejection> + Clone {
-    const DEFAULT_ACCEPT: &str = "text/plain";
-    header::optional::<String>("accept").map(|accept: Option<String>| {
-        String::from(
-            accept
-                .unwrap_or(String::from(DEFAULT_ACCEPT))
-                .split([',', ';'])
-                .next()
-                .unwrap_or(DEFAULT_ACCEPT),
-        )
-    })
-}
-
 fn get_options(params: LogParams, default_exact: bool) -> EventQueryOptions {
     EventQueryOptions {
         include_serialization: params.detailed.unwrap_or(false),
         descending: true,
-        skip: 0,
+        skip: params.offset.unwrap_or(0),
         take: params.limit.unwrap_or(20),
         exact_match: params.exact.unwrap_or(default_exact),
+        from_timestamp: params.from_timestamp,
+        to_timestamp: params.to_timestamp,
     }
 }
 
@@ -108,6 +112,8 @@ fn get_detailed_response(
             .header("Content-Type", &accept)
             .body(events.join("\n"))
             .into_response(),
+        "application/json" => reply::json(&events).into_response(),
+        // Clients that accept anything, such as `*/*`, get JSON too
         _ => reply::json(&events).into_response(),
     }
 }
@@ -145,6 +151,8 @@ fn get_summary_response(
             .header("Content-Type", &accept)
             .body(events.join("\n"))
             .into_response(),
+        "application/json" => reply::json(&events).into_response(),
+        // Clients that accept anything, such as `*/*`, get JSON too
         _ => reply::json(&events).into_response(),
     }
 }
@@ -175,6 +183,76 @@ fn get_events(
     }
 }
 
+/// Follows the event log, writing each new entry whose key starts with the prefix as a line
+/// of JSON, or as a line of text when plain text is accepted. The response continues until
+/// the client disconnects or the broker stops. Only the `detailed` parameter applies
+fn stream_events(
+    app: Arc<App>,
+    params: LogParams,
+    accept: String,
+    prefix: String,
+) -> Result<impl Reply, Rejection> {
+    let detailed = params.detailed.unwrap_or(false);
+    let plain = accept == "text/plain";
+    let mut receiver = app.peristence.tail_events();
+
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        loop {
+            let entry = match time::timeout(STREAM_STOP_INTERVAL, receiver.recv()).await {
+                Ok(Ok(entry)) => entry,
+                Ok(Err(RecvError::Lagged(count))) => {
+                    warn!(
+                        "Event log stream for '{prefix}' fell behind and skipped {count} entries"
+                    );
+                    continue;
+                }
+                Ok(Err(RecvError::Closed)) => break,
+                Err(_) => {
+                    if app.stop_signal.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    continue;
+                }
+            };
+            if !entry.key.starts_with(&prefix) {
+                continue;
+            }
+            match stream_line(&entry, detailed, plain) {
+                Ok(line) => {
+                    if sender.send_data(Bytes::from(line + "\n")).await.is_err() {
+                        break;
+                    }
+                }
+                Err(err) => warn!("Failed to serialize event log stream line. {err}"),
+            }
+        }
+    });
+
+    let content_type = if plain {
+        "text/plain"
+    } else {
+        DEFAULT_STREAM_ACCEPT
+    };
+    Ok(Response::builder()
+        .header("Content-Type", content_type)
+        .body(body)
+        .into_response())
+}
+
+fn stream_line(
+    entry: &log_entries::LogEntry,
+    detailed: bool,
+    plain: bool,
+) -> serde_json::Result<String> {
+    Ok(match (detailed, plain) {
+        (true, true) => LogEntry::from(entry).to_string(),
+        (true, false) => serde_json::to_string(&LogEntry::from(entry))?,
+        (false, true) => LogEntrySummary::from(entry).to_string(),
+        (false, false) => serde_json::to_string(&LogEntrySummary::from(entry))?,
+    })
+}
+
 async fn get_cluster_log(
     params: LogParams,
     accept: String,
@@ -249,21 +327,86 @@ async fn get_message_log(
     )
 }
 
+async fn stream_cluster_log(
+    params: LogParams,
+    accept: String,
+    app: Arc<App>,
+) -> Result<impl Reply, Rejection> {
+    stream_events(app, params, accept, String::default())
+}
+
+async fn stream_topic_log(
+    topic_id: TopicId,
+    params: LogParams,
+    accept: String,
+    app: Arc<App>,
+) -> Result<impl Reply, Rejection> {
+    stream_events(
+        app,
+        params,
+        accept,
+        PersistenceLayer::build_topic_prefix(topic_id),
+    )
+}
+
+async fn stream_partition_log(
+    topic_id: TopicId,
+    partition_id: PartitionId,
+    params: LogParams,
+    accept: String,
+    app: Arc<App>,
+) -> Result<impl Reply, Rejection> {
+    stream_events(
+        app,
+        params,
+        accept,
+        PersistenceLayer::build_partition_prefix(topic_id, partition_id),
+    )
+}
+
+async fn stream_ledger_log(
+    topic_id: TopicId,
+    partition_id: PartitionId,
+    ledger_id: LedgerId,
+    params: LogParams,
+    accept: String,
+    app: Arc<App>,
+) -> Result<impl Reply, Rejection> {
+    stream_events(
+        app,
+        params,
+        accept,
+        PersistenceLayer::build_ledger_prefix(topic_id, partition_id, ledger_id),
+    )
+}
+
 #[rustfmt::skip]
 pub fn routes(app: &Arc<App>) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
     path!("v1" / "logs" )
-        .and(get()).and(with_params()).and(with_accept()).and(with_app(app))
+        .and(get()).and(with_params()).and(with_accept(DEFAULT_ACCEPT)).and(with_app(app))
         .and_then(get_cluster_log)
     .or(path!("v1" / "logs" / "topic" / TopicId)
-        .and(get()).and(with_params()).and(with_accept()).and(with_app(app))
+        .and(get()).and(with_params()).and(with_accept(DEFAULT_ACCEPT)).and(with_app(app))
         .and_then(get_topic_log))
     .or(path!("v1" / "logs" / "topic" / TopicId / "partition" / PartitionId)
-        .and(get()).and(with_params()).and(with_accept()).and(with_app(app))
+        .and(get()).and(with_params()).and(with_accept(DEFAULT_ACCEPT)).and(with_app(app))
         .and_then(get_partition_log))
     .or(path!("v1" / "logs" / "topic" / TopicId / "partition" / PartitionId / "ledger" / LedgerId)
-        .and(get()).and(with_params()).and(with_accept()).and(with_app(app))
+        .and(get()).and(with_params()).and(with_accept(DEFAULT_ACCEPT)).and(with_app(app))
         .and_then(get_ledger_log))
     .or(path!("v1" / "logs" / "topic" / TopicId / "partition" / PartitionId / "ledger" / LedgerId / "message" / MessageId)
-        .and(get()).and(with_params()).and(with_accept()).and(with_app(app))
+        .and(get()).and(with_params()).and(with_accept(DEFAULT_ACCEPT)).and(with_app(app))
         .and_then(get_message_log))
+    .or(path!("v1" / "logs" / "stream")
+        .and(get()).and(with_params()).and(with_accept(DEFAULT_STREAM_ACCEPT)).and(with_app(app))
+        .and_then(stream_cluster_log))
+    .or(path!("v1" / "logs" / "stream" / "topic" / TopicId)
+        .and(get()).and(with_params()).and(with_accept(DEFAULT_STREAM_ACCEPT)).and(with_app(app))
+        .and_then(stream_topic_log))
+    .or(path!("v1" / "logs" / "stream" / "topic" / TopicId / "partition" / PartitionId)
+        .and(get()).and(with_params()).and(with_accept(DEFAULT_STREAM_ACCEPT)).and(with_app(app))
+        .and_then(stream_partition_log))
+    .or(path!("v1" / "logs" / "stream" / "topic" / TopicId / "partition" / PartitionId / "ledger" / LedgerId)
+        .and(get()).and(with_params()).and(with_accept(DEFAULT_STREAM_ACCEPT)).and(with_app(app))
+        .and_then(stream_ledger_log))
 }
@@ -5,7 +5,9 @@ use pulsar_rust_net::{
         requests,
         responses::{self, Response},
     },
-    error_codes::{ERROR_CODE_BACKLOG_FULL, ERROR_CODE_GENERAL_FAILURE, ERROR_CODE_INCORRECT_NODE},
+    error_codes::{
+        ERROR_CODE_BACKLOG_FULL, ERROR_CODE_DUPLICATE_SEQUENCE, ERROR_CODE_GENERAL_FAILURE,
+    },
 };
 use std::sync::Arc;
 use warp::{body, get, path, post, reply, Filter, Rejection, Reply};
@@ -36,11 +38,16 @@ async fn publish_message(
     message: requests::Publish,
     app: Arc<App>,
 ) -> Result<impl Reply, Rejection> {
-    app.metrics.incr(Metrics::METRIC_HTTP_PUB_MESSAGE_COUNT);
-    let response = match app.pub_service.publish_message(message.into()) {
-        Ok(message_ref) => responses::Response::success(responses::PublishResult {
-            message_ref: message_ref.into(),
-        }),
+    app.metrics
+        .incr_topic(Metrics::METRIC_HTTP_PUB_MESSAGE_COUNT, message.topic_id);
+    let ack_level = message.ack_level;
+    let response = match app
+        .pub_service
+        .publish_message_with_ack_level(message.into(), ack_level)
+    {
+        Ok(message_ref) => responses::Response::success(responses::PublishResult::from(
+            &app.pub_service.receipt(message_ref),
+        )),
         Err(err) => match err {
             PubError::Error(msg) => {
                 responses::Response::error(&msg.clone(), ERROR_CODE_GENERAL_FAILURE)
@@ -50,12 +57,12 @@ async fn publish_message(
                 responses::Response::warning("No partition with this ID")
             }
             PubError::NodeNotFound => responses::Response::warning("No node with this ID"),
-            PubError::WrongNode(node) => responses::Response::error(
+            PubError::WrongNode(node) => responses::Response::incorrect_node(
                 &format!(
                     "Wrong node for this partition. Publish to {} instead",
                     node.ip_address()
                 ),
-                ERROR_CODE_INCORRECT_NODE,
+                node.admin_authority(),
             ),
             PubError::BacklogCapacityExceeded => {
                 responses::Response::error("The backlog storage is full", ERROR_CODE_BACKLOG_FULL)
@@ -63,8 +70,22 @@ async fn publish_message(
             PubError::NoSubscribers => {
                 responses::Response::warning("There are no active subscribers to this topic")
             }
+            PubError::NoLedger => responses::Response::error(
+                "No ledger is available for this partition",
+                ERROR_CODE_GENERAL_FAILURE,
+            ),
+            PubError::DuplicateSequenceNumber => responses::Response::error(
+                "The producer already published this sequence number",
+                ERROR_CODE_DUPLICATE_SEQUENCE,
+            ),
         },
     };
+
+    // The publisher is told that the message was published without waiting for the message
+    // to be logged and added to subscriptions
+    if ack_level == requests::PublishAckLevel::None {
+        tokio::task::spawn_blocking(move || app.pub_service.flush_publishes());
+    }
     Ok(reply::json(&response))
 }
 
@@ -169,6 +169,19 @@ async fn get_cluster_stats(accept: String, app: Arc<App>) -> Result<impl Reply,
     Ok(get_cluster_response(accept, app))
 }
 
+/// Counters since the broker started, for Prometheus to scrape
+async fn get_metrics(app: Arc<App>) -> Result<impl Reply, Rejection> {
+    Ok(Response::builder()
+        .header("Content-Type", "text/plain; version=0.0.4")
+        .body(app.metrics.prometheus_text())
+        .into_response())
+}
+
+/// Buffer pool, channel and connection queue depths, and thread counts for the binary API
+async fn get_internals(app: Arc<App>) -> Result<impl Reply, Rejection> {
+    Ok(reply::json(&app.metrics.internals().snapshot()))
+}
+
 #[rustfmt::skip]
 pub fn routes(app: &Arc<App>) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
     path!("stats" )
@@ -183,4 +196,10 @@ pub fn routes(app: &Arc<App>) -> impl Filter<Extract = impl Reply, Error = Rejec
     .or(path!("stats" / "topic" / TopicId / "partition" / PartitionId / "ledger" / LedgerId)
         .and(get()).and(with_accept()).and(with_app(app))
         .and_then(get_ledger_stats))
+    .or(path!("metrics")
+        .and(get()).and(with_app(app))
+        .and_then(get_metrics))
+    .or(path!("v1" / "internals")
+        .and(get()).and(with_app(app))
+        .and_then(get_internals))
 }
@@ -1,12 +1,26 @@
-use super::with_app;
-use crate::{observability::Metrics, services::sub_service::SubError, App};
+use super::{with_accept, with_app};
+use crate::{
+    model::{messages::MessageRef, response_mapping},
+    observability::Metrics,
+    services::sub_service::SubError,
+    App,
+};
+use log::warn;
 use pulsar_rust_net::{
     contracts::v1::{requests, responses},
     data_types::{ConsumerId, SubscriptionId, TopicId},
-    error_codes::ERROR_CODE_GENERAL_FAILURE,
+    error_codes::{ERROR_CODE_GENERAL_FAILURE, ERROR_CODE_TOO_MANY_CONSUMERS},
+};
+use std::{iter, mem, sync::Arc};
+use warp::{
+    body, get,
+    http::Response,
+    hyper::{body::Bytes, Body},
+    path, post, reply, Filter, Rejection, Reply,
 };
-use std::sync::Arc;
-use warp::{body, get, path, post, reply, Filter, Rejection, Reply};
+
+const JSON_CONTENT_TYPE: &str = "application/json";
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
 
 async fn get_message(
     topic_id: TopicId,
@@ -14,7 +28,11 @@ async fn get_message(
     consumer_id: ConsumerId,
     app: Arc<App>,
 ) -> Result<impl Reply, Rejection> {
-    app.metrics.incr(Metrics::METRIC_HTTP_SUB_MESSAGE_COUNT);
+    app.metrics.incr_subscription(
+        Metrics::METRIC_HTTP_SUB_MESSAGE_COUNT,
+        topic_id,
+        subscription_id,
+    );
     let response = match app.sub_service.next_message(topic_id, subscription_id, consumer_id) {
         Ok(message) => {
             let message = responses::Message {
@@ -23,8 +41,12 @@ async fn get_message(
                 message_ack_key: message.published_message.message_ref.to_key(),
                 published: message.published_message.published,
                 attributes: message.published_message.attributes,
+                headers: message.published_message.headers,
                 delivered: message.subscribed_message.delivered_timestamp.unwrap(),
+                first_delivered: message.subscribed_message.first_delivered_timestamp,
                 delivery_count: message.subscribed_message.delivery_count,
+                redelivered: message.subscribed_message.delivery_count > 1,
+                last_in_ledger: message.last_in_ledger,
             };
             responses::Response::success(message)
         }
@@ -38,6 +60,15 @@ async fn get_message(
                 SubError::MessageNotFound => responses::Response::warning(&String::from("No message found with this message id. The message may have been acked by all subscriptions")),
                 SubError::NoneAvailable => responses::Response::no_data(&String::from("There are no more messages available at this time")),
                 SubError::FailedToAllocateConsumerId => responses::Response::warning("Failed allocate consumer id"),
+                SubError::TooManyConsumers => responses::Response::error("The subscription already has the maximum number of consumers", ERROR_CODE_TOO_MANY_CONSUMERS),
+                SubError::NodeNotFound => responses::Response::warning("Unknown node for this partition"),
+                SubError::WrongNode(node) => responses::Response::incorrect_node(
+                    &format!(
+                        "This node is not the owner of the partition, consume from {} instead",
+                        node.ip_address()
+                    ),
+                    node.admin_authority(),
+                ),
             }
         }
     };
@@ -58,15 +89,36 @@ async fn get_topics(app: Arc<App>) -> Result<impl Reply, Rejection> {
     )))
 }
 
-async fn consume(body: requests::Consume, app: Arc<App>) -> Result<impl Reply, Rejection> {
-    app.metrics.incr(Metrics::METRIC_HTTP_SUB_CONSUME_COUNT);
-
-    let response = match app.sub_service.consume_max_messages(
+async fn consume(
+    body: requests::Consume,
+    accept: String,
+    app: Arc<App>,
+) -> Result<impl Reply, Rejection> {
+    app.metrics.incr_subscription(
+        Metrics::METRIC_HTTP_SUB_CONSUME_COUNT,
         body.topic_id,
         body.subscription_id,
-        body.consumer_id,
-        body.max_messages,
-    ) {
+    );
+
+    let consumed = if body.ack_previous {
+        app.sub_service.consume_and_ack_previous(
+            body.topic_id,
+            body.subscription_id,
+            body.consumer_id,
+            body.max_messages,
+            body.max_bytes,
+            None,
+        )
+    } else {
+        app.sub_service.consume_max_bytes(
+            body.topic_id,
+            body.subscription_id,
+            body.consumer_id,
+            body.max_messages,
+            body.max_bytes,
+        )
+    };
+    let response = match consumed {
         Ok(result) => responses::Response::success(responses::ConsumeResult::from(&result)),
         Err(err) => match err {
             SubError::Error(msg) => responses::Response::error(&msg, ERROR_CODE_GENERAL_FAILURE),
@@ -84,13 +136,69 @@ async fn consume(body: requests::Consume, app: Arc<App>) -> Result<impl Reply, R
                 "Failed to allocate consumer id",
                 ERROR_CODE_GENERAL_FAILURE,
             ),
+            SubError::TooManyConsumers => responses::Response::error(
+                "The subscription already has the maximum number of consumers",
+                ERROR_CODE_TOO_MANY_CONSUMERS,
+            ),
+            SubError::NodeNotFound => {
+                responses::Response::warning("Unknown node for this partition")
+            }
+            SubError::WrongNode(node) => responses::Response::incorrect_node(
+                &format!(
+                    "This node is not the owner of the partition, consume from {} instead",
+                    node.ip_address()
+                ),
+                node.admin_authority(),
+            ),
         },
     };
-    Ok(reply::json(&response))
+    match accept.as_str() {
+        NDJSON_CONTENT_TYPE => Ok(ndjson_response(response)),
+        _ => Ok(reply::json(&response).into_response()),
+    }
+}
+
+/// Streams a consume response as newline delimited JSON so that clients can process messages
+/// as they arrive. The first line is the response without its messages, and each of the
+/// following lines is one message
+fn ndjson_response(
+    mut response: responses::Response<responses::ConsumeResult>,
+) -> warp::reply::Response {
+    let messages = match &mut response.data {
+        Some(result) => mem::take(&mut result.messages),
+        None => Vec::new(),
+    };
+
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        let lines = iter::once(serde_json::to_string(&response))
+            .chain(messages.iter().map(serde_json::to_string));
+        for line in lines {
+            match line {
+                Ok(line) => {
+                    if sender.send_data(Bytes::from(line + "\n")).await.is_err() {
+                        break;
+                    }
+                }
+                Err(err) => warn!("Failed to serialize consume response line. {err}"),
+            }
+        }
+    });
+
+    Response::builder()
+        .header("Content-Type", NDJSON_CONTENT_TYPE)
+        .body(body)
+        .into_response()
 }
 
 async fn ack_message(body: requests::Ack, app: Arc<App>) -> Result<impl Reply, Rejection> {
-    app.metrics.incr(Metrics::METRIC_HTTP_SUB_ACK_COUNT);
+    if let Some(message_ref) = MessageRef::try_from_key(&body.message_ref_key) {
+        app.metrics.incr_subscription(
+            Metrics::METRIC_HTTP_SUB_ACK_COUNT,
+            message_ref.topic_id,
+            body.subscription_id,
+        );
+    }
     let response =
         match app
             .sub_service
@@ -131,13 +239,33 @@ async fn ack_message(body: requests::Ack, app: Arc<App>) -> Result<impl Reply, R
                     &String::from("Failed to allocate consumer id"),
                     ERROR_CODE_GENERAL_FAILURE,
                 ),
+                SubError::TooManyConsumers => responses::Response::error(
+                    &String::from("The subscription already has the maximum number of consumers"),
+                    ERROR_CODE_TOO_MANY_CONSUMERS,
+                ),
+                SubError::NodeNotFound => {
+                    responses::Response::warning(&String::from("Unknown node for this partition"))
+                }
+                SubError::WrongNode(node) => responses::Response::incorrect_node(
+                    &format!(
+                        "This node is not the owner of the partition, send to {} instead",
+                        node.ip_address()
+                    ),
+                    node.admin_authority(),
+                ),
             },
         };
     Ok(reply::json(&response))
 }
 
 async fn nack_message(body: requests::Nack, app: Arc<App>) -> Result<impl Reply, Rejection> {
-    app.metrics.incr(Metrics::METRIC_HTTP_SUB_NACK_COUNT);
+    if let Some(message_ref) = MessageRef::try_from_key(&body.message_ref_key) {
+        app.metrics.incr_subscription(
+            Metrics::METRIC_HTTP_SUB_NACK_COUNT,
+            message_ref.topic_id,
+            body.subscription_id,
+        );
+    }
     let response =
         match app
             .sub_service
@@ -178,11 +306,168 @@ async fn nack_message(body: requests::Nack, app: Arc<App>) -> Result<impl Reply,
                     &String::from("Failed to allocate consumer id"),
                     ERROR_CODE_GENERAL_FAILURE,
                 ),
+                SubError::TooManyConsumers => responses::Response::error(
+                    &String::from("The subscription already has the maximum number of consumers"),
+                    ERROR_CODE_TOO_MANY_CONSUMERS,
+                ),
+                SubError::NodeNotFound => {
+                    responses::Response::warning(&String::from("Unknown node for this partition"))
+                }
+                SubError::WrongNode(node) => responses::Response::incorrect_node(
+                    &format!(
+                        "This node is not the owner of the partition, send to {} instead",
+                        node.ip_address()
+                    ),
+                    node.admin_authority(),
+                ),
             },
         };
     Ok(reply::json(&response))
 }
 
+async fn quarantine_message(
+    body: requests::Quarantine,
+    app: Arc<App>,
+) -> Result<impl Reply, Rejection> {
+    if let Some(message_ref) = MessageRef::try_from_key(&body.message_ref_key) {
+        app.metrics.incr_subscription(
+            Metrics::METRIC_HTTP_SUB_QUARANTINE_COUNT,
+            message_ref.topic_id,
+            body.subscription_id,
+        );
+    }
+    let response = match app.sub_service.quarantine(
+        body.message_ref_key,
+        body.subscription_id,
+        body.consumer_id,
+        &body.reason,
+    ) {
+        Ok(found) => {
+            if found {
+                responses::Response::success(responses::QuarantineResult { success: true })
+            } else {
+                responses::Response::warning(&String::from(
+                    "No message found with this id, maybe this was acked already",
+                ))
+            }
+        }
+        Err(err) => match err {
+            SubError::Error(msg) => responses::Response::error(&msg, ERROR_CODE_GENERAL_FAILURE),
+            SubError::TopicNotFound => {
+                responses::Response::warning(&String::from("No topic found with this id"))
+            }
+            SubError::SubscriptionNotFound => {
+                responses::Response::warning(&String::from("No subscription found with this id"))
+            }
+            SubError::PartitionNotFound => {
+                responses::Response::warning(&String::from("No partition found with this id"))
+            }
+            SubError::LedgerNotFound => {
+                responses::Response::warning(&String::from("No ledger found with this id"))
+            }
+            SubError::MessageNotFound => {
+                responses::Response::warning(&String::from("No message found with this id"))
+            }
+            SubError::NoneAvailable => {
+                responses::Response::no_data(&String::from("No data was available"))
+            }
+            SubError::FailedToAllocateConsumerId => responses::Response::error(
+                &String::from("Failed to allocate consumer id"),
+                ERROR_CODE_GENERAL_FAILURE,
+            ),
+            SubError::TooManyConsumers => responses::Response::error(
+                &String::from("The subscription already has the maximum number of consumers"),
+                ERROR_CODE_TOO_MANY_CONSUMERS,
+            ),
+            SubError::NodeNotFound => {
+                responses::Response::warning(&String::from("Unknown node for this partition"))
+            }
+            SubError::WrongNode(node) => responses::Response::incorrect_node(
+                &format!(
+                    "This node is not the owner of the partition, send to {} instead",
+                    node.ip_address()
+                ),
+                node.admin_authority(),
+            ),
+        },
+    };
+    Ok(reply::json(&response))
+}
+
+async fn get_quarantined(
+    topic_id: TopicId,
+    subscription_id: SubscriptionId,
+    app: Arc<App>,
+) -> Result<impl Reply, Rejection> {
+    app.metrics.incr_subscription(
+        Metrics::METRIC_HTTP_SUB_QUARANTINED_COUNT,
+        topic_id,
+        subscription_id,
+    );
+    let messages = app
+        .sub_service
+        .quarantined_messages(topic_id, subscription_id);
+    Ok(reply::json(&responses::Response::success(
+        response_mapping::quarantined_message_list(&messages),
+    )))
+}
+
+async fn get_subscription_stats(
+    topic_id: TopicId,
+    subscription_id: SubscriptionId,
+    app: Arc<App>,
+) -> Result<impl Reply, Rejection> {
+    app.metrics.incr_subscription(
+        Metrics::METRIC_HTTP_SUB_STATS_COUNT,
+        topic_id,
+        subscription_id,
+    );
+    let response = match app
+        .sub_service
+        .subscription_stats(topic_id, subscription_id)
+    {
+        Ok(stats) => responses::Response::success(responses::SubscriptionStats::from(&stats)),
+        Err(err) => stats_error_response(err, topic_id, Some(subscription_id)),
+    };
+    Ok(reply::json(&response))
+}
+
+async fn get_topic_subscription_stats(
+    topic_id: TopicId,
+    app: Arc<App>,
+) -> Result<impl Reply, Rejection> {
+    app.metrics
+        .incr_topic(Metrics::METRIC_HTTP_SUB_STATS_COUNT, topic_id);
+    let response = match app.sub_service.topic_subscription_stats(topic_id) {
+        Ok(stats) => responses::Response::success(response_mapping::topic_subscription_stats(
+            topic_id, &stats,
+        )),
+        Err(err) => stats_error_response(err, topic_id, None),
+    };
+    Ok(reply::json(&response))
+}
+
+/// Looking up stats can only fail when the topic or subscription does not exist
+fn stats_error_response<T>(
+    err: SubError,
+    topic_id: TopicId,
+    subscription_id: Option<SubscriptionId>,
+) -> responses::Response<T> {
+    match (err, subscription_id) {
+        (SubError::TopicNotFound, _) => {
+            responses::Response::warning(&format!("No topic found with id {topic_id}"))
+        }
+        (SubError::SubscriptionNotFound, Some(subscription_id)) => responses::Response::warning(
+            &format!("No subscription found with id {subscription_id}"),
+        ),
+        (SubError::Error(msg), _) => responses::Response::error(&msg, ERROR_CODE_GENERAL_FAILURE),
+        _ => responses::Response::error(
+            "Failed to get subscription stats",
+            ERROR_CODE_GENERAL_FAILURE,
+        ),
+    }
+}
+
 async fn ping(app: Arc<App>) -> Result<impl Reply, Rejection> {
     app.metrics.incr(Metrics::METRIC_HTTP_SUB_PING_COUNT);
     Ok(reply::html("pong"))
@@ -202,6 +487,18 @@ pub fn routes(app: &Arc<App>) -> impl Filter<Extract = impl Reply, Error = Rejec
     .or(path!("v1" / "sub" / "nack")
         .and(post()).and(body::content_length_limit(512)).and(body::json()).and(with_app(app))
         .and_then(nack_message))
+    .or(path!("v1" / "sub" / "quarantine")
+        .and(post()).and(body::content_length_limit(1024)).and(body::json()).and(with_app(app))
+        .and_then(quarantine_message))
+    .or(path!("v1" / "sub" / "topic" / TopicId / "subscription" / SubscriptionId / "quarantine")
+        .and(get()).and(with_app(app))
+        .and_then(get_quarantined))
+    .or(path!("v1" / "sub" / "topic" / TopicId / "subscription" / SubscriptionId / "stats")
+        .and(get()).and(with_app(app))
+        .and_then(get_subscription_stats))
+    .or(path!("v1" / "sub" / "topic" / TopicId / "stats")
+        .and(get()).and(with_app(app))
+        .and_then(get_topic_subscription_stats))
     .or(path!("v1" / "sub" / "nodes")
         .and(get()).and(with_app(app))
         .and_then(get_nodes))
@@ -209,6 +506,6 @@ pub fn routes(app: &Arc<App>) -> impl Filter<Extract = impl Reply, Error = Rejec
         .and(get()).and(with_app(app))
         .and_then(get_topics))
     .or(path!("v1" / "sub" / "consumer")
-        .and(post()).and(body::content_length_limit(512)).and(body::json()).and(with_app(app))
+        .and(post()).and(body::content_length_limit(512)).and(body::json()).and(with_accept(JSON_CONTENT_TYPE)).and(with_app(app))
         .and_then(consume))
 }
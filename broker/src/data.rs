@@ -21,7 +21,14 @@ pub mod partition;
 pub mod subscription;
 pub mod topic;
 
-use crate::persistence::{entity_persister::LoadError, Keyed, PersistenceLayer};
+mod ledger_rollovers;
+
+use crate::{
+    observability::Metrics,
+    persistence::{entity_persister::LoadError, Keyed, PersistenceLayer},
+};
+use ledger_rollovers::LedgerRollovers;
+use pulsar_rust_net::data_types::Timestamp;
 use serde::Deserialize;
 use std::{fmt::Debug, sync::Arc};
 
@@ -44,12 +51,20 @@ pub type DataReadResult<T> = Result<T, DataReadError>;
 pub enum DataAddError {
     Duplicate { msg: String },
     PersistenceFailure { msg: String },
+    NoNodes,
 }
 pub type DataAddResult<T> = Result<T, DataAddError>;
 
+// The number of partitions given to topics that are created without specifying how
+// many partitions they should have
+const DEFAULT_PARTITION_COUNT: usize = 1;
+
 pub struct DataLayer {
     cluster_name: String,
     persistence: Arc<PersistenceLayer>,
+    default_partition_count: usize,
+    metrics: Option<Arc<Metrics>>,
+    ledger_rollovers: LedgerRollovers,
 }
 
 impl DataLayer {
@@ -57,9 +72,41 @@ impl DataLayer {
         Self {
             cluster_name,
             persistence: Arc::clone(persistence),
+            default_partition_count: DEFAULT_PARTITION_COUNT,
+            metrics: None,
+            ledger_rollovers: LedgerRollovers::new(),
         }
     }
 
+    /// The persistence layer that entities are saved to, which also holds the event log
+    pub fn persistence(self: &Self) -> &Arc<PersistenceLayer> {
+        &self.persistence
+    }
+
+    /// Sets the number of partitions that are added to topics created by `add_topic_with_default_partitions`
+    pub fn with_default_partition_count(mut self: Self, partition_count: usize) -> Self {
+        self.default_partition_count = partition_count.max(1);
+        self
+    }
+
+    /// Counts significant changes to the data, such as ledger creation, in these metrics
+    pub fn with_metrics(mut self: Self, metrics: &Arc<Metrics>) -> Self {
+        self.metrics = Some(Arc::clone(metrics));
+        self
+    }
+
+    /// Logs a warning when a partition gets more than `max_rollovers` new ledgers within
+    /// the window. Frequent rollovers mean the cluster is misconfigured or message ids are
+    /// being exhausted
+    pub fn with_ledger_rollover_warning(
+        mut self: Self,
+        max_rollovers: usize,
+        window_millis: Timestamp,
+    ) -> Self {
+        self.ledger_rollovers = LedgerRollovers::with_limit(max_rollovers, window_millis);
+        self
+    }
+
     fn get_entity<'b, T: Deserialize<'b>>(self: &Self, key: &impl Keyed) -> DataReadResult<T> {
         match self.persistence.load::<T>(key) {
             Ok(entity) => DataReadResult::Ok(entity),
@@ -2,10 +2,15 @@ use super::{
     DataAddError, DataAddResult, DataLayer, DataReadError, DataReadResult, DataUpdateError,
     DataUpdateResult,
 };
-use crate::persistence::{
-    entity_persister::{DeleteError, SaveError},
-    persisted_entities::{Ledger, Partition},
+use crate::{
+    observability::Metrics,
+    persistence::{
+        entity_persister::{DeleteError, SaveError},
+        persisted_entities::{Ledger, Partition},
+    },
+    utils::now_epoc_millis,
 };
+use log::warn;
 use pulsar_rust_net::data_types::{LedgerId, NodeId, PartitionId, TopicId};
 
 impl DataLayer {
@@ -58,7 +63,10 @@ impl DataLayer {
 
         let mut ledger = Ledger::new(topic_id, partition_id, ledger_id, node_id);
         match self.persistence.save(&mut ledger) {
-            Ok(_) => DataAddResult::Ok(ledger),
+            Ok(_) => {
+                self.ledger_created(&ledger);
+                DataAddResult::Ok(ledger)
+            }
             Err(e) => match e {
                 SaveError::Error { msg } => DataAddResult::Err(DataAddError::PersistenceFailure {
                     msg: msg + " saving the new ledger",
@@ -120,7 +128,10 @@ impl DataLayer {
         // A new ledger id was allocated, so we need to create the ledger record
         let mut ledger = Ledger::new(topic_id, partition_id, ledger_id, node_id);
         match self.persistence.save(&mut ledger) {
-            Ok(_) => DataAddResult::Ok(ledger),
+            Ok(_) => {
+                self.ledger_created(&ledger);
+                DataAddResult::Ok(ledger)
+            }
             Err(e) => match e {
                 SaveError::Error { msg } => DataAddResult::Err(DataAddError::PersistenceFailure {
                     msg: msg + " saving the new ledger",
@@ -202,4 +213,29 @@ impl DataLayer {
             },
         }
     }
+
+    /// Counts a new ledger in the partition, and warns if the partition is rolling over to
+    /// new ledgers more often than expected
+    fn ledger_created(self: &Self, ledger: &Ledger) {
+        if let Some(metrics) = &self.metrics {
+            metrics.incr_partition(
+                Metrics::METRIC_LEDGER_CREATE_COUNT,
+                ledger.topic_id,
+                ledger.partition_id,
+            );
+        }
+
+        if self
+            .ledger_rollovers
+            .record(ledger.topic_id, ledger.partition_id, now_epoc_millis())
+        {
+            warn!(
+                "Partition {} of topic {} has had more than {} new ledgers in {}ms. The cluster may be misconfigured, or message ids are being exhausted",
+                ledger.partition_id,
+                ledger.topic_id,
+                self.ledger_rollovers.max_rollovers(),
+                self.ledger_rollovers.window_millis(),
+            );
+        }
+    }
 }
@@ -0,0 +1,91 @@
+/*
+Remembers when recent ledgers were created in each partition. A partition normally gets a
+new ledger only when it moves to another node or its message ids run out, so many new
+ledgers in a short time means the cluster is misconfigured or message ids are being
+exhausted much faster than expected.
+*/
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+use pulsar_rust_net::data_types::{PartitionId, Timestamp, TopicId};
+
+// By default warn when a partition gets more than this many ledgers in the window
+const DEFAULT_MAX_ROLLOVERS: usize = 5;
+const DEFAULT_WINDOW_MILLIS: Timestamp = 60 * 60 * 1000;
+
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub(super) struct LedgerRollovers {
+    max_rollovers: usize,
+    window_millis: Timestamp,
+    creations: Mutex<HashMap<(TopicId, PartitionId), VecDeque<Timestamp>>>,
+}
+
+impl LedgerRollovers {
+    pub fn new() -> Self {
+        Self::with_limit(DEFAULT_MAX_ROLLOVERS, DEFAULT_WINDOW_MILLIS)
+    }
+
+    pub fn with_limit(max_rollovers: usize, window_millis: Timestamp) -> Self {
+        Self {
+            max_rollovers,
+            window_millis,
+            creations: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn max_rollovers(self: &Self) -> usize {
+        self.max_rollovers
+    }
+
+    pub fn window_millis(self: &Self) -> Timestamp {
+        self.window_millis
+    }
+
+    /// Records the creation of a ledger and returns true if the partition has now had
+    /// more ledgers created within the window than expected
+    pub fn record(
+        self: &Self,
+        topic_id: TopicId,
+        partition_id: PartitionId,
+        timestamp: Timestamp,
+    ) -> bool {
+        let mut creations = self.creations.lock().unwrap();
+        let recent = creations.entry((topic_id, partition_id)).or_default();
+
+        let window_start = timestamp.saturating_sub(self.window_millis);
+        while recent
+            .front()
+            .is_some_and(|&created| created < window_start)
+        {
+            recent.pop_front();
+        }
+        recent.push_back(timestamp);
+
+        recent.len() > self.max_rollovers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LedgerRollovers;
+
+    #[test]
+    fn should_detect_frequent_rollovers_in_each_partition() {
+        let rollovers = LedgerRollovers::with_limit(2, 1000);
+
+        assert!(!rollovers.record(1, 1, 10_000));
+        assert!(!rollovers.record(1, 1, 10_100));
+        assert!(rollovers.record(1, 1, 10_200));
+
+        // Other partitions are counted separately
+        assert!(!rollovers.record(1, 2, 10_300));
+        assert!(!rollovers.record(2, 1, 10_300));
+
+        // Ledgers created before the window are forgotten
+        assert!(!rollovers.record(1, 1, 11_150));
+        assert!(rollovers.record(1, 1, 11_160));
+    }
+}
@@ -4,7 +4,9 @@ use super::{
 };
 use crate::persistence::{
     entity_persister::{DeleteError, SaveError},
-    persisted_entities::{Partition, Topic},
+    log_entries::LoggedEvent,
+    logged_events::PartitionReassignedEvent,
+    persisted_entities::{Ledger, Partition, Topic},
 };
 use pulsar_rust_net::data_types::{NodeId, PartitionId, TopicId};
 
@@ -104,6 +106,65 @@ impl DataLayer {
         }
     }
 
+    /// Moves a partition to another node. A new ledger is started on that node, so that the
+    /// messages published before and after the move are in different ledgers, and an event
+    /// is logged unless the topic is ephemeral. Returns the new ledger
+    pub fn reassign_partition(
+        self: &Self,
+        topic_id: TopicId,
+        partition_id: PartitionId,
+        node_id: NodeId,
+    ) -> DataUpdateResult<Ledger> {
+        let topic = match self.get_topic(topic_id) {
+            Ok(topic) => topic,
+            Err(err) => {
+                return match err {
+                    DataReadError::PersistenceFailure { msg } => {
+                        Err(DataUpdateError::PersistenceFailure { msg })
+                    }
+                    DataReadError::NotFound => Err(DataUpdateError::NotFound),
+                }
+            }
+        };
+
+        let mut from_node_id: NodeId = 0;
+        self.update_partition(topic_id, partition_id, |partition| {
+            if partition.node_id == node_id {
+                return false;
+            }
+            from_node_id = partition.node_id;
+            partition.node_id = node_id;
+            true
+        })?;
+
+        let ledger = match self.add_ledger(topic_id, partition_id, node_id) {
+            Ok(ledger) => ledger,
+            Err(err) => {
+                return Err(DataUpdateError::PersistenceFailure {
+                    msg: format!(
+                        "Failed to add a ledger for partition {partition_id} of topic {topic_id} on node {node_id}. {err:?}"
+                    ),
+                })
+            }
+        };
+
+        if !topic.ephemeral {
+            let _ = self
+                .persistence
+                .log_event(&LoggedEvent::PartitionReassigned(
+                    PartitionReassignedEvent {
+                        topic_id,
+                        partition_id,
+                        ledger_id: ledger.ledger_id,
+                        from_node_id,
+                        to_node_id: node_id,
+                    },
+                ));
+        }
+
+        Ok(ledger)
+    }
+
     pub fn update_partition<F>(
         self: &Self,
         topic_id: TopicId,
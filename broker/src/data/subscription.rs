@@ -1,7 +1,13 @@
 use super::*;
-use crate::persistence::{
-    entity_persister::{DeleteError, SaveError},
-    persisted_entities::{Subscription, Topic},
+use crate::{
+    persistence::{
+        entity_persister::{DeleteError, SaveError},
+        persisted_entities::{
+            DeliveryOrder, DeliverySemantics, DeliveryTransform, QueueOverflowPolicy, Subscription,
+            SubscriptionType, Topic,
+        },
+    },
+    utils::now_epoc_millis,
 };
 use pulsar_rust_net::data_types::{SubscriptionId, TopicId};
 
@@ -22,12 +28,65 @@ impl DataLayer {
         Ok(subscriptions)
     }
 
+    /// Adds a key-shared subscription to the topic if `has_key_affinity` is true, otherwise
+    /// a shared subscription. Use `set_subscription_type` for the other subscription types
     pub fn add_subscription(
         self: &Self,
         topic_id: TopicId,
         name: &str,
         has_key_affinity: bool,
     ) -> DataAddResult<Subscription> {
+        self.insert_subscription(topic_id, |subscription_id| {
+            Subscription::new(
+                topic_id,
+                subscription_id,
+                name.to_owned(),
+                if has_key_affinity {
+                    SubscriptionType::KeyShared
+                } else {
+                    SubscriptionType::Shared
+                },
+                1,
+            )
+        })
+    }
+
+    /// Adds a subscription to the topic with the same type and settings as an existing
+    /// subscription. The new subscription starts with no consumers, and receives messages
+    /// published after it was added
+    pub fn copy_subscription(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        name: &str,
+    ) -> DataAddResult<Subscription> {
+        let template = match self.get_subscription(topic_id, subscription_id) {
+            Ok(subscription) => subscription,
+            Err(err) => {
+                return Err(DataAddError::PersistenceFailure {
+                    msg: format!("Failed to read subscription {subscription_id}. {:?}", err),
+                })
+            }
+        };
+
+        self.insert_subscription(topic_id, |subscription_id| Subscription {
+            version: 0,
+            subscription_id,
+            name: name.to_owned(),
+            next_consumer_id: 1,
+            created: now_epoc_millis(),
+            ..template
+        })
+    }
+
+    fn insert_subscription<F>(
+        self: &Self,
+        topic_id: TopicId,
+        create: F,
+    ) -> DataAddResult<Subscription>
+    where
+        F: FnOnce(SubscriptionId) -> Subscription,
+    {
         let mut subscription_id: SubscriptionId = 0;
 
         if let Err(err) = self.update_topic(topic_id, |topic| {
@@ -41,13 +100,7 @@ impl DataLayer {
             });
         }
 
-        let mut subscription = Subscription::new(
-            topic_id,
-            subscription_id,
-            name.to_owned(),
-            has_key_affinity,
-            1,
-        );
+        let mut subscription = create(subscription_id);
         match self.persistence.save(&mut subscription) {
             Ok(_) => DataAddResult::Ok(subscription),
             Err(e) => match e {
@@ -64,6 +117,194 @@ impl DataLayer {
         }
     }
 
+    /// Changes how messages are shared between the consumers of a subscription. This takes
+    /// effect when the subscription is next loaded into the cluster
+    pub fn set_subscription_type(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        subscription_type: SubscriptionType,
+    ) -> DataUpdateResult<Subscription> {
+        self.update_subscription(topic_id, subscription_id, |subscription| {
+            subscription.subscription_type = subscription_type;
+            true
+        })
+    }
+
+    /// Limits the number of messages that can be queued for a subscription. A depth of zero
+    /// means that the queue is unbounded
+    pub fn set_subscription_queue_limit(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        max_queue_depth: usize,
+        queue_overflow_policy: QueueOverflowPolicy,
+    ) -> DataUpdateResult<Subscription> {
+        self.update_subscription(topic_id, subscription_id, |subscription| {
+            subscription.max_queue_depth = max_queue_depth;
+            subscription.queue_overflow_policy = queue_overflow_policy;
+            true
+        })
+    }
+
+    /// Changes the order in which queued messages are delivered to consumers
+    pub fn set_subscription_delivery_order(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        delivery_order: DeliveryOrder,
+    ) -> DataUpdateResult<Subscription> {
+        self.update_subscription(topic_id, subscription_id, |subscription| {
+            subscription.delivery_order = delivery_order;
+            true
+        })
+    }
+
+    /// Changes whether delivered messages are kept until they are acked, or acked as they
+    /// are delivered
+    pub fn set_subscription_delivery_semantics(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        delivery_semantics: DeliverySemantics,
+    ) -> DataUpdateResult<Subscription> {
+        self.update_subscription(topic_id, subscription_id, |subscription| {
+            subscription.delivery_semantics = delivery_semantics;
+            true
+        })
+    }
+
+    /// Messages older than the max age are skipped rather than delivered to consumers.
+    /// A max age of zero means that messages are delivered regardless of their age
+    pub fn set_subscription_max_message_age(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        max_message_age_millis: u64,
+    ) -> DataUpdateResult<Subscription> {
+        self.update_subscription(topic_id, subscription_id, |subscription| {
+            subscription.max_message_age_millis = max_message_age_millis;
+            true
+        })
+    }
+
+    /// For key-shared subscriptions, messages that were assigned to a consumer because of key
+    /// affinity, but not consumed within this timeout, are reassigned to another consumer.
+    /// A timeout of zero means that messages remain assigned until the consumer disconnects
+    pub fn set_subscription_assignment_timeout(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        assignment_timeout_millis: u64,
+    ) -> DataUpdateResult<Subscription> {
+        self.update_subscription(topic_id, subscription_id, |subscription| {
+            subscription.assignment_timeout_millis = assignment_timeout_millis;
+            true
+        })
+    }
+
+    /// For key-shared subscriptions, strict ordering stops a message from being delivered while
+    /// an earlier message with the same key is unacked, so that a nacked message is always
+    /// processed before the messages that follow it
+    pub fn set_subscription_strict_ordering(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        strict_ordering: bool,
+    ) -> DataUpdateResult<Subscription> {
+        self.update_subscription(topic_id, subscription_id, |subscription| {
+            subscription.strict_ordering = strict_ordering;
+            true
+        })
+    }
+
+    /// Limits the number of consumers that can be connected to a subscription at the same time.
+    /// A limit of zero means that any number of consumers can connect
+    pub fn set_subscription_max_consumers(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        max_consumers: usize,
+    ) -> DataUpdateResult<Subscription> {
+        self.update_subscription(topic_id, subscription_id, |subscription| {
+            subscription.max_consumers = max_consumers;
+            true
+        })
+    }
+
+    /// Messages that are nacked are not redelivered until this delay has elapsed, so that a
+    /// consumer that repeatedly fails to process a message does not receive it in a tight loop.
+    /// A delay of zero means that nacked messages can be redelivered immediately
+    pub fn set_subscription_nack_redelivery_delay(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        nack_redelivery_delay_millis: u64,
+    ) -> DataUpdateResult<Subscription> {
+        self.update_subscription(topic_id, subscription_id, |subscription| {
+            subscription.nack_redelivery_delay_millis = nack_redelivery_delay_millis;
+            true
+        })
+    }
+
+    /// Messages that are delivered to a consumer and not acked within this time are treated as
+    /// if they were nacked, so that they are redelivered. A timeout of zero means that
+    /// delivered messages wait for an ack or nack indefinitely
+    pub fn set_subscription_ack_timeout(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        ack_timeout_millis: u64,
+    ) -> DataUpdateResult<Subscription> {
+        self.update_subscription(topic_id, subscription_id, |subscription| {
+            subscription.ack_timeout_millis = ack_timeout_millis;
+            true
+        })
+    }
+
+    /// Messages that are about to be delivered more than this number of times are moved to the
+    /// dead letter topic of the subscription instead, so that a message that consumers can never
+    /// process is not redelivered forever. A count of zero means that there is no limit
+    pub fn set_subscription_max_delivery_count(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        max_delivery_count: usize,
+    ) -> DataUpdateResult<Subscription> {
+        self.update_subscription(topic_id, subscription_id, |subscription| {
+            subscription.max_delivery_count = max_delivery_count;
+            true
+        })
+    }
+
+    /// The name of the topic that messages are moved to when they reach the max delivery count.
+    /// When this is None the topic is named after the topic and subscription, with a DLQ suffix
+    pub fn set_subscription_dead_letter_topic(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        dead_letter_topic: Option<&str>,
+    ) -> DataUpdateResult<Subscription> {
+        self.update_subscription(topic_id, subscription_id, |subscription| {
+            subscription.dead_letter_topic = dead_letter_topic.map(|name| name.to_owned());
+            true
+        })
+    }
+
+    /// Replaces the rules that are applied to messages as they are delivered to the consumers
+    /// of this subscription. The rules are applied in order
+    pub fn set_subscription_delivery_transforms(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        delivery_transforms: Vec<DeliveryTransform>,
+    ) -> DataUpdateResult<Subscription> {
+        self.update_subscription(topic_id, subscription_id, |subscription| {
+            subscription.delivery_transforms = delivery_transforms.clone();
+            true
+        })
+    }
+
     pub fn delete_subscription(
         self: &Self,
         topic_id: TopicId,
@@ -59,6 +59,92 @@ impl DataLayer {
         }
     }
 
+    /// Adds a topic with the default number of partitions, distributing the partitions
+    /// across the nodes in the cluster. Each partition is created with an empty ledger
+    pub fn add_topic_with_default_partitions(self: &Self, name: &str) -> DataAddResult<Topic> {
+        let nodes = match self.get_nodes() {
+            Ok(nodes) => nodes,
+            Err(err) => {
+                return Err(DataAddError::PersistenceFailure {
+                    msg: format!("Failed to get nodes. {:?}", err),
+                })
+            }
+        };
+        if nodes.is_empty() {
+            return Err(DataAddError::NoNodes);
+        }
+
+        let topic = self.add_topic(name)?;
+        for index in 0..self.default_partition_count {
+            let node_id = nodes[index % nodes.len()].node_id;
+            let partition = self.add_partition(topic.topic_id, node_id)?;
+            self.add_ledger(topic.topic_id, partition.partition_id, node_id)?;
+        }
+
+        match self.get_topic(topic.topic_id) {
+            Ok(topic) => Ok(topic),
+            Err(err) => Err(DataAddError::PersistenceFailure {
+                msg: format!("Failed to reload topic. {:?}", err),
+            }),
+        }
+    }
+
+    /// Ephemeral topics do not log events for the messages that are published to them, or for
+    /// the consumers of their subscriptions. This makes them faster, but their messages are
+    /// lost when the broker restarts
+    pub fn set_topic_ephemeral(
+        self: &Self,
+        topic_id: TopicId,
+        ephemeral: bool,
+    ) -> DataUpdateResult<Topic> {
+        self.update_topic(topic_id, |topic| {
+            topic.ephemeral = ephemeral;
+            true
+        })
+    }
+
+    /// Messages published with an idempotency key attribute are not published again if a
+    /// message with the same key was published to the topic within this window. A window of
+    /// zero disables duplicate detection for the topic
+    pub fn set_topic_idempotency_window(
+        self: &Self,
+        topic_id: TopicId,
+        idempotency_window_millis: u64,
+    ) -> DataUpdateResult<Topic> {
+        self.update_topic(topic_id, |topic| {
+            topic.idempotency_window_millis = idempotency_window_millis;
+            true
+        })
+    }
+
+    /// Messages that are still queued on a subscription this long after they were published
+    /// are removed without being delivered. Messages that were already delivered to a consumer
+    /// are not affected. A TTL of zero means that messages never expire
+    pub fn set_topic_message_ttl(
+        self: &Self,
+        topic_id: TopicId,
+        message_ttl_millis: u64,
+    ) -> DataUpdateResult<Topic> {
+        self.update_topic(topic_id, |topic| {
+            topic.message_ttl_millis = message_ttl_millis;
+            true
+        })
+    }
+
+    /// Messages published with a producer id and sequence number are discarded if the producer
+    /// already published that sequence number to the partition. Topics without deduplication
+    /// do not track producers at all
+    pub fn set_topic_sequence_dedup(
+        self: &Self,
+        topic_id: TopicId,
+        sequence_dedup: bool,
+    ) -> DataUpdateResult<Topic> {
+        self.update_topic(topic_id, |topic| {
+            topic.sequence_dedup = sequence_dedup;
+            true
+        })
+    }
+
     pub fn delete_topic(self: &Self, topic_id: TopicId) -> DataUpdateResult<()> {
         let topic = match self.get_topic(topic_id) {
             Ok(topic) => topic,
@@ -1,12 +1,16 @@
 #[macro_use]
 extern crate lazy_static;
 
+use log::{info, warn};
 use observability::Metrics;
 use persistence::PersistenceLayer;
 use services::pub_service::PubService;
 use services::sub_service::SubService;
 use services::{admin_service::AdminService, stats_service::StatsService};
-use std::sync::{atomic::AtomicBool, Arc};
+use std::sync::{
+    atomic::{AtomicBool, AtomicU8, Ordering},
+    Arc,
+};
 
 /// This module is updated with a randomly generated build number automatically on each build
 /// The build number is used as a version identifier
@@ -47,8 +51,13 @@ mod utils;
 /// Metrics and logging
 pub mod observability;
 
+/// Builders that construct populated clusters for use in tests
+#[cfg(feature = "test-support")]
+pub mod test_support;
+
 pub struct App {
     pub stop_signal: Arc<AtomicBool>,
+    pub run_state: Arc<AtomicU8>,
     pub metrics: Arc<Metrics>,
     pub peristence: Arc<PersistenceLayer>,
     pub pub_service: Arc<PubService>,
@@ -56,3 +65,112 @@ pub struct App {
     pub admin_service: Arc<AdminService>,
     pub stats_service: Arc<StatsService>,
 }
+
+/// The states that the application moves through when it stops. The state is stored in
+/// `App::run_state` as a `u8` so that the API threads can check it without locking
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+#[repr(u8)]
+pub enum RunState {
+    /// Accepting connections and processing requests
+    Running = 0,
+
+    /// New connections are refused, but requests that were already received are processed
+    /// and their responses are sent, until there are none left or the drain timeout elapses
+    Draining = 1,
+
+    /// The stop signal is set
+    Stopped = 2,
+}
+
+impl From<u8> for RunState {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => RunState::Running,
+            1 => RunState::Draining,
+            _ => RunState::Stopped,
+        }
+    }
+}
+
+/// The stages of shutting down the application, in the order that they happen
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub enum ShutdownStage {
+    /// The stop signal is set, so the APIs stop accepting connections and requests
+    StopIngress,
+
+    /// Deferred publishes and batched acks are applied to the subscriptions
+    DrainProcessing,
+
+    /// The event log is flushed to durable storage
+    FlushPersistence,
+
+    /// Background threads owned by the services are stopped
+    StopBackgroundTasks,
+}
+
+impl App {
+    pub fn run_state(self: &Self) -> RunState {
+        RunState::from(self.run_state.load(Ordering::Relaxed))
+    }
+
+    /// Stops accepting new connections, and lets the binary API finish the requests it
+    /// already received before setting the stop signal. Returns false if the app was not
+    /// running
+    pub fn begin_drain(self: &Self) -> bool {
+        let began = self
+            .run_state
+            .compare_exchange(
+                RunState::Running as u8,
+                RunState::Draining as u8,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            )
+            .is_ok();
+        if began {
+            info!("App: Draining");
+        }
+        began
+    }
+
+    /// Sets the stop signal, whether or not the app finished draining
+    pub fn stop(self: &Self) {
+        self.run_state
+            .store(RunState::Stopped as u8, Ordering::Relaxed);
+        self.stop_signal.store(true, Ordering::Relaxed);
+    }
+
+    /// Shuts the application down in an order that does not lose work that was already
+    /// accepted. Requests must stop arriving before the work they create is drained, and
+    /// the drained work must be logged before the event log is flushed
+    pub fn shutdown(self: &Self) {
+        self.shutdown_with_observer(|_| {});
+    }
+
+    /// As for `shutdown`, but calls the observer after each stage is complete
+    pub fn shutdown_with_observer<F>(self: &Self, mut observer: F)
+    where
+        F: FnMut(ShutdownStage),
+    {
+        info!("App: Shutting down");
+
+        self.stop();
+        observer(ShutdownStage::StopIngress);
+
+        self.pub_service.flush_publishes();
+        self.sub_service.flush_acks();
+        observer(ShutdownStage::DrainProcessing);
+
+        if let Err(err) = self.peristence.flush_events() {
+            warn!("App: Failed to flush the event log. {err:?}");
+        }
+        observer(ShutdownStage::FlushPersistence);
+
+        self.pub_service.stop();
+        self.sub_service.stop();
+        observer(ShutdownStage::StopBackgroundTasks);
+
+        info!("App: Shut down");
+    }
+}
@@ -1,29 +1,37 @@
 use config::Config;
 use log::LevelFilter;
 use pulsar_rust_broker::{
-    api_bin, api_http,
+    api_bin::{self, ConnectionLimits, RequestLimits},
+    api_http,
     data::DataLayer,
-    model::cluster::Cluster,
+    model::cluster::{BootstrapError, Cluster, NodeBootstrap},
     observability::Metrics,
-    persistence::{PersistenceLayer, PersistenceScheme},
+    persistence::{PersistenceLayer, PersistenceScheme, DEFAULT_DATA_DIR},
     services::{
         admin_service::AdminService, pub_service::PubService, stats_service::StatsService,
-        sub_service::SubService,
+        sub_service::{SerializationErrorPolicy, SubService},
     },
-    App,
+    App, RunState,
 };
 use std::{
     collections::HashMap,
     env,
     net::{Ipv4Addr, SocketAddrV4},
+    path::Path,
     str::FromStr,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU8},
         Arc,
     },
+    time::Duration,
 };
 use tokio::task;
 
+use pulsar_rust_net::{
+    data_types::{ContractVersionNumber, PortNumber},
+    sockets::tls::ConnectionSecurity,
+};
+
 use pulsar_rust_broker::model::cluster::{
     DEFAULT_ADMIN_PORT, DEFAULT_PUBSUB_PORT, DEFAULT_SYNC_PORT,
 };
@@ -75,13 +83,56 @@ async fn main() {
         PersistenceScheme::from_string(settings.get("persist-events").unwrap());
     let entity_persistence_scheme =
         PersistenceScheme::from_string(settings.get("persist-state").unwrap());
-    let persistence_layer = Arc::new(PersistenceLayer::new(
+    let event_log_capacity = match settings.get("event-log-capacity") {
+        Some(capacity) => capacity.parse::<usize>().unwrap(),
+        None => 0,
+    };
+    let event_spillover_scheme = settings
+        .get("event-log-spillover")
+        .map(|scheme| PersistenceScheme::from_string(scheme));
+    let data_dir = settings
+        .get("data-dir")
+        .map_or(DEFAULT_DATA_DIR, |data_dir| data_dir.as_str());
+    let persistence_layer = Arc::new(PersistenceLayer::with_data_dir(
         event_persistence_scheme,
         entity_persistence_scheme,
+        event_log_capacity,
+        event_spillover_scheme,
+        Path::new(data_dir),
     ));
 
     // Build a data access layer on top of the persistence layer
-    let data_layer = Arc::new(DataLayer::new(cluster_name.to_owned(), &persistence_layer));
+    let default_partition_count = match settings.get("default-partition-count") {
+        Some(count) => count.parse::<usize>().unwrap(),
+        None => 1,
+    };
+    let metrics = Arc::new(Metrics::new());
+    let mut data_layer = DataLayer::new(cluster_name.to_owned(), &persistence_layer)
+        .with_default_partition_count(default_partition_count)
+        .with_metrics(&metrics);
+
+    // Optionally warn when partitions roll over to new ledgers more often than this
+    if let Some(max_rollovers) = settings.get("ledger-rollover-warning-count") {
+        let window_millis = settings
+            .get("ledger-rollover-warning-millis")
+            .map_or(60 * 60 * 1000, |millis| millis.parse::<u64>().unwrap());
+        data_layer = data_layer
+            .with_ledger_rollover_warning(max_rollovers.parse::<usize>().unwrap(), window_millis);
+    }
+    let data_layer = Arc::new(data_layer);
+
+    // A node registers itself in the cluster with these ports the first time it starts
+    let node_port = |name: &str, default: PortNumber| {
+        settings
+            .get(name)
+            .map_or(default, |port| port.parse::<PortNumber>().unwrap())
+    };
+    let node_bootstrap = NodeBootstrap {
+        ip_address: ip_address.to_owned(),
+        admin_port: node_port("admin-port", DEFAULT_ADMIN_PORT),
+        pubsub_port: node_port("pubsub-port", DEFAULT_PUBSUB_PORT),
+        sync_port: node_port("sync-port", DEFAULT_SYNC_PORT),
+    };
 
     // If this is a debug build, then delete all of the data and build a dev configuration
     #[cfg(debug_assertions)]
@@ -93,9 +144,9 @@ async fn main() {
         let node = data_layer
             .add_node(
                 &ip_address,
-                DEFAULT_ADMIN_PORT,
-                DEFAULT_PUBSUB_PORT,
-                DEFAULT_SYNC_PORT,
+                node_bootstrap.admin_port,
+                node_bootstrap.pubsub_port,
+                node_bootstrap.sync_port,
             )
             .unwrap();
         let topic1 = data_layer.add_topic("topic-1").unwrap();
@@ -169,39 +220,180 @@ async fn main() {
     }
 
     // Temporary code for performance testing only. This code should be removed once the
-    // broker stores its configuration persistently
+    // broker stores its configuration persistently. Only seeds an empty cluster so that
+    // restarting a node does not add another topic
     #[cfg(not(debug_assertions))]
-    {
-        let node = data_layer
-            .add_node(&ip_address,DEFAULT_ADMIN_PORT,DEFAULT_PUBSUB_PORT,DEFAULT_SYNC_PORT)
-            .unwrap();
+    if data_layer.get_cluster().unwrap().topic_ids.is_empty() {
+        let node_id = bootstrap_cluster(&data_layer, &node_bootstrap).my_node_id();
         let topic = data_layer.add_topic("topic").unwrap();
-        let partition = data_layer.add_partition(topic.topic_id, node.node_id)
+        let partition = data_layer.add_partition(topic.topic_id, node_id)
             .unwrap();
         data_layer.add_subscription(topic.topic_id, "perftest", false)
             .unwrap();
-        data_layer.add_ledger(partition.topic_id,partition.partition_id,node.node_id)
+        data_layer.add_ledger(partition.topic_id,partition.partition_id,node_id)
             .unwrap();
     }
 
     // Cluster is at the root of thr data model
-    let cluster = Arc::new(Cluster::new(&data_layer, ip_address));
+    let cluster = Arc::new(bootstrap_cluster(&data_layer, &node_bootstrap));
+
+    // Acks can optionally be applied in batches to reduce lock contention
+    let ack_batch_window = settings
+        .get("ack-batch-millis")
+        .map(|millis| Duration::from_millis(millis.parse::<u64>().unwrap()));
+
+    // Subscriptions are swept for messages that were not acked within their ack timeout
+    let ack_timeout_sweep_interval = Duration::from_millis(
+        settings
+            .get("ack-timeout-sweep-millis")
+            .map_or(1000, |millis| millis.parse::<u64>().unwrap()),
+    );
+
+    // Subscriptions are swept for queued messages that are older than their topic's TTL
+    let message_expiry_sweep_interval = Duration::from_millis(
+        settings
+            .get("message-expiry-sweep-millis")
+            .map_or(1000, |millis| millis.parse::<u64>().unwrap()),
+    );
+
+    // Messages published with a delivery delay are added to their subscriptions when they are due
+    let delayed_delivery_sweep_interval = Duration::from_millis(
+        settings
+            .get("delayed-delivery-sweep-millis")
+            .map_or(100, |millis| millis.parse::<u64>().unwrap()),
+    );
+
+    // Messages that can not be serialized are skipped unless configured to be discarded
+    let serialization_error_policy = match settings
+        .get("serialization-error-policy")
+        .map(|policy| policy.as_str())
+    {
+        None | Some("skip") => SerializationErrorPolicy::Skip,
+        Some("discard") => SerializationErrorPolicy::Discard,
+        Some(policy) => panic!("Unknown serialization error policy {policy}"),
+    };
+
+    // Binary API requests with more content than these limits are rejected
+    let default_limits = RequestLimits::default();
+    let request_limit = |name: &str, default: usize| {
+        settings
+            .get(name)
+            .map_or(default, |bytes| bytes.parse::<usize>().unwrap())
+    };
+    let request_limits = RequestLimits {
+        max_publish_bytes: request_limit("max-publish-bytes", default_limits.max_publish_bytes),
+        max_ack_bytes: request_limit("max-ack-bytes", default_limits.max_ack_bytes),
+        max_nack_bytes: request_limit("max-nack-bytes", default_limits.max_nack_bytes),
+        max_quarantine_bytes: request_limit(
+            "max-quarantine-bytes",
+            default_limits.max_quarantine_bytes,
+        ),
+        max_join_group_bytes: request_limit(
+            "max-join-group-bytes",
+            default_limits.max_join_group_bytes,
+        ),
+        max_publish_batch_count: request_limit(
+            "max-publish-batch-count",
+            default_limits.max_publish_batch_count,
+        ),
+        // Setting the timeout to zero processes requests without a timeout
+        processing_timeout: settings.get("processing-timeout-millis").map_or(
+            default_limits.processing_timeout,
+            |millis| match millis.parse::<u64>().unwrap() {
+                0 => None,
+                millis => Some(Duration::from_millis(millis)),
+            },
+        ),
+        drain_timeout: settings
+            .get("drain-timeout-millis")
+            .map_or(default_limits.drain_timeout, |millis| {
+                Duration::from_millis(millis.parse::<u64>().unwrap())
+            }),
+        max_contract_version: settings
+            .get("max-contract-version")
+            .map_or(default_limits.max_contract_version, |version| {
+                version.parse::<ContractVersionNumber>().unwrap()
+            }),
+    };
+
+    // Binary API connections beyond these limits are refused or closed
+    let default_connection_limits = ConnectionLimits::default();
+    let connection_limits = ConnectionLimits {
+        listen_backlog: settings
+            .get("listen-backlog")
+            .map_or(default_connection_limits.listen_backlog, |backlog| {
+                backlog.parse::<i32>().unwrap()
+            }),
+        max_connections_per_ip: settings
+            .get("max-connections-per-ip")
+            .map_or(default_connection_limits.max_connections_per_ip, |count| {
+                count.parse::<usize>().unwrap()
+            }),
+        max_message_size: settings
+            .get("max-message-size")
+            .map_or(default_connection_limits.max_message_size, |size| {
+                size.parse::<usize>().unwrap()
+            }),
+        max_pooled_buffers: settings
+            .get("max-pooled-buffers")
+            .map_or(default_connection_limits.max_pooled_buffers, |count| {
+                count.parse::<usize>().unwrap()
+            }),
+        max_pooled_buffer_capacity: settings.get("max-pooled-buffer-capacity").map_or(
+            default_connection_limits.max_pooled_buffer_capacity,
+            |capacity| capacity.parse::<usize>().unwrap(),
+        ),
+    };
+
+    // Binary API connections are encrypted with TLS when a certificate is configured. Clients
+    // must also present a certificate when a client certificate authority is configured
+    let connection_security = match (settings.get("tls-cert-file"), settings.get("tls-key-file")) {
+        (Some(cert_file), Some(key_file)) => ConnectionSecurity::tls_server(
+            cert_file,
+            key_file,
+            settings.get("tls-client-ca-file").map(|path| path.as_str()),
+        )
+        .unwrap_or_else(|err| panic!("Failed to configure TLS. {err}")),
+        (None, None) => ConnectionSecurity::Plain,
+        _ => panic!("Both tls-cert-file and tls-key-file must be configured to enable TLS"),
+    };
+
+    let pub_service = Arc::new(
+        PubService::new(&persistence_layer, &cluster)
+            .with_delayed_delivery(delayed_delivery_sweep_interval),
+    );
 
     // App is a container for the application singletons. Injecting App is much simpler than injecting dependnecies individually.
     // The application owns Arcs and the Arcs own the singeltons.
     let app = Arc::new(App {
         stop_signal: Arc::new(AtomicBool::new(false)),
-        metrics: Arc::new(Metrics::new()),
+        run_state: Arc::new(AtomicU8::new(RunState::Running as u8)),
+        metrics,
         peristence: Arc::clone(&persistence_layer),
-        pub_service: Arc::new(PubService::new(&persistence_layer, &cluster)),
-        sub_service: Arc::new(SubService::new(&persistence_layer, &cluster)),
-        admin_service: Arc::new(AdminService::new(&cluster)),
+        pub_service: Arc::clone(&pub_service),
+        sub_service: Arc::new(
+            match ack_batch_window {
+                Some(window) => SubService::with_ack_batching(&persistence_layer, &cluster, window),
+                None => SubService::new(&persistence_layer, &cluster),
+            }
+            .with_serialization_error_policy(serialization_error_policy)
+            .with_ack_timeouts(ack_timeout_sweep_interval)
+            .with_message_expiry(message_expiry_sweep_interval)
+            .with_dead_letters(&pub_service),
+        ),
+        admin_service: Arc::new(AdminService::new(&persistence_layer, &cluster)),
         stats_service: Arc::new(StatsService::new(&cluster)),
     });
 
-    // Handle SIGTERM by setting the stop_signal boolean
-    let stop_signal = app.stop_signal.clone();
-    ctrlc::set_handler(move || stop_signal.store(true, Ordering::Relaxed)).unwrap();
+    // Handle SIGTERM by draining the binary API before it stops. A second SIGTERM stops
+    // without waiting for the drain to complete
+    let signalled_app = Arc::clone(&app);
+    ctrlc::set_handler(move || {
+        if !signalled_app.begin_drain() {
+            signalled_app.stop();
+        }
+    })
+    .unwrap();
 
     // Start sending metrics to StatsD
     task::spawn(send_metrics(Arc::clone(&app)));
@@ -215,7 +407,13 @@ async fn main() {
 
     // Serve binary serialized requests over TCP/IP
     let admin_endpoint = SocketAddrV4::new(ip_address, my_node.pubsub_port());
-    let api_bin_handle = api_bin::serve(&app, admin_endpoint);
+    let api_bin_handle = api_bin::serve_with_security(
+        &app,
+        admin_endpoint,
+        request_limits,
+        connection_limits,
+        connection_security,
+    );
 
     // Serve requests over http using warp and wait for it to terminate
     let admin_endpoint = SocketAddrV4::new(ip_address, my_node.admin_port());
@@ -223,6 +421,26 @@ async fn main() {
 
     // Wait for the bin api to terminate
     api_bin_handle.join().unwrap();
+
+    // Drain and flush anything the APIs accepted before they stopped
+    app.shutdown();
+}
+
+/// Loads the cluster model, registering this node if this is the first time it started.
+/// Exits with an explanation if the node can not be registered
+fn bootstrap_cluster(data_layer: &Arc<DataLayer>, node_bootstrap: &NodeBootstrap) -> Cluster {
+    match Cluster::bootstrap(data_layer, node_bootstrap) {
+        Ok(cluster) => cluster,
+        Err(BootstrapError::MissingIpAddress) => panic!(
+            "No IP address to bootstrap this node with. Pass the IP address of the network interface to listen on as the 3rd command line argument"
+        ),
+        Err(BootstrapError::InvalidIpAddress { ip_address }) => panic!(
+            "Can not bootstrap this node with IP address '{ip_address}'. The 3rd command line argument must be an IPv4 address"
+        ),
+        Err(BootstrapError::PersistenceFailure { msg }) => {
+            panic!("Failed to bootstrap this node in the cluster. {msg}")
+        }
+    }
 }
 
 async fn send_metrics(app: Arc<App>) {
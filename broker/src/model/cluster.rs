@@ -1,16 +1,23 @@
 use super::{
     node::{Node, NodeList, NodeRef},
-    topic::{Topic, TopicList, TopicStats},
-    EntityList, RefreshStatus,
+    partition::PartitionRef,
+    subscription::SubscriptionRef,
+    topic::{Topic, TopicList, TopicRef, TopicStats},
+    EntityList, EntityRef, RefreshStatus,
 };
 use crate::{
-    data::DataLayer,
+    data::{DataAddResult, DataLayer, DataUpdateResult},
     formatting::plain_text_builder::{PlainTextBuilder, ToPlainText},
     persistence::persisted_entities,
 };
-use pulsar_rust_net::data_types::{NodeId, PortNumber, TopicId};
+use log::info;
+use pulsar_rust_net::data_types::{NodeId, PartitionId, PortNumber, SubscriptionId, TopicId};
 use serde::Serialize;
-use std::sync::{Arc, RwLock};
+use std::{
+    net::Ipv4Addr,
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
 
 #[cfg_attr(debug_assertions, derive(Debug))]
 #[derive(Clone, Serialize)]
@@ -78,6 +85,40 @@ pub const DEFAULT_ADMIN_PORT: PortNumber = 8000;
 pub const DEFAULT_PUBSUB_PORT: PortNumber = 8001;
 pub const DEFAULT_SYNC_PORT: PortNumber = 8002;
 
+/// The information that a node uses to register itself in the cluster the first time it
+/// starts. When the node is already registered, the stored node record is used instead
+#[derive(Clone)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct NodeBootstrap {
+    pub ip_address: String,
+    pub admin_port: PortNumber,
+    pub pubsub_port: PortNumber,
+    pub sync_port: PortNumber,
+}
+
+impl NodeBootstrap {
+    pub fn new(ip_address: &str) -> Self {
+        Self {
+            ip_address: ip_address.to_owned(),
+            admin_port: DEFAULT_ADMIN_PORT,
+            pubsub_port: DEFAULT_PUBSUB_PORT,
+            sync_port: DEFAULT_SYNC_PORT,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BootstrapError {
+    /// No IP address was configured, so the node can not find or register itself
+    MissingIpAddress,
+
+    /// The configured IP address is not a valid IPv4 address
+    InvalidIpAddress { ip_address: String },
+
+    /// The cluster or node records could not be read or written
+    PersistenceFailure { msg: String },
+}
+
 impl Cluster {
     pub fn nodes(self: &Self) -> &NodeList {
         &self.nodes
@@ -91,26 +132,73 @@ impl Cluster {
         self.my_node_id
     }
 
+    /// Loads the cluster model, registering this node in the cluster with default ports if
+    /// it is not already registered. Panics if the node can not be bootstrapped
     pub fn new(data_layer: &Arc<DataLayer>, my_ip_address: &str) -> Self {
-        let mut cluster = data_layer.get_cluster().unwrap();
+        match Self::bootstrap(data_layer, &NodeBootstrap::new(my_ip_address)) {
+            Ok(cluster) => cluster,
+            Err(err) => panic!("Failed to bootstrap node {my_ip_address}. {err:?}"),
+        }
+    }
 
-        let my_node_id = match cluster
-            .node_ids
-            .iter()
-            .map(|&node_id| data_layer.get_node(node_id).unwrap())
-            .find(|node| node.ip_address == my_ip_address)
-        {
-            Some(node) => node.node_id,
+    /// Loads the cluster model. If there is no node with the bootstrap IP address, then this
+    /// is the first time the node has started, and it registers itself in the cluster. This
+    /// works against an empty database, where the cluster record is also created
+    pub fn bootstrap(
+        data_layer: &Arc<DataLayer>,
+        bootstrap: &NodeBootstrap,
+    ) -> Result<Self, BootstrapError> {
+        let my_ip_address = bootstrap.ip_address.trim();
+        if my_ip_address.is_empty() {
+            return Err(BootstrapError::MissingIpAddress);
+        }
+        if Ipv4Addr::from_str(my_ip_address).is_err() {
+            return Err(BootstrapError::InvalidIpAddress {
+                ip_address: my_ip_address.to_owned(),
+            });
+        }
+
+        let mut cluster = data_layer
+            .get_cluster()
+            .map_err(|err| BootstrapError::PersistenceFailure {
+                msg: format!("{err:?} getting the cluster"),
+            })?;
+
+        let mut my_node_id = None;
+        for &node_id in cluster.node_ids.iter() {
+            let node = data_layer
+                .get_node(node_id)
+                .map_err(|err| BootstrapError::PersistenceFailure {
+                    msg: format!("{err:?} getting node {node_id}"),
+                })?;
+            if node.ip_address == my_ip_address {
+                my_node_id = Some(node.node_id);
+                break;
+            }
+        }
+
+        let my_node_id = match my_node_id {
+            Some(node_id) => node_id,
             None => {
                 let node = data_layer
                     .add_node(
-                        &my_ip_address,
-                        DEFAULT_ADMIN_PORT,
-                        DEFAULT_PUBSUB_PORT,
-                        DEFAULT_SYNC_PORT,
+                        my_ip_address,
+                        bootstrap.admin_port,
+                        bootstrap.pubsub_port,
+                        bootstrap.sync_port,
                     )
-                    .unwrap();
-                cluster = data_layer.get_cluster().unwrap();
+                    .map_err(|err| BootstrapError::PersistenceFailure {
+                        msg: format!("{err:?} registering node {my_ip_address}"),
+                    })?;
+                info!(
+                    "Cluster: Registered this node as node {} with IP address {my_ip_address}",
+                    node.node_id
+                );
+                cluster = data_layer
+                    .get_cluster()
+                    .map_err(|err| BootstrapError::PersistenceFailure {
+                        msg: format!("{err:?} getting the cluster"),
+                    })?;
                 node.node_id
             }
         };
@@ -129,7 +217,7 @@ impl Cluster {
                 .map(|&topic_id| Topic::new(data_layer, topic_id)),
         );
 
-        Self {
+        Ok(Self {
             data_layer: Arc::clone(data_layer),
             state: RwLock::new(ClusterState {
                 refresh_status: RefreshStatus::Updated,
@@ -138,7 +226,7 @@ impl Cluster {
             nodes,
             topics,
             my_node_id,
-        }
+        })
     }
 
     pub fn refresh(self: &Self) -> () {
@@ -161,6 +249,72 @@ impl Cluster {
         // original value of persisted_data to find changes.
     }
 
+    /// Adds a topic with no partitions or subscriptions
+    pub fn add_topic(self: &Self, name: &str) -> DataAddResult<TopicRef> {
+        let topic = self.data_layer.add_topic(name)?;
+        let topic_ref = EntityRef::new(Topic::new(&self.data_layer, topic.topic_id));
+        self.topics.insert_ref(topic_ref.clone());
+        self.refresh();
+        Ok(topic_ref)
+    }
+
+    /// Adds a partition to a topic, owned by the specified node
+    pub fn add_partition(
+        self: &Self,
+        topic: &TopicRef,
+        node_id: NodeId,
+    ) -> DataAddResult<PartitionRef> {
+        topic.add_partition(&self.data_layer, node_id)
+    }
+
+    /// Adds a subscription to a topic
+    pub fn add_subscription(
+        self: &Self,
+        topic: &TopicRef,
+        name: &str,
+        has_key_affinity: bool,
+    ) -> DataAddResult<SubscriptionRef> {
+        topic.add_subscription(&self.data_layer, name, has_key_affinity)
+    }
+
+    /// Adds a subscription to a topic with the same type and settings as an existing one
+    pub fn copy_subscription(
+        self: &Self,
+        topic: &TopicRef,
+        subscription_id: SubscriptionId,
+        name: &str,
+    ) -> DataAddResult<SubscriptionRef> {
+        topic.copy_subscription(&self.data_layer, subscription_id, name)
+    }
+
+    /// Deletes a topic from the database along with its partitions, ledgers and
+    /// subscriptions, and removes it from the cluster
+    pub fn delete_topic(self: &Self, topic_id: TopicId) -> DataUpdateResult<()> {
+        self.data_layer.delete_topic(topic_id)?;
+        self.topics.remove(&topic_id);
+        self.refresh();
+        Ok(())
+    }
+
+    /// Deletes a subscription from a topic
+    pub fn delete_subscription(
+        self: &Self,
+        topic: &TopicRef,
+        subscription_id: SubscriptionId,
+    ) -> DataUpdateResult<()> {
+        topic.delete_subscription(&self.data_layer, subscription_id)
+    }
+
+    /// Moves a partition of a topic to another node
+    pub fn reassign_partition(
+        self: &Self,
+        topic: &TopicRef,
+        partition_id: PartitionId,
+        node_id: NodeId,
+    ) -> DataUpdateResult<PartitionRef> {
+        topic.reassign_partition(&self.data_layer, partition_id, node_id)
+    }
+
     pub fn stats(self: &Self) -> ClusterStats {
         let topics = self
             .topics
@@ -1,7 +1,7 @@
 use crate::{
     data::DataLayer,
     formatting::plain_text_builder::{PlainTextBuilder, ToPlainText},
-    utils::now_epoc_millis,
+    utils::wall_clock_millis,
 };
 use pulsar_rust_net::data_types::{LedgerId, MessageId, NodeId, PartitionId, Timestamp, TopicId};
 use serde::Serialize;
@@ -80,6 +80,11 @@ impl Ledger {
     pub fn next_message_id(self: &Self) -> MessageId {
         self.state.read().unwrap().stats.next_message_id
     }
+    /// Returns true if no later message has been published to this ledger. When the ledger is
+    /// full, this is true for the final message in the ledger
+    pub fn is_last_message(self: &Self, message_id: MessageId) -> bool {
+        message_id.wrapping_add(1) == self.next_message_id()
+    }
     pub fn create_timestamp(self: &Self) -> Timestamp {
         self.create_timestamp
     }
@@ -124,7 +129,7 @@ impl Ledger {
 
         let node_id = ledger.node_id;
         let messages: HashMap<MessageId, PublishedMessage> = HashMap::with_capacity(10000);
-        let timestamp = now_epoc_millis();
+        let timestamp = wall_clock_millis();
 
         let stats = LedgerStats {
             message_count: 0,
@@ -157,7 +162,7 @@ impl Ledger {
 
         let id = state.stats.next_message_id;
         state.stats.next_message_id = if id == MessageId::MAX { 0 } else { id + 1 };
-        state.stats.last_update_timestamp = now_epoc_millis();
+        state.stats.last_update_timestamp = wall_clock_millis();
 
         Some(id)
     }
@@ -166,12 +171,32 @@ impl Ledger {
         let state: &mut LedgerState = &mut *self.state.write().unwrap();
         state.stats.message_count += 1;
         state.stats.unacked_count += message.subscriber_count;
-        state.stats.last_update_timestamp = now_epoc_millis();
+        state.stats.last_update_timestamp = wall_clock_millis();
         state
             .messages
             .insert(message.message_ref.message_id, message);
     }
 
+    /// Keeps a message in the ledger until one more subscription acks it, so that it can be
+    /// delivered again. A message that every subscription already acked is put back
+    pub fn retain_message(self: &Self, message: &PublishedMessage) {
+        let state: &mut LedgerState = &mut *self.state.write().unwrap();
+        match state.messages.get_mut(&message.message_ref.message_id) {
+            Some(retained) => retained.subscriber_count += 1,
+            None => {
+                let mut retained = message.clone();
+                retained.subscriber_count = 1;
+                retained.ack_count = 0;
+                state
+                    .messages
+                    .insert(message.message_ref.message_id, retained);
+                state.stats.message_count += 1;
+            }
+        }
+        state.stats.unacked_count += 1;
+        state.stats.last_update_timestamp = wall_clock_millis();
+    }
+
     pub fn get_message(self: &Self, message_id: &MessageId) -> Option<PublishedMessage> {
         let state = self.state.read().unwrap();
         Some(PublishedMessage::clone(state.messages.get(message_id)?))
@@ -189,7 +214,7 @@ impl Ledger {
                     messages.remove(message_id);
                     state.stats.message_count -= 1;
                 }
-                state.stats.last_update_timestamp = now_epoc_millis();
+                state.stats.last_update_timestamp = wall_clock_millis();
             }
             // TODO: Log a warning, this should not happen because acked messages are removed from the subscription
             // and this struct should never see double acks.
@@ -1,6 +1,9 @@
 use pulsar_rust_net::{
-    contracts::v1::responses,
-    data_types::{ConsumerId, LedgerId, MessageId, PartitionId, Timestamp, TopicId},
+    contracts::v1::{requests::MessageHeaders, responses},
+    data_types::{
+        ConsumerId, LedgerId, MessageId, PartitionId, Priority, ProducerId, SequenceNumber,
+        Timestamp, TopicId,
+    },
 };
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -31,6 +34,23 @@ impl MessageRef {
         }
     }
 
+    /// Parses a key that came from outside of the broker, returning None if it is malformed
+    pub fn try_from_key(key: &str) -> Option<MessageRef> {
+        let mut splits = key.split(':');
+
+        let message_ref = Self {
+            topic_id: splits.next()?.parse().ok()?,
+            partition_id: splits.next()?.parse().ok()?,
+            ledger_id: splits.next()?.parse().ok()?,
+            message_id: splits.next()?.parse().ok()?,
+        };
+
+        match splits.next() {
+            Some(_) => None,
+            None => Some(message_ref),
+        }
+    }
+
     pub fn to_key(self: &Self) -> String {
         self.topic_id.to_string()
             + ":"
@@ -49,9 +69,27 @@ pub struct PublishedMessage {
     pub key: String,
     pub timestamp: Timestamp,
     pub published: Timestamp,
+    pub priority: Priority,
+
+    #[serde(serialize_with = "pulsar_rust_net::contracts::sorted_map::serialize")]
     pub attributes: HashMap<String, String>,
+
     pub subscriber_count: usize,
     pub ack_count: usize,
+
+    #[serde(default)]
+    pub headers: MessageHeaders,
+
+    /// Producers that number their messages can have duplicates discarded by the broker
+    #[serde(default)]
+    pub producer_id: Option<ProducerId>,
+
+    #[serde(default)]
+    pub sequence_number: Option<SequenceNumber>,
+
+    /// Messages are held back from subscriptions until this time
+    #[serde(default)]
+    pub deliver_at: Option<Timestamp>,
 }
 
 #[cfg_attr(debug_assertions, derive(Debug))]
@@ -59,19 +97,31 @@ pub struct PublishedMessage {
 pub struct SubscribedMessage {
     pub message_ref_key: String,
     pub key: String,
+    pub published: Timestamp,
+    pub priority: Priority,
     pub consumer_id: Option<ConsumerId>,
+    pub assigned_timestamp: Option<Timestamp>,
+    pub first_delivered_timestamp: Option<Timestamp>,
     pub delivered_timestamp: Option<Timestamp>,
     pub delivery_count: usize,
+
+    /// When a message is nacked, it is not redelivered until this time
+    pub nack_until: Option<Timestamp>,
 }
 
 impl SubscribedMessage {
-    pub fn new(message_ref_key: &str, key: &str) -> Self {
+    pub fn new(message_ref_key: &str, key: &str, published: Timestamp, priority: Priority) -> Self {
         Self {
             message_ref_key: message_ref_key.to_owned(),
             key: key.to_owned(),
+            published,
+            priority,
             consumer_id: None,
+            assigned_timestamp: None,
+            first_delivered_timestamp: None,
             delivered_timestamp: None,
             delivery_count: 0,
+            nack_until: None,
         }
     }
 }
@@ -114,9 +164,14 @@ impl From<&PublishedMessage> for SubscribedMessage {
         Self {
             message_ref_key: message.message_ref.to_key(),
             key: message.key.clone(),
+            published: message.published,
+            priority: message.priority,
             consumer_id: None,
+            assigned_timestamp: None,
+            first_delivered_timestamp: None,
             delivered_timestamp: None,
             delivery_count: 0,
+            nack_until: None,
         }
     }
 }
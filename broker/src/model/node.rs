@@ -44,6 +44,12 @@ impl Node {
     pub fn sync_port(self: &Self) -> PortNumber {
         self.sync_port
     }
+    pub fn admin_authority(self: &Self) -> String {
+        format!("{}:{}", self.ip_address, self.admin_port)
+    }
+    pub fn pubsub_authority(self: &Self) -> String {
+        format!("{}:{}", self.ip_address, self.pubsub_port)
+    }
 
     pub fn new(data_layer: &Arc<DataLayer>, node_id: NodeId) -> Self {
         let node = data_layer.get_node(node_id).unwrap();
@@ -59,7 +59,7 @@ impl ToPlainText for PartitionLedgerStats {
 
 #[cfg_attr(debug_assertions, derive(Debug))]
 pub struct Partition {
-    current_ledger_id: LedgerId,
+    current_ledger_id: Option<LedgerId>,
     node_id: NodeId,
     topic_id: TopicId,
     partition_id: PartitionId,
@@ -93,21 +93,63 @@ impl Partition {
         let partition = data_layer.get_partition(topic_id, partition_id).unwrap();
         let node_id = partition.node_id;
 
-        if let Some(current_ledger_id) = data_layer.get_last_ledger_id(&partition) {
-            let ledgers =
-                EntityList::from_iter(partition.ledger_ids.iter().map(|&ledger_id| {
-                    Ledger::new(data_layer, topic_id, partition_id, ledger_id, 1)
-                }));
-
-            Self {
-                topic_id,
-                partition_id,
-                ledgers,
-                current_ledger_id,
-                node_id,
+        // If a ledger can't be obtained then the partition is still loaded, but publishing
+        // to it will fail until a ledger is added
+        let current_ledger_id = data_layer.get_last_ledger_id(&partition);
+
+        // The current ledger might have just been created, in which case it's not in the
+        // list of ledger ids that were read with the partition
+        let mut ledger_ids = partition.ledger_ids.clone();
+        if let Some(ledger_id) = current_ledger_id {
+            if !ledger_ids.contains(&ledger_id) {
+                ledger_ids.push(ledger_id);
             }
-        } else {
-            panic!("No current ledger for partition {partition_id}")
+        }
+
+        let ledgers = EntityList::from_iter(
+            ledger_ids
+                .iter()
+                .filter(|&&ledger_id| {
+                    data_layer
+                        .get_ledger(topic_id, partition_id, ledger_id)
+                        .is_ok()
+                })
+                .map(|&ledger_id| Ledger::new(data_layer, topic_id, partition_id, ledger_id, 1)),
+        );
+
+        Self {
+            topic_id,
+            partition_id,
+            ledgers,
+            current_ledger_id,
+            node_id,
+        }
+    }
+
+    /// Makes a copy of this partition that is owned by another node, and publishes to a new
+    /// ledger on that node. The existing ledgers are shared with the copy, so that messages
+    /// that were already published to them can still be found
+    pub fn reassigned(
+        self: &Self,
+        data_layer: &Arc<DataLayer>,
+        node_id: NodeId,
+        ledger_id: LedgerId,
+    ) -> Self {
+        let ledgers = EntityList::from_iter_ref(self.ledgers.values().into_iter());
+        ledgers.insert(Ledger::new(
+            data_layer,
+            self.topic_id,
+            self.partition_id,
+            ledger_id,
+            1,
+        ));
+
+        Self {
+            topic_id: self.topic_id,
+            partition_id: self.partition_id,
+            ledgers,
+            current_ledger_id: Some(ledger_id),
+            node_id,
         }
     }
 
@@ -130,7 +172,7 @@ impl Partition {
     }
 
     pub fn current_ledger(self: &Self, node_id: NodeId) -> Option<LedgerRef> {
-        let ledger = self.ledgers.get(&self.current_ledger_id)?;
+        let ledger = self.ledgers.get(&self.current_ledger_id?)?;
         if ledger.node_id() == node_id {
             Some(ledger.clone())
         } else {
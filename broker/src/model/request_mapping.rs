@@ -1,6 +1,6 @@
-use crate::utils::now_epoc_millis;
+use crate::utils::{now_epoc_millis, wall_clock_millis};
 use pulsar_rust_net::{
-    contracts::v1::requests,
+    contracts::{v1::requests, v2},
     data_types::{LedgerId, MessageId, Timestamp},
 };
 
@@ -13,7 +13,15 @@ impl From<&super::messages::PublishedMessage> for requests::Publish {
             partition_id: value.message_ref.partition_id,
             key: value.key.clone(),
             timestamp: Some(value.timestamp),
+            priority: Some(value.priority),
             attributes: value.attributes.clone(),
+            ack_level: requests::PublishAckLevel::default(),
+            headers: value.headers.clone(),
+            producer_id: value.producer_id,
+            sequence_number: value.sequence_number,
+            deliver_after_millis: value
+                .deliver_at
+                .map(|deliver_at| deliver_at.saturating_sub(value.published)),
         }
     }
 }
@@ -30,12 +38,49 @@ impl Into<super::messages::PublishedMessage> for requests::Publish {
             key: self.key.clone(),
             timestamp: match self.timestamp {
                 Some(epoch_time) => epoch_time,
-                None => now_epoc_millis(),
+                None => wall_clock_millis(),
             },
             published: Timestamp::default(),
+            priority: self.priority.unwrap_or_default(),
             attributes: self.attributes.clone(),
             subscriber_count: usize::default(),
             ack_count: usize::default(),
+            headers: self.headers.clone(),
+            producer_id: self.producer_id,
+            sequence_number: self.sequence_number,
+            deliver_at: self
+                .deliver_after_millis
+                .map(|delay_millis| now_epoc_millis() + delay_millis),
+        }
+    }
+}
+
+impl Into<super::messages::PublishedMessage> for v2::requests::Publish {
+    fn into(self) -> super::messages::PublishedMessage {
+        super::messages::PublishedMessage {
+            message_ref: MessageRef {
+                topic_id: self.topic_id,
+                partition_id: self.partition_id,
+                ledger_id: LedgerId::default(),
+                message_id: MessageId::default(),
+            },
+            key: self.key,
+            timestamp: match self.timestamp {
+                Some(epoch_time) => epoch_time,
+                None => wall_clock_millis(),
+            },
+            published: Timestamp::default(),
+            priority: self.priority.unwrap_or_default(),
+            attributes: self.attributes,
+            subscriber_count: usize::default(),
+            ack_count: usize::default(),
+            headers: self.headers,
+            producer_id: self.producer_id,
+            sequence_number: self.sequence_number,
+            deliver_at: self.deliver_at.or_else(|| {
+                self.deliver_after_millis
+                    .map(|delay_millis| now_epoc_millis() + delay_millis)
+            }),
         }
     }
 }
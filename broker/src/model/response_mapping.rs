@@ -9,19 +9,24 @@ use super::{
     messages::PublishedMessage,
     node::{NodeList, NodeRef},
     partition::{PartitionList, PartitionRef},
-    topic::{TopicList, TopicRef},
+    topic::{TopicList, TopicRef, TopicSubscriptionStats},
 };
 use crate::{
     persistence::{
         log_entries::{LogEntry, LoggedEvent},
         logged_events::{
-            AckEvent, DropConsumerEvent, KeyAffinityEvent, NackEvent, NewConsumerEvent,
-            PublishEvent,
+            AckEvent, AdminAckEvent, DeadLetterEvent, DropConsumerEvent, ExpiryEvent,
+            KeyAffinityEvent, NackEvent, NewConsumerEvent, PartitionReassignedEvent, PublishEvent,
+            QuarantineEvent,
         },
     },
-    services::sub_service::{ConsumedMessages, NextMessage},
+    services::{
+        admin_service::SubscriptionRepair,
+        pub_service::PublishReceipt,
+        sub_service::{ConsumedMessages, QuarantinedMessage, RemotePartition, SubscriptionSeek},
+    },
 };
-use pulsar_rust_net::{contracts::v1::responses, data_types::ConsumerId};
+use pulsar_rust_net::{contracts::v1::responses, data_types::TopicId};
 
 impl From<&NodeRef> for responses::NodeSummary {
     fn from(node: &NodeRef) -> Self {
@@ -49,6 +54,9 @@ impl From<&TopicRef> for responses::TopicSummary {
     fn from(topic: &TopicRef) -> Self {
         Self {
             topic_id: topic.topic_id(),
+            name: topic.name().to_owned(),
+            partition_count: topic.partitions().keys().len(),
+            subscription_count: topic.subscriptions().keys().len(),
         }
     }
 }
@@ -78,6 +86,7 @@ impl From<&PartitionRef> for responses::PartitionDetail {
             topic_id: partition.topic_id(),
             partition_id: partition.partition_id(),
             ledgers: Vec::default(), // TODO
+            node_id: partition.node_id(),
         }
     }
 }
@@ -116,8 +125,12 @@ impl From<&PublishedMessage> for responses::Message {
             message_ack_key: message.message_ref.to_key(),
             published: message.published,
             attributes: message.attributes.clone(),
+            headers: message.headers.clone(),
             delivered: 0,
+            first_delivered: None,
             delivery_count: 0,
+            redelivered: false,
+            last_in_ledger: false,
         }
     }
 }
@@ -219,6 +232,9 @@ impl From<&LoggedEvent> for responses::LogEntryDetail {
             LoggedEvent::Ack(event) => {
                 responses::LogEntryDetail::Ack(responses::AckLogEntry::from(event))
             }
+            LoggedEvent::AdminAck(event) => {
+                responses::LogEntryDetail::AdminAck(responses::AdminAckLogEntry::from(event))
+            }
             LoggedEvent::Nack(event) => {
                 responses::LogEntryDetail::Nack(responses::NackLogEntry::from(event))
             }
@@ -231,6 +247,20 @@ impl From<&LoggedEvent> for responses::LogEntryDetail {
             LoggedEvent::KeyAffinity(event) => {
                 responses::LogEntryDetail::KeyAffinity(responses::KeyAffinityLogEntry::from(event))
             }
+            LoggedEvent::Quarantine(event) => {
+                responses::LogEntryDetail::Quarantine(responses::QuarantineLogEntry::from(event))
+            }
+            LoggedEvent::Expiry(event) => {
+                responses::LogEntryDetail::Expiry(responses::ExpiryLogEntry::from(event))
+            }
+            LoggedEvent::DeadLetter(event) => {
+                responses::LogEntryDetail::DeadLetter(responses::DeadLetterLogEntry::from(event))
+            }
+            LoggedEvent::PartitionReassigned(event) => {
+                responses::LogEntryDetail::PartitionReassigned(
+                    responses::PartitionReassignedLogEntry::from(event),
+                )
+            }
         }
     }
 }
@@ -253,6 +283,15 @@ impl From<&AckEvent> for responses::AckLogEntry {
     }
 }
 
+impl From<&AdminAckEvent> for responses::AdminAckLogEntry {
+    fn from(entry: &AdminAckEvent) -> Self {
+        Self {
+            message_ref: responses::MessageRef::from(&entry.message_ref),
+            subscription_id: entry.subscription_id,
+        }
+    }
+}
+
 impl From<&NackEvent> for responses::NackLogEntry {
     fn from(entry: &NackEvent) -> Self {
         Self {
@@ -263,6 +302,49 @@ impl From<&NackEvent> for responses::NackLogEntry {
     }
 }
 
+impl From<&QuarantineEvent> for responses::QuarantineLogEntry {
+    fn from(entry: &QuarantineEvent) -> Self {
+        Self {
+            message_ref: responses::MessageRef::from(&entry.message_ref),
+            subscription_id: entry.subscription_id,
+            consumer_id: entry.consumer_id,
+            reason: entry.reason.clone(),
+        }
+    }
+}
+
+impl From<&ExpiryEvent> for responses::ExpiryLogEntry {
+    fn from(entry: &ExpiryEvent) -> Self {
+        Self {
+            message_ref: responses::MessageRef::from(&entry.message_ref),
+            subscription_id: entry.subscription_id,
+        }
+    }
+}
+
+impl From<&DeadLetterEvent> for responses::DeadLetterLogEntry {
+    fn from(entry: &DeadLetterEvent) -> Self {
+        Self {
+            message_ref: responses::MessageRef::from(&entry.message_ref),
+            subscription_id: entry.subscription_id,
+            consumer_id: entry.consumer_id,
+            dead_letter_ref: responses::MessageRef::from(&entry.dead_letter_ref),
+        }
+    }
+}
+
+impl From<&PartitionReassignedEvent> for responses::PartitionReassignedLogEntry {
+    fn from(entry: &PartitionReassignedEvent) -> Self {
+        Self {
+            topic_id: entry.topic_id,
+            partition_id: entry.partition_id,
+            ledger_id: entry.ledger_id,
+            from_node_id: entry.from_node_id,
+            to_node_id: entry.to_node_id,
+        }
+    }
+}
+
 impl From<&NewConsumerEvent> for responses::NewConsumerLogEntry {
     fn from(entry: &NewConsumerEvent) -> Self {
         Self {
@@ -309,10 +391,113 @@ impl From<&ConsumedMessages> for responses::ConsumeResult {
                     message_ack_key: message.published_message.message_ref.to_key(),
                     published: message.published_message.published,
                     attributes: message.published_message.attributes.clone(),
+                    headers: message.published_message.headers.clone(),
                     delivered: message.subscribed_message.delivered_timestamp.unwrap(),
+                    first_delivered: message.subscribed_message.first_delivered_timestamp,
                     delivery_count: message.subscribed_message.delivery_count,
+                    redelivered: message.subscribed_message.delivery_count > 1,
+                    last_in_ledger: message.last_in_ledger,
                 })
                 .collect(),
+            remote_partitions: consumed_messages
+                .remote_partitions
+                .iter()
+                .map(|remote_partition| responses::PartitionOwner::from(remote_partition))
+                .collect(),
+        }
+    }
+}
+
+impl From<&RemotePartition> for responses::PartitionOwner {
+    fn from(remote_partition: &RemotePartition) -> Self {
+        Self {
+            topic_id: remote_partition.topic_id,
+            partition_id: remote_partition.partition_id,
+            node_id: remote_partition.node.node_id(),
+            ip_address: remote_partition.node.ip_address().clone(),
+            pubsub_port: remote_partition.node.pubsub_port(),
+        }
+    }
+}
+
+impl From<&QuarantinedMessage> for responses::QuarantinedMessage {
+    fn from(quarantined: &QuarantinedMessage) -> Self {
+        Self {
+            message: responses::Message::from(&quarantined.message),
+            subscription_id: quarantined.subscription_id,
+            consumer_id: quarantined.consumer_id,
+            reason: quarantined.reason.clone(),
+            quarantined: quarantined.quarantined,
+        }
+    }
+}
+
+pub fn quarantined_message_list(
+    messages: &[QuarantinedMessage],
+) -> responses::QuarantinedMessageList {
+    responses::QuarantinedMessageList {
+        messages: messages
+            .iter()
+            .map(|message| responses::QuarantinedMessage::from(message))
+            .collect(),
+    }
+}
+
+impl From<&PublishReceipt> for responses::PublishResult {
+    fn from(receipt: &PublishReceipt) -> Self {
+        Self {
+            message_ref: receipt.message_ref.into(),
+            node_id: receipt.node_id,
+            ledger_message_count: receipt.ledger_message_count,
+        }
+    }
+}
+
+impl From<&SubscriptionRepair> for responses::SubscriptionRepair {
+    fn from(repair: &SubscriptionRepair) -> Self {
+        Self {
+            unacked_count: repair.unacked_count,
+            restored: repair.restored.clone(),
+            removed: repair.removed.clone(),
+        }
+    }
+}
+
+impl From<&SubscriptionSeek> for responses::SubscriptionSeek {
+    fn from(seek: &SubscriptionSeek) -> Self {
+        Self {
+            queued_count: seek.queued_count,
+            earliest_published: seek.earliest_published,
+        }
+    }
+}
+
+impl From<&TopicSubscriptionStats> for responses::SubscriptionStats {
+    fn from(stats: &TopicSubscriptionStats) -> Self {
+        Self {
+            subscription_id: stats.subscription_id,
+            queued_count: stats.subscription_stats.queued_count,
+            unacked_count: stats.subscription_stats.unacked_count,
+            assigned_count: stats.subscription_stats.assigned_count,
+            affinity_count: stats.subscription_stats.affinity_count,
         }
     }
 }
+
+pub fn topic_subscription_stats(
+    topic_id: TopicId,
+    stats: &[TopicSubscriptionStats],
+) -> responses::TopicSubscriptionStats {
+    let subscriptions: Vec<responses::SubscriptionStats> = stats
+        .iter()
+        .map(|stats| responses::SubscriptionStats::from(stats))
+        .collect();
+    responses::TopicSubscriptionStats {
+        topic_id,
+        queued_count: subscriptions.iter().map(|s| s.queued_count).sum(),
+        unacked_count: subscriptions.iter().map(|s| s.unacked_count).sum(),
+        assigned_count: subscriptions.iter().map(|s| s.assigned_count).sum(),
+        affinity_count: subscriptions.iter().map(|s| s.affinity_count).sum(),
+        subscriptions,
+    }
+}
@@ -1,24 +1,335 @@
 pub mod key_shared;
 pub mod shared;
 
-use crate::formatting::plain_text_builder::{PlainTextBuilder, ToPlainText};
+mod message_queue;
 
-use super::{messages::SubscribedMessage, Entity, EntityList, EntityRef};
-use pulsar_rust_net::data_types::{ConsumerId, SubscriptionId, TopicId};
+use crate::{
+    formatting::plain_text_builder::{PlainTextBuilder, ToPlainText},
+    persistence::{
+        event_logger::EventQueryOptions,
+        log_entries::LoggedEvent,
+        logged_events,
+        persisted_entities::{
+            DeliveryOrder, DeliverySemantics, DeliveryTransform, QueueOverflowPolicy,
+        },
+        PersistenceLayer,
+    },
+    utils::now_epoc_millis,
+};
+
+use super::{
+    messages::{PublishedMessage, SubscribedMessage},
+    partition::PartitionList,
+    Entity, EntityList, EntityRef,
+};
+use pulsar_rust_net::data_types::{ConsumerId, SubscriptionId, Timestamp, TopicId};
 use serde::Serialize;
+use message_queue::MessageQueue;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::RwLock,
+};
 
 pub enum Subscription {
     Shared(shared::Subscription),
     KeyShared(key_shared::Subscription),
 }
 
+/// The outcome of pushing a message onto a subscription queue
+pub enum PushResult {
+    /// The message was added to the queue
+    Queued,
+
+    /// The message was added to the queue, and the oldest message was dropped to make room
+    Dropped(SubscribedMessage),
+
+    /// The queue is full, and the message was not added
+    Rejected(SubscribedMessage),
+}
+
+/// The reasons that a consumer can not be connected to a subscription
+pub enum ConnectError {
+    /// The next consumer id could not be allocated in the database
+    FailedToAllocateConsumerId,
+
+    /// The maximum number of consumers are already connected to the subscription
+    TooManyConsumers,
+}
+
+pub type ConnectResult = Result<ConsumerId, ConnectError>;
+
+/// Keeps track of the consumers that are connected to a subscription, so that the number of
+/// consumers can be limited. Each consumer holds queues and key affinities in the subscription.
+/// Consumers are kept in the order that they connected, so that failover subscriptions can
+/// deliver to the first one
+struct ConnectedConsumers {
+    max_consumers: usize,
+    consumer_ids: RwLock<Vec<ConsumerId>>,
+}
+
+impl ConnectedConsumers {
+    fn new(max_consumers: usize) -> Self {
+        Self {
+            max_consumers,
+            consumer_ids: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Allocates an id for a new consumer unless the subscription already has the maximum
+    /// number of consumers. A maximum of zero means that the number of consumers is unlimited
+    fn connect(self: &Self, allocate: impl FnOnce() -> Option<ConsumerId>) -> ConnectResult {
+        let mut consumer_ids = self.consumer_ids.write().unwrap();
+        if self.max_consumers > 0 && consumer_ids.len() >= self.max_consumers {
+            return Err(ConnectError::TooManyConsumers);
+        }
+        let consumer_id = allocate().ok_or(ConnectError::FailedToAllocateConsumerId)?;
+        consumer_ids.push(consumer_id);
+        Ok(consumer_id)
+    }
+
+    fn disconnect(self: &Self, consumer_id: ConsumerId) {
+        self.consumer_ids
+            .write()
+            .unwrap()
+            .retain(|connected| *connected != consumer_id);
+    }
+
+    /// Returns true if this is the longest connected consumer, or if no consumers are connected
+    fn is_first(self: &Self, consumer_id: ConsumerId) -> bool {
+        self.consumer_ids
+            .read()
+            .unwrap()
+            .first()
+            .is_none_or(|first| *first == consumer_id)
+    }
+}
+
+/// Adds a message to a subscription queue, applying the overflow policy if the queue is full
+fn push_with_limit(
+    queue: &mut MessageQueue,
+    message: SubscribedMessage,
+    max_queue_depth: usize,
+    overflow_policy: QueueOverflowPolicy,
+) -> PushResult {
+    if max_queue_depth == 0 || queue.len() < max_queue_depth {
+        queue.push_back(message);
+        return PushResult::Queued;
+    }
+    match overflow_policy {
+        QueueOverflowPolicy::DropOldest => {
+            let dropped = queue.pop_lowest();
+            queue.push_back(message);
+            match dropped {
+                Some(dropped) => PushResult::Dropped(dropped),
+                None => PushResult::Queued,
+            }
+        }
+        QueueOverflowPolicy::Block | QueueOverflowPolicy::Reject => PushResult::Rejected(message),
+    }
+}
+
+/// Takes the next message to deliver from a subscription queue
+fn pop_next(
+    queue: &mut MessageQueue,
+    delivery_order: DeliveryOrder,
+) -> Option<SubscribedMessage> {
+    match delivery_order {
+        DeliveryOrder::Fifo => queue.pop_front(),
+        DeliveryOrder::Lifo => queue.pop_back(),
+    }
+}
+
+/// Takes the next message to deliver from a subscription queue, leaving nacked messages whose
+/// redelivery delay has not elapsed in the queue. With `hold_keys`, messages with the same key
+/// as a delayed message are also left in the queue, so that they are not delivered out of order
+fn pop_ready(
+    queue: &mut MessageQueue,
+    delivery_order: DeliveryOrder,
+    hold_keys: bool,
+) -> Option<SubscribedMessage> {
+    let now = now_epoc_millis();
+    let mut skipped = Vec::new();
+    let mut held_keys = HashSet::new();
+    let ready = loop {
+        let message = match pop_next(queue, delivery_order) {
+            Some(message) => message,
+            None => break None,
+        };
+        if message
+            .nack_until
+            .is_some_and(|nack_until| nack_until > now)
+        {
+            if hold_keys {
+                held_keys.insert(message.key.clone());
+            }
+            skipped.push(message);
+        } else if held_keys.contains(&message.key) {
+            skipped.push(message);
+        } else {
+            break Some(message);
+        }
+    };
+
+    // Requeueing reverses the order, so start with the last message skipped
+    for message in skipped.into_iter().rev() {
+        requeue(queue, message, delivery_order);
+    }
+    ready
+}
+
+/// Takes the next message to deliver from a subscription queue, skipping messages that were
+/// published more than `max_message_age_millis` ago. Skipped messages are added to `expired`
+fn pop_fresh(
+    queue: &mut MessageQueue,
+    delivery_order: DeliveryOrder,
+    hold_keys: bool,
+    max_message_age_millis: u64,
+    expired: &mut Vec<SubscribedMessage>,
+) -> Option<SubscribedMessage> {
+    if max_message_age_millis == 0 {
+        return pop_ready(queue, delivery_order, hold_keys);
+    }
+    let oldest_published = now_epoc_millis().saturating_sub(max_message_age_millis);
+    loop {
+        let message = pop_ready(queue, delivery_order, hold_keys)?;
+        if message.published >= oldest_published {
+            return Some(message);
+        }
+        expired.push(message);
+    }
+}
+
+/// Returns the time until which a nacked message should not be redelivered, or `None` if there
+/// is no redelivery delay
+fn nack_until(nack_redelivery_delay_millis: u64) -> Option<Timestamp> {
+    if nack_redelivery_delay_millis == 0 {
+        None
+    } else {
+        Some(now_epoc_millis() + nack_redelivery_delay_millis)
+    }
+}
+
+/// Puts a message back into a subscription queue so that it is the next one delivered
+fn requeue(
+    queue: &mut MessageQueue,
+    message: SubscribedMessage,
+    delivery_order: DeliveryOrder,
+) {
+    match delivery_order {
+        DeliveryOrder::Fifo => queue.push_front(message),
+        DeliveryOrder::Lifo => queue.push_back(message),
+    }
+}
+
+/// Logs an event that changes the state of a subscription. Events are not logged for ephemeral
+/// topics
+fn log_event(persistence: &PersistenceLayer, ephemeral: bool, event: &LoggedEvent) {
+    if !ephemeral {
+        let _ = persistence.log_event(event);
+    }
+}
+
+/// Reverses the changes that popping a message made to its delivery state, for a message that
+/// goes back to the subscription without being delivered
+fn undeliver(message: &mut SubscribedMessage) {
+    message.delivery_count = message.delivery_count.saturating_sub(1);
+    if message.delivery_count == 0 {
+        message.consumer_id = None;
+        message.delivered_timestamp = None;
+        message.first_delivered_timestamp = None;
+    }
+}
+
+/// Replays the event log of a topic in the order that it was logged, to find the messages that a
+/// subscription had not finished with, in the order that they were published. Messages that were
+/// published before the subscription was created are skipped, and the delivery count of each
+/// message is the number of times that it was nacked. Key affinities are not replayed, because
+/// consumers have to reconnect after a restart, and affinities form again as messages are delivered
+fn replay_backlog(
+    persistence: &PersistenceLayer,
+    topic_id: TopicId,
+    subscription_id: SubscriptionId,
+    created: Timestamp,
+) -> Vec<(PublishedMessage, SubscribedMessage)> {
+    let _ = persistence.flush_events();
+
+    let mut backlog: Vec<Option<(PublishedMessage, SubscribedMessage)>> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    let key_prefix = PersistenceLayer::build_topic_prefix(topic_id);
+    let options = EventQueryOptions::replay();
+    for log_entry in persistence.events_by_key_prefix(&key_prefix, &options) {
+        let finished = match log_entry.deserialize() {
+            Some(LoggedEvent::Publish(event)) => {
+                if event.message.published >= created {
+                    let message = SubscribedMessage::from(&event.message);
+                    positions.insert(message.message_ref_key.clone(), backlog.len());
+                    backlog.push(Some((event.message, message)));
+                }
+                None
+            }
+            Some(LoggedEvent::Nack(event)) if event.subscription_id == subscription_id => {
+                if let Some(Some((_, message))) = positions
+                    .get(&event.message_ref.to_key())
+                    .and_then(|&position| backlog.get_mut(position))
+                {
+                    message.delivery_count += 1;
+                }
+                None
+            }
+            Some(LoggedEvent::Ack(event)) if event.subscription_id == subscription_id => {
+                Some(event.message_ref)
+            }
+            Some(LoggedEvent::AdminAck(event)) if event.subscription_id == subscription_id => {
+                Some(event.message_ref)
+            }
+            Some(LoggedEvent::Quarantine(event)) if event.subscription_id == subscription_id => {
+                Some(event.message_ref)
+            }
+            Some(LoggedEvent::Expiry(event)) if event.subscription_id == subscription_id => {
+                Some(event.message_ref)
+            }
+            Some(LoggedEvent::DeadLetter(event)) if event.subscription_id == subscription_id => {
+                Some(event.message_ref)
+            }
+            _ => None,
+        };
+        if let Some(message_ref) = finished {
+            if let Some(position) = positions.remove(&message_ref.to_key()) {
+                backlog[position] = None;
+            }
+        }
+    }
+    backlog.into_iter().flatten().collect()
+}
+
+/// Applies delivery transform rules, in order, to the attributes of a message being delivered
+fn apply_delivery_transforms(
+    transforms: &[DeliveryTransform],
+    message: &mut PublishedMessage,
+    delivered: Timestamp,
+) {
+    for transform in transforms {
+        match transform {
+            DeliveryTransform::SetAttribute { name, value } => {
+                message.attributes.insert(name.clone(), value.clone());
+            }
+            DeliveryTransform::SetDeliveredTimestamp { name } => {
+                message.attributes.insert(name.clone(), delivered.to_string());
+            }
+            DeliveryTransform::RemoveAttributes { prefix } => {
+                message.attributes.retain(|name, _| !name.starts_with(prefix.as_str()));
+            }
+        }
+    }
+}
+
 #[cfg_attr(debug_assertions, derive(Debug))]
 #[derive(Clone, Serialize)]
 pub struct SubscriptionStats {
-    queued_count: usize,
-    unacked_count: usize,
-    assigned_count: usize,
-    affinity_count: usize,
+    pub queued_count: usize,
+    pub unacked_count: usize,
+    pub assigned_count: usize,
+    pub affinity_count: usize,
 }
 
 impl ToPlainText for SubscriptionStats {
@@ -70,21 +381,93 @@ impl Subscription {
         }
     }
 
-    pub fn push(self: &Self, message: SubscribedMessage) {
+    /// When the subscription was created. Messages published before this are not delivered to it
+    pub fn created(self: &Self) -> Timestamp {
+        match self {
+            Subscription::Shared(subscription) => subscription.created(),
+            Subscription::KeyShared(subscription) => subscription.created(),
+        }
+    }
+
+    /// The number of times that a message can be delivered before it is moved to the dead
+    /// letter topic. Zero means that messages can be redelivered any number of times
+    pub fn max_delivery_count(self: &Self) -> usize {
+        match self {
+            Subscription::Shared(subscription) => subscription.max_delivery_count(),
+            Subscription::KeyShared(subscription) => subscription.max_delivery_count(),
+        }
+    }
+
+    /// Whether delivered messages are kept until they are acked, or acked as they are delivered
+    pub fn delivery_semantics(self: &Self) -> DeliverySemantics {
+        match self {
+            Subscription::Shared(subscription) => subscription.delivery_semantics(),
+            Subscription::KeyShared(subscription) => subscription.delivery_semantics(),
+        }
+    }
+
+    /// The name of the dead letter topic if one was configured for this subscription
+    pub fn dead_letter_topic(self: &Self) -> Option<String> {
+        match self {
+            Subscription::Shared(subscription) => subscription.dead_letter_topic(),
+            Subscription::KeyShared(subscription) => subscription.dead_letter_topic(),
+        }
+    }
+
+    /// Applies the delivery transforms of this subscription to a copy of a published message
+    /// that is about to be delivered to a consumer
+    pub fn transform(self: &Self, message: &mut PublishedMessage, delivered: Timestamp) {
+        let transforms = match self {
+            Subscription::Shared(subscription) => subscription.delivery_transforms(),
+            Subscription::KeyShared(subscription) => subscription.delivery_transforms(),
+        };
+        apply_delivery_transforms(transforms, message, delivered);
+    }
+
+    pub fn push(self: &Self, message: SubscribedMessage) -> PushResult {
         match self {
             Subscription::Shared(subscription) => subscription.push(message),
             Subscription::KeyShared(subscription) => subscription.push(message),
         }
     }
 
-    pub fn pop(self: &Self, consumer_id: ConsumerId) -> Option<SubscribedMessage> {
+    /// Returns true if this subscription is full, and the publisher should not be
+    /// allowed to publish any more messages to the topic
+    pub fn blocks_publisher(self: &Self) -> bool {
         match self {
-            Subscription::Shared(subscription) => subscription.pop(consumer_id),
-            Subscription::KeyShared(subscription) => subscription.pop(consumer_id),
+            Subscription::Shared(subscription) => subscription.blocks_publisher(),
+            Subscription::KeyShared(subscription) => subscription.blocks_publisher(),
         }
     }
 
-    pub fn connect_consumer(self: &Self) -> Option<ConsumerId> {
+    /// Retrieves the next message to deliver to a consumer. Messages that are older than the
+    /// max message age for this subscription are skipped and added to `expired` so that the
+    /// caller can remove them from the ledger
+    pub fn pop(
+        self: &Self,
+        consumer_id: ConsumerId,
+        expired: &mut Vec<SubscribedMessage>,
+    ) -> Option<SubscribedMessage> {
+        match self {
+            Subscription::Shared(subscription) => subscription.pop(consumer_id, expired),
+            Subscription::KeyShared(subscription) => subscription.pop(consumer_id, expired),
+        }
+    }
+
+    /// Puts back a message that was popped for a consumer but not delivered to it, so that it
+    /// is the next message popped, as if it had never been popped. Unlike a nack, there is no
+    /// redelivery delay and the delivery count is not increased. The popped message is passed
+    /// back because at-most-once subscriptions do not keep a copy of delivered messages
+    pub fn unpop(self: &Self, consumer_id: ConsumerId, message: SubscribedMessage) -> bool {
+        match self {
+            Subscription::Shared(subscription) => subscription.unpop(message),
+            Subscription::KeyShared(subscription) => subscription.unpop(consumer_id, message),
+        }
+    }
+
+    /// Allocates an id for a new consumer, unless the subscription already has its maximum
+    /// number of consumers
+    pub fn connect_consumer(self: &Self) -> ConnectResult {
         match self {
             Subscription::Shared(subscription) => subscription.connect_consumer(),
             Subscription::KeyShared(subscription) => subscription.connect_consumer(),
@@ -105,6 +488,32 @@ impl Subscription {
         }
     }
 
+    /// Returns true if the message was delivered to a consumer and has not been acked or nacked
+    pub fn is_delivered(self: &Self, message_ref_key: &str) -> bool {
+        match self {
+            Subscription::Shared(subscription) => subscription.is_delivered(message_ref_key),
+            Subscription::KeyShared(subscription) => subscription.is_delivered(message_ref_key),
+        }
+    }
+
+    /// Acks a batch of messages while only taking the subscription locks once. Returns
+    /// the message ref keys of the messages that were acked
+    pub fn ack_batch(self: &Self, acks: &[(ConsumerId, String)]) -> Vec<String> {
+        match self {
+            Subscription::Shared(subscription) => subscription.ack_batch(acks),
+            Subscription::KeyShared(subscription) => subscription.ack_batch(acks),
+        }
+    }
+
+    /// Acks a message on behalf of an administrator, regardless of which consumer it
+    /// was delivered to, or whether it was delivered at all
+    pub fn force_ack(self: &Self, message_ref_key: &str) -> bool {
+        match self {
+            Subscription::Shared(subscription) => subscription.force_ack(message_ref_key),
+            Subscription::KeyShared(subscription) => subscription.force_ack(message_ref_key),
+        }
+    }
+
     pub fn nack(self: &Self, consumer_id: ConsumerId, message_ref_key: &str) -> bool {
         match self {
             Subscription::Shared(subscription) => subscription.nack(consumer_id, message_ref_key),
@@ -114,6 +523,84 @@ impl Subscription {
         }
     }
 
+    /// Nacks messages that were delivered to a consumer and not acked within the ack timeout
+    /// of the subscription. Returns the number of messages that timed out
+    pub fn nack_timed_out(self: &Self) -> usize {
+        match self {
+            Subscription::Shared(subscription) => subscription.nack_timed_out(),
+            Subscription::KeyShared(subscription) => subscription.nack_timed_out(),
+        }
+    }
+
+    /// Removes messages that are still waiting to be delivered and were published before
+    /// `oldest_published`, returning them so that the caller can remove them from the ledger.
+    /// Messages that were delivered to a consumer are never expired, so that they can be acked
+    pub fn expire(self: &Self, oldest_published: Timestamp) -> Vec<SubscribedMessage> {
+        match self {
+            Subscription::Shared(subscription) => subscription.expire(oldest_published),
+            Subscription::KeyShared(subscription) => subscription.expire(oldest_published),
+        }
+    }
+
+    /// The message ref keys of all of the messages that this subscription is holding, whether
+    /// they are queued, assigned to a consumer, or delivered and waiting to be acked
+    pub fn message_ref_keys(self: &Self) -> HashSet<String> {
+        match self {
+            Subscription::Shared(subscription) => subscription.message_ref_keys(),
+            Subscription::KeyShared(subscription) => subscription.message_ref_keys(),
+        }
+    }
+
+    /// Queues a message that the subscription lost track of. The max queue depth is not
+    /// applied because the message was already accepted when it was published
+    pub fn restore(self: &Self, message: SubscribedMessage) {
+        match self {
+            Subscription::Shared(subscription) => subscription.restore(message),
+            Subscription::KeyShared(subscription) => subscription.restore(message),
+        }
+    }
+
+    /// Rebuilds the messages that this subscription holds by replaying the event log of its
+    /// topic, so that a broker that restarts resumes delivering the messages that were not acked.
+    /// Messages that were delivered but not acked go back into the queue to be delivered again.
+    /// Ledgers start empty, so each replayed message is kept in its ledger until this
+    /// subscription acks it, and messages whose ledger was not loaded are skipped
+    pub fn replay(
+        self: &Self,
+        persistence: &PersistenceLayer,
+        partitions: &PartitionList,
+        created: Timestamp,
+    ) {
+        let backlog = replay_backlog(
+            persistence,
+            self.topic_id(),
+            self.subscription_id(),
+            created,
+        )
+        .into_iter()
+        .filter_map(|(published, message)| {
+            let message_ref = published.message_ref;
+            let ledger = partitions
+                .get(&message_ref.partition_id)?
+                .ledgers()
+                .get(&message_ref.ledger_id)?;
+            ledger.retain_message(&published);
+            Some(message)
+        })
+        .collect();
+        self.reset(backlog);
+    }
+
+    /// Replaces all of the messages that this subscription holds with a backlog, which is queued
+    /// in the order given. Messages that were delivered and not acked are forgotten, so later
+    /// acks and nacks for them have no effect. Returns the messages that were held before
+    pub fn reset(self: &Self, backlog: Vec<SubscribedMessage>) -> Vec<SubscribedMessage> {
+        match self {
+            Subscription::Shared(subscription) => subscription.replay(backlog),
+            Subscription::KeyShared(subscription) => subscription.replay(backlog),
+        }
+    }
+
     pub fn stats(self: &Self) -> SubscriptionStats {
         match self {
             Subscription::Shared(subscription) => subscription.stats(),
@@ -1,8 +1,8 @@
 use super::*;
-use crate::{data::DataLayer, utils::now_epoc_millis};
+use crate::{data::DataLayer, model::messages::MessageRef, utils::now_epoc_millis};
 use pulsar_rust_net::data_types::{ConsumerId, SubscriptionId, TopicId};
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     sync::{Arc, RwLock},
 };
 
@@ -20,20 +20,38 @@ struct MessageAffinity {
 }
 
 /// Implememts the semantic of key-shared subscriptions where 2 messages with the same
-/// key can not be in-flight with different consumers at the same point in time.
+/// key can not be in-flight with different consumers at the same point in time. With
+/// strict ordering, only one message for each key can be in-flight at a time.
 pub struct Subscription {
     data_layer: Arc<DataLayer>,
+    persistence: Arc<PersistenceLayer>,
+    ephemeral: bool,
     name: String,
     topic_id: TopicId,
     subscription_id: SubscriptionId,
+    created: Timestamp,
+    max_queue_depth: usize,
+    overflow_policy: QueueOverflowPolicy,
+    delivery_order: DeliveryOrder,
+    delivery_semantics: DeliverySemantics,
+    max_message_age_millis: u64,
+    assignment_timeout_millis: u64,
+    nack_redelivery_delay_millis: u64,
+    ack_timeout_millis: u64,
+    max_delivery_count: usize,
+    dead_letter_topic: Option<String>,
+    strict_ordering: bool,
+    delivery_transforms: Vec<DeliveryTransform>,
+    consumers: ConnectedConsumers,
 
     /// Newly published messages are initially added to this queue, and represent
     /// a subscription backlog
-    queued_messages: RwLock<VecDeque<SubscribedMessage>>,
+    queued_messages: RwLock<MessageQueue>,
 
     /// When messages are delivered to the consumer, they are dequeued from above,
     /// and added to this map so that we can find the message related to ack/nack.
-    /// This map is also used by a background thread that looks for Nack timeouts.
+    /// This map is also swept by a background thread that nacks messages that are not
+    /// acked within the ack timeout.
     delivered_messages: RwLock<HashMap<MessageRefKey, SubscribedMessage>>,
 
     /// These are messages that have the same key, and have an affinity to a consumer
@@ -55,6 +73,21 @@ impl Subscription {
     pub fn name(self: &Self) -> String {
         self.name.clone()
     }
+    pub fn created(self: &Self) -> Timestamp {
+        self.created
+    }
+    pub fn delivery_transforms(self: &Self) -> &[DeliveryTransform] {
+        &self.delivery_transforms
+    }
+    pub fn max_delivery_count(self: &Self) -> usize {
+        self.max_delivery_count
+    }
+    pub fn dead_letter_topic(self: &Self) -> Option<String> {
+        self.dead_letter_topic.clone()
+    }
+    pub fn delivery_semantics(self: &Self) -> DeliverySemantics {
+        self.delivery_semantics
+    }
 
     pub fn new(
         data_layer: &Arc<DataLayer>,
@@ -64,14 +97,45 @@ impl Subscription {
         let subscription = data_layer
             .get_subscription(topic_id, subscription_id)
             .unwrap();
+        let ephemeral = data_layer.get_topic(topic_id).unwrap().ephemeral;
         let name = subscription.name;
+        let created = subscription.created;
+        let max_queue_depth = subscription.max_queue_depth;
+        let overflow_policy = subscription.queue_overflow_policy;
+        let delivery_order = subscription.delivery_order;
+        let delivery_semantics = subscription.delivery_semantics;
+        let max_message_age_millis = subscription.max_message_age_millis;
+        let assignment_timeout_millis = subscription.assignment_timeout_millis;
+        let nack_redelivery_delay_millis = subscription.nack_redelivery_delay_millis;
+        let ack_timeout_millis = subscription.ack_timeout_millis;
+        let max_delivery_count = subscription.max_delivery_count;
+        let dead_letter_topic = subscription.dead_letter_topic;
+        let strict_ordering = subscription.strict_ordering;
+        let delivery_transforms = subscription.delivery_transforms;
+        let max_consumers = subscription.max_consumers;
 
         Self {
             data_layer: data_layer.clone(),
+            persistence: data_layer.persistence().clone(),
+            ephemeral,
             name,
             topic_id,
             subscription_id,
-            queued_messages: RwLock::new(VecDeque::new()),
+            created,
+            max_queue_depth,
+            overflow_policy,
+            delivery_order,
+            delivery_semantics,
+            max_message_age_millis,
+            assignment_timeout_millis,
+            nack_redelivery_delay_millis,
+            ack_timeout_millis,
+            max_delivery_count,
+            dead_letter_topic,
+            strict_ordering,
+            delivery_transforms,
+            consumers: ConnectedConsumers::new(max_consumers),
+            queued_messages: RwLock::new(MessageQueue::new()),
             delivered_messages: RwLock::new(HashMap::new()),
             assigned_messages: RwLock::new(HashMap::new()),
             affinity_map: RwLock::new(HashMap::new()),
@@ -79,21 +143,41 @@ impl Subscription {
     }
 
     pub fn stats(self: &Self) -> SubscriptionStats {
+        // Each lock is released before the next is taken. Holding them all at once can
+        // deadlock with writers that take them in a different order
+        let queued_count = self.queued_messages.read().unwrap().len();
+        let unacked_count = self.delivered_messages.read().unwrap().len();
+        let assigned_count = self
+            .assigned_messages
+            .read()
+            .unwrap()
+            .iter()
+            .fold(0, |sum, entry| sum + entry.1.len());
+        let affinity_count = self.affinity_map.read().unwrap().len();
         SubscriptionStats {
-            queued_count: self.queued_messages.read().unwrap().len(),
-            unacked_count: self.delivered_messages.read().unwrap().len(),
-            assigned_count: self
-                .assigned_messages
-                .read()
-                .unwrap()
-                .iter()
-                .fold(0, |sum, entry| sum + entry.1.len()),
-            affinity_count: self.affinity_map.read().unwrap().len(),
+            queued_count,
+            unacked_count,
+            assigned_count,
+            affinity_count,
         }
     }
 
+    pub fn connect_consumer(self: &Self) -> ConnectResult {
+        let consumer_id = self.consumers.connect(|| self.allocate_consumer_id())?;
+        log_event(
+            &self.persistence,
+            self.ephemeral,
+            &LoggedEvent::NewConsumer(logged_events::NewConsumerEvent {
+                topic_id: self.topic_id,
+                subscription_id: self.subscription_id,
+                consumer_id,
+            }),
+        );
+        Ok(consumer_id)
+    }
+
     /// Increments the next consumer id in the database and returns the original value
-    pub fn connect_consumer(self: &Self) -> Option<ConsumerId> {
+    fn allocate_consumer_id(self: &Self) -> Option<ConsumerId> {
         let mut next_consumer_id: ConsumerId = 0;
 
         match self.data_layer.update_subscription(
@@ -117,47 +201,130 @@ impl Subscription {
         }
     }
 
+    /// Moves the messages and key affinities of a disconnected consumer to another consumer
+    /// so that messages with the same key continue to be delivered in order. Messages that
+    /// were delivered to the disconnected consumer but not acked are delivered again first.
+    /// If there are no other consumers, the messages go back to the front of the input queue
     pub fn disconnect_consumer(self: &Self, consumer_id: ConsumerId) {
-        // Make a list of the keys with affinity to this consumer
-        let affinity_map = self.affinity_map.read().unwrap();
-        let keys: Vec<&String> = affinity_map
-            .iter()
-            .filter_map(|a| {
-                if a.1.consumer_id == consumer_id {
-                    Some(a.0)
-                } else {
-                    None
-                }
-            })
-            .collect();
+        self.consumers.disconnect(consumer_id);
+        log_event(
+            &self.persistence,
+            self.ephemeral,
+            &LoggedEvent::DropConsumer(logged_events::DropConsumerEvent {
+                topic_id: self.topic_id,
+                subscription_id: self.subscription_id,
+                consumer_id,
+            }),
+        );
 
-        // Remove all of these keys from the affinity map
+        let mut delivered_messages = self.delivered_messages.write().unwrap();
         let mut affinity_map = self.affinity_map.write().unwrap();
-        for key in keys {
-            affinity_map.remove(key);
-        }
-
-        // Push messages assigned to this consumer back into the input queue
         let mut assigned_messages = self.assigned_messages.write().unwrap();
-        if let Some(queue) = assigned_messages.get_mut(&consumer_id) {
-            queue.into_iter().for_each(|message| {
-                self.queued_messages
-                    .write()
-                    .unwrap()
-                    .push_front(message.to_owned());
-            });
-        }
 
-        assigned_messages.remove(&consumer_id);
+        // Take the in-flight messages for this consumer, and sort them into publication order
+        let mut messages: Vec<SubscribedMessage> = assigned_messages
+            .remove(&consumer_id)
+            .map(|queue| queue.into_iter().collect())
+            .unwrap_or_default();
+        let unacked_keys: Vec<MessageRefKey> = delivered_messages
+            .values()
+            .filter(|message| message.consumer_id == Some(consumer_id))
+            .map(|message| message.message_ref_key.clone())
+            .collect();
+        for message_ref_key in unacked_keys {
+            if let Some(message) = delivered_messages.remove(&message_ref_key) {
+                messages.push(message);
+            }
+        }
+        messages.sort_by_key(|message| {
+            let message_ref = MessageRef::from_key(&message.message_ref_key);
+            (message_ref.ledger_id, message_ref.message_id)
+        });
+
+        // Choose the remaining consumer with the fewest assigned messages
+        let survivor = affinity_map
+            .values()
+            .map(|affinity| affinity.consumer_id)
+            .chain(assigned_messages.keys().copied())
+            .chain(
+                delivered_messages
+                    .values()
+                    .filter_map(|message| message.consumer_id),
+            )
+            .filter(|id| *id != consumer_id)
+            .min_by_key(|id| assigned_messages.get(id).map_or(0, |queue| queue.len()));
+
+        match survivor {
+            Some(survivor) => {
+                for affinity in affinity_map.values_mut() {
+                    if affinity.consumer_id == consumer_id {
+                        affinity.consumer_id = survivor;
+                    }
+                }
+                let now = now_epoc_millis();
+                let queue = assigned_messages.entry(survivor).or_default();
+                for mut message in messages.into_iter().rev() {
+                    message.consumer_id = None;
+                    message.assigned_timestamp = Some(now);
+                    queue.push_front(message);
+                }
+            }
+            None => {
+                affinity_map.retain(|_, affinity| affinity.consumer_id != consumer_id);
+                let mut queue = self.queued_messages.write().unwrap();
+                for mut message in messages.into_iter().rev() {
+                    message.consumer_id = None;
+                    message.assigned_timestamp = None;
+                    queue.push_front(message);
+                }
+            }
+        }
     }
 
     /// Queues a message for delivery to this subscription
-    pub fn push(self: &Self, message: SubscribedMessage) {
+    pub fn push(self: &Self, message: SubscribedMessage) -> PushResult {
+        let mut queue = self.queued_messages.write().unwrap();
+        push_with_limit(
+            &mut queue,
+            message,
+            self.max_queue_depth,
+            self.overflow_policy,
+        )
+    }
+
+    pub fn restore(self: &Self, message: SubscribedMessage) {
         self.queued_messages.write().unwrap().push_back(message);
     }
 
+    pub fn message_ref_keys(self: &Self) -> HashSet<MessageRefKey> {
+        // Locks are taken in the same order as disconnect_consumer
+        let delivered_messages = self.delivered_messages.read().unwrap();
+        let assigned_messages = self.assigned_messages.read().unwrap();
+        let queue = self.queued_messages.read().unwrap();
+        queue
+            .iter()
+            .chain(assigned_messages.values().flatten())
+            .map(|message| message.message_ref_key.clone())
+            .chain(delivered_messages.keys().cloned())
+            .collect()
+    }
+
+    /// Returns true if the queue is full and the overflow policy is to block the publisher
+    pub fn blocks_publisher(self: &Self) -> bool {
+        self.overflow_policy == QueueOverflowPolicy::Block
+            && self.max_queue_depth > 0
+            && self.queued_messages.read().unwrap().len() >= self.max_queue_depth
+    }
+
     /// Retrieves the next message for a consumer if there is one
-    pub fn pop(self: &Self, consumer_id: ConsumerId) -> Option<SubscribedMessage> {
+    pub fn pop(
+        self: &Self,
+        consumer_id: ConsumerId,
+        expired: &mut Vec<SubscribedMessage>,
+    ) -> Option<SubscribedMessage> {
+        // Take back messages from consumers that stopped consuming
+        self.reassign_stalled();
+
         // Loop until we dequeue a message that is deliverable to this consumer
         loop {
             // 1. deliver messages that are queued for this consumer specifically
@@ -168,7 +335,13 @@ impl Subscription {
 
             // 2. Get a message from the general input queue
             let mut queue = self.queued_messages.write().unwrap();
-            let message = queue.pop_front()?;
+            let message = pop_fresh(
+                &mut queue,
+                self.delivery_order,
+                self.strict_ordering,
+                self.max_message_age_millis,
+                expired,
+            )?;
             drop(queue);
 
             // 3. If this message has an affinity to a consumer then assign it to that consumer
@@ -186,16 +359,123 @@ impl Subscription {
         }
     }
 
+    /// The message stays assigned to the consumer, so the affinity for its key is unchanged.
+    /// At-most-once subscriptions released the affinity when the message was popped, so it
+    /// is taken again
+    pub fn unpop(self: &Self, consumer_id: ConsumerId, message: SubscribedMessage) -> bool {
+        // Locks are taken in the same order as disconnect_consumer
+        let mut delivered_messages = self.delivered_messages.write().unwrap();
+        let mut affinity_map = self.affinity_map.write().unwrap();
+        let mut assigned_messages = self.assigned_messages.write().unwrap();
+        let (mut message, consumer_id) = match self.delivery_semantics {
+            DeliverySemantics::AtLeastOnce => {
+                match delivered_messages.remove(&message.message_ref_key) {
+                    Some(message) => (message, consumer_id),
+                    None => return false,
+                }
+            }
+            DeliverySemantics::AtMostOnce => {
+                let affinity = affinity_map
+                    .entry(message.key.clone())
+                    .or_insert(MessageAffinity {
+                        consumer_id,
+                        message_count: 0,
+                    });
+                affinity.message_count += 1;
+                let consumer_id = affinity.consumer_id;
+                (message, consumer_id)
+            }
+        };
+        undeliver(&mut message);
+        assigned_messages
+            .entry(consumer_id)
+            .or_default()
+            .push_front(message);
+        true
+    }
+
     pub fn ack(self: &Self, consumer_id: ConsumerId, message_ref_key: &str) -> bool {
         self.decrement_affinity(message_ref_key, consumer_id)
             .is_some()
     }
 
+    pub fn is_delivered(self: &Self, message_ref_key: &str) -> bool {
+        let delivered_messages = self.delivered_messages.read().unwrap();
+        delivered_messages.contains_key(message_ref_key)
+    }
+
+    pub fn ack_batch(self: &Self, acks: &[(ConsumerId, String)]) -> Vec<String> {
+        let mut delivered_messages = self.delivered_messages.write().unwrap();
+        let mut affinity_map = self.affinity_map.write().unwrap();
+        acks.iter()
+            .filter(|(consumer_id, message_ref_key)| {
+                Self::release_affinity(
+                    &mut delivered_messages,
+                    &mut affinity_map,
+                    message_ref_key,
+                    *consumer_id,
+                )
+                .is_some()
+            })
+            .map(|(_, message_ref_key)| message_ref_key.clone())
+            .collect()
+    }
+
+    /// Removes a message from the subscription whether it was delivered, assigned to a
+    /// consumer or is still queued
+    pub fn force_ack(self: &Self, message_ref_key: &str) -> bool {
+        let mut delivered_messages = self.delivered_messages.write().unwrap();
+        let mut affinity_map = self.affinity_map.write().unwrap();
+        let mut assigned_messages = self.assigned_messages.write().unwrap();
+
+        let in_flight = delivered_messages.remove(message_ref_key).or_else(|| {
+            assigned_messages.values_mut().find_map(|queue| {
+                let index = queue
+                    .iter()
+                    .position(|message| message.message_ref_key == message_ref_key)?;
+                queue.remove(index)
+            })
+        });
+
+        match in_flight {
+            Some(message) => {
+                if let Some(affinity) = affinity_map.get_mut(&message.key) {
+                    if affinity.message_count <= 1 {
+                        affinity_map.remove(&message.key);
+                    } else {
+                        affinity.message_count -= 1;
+                    }
+                }
+                true
+            }
+            None => {
+                let mut queue = self.queued_messages.write().unwrap();
+                queue.remove(message_ref_key).is_some()
+            }
+        }
+    }
+
     pub fn nack(self: &Self, consumer_id: ConsumerId, message_ref_key: &str) -> bool {
-        if let Some((message, count)) = self.decrement_affinity(message_ref_key, consumer_id) {
+        // Locks are taken in the same order as disconnect_consumer
+        let mut delivered_messages = self.delivered_messages.write().unwrap();
+        let mut affinity_map = self.affinity_map.write().unwrap();
+        let released = Self::release_affinity(
+            &mut delivered_messages,
+            &mut affinity_map,
+            message_ref_key,
+            consumer_id,
+        );
+        if let Some((mut message, count)) = released {
+            message.nack_until = nack_until(self.nack_redelivery_delay_millis);
             if count == 0 {
-                self.queued_messages.write().unwrap().push_front(message);
+                let mut queue = self.queued_messages.write().unwrap();
+                requeue(&mut queue, message, self.delivery_order);
             } else {
+                // The message goes back to the consumer, so it still counts towards the affinity
+                if let Some(affinity) = affinity_map.get_mut(&message.key) {
+                    affinity.message_count += 1;
+                }
+                message.assigned_timestamp = Some(now_epoc_millis());
                 let mut assigned_messages = self.assigned_messages.write().unwrap();
                 let consumer_queue = assigned_messages.get_mut(&consumer_id);
                 match consumer_queue {
@@ -213,11 +493,112 @@ impl Subscription {
         }
     }
 
+    /// Treats messages that were delivered more than the ack timeout ago as if the consumer had
+    /// nacked them. Messages whose key still has an affinity with the consumer are assigned back
+    /// to that consumer, and the others go back to the input queue. Returns the number of
+    /// messages that timed out
+    pub fn nack_timed_out(self: &Self) -> usize {
+        if self.ack_timeout_millis == 0 {
+            return 0;
+        }
+        let oldest_delivered = now_epoc_millis().saturating_sub(self.ack_timeout_millis);
+
+        // Locks are taken in the same order as ack, nack and disconnect_consumer
+        let mut delivered_messages = self.delivered_messages.write().unwrap();
+        let mut affinity_map = self.affinity_map.write().unwrap();
+
+        let timed_out: Vec<(MessageRefKey, ConsumerId)> = delivered_messages
+            .values()
+            .filter(|message| {
+                message
+                    .delivered_timestamp
+                    .is_some_and(|delivered| delivered < oldest_delivered)
+            })
+            .filter_map(|message| Some((message.message_ref_key.clone(), message.consumer_id?)))
+            .collect();
+        if timed_out.is_empty() {
+            return 0;
+        }
+
+        let nack_until = nack_until(self.nack_redelivery_delay_millis);
+        let mut messages: Vec<(SubscribedMessage, ConsumerId)> = timed_out
+            .iter()
+            .filter_map(|(message_ref_key, consumer_id)| {
+                let (mut message, _) = Self::release_affinity(
+                    &mut delivered_messages,
+                    &mut affinity_map,
+                    message_ref_key,
+                    *consumer_id,
+                )?;
+                message.nack_until = nack_until;
+                Some((message, *consumer_id))
+            })
+            .collect();
+        messages.sort_by_key(|(message, _)| {
+            let message_ref = MessageRef::from_key(&message.message_ref_key);
+            (message_ref.ledger_id, message_ref.message_id)
+        });
+        let count = messages.len();
+
+        // Keys are only released once all of their timed out messages are, so that messages
+        // with the same key are not split between a consumer and the input queue
+        let (mut assigned, mut queued): (Vec<_>, Vec<_>) =
+            messages.into_iter().partition(|(message, consumer_id)| {
+                affinity_map
+                    .get(&message.key)
+                    .is_some_and(|affinity| affinity.consumer_id == *consumer_id)
+            });
+
+        // Requeueing at the front reverses the order, so start with the newest message
+        assigned.reverse();
+        if let DeliveryOrder::Fifo = self.delivery_order {
+            queued.reverse();
+        }
+
+        let now = now_epoc_millis();
+        let mut assigned_messages = self.assigned_messages.write().unwrap();
+        for (mut message, consumer_id) in assigned {
+            // The message goes back to the consumer, so it still counts towards the affinity
+            if let Some(affinity) = affinity_map.get_mut(&message.key) {
+                affinity.message_count += 1;
+            }
+            message.assigned_timestamp = Some(now);
+            assigned_messages
+                .entry(consumer_id)
+                .or_default()
+                .push_front(message);
+        }
+        let mut queue = self.queued_messages.write().unwrap();
+        for (message, _) in queued {
+            requeue(&mut queue, message, self.delivery_order);
+        }
+        count
+    }
+
     fn message_delivered_to(self: &Self, message: &mut SubscribedMessage, consumer_id: ConsumerId) {
         message.delivered_timestamp = Some(now_epoc_millis());
+        message.first_delivered_timestamp = message
+            .first_delivered_timestamp
+            .or(message.delivered_timestamp);
         message.consumer_id = Some(consumer_id);
         message.delivery_count += 1;
 
+        // At-most-once subscriptions forget messages as soon as they are delivered, so the
+        // affinity is released now rather than when the message is acked
+        if self.delivery_semantics == DeliverySemantics::AtMostOnce {
+            let mut affinity_map = self.affinity_map.write().unwrap();
+            if let Some(affinity) = affinity_map.get_mut(&message.key) {
+                if affinity.consumer_id == consumer_id {
+                    if affinity.message_count <= 1 {
+                        affinity_map.remove(&message.key);
+                    } else {
+                        affinity.message_count -= 1;
+                    }
+                }
+            }
+            return;
+        }
+
         let mut delivered_messages = self.delivered_messages.write().unwrap();
         delivered_messages.insert(message.message_ref_key.clone(), message.clone());
     }
@@ -231,6 +612,18 @@ impl Subscription {
                 message_count: 1,
             },
         );
+        drop(affinity_map);
+
+        log_event(
+            &self.persistence,
+            self.ephemeral,
+            &LoggedEvent::KeyAffinity(logged_events::KeyAffinityEvent {
+                topic_id: self.topic_id,
+                subscription_id: self.subscription_id,
+                consumer_id,
+                message_key: message.key.clone(),
+            }),
+        );
     }
 
     fn increment_affinity(self: &Self, message: &SubscribedMessage) -> Option<ConsumerId> {
@@ -249,10 +642,26 @@ impl Subscription {
         consumer_id: ConsumerId,
     ) -> Option<(SubscribedMessage, usize)> {
         let mut delivered_messages = self.delivered_messages.write().unwrap();
+        let mut affinity_map = self.affinity_map.write().unwrap();
+        Self::release_affinity(
+            &mut delivered_messages,
+            &mut affinity_map,
+            message_ref_key,
+            consumer_id,
+        )
+    }
+
+    // Removes a delivered message and decrements the affinity count for its key. The
+    // caller must hold the locks on the delivered messages and the affinity map
+    fn release_affinity(
+        delivered_messages: &mut HashMap<MessageRefKey, SubscribedMessage>,
+        affinity_map: &mut HashMap<MessageKey, MessageAffinity>,
+        message_ref_key: &str,
+        consumer_id: ConsumerId,
+    ) -> Option<(SubscribedMessage, usize)> {
         match delivered_messages.remove(message_ref_key) {
             Some(message) => {
                 let mut count = 0;
-                let mut affinity_map = self.affinity_map.write().unwrap();
                 if let Some(affinity) = affinity_map.get_mut(&message.key) {
                     if consumer_id == affinity.consumer_id {
                         if affinity.message_count == 1 {
@@ -270,7 +679,8 @@ impl Subscription {
     }
 
     // Assign a message to the consumer that it has an affinity with
-    fn assign_consumer(self: &Self, message: SubscribedMessage, consumer_id: ConsumerId) {
+    fn assign_consumer(self: &Self, mut message: SubscribedMessage, consumer_id: ConsumerId) {
+        message.assigned_timestamp = Some(now_epoc_millis());
         let mut assigned_messages = self.assigned_messages.write().unwrap();
         let consumer_queue = assigned_messages.get_mut(&consumer_id);
         match consumer_queue {
@@ -283,11 +693,130 @@ impl Subscription {
         }
     }
 
-    // Return the next message that is assigned to a consumer
+    // Moves messages that were assigned to a consumer more than the assignment timeout ago back
+    // into the input queue so that they can be assigned to another consumer. Keys that have
+    // delivered messages still in-flight with the consumer keep their affinity
+    fn reassign_stalled(self: &Self) {
+        if self.assignment_timeout_millis == 0 {
+            return;
+        }
+        let oldest_assigned = now_epoc_millis().saturating_sub(self.assignment_timeout_millis);
+
+        let mut affinity_map = self.affinity_map.write().unwrap();
+        let mut assigned_messages = self.assigned_messages.write().unwrap();
+        let mut reassigned = Vec::new();
+
+        for (consumer_id, queue) in assigned_messages.iter_mut() {
+            // Count the assigned messages for each key, and find keys with stalled messages
+            let mut assigned_counts: HashMap<MessageKey, usize> = HashMap::new();
+            let mut stalled_keys: HashSet<MessageKey> = HashSet::new();
+            for message in queue.iter() {
+                *assigned_counts.entry(message.key.clone()).or_default() += 1;
+                if message
+                    .assigned_timestamp
+                    .is_some_and(|assigned| assigned < oldest_assigned)
+                {
+                    stalled_keys.insert(message.key.clone());
+                }
+            }
+            if stalled_keys.is_empty() {
+                continue;
+            }
+
+            // Only release the affinity if none of the messages with this key are in-flight
+            stalled_keys.retain(|key| match affinity_map.get(key) {
+                Some(affinity) => {
+                    affinity.consumer_id == *consumer_id
+                        && affinity.message_count == assigned_counts[key]
+                }
+                None => true,
+            });
+            for key in &stalled_keys {
+                affinity_map.remove(key);
+            }
+
+            let (released, kept): (VecDeque<_>, VecDeque<_>) = queue
+                .drain(..)
+                .partition(|message| stalled_keys.contains(&message.key));
+            *queue = kept;
+            reassigned.extend(released);
+        }
+
+        if !reassigned.is_empty() {
+            let mut queue = self.queued_messages.write().unwrap();
+            for mut message in reassigned.into_iter().rev() {
+                message.assigned_timestamp = None;
+                queue.push_front(message);
+            }
+        }
+    }
+
+    // Return the next message that is assigned to a consumer, skipping nacked messages whose
+    // redelivery delay has not elapsed. With strict ordering, messages are also skipped while
+    // an earlier message with the same key is delivered and not yet acked, or is delayed
     fn pop_assigned(self: &Self, consumer_id: ConsumerId) -> Option<SubscribedMessage> {
+        let now = now_epoc_millis();
+        let is_delayed =
+            |message: &SubscribedMessage| message.nack_until.is_some_and(|until| until > now);
+
+        if !self.strict_ordering {
+            let mut assigned_messages = self.assigned_messages.write().unwrap();
+            let consumer_queue = assigned_messages.get_mut(&consumer_id)?;
+            let index = consumer_queue
+                .iter()
+                .position(|message| !is_delayed(message))?;
+            return consumer_queue.remove(index);
+        }
+
+        // Locks are taken in the same order as disconnect_consumer
+        let delivered_messages = self.delivered_messages.read().unwrap();
         let mut assigned_messages = self.assigned_messages.write().unwrap();
         let consumer_queue = assigned_messages.get_mut(&consumer_id)?;
-        consumer_queue.pop_front()
+        let index = {
+            let mut held_keys: HashSet<&MessageKey> = delivered_messages
+                .values()
+                .filter(|message| message.consumer_id == Some(consumer_id))
+                .map(|message| &message.key)
+                .collect();
+            consumer_queue.iter().position(|message| {
+                if is_delayed(message) {
+                    held_keys.insert(&message.key);
+                    false
+                } else {
+                    !held_keys.contains(&message.key)
+                }
+            })
+        }?;
+        consumer_queue.remove(index)
+    }
+
+    /// Removes messages from the input queue that were published before `oldest_published`.
+    /// Messages that were assigned to a consumer count towards the affinity of their key, so
+    /// they are left to be delivered, and delivered messages stay in the subscription until
+    /// the consumer acks or nacks them
+    pub fn expire(self: &Self, oldest_published: Timestamp) -> Vec<SubscribedMessage> {
+        let mut queue = self.queued_messages.write().unwrap();
+        queue.remove_published_before(oldest_published)
+    }
+
+    /// Replaces all of the messages in the subscription with a backlog that was replayed from
+    /// the event log. The backlog is queued in the order given, and no keys have an affinity.
+    /// Returns the messages that the subscription held before
+    pub fn replay(self: &Self, backlog: Vec<SubscribedMessage>) -> Vec<SubscribedMessage> {
+        // Locks are taken in the same order as force_ack
+        let mut delivered_messages = self.delivered_messages.write().unwrap();
+        let mut affinity_map = self.affinity_map.write().unwrap();
+        let mut assigned_messages = self.assigned_messages.write().unwrap();
+        let mut queue = self.queued_messages.write().unwrap();
+        let mut held: Vec<SubscribedMessage> = queue.iter().cloned().collect();
+        held.extend(assigned_messages.drain().flat_map(|(_, messages)| messages));
+        held.extend(delivered_messages.drain().map(|(_, message)| message));
+        affinity_map.clear();
+        *queue = MessageQueue::new();
+        for message in backlog {
+            queue.push_back(message);
+        }
+        held
     }
 
     pub fn refresh(self: &mut Self, _data_layer: &Arc<DataLayer>) {}
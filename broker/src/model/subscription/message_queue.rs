@@ -0,0 +1,119 @@
+/*
+Holds the messages that are waiting to be delivered to the consumers of a subscription.
+Messages with a higher priority are delivered first, and messages with the same priority
+are kept in the order that they were published.
+*/
+
+use super::SubscribedMessage;
+use pulsar_rust_net::data_types::{Priority, Timestamp};
+use std::collections::{BTreeMap, VecDeque};
+
+pub struct MessageQueue {
+    levels: BTreeMap<Priority, VecDeque<SubscribedMessage>>,
+    len: usize,
+}
+
+impl MessageQueue {
+    pub fn new() -> Self {
+        Self {
+            levels: BTreeMap::new(),
+            len: 0,
+        }
+    }
+
+    pub fn len(self: &Self) -> usize {
+        self.len
+    }
+
+    /// Adds a message after all other messages with the same priority
+    pub fn push_back(self: &mut Self, message: SubscribedMessage) {
+        self.levels
+            .entry(message.priority)
+            .or_default()
+            .push_back(message);
+        self.len += 1;
+    }
+
+    /// Adds a message before all other messages with the same priority
+    pub fn push_front(self: &mut Self, message: SubscribedMessage) {
+        self.levels
+            .entry(message.priority)
+            .or_default()
+            .push_front(message);
+        self.len += 1;
+    }
+
+    /// Removes the oldest message with the highest priority
+    pub fn pop_front(self: &mut Self) -> Option<SubscribedMessage> {
+        let mut level = self.levels.last_entry()?;
+        let message = level.get_mut().pop_front();
+        if level.get().is_empty() {
+            level.remove();
+        }
+        self.len -= 1;
+        message
+    }
+
+    /// Removes the newest message with the highest priority
+    pub fn pop_back(self: &mut Self) -> Option<SubscribedMessage> {
+        let mut level = self.levels.last_entry()?;
+        let message = level.get_mut().pop_back();
+        if level.get().is_empty() {
+            level.remove();
+        }
+        self.len -= 1;
+        message
+    }
+
+    /// Removes the oldest message with the lowest priority
+    pub fn pop_lowest(self: &mut Self) -> Option<SubscribedMessage> {
+        let mut level = self.levels.first_entry()?;
+        let message = level.get_mut().pop_front();
+        if level.get().is_empty() {
+            level.remove();
+        }
+        self.len -= 1;
+        message
+    }
+
+    /// Iterates over the messages in the queue, in no particular order
+    pub fn iter(self: &Self) -> impl Iterator<Item = &SubscribedMessage> {
+        self.levels.values().flatten()
+    }
+
+    /// Removes a specific message from anywhere in the queue
+    pub fn remove(self: &mut Self, message_ref_key: &str) -> Option<SubscribedMessage> {
+        let (priority, index) = self.levels.iter().find_map(|(priority, level)| {
+            level
+                .iter()
+                .position(|message| message.message_ref_key == message_ref_key)
+                .map(|index| (*priority, index))
+        })?;
+        let level = self.levels.get_mut(&priority)?;
+        let message = level.remove(index);
+        if level.is_empty() {
+            self.levels.remove(&priority);
+        }
+        self.len -= 1;
+        message
+    }
+
+    /// Removes all of the messages that were published before `oldest_published`, keeping
+    /// the remaining messages in order
+    pub fn remove_published_before(
+        self: &mut Self,
+        oldest_published: Timestamp,
+    ) -> Vec<SubscribedMessage> {
+        let mut removed = Vec::new();
+        for level in self.levels.values_mut() {
+            let (expired, kept): (VecDeque<_>, VecDeque<_>) = level
+                .drain(..)
+                .partition(|message| message.published < oldest_published);
+            *level = kept;
+            removed.extend(expired);
+        }
+        self.levels.retain(|_, level| !level.is_empty());
+        self.len -= removed.len();
+        removed
+    }
+}
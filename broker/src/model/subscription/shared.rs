@@ -1,7 +1,10 @@
-use crate::{data::DataLayer, utils::now_epoc_millis};
+use crate::{
+    data::DataLayer, model::messages::MessageRef,
+    persistence::persisted_entities::SubscriptionType, utils::now_epoc_millis,
+};
 use pulsar_rust_net::data_types::{ConsumerId, SubscriptionId, TopicId};
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet},
     sync::{Arc, RwLock},
 };
 
@@ -9,21 +12,40 @@ use super::*;
 
 pub struct Subscription {
     data_layer: Arc<DataLayer>,
+    persistence: Arc<PersistenceLayer>,
+    ephemeral: bool,
     name: String,
     topic_id: TopicId,
     subscription_id: SubscriptionId,
+    created: Timestamp,
+    subscription_type: SubscriptionType,
+    max_queue_depth: usize,
+    overflow_policy: QueueOverflowPolicy,
+    delivery_order: DeliveryOrder,
+    delivery_semantics: DeliverySemantics,
+    max_message_age_millis: u64,
+    nack_redelivery_delay_millis: u64,
+    ack_timeout_millis: u64,
+    max_delivery_count: usize,
+    dead_letter_topic: Option<String>,
+    delivery_transforms: Vec<DeliveryTransform>,
+    consumers: ConnectedConsumers,
 
     /// Newly published messages are initially added to this queue, and represent
     /// a subscription backlog
-    queued_messages: RwLock<VecDeque<SubscribedMessage>>,
+    queued_messages: RwLock<MessageQueue>,
 
     /// When messages are delivered to the consumer, they are dequeued from above,
     /// and added to this map so that we can find the message related to ack/nack.
-    /// This map is also used by a background thread that looks for Nack timeouts.
+    /// This map is also swept by a background thread that nacks messages that are not
+    /// acked within the ack timeout.
     delivered_messages: RwLock<HashMap<String, SubscribedMessage>>,
 }
 
-/// Implements semantics for shared subscriptions where messages do not have consumer affinity
+/// Implements semantics for shared subscriptions where messages do not have consumer affinity.
+/// Exclusive and failover subscriptions work the same way, except that exclusive subscriptions
+/// only allow one consumer to connect, and failover subscriptions only deliver to the consumer
+/// that connected first
 impl Subscription {
     pub fn topic_id(self: &Self) -> TopicId {
         self.topic_id
@@ -34,6 +56,21 @@ impl Subscription {
     pub fn name(self: &Self) -> String {
         self.name.clone()
     }
+    pub fn created(self: &Self) -> Timestamp {
+        self.created
+    }
+    pub fn delivery_transforms(self: &Self) -> &[DeliveryTransform] {
+        &self.delivery_transforms
+    }
+    pub fn max_delivery_count(self: &Self) -> usize {
+        self.max_delivery_count
+    }
+    pub fn dead_letter_topic(self: &Self) -> Option<String> {
+        self.dead_letter_topic.clone()
+    }
+    pub fn delivery_semantics(self: &Self) -> DeliverySemantics {
+        self.delivery_semantics
+    }
 
     pub fn new(
         data_layer: &Arc<DataLayer>,
@@ -43,42 +80,128 @@ impl Subscription {
         let subscription = data_layer
             .get_subscription(topic_id, subscription_id)
             .unwrap();
+        let ephemeral = data_layer.get_topic(topic_id).unwrap().ephemeral;
         let name = subscription.name;
+        let created = subscription.created;
+        let subscription_type = subscription.subscription_type;
+        let max_queue_depth = subscription.max_queue_depth;
+        let overflow_policy = subscription.queue_overflow_policy;
+        let delivery_order = subscription.delivery_order;
+        let delivery_semantics = subscription.delivery_semantics;
+        let max_message_age_millis = subscription.max_message_age_millis;
+        let nack_redelivery_delay_millis = subscription.nack_redelivery_delay_millis;
+        let ack_timeout_millis = subscription.ack_timeout_millis;
+        let max_delivery_count = subscription.max_delivery_count;
+        let dead_letter_topic = subscription.dead_letter_topic;
+        let delivery_transforms = subscription.delivery_transforms;
+        let max_consumers = match subscription_type {
+            SubscriptionType::Exclusive => 1,
+            _ => subscription.max_consumers,
+        };
 
         Self {
             data_layer: data_layer.clone(),
+            persistence: data_layer.persistence().clone(),
+            ephemeral,
             name,
             topic_id,
             subscription_id,
-            queued_messages: RwLock::new(VecDeque::new()),
+            created,
+            subscription_type,
+            max_queue_depth,
+            overflow_policy,
+            delivery_order,
+            delivery_semantics,
+            max_message_age_millis,
+            nack_redelivery_delay_millis,
+            ack_timeout_millis,
+            max_delivery_count,
+            dead_letter_topic,
+            delivery_transforms,
+            consumers: ConnectedConsumers::new(max_consumers),
+            queued_messages: RwLock::new(MessageQueue::new()),
             delivered_messages: RwLock::new(HashMap::new()),
         }
     }
 
     pub fn stats(self: &Self) -> SubscriptionStats {
+        // Each lock is released before the next is taken, so that readers never hold both
+        let queued_count = self.queued_messages.read().unwrap().len();
+        let unacked_count = self.delivered_messages.read().unwrap().len();
         SubscriptionStats {
-            queued_count: self.queued_messages.read().unwrap().len(),
-            unacked_count: self.delivered_messages.read().unwrap().len(),
+            queued_count,
+            unacked_count,
             assigned_count: 0,
             affinity_count: 0,
         }
     }
 
     /// Adds a message to the queue for delivery to this subscriber
-    pub fn push(self: &Self, message: SubscribedMessage) {
+    pub fn push(self: &Self, message: SubscribedMessage) -> PushResult {
         let mut queue = self.queued_messages.write().unwrap();
-        queue.push_back(message);
+        push_with_limit(
+            &mut queue,
+            message,
+            self.max_queue_depth,
+            self.overflow_policy,
+        )
+    }
+
+    pub fn restore(self: &Self, message: SubscribedMessage) {
+        self.queued_messages.write().unwrap().push_back(message);
     }
 
-    /// Removes a message from the front of the queue for this subscription if there is one
-    pub fn pop(self: &Self, consumer_id: ConsumerId) -> Option<SubscribedMessage> {
+    pub fn message_ref_keys(self: &Self) -> HashSet<String> {
+        let queue = self.queued_messages.read().unwrap();
+        let delivered_messages = self.delivered_messages.read().unwrap();
+        queue
+            .iter()
+            .map(|message| message.message_ref_key.clone())
+            .chain(delivered_messages.keys().cloned())
+            .collect()
+    }
+
+    /// Returns true if the queue is full and the overflow policy is to block the publisher
+    pub fn blocks_publisher(self: &Self) -> bool {
+        self.overflow_policy == QueueOverflowPolicy::Block
+            && self.max_queue_depth > 0
+            && self.queued_messages.read().unwrap().len() >= self.max_queue_depth
+    }
+
+    /// Removes a message from the front of the queue for this subscription if there is one.
+    /// Standby consumers of a failover subscription do not receive any messages
+    pub fn pop(
+        self: &Self,
+        consumer_id: ConsumerId,
+        expired: &mut Vec<SubscribedMessage>,
+    ) -> Option<SubscribedMessage> {
+        if self.subscription_type == SubscriptionType::Failover
+            && !self.consumers.is_first(consumer_id)
+        {
+            return None;
+        }
+
         let mut queue = self.queued_messages.write().unwrap();
-        let mut message = queue.pop_front()?;
+        let mut message = pop_fresh(
+            &mut queue,
+            self.delivery_order,
+            false,
+            self.max_message_age_millis,
+            expired,
+        )?;
         drop(queue);
 
         message.consumer_id = Some(consumer_id);
         message.delivery_count += 1;
         message.delivered_timestamp = Some(now_epoc_millis());
+        message.first_delivered_timestamp = message
+            .first_delivered_timestamp
+            .or(message.delivered_timestamp);
+
+        // At-most-once subscriptions forget messages as soon as they are delivered
+        if self.delivery_semantics == DeliverySemantics::AtMostOnce {
+            return Some(message);
+        }
 
         let result = Some(message.clone());
         let mut delivered_messages = self.delivered_messages.write().unwrap();
@@ -86,7 +209,39 @@ impl Subscription {
         result
     }
 
-    pub fn connect_consumer(self: &Self) -> Option<ConsumerId> {
+    pub fn unpop(self: &Self, message: SubscribedMessage) -> bool {
+        let message = match self.delivery_semantics {
+            DeliverySemantics::AtLeastOnce => {
+                let mut delivered_messages = self.delivered_messages.write().unwrap();
+                delivered_messages.remove(&message.message_ref_key)
+            }
+            DeliverySemantics::AtMostOnce => Some(message),
+        };
+        if let Some(mut message) = message {
+            undeliver(&mut message);
+            let mut queue = self.queued_messages.write().unwrap();
+            requeue(&mut queue, message, self.delivery_order);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn connect_consumer(self: &Self) -> ConnectResult {
+        let consumer_id = self.consumers.connect(|| self.allocate_consumer_id())?;
+        log_event(
+            &self.persistence,
+            self.ephemeral,
+            &LoggedEvent::NewConsumer(logged_events::NewConsumerEvent {
+                topic_id: self.topic_id,
+                subscription_id: self.subscription_id,
+                consumer_id,
+            }),
+        );
+        Ok(consumer_id)
+    }
+
+    fn allocate_consumer_id(self: &Self) -> Option<ConsumerId> {
         let mut next_consumer_id: ConsumerId = 0;
 
         match self.data_layer.update_subscription(
@@ -110,23 +265,151 @@ impl Subscription {
         }
     }
 
-    pub fn disconnect_consumer(self: &Self, _consumer_id: ConsumerId) {}
+    /// Returns messages that were delivered to a disconnected consumer but not acked to the
+    /// queue so that they are redelivered to the remaining consumers
+    pub fn disconnect_consumer(self: &Self, consumer_id: ConsumerId) {
+        self.consumers.disconnect(consumer_id);
+        log_event(
+            &self.persistence,
+            self.ephemeral,
+            &LoggedEvent::DropConsumer(logged_events::DropConsumerEvent {
+                topic_id: self.topic_id,
+                subscription_id: self.subscription_id,
+                consumer_id,
+            }),
+        );
+
+        let mut delivered_messages = self.delivered_messages.write().unwrap();
+        let unacked_keys: Vec<String> = delivered_messages
+            .values()
+            .filter(|message| message.consumer_id == Some(consumer_id))
+            .map(|message| message.message_ref_key.clone())
+            .collect();
+        let mut messages: Vec<SubscribedMessage> = unacked_keys
+            .iter()
+            .filter_map(|message_ref_key| delivered_messages.remove(message_ref_key))
+            .collect();
+        messages.sort_by_key(|message| {
+            let message_ref = MessageRef::from_key(&message.message_ref_key);
+            (message_ref.ledger_id, message_ref.message_id)
+        });
+
+        // Requeueing at the front reverses the order, so start with the newest message
+        if let DeliveryOrder::Fifo = self.delivery_order {
+            messages.reverse();
+        }
+        let mut queue = self.queued_messages.write().unwrap();
+        for mut message in messages {
+            message.consumer_id = None;
+            requeue(&mut queue, message, self.delivery_order);
+        }
+    }
 
     pub fn ack(self: &Self, _consumer_id: ConsumerId, message_ref_key: &str) -> bool {
         let mut delivered_messages = self.delivered_messages.write().unwrap();
         delivered_messages.remove(message_ref_key).is_some()
     }
 
+    pub fn is_delivered(self: &Self, message_ref_key: &str) -> bool {
+        let delivered_messages = self.delivered_messages.read().unwrap();
+        delivered_messages.contains_key(message_ref_key)
+    }
+
+    pub fn ack_batch(self: &Self, acks: &[(ConsumerId, String)]) -> Vec<String> {
+        let mut delivered_messages = self.delivered_messages.write().unwrap();
+        acks.iter()
+            .filter(|(_, message_ref_key)| delivered_messages.remove(message_ref_key).is_some())
+            .map(|(_, message_ref_key)| message_ref_key.clone())
+            .collect()
+    }
+
+    /// Removes a message from the subscription whether it was delivered or is still queued
+    pub fn force_ack(self: &Self, message_ref_key: &str) -> bool {
+        let mut delivered_messages = self.delivered_messages.write().unwrap();
+        if delivered_messages.remove(message_ref_key).is_some() {
+            return true;
+        }
+        let mut queue = self.queued_messages.write().unwrap();
+        queue.remove(message_ref_key).is_some()
+    }
+
     pub fn nack(self: &Self, _consumer_id: ConsumerId, message_ref_key: &str) -> bool {
         let mut delivered_messages = self.delivered_messages.write().unwrap();
-        if let Some(message) = delivered_messages.remove(message_ref_key) {
+        if let Some(mut message) = delivered_messages.remove(message_ref_key) {
+            message.nack_until = nack_until(self.nack_redelivery_delay_millis);
             let mut queue = self.queued_messages.write().unwrap();
-            queue.push_front(message);
+            requeue(&mut queue, message, self.delivery_order);
             true
         } else {
             false
         }
     }
 
+    /// Returns messages that were delivered more than the ack timeout ago to the queue as if
+    /// the consumer had nacked them. Returns the number of messages that timed out
+    pub fn nack_timed_out(self: &Self) -> usize {
+        if self.ack_timeout_millis == 0 {
+            return 0;
+        }
+        let oldest_delivered = now_epoc_millis().saturating_sub(self.ack_timeout_millis);
+
+        let mut delivered_messages = self.delivered_messages.write().unwrap();
+        let timed_out: Vec<String> = delivered_messages
+            .values()
+            .filter(|message| {
+                message
+                    .delivered_timestamp
+                    .is_some_and(|delivered| delivered < oldest_delivered)
+            })
+            .map(|message| message.message_ref_key.clone())
+            .collect();
+        let mut messages: Vec<SubscribedMessage> = timed_out
+            .iter()
+            .filter_map(|message_ref_key| delivered_messages.remove(message_ref_key))
+            .collect();
+        if messages.is_empty() {
+            return 0;
+        }
+        messages.sort_by_key(|message| {
+            let message_ref = MessageRef::from_key(&message.message_ref_key);
+            (message_ref.ledger_id, message_ref.message_id)
+        });
+
+        // Requeueing at the front reverses the order, so start with the newest message
+        if let DeliveryOrder::Fifo = self.delivery_order {
+            messages.reverse();
+        }
+        let count = messages.len();
+        let nack_until = nack_until(self.nack_redelivery_delay_millis);
+        let mut queue = self.queued_messages.write().unwrap();
+        for mut message in messages {
+            message.nack_until = nack_until;
+            requeue(&mut queue, message, self.delivery_order);
+        }
+        count
+    }
+
+    /// Removes queued messages that were published before `oldest_published`. Messages that
+    /// were delivered stay in the subscription until the consumer acks or nacks them
+    pub fn expire(self: &Self, oldest_published: Timestamp) -> Vec<SubscribedMessage> {
+        let mut queue = self.queued_messages.write().unwrap();
+        queue.remove_published_before(oldest_published)
+    }
+
+    /// Replaces all of the messages in the subscription with a backlog that was replayed from
+    /// the event log. The backlog is queued in the order given. Returns the messages that the
+    /// subscription held before
+    pub fn replay(self: &Self, backlog: Vec<SubscribedMessage>) -> Vec<SubscribedMessage> {
+        let mut delivered_messages = self.delivered_messages.write().unwrap();
+        let mut queue = self.queued_messages.write().unwrap();
+        let mut held: Vec<SubscribedMessage> = queue.iter().cloned().collect();
+        held.extend(delivered_messages.drain().map(|(_, message)| message));
+        *queue = MessageQueue::new();
+        for message in backlog {
+            queue.push_back(message);
+        }
+        held
+    }
+
     pub fn refresh(self: &mut Self, _data_layer: &Arc<DataLayer>) {}
 }
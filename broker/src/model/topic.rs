@@ -1,13 +1,16 @@
 use super::{
-    partition::{Partition, PartitionList, PartitionStats},
-    subscription::{key_shared, shared, Subscription, SubscriptionList, SubscriptionStats},
+    partition::{Partition, PartitionList, PartitionRef, PartitionStats},
+    subscription::{
+        key_shared, shared, Subscription, SubscriptionList, SubscriptionRef, SubscriptionStats,
+    },
     Entity, EntityList, EntityRef,
 };
 use crate::{
-    data::DataLayer,
+    data::{DataAddResult, DataLayer, DataUpdateError, DataUpdateResult},
     formatting::plain_text_builder::{PlainTextBuilder, ToPlainText},
+    persistence::persisted_entities::SubscriptionType,
 };
-use pulsar_rust_net::data_types::{PartitionId, SubscriptionId, TopicId};
+use pulsar_rust_net::data_types::{NodeId, PartitionId, SubscriptionId, TopicId};
 use serde::Serialize;
 use std::sync::Arc;
 
@@ -95,6 +98,10 @@ pub struct Topic {
     name: String,
     partitions: PartitionList,
     subscriptions: SubscriptionList,
+    ephemeral: bool,
+    idempotency_window_millis: u64,
+    message_ttl_millis: u64,
+    sequence_dedup: bool,
 }
 
 impl Entity<TopicId> for Topic {
@@ -123,6 +130,29 @@ impl Topic {
         &self.subscriptions
     }
 
+    /// Events are not logged for ephemeral topics
+    pub fn is_ephemeral(self: &Self) -> bool {
+        self.ephemeral
+    }
+
+    /// How long the idempotency keys of published messages are remembered for, or zero if
+    /// duplicate publishes are not detected
+    pub fn idempotency_window_millis(self: &Self) -> u64 {
+        self.idempotency_window_millis
+    }
+
+    /// How long messages can wait on a subscription queue before they expire, or zero if
+    /// messages never expire
+    pub fn message_ttl_millis(self: &Self) -> u64 {
+        self.message_ttl_millis
+    }
+
+    /// Messages that a producer publishes more than once with the same sequence number are
+    /// only published the first time
+    pub fn is_sequence_dedup(self: &Self) -> bool {
+        self.sequence_dedup
+    }
+
     pub fn new(data_layer: &Arc<DataLayer>, topic_id: TopicId) -> Self {
         let topic = data_layer.get_topic(topic_id).unwrap();
 
@@ -135,22 +165,7 @@ impl Topic {
 
         let subscriptions =
             EntityList::from_iter(topic.subscription_ids.iter().map(|&subscription_id| {
-                let subscription = data_layer
-                    .get_subscription(topic_id, subscription_id)
-                    .unwrap();
-                if subscription.has_key_affinity {
-                    Subscription::KeyShared(key_shared::Subscription::new(
-                        data_layer,
-                        topic_id,
-                        subscription_id,
-                    ))
-                } else {
-                    Subscription::Shared(shared::Subscription::new(
-                        data_layer,
-                        topic_id,
-                        subscription_id,
-                    ))
-                }
+                Self::restore_subscription(data_layer, &partitions, topic_id, subscription_id)
             }));
 
         let name = topic.name.clone();
@@ -160,9 +175,139 @@ impl Topic {
             name,
             partitions,
             subscriptions,
+            ephemeral: topic.ephemeral,
+            idempotency_window_millis: topic.idempotency_window_millis,
+            message_ttl_millis: topic.message_ttl_millis,
+            sequence_dedup: topic.sequence_dedup,
         }
     }
 
+    /// Loads a subscription that existed before the broker started, and replays the event log
+    /// to restore the messages that it had not finished with
+    fn restore_subscription(
+        data_layer: &Arc<DataLayer>,
+        partitions: &PartitionList,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+    ) -> Subscription {
+        let subscription = Self::load_subscription(data_layer, topic_id, subscription_id);
+        let created = data_layer
+            .get_subscription(topic_id, subscription_id)
+            .unwrap()
+            .created;
+        subscription.replay(data_layer.persistence(), partitions, created);
+        subscription
+    }
+
+    fn load_subscription(
+        data_layer: &Arc<DataLayer>,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+    ) -> Subscription {
+        let subscription = data_layer
+            .get_subscription(topic_id, subscription_id)
+            .unwrap();
+        match subscription.subscription_type {
+            SubscriptionType::KeyShared => Subscription::KeyShared(key_shared::Subscription::new(
+                data_layer,
+                topic_id,
+                subscription_id,
+            )),
+            SubscriptionType::Shared | SubscriptionType::Exclusive | SubscriptionType::Failover => {
+                Subscription::Shared(shared::Subscription::new(
+                    data_layer,
+                    topic_id,
+                    subscription_id,
+                ))
+            }
+        }
+    }
+
+    /// Adds a partition that is owned by the specified node, with a ledger that messages can
+    /// be published to straight away
+    pub fn add_partition(
+        self: &Self,
+        data_layer: &Arc<DataLayer>,
+        node_id: NodeId,
+    ) -> DataAddResult<PartitionRef> {
+        let partition = data_layer.add_partition(self.topic_id, node_id)?;
+        data_layer.add_ledger(self.topic_id, partition.partition_id, node_id)?;
+        let partition_ref = EntityRef::new(Partition::new(
+            data_layer,
+            self.topic_id,
+            partition.partition_id,
+        ));
+        self.partitions.insert_ref(partition_ref.clone());
+        Ok(partition_ref)
+    }
+
+    /// Adds a subscription that receives messages published after it was added
+    pub fn add_subscription(
+        self: &Self,
+        data_layer: &Arc<DataLayer>,
+        name: &str,
+        has_key_affinity: bool,
+    ) -> DataAddResult<SubscriptionRef> {
+        let subscription = data_layer.add_subscription(self.topic_id, name, has_key_affinity)?;
+        let subscription_ref = EntityRef::new(Self::load_subscription(
+            data_layer,
+            self.topic_id,
+            subscription.subscription_id,
+        ));
+        self.subscriptions.insert_ref(subscription_ref.clone());
+        Ok(subscription_ref)
+    }
+
+    /// Adds a subscription with the same type and settings as an existing subscription. It
+    /// receives messages published after it was added
+    pub fn copy_subscription(
+        self: &Self,
+        data_layer: &Arc<DataLayer>,
+        subscription_id: SubscriptionId,
+        name: &str,
+    ) -> DataAddResult<SubscriptionRef> {
+        let subscription = data_layer.copy_subscription(self.topic_id, subscription_id, name)?;
+        let subscription_ref = EntityRef::new(Self::load_subscription(
+            data_layer,
+            self.topic_id,
+            subscription.subscription_id,
+        ));
+        self.subscriptions.insert_ref(subscription_ref.clone());
+        Ok(subscription_ref)
+    }
+
+    /// Deletes a subscription from the database and stops delivering messages to it.
+    /// Consumers that still hold a reference to the subscription can finish what they are
+    /// doing, but will not find it again
+    pub fn delete_subscription(
+        self: &Self,
+        data_layer: &Arc<DataLayer>,
+        subscription_id: SubscriptionId,
+    ) -> DataUpdateResult<()> {
+        data_layer.delete_subscription(self.topic_id, subscription_id)?;
+        self.subscriptions.remove(&subscription_id);
+        Ok(())
+    }
+
+    /// Moves a partition to another node. Publishers that send messages for this partition
+    /// to a node that no longer owns it are redirected to the new owner
+    pub fn reassign_partition(
+        self: &Self,
+        data_layer: &Arc<DataLayer>,
+        partition_id: PartitionId,
+        node_id: NodeId,
+    ) -> DataUpdateResult<PartitionRef> {
+        let partition = self
+            .partitions
+            .get(&partition_id)
+            .ok_or(DataUpdateError::NotFound)?;
+        let ledger = data_layer.reassign_partition(self.topic_id, partition_id, node_id)?;
+        let partition_ref =
+            EntityRef::new(partition.reassigned(data_layer, node_id, ledger.ledger_id));
+        self.partitions.insert_ref(partition_ref.clone());
+        Ok(partition_ref)
+    }
+
     pub fn active_subscription_ids(self: &Self) -> Vec<SubscriptionId> {
         self.subscriptions.keys()
     }
@@ -1,3 +1,5 @@
+use internals::Internals;
+use pulsar_rust_net::data_types::{PartitionId, SubscriptionId, TopicId};
 use statsd::Client;
 use std::{
     collections::HashMap,
@@ -9,9 +11,20 @@ use std::{
 };
 use tokio::time;
 
+/// Queue depths and thread counts for the binary API
+pub mod internals;
+
+/// Renders counters in the Prometheus text exposition format
+mod prometheus;
+
 pub struct Metrics {
     client: Mutex<Client>,
     counts: Mutex<HashMap<String, f64>>,
+
+    /// Counts since the broker started. Unlike `counts` these are never cleared, because
+    /// Prometheus expects counters to only ever go up
+    totals: Mutex<HashMap<String, f64>>,
+    internals: Arc<Internals>,
 }
 
 impl Metrics {
@@ -25,10 +38,21 @@ impl Metrics {
     pub const METRIC_HTTP_SUB_TOPICS_COUNT: &str = "http.request.sub.topics.count";
     pub const METRIC_HTTP_SUB_ACK_COUNT: &str = "http.request.sub.ack.count";
     pub const METRIC_HTTP_SUB_NACK_COUNT: &str = "http.request.sub.nack.count";
+    pub const METRIC_HTTP_SUB_QUARANTINE_COUNT: &str = "http.request.sub.quarantine.count";
+    pub const METRIC_HTTP_SUB_QUARANTINED_COUNT: &str = "http.request.sub.quarantined.count";
     pub const METRIC_HTTP_SUB_PING_COUNT: &str = "http.request.sub.ping.count";
+    pub const METRIC_HTTP_SUB_STATS_COUNT: &str = "http.request.sub.stats.count";
 
     pub const METRIC_HTTP_ADMIN_COUNT: &str = "http.request.admin.count";
 
+    pub const METRIC_BIN_SERIALIZE_ERROR_COUNT: &str = "bin.serialize.error.count";
+    pub const METRIC_BIN_DESERIALIZE_ERROR_COUNT: &str = "bin.deserialize.error.count";
+    pub const METRIC_BIN_REQUEST_TIMEOUT_COUNT: &str = "bin.request.timeout.count";
+    pub const METRIC_BIN_ABANDON_LIMIT_COUNT: &str = "bin.abandon.limit.count";
+    pub const METRIC_BIN_DELIVERY_COUNT: &str = "bin.delivery.count";
+
+    pub const METRIC_LEDGER_CREATE_COUNT: &str = "ledger.create.count";
+
     pub fn new() -> Self {
         let client = statsd::Client::new("127.0.0.1:8125", "pulsar").unwrap();
         let counts = HashMap::with_capacity(200);
@@ -36,25 +60,92 @@ impl Metrics {
         Self {
             client: Mutex::new(client),
             counts: Mutex::new(counts),
+            totals: Mutex::new(HashMap::with_capacity(200)),
+            internals: Arc::new(Internals::new()),
         }
     }
 
+    pub fn internals(self: &Self) -> &Arc<Internals> {
+        &self.internals
+    }
+
     pub fn incr(self: &Self, metric: &str) {
-        let metric = String::from(metric);
-        let mut counts = self.counts.lock().unwrap();
-        *counts.entry(metric).or_insert(0.0) += 1.0;
+        self.add(metric, 1.0);
+    }
+
+    /// Increments the aggregate metric, and the same metric namespaced by topic
+    pub fn incr_topic(self: &Self, metric: &str, topic_id: TopicId) {
+        self.incr(metric);
+        self.incr(&Self::topic_metric(metric, topic_id));
+    }
+
+    /// Increments the aggregate metric, and the same metric namespaced by topic and
+    /// by subscription within the topic
+    pub fn incr_subscription(
+        self: &Self,
+        metric: &str,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+    ) {
+        self.incr_topic(metric, topic_id);
+        self.incr(&Self::subscription_metric(
+            metric,
+            topic_id,
+            subscription_id,
+        ));
+    }
+
+    /// Increments the aggregate metric, and the same metric namespaced by topic and
+    /// by partition within the topic
+    pub fn incr_partition(self: &Self, metric: &str, topic_id: TopicId, partition_id: PartitionId) {
+        self.incr_topic(metric, topic_id);
+        self.incr(&Self::partition_metric(metric, topic_id, partition_id));
+    }
+
+    pub fn topic_metric(metric: &str, topic_id: TopicId) -> String {
+        format!("{metric}.topic.{topic_id}")
+    }
+
+    pub fn subscription_metric(
+        metric: &str,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+    ) -> String {
+        format!("{metric}.topic.{topic_id}.subscription.{subscription_id}")
+    }
+
+    pub fn partition_metric(metric: &str, topic_id: TopicId, partition_id: PartitionId) -> String {
+        format!("{metric}.topic.{topic_id}.partition.{partition_id}")
     }
 
     pub fn decr(self: &Self, metric: &str) {
-        let metric = String::from(metric);
-        let mut counts = self.counts.lock().unwrap();
-        *counts.entry(metric).or_insert(0.0) += 1.0;
+        self.add(metric, 1.0);
     }
 
     pub fn count(self: &Self, metric: &str, count: f64) {
+        self.add(metric, count);
+    }
+
+    fn add(self: &Self, metric: &str, count: f64) {
         let metric = String::from(metric);
-        let mut counts = self.counts.lock().unwrap();
-        *counts.entry(metric).or_insert(0.0) += count;
+        *self
+            .totals
+            .lock()
+            .unwrap()
+            .entry(metric.clone())
+            .or_insert(0.0) += count;
+        *self.counts.lock().unwrap().entry(metric).or_insert(0.0) += count;
+    }
+
+    /// Renders the counts since the broker started in the Prometheus text exposition
+    /// format, so that Prometheus can scrape them alongside the StatsD push
+    pub fn prometheus_text(self: &Self) -> String {
+        prometheus::render(&self.totals.lock().unwrap())
+    }
+
+    /// The count accumulated for a metric since counts were last sent to StatsD
+    pub fn unsent_count(self: &Self, metric: &str) -> f64 {
+        *self.counts.lock().unwrap().get(metric).unwrap_or(&0.0)
     }
 
     // pub fn guage(self: &Self, metric: &str, value: f64) {
@@ -85,3 +176,48 @@ impl Metrics {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Metrics;
+
+    fn count(metrics: &Metrics, metric: &str) -> f64 {
+        *metrics.counts.lock().unwrap().get(metric).unwrap_or(&0.0)
+    }
+
+    #[test]
+    fn should_count_topics_independently() {
+        let metrics = Metrics::new();
+        let metric = Metrics::METRIC_HTTP_SUB_CONSUME_COUNT;
+
+        metrics.incr_subscription(metric, 1, 1);
+        metrics.incr_subscription(metric, 1, 2);
+        metrics.incr_subscription(metric, 2, 1);
+        metrics.incr_topic(Metrics::METRIC_HTTP_PUB_MESSAGE_COUNT, 2);
+
+        assert_eq!(count(&metrics, metric), 3.0);
+        assert_eq!(count(&metrics, &Metrics::topic_metric(metric, 1)), 2.0);
+        assert_eq!(count(&metrics, &Metrics::topic_metric(metric, 2)), 1.0);
+        assert_eq!(
+            count(&metrics, &Metrics::subscription_metric(metric, 1, 1)),
+            1.0
+        );
+        assert_eq!(
+            count(&metrics, &Metrics::subscription_metric(metric, 1, 2)),
+            1.0
+        );
+        assert_eq!(
+            count(&metrics, &Metrics::subscription_metric(metric, 2, 1)),
+            1.0
+        );
+        assert_eq!(
+            count(&metrics, &Metrics::subscription_metric(metric, 2, 2)),
+            0.0
+        );
+
+        let pub_metric = Metrics::METRIC_HTTP_PUB_MESSAGE_COUNT;
+        assert_eq!(count(&metrics, pub_metric), 1.0);
+        assert_eq!(count(&metrics, &Metrics::topic_metric(pub_metric, 1)), 0.0);
+        assert_eq!(count(&metrics, &Metrics::topic_metric(pub_metric, 2)), 1.0);
+    }
+}
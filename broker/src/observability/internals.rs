@@ -0,0 +1,209 @@
+/*
+Tracks the internal pressure on the binary API, so that throughput problems can be diagnosed
+from one place. The binary API registers its buffer pools, the depth of the channels that feed
+each processing thread, the send and receive queue depths of each connection, and the threads
+that it is running. A snapshot of all of these is served as JSON by the http API.
+*/
+
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+};
+
+use pulsar_rust_net::sockets::buffer_pool::BufferPool;
+
+pub const THREAD_LISTENER: &str = "listener";
+pub const THREAD_ROUTER: &str = "router";
+pub const THREAD_CONNECTION: &str = "connection";
+pub const THREAD_PROCESSING: &str = "processing";
+pub const THREAD_PROCESSING_POOL: &str = "processing_pool";
+pub const THREAD_DELIVERY: &str = "delivery";
+pub const THREAD_WATCHDOG: &str = "watchdog";
+
+/// The number of messages waiting in the queues of one connection
+#[derive(Default)]
+pub struct ConnectionQueues {
+    /// Responses that were routed to the connection and not written to the Tcp stream yet
+    send: AtomicUsize,
+
+    /// Requests that were received from the Tcp stream and not responded to yet
+    receive: AtomicUsize,
+}
+
+impl ConnectionQueues {
+    pub fn sent(self: &Self) {
+        increment(&self.send);
+    }
+
+    pub fn written(self: &Self) {
+        decrement(&self.send);
+    }
+
+    pub fn received(self: &Self) {
+        increment(&self.receive);
+    }
+
+    pub fn responded(self: &Self) {
+        decrement(&self.receive);
+    }
+}
+
+#[derive(Serialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct ConnectionSnapshot {
+    pub connection_id: u32,
+    pub send_queue_depth: usize,
+    pub receive_queue_depth: usize,
+}
+
+#[derive(Serialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct InternalsSnapshot {
+    pub buffers_in_use: usize,
+    pub buffers_pooled: usize,
+    pub buffer_pool_hits: usize,
+    pub buffer_pool_misses: usize,
+    pub buffers_dropped: usize,
+    pub processing_queue_depths: Vec<usize>,
+    pub connections: Vec<ConnectionSnapshot>,
+    pub threads: BTreeMap<String, usize>,
+}
+
+/// Decrements the count of running threads of one kind when the thread exits
+pub struct RunningThread {
+    internals: Arc<Internals>,
+    kind: &'static str,
+}
+
+impl Drop for RunningThread {
+    fn drop(&mut self) {
+        let mut threads = self.internals.threads.lock().unwrap();
+        if let Some(count) = threads.get_mut(self.kind) {
+            *count = count.saturating_sub(1);
+        }
+    }
+}
+
+pub struct Internals {
+    buffer_pools: RwLock<Vec<Arc<BufferPool>>>,
+    processing_queues: RwLock<Vec<Arc<AtomicUsize>>>,
+    connections: Mutex<Vec<(u32, Arc<ConnectionQueues>)>>,
+    threads: Mutex<BTreeMap<&'static str, usize>>,
+}
+
+impl Internals {
+    pub fn new() -> Self {
+        Self {
+            buffer_pools: RwLock::new(Vec::new()),
+            processing_queues: RwLock::new(Vec::new()),
+            connections: Mutex::new(Vec::new()),
+            threads: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn register_buffer_pool(self: &Self, buffer_pool: &Arc<BufferPool>) {
+        self.buffer_pools
+            .write()
+            .unwrap()
+            .push(Arc::clone(buffer_pool));
+    }
+
+    /// Returns a counter that the caller should increment when a message is sent to the
+    /// processing thread, and decrement when the processing thread receives it
+    pub fn register_processing_queue(self: &Self) -> Arc<AtomicUsize> {
+        let depth = Arc::new(AtomicUsize::new(0));
+        self.processing_queues
+            .write()
+            .unwrap()
+            .push(Arc::clone(&depth));
+        depth
+    }
+
+    pub fn register_connection(self: &Self, connection_id: u32, queues: &Arc<ConnectionQueues>) {
+        self.connections
+            .lock()
+            .unwrap()
+            .push((connection_id, Arc::clone(queues)));
+    }
+
+    pub fn deregister_connection(self: &Self, queues: &Arc<ConnectionQueues>) {
+        self.connections
+            .lock()
+            .unwrap()
+            .retain(|(_, registered)| !Arc::ptr_eq(registered, queues));
+    }
+
+    /// Counts a running thread of this kind until the returned value is dropped
+    pub fn thread_started(self: &Arc<Self>, kind: &'static str) -> RunningThread {
+        *self.threads.lock().unwrap().entry(kind).or_insert(0) += 1;
+        RunningThread {
+            internals: Arc::clone(self),
+            kind,
+        }
+    }
+
+    /// True when no requests are waiting to be processed, and no responses are waiting to
+    /// be sent to the client
+    pub fn is_idle(self: &Self) -> bool {
+        self.processing_queues
+            .read()
+            .unwrap()
+            .iter()
+            .all(|depth| depth.load(Ordering::Relaxed) == 0)
+            && self.connections.lock().unwrap().iter().all(|(_, queues)| {
+                queues.send.load(Ordering::Relaxed) == 0
+                    && queues.receive.load(Ordering::Relaxed) == 0
+            })
+    }
+
+    pub fn snapshot(self: &Self) -> InternalsSnapshot {
+        let buffer_pools = self.buffer_pools.read().unwrap();
+        InternalsSnapshot {
+            buffers_in_use: buffer_pools.iter().map(|pool| pool.in_use()).sum(),
+            buffers_pooled: buffer_pools.iter().map(|pool| pool.pooled()).sum(),
+            buffer_pool_hits: buffer_pools.iter().map(|pool| pool.hits()).sum(),
+            buffer_pool_misses: buffer_pools.iter().map(|pool| pool.misses()).sum(),
+            buffers_dropped: buffer_pools.iter().map(|pool| pool.dropped()).sum(),
+            processing_queue_depths: self
+                .processing_queues
+                .read()
+                .unwrap()
+                .iter()
+                .map(|depth| depth.load(Ordering::Relaxed))
+                .collect(),
+            connections: self
+                .connections
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(connection_id, queues)| ConnectionSnapshot {
+                    connection_id: *connection_id,
+                    send_queue_depth: queues.send.load(Ordering::Relaxed),
+                    receive_queue_depth: queues.receive.load(Ordering::Relaxed),
+                })
+                .collect(),
+            threads: self
+                .threads
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(kind, count)| (kind.to_string(), *count))
+                .collect(),
+        }
+    }
+}
+
+pub fn increment(depth: &AtomicUsize) {
+    depth.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Queue depths never go below zero, even if a message is counted out without being counted in
+pub fn decrement(depth: &AtomicUsize) {
+    let _ = depth.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |depth| {
+        depth.checked_sub(1)
+    });
+}
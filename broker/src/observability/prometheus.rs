@@ -0,0 +1,91 @@
+/*
+Renders counters in the Prometheus text exposition format. Metrics that are namespaced by
+topic, subscription or partition (see `Metrics::topic_metric` etc.) are rendered as the
+aggregate metric with labels, so that Prometheus can filter and sum them. The aggregate
+series without labels is the total over all topics.
+*/
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+};
+
+/// The same prefix that is used for StatsD
+const PREFIX: &str = "pulsar";
+
+pub(super) fn render(totals: &HashMap<String, f64>) -> String {
+    let mut families: BTreeMap<String, Vec<(String, f64)>> = BTreeMap::new();
+    for (metric, count) in totals {
+        let (name, labels) = split_labels(metric);
+        families
+            .entry(metric_name(name))
+            .or_default()
+            .push((labels, *count));
+    }
+
+    let mut text = String::new();
+    for (name, mut series) in families {
+        // The aggregate comes first, then the topic, then the more specific series
+        series.sort_by(|a, b| (a.0.len(), &a.0).cmp(&(b.0.len(), &b.0)));
+        writeln!(text, "# TYPE {name} counter").unwrap();
+        for (labels, count) in series {
+            writeln!(text, "{name}{labels} {count}").unwrap();
+        }
+    }
+    text
+}
+
+/// Splits a namespaced metric into the aggregate metric and its Prometheus labels
+fn split_labels(metric: &str) -> (&str, String) {
+    let Some((name, scope)) = metric.split_once(".topic.") else {
+        return (metric, String::new());
+    };
+    let mut parts = scope.split('.');
+    let mut labels = Vec::new();
+    if let Some(topic_id) = parts.next() {
+        labels.push(format!("topic=\"{topic_id}\""));
+    }
+    while let (Some(label), Some(value)) = (parts.next(), parts.next()) {
+        labels.push(format!("{label}=\"{value}\""));
+    }
+    (name, format!("{{{}}}", labels.join(",")))
+}
+
+/// Prometheus metric names can only contain letters, digits, underscores and colons
+fn metric_name(metric: &str) -> String {
+    let name: String = metric
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("{PREFIX}_{name}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render;
+    use crate::observability::Metrics;
+    use std::collections::HashMap;
+
+    #[test]
+    fn should_render_scoped_metrics_as_labels() {
+        let metric = Metrics::METRIC_HTTP_SUB_ACK_COUNT;
+        let totals = HashMap::from([
+            (metric.to_owned(), 3.0),
+            (Metrics::topic_metric(metric, 1), 3.0),
+            (Metrics::subscription_metric(metric, 1, 2), 3.0),
+            (Metrics::partition_metric(metric, 1, 4), 1.0),
+            (Metrics::METRIC_HTTP_PUB_PING_COUNT.to_owned(), 1.0),
+        ]);
+
+        assert_eq!(
+            render(&totals),
+            "# TYPE pulsar_http_request_pub_ping_count counter\n\
+             pulsar_http_request_pub_ping_count 1\n\
+             # TYPE pulsar_http_request_sub_ack_count counter\n\
+             pulsar_http_request_sub_ack_count 3\n\
+             pulsar_http_request_sub_ack_count{topic=\"1\"} 3\n\
+             pulsar_http_request_sub_ack_count{topic=\"1\",partition=\"4\"} 1\n\
+             pulsar_http_request_sub_ack_count{topic=\"1\",subscription=\"2\"} 3\n"
+        );
+    }
+}
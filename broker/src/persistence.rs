@@ -21,6 +21,15 @@ use pulsar_rust_net::data_types::{
     LedgerId, MessageId, PartitionId, Timestamp, TopicId, VersionNumber,
 };
 use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tokio::sync::broadcast;
+
+/// The folder where the file-system persistence scheme stores its files, unless the
+/// persistence layer is constructed with a different one
+pub const DEFAULT_DATA_DIR: &str = "data";
+
+/// The number of logged events that can be waiting for a slow tail before it misses some
+pub(crate) const DEFAULT_EVENT_TAIL_CAPACITY: usize = 1024;
 
 pub enum PersistenceScheme {
     InMemory,
@@ -75,22 +84,58 @@ impl Keyed for Key {
 pub struct PersistenceLayer {
     event_logger: EventLogger,
     entity_persister: EntityPersister,
+    event_tail: broadcast::Sender<LogEntry>,
 }
 
 impl PersistenceLayer {
     pub fn new(
         event_persistence: PersistenceScheme,
         entity_persistence: PersistenceScheme,
+    ) -> Self {
+        Self::with_event_log_capacity(event_persistence, entity_persistence, 0, None)
+    }
+
+    /// Constructs a persistence layer where the in-memory event log is limited to `event_log_capacity`
+    /// entries. When the capacity is exceeded, the oldest events are written to the `event_spillover`
+    /// scheme, or discarded if there is no spillover. A capacity of zero means unbounded. The capacity
+    /// has no effect on other persistence schemes.
+    pub fn with_event_log_capacity(
+        event_persistence: PersistenceScheme,
+        entity_persistence: PersistenceScheme,
+        event_log_capacity: usize,
+        event_spillover: Option<PersistenceScheme>,
+    ) -> Self {
+        Self::with_data_dir(
+            event_persistence,
+            entity_persistence,
+            event_log_capacity,
+            event_spillover,
+            Path::new(DEFAULT_DATA_DIR),
+        )
+    }
+
+    /// Constructs a persistence layer where the file-system scheme keeps entities in the `entities`
+    /// folder and the event log in the `events` folder of `data_dir`. State that was persisted
+    /// there by a previous run of the broker is available straight away.
+    pub fn with_data_dir(
+        event_persistence: PersistenceScheme,
+        entity_persistence: PersistenceScheme,
+        event_log_capacity: usize,
+        event_spillover: Option<PersistenceScheme>,
+        data_dir: &Path,
     ) -> Self {
         Self {
             event_logger: match event_persistence {
                 PersistenceScheme::InMemory => {
-                    EventLogger::InMemory(in_memory::event_logger::EventLogger::new())
+                    EventLogger::InMemory(in_memory::event_logger::EventLogger::with_capacity(
+                        event_log_capacity,
+                        event_spillover.map(|scheme| Self::build_event_logger(scheme, data_dir)),
+                    ))
                 }
 
-                PersistenceScheme::FileSystem => {
-                    EventLogger::FileSystem(file_system::event_logger::EventLogger::new())
-                }
+                PersistenceScheme::FileSystem => EventLogger::FileSystem(
+                    file_system::event_logger::EventLogger::new(&data_dir.join("events")),
+                ),
             },
             entity_persister: match entity_persistence {
                 PersistenceScheme::InMemory => {
@@ -98,9 +143,21 @@ impl PersistenceLayer {
                 }
 
                 PersistenceScheme::FileSystem => EntityPersister::FileSystem(
-                    file_system::entity_persister::EntityPersister::new(),
+                    file_system::entity_persister::EntityPersister::new(&data_dir.join("entities")),
                 ),
             },
+            event_tail: broadcast::channel(DEFAULT_EVENT_TAIL_CAPACITY).0,
+        }
+    }
+
+    fn build_event_logger(scheme: PersistenceScheme, data_dir: &Path) -> EventLogger {
+        match scheme {
+            PersistenceScheme::InMemory => {
+                EventLogger::InMemory(in_memory::event_logger::EventLogger::new())
+            }
+            PersistenceScheme::FileSystem => EventLogger::FileSystem(
+                file_system::event_logger::EventLogger::new(&data_dir.join("events")),
+            ),
         }
     }
 
@@ -126,8 +183,12 @@ impl PersistenceLayer {
     }
 
     pub fn log_event(self: &Self, event: &LoggedEvent) -> LogEventResult {
-        self.event_logger
-            .log(LogEntry::new(event, now_epoc_millis()))
+        self.log_entry(LogEntry::new(event, now_epoc_millis()))
+    }
+
+    /// Waits for logged events to be written to durable storage
+    pub fn flush_events(self: &Self) -> LogEventResult {
+        self.event_logger.flush()
     }
 
     pub fn log_with_timestamp(
@@ -135,7 +196,24 @@ impl PersistenceLayer {
         event: &LoggedEvent,
         timestamp: Timestamp,
     ) -> LogEventResult {
-        self.event_logger.log(LogEntry::new(event, timestamp))
+        self.log_entry(LogEntry::new(event, timestamp))
+    }
+
+    /// Returns a receiver for the events that are logged from now on, so that the log can be
+    /// followed as it is written. A receiver that falls more than `DEFAULT_EVENT_TAIL_CAPACITY`
+    /// events behind misses the oldest of them
+    pub fn tail_events(self: &Self) -> broadcast::Receiver<LogEntry> {
+        self.event_tail.subscribe()
+    }
+
+    fn log_entry(self: &Self, log_entry: LogEntry) -> LogEventResult {
+        // Entries are only copied when someone is following the log
+        let tail = (self.event_tail.receiver_count() > 0).then(|| log_entry.clone());
+        self.event_logger.log(log_entry)?;
+        if let Some(log_entry) = tail {
+            let _ = self.event_tail.send(log_entry);
+        }
+        Ok(())
     }
 
     pub fn events_by_key_prefix<'a>(
@@ -50,7 +50,7 @@ impl EntityPersister {
     pub fn delete_all(self: &Self) {
         match self {
             EntityPersister::InMemory(p) => p.delete_all(),
-            EntityPersister::FileSystem(_) => todo!(),
+            EntityPersister::FileSystem(p) => p.delete_all(),
         }
     }
 
@@ -60,7 +60,7 @@ impl EntityPersister {
     {
         match self {
             EntityPersister::InMemory(p) => p.save(entity),
-            EntityPersister::FileSystem(_) => todo!(),
+            EntityPersister::FileSystem(p) => p.save(entity),
         }
     }
 
@@ -70,14 +70,14 @@ impl EntityPersister {
     {
         match self {
             EntityPersister::InMemory(p) => p.load(key),
-            EntityPersister::FileSystem(_) => todo!(),
+            EntityPersister::FileSystem(p) => p.load(key),
         }
     }
 
     pub fn delete(self: &Self, key: &impl Keyed) -> DeleteResult {
         match self {
             EntityPersister::InMemory(p) => p.delete(key),
-            EntityPersister::FileSystem(_) => todo!(),
+            EntityPersister::FileSystem(p) => p.delete(key),
         }
     }
 }
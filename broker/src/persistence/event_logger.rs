@@ -6,8 +6,13 @@ pub struct EventQueryOptions {
     pub include_serialization: bool,
     pub descending: bool,
     pub exact_match: bool,
+    /// The number of matching entries to skip before returning any, for paging through the log
     pub skip: usize,
     pub take: usize,
+    /// Only entries logged at or after this timestamp are returned
+    pub from_timestamp: Option<Timestamp>,
+    /// Only entries logged before this timestamp are returned
+    pub to_timestamp: Option<Timestamp>,
 }
 
 pub type LogDeleteResult = Result<(), ()>;
@@ -20,6 +25,8 @@ impl EventQueryOptions {
             exact_match: false,
             skip: 0,
             take: 0,
+            from_timestamp: None,
+            to_timestamp: None,
         }
     }
     pub fn range(skip: usize, take: usize) -> Self {
@@ -29,6 +36,8 @@ impl EventQueryOptions {
             exact_match: false,
             skip,
             take,
+            from_timestamp: None,
+            to_timestamp: None,
         }
     }
     pub fn limit(take: usize) -> Self {
@@ -38,6 +47,8 @@ impl EventQueryOptions {
             exact_match: false,
             skip: 0,
             take,
+            from_timestamp: None,
+            to_timestamp: None,
         }
     }
     pub fn replay() -> Self {
@@ -47,8 +58,19 @@ impl EventQueryOptions {
             exact_match: false,
             skip: 0,
             take: 0,
+            from_timestamp: None,
+            to_timestamp: None,
         }
     }
+
+    /// True if the entry was logged within the time range of the query
+    pub fn in_time_range(self: &Self, log_entry: &LogEntry) -> bool {
+        self.from_timestamp
+            .map_or(true, |from| log_entry.timestamp >= from)
+            && self
+                .to_timestamp
+                .map_or(true, |to| log_entry.timestamp < to)
+    }
 }
 
 pub enum EventLogger {
@@ -71,14 +93,22 @@ impl EventLogger {
     pub fn delete_all(self: &Self) {
         match self {
             EventLogger::InMemory(p) => p.delete_all(),
-            EventLogger::FileSystem(_) => todo!(),
+            EventLogger::FileSystem(p) => p.delete_all(),
         }
     }
 
     pub fn log(self: &Self, log_entry: LogEntry) -> LogEventResult {
         match self {
             EventLogger::InMemory(p) => p.log(log_entry),
-            EventLogger::FileSystem(_) => todo!(),
+            EventLogger::FileSystem(p) => p.log(log_entry),
+        }
+    }
+
+    /// Returns once all of the events that were logged have been written to durable storage
+    pub fn flush(self: &Self) -> LogEventResult {
+        match self {
+            EventLogger::InMemory(p) => p.flush(),
+            EventLogger::FileSystem(p) => p.flush(),
         }
     }
 
@@ -90,7 +120,7 @@ impl EventLogger {
     ) -> impl Iterator<Item = LogEntry> + use<'a> {
         match self {
             EventLogger::InMemory(p) => p.query_by_timestamp(start, end, options),
-            EventLogger::FileSystem(_) => todo!(),
+            EventLogger::FileSystem(p) => p.query_by_timestamp(start, end, options),
         }
     }
 
@@ -101,21 +131,21 @@ impl EventLogger {
     ) -> impl Iterator<Item = LogEntry> + use<'a> {
         match self {
             EventLogger::InMemory(p) => p.query_by_key_prefix(key_prefix, options),
-            EventLogger::FileSystem(_) => todo!(),
+            EventLogger::FileSystem(p) => p.query_by_key_prefix(key_prefix, options),
         }
     }
 
     pub fn delete_before(self: &Self, end: Timestamp) -> LogDeleteResult {
         match self {
             EventLogger::InMemory(p) => p.delete_before(end),
-            EventLogger::FileSystem(_) => todo!(),
+            EventLogger::FileSystem(p) => p.delete_before(end),
         }
     }
 
     pub fn delete_by_key_prefix(self: &Self, key_prefix: &str) -> LogDeleteResult {
         match self {
             EventLogger::InMemory(p) => p.delete_by_key_prefix(key_prefix),
-            EventLogger::FileSystem(_) => todo!(),
+            EventLogger::FileSystem(p) => p.delete_by_key_prefix(key_prefix),
         }
     }
 }
@@ -1,7 +1,177 @@
-pub struct EntityPersister {}
+use crate::persistence::{
+    entity_persister::{DeleteError, DeleteResult, LoadError, LoadResult, SaveError, SaveResult},
+    Keyed, Versioned,
+};
+use pulsar_rust_net::data_types::VersionNumber;
+use rmp_serde::{Deserializer, Serializer};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+const VERSION_SIZE: usize = size_of::<VersionNumber>();
+
+/// Stores each entity in its own file, in a folder for each type of entity. The file name is
+/// the entity key, and the file contains the version number followed by the MessagePack
+/// serialization of the entity. Files are written to a temporary file first and then renamed,
+/// so that a crash part way through a save leaves the previous version intact.
+pub struct EntityPersister {
+    path: PathBuf,
+
+    /// Saves and deletes are serialized so that version numbers can be checked and updated
+    /// without another thread saving the same entity in between
+    lock: Mutex<()>,
+}
 
 impl EntityPersister {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    pub fn delete_all(self: &Self) {
+        let _lock = self.lock.lock().unwrap();
+        let _ = fs::remove_dir_all(&self.path);
+    }
+
+    pub fn save<T: Versioned + Keyed + Serialize>(self: &Self, entity: &mut T) -> SaveResult {
+        let _lock = self.lock.lock().unwrap();
+        let file_name = self.file_name(entity);
+
+        let version = match read_entity_file(&file_name) {
+            Ok((saved_version, _)) => {
+                if saved_version != entity.version() {
+                    return SaveResult::Err(SaveError::VersionMissmatch);
+                }
+                saved_version + 1
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => 1,
+            Err(err) => return SaveResult::Err(save_error(&file_name, err)),
+        };
+
+        let previous_version = entity.version();
+        entity.set_version(version);
+
+        let mut buffer = version.to_le_bytes().to_vec();
+        let mut serializer = Serializer::new(&mut buffer);
+        entity.serialize(&mut serializer).unwrap();
+
+        match write_entity_file(&file_name, &buffer) {
+            Ok(()) => SaveResult::Ok(()),
+            Err(err) => {
+                entity.set_version(previous_version);
+                SaveResult::Err(save_error(&file_name, err))
+            }
+        }
+    }
+
+    pub fn load<'a, TEntity>(self: &Self, keyed: &impl Keyed) -> LoadResult<TEntity>
+    where
+        TEntity: Deserialize<'a>,
+    {
+        let file_name = self.file_name(keyed);
+        match read_entity_file(&file_name) {
+            Ok((_, serialization)) => {
+                let mut deserializer = Deserializer::new(&serialization[..]);
+                match Deserialize::deserialize(&mut deserializer) {
+                    Ok(entity) => LoadResult::Ok(entity),
+                    Err(err) => LoadResult::Err(LoadError::Error {
+                        msg: format!("Failed to deserialize {}: {err}", file_name.display()),
+                    }),
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                LoadResult::Err(LoadError::NotFound {
+                    entity_type: keyed.type_name().to_string(),
+                    entity_key: keyed.key().to_string(),
+                })
+            }
+            Err(err) => LoadResult::Err(LoadError::Error {
+                msg: format!("Failed to read {}: {err}", file_name.display()),
+            }),
+        }
+    }
+
+    pub fn delete(self: &Self, keyed: &impl Keyed) -> DeleteResult {
+        let _lock = self.lock.lock().unwrap();
+        let file_name = self.file_name(keyed);
+        match fs::remove_file(&file_name) {
+            Ok(()) => DeleteResult::Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                DeleteResult::Err(DeleteError::NotFound {
+                    entity_type: keyed.type_name().to_owned(),
+                    entity_key: keyed.key(),
+                })
+            }
+            Err(err) => DeleteResult::Err(DeleteError::Error {
+                msg: format!("Failed to delete {}: {err}", file_name.display()),
+            }),
+        }
+    }
+
+    fn file_name(self: &Self, keyed: &impl Keyed) -> PathBuf {
+        self.path
+            .join(keyed.type_name())
+            .join(escape_key(&keyed.key()) + ".entity")
+    }
+}
+
+/// Entity keys contain characters like `:` that are not allowed in file names on every
+/// platform, so anything other than letters, digits, `-` and `_` is written as `%xx`
+fn escape_key(key: &str) -> String {
+    let mut escaped = String::with_capacity(key.len());
+    for byte in key.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_' {
+            escaped.push(byte as char);
+        } else {
+            escaped.push_str(&format!("%{byte:02x}"));
+        }
+    }
+    escaped
+}
+
+fn read_entity_file(file_name: &Path) -> io::Result<(VersionNumber, Vec<u8>)> {
+    let mut contents = fs::read(file_name)?;
+    if contents.len() < VERSION_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "entity file is truncated",
+        ));
+    }
+    let serialization = contents.split_off(VERSION_SIZE);
+    let version = VersionNumber::from_le_bytes(contents.try_into().unwrap());
+    Ok((version, serialization))
+}
+
+fn write_entity_file(file_name: &Path, contents: &[u8]) -> io::Result<()> {
+    if let Some(folder) = file_name.parent() {
+        fs::create_dir_all(folder)?;
+    }
+    let temp_file_name = file_name.with_extension("tmp");
+    fs::write(&temp_file_name, contents)?;
+    fs::rename(&temp_file_name, file_name)
+}
+
+fn save_error(file_name: &Path, err: io::Error) -> SaveError {
+    SaveError::Error {
+        msg: format!("Failed to save {}: {err}", file_name.display()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::escape_key;
+
+    #[test]
+    fn should_escape_keys_for_file_names() {
+        assert_eq!(escape_key("12"), "12");
+        assert_eq!(escape_key("1:16:12"), "1%3a16%3a12");
+        assert_eq!(escape_key("my_cluster-1"), "my_cluster-1");
+        assert_eq!(escape_key("../etc"), "%2e%2e%2fetc");
     }
 }
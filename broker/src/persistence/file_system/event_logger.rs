@@ -1,7 +1,406 @@
-pub struct EventLogger {}
+use crate::persistence::{
+    event_logger::{EventQueryOptions, LogDeleteResult, LogEventError, LogEventResult},
+    log_entries::LogEntry,
+};
+use log::warn;
+use pulsar_rust_net::data_types::Timestamp;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// When the current segment file grows beyond this size, a new segment is started
+pub const DEFAULT_SEGMENT_SIZE: u64 = 16 * 1024 * 1024;
+
+const SEGMENT_EXTENSION: &str = "log";
+
+/// Written in place of the serialization length for entries that have no serialization
+const NO_SERIALIZATION: u32 = u32::MAX;
+
+/// Appends log entries to segment files in a folder. Segments are numbered in the order that
+/// they were created, and a new segment is started when the current one reaches the segment
+/// size. Each entry is written as its timestamp, type name, key and serialization, each
+/// preceded by its length. Queries read the segments back from disk, and stop reading a
+/// segment at the first incomplete entry, which can only be the last entry written before
+/// the broker stopped. Deletes rewrite the affected segments, so they are expensive and
+/// are intended for occasional housekeeping.
+pub struct EventLogger {
+    path: PathBuf,
+    segment_size: u64,
+    writer: Mutex<SegmentWriter>,
+}
+
+struct SegmentWriter {
+    segment_number: u64,
+    size: u64,
+    file: BufWriter<File>,
+}
 
 impl EventLogger {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(path: &Path) -> Self {
+        Self::with_segment_size(path, DEFAULT_SEGMENT_SIZE)
+    }
+
+    /// Constructs an event logger that appends to the last segment in `path`, and starts a new
+    /// segment whenever the current one grows beyond `segment_size` bytes
+    pub fn with_segment_size(path: &Path, segment_size: u64) -> Self {
+        fs::create_dir_all(path).unwrap();
+        let segment_number = segment_numbers(path).last().copied().unwrap_or(1);
+        let writer = SegmentWriter::open(path, segment_number).unwrap();
+        Self {
+            path: path.to_path_buf(),
+            segment_size,
+            writer: Mutex::new(writer),
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    pub fn delete_all(self: &Self) {
+        let mut writer = self.writer.lock().unwrap();
+        for segment_number in segment_numbers(&self.path) {
+            let _ = fs::remove_file(segment_file_name(&self.path, segment_number));
+        }
+        *writer = SegmentWriter::open(&self.path, 1).unwrap();
+    }
+
+    pub fn log(self: &Self, log_entry: LogEntry) -> LogEventResult {
+        let mut writer = self.writer.lock().unwrap();
+        let record = encode_entry(&log_entry);
+        if writer.file.write_all(&record).is_err() {
+            return Err(LogEventError::Error);
+        }
+        writer.size += record.len() as u64;
+
+        if writer.size >= self.segment_size {
+            if writer.file.flush().is_err() {
+                return Err(LogEventError::Error);
+            }
+            match SegmentWriter::open(&self.path, writer.segment_number + 1) {
+                Ok(next) => *writer = next,
+                Err(_) => return Err(LogEventError::Error),
+            }
+        }
+        Result::Ok(())
+    }
+
+    /// Writes buffered entries to the current segment, and waits for the operating system to
+    /// write them to disk
+    pub fn flush(self: &Self) -> LogEventResult {
+        let mut writer = self.writer.lock().unwrap();
+        match writer
+            .file
+            .flush()
+            .and_then(|_| writer.file.get_ref().sync_data())
+        {
+            Ok(()) => Result::Ok(()),
+            Err(_) => Err(LogEventError::Error),
+        }
+    }
+
+    pub fn query_by_timestamp<'a, 'b>(
+        self: &'a Self,
+        start: Timestamp,
+        end: Timestamp,
+        options: &'b EventQueryOptions,
+    ) -> Box<dyn Iterator<Item = LogEntry> + 'b>
+    where
+        'a: 'b,
+    {
+        self.query(options, move |entry| {
+            entry.timestamp >= start && entry.timestamp < end
+        })
+    }
+
+    pub fn query_by_key_prefix<'a, 'b>(
+        self: &'a Self,
+        key_prefix: &'b str,
+        options: &'b EventQueryOptions,
+    ) -> Box<dyn Iterator<Item = LogEntry> + 'b>
+    where
+        'a: 'b,
+    {
+        if options.exact_match {
+            self.query(options, move |entry| entry.key == key_prefix)
+        } else {
+            self.query(options, move |entry| entry.key.starts_with(key_prefix))
+        }
+    }
+
+    pub fn delete_before(self: &Self, end: Timestamp) -> LogDeleteResult {
+        self.rewrite(|log_entry| log_entry.timestamp >= end)
+    }
+
+    pub fn delete_by_key_prefix(self: &Self, key_prefix: &str) -> LogDeleteResult {
+        self.rewrite(|log_entry| !log_entry.key.starts_with(key_prefix))
+    }
+
+    /// Returns the entries that match the filter and the time range of the query. Like the
+    /// in-memory logger, `skip` counts the matching entries from the start or end of the log
+    fn query<'b, F>(
+        self: &Self,
+        options: &'b EventQueryOptions,
+        filter: F,
+    ) -> Box<dyn Iterator<Item = LogEntry> + 'b>
+    where
+        F: Fn(&LogEntry) -> bool + 'b,
+    {
+        // Make sure that everything logged so far is visible to the reader
+        let segment_numbers = {
+            let mut writer = self.writer.lock().unwrap();
+            let _ = writer.file.flush();
+            segment_numbers(&self.path)
+        };
+
+        let include_serialization = options.include_serialization;
+        let entries = segment_numbers
+            .into_iter()
+            .flat_map({
+                let path = self.path.clone();
+                move |segment_number| {
+                    SegmentReader::open(&path, segment_number)
+                        .into_iter()
+                        .flatten()
+                }
+            })
+            .map(move |mut entry| {
+                if !include_serialization {
+                    entry.serialization = None;
+                }
+                entry
+            })
+            .filter(move |entry| options.in_time_range(entry) && filter(entry));
+
+        let take = if options.take > 0 {
+            options.take
+        } else {
+            usize::MAX
+        };
+        if options.descending {
+            let entries: Vec<LogEntry> = entries.collect();
+            Box::new(entries.into_iter().rev().skip(options.skip).take(take))
+        } else {
+            Box::new(entries.skip(options.skip).take(take))
+        }
+    }
+
+    /// Rewrites every segment, keeping only the entries that match the filter. Segments that
+    /// end up empty are deleted, apart from the current one which is still being written
+    fn rewrite<F>(self: &Self, keep: F) -> LogDeleteResult
+    where
+        F: Fn(&LogEntry) -> bool,
+    {
+        let mut writer = self.writer.lock().unwrap();
+        if writer.file.flush().is_err() {
+            return Err(());
+        }
+
+        for segment_number in segment_numbers(&self.path) {
+            let file_name = segment_file_name(&self.path, segment_number);
+            let mut contents = Vec::new();
+            let mut removed = false;
+            if let Some(reader) = SegmentReader::open(&self.path, segment_number) {
+                for entry in reader {
+                    if keep(&entry) {
+                        contents.extend(encode_entry(&entry));
+                    } else {
+                        removed = true;
+                    }
+                }
+            }
+            if !removed {
+                continue;
+            }
+
+            let result = if contents.is_empty() && segment_number != writer.segment_number {
+                fs::remove_file(&file_name)
+            } else {
+                let temp_file_name = file_name.with_extension("tmp");
+                fs::write(&temp_file_name, &contents)
+                    .and_then(|_| fs::rename(&temp_file_name, &file_name))
+            };
+            if let Err(err) = result {
+                warn!(
+                    "Failed to rewrite event log segment {}: {err}",
+                    file_name.display()
+                );
+                return Err(());
+            }
+        }
+
+        // The current segment may have been replaced, so the writer must reopen it
+        match SegmentWriter::open(&self.path, writer.segment_number) {
+            Ok(reopened) => *writer = reopened,
+            Err(_) => return Err(()),
+        }
+        LogDeleteResult::Ok(())
+    }
+}
+
+impl SegmentWriter {
+    fn open(path: &Path, segment_number: u64) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(segment_file_name(path, segment_number))?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            segment_number,
+            size,
+            file: BufWriter::new(file),
+        })
+    }
+}
+
+struct SegmentReader {
+    reader: BufReader<File>,
+}
+
+impl SegmentReader {
+    fn open(path: &Path, segment_number: u64) -> Option<Self> {
+        let file = File::open(segment_file_name(path, segment_number)).ok()?;
+        Some(Self {
+            reader: BufReader::new(file),
+        })
+    }
+
+    fn read_bytes(self: &mut Self, len: usize) -> Option<Vec<u8>> {
+        let mut buffer = vec![0; len];
+        self.reader.read_exact(&mut buffer).ok()?;
+        Some(buffer)
+    }
+
+    fn read_u16(self: &mut Self) -> Option<u16> {
+        let mut buffer = [0; 2];
+        self.reader.read_exact(&mut buffer).ok()?;
+        Some(u16::from_le_bytes(buffer))
+    }
+
+    fn read_u32(self: &mut Self) -> Option<u32> {
+        let mut buffer = [0; 4];
+        self.reader.read_exact(&mut buffer).ok()?;
+        Some(u32::from_le_bytes(buffer))
+    }
+
+    fn read_u64(self: &mut Self) -> Option<u64> {
+        let mut buffer = [0; 8];
+        self.reader.read_exact(&mut buffer).ok()?;
+        Some(u64::from_le_bytes(buffer))
+    }
+}
+
+impl Iterator for SegmentReader {
+    type Item = LogEntry;
+
+    fn next(self: &mut Self) -> Option<Self::Item> {
+        let timestamp = self.read_u64()?;
+        let type_name_len = self.read_u16()? as usize;
+        let type_name = String::from_utf8(self.read_bytes(type_name_len)?).ok()?;
+        let key_len = self.read_u16()? as usize;
+        let key = String::from_utf8(self.read_bytes(key_len)?).ok()?;
+        let serialization = match self.read_u32()? {
+            NO_SERIALIZATION => None,
+            len => Some(self.read_bytes(len as usize)?),
+        };
+        Some(LogEntry {
+            timestamp,
+            type_name,
+            key,
+            serialization,
+        })
+    }
+}
+
+fn encode_entry(log_entry: &LogEntry) -> Vec<u8> {
+    let mut record = Vec::with_capacity(
+        16 + log_entry.type_name.len()
+            + log_entry.key.len()
+            + log_entry.serialization.as_ref().map_or(0, |s| s.len()),
+    );
+    record.extend(log_entry.timestamp.to_le_bytes());
+    record.extend((log_entry.type_name.len() as u16).to_le_bytes());
+    record.extend(log_entry.type_name.as_bytes());
+    record.extend((log_entry.key.len() as u16).to_le_bytes());
+    record.extend(log_entry.key.as_bytes());
+    match &log_entry.serialization {
+        Some(serialization) => {
+            record.extend((serialization.len() as u32).to_le_bytes());
+            record.extend(serialization);
+        }
+        None => record.extend(NO_SERIALIZATION.to_le_bytes()),
+    }
+    record
+}
+
+fn segment_file_name(path: &Path, segment_number: u64) -> PathBuf {
+    path.join(format!("{segment_number:010}.{SEGMENT_EXTENSION}"))
+}
+
+/// Returns the numbers of the segment files in the folder, in the order they were created
+fn segment_numbers(path: &Path) -> Vec<u64> {
+    let mut numbers: Vec<u64> = match fs::read_dir(path) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|file_name| {
+                file_name
+                    .extension()
+                    .is_some_and(|extension| extension == SEGMENT_EXTENSION)
+            })
+            .filter_map(|file_name| file_name.file_stem()?.to_str()?.parse().ok())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    numbers.sort_unstable();
+    numbers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{segment_numbers, EventLogger};
+    use crate::persistence::{event_logger::EventQueryOptions, log_entries::LogEntry};
+    use std::{env, fs};
+
+    fn log_entry(timestamp: u64) -> LogEntry {
+        LogEntry {
+            timestamp,
+            type_name: LogEntry::PUBLISH_TYPE_NAME.to_owned(),
+            key: format!("1:1:{}:{timestamp}", timestamp % 2),
+            serialization: Some(vec![1, 2, 3]),
+        }
+    }
+
+    #[test]
+    fn should_start_new_segments_and_delete_across_them() {
+        let path = env::temp_dir().join(format!("pulsar_rust_segments_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+
+        let logger = EventLogger::with_segment_size(&path, 100);
+        for timestamp in 1..=10 {
+            logger.log(log_entry(timestamp)).unwrap();
+        }
+        assert!(segment_numbers(&path).len() > 1);
+
+        let options = EventQueryOptions::replay();
+        let timestamps: Vec<u64> = logger
+            .query_by_timestamp(0, 100, &options)
+            .map(|entry| entry.timestamp)
+            .collect();
+        assert_eq!(timestamps, (1..=10).collect::<Vec<_>>());
+
+        logger.delete_before(4).unwrap();
+        logger.delete_by_key_prefix("1:1:1:").unwrap();
+        let timestamps: Vec<u64> = logger
+            .query_by_key_prefix("1:1:", &EventQueryOptions::default())
+            .map(|entry| entry.timestamp)
+            .collect();
+        assert_eq!(timestamps, vec![10, 8, 6, 4]);
+
+        logger.log(log_entry(12)).unwrap();
+        assert_eq!(logger.query_by_timestamp(0, 100, &options).count(), 5);
+
+        drop(logger);
+        fs::remove_dir_all(&path).unwrap();
     }
 }
@@ -1,21 +1,44 @@
 use crate::persistence::{
-    event_logger::{EventQueryOptions, LogDeleteResult, LogEventResult},
+    event_logger::{self, EventQueryOptions, LogDeleteResult, LogEventResult},
     log_entries::LogEntry,
 };
 use pulsar_rust_net::data_types::Timestamp;
-use std::sync::RwLock;
+use std::{collections::VecDeque, sync::RwLock};
 
+/// Keeps log entries in memory so that they can be queried quickly. If a capacity is set then
+/// the oldest entries are evicted once the capacity is reached. Evicted entries are written to
+/// the spillover logger if there is one, otherwise they are discarded. Queries only return
+/// entries that are still held in memory. Eviction happens inline when entries are logged, so
+/// this logger has no background threads that need to be stopped when the broker shuts down.
 pub struct EventLogger {
-    entries: RwLock<Vec<LogEntry>>,
+    entries: RwLock<VecDeque<LogEntry>>,
+    capacity: usize,
+    spillover: Option<Box<event_logger::EventLogger>>,
 }
 
 impl EventLogger {
     pub fn new() -> Self {
+        Self::with_capacity(0, None)
+    }
+
+    /// Constructs an event logger that retains at most `capacity` entries in memory. A capacity
+    /// of zero means that the log is unbounded.
+    pub fn with_capacity(capacity: usize, spillover: Option<event_logger::EventLogger>) -> Self {
         Self {
-            entries: RwLock::new(Vec::new()),
+            entries: RwLock::new(VecDeque::new()),
+            capacity,
+            spillover: spillover.map(|logger| Box::new(logger)),
         }
     }
 
+    pub fn capacity(self: &Self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(self: &Self) -> usize {
+        self.entries.read().unwrap().len()
+    }
+
     #[cfg(debug_assertions)]
     pub fn delete_all(self: &Self) {
         self.entries.write().unwrap().clear();
@@ -23,10 +46,37 @@ impl EventLogger {
     }
 
     pub fn log(self: &Self, log_entry: LogEntry) -> LogEventResult {
-        self.entries.write().unwrap().push(log_entry);
+        let mut evicted = Vec::new();
+        {
+            let mut entries = self.entries.write().unwrap();
+            entries.push_back(log_entry);
+            if self.capacity > 0 {
+                while entries.len() > self.capacity {
+                    if let Some(entry) = entries.pop_front() {
+                        evicted.push(entry);
+                    }
+                }
+            }
+        }
+
+        // Spill outside of the lock so that queries are not blocked by a slow logger
+        if let Some(spillover) = &self.spillover {
+            for entry in evicted {
+                spillover.log(entry)?;
+            }
+        }
         Result::Ok(())
     }
 
+    /// Entries are kept in memory so there is nothing to write, but entries that were
+    /// evicted into the spillover logger must be flushed there
+    pub fn flush(self: &Self) -> LogEventResult {
+        match &self.spillover {
+            Some(spillover) => spillover.flush(),
+            None => Result::Ok(()),
+        }
+    }
+
     pub fn query_by_timestamp<'a, 'b>(
         self: &'a Self,
         start: Timestamp,
@@ -107,130 +157,178 @@ impl EventLogger {
     }
 }
 
-pub struct AscendingLogEntryIterator<'a, F: Fn(&LogEntry) -> bool> {
-    entries: &'a RwLock<Vec<LogEntry>>,
-    count: usize,
-    index: usize,
-    taken: usize,
-    filter: F,
-    options: &'a EventQueryOptions,
+/// Iterates over the matching entries in the order they were logged. The matches are copied
+/// when the iterator is constructed, so that entries logged or evicted while the caller is
+/// iterating can not cause entries to be skipped or returned twice
+pub struct AscendingLogEntryIterator {
+    entries: std::vec::IntoIter<LogEntry>,
 }
 
-impl<'a, F: Fn(&LogEntry) -> bool> AscendingLogEntryIterator<'a, F> {
-    pub fn new(
-        entries: &'a RwLock<Vec<LogEntry>>,
-        options: &'a EventQueryOptions,
+impl AscendingLogEntryIterator {
+    pub fn new<F: Fn(&LogEntry) -> bool>(
+        entries: &RwLock<VecDeque<LogEntry>>,
+        options: &EventQueryOptions,
         filter: F,
     ) -> Self {
-        let count = entries.read().unwrap().len();
-        let index = if count > options.skip {
-            options.skip
-        } else {
-            count
-        };
+        let entries = entries.read().unwrap();
         Self {
-            entries,
-            filter: filter,
-            count,
-            index,
-            options,
-            taken: 0,
+            entries: snapshot(entries.iter(), options, filter),
         }
     }
 }
 
-impl<'a, F: Fn(&LogEntry) -> bool> Iterator for AscendingLogEntryIterator<'a, F> {
+impl Iterator for AscendingLogEntryIterator {
     type Item = LogEntry;
 
     fn next(self: &mut Self) -> Option<Self::Item> {
-        loop {
-            if (self.index == self.count)
-                || (self.options.take > 0 && self.taken == self.options.take)
-            {
-                return None;
-            }
-
-            let entries = self.entries.read().unwrap();
-            let log_entry = entries.get(self.index);
-            self.index = self.index + 1;
-            match log_entry {
-                Some(entry) if (self.filter)(&entry) => {
-                    self.taken += 1;
-                    return Some(LogEntry {
-                        key: entry.key.clone(),
-                        timestamp: entry.timestamp,
-                        type_name: entry.type_name.clone(),
-                        serialization: match self.options.include_serialization {
-                            true => entry.serialization.clone(),
-                            false => None,
-                        },
-                    });
-                }
-                Some(_) => continue,
-                None => return None,
-            }
-        }
+        self.entries.next()
     }
 }
 
-pub struct DescendingLogEntryIterator<'a, F: Fn(&LogEntry) -> bool> {
-    entries: &'a RwLock<Vec<LogEntry>>,
-    index: usize,
-    taken: usize,
-    filter: F,
-    options: &'a EventQueryOptions,
+/// Iterates over the matching entries, most recently logged first. The matches are copied
+/// when the iterator is constructed, as for `AscendingLogEntryIterator`
+pub struct DescendingLogEntryIterator {
+    entries: std::vec::IntoIter<LogEntry>,
 }
 
-impl<'a, F: Fn(&LogEntry) -> bool> DescendingLogEntryIterator<'a, F> {
-    pub fn new(
-        entries: &'a RwLock<Vec<LogEntry>>,
-        options: &'a EventQueryOptions,
+impl DescendingLogEntryIterator {
+    pub fn new<F: Fn(&LogEntry) -> bool>(
+        entries: &RwLock<VecDeque<LogEntry>>,
+        options: &EventQueryOptions,
         filter: F,
     ) -> Self {
-        let len = entries.read().unwrap().len();
-        let index = if len > options.skip {
-            len - options.skip
-        } else {
-            0
-        };
+        let entries = entries.read().unwrap();
         Self {
-            entries,
-            filter: filter,
-            index,
-            options,
-            taken: 0,
+            entries: snapshot(entries.iter().rev(), options, filter),
         }
     }
 }
 
-impl<'a, F: Fn(&LogEntry) -> bool> Iterator for DescendingLogEntryIterator<'a, F> {
+impl Iterator for DescendingLogEntryIterator {
     type Item = LogEntry;
 
     fn next(self: &mut Self) -> Option<Self::Item> {
-        loop {
-            if (self.index == 0) || (self.options.take > 0 && self.taken == self.options.take) {
-                return None;
-            }
-            self.index = self.index - 1;
-
-            let entries = self.entries.read().unwrap();
-            let log_entry = entries.get(self.index);
-            match log_entry {
-                Some(entry) if (self.filter)(&entry) => {
-                    self.taken += 1;
-                    return Some(LogEntry {
-                        key: entry.key.clone(),
-                        timestamp: entry.timestamp,
-                        type_name: entry.type_name.clone(),
-                        serialization: match self.options.include_serialization {
-                            true => entry.serialization.clone(),
-                            false => None,
-                        },
-                    });
-                }
-                Some(_) => continue,
-                None => return None,
-            }
+        self.entries.next()
+    }
+}
+
+/// Copies the entries that match the query, applying the skip and take options so that only
+/// the entries that will be returned are copied
+fn snapshot<'a, F: Fn(&LogEntry) -> bool>(
+    entries: impl Iterator<Item = &'a LogEntry>,
+    options: &EventQueryOptions,
+    filter: F,
+) -> std::vec::IntoIter<LogEntry> {
+    let take = match options.take {
+        0 => usize::MAX,
+        take => take,
+    };
+    entries
+        .filter(|entry| options.in_time_range(entry) && filter(entry))
+        .skip(options.skip)
+        .take(take)
+        .map(|entry| LogEntry {
+            key: entry.key.clone(),
+            timestamp: entry.timestamp,
+            type_name: entry.type_name.clone(),
+            serialization: match options.include_serialization {
+                true => entry.serialization.clone(),
+                false => None,
+            },
+        })
+        .collect::<Vec<LogEntry>>()
+        .into_iter()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EventLogger;
+    use crate::persistence::{
+        event_logger::{self, EventQueryOptions},
+        log_entries::LogEntry,
+    };
+
+    fn log_entry(timestamp: u64) -> LogEntry {
+        LogEntry {
+            timestamp,
+            type_name: LogEntry::PUBLISH_TYPE_NAME.to_owned(),
+            key: format!("1:1:1:{timestamp}"),
+            serialization: None,
         }
     }
+
+    #[test]
+    fn should_spill_oldest_entries_when_full() {
+        let logger = EventLogger::with_capacity(
+            3,
+            Some(event_logger::EventLogger::InMemory(EventLogger::new())),
+        );
+
+        for timestamp in 1..=5 {
+            logger.log(log_entry(timestamp)).unwrap();
+        }
+        assert_eq!(logger.len(), 3);
+
+        let options = EventQueryOptions::replay();
+        let recent: Vec<u64> = logger
+            .query_by_timestamp(0, 100, &options)
+            .map(|entry| entry.timestamp)
+            .collect();
+        assert_eq!(recent, vec![3, 4, 5]);
+
+        let spilled: Vec<u64> = logger
+            .spillover
+            .as_ref()
+            .unwrap()
+            .query_by_timestamp(0, 100, &options)
+            .map(|entry| entry.timestamp)
+            .collect();
+        assert_eq!(spilled, vec![1, 2]);
+    }
+
+    #[test]
+    fn should_not_skip_or_repeat_entries_logged_while_iterating() {
+        let logger = EventLogger::with_capacity(4, None);
+        for timestamp in 1..=4 {
+            logger.log(log_entry(timestamp)).unwrap();
+        }
+
+        let options = EventQueryOptions::replay();
+        let mut ascending = logger.query_by_timestamp(0, 100, &options);
+        assert_eq!(ascending.next().unwrap().timestamp, 1);
+
+        let descending_options = EventQueryOptions {
+            descending: true,
+            ..EventQueryOptions::replay()
+        };
+        let mut descending = logger.query_by_timestamp(0, 100, &descending_options);
+        assert_eq!(descending.next().unwrap().timestamp, 4);
+
+        // Each of these evicts the oldest entry, moving the remaining entries down the log
+        for timestamp in 5..=6 {
+            logger.log(log_entry(timestamp)).unwrap();
+        }
+
+        let rest: Vec<u64> = ascending.map(|entry| entry.timestamp).collect();
+        assert_eq!(rest, vec![2, 3, 4]);
+
+        let rest: Vec<u64> = descending.map(|entry| entry.timestamp).collect();
+        assert_eq!(rest, vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn should_drop_oldest_entries_without_spillover() {
+        let logger = EventLogger::with_capacity(2, None);
+
+        for timestamp in 1..=4 {
+            logger.log(log_entry(timestamp)).unwrap();
+        }
+
+        let options = EventQueryOptions::replay();
+        let recent: Vec<u64> = logger
+            .query_by_key_prefix("1:1:1:", &options)
+            .map(|entry| entry.timestamp)
+            .collect();
+        assert_eq!(recent, vec![3, 4]);
+    }
 }
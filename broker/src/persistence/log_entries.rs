@@ -1,6 +1,7 @@
 use super::{
     logged_events::{
-        AckEvent, DropConsumerEvent, KeyAffinityEvent, NackEvent, NewConsumerEvent, PublishEvent,
+        AckEvent, AdminAckEvent, DeadLetterEvent, DropConsumerEvent, ExpiryEvent, KeyAffinityEvent,
+        NackEvent, NewConsumerEvent, PartitionReassignedEvent, PublishEvent, QuarantineEvent,
     },
     Keyed,
 };
@@ -9,6 +10,7 @@ use rmp_serde::{Deserializer, Serializer};
 use serde::{Deserialize, Serialize};
 
 #[cfg_attr(debug_assertions, derive(Debug))]
+#[derive(Clone)]
 pub struct LogEntry {
     pub timestamp: Timestamp,
     pub type_name: String,
@@ -20,19 +22,29 @@ pub struct LogEntry {
 pub enum LoggedEvent {
     Publish(PublishEvent),
     Ack(AckEvent),
+    AdminAck(AdminAckEvent),
     Nack(NackEvent),
     NewConsumer(NewConsumerEvent),
     DropConsumer(DropConsumerEvent),
     KeyAffinity(KeyAffinityEvent),
+    Quarantine(QuarantineEvent),
+    Expiry(ExpiryEvent),
+    DeadLetter(DeadLetterEvent),
+    PartitionReassigned(PartitionReassignedEvent),
 }
 
 impl LogEntry {
     pub const PUBLISH_TYPE_NAME: &'static str = "Publish";
     pub const ACK_TYPE_NAME: &'static str = "Ack";
+    pub const ADMIN_ACK_TYPE_NAME: &'static str = "AdminAck";
     pub const NACK_TYPE_NAME: &'static str = "Nack";
     pub const NEW_CONSUMER_TYPE_NAME: &'static str = "NewConsumer";
     pub const DROP_CONSUMER_TYPE_NAME: &'static str = "DropConsumer";
     pub const KEY_AFFINITY_TYPE_NAME: &'static str = "KeyAffinity";
+    pub const QUARANTINE_TYPE_NAME: &'static str = "Quarantine";
+    pub const EXPIRY_TYPE_NAME: &'static str = "Expiry";
+    pub const DEAD_LETTER_TYPE_NAME: &'static str = "DeadLetter";
+    pub const PARTITION_REASSIGNED_TYPE_NAME: &'static str = "PartitionReassigned";
 
     pub fn new(event: &LoggedEvent, timestamp: Timestamp) -> Self {
         let type_name: String;
@@ -52,6 +64,11 @@ impl LogEntry {
                 key = ack.key();
                 ack.serialize(&mut serializer).unwrap();
             }
+            LoggedEvent::AdminAck(admin_ack) => {
+                type_name = LogEntry::ADMIN_ACK_TYPE_NAME.to_owned();
+                key = admin_ack.key();
+                admin_ack.serialize(&mut serializer).unwrap();
+            }
             LoggedEvent::Nack(nack) => {
                 type_name = LogEntry::NACK_TYPE_NAME.to_owned();
                 key = nack.key();
@@ -68,10 +85,30 @@ impl LogEntry {
                 drop_consumer.serialize(&mut serializer).unwrap();
             }
             LoggedEvent::KeyAffinity(key_affinity) => {
-                type_name = LogEntry::NACK_TYPE_NAME.to_owned();
+                type_name = LogEntry::KEY_AFFINITY_TYPE_NAME.to_owned();
                 key = key_affinity.key();
                 key_affinity.serialize(&mut serializer).unwrap();
             }
+            LoggedEvent::Quarantine(quarantine) => {
+                type_name = LogEntry::QUARANTINE_TYPE_NAME.to_owned();
+                key = quarantine.key();
+                quarantine.serialize(&mut serializer).unwrap();
+            }
+            LoggedEvent::Expiry(expiry) => {
+                type_name = LogEntry::EXPIRY_TYPE_NAME.to_owned();
+                key = expiry.key();
+                expiry.serialize(&mut serializer).unwrap();
+            }
+            LoggedEvent::DeadLetter(dead_letter) => {
+                type_name = LogEntry::DEAD_LETTER_TYPE_NAME.to_owned();
+                key = dead_letter.key();
+                dead_letter.serialize(&mut serializer).unwrap();
+            }
+            LoggedEvent::PartitionReassigned(partition_reassigned) => {
+                type_name = LogEntry::PARTITION_REASSIGNED_TYPE_NAME.to_owned();
+                key = partition_reassigned.key();
+                partition_reassigned.serialize(&mut serializer).unwrap();
+            }
         }
 
         Self {
@@ -93,6 +130,11 @@ impl LogEntry {
                             Deserialize::deserialize(&mut deserializer).unwrap();
                         Some(LoggedEvent::Ack(ack_event))
                     }
+                    LogEntry::ADMIN_ACK_TYPE_NAME => {
+                        let admin_ack_event: AdminAckEvent =
+                            Deserialize::deserialize(&mut deserializer).unwrap();
+                        Some(LoggedEvent::AdminAck(admin_ack_event))
+                    }
                     LogEntry::NACK_TYPE_NAME => {
                         let nack_event: NackEvent =
                             Deserialize::deserialize(&mut deserializer).unwrap();
@@ -118,6 +160,26 @@ impl LogEntry {
                             Deserialize::deserialize(&mut deserializer).unwrap();
                         Some(LoggedEvent::KeyAffinity(key_affinity_event))
                     }
+                    LogEntry::QUARANTINE_TYPE_NAME => {
+                        let quarantine_event: QuarantineEvent =
+                            Deserialize::deserialize(&mut deserializer).unwrap();
+                        Some(LoggedEvent::Quarantine(quarantine_event))
+                    }
+                    LogEntry::EXPIRY_TYPE_NAME => {
+                        let expiry_event: ExpiryEvent =
+                            Deserialize::deserialize(&mut deserializer).unwrap();
+                        Some(LoggedEvent::Expiry(expiry_event))
+                    }
+                    LogEntry::DEAD_LETTER_TYPE_NAME => {
+                        let dead_letter_event: DeadLetterEvent =
+                            Deserialize::deserialize(&mut deserializer).unwrap();
+                        Some(LoggedEvent::DeadLetter(dead_letter_event))
+                    }
+                    LogEntry::PARTITION_REASSIGNED_TYPE_NAME => {
+                        let partition_reassigned_event: PartitionReassignedEvent =
+                            Deserialize::deserialize(&mut deserializer).unwrap();
+                        Some(LoggedEvent::PartitionReassigned(partition_reassigned_event))
+                    }
                     &_ => None, // TODO: Log this as an error
                 }
             }
@@ -2,7 +2,9 @@ use crate::{
     model::messages::{MessageRef, PublishedMessage},
     persistence::Keyed,
 };
-use pulsar_rust_net::data_types::{ConsumerId, SubscriptionId, TopicId};
+use pulsar_rust_net::data_types::{
+    ConsumerId, LedgerId, NodeId, PartitionId, SubscriptionId, TopicId,
+};
 use serde::{Deserialize, Serialize};
 
 use super::log_entries::LogEntry;
@@ -15,6 +17,13 @@ pub struct AckEvent {
     pub consumer_id: ConsumerId,
 }
 
+#[cfg_attr(debug_assertions, derive(Debug))]
+#[derive(Deserialize, Serialize)]
+pub struct AdminAckEvent {
+    pub message_ref: MessageRef,
+    pub subscription_id: SubscriptionId,
+}
+
 #[cfg_attr(debug_assertions, derive(Debug))]
 #[derive(Deserialize, Serialize)]
 pub struct NackEvent {
@@ -23,6 +32,44 @@ pub struct NackEvent {
     pub consumer_id: ConsumerId,
 }
 
+#[cfg_attr(debug_assertions, derive(Debug))]
+#[derive(Deserialize, Serialize)]
+pub struct QuarantineEvent {
+    pub message_ref: MessageRef,
+    pub subscription_id: SubscriptionId,
+    pub consumer_id: ConsumerId,
+    pub reason: String,
+}
+
+#[cfg_attr(debug_assertions, derive(Debug))]
+#[derive(Deserialize, Serialize)]
+pub struct ExpiryEvent {
+    pub message_ref: MessageRef,
+    pub subscription_id: SubscriptionId,
+}
+
+/// A message that was delivered too many times, and was moved to the dead letter topic of
+/// its subscription. `dead_letter_ref` is the copy of the message in the dead letter topic
+#[cfg_attr(debug_assertions, derive(Debug))]
+#[derive(Deserialize, Serialize)]
+pub struct DeadLetterEvent {
+    pub message_ref: MessageRef,
+    pub subscription_id: SubscriptionId,
+    pub consumer_id: ConsumerId,
+    pub dead_letter_ref: MessageRef,
+}
+
+/// A partition was moved to another node, and a new ledger was started on that node
+#[cfg_attr(debug_assertions, derive(Debug))]
+#[derive(Deserialize, Serialize)]
+pub struct PartitionReassignedEvent {
+    pub topic_id: TopicId,
+    pub partition_id: PartitionId,
+    pub ledger_id: LedgerId,
+    pub from_node_id: NodeId,
+    pub to_node_id: NodeId,
+}
+
 #[cfg_attr(debug_assertions, derive(Debug))]
 #[derive(Deserialize, Serialize)]
 pub struct PublishEvent {
@@ -68,6 +115,15 @@ impl AckEvent {
     }
 }
 
+impl AdminAckEvent {
+    pub fn new(message_ref: MessageRef, subscription_id: SubscriptionId) -> Self {
+        AdminAckEvent {
+            message_ref,
+            subscription_id,
+        }
+    }
+}
+
 impl NackEvent {
     pub fn new(
         message_ref: MessageRef,
@@ -82,6 +138,47 @@ impl NackEvent {
     }
 }
 
+impl QuarantineEvent {
+    pub fn new(
+        message_ref: MessageRef,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+        reason: &str,
+    ) -> Self {
+        QuarantineEvent {
+            message_ref,
+            subscription_id,
+            consumer_id,
+            reason: reason.to_owned(),
+        }
+    }
+}
+
+impl ExpiryEvent {
+    pub fn new(message_ref: MessageRef, subscription_id: SubscriptionId) -> Self {
+        ExpiryEvent {
+            message_ref,
+            subscription_id,
+        }
+    }
+}
+
+impl DeadLetterEvent {
+    pub fn new(
+        message_ref: MessageRef,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+        dead_letter_ref: MessageRef,
+    ) -> Self {
+        DeadLetterEvent {
+            message_ref,
+            subscription_id,
+            consumer_id,
+            dead_letter_ref,
+        }
+    }
+}
+
 impl PublishEvent {
     pub fn new(message: &PublishedMessage) -> Self {
         PublishEvent {
@@ -99,6 +196,15 @@ impl Keyed for AckEvent {
     }
 }
 
+impl Keyed for AdminAckEvent {
+    fn type_name(self: &Self) -> &'static str {
+        LogEntry::ADMIN_ACK_TYPE_NAME
+    }
+    fn key(self: &Self) -> String {
+        self.message_ref.to_key()
+    }
+}
+
 impl Keyed for NackEvent {
     fn type_name(self: &Self) -> &'static str {
         LogEntry::NACK_TYPE_NAME
@@ -108,6 +214,46 @@ impl Keyed for NackEvent {
     }
 }
 
+impl Keyed for QuarantineEvent {
+    fn type_name(self: &Self) -> &'static str {
+        LogEntry::QUARANTINE_TYPE_NAME
+    }
+    fn key(self: &Self) -> String {
+        self.message_ref.to_key()
+    }
+}
+
+impl Keyed for ExpiryEvent {
+    fn type_name(self: &Self) -> &'static str {
+        LogEntry::EXPIRY_TYPE_NAME
+    }
+    fn key(self: &Self) -> String {
+        self.message_ref.to_key()
+    }
+}
+
+impl Keyed for DeadLetterEvent {
+    fn type_name(self: &Self) -> &'static str {
+        LogEntry::DEAD_LETTER_TYPE_NAME
+    }
+    fn key(self: &Self) -> String {
+        self.message_ref.to_key()
+    }
+}
+
+impl Keyed for PartitionReassignedEvent {
+    fn type_name(self: &Self) -> &'static str {
+        LogEntry::PARTITION_REASSIGNED_TYPE_NAME
+    }
+    fn key(self: &Self) -> String {
+        self.topic_id.to_string()
+            + ":"
+            + &self.partition_id.to_string()
+            + ":"
+            + &self.ledger_id.to_string()
+    }
+}
+
 impl Keyed for PublishEvent {
     fn type_name(self: &Self) -> &'static str {
         LogEntry::PUBLISH_TYPE_NAME
@@ -1,8 +1,12 @@
 use serde::{Deserialize, Serialize};
 
-use crate::persistence::{Keyed, Versioned};
+use crate::{
+    persistence::{Keyed, Versioned},
+    utils::now_epoc_millis,
+};
 use pulsar_rust_net::data_types::{
-    ConsumerId, LedgerId, NodeId, PartitionId, PortNumber, SubscriptionId, TopicId, VersionNumber,
+    ConsumerId, LedgerId, NodeId, PartitionId, PortNumber, SubscriptionId, Timestamp, TopicId,
+    VersionNumber,
 };
 
 use super::Key;
@@ -126,6 +130,10 @@ pub struct Topic {
     pub subscription_ids: Vec<SubscriptionId>,
     pub next_partition_id: PartitionId,
     pub next_subscription_id: SubscriptionId,
+    pub ephemeral: bool,
+    pub idempotency_window_millis: u64,
+    pub message_ttl_millis: u64,
+    pub sequence_dedup: bool,
 }
 
 #[rustfmt::skip]
@@ -146,6 +154,10 @@ impl Topic {
             subscription_ids: subscriptions,
             next_partition_id,
             next_subscription_id,
+            ephemeral: false,
+            idempotency_window_millis: 0,
+            message_ttl_millis: 0,
+            sequence_dedup: false,
         }
     }
     pub fn key(topic_id: TopicId) -> impl Keyed {
@@ -276,6 +288,78 @@ impl Versioned for Ledger {
     fn set_version(self: &mut Self, version: VersionNumber) { self.version = version }
 }
 
+/// Determines what happens when a message is published to a subscription whose queue
+/// has reached its maximum depth
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Copy)]
+pub enum QueueOverflowPolicy {
+    /// The publisher is told that the backlog is full and the message is not published
+    Block,
+
+    /// The oldest queued message with the lowest priority is removed from this subscription to make room
+    DropOldest,
+
+    /// The new message is not added to this subscription, other subscriptions still receive it
+    Reject,
+}
+
+/// Determines how the messages in a subscription are shared between its consumers
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Copy)]
+pub enum SubscriptionType {
+    /// Each message is delivered to whichever consumer asks for messages next, regardless
+    /// of its key
+    Shared,
+
+    /// Two messages with the same key are never in-flight with different consumers at the
+    /// same time
+    KeyShared,
+
+    /// Only one consumer can be connected at a time, and it receives all of the messages
+    Exclusive,
+
+    /// All of the messages are delivered to the consumer that connected first. Other consumers
+    /// are on standby, and the next one takes over when the active consumer disconnects
+    Failover,
+}
+
+/// Determines the order in which queued messages are delivered to consumers
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Copy)]
+pub enum DeliveryOrder {
+    /// Messages are delivered in the order that they were published
+    Fifo,
+
+    /// The most recently published message is delivered first. This is useful when
+    /// only the latest value matters, but messages are no longer delivered in order
+    Lifo,
+}
+
+/// Determines whether a message is kept by a subscription until a consumer acks it
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Copy)]
+pub enum DeliverySemantics {
+    /// Delivered messages are kept until they are acked, and are redelivered if they are
+    /// nacked, time out, or their consumer disconnects
+    AtLeastOnce,
+
+    /// Messages are acked as they are delivered, so they are never redelivered and consumers
+    /// do not need to ack them. This suits streams such as metrics where losing a message
+    /// matters less than the cost of acking every one
+    AtMostOnce,
+}
+
+/// A rule that changes the copy of a message that is delivered to the consumers of a
+/// subscription. The message in the ledger, and the copies delivered to other
+/// subscriptions, are not changed
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub enum DeliveryTransform {
+    /// Sets an attribute to a fixed value, replacing any value set by the publisher
+    SetAttribute { name: String, value: String },
+
+    /// Sets an attribute to the time that the message was delivered, in epoch milliseconds
+    SetDeliveredTimestamp { name: String },
+
+    /// Removes all attributes whose names start with the prefix
+    RemoveAttributes { prefix: String },
+}
+
 /// A subscription connects an application to a topic. Each message published to the
 /// topic will be delivered at least once to each sunscription associated with that topic
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
@@ -284,8 +368,26 @@ pub struct Subscription {
     pub topic_id: TopicId,
     pub subscription_id: SubscriptionId,
     pub name: String,
-    pub has_key_affinity: bool,
+    pub subscription_type: SubscriptionType,
     pub next_consumer_id: ConsumerId,
+    pub max_queue_depth: usize,
+    pub queue_overflow_policy: QueueOverflowPolicy,
+    pub delivery_order: DeliveryOrder,
+    pub delivery_semantics: DeliverySemantics,
+    pub max_message_age_millis: u64,
+    pub assignment_timeout_millis: u64,
+    pub strict_ordering: bool,
+    pub delivery_transforms: Vec<DeliveryTransform>,
+    pub max_consumers: usize,
+    pub nack_redelivery_delay_millis: u64,
+    pub ack_timeout_millis: u64,
+    pub max_delivery_count: usize,
+    pub dead_letter_topic: Option<String>,
+
+    /// Messages published before this time are not replayed into the subscription on restart.
+    /// Subscriptions saved before this was recorded replay the whole event log
+    #[serde(default)]
+    pub created: Timestamp,
 }
 
 #[rustfmt::skip]
@@ -294,7 +396,7 @@ impl Subscription {
         topic_id: TopicId, 
         subscription_id: SubscriptionId, 
         name: String, 
-        has_key_affinity: bool,
+        subscription_type: SubscriptionType,
         next_consumer_id: ConsumerId,
     ) -> Self {
         Self {
@@ -302,8 +404,22 @@ impl Subscription {
             topic_id,
             subscription_id,
             name,
-            has_key_affinity,
+            subscription_type,
             next_consumer_id,
+            max_queue_depth: 0,
+            queue_overflow_policy: QueueOverflowPolicy::Reject,
+            delivery_order: DeliveryOrder::Fifo,
+            delivery_semantics: DeliverySemantics::AtLeastOnce,
+            max_message_age_millis: 0,
+            assignment_timeout_millis: 0,
+            strict_ordering: false,
+            delivery_transforms: Vec::new(),
+            max_consumers: 0,
+            nack_redelivery_delay_millis: 0,
+            ack_timeout_millis: 0,
+            max_delivery_count: 0,
+            dead_letter_topic: None,
+            created: now_epoc_millis(),
         }
     }
     pub fn key(topic_id: TopicId, subscription_id: SubscriptionId) -> impl Keyed {
@@ -6,24 +6,110 @@ the change to other nodes in the cluster, and coordinates the handoff between no
 appropriate.
 */
 
-use std::sync::Arc;
-
-use crate::model::{
-    cluster::Cluster,
-    ledger::LedgerRef,
-    node::{NodeList, NodeRef},
-    partition::PartitionRef,
-    topic::{TopicList, TopicRef},
+use std::{collections::HashSet, sync::Arc};
+
+use crate::{
+    data::{DataAddError, DataUpdateError},
+    model::{
+        cluster::Cluster,
+        ledger::LedgerRef,
+        messages::{MessageRef, SubscribedMessage},
+        node::{NodeList, NodeRef},
+        partition::PartitionRef,
+        subscription::SubscriptionRef,
+        topic::{TopicList, TopicRef},
+    },
+    persistence::{
+        event_logger::EventQueryOptions, log_entries::LoggedEvent, logged_events::AdminAckEvent,
+        PersistenceLayer,
+    },
 };
-use pulsar_rust_net::data_types::{LedgerId, NodeId, PartitionId, TopicId};
+use pulsar_rust_net::data_types::{LedgerId, NodeId, PartitionId, SubscriptionId, TopicId};
+
+pub enum AdminError {
+    TopicNotFound,
+    SubscriptionNotFound,
+    PartitionNotFound,
+    LedgerNotFound,
+
+    /// Events are not logged for ephemeral topics, so there is nothing to replay
+    TopicIsEphemeral,
+}
+
+pub type ForceAckResult = Result<bool, AdminError>;
+
+/// The differences that were found between the event log and the messages held by a
+/// subscription, and the changes that were made to reconcile them
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct SubscriptionRepair {
+    /// The number of messages that the event log shows as published and not acked
+    pub unacked_count: usize,
+
+    /// Message ref keys of unacked messages that the subscription had lost, and were queued again
+    pub restored: Vec<String>,
+
+    /// Message ref keys of acked messages that the subscription was still holding, and were removed
+    pub removed: Vec<String>,
+}
+
+pub type RepairResult = Result<SubscriptionRepair, AdminError>;
+
+pub enum CreateError {
+    TopicNotFound,
+    NodeNotFound,
+
+    /// There is already a topic with this name, or a subscription with this name in the topic
+    NameInUse,
+
+    /// The change could not be saved to the database
+    PersistenceFailure(String),
+}
+
+pub type CreateResult<T> = Result<T, CreateError>;
+
+impl From<DataAddError> for CreateError {
+    fn from(err: DataAddError) -> Self {
+        match err {
+            DataAddError::Duplicate { .. } => CreateError::NameInUse,
+            DataAddError::PersistenceFailure { msg } => CreateError::PersistenceFailure(msg),
+            DataAddError::NoNodes => CreateError::NodeNotFound,
+        }
+    }
+}
+
+pub enum DeleteError {
+    TopicNotFound,
+    SubscriptionNotFound,
+
+    /// The change could not be saved to the database
+    PersistenceFailure(String),
+}
+
+pub type DeleteResult = Result<(), DeleteError>;
+
+pub enum ReassignError {
+    TopicNotFound,
+    PartitionNotFound,
+    NodeNotFound,
+
+    /// The partition is already owned by this node
+    AlreadyOwned,
+
+    /// The change could not be saved to the database
+    PersistenceFailure(String),
+}
+
+pub type ReassignResult = Result<PartitionRef, ReassignError>;
 
 pub struct AdminService {
+    persistence: Arc<PersistenceLayer>,
     cluster: Arc<Cluster>,
 }
 
 impl AdminService {
-    pub fn new(cluster: &Arc<Cluster>) -> Self {
+    pub fn new(persistence: &Arc<PersistenceLayer>, cluster: &Arc<Cluster>) -> Self {
         Self {
+            persistence: Arc::clone(persistence),
             cluster: Arc::clone(cluster),
         }
     }
@@ -74,4 +160,269 @@ impl AdminService {
             .ledgers()
             .get(&ledger_id)
     }
+
+    /// Adds a topic to the cluster. Topic names must be unique because publishers and
+    /// consumers can look topics up by name
+    pub fn create_topic(self: &Self, name: &str) -> CreateResult<TopicRef> {
+        if self.topic_by_name(name).is_some() {
+            return Err(CreateError::NameInUse);
+        }
+        Ok(self.cluster.add_topic(name)?)
+    }
+
+    /// Adds a partition to a topic. The partition is owned by this node if no node is specified
+    pub fn create_partition(
+        self: &Self,
+        topic_id: TopicId,
+        node_id: Option<NodeId>,
+    ) -> CreateResult<PartitionRef> {
+        let topic = self
+            .topic_by_id(topic_id)
+            .ok_or(CreateError::TopicNotFound)?;
+        let node_id = node_id.unwrap_or(self.cluster.my_node_id());
+        if self.node_by_id(node_id).is_none() {
+            return Err(CreateError::NodeNotFound);
+        }
+        Ok(self.cluster.add_partition(&topic, node_id)?)
+    }
+
+    /// Adds a subscription to a topic. Subscription names must be unique within the topic
+    pub fn create_subscription(
+        self: &Self,
+        topic_id: TopicId,
+        name: &str,
+        has_key_affinity: bool,
+    ) -> CreateResult<SubscriptionRef> {
+        let topic = self
+            .topic_by_id(topic_id)
+            .ok_or(CreateError::TopicNotFound)?;
+        if topic
+            .subscriptions()
+            .find(|subscription| subscription.name() == name)
+            .is_some()
+        {
+            return Err(CreateError::NameInUse);
+        }
+        Ok(self
+            .cluster
+            .add_subscription(&topic, name, has_key_affinity)?)
+    }
+
+    /// Deletes a topic along with its partitions, ledgers and subscriptions. Messages that
+    /// have not been consumed yet are lost
+    pub fn delete_topic(self: &Self, topic_id: TopicId) -> DeleteResult {
+        if self.topic_by_id(topic_id).is_none() {
+            return Err(DeleteError::TopicNotFound);
+        }
+        self.cluster
+            .delete_topic(topic_id)
+            .map_err(|err| match err {
+                DataUpdateError::NotFound => DeleteError::TopicNotFound,
+                DataUpdateError::PersistenceFailure { msg } => DeleteError::PersistenceFailure(msg),
+                DataUpdateError::Unmodified => {
+                    DeleteError::PersistenceFailure(format!("Topic {topic_id} was not modified"))
+                }
+            })
+    }
+
+    /// Deletes a subscription from a topic. Messages that were published to the topic are
+    /// no longer held for this subscription
+    pub fn delete_subscription(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+    ) -> DeleteResult {
+        let topic = self
+            .topic_by_id(topic_id)
+            .ok_or(DeleteError::TopicNotFound)?;
+        if topic.subscriptions().get(&subscription_id).is_none() {
+            return Err(DeleteError::SubscriptionNotFound);
+        }
+        self.cluster
+            .delete_subscription(&topic, subscription_id)
+            .map_err(|err| match err {
+                DataUpdateError::NotFound => DeleteError::SubscriptionNotFound,
+                DataUpdateError::PersistenceFailure { msg } => DeleteError::PersistenceFailure(msg),
+                DataUpdateError::Unmodified => {
+                    DeleteError::PersistenceFailure(format!("Topic {topic_id} was not modified"))
+                }
+            })
+    }
+
+    /// Moves a partition to another node, which starts a new ledger for the partition.
+    /// Messages that were published before the move stay in the ledgers they were
+    /// published to, and publishers are redirected to the new owner
+    pub fn reassign_partition(
+        self: &Self,
+        topic_id: TopicId,
+        partition_id: PartitionId,
+        node_id: NodeId,
+    ) -> ReassignResult {
+        let topic = self
+            .topic_by_id(topic_id)
+            .ok_or(ReassignError::TopicNotFound)?;
+        if topic.partitions().get(&partition_id).is_none() {
+            return Err(ReassignError::PartitionNotFound);
+        }
+        if self.node_by_id(node_id).is_none() {
+            return Err(ReassignError::NodeNotFound);
+        }
+        self.cluster
+            .reassign_partition(&topic, partition_id, node_id)
+            .map_err(|err| match err {
+                DataUpdateError::NotFound => ReassignError::PartitionNotFound,
+                DataUpdateError::Unmodified => ReassignError::AlreadyOwned,
+                DataUpdateError::PersistenceFailure { msg } => {
+                    ReassignError::PersistenceFailure(msg)
+                }
+            })
+    }
+
+    /// Acks a message on a subscription regardless of which consumer it was delivered to, or
+    /// whether it was delivered yet. This allows an operator to skip a message that no consumer
+    /// is able to process. Returns false if the subscription was not waiting for this message
+    pub fn force_ack(
+        self: &Self,
+        message_ref: MessageRef,
+        subscription_id: SubscriptionId,
+    ) -> ForceAckResult {
+        let topic = self
+            .cluster
+            .topics()
+            .get(&message_ref.topic_id)
+            .ok_or(AdminError::TopicNotFound)?;
+        let subscription = topic
+            .subscriptions()
+            .get(&subscription_id)
+            .ok_or(AdminError::SubscriptionNotFound)?;
+        let partition = topic
+            .partitions()
+            .get(&message_ref.partition_id)
+            .ok_or(AdminError::PartitionNotFound)?;
+        let ledger = partition
+            .ledgers()
+            .get(&message_ref.ledger_id)
+            .ok_or(AdminError::LedgerNotFound)?;
+
+        if !subscription.force_ack(&message_ref.to_key()) {
+            return Ok(false);
+        }
+        if !topic.is_ephemeral() {
+            let _ = self
+                .persistence
+                .log_event(&LoggedEvent::AdminAck(AdminAckEvent::new(
+                    message_ref,
+                    subscription_id,
+                )));
+        }
+        ledger.ack(&message_ref.message_id);
+        Ok(true)
+    }
+
+    /// Rebuilds the backlog of a subscription by replaying the events logged for its topic.
+    /// Messages that were published after the subscription was created and not acked, and are
+    /// still in their ledger, should be held by the subscription. Nacked messages are still unacked. Any of these messages that
+    /// the subscription lost are queued again, and acked messages that the subscription is
+    /// still holding are removed. Messages that are published while the repair is running
+    /// can be restored before they are queued, so publishing should be paused first
+    pub fn repair_subscription(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+    ) -> RepairResult {
+        let topic = self
+            .cluster
+            .topics()
+            .get(&topic_id)
+            .ok_or(AdminError::TopicNotFound)?;
+        let subscription = topic
+            .subscriptions()
+            .get(&subscription_id)
+            .ok_or(AdminError::SubscriptionNotFound)?;
+        if topic.is_ephemeral() {
+            return Err(AdminError::TopicIsEphemeral);
+        }
+
+        let _ = self.persistence.flush_events();
+
+        let created = subscription.created();
+        let mut published: Vec<MessageRef> = Vec::new();
+        let mut acked: HashSet<String> = HashSet::new();
+        let key_prefix = PersistenceLayer::build_topic_prefix(topic_id);
+        let options = EventQueryOptions::replay();
+        for log_entry in self.persistence.events_by_key_prefix(&key_prefix, &options) {
+            match log_entry.deserialize() {
+                Some(LoggedEvent::Publish(event)) if event.message.published >= created => {
+                    published.push(event.message.message_ref)
+                }
+                Some(LoggedEvent::Ack(event)) if event.subscription_id == subscription_id => {
+                    acked.insert(event.message_ref.to_key());
+                }
+                Some(LoggedEvent::AdminAck(event)) if event.subscription_id == subscription_id => {
+                    acked.insert(event.message_ref.to_key());
+                }
+                Some(LoggedEvent::Quarantine(event))
+                    if event.subscription_id == subscription_id =>
+                {
+                    acked.insert(event.message_ref.to_key());
+                }
+                Some(LoggedEvent::Expiry(event)) if event.subscription_id == subscription_id => {
+                    acked.insert(event.message_ref.to_key());
+                }
+                Some(LoggedEvent::DeadLetter(event))
+                    if event.subscription_id == subscription_id =>
+                {
+                    acked.insert(event.message_ref.to_key());
+                }
+                _ => {}
+            }
+        }
+
+        let held = subscription.message_ref_keys();
+        let mut unacked_count = 0;
+        let mut restored = Vec::new();
+        for message_ref in published {
+            let message_ref_key = message_ref.to_key();
+            if acked.contains(&message_ref_key) {
+                continue;
+            }
+
+            // Messages that are no longer in the ledger were discarded, for example because
+            // they expired or the subscription queue overflowed
+            let ledger = match self.ledger_by_id(
+                topic_id,
+                message_ref.partition_id,
+                message_ref.ledger_id,
+            ) {
+                Some(ledger) => ledger,
+                None => continue,
+            };
+            let message = match ledger.peek_message(message_ref.message_id) {
+                Some(message) => message,
+                None => continue,
+            };
+
+            // The ledger must keep a restored message until this subscription acks it, as
+            // well as any other subscriptions that are still holding it
+            unacked_count += 1;
+            if !held.contains(&message_ref_key) {
+                ledger.retain_message(&message);
+                subscription.restore(SubscribedMessage::from(&message));
+                restored.push(message_ref_key);
+            }
+        }
+
+        let removed = held
+            .into_iter()
+            .filter(|message_ref_key| {
+                acked.contains(message_ref_key) && subscription.force_ack(message_ref_key)
+            })
+            .collect();
+
+        Ok(SubscriptionRepair {
+            unacked_count,
+            restored,
+            removed,
+        })
+    }
 }
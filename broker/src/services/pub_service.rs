@@ -6,16 +6,39 @@ be layered on top of this service to expose this funtionallity to applicatins.
 use crate::{
     model::{
         cluster::Cluster,
+        ledger::LedgerRef,
         messages::{MessageRef, PublishedMessage, SubscribedMessage},
         node::NodeRef,
+        subscription::PushResult,
         topic::TopicRef,
     },
     persistence::{log_entries::LoggedEvent, logged_events::PublishEvent, PersistenceLayer},
     utils::now_epoc_millis,
 };
-use pulsar_rust_net::data_types::Timestamp;
-use std::sync::{Arc, Mutex};
+use delayed_delivery::DelayedDelivery;
+use idempotency_keys::IdempotencyKeys;
+use log::warn;
+use producer_sequences::{ProducerSequences, SequenceCheck};
+use pulsar_rust_net::{
+    contracts::v1::requests::PublishAckLevel,
+    data_types::{NodeId, ProducerId, SequenceNumber, SubscriptionId, Timestamp},
+};
+use std::{
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+mod delayed_delivery;
+mod idempotency_keys;
+mod producer_sequences;
 
+/// Messages with this attribute are only published once to topics that have an idempotency
+/// window. Publishing another message with the same value for this attribute within the window
+/// returns the message ref of the original message
+pub const IDEMPOTENCY_KEY_ATTRIBUTE: &str = "idempotency-key";
+
+#[cfg_attr(debug_assertions, derive(Debug))]
 pub enum PubError {
     Error(String),
     TopicNotFound,
@@ -24,14 +47,47 @@ pub enum PubError {
     WrongNode(NodeRef),
     BacklogCapacityExceeded,
     NoSubscribers,
+    NoLedger,
+    DuplicateSequenceNumber,
 }
 
 pub type PubResult<'a> = Result<MessageRef, PubError>;
 
+/// Where a message was published, so that producers can see how their messages are
+/// distributed across partitions and nodes
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct PublishReceipt {
+    pub message_ref: MessageRef,
+    pub node_id: NodeId,
+
+    /// The number of messages in the ledger when the receipt was made
+    pub ledger_message_count: usize,
+}
+
+/// A message that was allocated an id and acknowledged to the publisher, but has not
+/// been logged or added to its subscriptions yet
+struct DeferredPublish {
+    message: PublishedMessage,
+    subscription_ids: Vec<SubscriptionId>,
+}
+
 pub struct PubService {
     persistence: Arc<PersistenceLayer>,
     cluster: Arc<Cluster>,
     new_ledger_lock: Mutex<()>,
+    deferred: Mutex<Vec<DeferredPublish>>,
+    flushing: Mutex<()>,
+
+    /// Publishes with an idempotency key hold this lock until the message is published, so
+    /// that two messages with the same key can not both be published
+    idempotency_keys: Mutex<IdempotencyKeys>,
+
+    /// Publishes with a producer sequence number hold this lock until the message is published,
+    /// so that a message that the producer sends twice can not both be published
+    producer_sequences: Mutex<ProducerSequences>,
+
+    delayed_delivery: Arc<DelayedDelivery>,
+    delayed_delivery_thread: Mutex<Option<JoinHandle<()>>>,
 }
 
 impl PubService {
@@ -40,6 +96,30 @@ impl PubService {
             persistence: Arc::clone(persistence),
             cluster: Arc::clone(cluster),
             new_ledger_lock: Mutex::new(()),
+            deferred: Mutex::new(Vec::new()),
+            flushing: Mutex::new(()),
+            idempotency_keys: Mutex::new(IdempotencyKeys::new(persistence)),
+            producer_sequences: Mutex::new(ProducerSequences::new()),
+            delayed_delivery: Arc::new(DelayedDelivery::new(cluster)),
+            delayed_delivery_thread: Mutex::new(None),
+        }
+    }
+
+    /// Starts a background thread that adds messages that were published with a delivery delay
+    /// to their subscriptions once they are due, checking each time the sweep interval elapses.
+    /// Without this, delayed messages are held back from their subscriptions indefinitely
+    pub fn with_delayed_delivery(mut self: Self, sweep_interval: Duration) -> Self {
+        self.delayed_delivery_thread =
+            Mutex::new(Some(self.delayed_delivery.start(sweep_interval)));
+        self
+    }
+
+    /// Stops the background thread that delivers delayed messages, and waits for it to finish.
+    /// Delayed messages that are not due yet stay in the ledger and the event log
+    pub fn stop(self: &Self) {
+        self.delayed_delivery.stop();
+        if let Some(delayed_delivery_thread) = self.delayed_delivery_thread.lock().unwrap().take() {
+            let _ = delayed_delivery_thread.join();
         }
     }
 
@@ -47,7 +127,97 @@ impl PubService {
         self.cluster.topics().find(|topic| topic.name() == name)
     }
 
-    pub fn publish_message(self: &Self, mut message: PublishedMessage) -> PubResult {
+    pub fn publish_message(self: &Self, message: PublishedMessage) -> PubResult {
+        self.publish_message_with_ack_level(message, PublishAckLevel::Queued)
+    }
+
+    /// Publishes a message, responding once the work required by the ack level is done. Messages
+    /// published with `PublishAckLevel::None` are completed by `flush_publishes`, which is also
+    /// called at the start of every publish so that messages are queued in the order that they
+    /// were published. Messages with an idempotency key that was already published to the topic
+    /// within its idempotency window are not published again, and neither are messages with a
+    /// sequence number that their producer already published to a topic that deduplicates them
+    pub fn publish_message_with_ack_level(
+        self: &Self,
+        message: PublishedMessage,
+        ack_level: PublishAckLevel,
+    ) -> PubResult {
+        self.flush_publishes();
+
+        match self.producer_sequence(&message) {
+            Some((producer_id, sequence_number)) => {
+                let message_ref = message.message_ref;
+                let mut producer_sequences = self.producer_sequences.lock().unwrap();
+                match producer_sequences.check(
+                    message_ref.topic_id,
+                    message_ref.partition_id,
+                    producer_id,
+                    sequence_number,
+                ) {
+                    SequenceCheck::New => {}
+                    SequenceCheck::Published(message_ref) => return Ok(message_ref),
+                    SequenceCheck::Stale => return Err(PubError::DuplicateSequenceNumber),
+                }
+                let message_ref = self.publish_idempotent(message, ack_level)?;
+                producer_sequences.remember(producer_id, sequence_number, message_ref);
+                Ok(message_ref)
+            }
+            None => self.publish_idempotent(message, ack_level),
+        }
+    }
+
+    fn publish_idempotent(
+        self: &Self,
+        message: PublishedMessage,
+        ack_level: PublishAckLevel,
+    ) -> PubResult {
+        let topic_id = message.message_ref.topic_id;
+        match self.idempotency_key(&message) {
+            Some((key, window_millis)) => {
+                let mut idempotency_keys = self.idempotency_keys.lock().unwrap();
+                if let Some(message_ref) = idempotency_keys.find(topic_id, &key, window_millis) {
+                    return Ok(message_ref);
+                }
+                let message_ref = self.publish_new_message(message, ack_level)?;
+                idempotency_keys.remember(topic_id, key, message_ref);
+                Ok(message_ref)
+            }
+            None => self.publish_new_message(message, ack_level),
+        }
+    }
+
+    /// Returns the producer id and sequence number of a message, or `None` if the message is
+    /// not numbered by its producer or the topic does not deduplicate sequence numbers
+    fn producer_sequence(
+        self: &Self,
+        message: &PublishedMessage,
+    ) -> Option<(ProducerId, SequenceNumber)> {
+        let producer_id = message.producer_id?;
+        let sequence_number = message.sequence_number?;
+        let topic = self.cluster.topics().get(&message.message_ref.topic_id)?;
+        if topic.is_sequence_dedup() {
+            Some((producer_id, sequence_number))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the idempotency key of a message and the idempotency window of its topic, or
+    /// `None` if the message has no key or the topic does not detect duplicates
+    fn idempotency_key(self: &Self, message: &PublishedMessage) -> Option<(String, u64)> {
+        let key = message.attributes.get(IDEMPOTENCY_KEY_ATTRIBUTE)?;
+        let topic = self.cluster.topics().get(&message.message_ref.topic_id)?;
+        match topic.idempotency_window_millis() {
+            0 => None,
+            window_millis => Some((key.clone(), window_millis)),
+        }
+    }
+
+    fn publish_new_message(
+        self: &Self,
+        mut message: PublishedMessage,
+        ack_level: PublishAckLevel,
+    ) -> PubResult {
         // Short circuit publising to troubleshoot network performance
         // return Ok(MessageRef {
         //     topic_id: message.message_ref.topic_id,
@@ -68,6 +238,17 @@ impl PubService {
             return PubResult::Err(PubError::NoSubscribers);
         }
 
+        // Subscriptions that are full and configured to block publishers prevent
+        // any more messages from being published to the topic
+        if subscrition_ids.iter().any(|subscription_id| {
+            topic
+                .subscriptions()
+                .get(subscription_id)
+                .is_some_and(|subscription| subscription.blocks_publisher())
+        }) {
+            return PubResult::Err(PubError::BacklogCapacityExceeded);
+        }
+
         // Find the partition within this topic
         let partition = match topic.partitions().get(&message.message_ref.partition_id) {
             Some(partition) => partition,
@@ -96,36 +277,21 @@ impl PubService {
                         message.timestamp = message.published;
                     }
 
-                    let message_ref = message.message_ref;
-                    match self
-                        .persistence
-                        .log_event(&LoggedEvent::Publish(PublishEvent::new(&message)))
-                    {
-                        Ok(_) => {
-                            // We must add the message to the ledger first becuase subscribers could immediately
-                            // try to send the message to consumers
-                            let message_ref_key = message.message_ref.to_key();
-                            let key = message.key.clone();
-                            ledger.publish_message(message);
-
-                            // Add the message to all subscribers
-                            for subscription_id in subscrition_ids {
-                                if let Some(subscription) =
-                                    topic.subscriptions().get(&subscription_id)
-                                {
-                                    let subscribed_message =
-                                        SubscribedMessage::new(&message_ref_key, &key);
-                                    subscription.push(subscribed_message);
-                                }
-                            }
-
-                            // Confirm that the message was sucesfully published
+                    match ack_level {
+                        PublishAckLevel::None => {
+                            let message_ref = message.message_ref;
+                            self.deferred.lock().unwrap().push(DeferredPublish {
+                                message,
+                                subscription_ids: subscrition_ids,
+                            });
                             Ok(message_ref)
                         }
-                        Err(err) => PubResult::Err(PubError::Error(format!(
-                            "Failed to write publish event to transaction log. {:?}",
-                            err
-                        ))),
+                        PublishAckLevel::Queued => {
+                            self.complete_publish(&topic, &ledger, message, subscrition_ids, false)
+                        }
+                        PublishAckLevel::Persisted => {
+                            self.complete_publish(&topic, &ledger, message, subscrition_ids, true)
+                        }
                     }
                 } else {
                     let _lock = self
@@ -135,6 +301,10 @@ impl PubService {
                     PubResult::Err(PubError::BacklogCapacityExceeded) // TODO: Create a new ledger if this one is full
                 }
             }
+            None if partition.node_id() == self.cluster.my_node_id() => {
+                // We own this partition, but a ledger could not be obtained for it
+                PubResult::Err(PubError::NoLedger)
+            }
             None => {
                 // We don't own this partition, tell the caller to post to the broker that does
                 let node_id = partition.node_id();
@@ -146,4 +316,140 @@ impl PubService {
             }
         }
     }
+
+    /// Describes where a published message was stored. Messages in ledgers that this node no
+    /// longer has are reported as being on this node, in an empty ledger
+    pub fn receipt(self: &Self, message_ref: MessageRef) -> PublishReceipt {
+        let ledger = self
+            .cluster
+            .topics()
+            .get(&message_ref.topic_id)
+            .and_then(|topic| topic.partitions().get(&message_ref.partition_id))
+            .and_then(|partition| partition.ledgers().get(&message_ref.ledger_id));
+        match ledger {
+            Some(ledger) => PublishReceipt {
+                message_ref,
+                node_id: ledger.node_id(),
+                ledger_message_count: ledger.message_count(),
+            },
+            None => PublishReceipt {
+                message_ref,
+                node_id: self.cluster.my_node_id(),
+                ledger_message_count: 0,
+            },
+        }
+    }
+
+    /// Completes any publishes that were acknowledged to the publisher before the messages were
+    /// logged and added to subscriptions. Flushes are serialized so that when this returns,
+    /// messages taken by a concurrent flush have also been completed
+    pub fn flush_publishes(self: &Self) {
+        let _flushing = self.flushing.lock().unwrap();
+        let deferred = std::mem::take(&mut *self.deferred.lock().unwrap());
+
+        for publish in deferred {
+            let message_ref = publish.message.message_ref;
+            let topic = match self.cluster.topics().get(&message_ref.topic_id) {
+                Some(topic) => topic,
+                None => continue,
+            };
+            let ledger = match topic
+                .partitions()
+                .get(&message_ref.partition_id)
+                .and_then(|partition| partition.ledgers().get(&message_ref.ledger_id))
+            {
+                Some(ledger) => ledger,
+                None => continue,
+            };
+            if let Err(PubError::Error(msg)) = self.complete_publish(
+                &topic,
+                &ledger,
+                publish.message,
+                publish.subscription_ids,
+                false,
+            ) {
+                warn!(
+                    "Message {} was acknowledged but not published. {msg}",
+                    message_ref.to_key()
+                );
+            }
+        }
+    }
+
+    /// Logs a message that has been allocated an id, then adds it to the ledger and to all of the
+    /// subscriptions. When `durable` is true the event log is flushed before the message is queued.
+    /// Messages published to ephemeral topics are not logged
+    fn complete_publish(
+        self: &Self,
+        topic: &TopicRef,
+        ledger: &LedgerRef,
+        message: PublishedMessage,
+        subscrition_ids: Vec<SubscriptionId>,
+        durable: bool,
+    ) -> PubResult {
+        let message_ref = message.message_ref;
+        if !topic.is_ephemeral() {
+            let logged = self
+                .persistence
+                .log_event(&LoggedEvent::Publish(PublishEvent::new(&message)))
+                .and_then(|_| {
+                    if durable {
+                        self.persistence.flush_events()
+                    } else {
+                        Ok(())
+                    }
+                });
+            if let Err(err) = logged {
+                return PubResult::Err(PubError::Error(format!(
+                    "Failed to write publish event to transaction log. {:?}",
+                    err
+                )));
+            }
+        }
+
+        // We must add the message to the ledger first becuase subscribers could immediately
+        // try to send the message to consumers
+        let message_ref_key = message.message_ref.to_key();
+        let key = message.key.clone();
+        let published = message.published;
+        let priority = message.priority;
+        let deliver_at = message.deliver_at;
+        ledger.publish_message(message);
+
+        // Messages with a future delivery time are added to the subscriptions when they are due
+        if let Some(deliver_at) = deliver_at.filter(|deliver_at| *deliver_at > published) {
+            let subscribed_message =
+                SubscribedMessage::new(&message_ref_key, &key, published, priority);
+            self.delayed_delivery
+                .schedule(deliver_at, subscribed_message, subscrition_ids);
+            return Ok(message_ref);
+        }
+
+        // Add the message to all subscribers
+        for subscription_id in subscrition_ids {
+            if let Some(subscription) = topic.subscriptions().get(&subscription_id) {
+                let subscribed_message =
+                    SubscribedMessage::new(&message_ref_key, &key, published, priority);
+                match subscription.push(subscribed_message) {
+                    PushResult::Queued => {}
+                    PushResult::Dropped(dropped) => Self::discard_message(topic, &dropped),
+                    PushResult::Rejected(rejected) => Self::discard_message(topic, &rejected),
+                }
+            }
+        }
+
+        // Confirm that the message was sucesfully published
+        Ok(message_ref)
+    }
+
+    /// When a message is removed from a subscription without being delivered, it
+    /// is treated as acked by that subscription so that it can be removed from the ledger
+    pub(crate) fn discard_message(topic: &TopicRef, message: &SubscribedMessage) {
+        let message_ref = MessageRef::from_key(&message.message_ref_key);
+        if let Some(partition) = topic.partitions().get(&message_ref.partition_id) {
+            if let Some(ledger) = partition.ledgers().get(&message_ref.ledger_id) {
+                ledger.ack(&message_ref.message_id);
+            }
+        }
+    }
 }
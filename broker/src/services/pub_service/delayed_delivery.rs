@@ -0,0 +1,163 @@
+/*
+Holds messages that were published with a delivery delay until their delivery time arrives.
+The messages are added to the ledger and logged when they are published, so that they are
+retained like any other message, but they are not added to the subscriptions until a background
+thread finds that they are due. The scheduled messages are kept in a heap ordered by delivery
+time, so each sweep only looks at the messages that are due.
+
+Messages that are scheduled for the same time are delivered in the order that they were
+published.
+*/
+
+use std::{
+    cmp::{Ordering as CmpOrdering, Reverse},
+    collections::BinaryHeap,
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use pulsar_rust_net::data_types::{SubscriptionId, Timestamp};
+
+use crate::{
+    model::{
+        cluster::Cluster,
+        messages::{MessageRef, SubscribedMessage},
+        subscription::PushResult,
+    },
+    utils::{now_epoc_millis, StopSignal},
+};
+
+struct ScheduledMessage {
+    deliver_at: Timestamp,
+
+    /// Breaks ties between messages with the same delivery time
+    sequence: u64,
+
+    message: SubscribedMessage,
+    subscription_ids: Vec<SubscriptionId>,
+}
+
+impl PartialEq for ScheduledMessage {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for ScheduledMessage {}
+
+impl PartialOrd for ScheduledMessage {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ScheduledMessage {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        (self.deliver_at, self.sequence).cmp(&(other.deliver_at, other.sequence))
+    }
+}
+
+#[derive(Default)]
+struct Schedule {
+    messages: BinaryHeap<Reverse<ScheduledMessage>>,
+    next_sequence: u64,
+}
+
+pub(super) struct DelayedDelivery {
+    cluster: Arc<Cluster>,
+    schedule: Mutex<Schedule>,
+    stop_signal: StopSignal,
+}
+
+impl DelayedDelivery {
+    pub(super) fn new(cluster: &Arc<Cluster>) -> Self {
+        Self {
+            cluster: Arc::clone(cluster),
+            schedule: Mutex::new(Schedule::default()),
+            stop_signal: StopSignal::new(),
+        }
+    }
+
+    /// Starts a thread that delivers the messages that are due each time the interval elapses
+    pub(super) fn start(self: &Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        let delayed_delivery = Arc::clone(self);
+        thread::spawn(move || {
+            while !delayed_delivery.stop_signal.wait(interval) {
+                delayed_delivery.sweep();
+            }
+        })
+    }
+
+    pub(super) fn stop(self: &Self) {
+        self.stop_signal.stop();
+    }
+
+    /// Holds a message back from its subscriptions until the delivery time
+    pub(super) fn schedule(
+        self: &Self,
+        deliver_at: Timestamp,
+        message: SubscribedMessage,
+        subscription_ids: Vec<SubscriptionId>,
+    ) {
+        let mut schedule = self.schedule.lock().unwrap();
+        let sequence = schedule.next_sequence;
+        schedule.next_sequence += 1;
+        schedule.messages.push(Reverse(ScheduledMessage {
+            deliver_at,
+            sequence,
+            message,
+            subscription_ids,
+        }));
+    }
+
+    /// Adds the messages that are due to their subscriptions. Returns the number of messages
+    /// delivered
+    pub(super) fn sweep(self: &Self) -> usize {
+        let now = now_epoc_millis();
+        let mut due = Vec::new();
+        {
+            let mut schedule = self.schedule.lock().unwrap();
+            while schedule
+                .messages
+                .peek()
+                .is_some_and(|Reverse(scheduled)| scheduled.deliver_at <= now)
+            {
+                due.push(schedule.messages.pop().unwrap().0);
+            }
+        }
+
+        let count = due.len();
+        for scheduled in due {
+            self.deliver(scheduled);
+        }
+        count
+    }
+
+    fn deliver(self: &Self, scheduled: ScheduledMessage) {
+        let message_ref = MessageRef::from_key(&scheduled.message.message_ref_key);
+        let topic = match self.cluster.topics().get(&message_ref.topic_id) {
+            Some(topic) => topic,
+            None => return,
+        };
+        for subscription_id in scheduled.subscription_ids {
+            if let Some(subscription) = topic.subscriptions().get(&subscription_id) {
+                match subscription.push(scheduled.message.clone()) {
+                    PushResult::Queued => {}
+                    PushResult::Dropped(discarded) | PushResult::Rejected(discarded) => {
+                        // The subscription will never deliver the message, so it is treated
+                        // as acked by that subscription
+                        let discarded_ref = MessageRef::from_key(&discarded.message_ref_key);
+                        if let Some(ledger) = topic
+                            .partitions()
+                            .get(&discarded_ref.partition_id)
+                            .and_then(|partition| partition.ledgers().get(&discarded_ref.ledger_id))
+                        {
+                            ledger.ack(&discarded_ref.message_id);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
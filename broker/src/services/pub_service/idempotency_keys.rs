@@ -0,0 +1,131 @@
+/*
+Remembers the idempotency keys of messages that were recently published to each topic, so that
+publishing another message with the same key returns the original message ref instead of
+publishing a duplicate. The keys for a topic are loaded from the publish events in the event
+log the first time they are needed, so duplicates are detected after a broker restart, and
+regardless of which producer publishes them, for as long as the events are retained.
+*/
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+};
+
+use pulsar_rust_net::data_types::{Timestamp, TopicId};
+
+use crate::{
+    model::messages::MessageRef,
+    persistence::{event_logger::EventQueryOptions, log_entries::LoggedEvent, PersistenceLayer},
+    utils::now_epoc_millis,
+};
+
+use super::IDEMPOTENCY_KEY_ATTRIBUTE;
+
+/// The idempotency keys published to one topic, with the keys in the order that they expire
+#[derive(Default)]
+struct TopicKeys {
+    published: HashMap<String, (MessageRef, Timestamp)>,
+    expiry: VecDeque<(Timestamp, String)>,
+}
+
+impl TopicKeys {
+    fn insert(self: &mut Self, key: String, message_ref: MessageRef, published: Timestamp) {
+        self.expiry.push_back((published, key.clone()));
+        self.published.insert(key, (message_ref, published));
+    }
+
+    fn expire(self: &mut Self, window_start: Timestamp) {
+        while let Some((published, key)) = self.expiry.front() {
+            if *published >= window_start {
+                break;
+            }
+            if self
+                .published
+                .get(key)
+                .is_some_and(|(_, remembered)| remembered == published)
+            {
+                self.published.remove(key);
+            }
+            self.expiry.pop_front();
+        }
+    }
+}
+
+pub(super) struct IdempotencyKeys {
+    persistence: Arc<PersistenceLayer>,
+    topics: HashMap<TopicId, TopicKeys>,
+}
+
+impl IdempotencyKeys {
+    pub(super) fn new(persistence: &Arc<PersistenceLayer>) -> Self {
+        Self {
+            persistence: Arc::clone(persistence),
+            topics: HashMap::new(),
+        }
+    }
+
+    /// Returns the message ref of the message that was published to the topic with this
+    /// idempotency key within the window, if there is one
+    pub(super) fn find(
+        self: &mut Self,
+        topic_id: TopicId,
+        key: &str,
+        window_millis: u64,
+    ) -> Option<MessageRef> {
+        let window_start = now_epoc_millis().saturating_sub(window_millis);
+        let persistence = &self.persistence;
+        let topic_keys = self
+            .topics
+            .entry(topic_id)
+            .or_insert_with(|| load_topic_keys(persistence, topic_id));
+        topic_keys.expire(window_start);
+        topic_keys
+            .published
+            .get(key)
+            .map(|(message_ref, _)| *message_ref)
+    }
+
+    pub(super) fn remember(
+        self: &mut Self,
+        topic_id: TopicId,
+        key: String,
+        message_ref: MessageRef,
+    ) {
+        self.topics
+            .entry(topic_id)
+            .or_default()
+            .insert(key, message_ref, now_epoc_millis());
+    }
+}
+
+/// Replays the publish events for a topic to find the idempotency keys of its messages. Events
+/// are replayed in message ref order rather than publication order, so the keys are sorted
+/// into the order that they expire afterwards
+fn load_topic_keys(persistence: &PersistenceLayer, topic_id: TopicId) -> TopicKeys {
+    let mut published: HashMap<String, (MessageRef, Timestamp)> = HashMap::new();
+    let key_prefix = PersistenceLayer::build_topic_prefix(topic_id);
+    let options = EventQueryOptions::replay();
+    for log_entry in persistence.events_by_key_prefix(&key_prefix, &options) {
+        if let Some(LoggedEvent::Publish(event)) = log_entry.deserialize() {
+            let message = event.message;
+            if let Some(key) = message.attributes.get(IDEMPOTENCY_KEY_ATTRIBUTE) {
+                if published
+                    .get(key)
+                    .is_none_or(|(_, previous)| *previous < message.published)
+                {
+                    published.insert(key.clone(), (message.message_ref, message.published));
+                }
+            }
+        }
+    }
+
+    let mut expiry: Vec<(Timestamp, String)> = published
+        .iter()
+        .map(|(key, (_, published))| (*published, key.clone()))
+        .collect();
+    expiry.sort_unstable();
+    TopicKeys {
+        published,
+        expiry: expiry.into(),
+    }
+}
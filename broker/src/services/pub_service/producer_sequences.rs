@@ -0,0 +1,181 @@
+/*
+Remembers the highest sequence number that each producer has published to each partition, so
+that a producer that sends a message again, for example after a timeout, does not publish it
+twice. The message refs of the most recent sequence numbers are kept, so that publishing one of
+these again returns the original message ref. Older sequence numbers are rejected because their
+message refs are no longer known.
+
+This is held in memory only. Producers that stop publishing are forgotten after a while, and
+the least recently used producers are forgotten when there are too many, so the memory used is
+bounded no matter how many producers come and go.
+*/
+
+use std::collections::{HashMap, VecDeque};
+
+use pulsar_rust_net::data_types::{PartitionId, ProducerId, SequenceNumber, Timestamp, TopicId};
+
+use crate::{model::messages::MessageRef, utils::now_epoc_millis};
+
+/// The number of message refs that are remembered for each producer and partition
+const MAX_RECENT_SEQUENCES: usize = 100;
+
+/// Producers that have not published to a partition for this long are forgotten
+const PRODUCER_IDLE_MILLIS: u64 = 10 * 60 * 1000;
+
+/// Idle producers are looked for at most this often
+const PRUNE_INTERVAL_MILLIS: u64 = 1000;
+
+/// The number of producer and partition combinations that are remembered
+const MAX_PRODUCERS: usize = 100_000;
+
+type ProducerKey = (TopicId, PartitionId, ProducerId);
+
+/// The outcome of checking a sequence number against the ones that the producer already published
+pub(super) enum SequenceCheck {
+    /// The producer has not published this sequence number, so the message should be published
+    New,
+
+    /// The producer already published this sequence number, and this is the message ref of
+    /// the message that was published
+    Published(MessageRef),
+
+    /// The producer already published this sequence number, or a higher one, and the message
+    /// ref of that message is no longer remembered
+    Stale,
+}
+
+struct ProducerSequence {
+    highest: SequenceNumber,
+    recent: VecDeque<(SequenceNumber, MessageRef)>,
+    last_publish: Timestamp,
+}
+
+pub(super) struct ProducerSequences {
+    producers: HashMap<ProducerKey, ProducerSequence>,
+    last_prune: Timestamp,
+}
+
+impl ProducerSequences {
+    pub(super) fn new() -> Self {
+        Self {
+            producers: HashMap::new(),
+            last_prune: now_epoc_millis(),
+        }
+    }
+
+    pub(super) fn check(
+        self: &Self,
+        topic_id: TopicId,
+        partition_id: PartitionId,
+        producer_id: ProducerId,
+        sequence_number: SequenceNumber,
+    ) -> SequenceCheck {
+        let producer = match self.producers.get(&(topic_id, partition_id, producer_id)) {
+            Some(producer) => producer,
+            None => return SequenceCheck::New,
+        };
+        if sequence_number > producer.highest {
+            return SequenceCheck::New;
+        }
+        match producer
+            .recent
+            .iter()
+            .find(|(recent, _)| *recent == sequence_number)
+        {
+            Some((_, message_ref)) => SequenceCheck::Published(*message_ref),
+            None => SequenceCheck::Stale,
+        }
+    }
+
+    /// Records that a sequence number was published, forgetting producers that are idle
+    pub(super) fn remember(
+        self: &mut Self,
+        producer_id: ProducerId,
+        sequence_number: SequenceNumber,
+        message_ref: MessageRef,
+    ) {
+        let now = now_epoc_millis();
+        self.prune(now);
+
+        let producer = self
+            .producers
+            .entry((message_ref.topic_id, message_ref.partition_id, producer_id))
+            .or_insert_with(|| ProducerSequence {
+                highest: sequence_number,
+                recent: VecDeque::new(),
+                last_publish: now,
+            });
+        producer.highest = producer.highest.max(sequence_number);
+        producer.last_publish = now;
+        producer.recent.push_back((sequence_number, message_ref));
+        if producer.recent.len() > MAX_RECENT_SEQUENCES {
+            producer.recent.pop_front();
+        }
+    }
+
+    fn prune(self: &mut Self, now: Timestamp) {
+        if now.saturating_sub(self.last_prune) >= PRUNE_INTERVAL_MILLIS {
+            self.last_prune = now;
+            let oldest_publish = now.saturating_sub(PRODUCER_IDLE_MILLIS);
+            self.producers
+                .retain(|_, producer| producer.last_publish >= oldest_publish);
+        }
+
+        if self.producers.len() >= MAX_PRODUCERS {
+            let least_recent = self
+                .producers
+                .iter()
+                .min_by_key(|(_, producer)| producer.last_publish)
+                .map(|(key, _)| *key);
+            if let Some(key) = least_recent {
+                self.producers.remove(&key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ProducerSequences, SequenceCheck, MAX_RECENT_SEQUENCES};
+    use crate::model::messages::MessageRef;
+
+    fn message_ref(message_id: u32) -> MessageRef {
+        MessageRef {
+            topic_id: 1,
+            partition_id: 2,
+            ledger_id: 3,
+            message_id,
+        }
+    }
+
+    #[test]
+    fn should_return_the_original_message_ref_for_recent_sequence_numbers() {
+        let mut sequences = ProducerSequences::new();
+        assert!(matches!(sequences.check(1, 2, 7, 1), SequenceCheck::New));
+        sequences.remember(7, 1, message_ref(10));
+        sequences.remember(7, 2, message_ref(11));
+
+        match sequences.check(1, 2, 7, 1) {
+            SequenceCheck::Published(original) => assert_eq!(original.message_id, 10),
+            _ => panic!("Sequence number 1 was already published"),
+        }
+        assert!(matches!(sequences.check(1, 2, 7, 3), SequenceCheck::New));
+
+        // Sequences are tracked separately for each producer and partition
+        assert!(matches!(sequences.check(1, 2, 8, 1), SequenceCheck::New));
+        assert!(matches!(sequences.check(1, 3, 7, 1), SequenceCheck::New));
+    }
+
+    #[test]
+    fn should_reject_sequence_numbers_that_are_no_longer_remembered() {
+        let mut sequences = ProducerSequences::new();
+        for sequence_number in 0..=MAX_RECENT_SEQUENCES as u64 {
+            sequences.remember(7, sequence_number, message_ref(sequence_number as u32));
+        }
+        assert!(matches!(sequences.check(1, 2, 7, 0), SequenceCheck::Stale));
+        assert!(matches!(
+            sequences.check(1, 2, 7, 1),
+            SequenceCheck::Published(_)
+        ));
+    }
+}
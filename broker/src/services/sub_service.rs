@@ -3,25 +3,89 @@ Provides the functionallity needed to support message subscribers. Various APIs
 be layered on top of this service to expose this funtionallity to applicatins.
 */
 
-use std::sync::Arc;
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+    time::Duration,
+};
 
-use pulsar_rust_net::data_types::{ConsumerId, MessageCount, SubscriptionId, TopicId};
+use log::{error, warn};
+use pulsar_rust_net::{
+    bin_serialization::CompressionScheme,
+    contracts::v1::responses,
+    data_types::{
+        ByteCount, ConsumerId, MessageCount, PartitionId, SubscriptionId, Timestamp, TopicId,
+    },
+};
 
 use crate::{
     model::{
         cluster::Cluster,
+        ledger::LedgerRef,
         messages::{MessageRef, PublishedMessage, SubscribedMessage},
-        node::NodeList,
-        subscription::SubscriptionRef,
-        topic::{TopicList, TopicRef},
+        node::{NodeList, NodeRef},
+        partition::PartitionRef,
+        subscription::{ConnectError, SubscriptionRef},
+        topic::{TopicList, TopicRef, TopicSubscriptionStats},
+    },
+    persistence::{
+        event_logger::EventQueryOptions, log_entries::LoggedEvent, logged_events,
+        persisted_entities::DeliverySemantics, PersistenceLayer,
     },
-    persistence::{log_entries::LoggedEvent, logged_events, PersistenceLayer},
+    services::pub_service::PubService,
+    utils::now_epoc_millis,
 };
 
+use ack_batcher::{AckBatcher, PendingAck};
+use ack_timeouts::AckTimeouts;
+use checkpoints::Checkpoints;
+use consumer_groups::ConsumerGroups;
+use dead_letters::DeadLetters;
+use message_expiry::MessageExpiry;
+use quarantine::Quarantine;
+
+mod ack_batcher;
+mod ack_timeouts;
+mod checkpoints;
+mod consumer_groups;
+mod dead_letters;
+mod message_expiry;
+mod quarantine;
+
+pub use dead_letters::DEAD_LETTER_SOURCE_ATTRIBUTE;
+
 // Max wire size for bin serialization is 32 kbytes, and messages are
 // limited to 512 bytes each.
 const MAX_MESSAGE_COUNT: MessageCount = 50;
 
+// Space in a response frame for the response header and the fields of the consume
+// result other than the messages.
+const RESPONSE_FRAME_RESERVE: usize = 1024;
+
+/// The frame that a consume response is sent to the consumer in
+#[derive(Clone, Copy)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct ResponseFrame {
+    /// The maximum length of a serialized response on the consumer's connection
+    pub max_size: usize,
+
+    /// How the body of the response is compressed on the consumer's connection
+    pub compression: CompressionScheme,
+}
+
+/// Defines what happens to a message that can not be serialized into a consume response
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub enum SerializationErrorPolicy {
+    /// Logs an error and puts the message back in the subscription, so that it can be
+    /// delivered to a consumer that can receive it, or investigated
+    Skip,
+
+    /// Logs a warning and removes the message from the subscription as if it was acked
+    Discard,
+}
+
 pub enum SubError {
     Error(String),
     TopicNotFound,
@@ -31,26 +95,95 @@ pub enum SubError {
     MessageNotFound,
     NoneAvailable,
     FailedToAllocateConsumerId,
+    TooManyConsumers,
+    NodeNotFound,
+    WrongNode(NodeRef),
+}
+
+impl From<ConnectError> for SubError {
+    fn from(err: ConnectError) -> Self {
+        match err {
+            ConnectError::FailedToAllocateConsumerId => SubError::FailedToAllocateConsumerId,
+            ConnectError::TooManyConsumers => SubError::TooManyConsumers,
+        }
+    }
+}
+
+/// A consumer that joined a group, and the subscription that the group consumes from
+pub struct GroupMember {
+    pub subscription_id: SubscriptionId,
+    pub consumer_id: ConsumerId,
 }
 
 pub struct NextMessage {
     pub subscribed_message: SubscribedMessage,
     pub published_message: PublishedMessage,
+
+    /// No later message had been published to the ledger when this message was delivered
+    pub last_in_ledger: bool,
+}
+
+/// A partition of the topic that is owned by another node. Messages from this partition
+/// can only be consumed by connecting to the owning node.
+pub struct RemotePartition {
+    pub topic_id: TopicId,
+    pub partition_id: PartitionId,
+    pub node: NodeRef,
+}
+
+/// A message that a consumer removed from its subscription because it could not be processed
+#[derive(Clone)]
+pub struct QuarantinedMessage {
+    pub message: PublishedMessage,
+    pub subscription_id: SubscriptionId,
+    pub consumer_id: ConsumerId,
+    pub reason: String,
+    pub quarantined: Timestamp,
+}
+
+/// The messages that were queued again when a subscription was moved to an earlier time
+pub struct SubscriptionSeek {
+    /// The number of messages in the subscription queue after the seek
+    pub queued_count: usize,
+
+    /// The time that the first queued message was published. This is later than the time
+    /// sought if older messages are no longer available
+    pub earliest_published: Option<Timestamp>,
 }
 
 pub struct ConsumedMessages {
     pub consumer_id: ConsumerId,
     pub messages: Vec<NextMessage>,
+    pub remote_partitions: Vec<RemotePartition>,
 }
 
 pub type NextMessageResult = Result<NextMessage, SubError>;
 pub type ConsumeResult = Result<ConsumedMessages, SubError>;
 pub type AckResult = Result<bool, SubError>;
 pub type NackResult = Result<bool, SubError>;
+pub type QuarantineResult = Result<bool, SubError>;
+pub type JoinGroupResult = Result<GroupMember, SubError>;
+pub type LeaveGroupResult = Result<bool, SubError>;
+pub type DisconnectResult = Result<(), SubError>;
+pub type GetMessageResult = Result<PublishedMessage, SubError>;
+pub type SubscriptionStatsResult = Result<TopicSubscriptionStats, SubError>;
+pub type TopicSubscriptionStatsResult = Result<Vec<TopicSubscriptionStats>, SubError>;
+pub type SeekResult = Result<SubscriptionSeek, SubError>;
 
 pub struct SubService {
     persistence: Arc<PersistenceLayer>,
     cluster: Arc<Cluster>,
+    ack_batcher: Option<Arc<AckBatcher>>,
+    ack_thread: Mutex<Option<JoinHandle<()>>>,
+    ack_timeouts: Option<Arc<AckTimeouts>>,
+    ack_timeout_thread: Mutex<Option<JoinHandle<()>>>,
+    message_expiry: Option<Arc<MessageExpiry>>,
+    message_expiry_thread: Mutex<Option<JoinHandle<()>>>,
+    serialization_error_policy: SerializationErrorPolicy,
+    consumer_groups: ConsumerGroups,
+    checkpoints: Checkpoints,
+    quarantine: Quarantine,
+    dead_letters: Option<DeadLetters>,
 }
 
 impl SubService {
@@ -58,6 +191,110 @@ impl SubService {
         Self {
             persistence: Arc::clone(persistence),
             cluster: Arc::clone(cluster),
+            ack_batcher: None,
+            ack_thread: Mutex::new(None),
+            ack_timeouts: None,
+            ack_timeout_thread: Mutex::new(None),
+            message_expiry: None,
+            message_expiry_thread: Mutex::new(None),
+            serialization_error_policy: SerializationErrorPolicy::Skip,
+            consumer_groups: ConsumerGroups::new(cluster),
+            checkpoints: Checkpoints::new(),
+            quarantine: Quarantine::new(),
+            dead_letters: None,
+        }
+    }
+
+    /// Constructs a service that accumulates acks and applies them in batches each time
+    /// the batch window elapses. Acks that have not been applied yet when the broker
+    /// stops are lost, and the corresponding messages will be redelivered.
+    pub fn with_ack_batching(
+        persistence: &Arc<PersistenceLayer>,
+        cluster: &Arc<Cluster>,
+        batch_window: Duration,
+    ) -> Self {
+        let ack_batcher = Arc::new(AckBatcher::new(persistence, cluster));
+        let ack_thread = ack_batcher.start(batch_window);
+        Self {
+            persistence: Arc::clone(persistence),
+            cluster: Arc::clone(cluster),
+            ack_batcher: Some(ack_batcher),
+            ack_thread: Mutex::new(Some(ack_thread)),
+            ack_timeouts: None,
+            ack_timeout_thread: Mutex::new(None),
+            message_expiry: None,
+            message_expiry_thread: Mutex::new(None),
+            serialization_error_policy: SerializationErrorPolicy::Skip,
+            consumer_groups: ConsumerGroups::new(cluster),
+            checkpoints: Checkpoints::new(),
+            quarantine: Quarantine::new(),
+            dead_letters: None,
+        }
+    }
+
+    /// Changes how messages that can not be serialized are handled. One bad message does
+    /// not prevent the other messages in the batch from being consumed.
+    pub fn with_serialization_error_policy(mut self: Self, policy: SerializationErrorPolicy) -> Self {
+        self.serialization_error_policy = policy;
+        self
+    }
+
+    /// Starts a background thread that nacks messages that were delivered to a consumer and
+    /// not acked within the ack timeout of their subscription, checking each time the sweep
+    /// interval elapses. Subscriptions without an ack timeout are not affected.
+    pub fn with_ack_timeouts(mut self: Self, sweep_interval: Duration) -> Self {
+        let ack_timeouts = Arc::new(AckTimeouts::new(&self.cluster));
+        self.ack_timeout_thread = Mutex::new(Some(ack_timeouts.start(sweep_interval)));
+        self.ack_timeouts = Some(ack_timeouts);
+        self
+    }
+
+    /// Starts a background thread that removes messages from subscription queues once they
+    /// are older than the message TTL of their topic, checking each time the sweep interval
+    /// elapses. Messages that were delivered to a consumer are left for the consumer to ack
+    pub fn with_message_expiry(mut self: Self, sweep_interval: Duration) -> Self {
+        let message_expiry = Arc::new(MessageExpiry::new(&self.persistence, &self.cluster));
+        self.message_expiry_thread = Mutex::new(Some(message_expiry.start(sweep_interval)));
+        self.message_expiry = Some(message_expiry);
+        self
+    }
+
+    /// Moves messages that are about to be delivered more times than the max delivery count of
+    /// their subscription to a dead letter topic, publishing them with the pub service. Without
+    /// this, messages are redelivered regardless of the max delivery count
+    pub fn with_dead_letters(mut self: Self, pub_service: &Arc<PubService>) -> Self {
+        self.dead_letters = Some(DeadLetters::new(&self.cluster, pub_service));
+        self
+    }
+
+    /// Applies any acks that are waiting for the batch window to elapse
+    pub fn flush_acks(self: &Self) {
+        if let Some(ack_batcher) = &self.ack_batcher {
+            ack_batcher.flush();
+        }
+    }
+
+    /// Stops the background threads that apply batched acks, nack timed out messages and expire
+    /// old messages, and waits for them to finish. Call `flush_acks` first to apply acks that
+    /// are still waiting for the batch window
+    pub fn stop(self: &Self) {
+        if let Some(ack_batcher) = &self.ack_batcher {
+            ack_batcher.stop();
+        }
+        if let Some(ack_timeouts) = &self.ack_timeouts {
+            ack_timeouts.stop();
+        }
+        if let Some(message_expiry) = &self.message_expiry {
+            message_expiry.stop();
+        }
+        if let Some(ack_thread) = self.ack_thread.lock().unwrap().take() {
+            let _ = ack_thread.join();
+        }
+        if let Some(ack_timeout_thread) = self.ack_timeout_thread.lock().unwrap().take() {
+            let _ = ack_timeout_thread.join();
+        }
+        if let Some(message_expiry_thread) = self.message_expiry_thread.lock().unwrap().take() {
+            let _ = message_expiry_thread.join();
         }
     }
 
@@ -90,6 +327,44 @@ impl SubService {
         subscription_id: SubscriptionId,
         consumer_id: Option<ConsumerId>,
         max_messages: MessageCount,
+    ) -> ConsumeResult {
+        self.consume_max_bytes(topic_id, subscription_id, consumer_id, max_messages, None)
+    }
+
+    /// Consumes up to `max_messages` messages, stopping early if the next message would take
+    /// the total serialized size of the batch over `max_bytes`. The first message is always
+    /// returned if there is one, so that a consumer is never stuck behind a large message
+    pub fn consume_max_bytes(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        consumer_id: Option<ConsumerId>,
+        max_messages: MessageCount,
+        max_bytes: Option<ByteCount>,
+    ) -> ConsumeResult {
+        self.consume_into_frame(
+            topic_id,
+            subscription_id,
+            consumer_id,
+            max_messages,
+            max_bytes,
+            None,
+        )
+    }
+
+    /// Consumes like `consume_max_bytes`, for a consumer that receives the response in a frame
+    /// of limited size. The batch is cut short before it would overflow the frame, and messages
+    /// that are too large for the frame on their own are rejected by the serialization error
+    /// policy. Sizes are measured after compression, so that compressible messages larger than
+    /// the frame can still be delivered
+    pub fn consume_into_frame(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        consumer_id: Option<ConsumerId>,
+        max_messages: MessageCount,
+        max_bytes: Option<ByteCount>,
+        frame: Option<ResponseFrame>,
     ) -> ConsumeResult {
         let topic = self.cluster.topics().get(&topic_id);
         if topic.is_none() {
@@ -97,6 +372,14 @@ impl SubService {
         }
         let topic = topic.unwrap();
 
+        // If none of the partitions are owned by this node then direct the consumer elsewhere
+        let remote_partitions = self.remote_partitions(&topic);
+        if !remote_partitions.is_empty()
+            && remote_partitions.len() == topic.partitions().keys().len()
+        {
+            return Err(SubError::WrongNode(remote_partitions[0].node.clone()));
+        }
+
         let subscription = topic.subscriptions().get(&subscription_id);
         if subscription.is_none() {
             return Err(SubError::SubscriptionNotFound);
@@ -104,30 +387,82 @@ impl SubService {
         let subscription = subscription.unwrap();
 
         let consumer_id = match consumer_id {
-            Some(id) => Some(id),
-            None => subscription.connect_consumer(),
+            Some(id) => id,
+            None => subscription.connect_consumer()?,
         };
-        if consumer_id.is_none() {
-            return Err(SubError::FailedToAllocateConsumerId);
-        }
-        let consumer_id = consumer_id.unwrap();
 
         let mut messages = Vec::new();
+        let mut expired = Vec::new();
+        let mut skipped = Vec::new();
+        let mut batch_bytes = 0;
+        let mut frame_bytes = 0;
 
         let max_message_count = if max_messages > MAX_MESSAGE_COUNT { MAX_MESSAGE_COUNT } else { max_messages };
 
         for _ in 0..max_message_count {
-            match subscription.pop(consumer_id) {
+            match subscription.pop(consumer_id, &mut expired) {
                 Some(subscribed_message) => {
                     let message_ref = MessageRef::from_key(&subscribed_message.message_ref_key);
                     match topic.partitions().get(&message_ref.partition_id) {
                         Some(partition) => match partition.ledgers().get(&message_ref.ledger_id) {
                             Some(ledger) => match ledger.get_message(&message_ref.message_id) {
-                                Some(published_message) => {
+                                Some(mut published_message) => {
+                                    if self.dead_letter(
+                                        &topic,
+                                        &subscription,
+                                        &ledger,
+                                        consumer_id,
+                                        &subscribed_message,
+                                        &published_message,
+                                    ) {
+                                        continue;
+                                    }
+                                    subscription.transform(
+                                        &mut published_message,
+                                        delivered_timestamp(&subscribed_message),
+                                    );
+                                    let (size, wire_size) =
+                                        match serialized_size(&published_message, frame) {
+                                            Ok(sizes) => sizes,
+                                            Err(reason) => {
+                                                if self.reject_message(
+                                                    &topic,
+                                                    &subscription,
+                                                    &ledger,
+                                                    consumer_id,
+                                                    &subscribed_message.message_ref_key,
+                                                    &reason,
+                                                ) {
+                                                    skipped.push(subscribed_message);
+                                                }
+                                                continue;
+                                            }
+                                        };
+                                    if !messages.is_empty()
+                                        && (max_bytes.is_some_and(|max_bytes| {
+                                            batch_bytes + size > max_bytes as usize
+                                        }) || frame.is_some_and(|frame| {
+                                            frame_bytes + wire_size > frame_capacity(frame)
+                                        }))
+                                    {
+                                        subscription.unpop(consumer_id, subscribed_message);
+                                        break;
+                                    }
+                                    batch_bytes += size;
+                                    frame_bytes += wire_size;
                                     messages.push(NextMessage {
                                         subscribed_message,
+                                        last_in_ledger: ledger
+                                            .is_last_message(message_ref.message_id),
                                         published_message,
                                     });
+                                    self.ack_on_delivery(
+                                        &topic,
+                                        &subscription,
+                                        &ledger,
+                                        consumer_id,
+                                        message_ref,
+                                    );
                                 }
                                 None => {
                                     break;
@@ -148,12 +483,231 @@ impl SubService {
             }
         }
 
+        // Skipped messages are put back after the batch is complete, otherwise they would be
+        // popped again straight away. They are put back in reverse so that they keep their order
+        for subscribed_message in skipped.into_iter().rev() {
+            subscription.unpop(consumer_id, subscribed_message);
+        }
+
+        self.discard_expired(&topic, &expired);
+
         Ok(ConsumedMessages {
             consumer_id,
             messages,
+            remote_partitions,
         })
     }
 
+    /// Consumes the next batch of messages, first acking the batch that was returned by the
+    /// previous call for the same consumer. This suits consumers that process each batch
+    /// completely before polling for the next one. Delivery is at-least-once: if the consumer
+    /// stops part way through a batch, the whole batch is redelivered, including messages that
+    /// were already processed. Messages consumed with `consume_max_messages` are not affected
+    pub fn consume_and_ack_previous(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        consumer_id: Option<ConsumerId>,
+        max_messages: MessageCount,
+        max_bytes: Option<ByteCount>,
+        frame: Option<ResponseFrame>,
+    ) -> ConsumeResult {
+        if let Some(consumer_id) = consumer_id {
+            for message_ref_key in self.checkpoints.take(topic_id, subscription_id, consumer_id) {
+                if let Err(_) = self.ack(message_ref_key.clone(), subscription_id, consumer_id) {
+                    warn!("Failed to implicitly ack message {message_ref_key} for consumer {consumer_id} of subscription {subscription_id}");
+                }
+            }
+        }
+
+        let consumed_messages = self.consume_into_frame(
+            topic_id,
+            subscription_id,
+            consumer_id,
+            max_messages,
+            max_bytes,
+            frame,
+        )?;
+
+        self.checkpoints.save(
+            topic_id,
+            subscription_id,
+            consumed_messages.consumer_id,
+            consumed_messages
+                .messages
+                .iter()
+                .map(|message| message.subscribed_message.message_ref_key.clone())
+                .collect(),
+        );
+        Ok(consumed_messages)
+    }
+
+    /// Messages that were skipped because they are too old to deliver are treated as acked
+    /// by the subscription so that they can be removed from the ledger
+    fn discard_expired(self: &Self, topic: &TopicRef, expired: &[SubscribedMessage]) {
+        for message in expired {
+            PubService::discard_message(topic, message);
+        }
+    }
+
+    /// Applies the serialization error policy to a message that can not be returned to the
+    /// consumer. Returns true if the message should be put back in the subscription
+    fn reject_message(
+        self: &Self,
+        topic: &TopicRef,
+        subscription: &SubscriptionRef,
+        ledger: &LedgerRef,
+        consumer_id: ConsumerId,
+        message_ref_key: &str,
+        reason: &str,
+    ) -> bool {
+        let subscription_id = subscription.subscription_id();
+        match self.serialization_error_policy {
+            SerializationErrorPolicy::Skip => {
+                error!(
+                    "Skipped message {message_ref_key} for consumer {consumer_id} of subscription {subscription_id}. {reason}"
+                );
+                true
+            }
+            SerializationErrorPolicy::Discard => {
+                warn!(
+                    "Discarded message {message_ref_key} for consumer {consumer_id} of subscription {subscription_id}. {reason}"
+                );
+                let message_ref = MessageRef::from_key(message_ref_key);
+                if subscription.ack(consumer_id, message_ref_key) {
+                    ledger.ack(&message_ref.message_id);
+                }
+
+                // At-most-once subscriptions forgot the message when it was popped, so it is
+                // not in the subscription to ack
+                self.ack_on_delivery(topic, subscription, ledger, consumer_id, message_ref);
+                false
+            }
+        }
+    }
+
+    /// At-most-once subscriptions forget messages as soon as they are popped for delivery, so
+    /// the message is acked in the ledger and the event log straight away, and the consumer
+    /// does not need to ack it
+    fn ack_on_delivery(
+        self: &Self,
+        topic: &TopicRef,
+        subscription: &SubscriptionRef,
+        ledger: &LedgerRef,
+        consumer_id: ConsumerId,
+        message_ref: MessageRef,
+    ) {
+        if subscription.delivery_semantics() != DeliverySemantics::AtMostOnce {
+            return;
+        }
+        if !topic.is_ephemeral() {
+            let subscription_id = subscription.subscription_id();
+            let event = logged_events::AckEvent::new(message_ref, subscription_id, consumer_id);
+            let _ = self.persistence.log_event(&LoggedEvent::Ack(event));
+        }
+        ledger.ack(&message_ref.message_id);
+    }
+
+    /// The name of the topic that messages from a subscription are moved to when they reach its
+    /// max delivery count. Unless the subscription names a dead letter topic, this is the name
+    /// of the topic and subscription with a DLQ suffix
+    pub fn dead_letter_topic_name(
+        self: &Self,
+        topic: &TopicRef,
+        subscription: &SubscriptionRef,
+    ) -> String {
+        match subscription.dead_letter_topic() {
+            Some(name) => name,
+            None => format!("{}-{}-DLQ", topic.name(), subscription.name()),
+        }
+    }
+
+    /// Moves a message that was popped for delivery to the dead letter topic of its subscription
+    /// if it has now been delivered more than the max delivery count, and removes it from the
+    /// subscription as if it was acked. Returns true if the message was moved. A message that
+    /// can not be moved is delivered as usual, so that it is not lost
+    fn dead_letter(
+        self: &Self,
+        topic: &TopicRef,
+        subscription: &SubscriptionRef,
+        ledger: &LedgerRef,
+        consumer_id: ConsumerId,
+        subscribed_message: &SubscribedMessage,
+        published_message: &PublishedMessage,
+    ) -> bool {
+        let max_delivery_count = subscription.max_delivery_count();
+        if max_delivery_count == 0 || subscribed_message.delivery_count <= max_delivery_count {
+            return false;
+        }
+        let dead_letters = match &self.dead_letters {
+            Some(dead_letters) => dead_letters,
+            None => return false,
+        };
+
+        let message_ref_key = &subscribed_message.message_ref_key;
+        let subscription_id = subscription.subscription_id();
+        let topic_name = self.dead_letter_topic_name(topic, subscription);
+        let dead_letter_ref = match dead_letters.publish(
+            &topic_name,
+            &subscription.name(),
+            published_message,
+        ) {
+            Ok(dead_letter_ref) => dead_letter_ref,
+            Err(msg) => {
+                error!("Failed to move message {message_ref_key} of subscription {subscription_id} to dead letter topic {topic_name}. {msg}");
+                return false;
+            }
+        };
+
+        if subscription.ack(consumer_id, message_ref_key) {
+            let message_ref = MessageRef::from_key(message_ref_key);
+            if !topic.is_ephemeral() {
+                let _ = self.persistence.log_event(&LoggedEvent::DeadLetter(
+                    logged_events::DeadLetterEvent::new(
+                        message_ref,
+                        subscription_id,
+                        consumer_id,
+                        dead_letter_ref,
+                    ),
+                ));
+            }
+            ledger.ack(&message_ref.message_id);
+        }
+        warn!("Moved message {message_ref_key} of subscription {subscription_id} to dead letter topic {topic_name} as {} after {max_delivery_count} deliveries", dead_letter_ref.to_key());
+        true
+    }
+
+    /// Returns an error identifying the owning node if this node does not own the partition
+    fn check_partition_owner(self: &Self, partition: &PartitionRef) -> Result<(), SubError> {
+        let node_id = partition.node_id();
+        if node_id == self.cluster.my_node_id() {
+            return Ok(());
+        }
+        match self.cluster.nodes().get(&node_id) {
+            Some(node) => Err(SubError::WrongNode(node)),
+            None => Err(SubError::NodeNotFound),
+        }
+    }
+
+    /// Returns the partitions of a topic that are owned by other nodes in the cluster, so that
+    /// multi-node aware clients know where to send consume requests for these partitions
+    pub fn remote_partitions(self: &Self, topic: &TopicRef) -> Vec<RemotePartition> {
+        let my_node_id = self.cluster.my_node_id();
+        topic
+            .partitions()
+            .values()
+            .iter()
+            .filter(|partition| partition.node_id() != my_node_id)
+            .filter_map(|partition| {
+                Some(RemotePartition {
+                    topic_id: partition.topic_id(),
+                    partition_id: partition.partition_id(),
+                    node: self.cluster.nodes().get(&partition.node_id())?,
+                })
+            })
+            .collect()
+    }
+
     pub fn next_message(
         self: &Self,
         topic_id: TopicId,
@@ -162,29 +716,64 @@ impl SubService {
     ) -> NextMessageResult {
         match self.cluster.topics().get(&topic_id) {
             Some(topic) => match topic.subscriptions().get(&subscription_id) {
-                Some(subscription) => match subscription.pop(consumer_id) {
-                    Some(subscribed_message) => {
-                        let message_ref = MessageRef::from_key(&subscribed_message.message_ref_key);
-                        match topic.partitions().get(&message_ref.partition_id) {
-                            Some(partition) => {
-                                match partition.ledgers().get(&message_ref.ledger_id) {
-                                    Some(ledger) => {
-                                        match ledger.get_message(&message_ref.message_id) {
-                                            Some(published_message) => Ok(NextMessage {
-                                                subscribed_message,
-                                                published_message,
-                                            }),
-                                            None => Err(SubError::LedgerNotFound),
+                Some(subscription) => {
+                    let mut expired = Vec::new();
+                    let next = subscription.pop(consumer_id, &mut expired);
+                    self.discard_expired(&topic, &expired);
+                    match next {
+                        Some(subscribed_message) => {
+                            let message_ref = MessageRef::from_key(&subscribed_message.message_ref_key);
+                            match topic.partitions().get(&message_ref.partition_id) {
+                                Some(partition) => {
+                                    match partition.ledgers().get(&message_ref.ledger_id) {
+                                        Some(ledger) => {
+                                            match ledger.get_message(&message_ref.message_id) {
+                                                Some(mut published_message) => {
+                                                    if self.dead_letter(
+                                                        &topic,
+                                                        &subscription,
+                                                        &ledger,
+                                                        consumer_id,
+                                                        &subscribed_message,
+                                                        &published_message,
+                                                    ) {
+                                                        return self.next_message(
+                                                            topic_id,
+                                                            subscription_id,
+                                                            consumer_id,
+                                                        );
+                                                    }
+                                                    subscription.transform(
+                                                        &mut published_message,
+                                                        delivered_timestamp(&subscribed_message),
+                                                    );
+                                                    let last_in_ledger = ledger
+                                                        .is_last_message(message_ref.message_id);
+                                                    self.ack_on_delivery(
+                                                        &topic,
+                                                        &subscription,
+                                                        &ledger,
+                                                        consumer_id,
+                                                        message_ref,
+                                                    );
+                                                    Ok(NextMessage {
+                                                        subscribed_message,
+                                                        last_in_ledger,
+                                                        published_message,
+                                                    })
+                                                }
+                                                None => Err(SubError::LedgerNotFound),
+                                            }
                                         }
+                                        None => Err(SubError::LedgerNotFound),
                                     }
-                                    None => Err(SubError::LedgerNotFound),
                                 }
+                                None => Err(SubError::PartitionNotFound),
                             }
-                            None => Err(SubError::PartitionNotFound),
                         }
+                        None => Err(SubError::NoneAvailable),
                     }
-                    None => Err(SubError::NoneAvailable),
-                },
+                }
                 None => Err(SubError::SubscriptionNotFound),
             },
             None => Err(SubError::TopicNotFound),
@@ -201,17 +790,39 @@ impl SubService {
         match self.cluster.topics().get(&message_ref.topic_id) {
             Some(topic) => match topic.subscriptions().get(&subscription_id) {
                 Some(subscription) => match topic.partitions().get(&message_ref.partition_id) {
-                    Some(partition) => match partition.ledgers().get(&message_ref.ledger_id) {
-                        Some(ledger) => {
-                            if subscription.ack(consumer_id, &message_ref_key) {
-                                ledger.ack(&message_ref.message_id);
-                                Ok(true)
-                            } else {
-                                Ok(false)
+                    Some(partition) => {
+                        self.check_partition_owner(&partition)?;
+                        match partition.ledgers().get(&message_ref.ledger_id) {
+                            Some(ledger) => {
+                                if let Some(ack_batcher) = &self.ack_batcher {
+                                    // Only acks for messages that are waiting for one are queued,
+                                    // so that unknown and duplicate acks are still reported
+                                    Ok(subscription.is_delivered(&message_ref_key)
+                                        && ack_batcher.queue(PendingAck {
+                                            message_ref,
+                                            message_ref_key,
+                                            subscription_id,
+                                            consumer_id,
+                                        }))
+                                } else if subscription.ack(consumer_id, &message_ref_key) {
+                                    if !topic.is_ephemeral() {
+                                        let _ = self.persistence.log_event(&LoggedEvent::Ack(
+                                            logged_events::AckEvent::new(
+                                                message_ref,
+                                                subscription_id,
+                                                consumer_id,
+                                            ),
+                                        ));
+                                    }
+                                    ledger.ack(&message_ref.message_id);
+                                    Ok(true)
+                                } else {
+                                    Ok(false)
+                                }
                             }
+                            None => Err(SubError::LedgerNotFound),
                         }
-                        None => Err(SubError::LedgerNotFound),
-                    },
+                    }
                     None => Err(SubError::PartitionNotFound),
                 },
                 None => Err(SubError::SubscriptionNotFound),
@@ -230,13 +841,24 @@ impl SubService {
         match self.cluster.topics().get(&message_ref.topic_id) {
             Some(topic) => match topic.subscriptions().get(&subscription_id) {
                 Some(subscription) => {
-                    let _ =
-                        self.persistence
-                            .log_event(&LoggedEvent::Nack(logged_events::NackEvent {
+                    match topic.partitions().get(&message_ref.partition_id) {
+                        Some(partition) => self.check_partition_owner(&partition)?,
+                        None => return Err(SubError::PartitionNotFound),
+                    }
+
+                    // Pending acks must be applied first, otherwise a message that was
+                    // acked and then nacked would be redelivered
+                    self.flush_acks();
+
+                    if !topic.is_ephemeral() {
+                        let _ = self.persistence.log_event(&LoggedEvent::Nack(
+                            logged_events::NackEvent {
                                 message_ref,
                                 subscription_id,
                                 consumer_id,
-                            }));
+                            },
+                        ));
+                    }
                     Ok(subscription.nack(consumer_id, &message_ref_key))
                 }
                 None => Err(SubError::SubscriptionNotFound),
@@ -244,4 +866,406 @@ impl SubService {
             None => Err(SubError::TopicNotFound),
         }
     }
+
+    /// Removes a message that was delivered to the consumer from the subscription without
+    /// redelivering it, and keeps it in quarantine with the reason. Consumers use this for
+    /// messages that they can see are malformed, and will never be able to process
+    pub fn quarantine(
+        self: &Self,
+        message_ref_key: String,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+        reason: &str,
+    ) -> QuarantineResult {
+        let message_ref = MessageRef::try_from_key(&message_ref_key)
+            .ok_or_else(|| SubError::Error(format!("Invalid message ref key {message_ref_key}")))?;
+        let topic = self
+            .cluster
+            .topics()
+            .get(&message_ref.topic_id)
+            .ok_or(SubError::TopicNotFound)?;
+        let subscription = topic
+            .subscriptions()
+            .get(&subscription_id)
+            .ok_or(SubError::SubscriptionNotFound)?;
+        let partition = topic
+            .partitions()
+            .get(&message_ref.partition_id)
+            .ok_or(SubError::PartitionNotFound)?;
+        self.check_partition_owner(&partition)?;
+        let ledger = partition
+            .ledgers()
+            .get(&message_ref.ledger_id)
+            .ok_or(SubError::LedgerNotFound)?;
+
+        // Pending acks must be applied first, otherwise a message that was acked
+        // and then quarantined would be in quarantine as well as acked
+        self.flush_acks();
+
+        let message = ledger
+            .peek_message(message_ref.message_id)
+            .ok_or(SubError::MessageNotFound)?;
+
+        if !subscription.ack(consumer_id, &message_ref_key) {
+            return Ok(false);
+        }
+        if !topic.is_ephemeral() {
+            let _ = self.persistence.log_event(&LoggedEvent::Quarantine(
+                logged_events::QuarantineEvent::new(
+                    message_ref,
+                    subscription_id,
+                    consumer_id,
+                    reason,
+                ),
+            ));
+        }
+        ledger.ack(&message_ref.message_id);
+
+        warn!("Consumer {consumer_id} of subscription {subscription_id} quarantined message {message_ref_key}. {reason}");
+        self.quarantine.add(QuarantinedMessage {
+            message,
+            subscription_id,
+            consumer_id,
+            reason: reason.to_owned(),
+            quarantined: now_epoc_millis(),
+        });
+        Ok(true)
+    }
+
+    /// Returns the messages that consumers of a subscription have quarantined, oldest first
+    pub fn quarantined_messages(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+    ) -> Vec<QuarantinedMessage> {
+        self.quarantine.list(topic_id, subscription_id)
+    }
+
+    /// The number of messages in each state for one subscription
+    pub fn subscription_stats(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+    ) -> SubscriptionStatsResult {
+        let topic = self
+            .cluster
+            .topics()
+            .get(&topic_id)
+            .ok_or(SubError::TopicNotFound)?;
+        let subscription = topic
+            .subscriptions()
+            .get(&subscription_id)
+            .ok_or(SubError::SubscriptionNotFound)?;
+        Ok(TopicSubscriptionStats {
+            subscription_id,
+            subscription_stats: subscription.stats(),
+        })
+    }
+
+    /// The number of messages in each state for every subscription to a topic
+    pub fn topic_subscription_stats(
+        self: &Self,
+        topic_id: TopicId,
+    ) -> TopicSubscriptionStatsResult {
+        let topic = self
+            .cluster
+            .topics()
+            .get(&topic_id)
+            .ok_or(SubError::TopicNotFound)?;
+        Ok(topic.stats().subscriptions)
+    }
+
+    /// Looks up a message in the ledger by its ack key. This does not count as a delivery
+    /// and has no effect on the message in any subscription
+    pub fn get_message(self: &Self, message_ref_key: &str) -> GetMessageResult {
+        let message_ref = MessageRef::try_from_key(message_ref_key)
+            .ok_or_else(|| SubError::Error(format!("Invalid message ref key {message_ref_key}")))?;
+        let topic = self
+            .cluster
+            .topics()
+            .get(&message_ref.topic_id)
+            .ok_or(SubError::TopicNotFound)?;
+        let partition = topic
+            .partitions()
+            .get(&message_ref.partition_id)
+            .ok_or(SubError::PartitionNotFound)?;
+        self.check_partition_owner(&partition)?;
+        let ledger = partition
+            .ledgers()
+            .get(&message_ref.ledger_id)
+            .ok_or(SubError::LedgerNotFound)?;
+        ledger
+            .peek_message(message_ref.message_id)
+            .ok_or(SubError::MessageNotFound)
+    }
+
+    /// Allocates a new consumer and adds it to a named group. Each group has its own
+    /// subscription, which is created the first time that a consumer joins the group, and
+    /// receives every message published to the topic after that. The members of a group share
+    /// the messages of the group's subscription, with each message delivered to only one member
+    pub fn join_group(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        group_name: &str,
+    ) -> JoinGroupResult {
+        let topic = self
+            .cluster
+            .topics()
+            .get(&topic_id)
+            .ok_or(SubError::TopicNotFound)?;
+        let remote_partitions = self.remote_partitions(&topic);
+        if !remote_partitions.is_empty()
+            && remote_partitions.len() == topic.partitions().keys().len()
+        {
+            return Err(SubError::WrongNode(remote_partitions[0].node.clone()));
+        }
+        let subscription = topic
+            .subscriptions()
+            .get(&subscription_id)
+            .ok_or(SubError::SubscriptionNotFound)?;
+        let (group_subscription, consumer_id) =
+            self.consumer_groups.join(&topic, &subscription, group_name)?;
+
+        Ok(GroupMember {
+            subscription_id: group_subscription.subscription_id(),
+            consumer_id,
+        })
+    }
+
+    /// Removes a consumer from its group and disconnects it from the group's subscription.
+    /// Messages that were delivered to this consumer but not acked are redelivered to the
+    /// remaining members of the group
+    pub fn leave_group(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+    ) -> LeaveGroupResult {
+        let topic = self
+            .cluster
+            .topics()
+            .get(&topic_id)
+            .ok_or(SubError::TopicNotFound)?;
+        let subscription = topic
+            .subscriptions()
+            .get(&subscription_id)
+            .ok_or(SubError::SubscriptionNotFound)?;
+
+        if !self
+            .consumer_groups
+            .leave(topic_id, subscription_id, consumer_id)
+        {
+            return Ok(false);
+        }
+        self.drop_consumer(&topic, &subscription, consumer_id);
+        Ok(true)
+    }
+
+    /// Disconnects a consumer that is shutting down, and removes it from its group if it is
+    /// a member of one. Messages that were delivered or assigned to the consumer but not acked
+    /// are redelivered to the remaining consumers of the subscription
+    pub fn disconnect_consumer(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+    ) -> DisconnectResult {
+        let topic = self
+            .cluster
+            .topics()
+            .get(&topic_id)
+            .ok_or(SubError::TopicNotFound)?;
+        let subscription = topic
+            .subscriptions()
+            .get(&subscription_id)
+            .ok_or(SubError::SubscriptionNotFound)?;
+
+        self.consumer_groups
+            .leave(topic_id, subscription_id, consumer_id);
+        self.drop_consumer(&topic, &subscription, consumer_id);
+        Ok(())
+    }
+
+    fn drop_consumer(
+        self: &Self,
+        topic: &TopicRef,
+        subscription: &SubscriptionRef,
+        consumer_id: ConsumerId,
+    ) {
+        let topic_id = topic.topic_id();
+        let subscription_id = subscription.subscription_id();
+        subscription.disconnect_consumer(consumer_id);
+        self.checkpoints.take(topic_id, subscription_id, consumer_id);
+    }
+
+    /// Moves a subscription back (or forward) to a point in time, so that every message
+    /// published to the topic since then is delivered again. The queues of the subscription are
+    /// replaced with the messages that the event log shows as published at or after
+    /// `to_timestamp`, in the order that they were published. Messages that expired under the topic's TTL are not
+    /// queued, and seeking to a time before the oldest logged message queues all of them.
+    /// Messages that were delivered and not acked are reset, so acks from their consumers are
+    /// ignored and they are delivered again. The seek is not logged, so if the broker restarts,
+    /// messages that were acked before the seek are not restored again
+    pub fn seek(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        to_timestamp: Timestamp,
+    ) -> SeekResult {
+        let topic = self
+            .cluster
+            .topics()
+            .get(&topic_id)
+            .ok_or(SubError::TopicNotFound)?;
+        let subscription = topic
+            .subscriptions()
+            .get(&subscription_id)
+            .ok_or(SubError::SubscriptionNotFound)?;
+        if topic.is_ephemeral() {
+            return Err(SubError::Error(
+                "Ephemeral topics have no event log to seek in".to_owned(),
+            ));
+        }
+
+        let oldest_published = match topic.message_ttl_millis() {
+            0 => to_timestamp,
+            ttl => to_timestamp.max(now_epoc_millis().saturating_sub(ttl)),
+        };
+
+        // Find the published messages that are still in a ledger on this node, or can be put
+        // back into one
+        let _ = self.persistence.flush_events();
+        let my_node_id = self.cluster.my_node_id();
+        let key_prefix = PersistenceLayer::build_topic_prefix(topic_id);
+        let options = EventQueryOptions::replay();
+        let mut published: Vec<(LedgerRef, PublishedMessage)> = Vec::new();
+        for log_entry in self.persistence.events_by_key_prefix(&key_prefix, &options) {
+            let message = match log_entry.deserialize() {
+                Some(LoggedEvent::Publish(event)) => event.message,
+                _ => continue,
+            };
+            if message.published < oldest_published {
+                continue;
+            }
+            let message_ref = message.message_ref;
+            let ledger = topic
+                .partitions()
+                .get(&message_ref.partition_id)
+                .filter(|partition| partition.node_id() == my_node_id)
+                .and_then(|partition| partition.ledgers().get(&message_ref.ledger_id));
+            if let Some(ledger) = ledger {
+                published.push((ledger, message));
+            }
+        }
+
+        let backlog = published
+            .iter()
+            .map(|(_, message)| SubscribedMessage::from(message))
+            .collect();
+        let held = subscription.reset(backlog);
+        self.checkpoints.clear(topic_id, subscription_id);
+
+        // Messages that the subscription already held are still in their ledger for it, the
+        // others need to be kept in their ledger until the subscription acks them again
+        let held_keys: HashSet<String> = held
+            .iter()
+            .map(|message| message.message_ref_key.clone())
+            .collect();
+        let mut queued_keys: HashSet<String> = HashSet::new();
+        for (ledger, message) in &published {
+            let message_ref_key = message.message_ref.to_key();
+            if !held_keys.contains(&message_ref_key) {
+                ledger.retain_message(message);
+            }
+            queued_keys.insert(message_ref_key);
+        }
+
+        // Messages that the subscription held and were not queued again are finished with,
+        // unless they were published after the event log was read
+        let mut queued_count = published.len();
+        for message in held {
+            if queued_keys.contains(&message.message_ref_key) {
+                continue;
+            }
+            let message_ref = MessageRef::from_key(&message.message_ref_key);
+            let ledger = match topic
+                .partitions()
+                .get(&message_ref.partition_id)
+                .and_then(|partition| partition.ledgers().get(&message_ref.ledger_id))
+            {
+                Some(ledger) => ledger,
+                None => continue,
+            };
+            if message.published >= oldest_published {
+                subscription.restore(SubscribedMessage::new(
+                    &message.message_ref_key,
+                    &message.key,
+                    message.published,
+                    message.priority,
+                ));
+                queued_count += 1;
+            } else {
+                let _ = self.persistence.log_event(&LoggedEvent::AdminAck(
+                    logged_events::AdminAckEvent::new(message_ref, subscription_id),
+                ));
+                ledger.ack(&message_ref.message_id);
+            }
+        }
+
+        Ok(SubscriptionSeek {
+            queued_count,
+            earliest_published: published.first().map(|(_, message)| message.published),
+        })
+    }
+
+    /// Returns the ids of the consumers that are currently members of a group that was joined
+    /// through the subscription
+    pub fn group_members(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        group_name: &str,
+    ) -> Vec<ConsumerId> {
+        self.consumer_groups
+            .members(topic_id, subscription_id, group_name)
+    }
+}
+
+impl Drop for SubService {
+    fn drop(self: &mut Self) {
+        self.stop();
+    }
+}
+
+/// The time that a message was delivered to the consumer, for use in delivery transforms
+fn delivered_timestamp(message: &SubscribedMessage) -> Timestamp {
+    message.delivered_timestamp.unwrap_or_else(now_epoc_millis)
+}
+
+/// The space in a response frame that is available for messages
+fn frame_capacity(frame: ResponseFrame) -> usize {
+    frame.max_size.saturating_sub(RESPONSE_FRAME_RESERVE)
+}
+
+/// Returns the size of a message when it is serialized into a consume response, and the size
+/// that it takes up in the response frame once compressed, checking that it is not so large
+/// that a response containing only this message would be too large to send
+fn serialized_size(
+    message: &PublishedMessage,
+    frame: Option<ResponseFrame>,
+) -> Result<(usize, usize), String> {
+    let buffer =
+        rmp_serde::to_vec(&responses::Message::from(message)).map_err(|err| format!("{err}"))?;
+    let Some(frame) = frame else {
+        return Ok((buffer.len(), buffer.len()));
+    };
+    let wire_size = frame.compression.compressed_size(&buffer);
+    if wire_size > frame_capacity(frame) {
+        return Err(format!(
+            "Serialized size of {wire_size} bytes exceeds the limit of {} bytes",
+            frame_capacity(frame)
+        ));
+    }
+    Ok((buffer.len(), wire_size))
 }
@@ -0,0 +1,138 @@
+/*
+Accumulates message acks and applies them to subscriptions and ledgers in batches. This
+reduces lock contention on the subscriptions when consumers ack at a high rate. Ack events
+are only written to the event log when the ack is applied, so any acks that were pending
+when the broker stopped are not replayed, and these messages will be redelivered.
+*/
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use pulsar_rust_net::data_types::{ConsumerId, SubscriptionId, TopicId};
+
+use crate::{
+    model::{cluster::Cluster, messages::MessageRef},
+    persistence::{log_entries::LoggedEvent, logged_events::AckEvent, PersistenceLayer},
+    utils::StopSignal,
+};
+
+pub(super) struct PendingAck {
+    pub message_ref: MessageRef,
+    pub message_ref_key: String,
+    pub subscription_id: SubscriptionId,
+    pub consumer_id: ConsumerId,
+}
+
+// The acks waiting to be applied, with the subscription and message ref key of each one so
+// that a message can only be acked once per batch
+#[derive(Default)]
+struct PendingAcks {
+    acks: Vec<PendingAck>,
+    keys: HashSet<(SubscriptionId, String)>,
+}
+
+pub(super) struct AckBatcher {
+    persistence: Arc<PersistenceLayer>,
+    cluster: Arc<Cluster>,
+    pending: Mutex<PendingAcks>,
+    flushing: Mutex<()>,
+    stop_signal: StopSignal,
+}
+
+impl AckBatcher {
+    pub(super) fn new(persistence: &Arc<PersistenceLayer>, cluster: &Arc<Cluster>) -> Self {
+        Self {
+            persistence: Arc::clone(persistence),
+            cluster: Arc::clone(cluster),
+            pending: Mutex::new(PendingAcks::default()),
+            flushing: Mutex::new(()),
+            stop_signal: StopSignal::new(),
+        }
+    }
+
+    /// Starts a thread that applies pending acks each time the batch window elapses
+    pub(super) fn start(self: &Arc<Self>, window: Duration) -> JoinHandle<()> {
+        let batcher = Arc::clone(self);
+        thread::spawn(move || {
+            while !batcher.stop_signal.wait(window) {
+                batcher.flush();
+            }
+            batcher.flush();
+        })
+    }
+
+    pub(super) fn stop(self: &Self) {
+        self.stop_signal.stop();
+    }
+
+    /// Queues an ack to be applied in the next batch. Returns false if the message is already
+    /// waiting to be acked for the same subscription
+    pub(super) fn queue(self: &Self, ack: PendingAck) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        if !pending
+            .keys
+            .insert((ack.subscription_id, ack.message_ref_key.clone()))
+        {
+            return false;
+        }
+        pending.acks.push(ack);
+        true
+    }
+
+    /// Applies all of the pending acks, taking the locks on each subscription once. Flushes are
+    /// serialized so that when this returns, acks taken by a concurrent flush are also applied
+    pub(super) fn flush(self: &Self) {
+        let _flushing = self.flushing.lock().unwrap();
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap()).acks;
+        if pending.is_empty() {
+            return;
+        }
+
+        let mut batches: HashMap<(TopicId, SubscriptionId), Vec<PendingAck>> = HashMap::new();
+        for ack in pending {
+            batches
+                .entry((ack.message_ref.topic_id, ack.subscription_id))
+                .or_default()
+                .push(ack);
+        }
+
+        for ((topic_id, subscription_id), acks) in batches {
+            let topic = match self.cluster.topics().get(&topic_id) {
+                Some(topic) => topic,
+                None => continue,
+            };
+            let subscription = match topic.subscriptions().get(&subscription_id) {
+                Some(subscription) => subscription,
+                None => continue,
+            };
+
+            let keys: Vec<(ConsumerId, String)> = acks
+                .iter()
+                .map(|ack| (ack.consumer_id, ack.message_ref_key.clone()))
+                .collect();
+            let acked_keys: HashSet<String> = subscription.ack_batch(&keys).into_iter().collect();
+
+            for ack in acks
+                .iter()
+                .filter(|ack| acked_keys.contains(&ack.message_ref_key))
+            {
+                if !topic.is_ephemeral() {
+                    let _ = self.persistence.log_event(&LoggedEvent::Ack(AckEvent::new(
+                        ack.message_ref,
+                        ack.subscription_id,
+                        ack.consumer_id,
+                    )));
+                }
+                if let Some(partition) = topic.partitions().get(&ack.message_ref.partition_id) {
+                    if let Some(ledger) = partition.ledgers().get(&ack.message_ref.ledger_id) {
+                        ledger.ack(&ack.message_ref.message_id);
+                    }
+                }
+            }
+        }
+    }
+}
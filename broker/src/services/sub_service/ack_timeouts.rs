@@ -0,0 +1,53 @@
+/*
+Periodically sweeps every subscription for messages that were delivered to a consumer and not
+acked within the ack timeout of the subscription. These messages are nacked so that they are
+redelivered, otherwise a consumer that stops processing without disconnecting would hold its
+messages forever. Subscriptions with an ack timeout of zero are skipped.
+*/
+
+use std::{
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{model::cluster::Cluster, utils::StopSignal};
+
+pub(super) struct AckTimeouts {
+    cluster: Arc<Cluster>,
+    stop_signal: StopSignal,
+}
+
+impl AckTimeouts {
+    pub(super) fn new(cluster: &Arc<Cluster>) -> Self {
+        Self {
+            cluster: Arc::clone(cluster),
+            stop_signal: StopSignal::new(),
+        }
+    }
+
+    /// Starts a thread that sweeps the subscriptions each time the interval elapses
+    pub(super) fn start(self: &Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        let ack_timeouts = Arc::clone(self);
+        thread::spawn(move || {
+            while !ack_timeouts.stop_signal.wait(interval) {
+                ack_timeouts.sweep();
+            }
+        })
+    }
+
+    pub(super) fn stop(self: &Self) {
+        self.stop_signal.stop();
+    }
+
+    /// Nacks timed out messages in all subscriptions. Returns the number of messages nacked
+    pub(super) fn sweep(self: &Self) -> usize {
+        self.cluster
+            .topics()
+            .values()
+            .iter()
+            .flat_map(|topic| topic.subscriptions().values())
+            .map(|subscription| subscription.nack_timed_out())
+            .sum()
+    }
+}
@@ -0,0 +1,68 @@
+/*
+Remembers the last batch of messages delivered to each consumer that consumes with implicit
+acks. When the consumer asks for its next batch, the previous batch is taken from here and
+acked on its behalf, so a consumer that processes its batches strictly in order only needs
+to poll. If the consumer stops before polling again, the last batch is never acked and will
+be redelivered, which gives at-least-once delivery with a checkpoint on each poll.
+*/
+
+use std::{collections::HashMap, sync::RwLock};
+
+use pulsar_rust_net::data_types::{ConsumerId, SubscriptionId, TopicId};
+
+type CheckpointKey = (TopicId, SubscriptionId, ConsumerId);
+
+pub(super) struct Checkpoints {
+    batches: RwLock<HashMap<CheckpointKey, Vec<String>>>,
+}
+
+impl Checkpoints {
+    pub fn new() -> Self {
+        Self {
+            batches: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Removes and returns the message ref keys of the last batch delivered to the consumer
+    pub fn take(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+    ) -> Vec<String> {
+        self.batches
+            .write()
+            .unwrap()
+            .remove(&(topic_id, subscription_id, consumer_id))
+            .unwrap_or_default()
+    }
+
+    /// Forgets the last batch delivered to every consumer of a subscription, so that those
+    /// messages are not acked when the consumers ask for their next batch
+    pub fn clear(self: &Self, topic_id: TopicId, subscription_id: SubscriptionId) {
+        self.batches
+            .write()
+            .unwrap()
+            .retain(|(batch_topic_id, batch_subscription_id, _), _| {
+                *batch_topic_id != topic_id || *batch_subscription_id != subscription_id
+            });
+    }
+
+    /// Records the message ref keys of a batch that was delivered to the consumer, so that
+    /// they can be acked when the consumer asks for its next batch
+    pub fn save(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+        message_ref_keys: Vec<String>,
+    ) {
+        if message_ref_keys.is_empty() {
+            return;
+        }
+        self.batches
+            .write()
+            .unwrap()
+            .insert((topic_id, subscription_id, consumer_id), message_ref_keys);
+    }
+}
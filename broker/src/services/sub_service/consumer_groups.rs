@@ -0,0 +1,138 @@
+/*
+Keeps track of named groups of consumers. Each group has its own subscription to the topic, so
+every group receives every message published after the group was created, and the members of
+a group share its messages with each message delivered to only one member. The subscription of
+a group is named after the subscription that the group was joined through, followed by a dot
+and the group name, and starts with the same type and settings. It is created the first time
+that a consumer joins the group, and because it is an ordinary subscription, the group keeps
+its backlog when all of its members leave, and after a restart. When a member leaves, its
+consumer is disconnected from the group's subscription, and the messages it was processing are
+rebalanced onto the remaining members.
+*/
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, RwLock},
+};
+
+use pulsar_rust_net::data_types::{ConsumerId, SubscriptionId, TopicId};
+
+use crate::model::{cluster::Cluster, subscription::SubscriptionRef, topic::TopicRef};
+
+use super::SubError;
+
+type GroupKey = (TopicId, SubscriptionId, String);
+
+struct Group {
+    subscription_id: SubscriptionId,
+    members: HashSet<ConsumerId>,
+}
+
+pub(super) struct ConsumerGroups {
+    cluster: Arc<Cluster>,
+    groups: RwLock<HashMap<GroupKey, Group>>,
+}
+
+impl ConsumerGroups {
+    pub fn new(cluster: &Arc<Cluster>) -> Self {
+        Self {
+            cluster: Arc::clone(cluster),
+            groups: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Connects a new consumer to the subscription of a group, creating the subscription if
+    /// this is the first consumer to join the group. Returns the group's subscription along
+    /// with the id of the new consumer
+    pub fn join(
+        self: &Self,
+        topic: &TopicRef,
+        subscription: &SubscriptionRef,
+        group_name: &str,
+    ) -> Result<(SubscriptionRef, ConsumerId), SubError> {
+        let key = (
+            topic.topic_id(),
+            subscription.subscription_id(),
+            group_name.to_owned(),
+        );
+
+        // The lock is held while creating the subscription, so that two consumers joining
+        // at the same time can not both create it
+        let mut groups = self.groups.write().unwrap();
+        let group_subscription = match groups
+            .get(&key)
+            .and_then(|group| topic.subscriptions().get(&group.subscription_id))
+        {
+            Some(group_subscription) => group_subscription,
+            None => self.find_or_create_subscription(topic, subscription, group_name)?,
+        };
+        let consumer_id = group_subscription.connect_consumer()?;
+
+        groups
+            .entry(key)
+            .or_insert_with(|| Group {
+                subscription_id: group_subscription.subscription_id(),
+                members: HashSet::new(),
+            })
+            .members
+            .insert(consumer_id);
+        Ok((group_subscription, consumer_id))
+    }
+
+    /// Removes a consumer from the group that owns the subscription. Returns false if the
+    /// consumer was not a member of a group with this subscription
+    pub fn leave(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+    ) -> bool {
+        self.groups
+            .write()
+            .unwrap()
+            .iter_mut()
+            .find(|(key, group)| key.0 == topic_id && group.subscription_id == subscription_id)
+            .map_or(false, |(_, group)| group.members.remove(&consumer_id))
+    }
+
+    pub fn members(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        group_name: &str,
+    ) -> Vec<ConsumerId> {
+        let mut members: Vec<ConsumerId> = self
+            .groups
+            .read()
+            .unwrap()
+            .get(&(topic_id, subscription_id, group_name.to_owned()))
+            .map(|group| group.members.iter().copied().collect())
+            .unwrap_or_default();
+        members.sort();
+        members
+    }
+
+    fn find_or_create_subscription(
+        self: &Self,
+        topic: &TopicRef,
+        subscription: &SubscriptionRef,
+        group_name: &str,
+    ) -> Result<SubscriptionRef, SubError> {
+        let name = format!("{}.{}", subscription.name(), group_name);
+        if let Some(group_subscription) = topic
+            .subscriptions()
+            .find(|group_subscription| group_subscription.name() == name)
+        {
+            return Ok(group_subscription);
+        }
+
+        self.cluster
+            .copy_subscription(topic, subscription.subscription_id(), &name)
+            .map_err(|err| {
+                SubError::Error(format!(
+                    "Failed to add a subscription for consumer group {group_name}. {:?}",
+                    err
+                ))
+            })
+    }
+}
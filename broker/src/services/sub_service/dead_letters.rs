@@ -0,0 +1,137 @@
+/*
+Moves messages that have been delivered too many times to a dead letter topic, so that a message
+that consumers can never process does not stop them from making progress. The message is
+published to the dead letter topic as a copy, with an attribute that identifies the original
+message, and the caller removes the original from its subscription.
+
+Dead letter topics are created the first time that a message is moved to them. They have one
+partition, owned by this node, and a subscription with the same name as the subscription that
+the messages came from, so that they can be consumed and investigated like any other topic.
+*/
+
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    model::{
+        cluster::Cluster,
+        messages::{MessageRef, PublishedMessage},
+        topic::TopicRef,
+    },
+    services::pub_service::{PubError, PubService},
+};
+
+/// Messages published to a dead letter topic have this attribute set to the message ref key
+/// of the message that was moved there
+pub const DEAD_LETTER_SOURCE_ATTRIBUTE: &str = "dead-letter-source";
+
+pub(super) struct DeadLetters {
+    cluster: Arc<Cluster>,
+    pub_service: Arc<PubService>,
+
+    /// Held while creating a dead letter topic, so that two consumers can not both create it
+    create_lock: Mutex<()>,
+}
+
+impl DeadLetters {
+    pub(super) fn new(cluster: &Arc<Cluster>, pub_service: &Arc<PubService>) -> Self {
+        Self {
+            cluster: Arc::clone(cluster),
+            pub_service: Arc::clone(pub_service),
+            create_lock: Mutex::new(()),
+        }
+    }
+
+    /// Publishes a copy of a message to the named dead letter topic, creating the topic if it
+    /// does not exist yet. Returns the message ref of the copy
+    pub(super) fn publish(
+        self: &Self,
+        topic_name: &str,
+        subscription_name: &str,
+        message: &PublishedMessage,
+    ) -> Result<MessageRef, String> {
+        let topic = self.find_or_create_topic(topic_name, subscription_name)?;
+        let my_node_id = self.cluster.my_node_id();
+        let partition = topic
+            .partitions()
+            .values()
+            .into_iter()
+            .find(|partition| partition.node_id() == my_node_id)
+            .ok_or_else(|| {
+                format!("Dead letter topic {topic_name} has no partitions on this node")
+            })?;
+
+        let mut dead_letter = message.clone();
+        dead_letter.message_ref = MessageRef {
+            topic_id: topic.topic_id(),
+            partition_id: partition.partition_id(),
+            ledger_id: 0,
+            message_id: 0,
+        };
+        dead_letter.subscriber_count = 0;
+        dead_letter.ack_count = 0;
+        dead_letter.producer_id = None;
+        dead_letter.sequence_number = None;
+        dead_letter.deliver_at = None;
+        dead_letter.attributes.insert(
+            DEAD_LETTER_SOURCE_ATTRIBUTE.to_owned(),
+            message.message_ref.to_key(),
+        );
+
+        match self.pub_service.publish_message(dead_letter) {
+            Ok(message_ref) => Ok(message_ref),
+            Err(PubError::Error(msg)) => Err(msg),
+            Err(PubError::NoSubscribers) => Err(format!(
+                "Dead letter topic {topic_name} has no subscriptions"
+            )),
+            Err(PubError::BacklogCapacityExceeded) => {
+                Err(format!("Dead letter topic {topic_name} is full"))
+            }
+            Err(_) => Err(format!(
+                "Failed to publish to dead letter topic {topic_name}"
+            )),
+        }
+    }
+
+    fn find_or_create_topic(
+        self: &Self,
+        topic_name: &str,
+        subscription_name: &str,
+    ) -> Result<TopicRef, String> {
+        if let Some(topic) = self.find_topic(topic_name) {
+            return Ok(topic);
+        }
+
+        let _lock = self.create_lock.lock().unwrap();
+        if let Some(topic) = self.find_topic(topic_name) {
+            return Ok(topic);
+        }
+
+        let topic = self
+            .cluster
+            .add_topic(topic_name)
+            .map_err(|err| format!("Failed to create dead letter topic {topic_name}. {:?}", err))?;
+        self.cluster
+            .add_partition(&topic, self.cluster.my_node_id())
+            .map_err(|err| {
+                format!(
+                    "Failed to add a partition to dead letter topic {topic_name}. {:?}",
+                    err
+                )
+            })?;
+        self.cluster
+            .add_subscription(&topic, subscription_name, false)
+            .map_err(|err| {
+                format!(
+                    "Failed to add a subscription to dead letter topic {topic_name}. {:?}",
+                    err
+                )
+            })?;
+        Ok(topic)
+    }
+
+    fn find_topic(self: &Self, topic_name: &str) -> Option<TopicRef> {
+        self.cluster
+            .topics()
+            .find(|topic| topic.name() == topic_name)
+    }
+}
@@ -0,0 +1,87 @@
+/*
+Periodically sweeps the subscriptions of topics that have a message TTL, removing messages
+that are still queued after the TTL has elapsed since they were published. Expired messages
+are treated as acked by the subscription so that they are removed from the ledger once every
+subscription is done with them, and an expiry event is logged for each one.
+
+Messages that were delivered to a consumer are not expired, because the consumer can still
+ack them. If the consumer nacks the message instead, it goes back to the queue and expires on
+the next sweep.
+*/
+
+use std::{
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{
+    model::{cluster::Cluster, messages::MessageRef},
+    persistence::{log_entries::LoggedEvent, logged_events::ExpiryEvent, PersistenceLayer},
+    utils::{now_epoc_millis, StopSignal},
+};
+
+pub(super) struct MessageExpiry {
+    persistence: Arc<PersistenceLayer>,
+    cluster: Arc<Cluster>,
+    stop_signal: StopSignal,
+}
+
+impl MessageExpiry {
+    pub(super) fn new(persistence: &Arc<PersistenceLayer>, cluster: &Arc<Cluster>) -> Self {
+        Self {
+            persistence: Arc::clone(persistence),
+            cluster: Arc::clone(cluster),
+            stop_signal: StopSignal::new(),
+        }
+    }
+
+    /// Starts a thread that sweeps the subscriptions each time the interval elapses
+    pub(super) fn start(self: &Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        let message_expiry = Arc::clone(self);
+        thread::spawn(move || {
+            while !message_expiry.stop_signal.wait(interval) {
+                message_expiry.sweep();
+            }
+        })
+    }
+
+    pub(super) fn stop(self: &Self) {
+        self.stop_signal.stop();
+    }
+
+    /// Expires queued messages in all subscriptions. Returns the number of messages expired
+    pub(super) fn sweep(self: &Self) -> usize {
+        let now = now_epoc_millis();
+        let mut count = 0;
+        for topic in self.cluster.topics().values() {
+            if topic.message_ttl_millis() == 0 {
+                continue;
+            }
+            let oldest_published = now.saturating_sub(topic.message_ttl_millis());
+
+            for subscription in topic.subscriptions().values() {
+                for message in subscription.expire(oldest_published) {
+                    let message_ref = MessageRef::from_key(&message.message_ref_key);
+                    if let Some(ledger) = topic
+                        .partitions()
+                        .get(&message_ref.partition_id)
+                        .and_then(|partition| partition.ledgers().get(&message_ref.ledger_id))
+                    {
+                        ledger.ack(&message_ref.message_id);
+                    }
+                    if !topic.is_ephemeral() {
+                        let _ = self
+                            .persistence
+                            .log_event(&LoggedEvent::Expiry(ExpiryEvent::new(
+                                message_ref,
+                                subscription.subscription_id(),
+                            )));
+                    }
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+}
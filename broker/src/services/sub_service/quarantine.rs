@@ -0,0 +1,71 @@
+/*
+Holds the messages that consumers have quarantined because they could not be processed. A
+quarantined message is removed from its subscription straight away rather than being
+redelivered until it fails enough times, and is kept here with the reason that the consumer
+gave so that it can be investigated. Only the most recent messages are kept for each
+subscription, so that a consumer that rejects everything can not exhaust the broker's memory.
+*/
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::RwLock,
+};
+
+use log::warn;
+use pulsar_rust_net::data_types::{SubscriptionId, TopicId};
+
+use super::QuarantinedMessage;
+
+const MAX_QUARANTINED_MESSAGES: usize = 1000;
+
+type QuarantineKey = (TopicId, SubscriptionId);
+
+pub(super) struct Quarantine {
+    messages: RwLock<HashMap<QuarantineKey, VecDeque<QuarantinedMessage>>>,
+}
+
+impl Quarantine {
+    pub fn new() -> Self {
+        Self {
+            messages: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Adds a message to the quarantine for its subscription, discarding the oldest
+    /// quarantined message if the subscription already has too many
+    pub fn add(self: &Self, quarantined: QuarantinedMessage) {
+        let key = (
+            quarantined.message.message_ref.topic_id,
+            quarantined.subscription_id,
+        );
+        let mut messages = self.messages.write().unwrap();
+        let subscription_messages = messages.entry(key).or_default();
+        if subscription_messages.len() >= MAX_QUARANTINED_MESSAGES {
+            if let Some(discarded) = subscription_messages.pop_front() {
+                warn!(
+                    "Discarded quarantined message {} from subscription {} to make room",
+                    discarded.message.message_ref.to_key(),
+                    discarded.subscription_id
+                );
+            }
+        }
+        subscription_messages.push_back(quarantined);
+    }
+
+    /// Returns the quarantined messages for a subscription, oldest first
+    pub fn list(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+    ) -> Vec<QuarantinedMessage> {
+        match self
+            .messages
+            .read()
+            .unwrap()
+            .get(&(topic_id, subscription_id))
+        {
+            Some(messages) => messages.iter().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+}
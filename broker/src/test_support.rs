@@ -0,0 +1,273 @@
+/*
+Builds a populated data layer and cluster model for tests, so that each test can describe
+the shape of the cluster it needs rather than repeating the data layer calls that create
+nodes, topics, partitions, ledgers and subscriptions. Also builds applications over the
+cluster model, and starts the binary API for them. This module is only compiled when the
+`test-support` feature is enabled.
+*/
+
+use std::{
+    collections::HashMap,
+    net::SocketAddrV4,
+    sync::{
+        atomic::{AtomicBool, AtomicU8},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    api_bin,
+    data::DataLayer,
+    model::{
+        cluster::Cluster,
+        messages::{MessageRef, PublishedMessage},
+    },
+    observability::{internals::THREAD_LISTENER, Metrics},
+    persistence::{
+        persisted_entities::{Ledger, Node, Partition, Subscription, Topic},
+        PersistenceLayer, PersistenceScheme,
+    },
+    services::{
+        admin_service::AdminService, pub_service::PubService, stats_service::StatsService,
+        sub_service::SubService,
+    },
+    App, RunState,
+};
+use pulsar_rust_net::{
+    contracts::v1::requests::MessageHeaders,
+    data_types::{PartitionId, PortNumber, TopicId},
+};
+
+const LISTEN_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct TopicShape {
+    name: String,
+    partition_count: usize,
+    subscriptions: Vec<(String, bool)>,
+}
+
+/// Describes the shape of a single node cluster to build
+pub struct ClusterBuilder {
+    ip_address: String,
+    admin_port: PortNumber,
+    pubsub_port: PortNumber,
+    sync_port: PortNumber,
+    topics: Vec<TopicShape>,
+}
+
+/// A partition that was added by the builder along with its ledger
+pub struct TestPartition {
+    pub partition: Partition,
+    pub ledger: Ledger,
+}
+
+/// A topic that was added by the builder with its partitions and subscriptions
+pub struct TestTopic {
+    pub topic: Topic,
+    pub partitions: Vec<TestPartition>,
+    pub subscriptions: Vec<Subscription>,
+}
+
+/// The entities that were added to an in-memory data layer by the builder
+pub struct TestCluster {
+    pub persistence: Arc<PersistenceLayer>,
+    pub data_layer: Arc<DataLayer>,
+    pub node: Node,
+    pub topics: Vec<TestTopic>,
+    ip_address: String,
+}
+
+impl ClusterBuilder {
+    pub fn new(ip_address: &str) -> Self {
+        Self {
+            ip_address: ip_address.to_owned(),
+            admin_port: 8000,
+            pubsub_port: 8001,
+            sync_port: 8002,
+            topics: Vec::new(),
+        }
+    }
+
+    /// Overrides the default port numbers for the node
+    pub fn ports(
+        mut self: Self,
+        admin_port: PortNumber,
+        pubsub_port: PortNumber,
+        sync_port: PortNumber,
+    ) -> Self {
+        self.admin_port = admin_port;
+        self.pubsub_port = pubsub_port;
+        self.sync_port = sync_port;
+        self
+    }
+
+    /// Adds a topic with a number of partitions, each of which has one ledger
+    pub fn topic(mut self: Self, name: &str, partition_count: usize) -> Self {
+        self.topics.push(TopicShape {
+            name: name.to_owned(),
+            partition_count,
+            subscriptions: Vec::new(),
+        });
+        self
+    }
+
+    /// Adds a subscription to the topic that was most recently added
+    pub fn subscription(mut self: Self, name: &str, has_key_affinity: bool) -> Self {
+        self.topics
+            .last_mut()
+            .expect("Add a topic before adding subscriptions")
+            .subscriptions
+            .push((name.to_owned(), has_key_affinity));
+        self
+    }
+
+    /// Adds all of the entities to a new in-memory data layer
+    pub fn build(self: Self) -> TestCluster {
+        let persistence = Arc::new(PersistenceLayer::new(
+            PersistenceScheme::InMemory,
+            PersistenceScheme::InMemory,
+        ));
+        let data_layer = Arc::new(DataLayer::new("local".to_owned(), &persistence));
+
+        let node = data_layer
+            .add_node(
+                &self.ip_address,
+                self.admin_port,
+                self.pubsub_port,
+                self.sync_port,
+            )
+            .unwrap();
+
+        let topics = self
+            .topics
+            .iter()
+            .map(|shape| {
+                let topic = data_layer.add_topic(&shape.name).unwrap();
+                let partitions = (0..shape.partition_count)
+                    .map(|_| {
+                        let partition = data_layer
+                            .add_partition(topic.topic_id, node.node_id)
+                            .unwrap();
+                        let ledger = data_layer
+                            .add_ledger(topic.topic_id, partition.partition_id, node.node_id)
+                            .unwrap();
+                        TestPartition { partition, ledger }
+                    })
+                    .collect();
+                let subscriptions = shape
+                    .subscriptions
+                    .iter()
+                    .map(|(name, has_key_affinity)| {
+                        data_layer
+                            .add_subscription(topic.topic_id, name, *has_key_affinity)
+                            .unwrap()
+                    })
+                    .collect();
+                TestTopic {
+                    topic,
+                    partitions,
+                    subscriptions,
+                }
+            })
+            .collect();
+
+        TestCluster {
+            persistence,
+            data_layer,
+            node,
+            topics,
+            ip_address: self.ip_address,
+        }
+    }
+}
+
+impl TestCluster {
+    /// Loads the cluster model from the data layer. Call this after making any
+    /// additional changes to the data layer that the test requires
+    pub fn cluster(self: &Self) -> Arc<Cluster> {
+        Arc::new(Cluster::new(&self.data_layer, &self.ip_address))
+    }
+
+    /// Builds a running application over a newly loaded cluster model
+    pub fn app(self: &Self) -> Arc<App> {
+        test_app(&self.persistence, &self.cluster())
+    }
+}
+
+/// Builds a running application with the default services over a cluster model
+pub fn test_app(persistence: &Arc<PersistenceLayer>, cluster: &Arc<Cluster>) -> Arc<App> {
+    Arc::new(App {
+        stop_signal: Arc::new(AtomicBool::new(false)),
+        run_state: Arc::new(AtomicU8::new(RunState::Running as u8)),
+        metrics: Arc::new(Metrics::new()),
+        peristence: Arc::clone(persistence),
+        pub_service: Arc::new(PubService::new(persistence, cluster)),
+        sub_service: Arc::new(SubService::new(persistence, cluster)),
+        admin_service: Arc::new(AdminService::new(persistence, cluster)),
+        stats_service: Arc::new(StatsService::new(cluster)),
+    })
+}
+
+/// Starts the binary API with the default limits, and returns once clients can connect
+pub fn serve(app: &Arc<App>, addr: SocketAddrV4) -> JoinHandle<()> {
+    serve_with(app, |app| api_bin::serve(app, addr))
+}
+
+/// Starts the binary API with one of the `api_bin` serve functions, and returns once the
+/// server is listening. Servers started for the same application are waited for one at a time
+pub fn serve_with(
+    app: &Arc<App>,
+    serve: impl FnOnce(&Arc<App>) -> JoinHandle<()>,
+) -> JoinHandle<()> {
+    let listening = listener_count(app);
+    let handle = serve(app);
+
+    // The listener thread starts after the socket is bound
+    let deadline = Instant::now() + LISTEN_TIMEOUT;
+    while listener_count(app) <= listening {
+        if handle.is_finished() || Instant::now() > deadline {
+            panic!("The binary API did not start listening");
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    handle
+}
+
+fn listener_count(app: &App) -> usize {
+    app.metrics
+        .internals()
+        .snapshot()
+        .threads
+        .get(THREAD_LISTENER)
+        .copied()
+        .unwrap_or(0)
+}
+
+/// A message to publish with a key and no attributes. The publisher fills in the message ref
+pub fn published_message(
+    topic_id: TopicId,
+    partition_id: PartitionId,
+    key: &str,
+) -> PublishedMessage {
+    PublishedMessage {
+        message_ref: MessageRef {
+            topic_id,
+            partition_id,
+            ledger_id: 0,
+            message_id: 0,
+        },
+        key: key.to_owned(),
+        timestamp: 0,
+        published: 0,
+        priority: 0,
+        attributes: HashMap::new(),
+        subscriber_count: 0,
+        ack_count: 0,
+        headers: MessageHeaders::default(),
+        producer_id: None,
+        sequence_number: None,
+        deliver_at: None,
+    }
+}
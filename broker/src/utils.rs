@@ -1,9 +1,109 @@
 use pulsar_rust_net::data_types::Timestamp;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Condvar, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
+static CLOCK: MonotonicClock = MonotonicClock::new();
+
+/// Issues timestamps that never go backwards. If the system clock is set back, then the
+/// last timestamp issued is repeated until the system clock catches up again
+pub struct MonotonicClock {
+    last_timestamp: AtomicU64,
+}
+
+impl MonotonicClock {
+    pub const fn new() -> Self {
+        Self {
+            last_timestamp: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the wall clock time, or the last timestamp issued if that is later
+    pub fn timestamp(self: &Self, wall_clock: Timestamp) -> Timestamp {
+        self.last_timestamp
+            .fetch_max(wall_clock, Ordering::Relaxed)
+            .max(wall_clock)
+    }
+}
+
+/// The current time for ordering events and calculating deadlines within the broker. This
+/// is never earlier than any time previously returned
 pub fn now_epoc_millis() -> Timestamp {
+    CLOCK.timestamp(wall_clock_millis())
+}
+
+/// The current time according to the system clock. Use this for timestamps that are
+/// displayed to people, and use `now_epoc_millis` for anything that relies on ordering
+pub fn wall_clock_millis() -> Timestamp {
     (SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
         .as_millis()) as u64
 }
+
+/// Tells a background thread to stop. The thread waits on this between runs instead of
+/// sleeping, so that it wakes up and exits as soon as it is stopped
+pub struct StopSignal {
+    stopped: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl StopSignal {
+    pub const fn new() -> Self {
+        Self {
+            stopped: Mutex::new(false),
+            condvar: Condvar::new(),
+        }
+    }
+
+    pub fn stop(self: &Self) {
+        *self.stopped.lock().unwrap() = true;
+        self.condvar.notify_all();
+    }
+
+    /// Waits until the timeout elapses or the signal is stopped. Returns true if stopped
+    pub fn wait(self: &Self, timeout: Duration) -> bool {
+        let stopped = self.stopped.lock().unwrap();
+        let (stopped, _) = self
+            .condvar
+            .wait_timeout_while(stopped, timeout, |stopped| !*stopped)
+            .unwrap();
+        *stopped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_not_go_backwards_when_wall_clock_does() {
+        let clock = MonotonicClock::new();
+
+        assert_eq!(clock.timestamp(1000), 1000);
+        assert_eq!(clock.timestamp(1005), 1005);
+
+        // The system clock is set back
+        assert_eq!(clock.timestamp(900), 1005);
+        assert_eq!(clock.timestamp(1004), 1005);
+
+        // The system clock catches up again
+        assert_eq!(clock.timestamp(1006), 1006);
+    }
+
+    #[test]
+    fn should_wake_waiting_thread_when_stopped() {
+        let signal = std::sync::Arc::new(StopSignal::new());
+        let waiter = std::sync::Arc::clone(&signal);
+        let thread = std::thread::spawn(move || waiter.wait(Duration::from_secs(3600)));
+
+        signal.stop();
+
+        assert!(thread.join().unwrap());
+        assert!(signal.wait(Duration::from_secs(3600)));
+    }
+}
@@ -0,0 +1,531 @@
+use pulsar_rust_broker::{
+    model::messages::MessageRef,
+    persistence::{
+        event_logger::EventQueryOptions, log_entries::LoggedEvent, logged_events::AdminAckEvent,
+        PersistenceLayer,
+    },
+    services::{
+        admin_service::{AdminService, CreateError, DeleteError, ReassignError},
+        pub_service::{PubError, PubService},
+        sub_service::{SubError, SubService},
+    },
+    test_support::{published_message, ClusterBuilder},
+};
+use std::{collections::HashSet, thread, time::Duration};
+
+#[test]
+fn should_not_deliver_force_acked_message() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let subscription = &test_cluster.topics[0].subscriptions[0];
+
+    let cluster = test_cluster.cluster();
+    let admin_service = AdminService::new(&test_cluster.persistence, &cluster);
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+
+    let message_refs: Vec<MessageRef> = ["1", "2", "3"]
+        .iter()
+        .map(|key| {
+            match pub_service.publish_message(published_message(
+                topic.topic_id,
+                partition.partition_id,
+                key,
+            )) {
+                Ok(message_ref) => message_ref,
+                Err(_) => panic!("Publish request failed"),
+            }
+        })
+        .collect();
+
+    match admin_service.force_ack(message_refs[1], subscription.subscription_id) {
+        Ok(acked) => assert!(acked),
+        Err(_) => panic!("Force ack request failed"),
+    }
+
+    // The message is no longer pending on this subscription
+    match admin_service.force_ack(message_refs[1], subscription.subscription_id) {
+        Ok(acked) => assert!(!acked),
+        Err(_) => panic!("Force ack request failed"),
+    }
+
+    let consumed_messages = match sub_service.consume_max_messages(
+        topic.topic_id,
+        subscription.subscription_id,
+        None,
+        10,
+    ) {
+        Ok(consumed_messages) => consumed_messages,
+        Err(_) => panic!("Consume request failed"),
+    };
+    let keys: Vec<String> = consumed_messages
+        .messages
+        .iter()
+        .map(|message| message.published_message.key.clone())
+        .collect();
+
+    assert_eq!(keys, vec!["1", "3"]);
+}
+
+#[test]
+fn should_repair_subscription_from_event_log() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let subscription_id = test_cluster.topics[0].subscriptions[0].subscription_id;
+
+    let cluster = test_cluster.cluster();
+    let admin_service = AdminService::new(&test_cluster.persistence, &cluster);
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+    let subscription = cluster
+        .topics()
+        .get(&topic.topic_id)
+        .unwrap()
+        .subscriptions()
+        .get(&subscription_id)
+        .unwrap();
+
+    let message_refs: Vec<MessageRef> = ["1", "2", "3", "4", "5"]
+        .iter()
+        .map(|key| {
+            match pub_service.publish_message(published_message(
+                topic.topic_id,
+                partition.partition_id,
+                key,
+            )) {
+                Ok(message_ref) => message_ref,
+                Err(_) => panic!("Publish request failed"),
+            }
+        })
+        .collect();
+    let message_ref_keys: Vec<String> = message_refs
+        .iter()
+        .map(|message_ref| message_ref.to_key())
+        .collect();
+
+    // The first message is acked, and the second is nacked so it is still unacked
+    let consumed_messages =
+        match sub_service.consume_max_messages(topic.topic_id, subscription_id, None, 2) {
+            Ok(consumed_messages) => consumed_messages,
+            Err(_) => panic!("Consume request failed"),
+        };
+    let consumer_id = consumed_messages.consumer_id;
+    if sub_service
+        .ack(message_ref_keys[0].clone(), subscription_id, consumer_id)
+        .is_err()
+    {
+        panic!("Ack request failed");
+    }
+    if sub_service
+        .nack(message_ref_keys[1].clone(), subscription_id, consumer_id)
+        .is_err()
+    {
+        panic!("Nack request failed");
+    }
+
+    // A subscription that is in step with the event log does not need repairing
+    let expected: HashSet<String> = message_ref_keys[1..].iter().cloned().collect();
+    match admin_service.repair_subscription(topic.topic_id, subscription_id) {
+        Ok(repair) => {
+            assert_eq!(repair.unacked_count, 4);
+            assert!(repair.restored.is_empty());
+            assert!(repair.removed.is_empty());
+        }
+        Err(_) => panic!("Repair request failed"),
+    }
+
+    // Lose all of the in-memory state of the subscription, and repair it
+    for message_ref_key in subscription.message_ref_keys() {
+        subscription.force_ack(&message_ref_key);
+    }
+    assert!(subscription.message_ref_keys().is_empty());
+
+    match admin_service.repair_subscription(topic.topic_id, subscription_id) {
+        Ok(repair) => {
+            assert_eq!(repair.unacked_count, 4);
+            assert_eq!(repair.restored, message_ref_keys[1..].to_vec());
+            assert!(repair.removed.is_empty());
+        }
+        Err(_) => panic!("Repair request failed"),
+    }
+    assert_eq!(subscription.message_ref_keys(), expected);
+
+    // A message that the event log shows as acked is removed from the subscription
+    let _ = test_cluster
+        .persistence
+        .log_event(&LoggedEvent::AdminAck(AdminAckEvent::new(
+            message_refs[2],
+            subscription_id,
+        )));
+
+    match admin_service.repair_subscription(topic.topic_id, subscription_id) {
+        Ok(repair) => {
+            assert_eq!(repair.unacked_count, 3);
+            assert!(repair.restored.is_empty());
+            assert_eq!(repair.removed, vec![message_ref_keys[2].clone()]);
+        }
+        Err(_) => panic!("Repair request failed"),
+    }
+
+    let consumed_messages =
+        match sub_service.consume_max_messages(topic.topic_id, subscription_id, None, 10) {
+            Ok(consumed_messages) => consumed_messages,
+            Err(_) => panic!("Consume request failed"),
+        };
+    let keys: Vec<String> = consumed_messages
+        .messages
+        .iter()
+        .map(|message| message.published_message.key.clone())
+        .collect();
+    assert_eq!(keys, vec!["2", "4", "5"]);
+}
+
+#[test]
+fn should_not_repair_messages_published_before_the_subscription_was_created() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic_id = test_cluster.topics[0].topic.topic_id;
+    let partition_id = test_cluster.topics[0].partitions[0].partition.partition_id;
+    let first_subscription_id = test_cluster.topics[0].subscriptions[0].subscription_id;
+
+    let cluster = test_cluster.cluster();
+    let admin_service = AdminService::new(&test_cluster.persistence, &cluster);
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+
+    let publish =
+        |key| match pub_service.publish_message(published_message(topic_id, partition_id, key)) {
+            Ok(message_ref) => message_ref,
+            Err(_) => panic!("Publish request failed"),
+        };
+    let consume = |subscription_id| match sub_service.consume_max_messages(
+        topic_id,
+        subscription_id,
+        None,
+        10,
+    ) {
+        Ok(consumed_messages) => consumed_messages,
+        Err(_) => panic!("Consume request failed"),
+    };
+
+    // The first message is still in the ledger because the first subscription has not acked it
+    publish("1");
+    thread::sleep(Duration::from_millis(10));
+    let second_subscription =
+        match admin_service.create_subscription(topic_id, "subscription2", false) {
+            Ok(subscription) => subscription,
+            Err(_) => panic!("Create subscription request failed"),
+        };
+    let second_subscription_id = second_subscription.subscription_id();
+    let message_ref_key = publish("2").to_key();
+
+    // Lose the message that the second subscription was holding, and repair it
+    second_subscription.force_ack(&message_ref_key);
+    match admin_service.repair_subscription(topic_id, second_subscription_id) {
+        Ok(repair) => {
+            assert_eq!(repair.unacked_count, 1);
+            assert_eq!(repair.restored, vec![message_ref_key]);
+            assert!(repair.removed.is_empty());
+        }
+        Err(_) => panic!("Repair request failed"),
+    }
+
+    let consumed_messages = consume(second_subscription_id);
+    for message in &consumed_messages.messages {
+        if sub_service
+            .ack(
+                message.subscribed_message.message_ref_key.clone(),
+                second_subscription_id,
+                consumed_messages.consumer_id,
+            )
+            .is_err()
+        {
+            panic!("Ack request failed");
+        }
+    }
+    let keys: Vec<String> = consumed_messages
+        .messages
+        .iter()
+        .map(|message| message.published_message.key.clone())
+        .collect();
+    assert_eq!(keys, vec!["2"]);
+
+    // Acks through the repaired subscription do not remove messages that the first
+    // subscription is still holding
+    let keys: Vec<String> = consume(first_subscription_id)
+        .messages
+        .iter()
+        .map(|message| message.published_message.key.clone())
+        .collect();
+    assert_eq!(keys, vec!["1", "2"]);
+}
+
+#[test]
+fn should_create_topics_partitions_and_subscriptions() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+
+    let cluster = test_cluster.cluster();
+    let admin_service = AdminService::new(&test_cluster.persistence, &cluster);
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+
+    let topic_id = match admin_service.create_topic("topic2") {
+        Ok(topic) => topic.topic_id(),
+        Err(_) => panic!("Create topic request failed"),
+    };
+    assert!(matches!(
+        admin_service.create_topic("topic1"),
+        Err(CreateError::NameInUse)
+    ));
+
+    // Partitions are owned by this node unless another node is specified
+    let partition = match admin_service.create_partition(topic_id, None) {
+        Ok(partition) => partition,
+        Err(_) => panic!("Create partition request failed"),
+    };
+    assert_eq!(partition.node_id(), cluster.my_node_id());
+    assert!(matches!(
+        admin_service.create_partition(topic_id, Some(99)),
+        Err(CreateError::NodeNotFound)
+    ));
+
+    let subscription_id = match admin_service.create_subscription(topic_id, "subscription1", true) {
+        Ok(subscription) => subscription.subscription_id(),
+        Err(_) => panic!("Create subscription request failed"),
+    };
+    assert!(matches!(
+        admin_service.create_subscription(topic_id, "subscription1", false),
+        Err(CreateError::NameInUse)
+    ));
+    assert!(matches!(
+        admin_service.create_subscription(99, "subscription1", false),
+        Err(CreateError::TopicNotFound)
+    ));
+
+    // The new entities can be used straight away
+    if pub_service
+        .publish_message(published_message(topic_id, partition.partition_id(), "1"))
+        .is_err()
+    {
+        panic!("Publish request failed");
+    }
+    let consumed_messages =
+        match sub_service.consume_max_messages(topic_id, subscription_id, None, 10) {
+            Ok(consumed_messages) => consumed_messages,
+            Err(_) => panic!("Consume request failed"),
+        };
+    assert_eq!(consumed_messages.messages.len(), 1);
+    assert_eq!(
+        admin_service.topic_by_name("topic2").unwrap().topic_id(),
+        topic_id
+    );
+}
+
+#[test]
+fn should_delete_topic_with_active_subscriptions() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 2)
+        .subscription("subscription1", false)
+        .subscription("subscription2", true)
+        .topic("topic2", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let subscription_id = test_cluster.topics[0].subscriptions[0].subscription_id;
+    let other_topic_id = test_cluster.topics[1].topic.topic_id;
+
+    let cluster = test_cluster.cluster();
+    let admin_service = AdminService::new(&test_cluster.persistence, &cluster);
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+
+    for key in ["1", "2"] {
+        if pub_service
+            .publish_message(published_message(
+                topic.topic_id,
+                partition.partition_id,
+                key,
+            ))
+            .is_err()
+        {
+            panic!("Publish request failed");
+        }
+    }
+    let consumer_id =
+        match sub_service.consume_max_messages(topic.topic_id, subscription_id, None, 1) {
+            Ok(consumed_messages) => consumed_messages.consumer_id,
+            Err(_) => panic!("Consume request failed"),
+        };
+
+    if admin_service.delete_topic(topic.topic_id).is_err() {
+        panic!("Delete topic request failed");
+    }
+    assert!(matches!(
+        admin_service.delete_topic(topic.topic_id),
+        Err(DeleteError::TopicNotFound)
+    ));
+
+    // The consumer finds that the subscription is gone
+    assert!(matches!(
+        sub_service.consume_max_messages(topic.topic_id, subscription_id, Some(consumer_id), 1),
+        Err(SubError::TopicNotFound)
+    ));
+    assert!(admin_service.topic_by_id(topic.topic_id).is_none());
+
+    // The partitions, ledgers and subscriptions were deleted from the database
+    for test_partition in &test_cluster.topics[0].partitions {
+        let ledger = &test_partition.ledger;
+        assert!(test_cluster
+            .data_layer
+            .get_ledger(ledger.topic_id, ledger.partition_id, ledger.ledger_id)
+            .is_err());
+        assert!(test_cluster
+            .data_layer
+            .get_partition(ledger.topic_id, ledger.partition_id)
+            .is_err());
+    }
+    for subscription in &test_cluster.topics[0].subscriptions {
+        assert!(test_cluster
+            .data_layer
+            .get_subscription(subscription.topic_id, subscription.subscription_id)
+            .is_err());
+    }
+    assert!(test_cluster.data_layer.get_topic(topic.topic_id).is_err());
+
+    // Other topics are not affected
+    assert!(admin_service.topic_by_id(other_topic_id).is_some());
+    assert!(test_cluster.data_layer.get_topic(other_topic_id).is_ok());
+}
+
+#[test]
+fn should_delete_subscription() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .subscription("subscription2", false)
+        .build();
+    let topic_id = test_cluster.topics[0].topic.topic_id;
+    let partition_id = test_cluster.topics[0].partitions[0].partition.partition_id;
+    let subscription_id = test_cluster.topics[0].subscriptions[0].subscription_id;
+    let other_subscription_id = test_cluster.topics[0].subscriptions[1].subscription_id;
+
+    let cluster = test_cluster.cluster();
+    let admin_service = AdminService::new(&test_cluster.persistence, &cluster);
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+
+    let consumer_id = match sub_service.consume_max_messages(topic_id, subscription_id, None, 1) {
+        Ok(consumed_messages) => consumed_messages.consumer_id,
+        Err(_) => panic!("Consume request failed"),
+    };
+
+    if admin_service
+        .delete_subscription(topic_id, subscription_id)
+        .is_err()
+    {
+        panic!("Delete subscription request failed");
+    }
+    assert!(matches!(
+        admin_service.delete_subscription(topic_id, subscription_id),
+        Err(DeleteError::SubscriptionNotFound)
+    ));
+    assert!(matches!(
+        sub_service.consume_max_messages(topic_id, subscription_id, Some(consumer_id), 1),
+        Err(SubError::SubscriptionNotFound)
+    ));
+    assert!(test_cluster
+        .data_layer
+        .get_subscription(topic_id, subscription_id)
+        .is_err());
+
+    // Messages are still delivered to the other subscription
+    if pub_service
+        .publish_message(published_message(topic_id, partition_id, "1"))
+        .is_err()
+    {
+        panic!("Publish request failed");
+    }
+    match sub_service.consume_max_messages(topic_id, other_subscription_id, None, 10) {
+        Ok(consumed_messages) => assert_eq!(consumed_messages.messages.len(), 1),
+        Err(_) => panic!("Consume request failed"),
+    }
+}
+
+#[test]
+fn should_redirect_publishers_after_partition_is_reassigned() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic_id = test_cluster.topics[0].topic.topic_id;
+    let partition_id = test_cluster.topics[0].partitions[0].partition.partition_id;
+    let subscription_id = test_cluster.topics[0].subscriptions[0].subscription_id;
+    let other_node = test_cluster
+        .data_layer
+        .add_node("10.0.0.2", 8000, 8001, 8002)
+        .unwrap();
+
+    let cluster = test_cluster.cluster();
+    let admin_service = AdminService::new(&test_cluster.persistence, &cluster);
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+
+    if pub_service
+        .publish_message(published_message(topic_id, partition_id, "1"))
+        .is_err()
+    {
+        panic!("Publish request failed");
+    }
+
+    let partition =
+        match admin_service.reassign_partition(topic_id, partition_id, other_node.node_id) {
+            Ok(partition) => partition,
+            Err(_) => panic!("Reassign partition request failed"),
+        };
+    assert_eq!(partition.node_id(), other_node.node_id);
+    assert!(matches!(
+        admin_service.reassign_partition(topic_id, partition_id, other_node.node_id),
+        Err(ReassignError::AlreadyOwned)
+    ));
+
+    // Publishers are told to publish to the new owner
+    match pub_service.publish_message(published_message(topic_id, partition_id, "2")) {
+        Err(PubError::WrongNode(node)) => assert_eq!(node.node_id(), other_node.node_id),
+        _ => panic!("Publisher should be redirected to the new owner"),
+    }
+
+    // Consumers follow the partition to its new owner
+    assert!(matches!(
+        sub_service.consume_max_messages(topic_id, subscription_id, None, 10),
+        Err(SubError::WrongNode(_))
+    ));
+
+    let _ = test_cluster.persistence.flush_events();
+    let key_prefix = PersistenceLayer::build_partition_prefix(topic_id, partition_id);
+    let options = EventQueryOptions::replay();
+    let reassigned = test_cluster
+        .persistence
+        .events_by_key_prefix(&key_prefix, &options)
+        .any(|log_entry| match log_entry.deserialize() {
+            Some(LoggedEvent::PartitionReassigned(event)) => {
+                event.to_node_id == other_node.node_id
+                    && event.from_node_id == test_cluster.node.node_id
+            }
+            _ => false,
+        });
+    assert!(reassigned);
+}
@@ -0,0 +1,103 @@
+use pulsar_rust_broker::{
+    observability::Metrics,
+    services::{
+        admin_service::AdminService, pub_service::PubService, stats_service::StatsService,
+        sub_service::SubService,
+    },
+    test_support::{published_message, ClusterBuilder},
+    App, RunState, ShutdownStage,
+};
+use pulsar_rust_net::contracts::v1::requests::PublishAckLevel;
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+#[test]
+fn should_shut_down_services_in_order() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+
+    let topic_id = test_cluster.topics[0].topic.topic_id;
+    let partition_id = test_cluster.topics[0].partitions[0].partition.partition_id;
+    let subscription_id = test_cluster.topics[0].subscriptions[0].subscription_id;
+
+    let persistence = &test_cluster.persistence;
+    let cluster = test_cluster.cluster();
+    let app = App {
+        stop_signal: Arc::new(AtomicBool::new(false)),
+        run_state: Arc::new(AtomicU8::new(RunState::Running as u8)),
+        metrics: Arc::new(Metrics::new()),
+        peristence: Arc::clone(persistence),
+        pub_service: Arc::new(PubService::new(persistence, &cluster)),
+        // A long batch window so that acks are only applied when the app shuts down
+        sub_service: Arc::new(SubService::with_ack_batching(
+            persistence,
+            &cluster,
+            Duration::from_secs(3600),
+        )),
+        admin_service: Arc::new(AdminService::new(persistence, &cluster)),
+        stats_service: Arc::new(StatsService::new(&cluster)),
+    };
+
+    app.pub_service
+        .publish_message_with_ack_level(
+            published_message(topic_id, partition_id, "a"),
+            PublishAckLevel::Queued,
+        )
+        .unwrap();
+
+    let consumed = match app
+        .sub_service
+        .consume_max_messages(topic_id, subscription_id, None, 10)
+    {
+        Ok(consumed) => consumed,
+        Err(_) => panic!("Consume request failed"),
+    };
+    assert_eq!(consumed.messages.len(), 1);
+
+    let message_ref_key = consumed.messages[0].published_message.message_ref.to_key();
+    if app
+        .sub_service
+        .ack(message_ref_key, subscription_id, consumed.consumer_id)
+        .is_err()
+    {
+        panic!("Ack request failed");
+    }
+
+    // Not queued for the subscription until the deferred publishes are flushed
+    app.pub_service
+        .publish_message_with_ack_level(
+            published_message(topic_id, partition_id, "b"),
+            PublishAckLevel::None,
+        )
+        .unwrap();
+
+    let mut observed = Vec::new();
+    app.shutdown_with_observer(|stage| {
+        let topic = cluster.topics().get(&topic_id).unwrap();
+        let subscriptions = topic.subscriptions();
+        let stats = subscriptions.get(&subscription_id).unwrap().stats();
+        observed.push((
+            stage,
+            app.stop_signal.load(Ordering::Relaxed),
+            stats.queued_count,
+            stats.unacked_count,
+        ));
+    });
+
+    assert_eq!(
+        observed,
+        vec![
+            (ShutdownStage::StopIngress, true, 0, 1),
+            (ShutdownStage::DrainProcessing, true, 1, 0),
+            (ShutdownStage::FlushPersistence, true, 1, 0),
+            (ShutdownStage::StopBackgroundTasks, true, 1, 0),
+        ]
+    );
+}
@@ -0,0 +1,99 @@
+use pulsar_rust_broker::{
+    data::DataLayer,
+    model::cluster::Cluster,
+    persistence::{PersistenceLayer, PersistenceScheme},
+    test_support::{serve, test_app},
+    App,
+};
+use pulsar_rust_client::{blocking, BufferPool};
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::{atomic::Ordering, Arc},
+};
+
+const SEED_PUBSUB_PORT: u16 = 18215;
+const OWNER_PUBSUB_PORT: u16 = 18216;
+
+fn app(persistence: &Arc<PersistenceLayer>, data_layer: &Arc<DataLayer>, ip: &str) -> Arc<App> {
+    let cluster = Arc::new(Cluster::new(data_layer, ip));
+    test_app(persistence, &cluster)
+}
+
+#[test]
+fn should_send_requests_to_the_node_that_owns_the_partition() {
+    let persistence = Arc::new(PersistenceLayer::new(
+        PersistenceScheme::InMemory,
+        PersistenceScheme::InMemory,
+    ));
+    let data_layer = Arc::new(DataLayer::new("local".to_owned(), &persistence));
+
+    // Both brokers listen on the loopback address, the seed node is only registered with
+    // another address so that it does not find itself as the owner when it starts
+    data_layer
+        .add_node("127.0.0.2", 18217, SEED_PUBSUB_PORT, 18218)
+        .unwrap();
+    let owner = data_layer
+        .add_node("127.0.0.1", 18219, OWNER_PUBSUB_PORT, 18220)
+        .unwrap();
+
+    let topic = data_layer.add_topic("topic1").unwrap();
+    for _ in 0..2 {
+        let partition = data_layer
+            .add_partition(topic.topic_id, owner.node_id)
+            .unwrap();
+        data_layer
+            .add_ledger(topic.topic_id, partition.partition_id, owner.node_id)
+            .unwrap();
+    }
+    let subscription = data_layer
+        .add_subscription(topic.topic_id, "subscription1", false)
+        .unwrap();
+
+    let seed_app = app(&persistence, &data_layer, "127.0.0.2");
+    let owner_app = app(&persistence, &data_layer, "127.0.0.1");
+    let seed_handle = serve(
+        &seed_app,
+        SocketAddrV4::new(Ipv4Addr::LOCALHOST, SEED_PUBSUB_PORT),
+    );
+    let owner_handle = serve(
+        &owner_app,
+        SocketAddrV4::new(Ipv4Addr::LOCALHOST, OWNER_PUBSUB_PORT),
+    );
+
+    let buffer_pool = Arc::new(BufferPool::new());
+    let seed = format!("127.0.0.1:{SEED_PUBSUB_PORT}");
+    let mut client = blocking::ClusterClient::new(&buffer_pool, &[&seed]);
+    client.connect().unwrap();
+
+    let map = client.topic_partition_map(topic.topic_id).unwrap();
+    assert_eq!(map.partitions.len(), 2);
+    assert!(map
+        .partitions
+        .iter()
+        .all(|partition| partition.pubsub_port == OWNER_PUBSUB_PORT));
+
+    for index in 0..3 {
+        let key = Some(format!("key{index}"));
+        client
+            .publish(topic.topic_id, key, None, HashMap::new())
+            .unwrap();
+    }
+
+    let consumed = client
+        .consume(topic.topic_id, subscription.subscription_id, 10)
+        .unwrap();
+    assert_eq!(consumed.messages.len(), 3);
+    for message in consumed.messages.iter() {
+        let acked = client
+            .ack(message, subscription.subscription_id, consumed.consumer_id)
+            .unwrap();
+        assert!(acked.success);
+    }
+
+    client.disconnect();
+    seed_app.stop_signal.store(true, Ordering::Relaxed);
+    owner_app.stop_signal.store(true, Ordering::Relaxed);
+    seed_handle.join().unwrap();
+    owner_handle.join().unwrap();
+}
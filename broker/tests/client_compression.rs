@@ -0,0 +1,59 @@
+use pulsar_rust_broker::{
+    api_bin::{self, RequestLimits},
+    test_support::{serve_with, ClusterBuilder},
+};
+use pulsar_rust_client::{blocking::Client, BufferPool, CompressionScheme};
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::{atomic::Ordering, Arc},
+};
+
+const PUBSUB_PORT: u16 = 18141;
+
+#[test]
+fn should_publish_and_consume_with_compression() {
+    let test_cluster = ClusterBuilder::new("127.0.0.1")
+        .ports(18140, PUBSUB_PORT, 18142)
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic_id = test_cluster.topics[0].topic.topic_id;
+    let subscription_id = test_cluster.topics[0].subscriptions[0].subscription_id;
+
+    let app = test_cluster.app();
+
+    let request_limits = RequestLimits {
+        max_publish_bytes: 64 * 1024,
+        ..RequestLimits::default()
+    };
+    let server_handle = serve_with(&app, |app| {
+        api_bin::serve_with_limits(
+            app,
+            SocketAddrV4::new(Ipv4Addr::LOCALHOST, PUBSUB_PORT),
+            request_limits,
+        )
+    });
+
+    let buffer_pool = Arc::new(BufferPool::new());
+    let mut client = Client::new(&buffer_pool, &format!("127.0.0.1:{PUBSUB_PORT}"))
+        .with_compression(CompressionScheme::Lz4);
+    client.connect().unwrap();
+
+    // Attributes that are larger than the default maximum message size when uncompressed
+    let attributes: HashMap<String, String> = (0..2000)
+        .map(|i| (format!("attribute-{i}"), String::from("repeated value")))
+        .collect();
+
+    client
+        .publish(topic_id, Some("key1".to_owned()), None, attributes.clone())
+        .unwrap();
+
+    let consumed = client.consume(topic_id, subscription_id, None, 1).unwrap();
+    assert_eq!(consumed.messages.len(), 1);
+    assert_eq!(consumed.messages[0].attributes, attributes);
+
+    drop(client);
+    app.stop_signal.store(true, Ordering::Relaxed);
+    server_handle.join().unwrap();
+}
@@ -0,0 +1,74 @@
+use pulsar_rust_broker::test_support::{serve, ClusterBuilder};
+use pulsar_rust_client::{non_blocking::Client, BufferPool};
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::{atomic::Ordering, Arc},
+};
+use tokio::runtime::Runtime;
+
+const PUBSUB_PORT: u16 = 18181;
+
+#[test]
+fn should_consume_as_the_same_consumer_on_each_poll() {
+    let test_cluster = ClusterBuilder::new("127.0.0.1")
+        .ports(18180, PUBSUB_PORT, 18182)
+        .topic("topic1", 1)
+        .subscription("subscription1", true)
+        .build();
+    let topic_id = test_cluster.topics[0].topic.topic_id;
+    let subscription_id = test_cluster.topics[0].subscriptions[0].subscription_id;
+
+    let app = test_cluster.app();
+
+    let server_handle = serve(&app, SocketAddrV4::new(Ipv4Addr::LOCALHOST, PUBSUB_PORT));
+
+    let buffer_pool = Arc::new(BufferPool::new());
+    let mut client = Client::new(&buffer_pool, &format!("127.0.0.1:{PUBSUB_PORT}"));
+    client.connect().unwrap();
+    let client = Arc::new(client);
+    let runtime = Runtime::new().unwrap();
+
+    for i in 0..6 {
+        let future = client
+            .publish(topic_id, Some(format!("key{i}")), None, HashMap::new())
+            .unwrap();
+        runtime.block_on(future).unwrap();
+    }
+
+    let stream = client.consume_stream(topic_id, subscription_id);
+    assert!(stream.consumer_id().is_none());
+
+    // The consumer id allocated by the first response is reused by every later poll
+    let mut consumed = 0;
+    for _ in 0..3 {
+        let result = runtime.block_on(stream.consume(2).unwrap()).unwrap();
+        assert_eq!(stream.consumer_id(), Some(result.consumer_id));
+        for message in &result.messages {
+            let future = stream
+                .session()
+                .ack(
+                    &message.message_ref_key,
+                    subscription_id,
+                    result.consumer_id,
+                )
+                .unwrap();
+            runtime.block_on(future).unwrap();
+        }
+        consumed += result.messages.len();
+    }
+    assert_eq!(consumed, 6);
+    let consumer_id = stream.consumer_id().unwrap();
+
+    // Each stream is a separate consumer
+    let other_stream = client.consume_stream(topic_id, subscription_id);
+    let result = runtime.block_on(other_stream.consume(2).unwrap()).unwrap();
+    assert_ne!(result.consumer_id, consumer_id);
+    assert_eq!(stream.consumer_id(), Some(consumer_id));
+
+    drop(stream);
+    drop(other_stream);
+    drop(client);
+    app.stop_signal.store(true, Ordering::Relaxed);
+    server_handle.join().unwrap();
+}
@@ -0,0 +1,172 @@
+use pulsar_rust_broker::test_support::{serve, ClusterBuilder};
+use pulsar_rust_client::{non_blocking::Client, BufferPool, MessageCount, SubscriptionId, TopicId};
+use std::{
+    collections::{HashMap, HashSet},
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::{atomic::Ordering, Arc},
+};
+use tokio::runtime::Runtime;
+
+const PUBSUB_PORT: u16 = 18051;
+
+fn publish(client: &Client, runtime: &Runtime, topic_id: TopicId, keys: &[String]) {
+    for key in keys {
+        let future = client
+            .publish(topic_id, Some(key.clone()), None, HashMap::new())
+            .unwrap();
+        runtime.block_on(future).unwrap();
+    }
+}
+
+fn consume(
+    client: &Client,
+    runtime: &Runtime,
+    topic_id: TopicId,
+    subscription_id: SubscriptionId,
+    max_messages: MessageCount,
+    ack: bool,
+) -> HashSet<String> {
+    let future = client
+        .consume(topic_id, subscription_id, &None, max_messages)
+        .unwrap();
+    let result = runtime.block_on(future).unwrap();
+    let mut keys = HashSet::new();
+    for message in result.messages {
+        if ack {
+            let future = client
+                .ack(
+                    &message.message_ref_key,
+                    subscription_id,
+                    result.consumer_id,
+                )
+                .unwrap();
+            runtime.block_on(future).unwrap();
+        }
+        keys.insert(message.message_key);
+    }
+    keys
+}
+
+#[test]
+fn should_share_messages_between_group_members() {
+    let test_cluster = ClusterBuilder::new("127.0.0.1")
+        .ports(18050, PUBSUB_PORT, 18052)
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic_id = test_cluster.topics[0].topic.topic_id;
+    let subscription_id = test_cluster.topics[0].subscriptions[0].subscription_id;
+
+    let app = test_cluster.app();
+
+    let server_handle = serve(&app, SocketAddrV4::new(Ipv4Addr::LOCALHOST, PUBSUB_PORT));
+
+    let buffer_pool = Arc::new(BufferPool::new());
+    let runtime = Runtime::new().unwrap();
+    let members: Vec<Client> = (0..3)
+        .map(|_| {
+            let mut client = Client::new(&buffer_pool, &format!("127.0.0.1:{PUBSUB_PORT}"));
+            client.connect().unwrap();
+            client
+        })
+        .collect();
+
+    let joined: Vec<_> = members
+        .iter()
+        .map(|client| {
+            let future = client
+                .join_group(topic_id, subscription_id, "workers")
+                .unwrap();
+            let result = runtime.block_on(future).unwrap();
+            (result.subscription_id, result.consumer_id)
+        })
+        .collect();
+    let group_subscription_id = joined[0].0;
+    let consumer_ids: Vec<_> = joined.iter().map(|(_, consumer_id)| *consumer_id).collect();
+    assert_ne!(group_subscription_id, subscription_id);
+    assert!(joined
+        .iter()
+        .all(|(subscription_id, _)| *subscription_id == group_subscription_id));
+    assert_eq!(consumer_ids.iter().collect::<HashSet<_>>().len(), 3);
+    assert_eq!(
+        app.sub_service
+            .group_members(topic_id, subscription_id, "workers")
+            .len(),
+        3
+    );
+
+    // Another group has its own subscription, so it also receives every message
+    let mut auditor = Client::new(&buffer_pool, &format!("127.0.0.1:{PUBSUB_PORT}"));
+    auditor.connect().unwrap();
+    let future = auditor
+        .join_group(topic_id, subscription_id, "auditors")
+        .unwrap();
+    let auditors_subscription_id = runtime.block_on(future).unwrap().subscription_id;
+    assert_ne!(auditors_subscription_id, subscription_id);
+    assert_ne!(auditors_subscription_id, group_subscription_id);
+
+    // Each message is delivered to exactly one member of the group
+    let keys: Vec<String> = (0..9).map(|i| format!("key{i}")).collect();
+    publish(&members[0], &runtime, topic_id, &keys);
+
+    let mut delivered = HashSet::new();
+    for client in &members {
+        let consumed = consume(client, &runtime, topic_id, group_subscription_id, 3, true);
+        assert!(consumed.is_disjoint(&delivered));
+        delivered.extend(consumed);
+    }
+    assert_eq!(delivered, keys.iter().cloned().collect());
+
+    let audited = consume(
+        &auditor,
+        &runtime,
+        topic_id,
+        auditors_subscription_id,
+        10,
+        true,
+    );
+    assert_eq!(audited, keys.iter().cloned().collect());
+
+    // Consumers of the subscription that the groups were joined through are not affected
+    let ungrouped = consume(&members[0], &runtime, topic_id, subscription_id, 10, true);
+    assert_eq!(ungrouped, keys.iter().cloned().collect());
+
+    // Messages that were not acked by a member that leaves go to the remaining members
+    let keys: Vec<String> = (9..15).map(|i| format!("key{i}")).collect();
+    publish(&members[0], &runtime, topic_id, &keys);
+
+    let abandoned = consume(
+        &members[2],
+        &runtime,
+        topic_id,
+        group_subscription_id,
+        2,
+        false,
+    );
+    assert_eq!(abandoned.len(), 2);
+
+    let future = members[2]
+        .leave_group(topic_id, group_subscription_id, consumer_ids[2])
+        .unwrap();
+    assert!(runtime.block_on(future).unwrap().success);
+    assert_eq!(
+        app.sub_service
+            .group_members(topic_id, subscription_id, "workers")
+            .len(),
+        2
+    );
+
+    let mut delivered = HashSet::new();
+    for client in &members[0..2] {
+        let consumed = consume(client, &runtime, topic_id, group_subscription_id, 10, true);
+        assert!(consumed.is_disjoint(&delivered));
+        delivered.extend(consumed);
+    }
+    assert_eq!(delivered, keys.iter().cloned().collect());
+    assert!(abandoned.is_subset(&delivered));
+
+    drop(auditor);
+    drop(members);
+    app.stop_signal.store(true, Ordering::Relaxed);
+    server_handle.join().unwrap();
+}
@@ -0,0 +1,58 @@
+use pulsar_rust_broker::test_support::{serve, ClusterBuilder};
+use pulsar_rust_client::{blocking::Client, BufferPool};
+use std::{
+    collections::{HashMap, HashSet},
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::{atomic::Ordering, Arc},
+};
+
+const PUBSUB_PORT: u16 = 18151;
+
+#[test]
+fn should_redeliver_messages_when_consumer_disconnects() {
+    let test_cluster = ClusterBuilder::new("127.0.0.1")
+        .ports(18150, PUBSUB_PORT, 18152)
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic_id = test_cluster.topics[0].topic.topic_id;
+    let subscription_id = test_cluster.topics[0].subscriptions[0].subscription_id;
+
+    let app = test_cluster.app();
+
+    let server_handle = serve(&app, SocketAddrV4::new(Ipv4Addr::LOCALHOST, PUBSUB_PORT));
+
+    let buffer_pool = Arc::new(BufferPool::new());
+    let mut client = Client::new(&buffer_pool, &format!("127.0.0.1:{PUBSUB_PORT}"));
+    client.connect().unwrap();
+
+    let keys: HashSet<String> = (0..3).map(|i| format!("key{i}")).collect();
+    for key in &keys {
+        client
+            .publish(topic_id, Some(key.clone()), None, HashMap::new())
+            .unwrap();
+    }
+
+    // The first consumer receives the messages but disconnects without acking them
+    let abandoned = client.consume(topic_id, subscription_id, None, 3).unwrap();
+    assert_eq!(abandoned.messages.len(), 3);
+
+    let result = client
+        .disconnect_consumer(topic_id, subscription_id, abandoned.consumer_id)
+        .unwrap();
+    assert!(result.success);
+
+    // The next consume allocates a new consumer id, and the messages are redelivered to it
+    let consumed = client.consume(topic_id, subscription_id, None, 3).unwrap();
+    assert_ne!(consumed.consumer_id, abandoned.consumer_id);
+    let redelivered: HashSet<String> = consumed
+        .messages
+        .iter()
+        .map(|message| message.message_key.clone())
+        .collect();
+    assert_eq!(redelivered, keys);
+
+    drop(client);
+    app.stop_signal.store(true, Ordering::Relaxed);
+    server_handle.join().unwrap();
+}
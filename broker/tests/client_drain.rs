@@ -0,0 +1,60 @@
+use pulsar_rust_broker::{
+    test_support::{serve, test_app, ClusterBuilder},
+    RunState,
+};
+use pulsar_rust_client::{blocking::Client, BufferPool};
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
+};
+
+const PUBSUB_PORT: u16 = 18171;
+
+#[test]
+fn should_stop_once_drained() {
+    let test_cluster = ClusterBuilder::new("127.0.0.1")
+        .ports(18170, PUBSUB_PORT, 18172)
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic_id = test_cluster.topics[0].topic.topic_id;
+    let subscription_id = test_cluster.topics[0].subscriptions[0].subscription_id;
+
+    let persistence = &test_cluster.persistence;
+    let cluster = test_cluster.cluster();
+    let app = test_app(persistence, &cluster);
+
+    let server_handle = serve(&app, SocketAddrV4::new(Ipv4Addr::LOCALHOST, PUBSUB_PORT));
+
+    let buffer_pool = Arc::new(BufferPool::new());
+    let mut client = Client::new(&buffer_pool, &format!("127.0.0.1:{PUBSUB_PORT}"));
+    client.connect().unwrap();
+
+    for i in 0..3 {
+        client
+            .publish(topic_id, Some(format!("key{i}")), None, HashMap::new())
+            .unwrap();
+    }
+
+    assert!(app.begin_drain());
+    assert!(!app.begin_drain());
+
+    // Nothing is in flight, so the binary API stops well within the default drain timeout
+    let started = Instant::now();
+    server_handle.join().unwrap();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(app.run_state(), RunState::Stopped);
+    assert!(app.stop_signal.load(Ordering::Relaxed));
+
+    let stats = cluster
+        .topics()
+        .get(&topic_id)
+        .unwrap()
+        .subscriptions()
+        .get(&subscription_id)
+        .unwrap()
+        .stats();
+    assert_eq!(stats.queued_count, 3);
+}
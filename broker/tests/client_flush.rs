@@ -0,0 +1,79 @@
+use pulsar_rust_broker::{
+    data::DataLayer,
+    model::cluster::Cluster,
+    persistence::{PersistenceLayer, PersistenceScheme},
+    test_support::{serve, test_app},
+};
+use pulsar_rust_client::{non_blocking::Client, BufferPool};
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+use tokio::runtime::Runtime;
+
+const PUBSUB_PORT: u16 = 18021;
+const MESSAGE_COUNT: usize = 10;
+
+#[test]
+fn should_resolve_all_futures_when_flushed() {
+    let persistence = Arc::new(PersistenceLayer::new(
+        PersistenceScheme::InMemory,
+        PersistenceScheme::InMemory,
+    ));
+    let data_layer = Arc::new(DataLayer::new("local".to_owned(), &persistence));
+
+    let node = data_layer
+        .add_node("127.0.0.1", 18020, PUBSUB_PORT, 18022)
+        .unwrap();
+    let topic = data_layer.add_topic("topic1").unwrap();
+    data_layer
+        .add_partition(topic.topic_id, node.node_id)
+        .unwrap();
+    data_layer
+        .add_subscription(topic.topic_id, "subscription1", false)
+        .unwrap();
+
+    let cluster = Arc::new(Cluster::new(&data_layer, "127.0.0.1"));
+    let app = test_app(&persistence, &cluster);
+
+    let server_handle = serve(&app, SocketAddrV4::new(Ipv4Addr::LOCALHOST, PUBSUB_PORT));
+
+    let buffer_pool = Arc::new(BufferPool::new());
+    let mut client = Client::new(&buffer_pool, &format!("127.0.0.1:{PUBSUB_PORT}"));
+    client.connect().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    let futures: Vec<_> = (0..MESSAGE_COUNT)
+        .map(|index| {
+            client
+                .publish(
+                    topic.topic_id,
+                    Some(index.to_string()),
+                    None,
+                    HashMap::new(),
+                )
+                .unwrap()
+        })
+        .collect();
+
+    runtime
+        .block_on(client.flush(Duration::from_secs(5)))
+        .unwrap();
+
+    for future in futures {
+        assert!(future.is_ready());
+        let result = runtime.block_on(future).unwrap();
+        assert_eq!(result.message_ref.topic_id, topic.topic_id);
+    }
+
+    // Flushing with nothing outstanding completes immediately
+    runtime
+        .block_on(client.flush(Duration::from_millis(0)))
+        .unwrap();
+
+    drop(client);
+    app.stop_signal.store(true, Ordering::Relaxed);
+    server_handle.join().unwrap();
+}
@@ -0,0 +1,57 @@
+use pulsar_rust_broker::test_support::{serve, ClusterBuilder};
+use pulsar_rust_client::{blocking::Client, BufferPool};
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::{atomic::Ordering, Arc},
+};
+
+const PUBSUB_PORT: u16 = 18061;
+
+#[test]
+fn should_get_message_by_ack_key_without_delivering_it() {
+    let test_cluster = ClusterBuilder::new("127.0.0.1")
+        .ports(18060, PUBSUB_PORT, 18062)
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic_id = test_cluster.topics[0].topic.topic_id;
+    let subscription_id = test_cluster.topics[0].subscriptions[0].subscription_id;
+
+    let app = test_cluster.app();
+
+    let server_handle = serve(&app, SocketAddrV4::new(Ipv4Addr::LOCALHOST, PUBSUB_PORT));
+
+    let buffer_pool = Arc::new(BufferPool::new());
+    let mut client = Client::new(&buffer_pool, &format!("127.0.0.1:{PUBSUB_PORT}"));
+    client.connect().unwrap();
+
+    let mut attributes = HashMap::new();
+    attributes.insert("order_number".to_owned(), "ABC123".to_owned());
+    client
+        .publish(topic_id, Some("key1".to_owned()), None, attributes)
+        .unwrap();
+
+    let consumed = client.consume(topic_id, subscription_id, None, 1).unwrap();
+    assert_eq!(consumed.messages.len(), 1);
+    let message_ref_key = consumed.messages[0].message_ref_key.clone();
+
+    let message = client.get_message(&message_ref_key).unwrap();
+    assert_eq!(message.message_key, "key1");
+    assert_eq!(message.message_ref_key, message_ref_key);
+    assert_eq!(message.attributes.get("order_number").unwrap(), "ABC123");
+
+    // Malformed keys are rejected without stopping the broker from processing requests
+    assert!(client.get_message("not-a-message-ref").is_err());
+    assert!(client.get_message("1:2:3").is_err());
+
+    // Fetching the message does not change its delivery state, so it can still be acked
+    let ack = client
+        .ack(&message_ref_key, subscription_id, consumed.consumer_id)
+        .unwrap();
+    assert!(ack.success);
+
+    drop(client);
+    app.stop_signal.store(true, Ordering::Relaxed);
+    server_handle.join().unwrap();
+}
@@ -0,0 +1,45 @@
+use pulsar_rust_broker::test_support::{serve, ClusterBuilder};
+use pulsar_rust_client::{non_blocking::Client, BufferPool};
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::{atomic::Ordering, Arc},
+    thread,
+    time::Duration,
+};
+use tokio::runtime::Runtime;
+
+const PUBSUB_PORT: u16 = 18201;
+
+#[test]
+fn should_stay_connected_while_the_broker_answers_pings() {
+    let test_cluster = ClusterBuilder::new("127.0.0.1")
+        .ports(18200, PUBSUB_PORT, 18202)
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic_id = test_cluster.topics[0].topic.topic_id;
+
+    let app = test_cluster.app();
+
+    let server_handle = serve(&app, SocketAddrV4::new(Ipv4Addr::LOCALHOST, PUBSUB_PORT));
+
+    let buffer_pool = Arc::new(BufferPool::new());
+    let mut client = Client::new(&buffer_pool, &format!("127.0.0.1:{PUBSUB_PORT}"))
+        .with_keepalive(Duration::from_millis(50), Duration::from_millis(500));
+    client.connect().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    // Several pings are sent and answered while the client is idle
+    thread::sleep(Duration::from_millis(400));
+    assert!(client.is_connected());
+
+    let future = client
+        .publish(topic_id, None, None, HashMap::new())
+        .unwrap();
+    assert!(runtime.block_on(future).is_ok());
+
+    drop(client);
+    app.stop_signal.store(true, Ordering::Relaxed);
+    server_handle.join().unwrap();
+}
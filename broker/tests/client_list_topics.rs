@@ -0,0 +1,58 @@
+use pulsar_rust_broker::test_support::{serve, ClusterBuilder};
+use pulsar_rust_client::{blocking::Client, BufferPool};
+use std::{
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::{atomic::Ordering, Arc},
+};
+
+const PUBSUB_PORT: u16 = 18131;
+
+#[test]
+fn should_list_topics_with_partition_counts() {
+    let test_cluster = ClusterBuilder::new("127.0.0.1")
+        .ports(18130, PUBSUB_PORT, 18132)
+        .topic("orders", 1)
+        .subscription("billing", false)
+        .topic("payments", 3)
+        .subscription("ledger", false)
+        .subscription("audit", true)
+        .topic("refunds", 5)
+        .build();
+
+    let app = test_cluster.app();
+
+    let server_handle = serve(&app, SocketAddrV4::new(Ipv4Addr::LOCALHOST, PUBSUB_PORT));
+
+    let buffer_pool = Arc::new(BufferPool::new());
+    let mut client = Client::new(&buffer_pool, &format!("127.0.0.1:{PUBSUB_PORT}"));
+    client.connect().unwrap();
+
+    let mut topics = client.list_topics().unwrap();
+    topics.sort_by(|a, b| a.name.cmp(&b.name));
+    let listed: Vec<_> = topics
+        .iter()
+        .map(|topic| {
+            (
+                topic.name.as_str(),
+                topic.partition_count,
+                topic.subscription_count,
+            )
+        })
+        .collect();
+    assert_eq!(
+        listed,
+        vec![("orders", 1, 1), ("payments", 3, 2), ("refunds", 5, 0)]
+    );
+
+    for test_topic in &test_cluster.topics {
+        let topic = topics
+            .iter()
+            .find(|topic| topic.name == test_topic.topic.name)
+            .unwrap();
+        assert_eq!(topic.topic_id, test_topic.topic.topic_id);
+    }
+
+    drop(client);
+    app.stop_signal.store(true, Ordering::Relaxed);
+    server_handle.join().unwrap();
+}
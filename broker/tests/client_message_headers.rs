@@ -0,0 +1,74 @@
+use pulsar_rust_broker::test_support::{serve, ClusterBuilder};
+use pulsar_rust_client::{blocking::Client, contracts::MessageHeaders, BufferPool, Priority};
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::{atomic::Ordering, Arc},
+};
+
+const PUBSUB_PORT: u16 = 18091;
+
+#[test]
+fn should_deliver_headers_separately_from_attributes() {
+    let test_cluster = ClusterBuilder::new("127.0.0.1")
+        .ports(18090, PUBSUB_PORT, 18092)
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic_id = test_cluster.topics[0].topic.topic_id;
+    let subscription_id = test_cluster.topics[0].subscriptions[0].subscription_id;
+
+    let app = test_cluster.app();
+
+    let server_handle = serve(&app, SocketAddrV4::new(Ipv4Addr::LOCALHOST, PUBSUB_PORT));
+
+    let buffer_pool = Arc::new(BufferPool::new());
+    let mut client = Client::new(&buffer_pool, &format!("127.0.0.1:{PUBSUB_PORT}"));
+    client.connect().unwrap();
+
+    let headers = MessageHeaders {
+        content_type: Some("application/json".to_owned()),
+        correlation_id: Some("request-42".to_owned()),
+        reply_to: Some("replies".to_owned()),
+        trace_id: Some("trace-7".to_owned()),
+    };
+
+    // An attribute with the same name as a header does not collide with it
+    let mut attributes = HashMap::new();
+    attributes.insert("content_type".to_owned(), "text/plain".to_owned());
+    attributes.insert("order_number".to_owned(), "ABC123".to_owned());
+
+    client
+        .publish_with_headers(
+            topic_id,
+            Some("key1".to_owned()),
+            None,
+            Priority::default(),
+            headers.clone(),
+            attributes,
+        )
+        .unwrap();
+    client
+        .publish(topic_id, Some("key2".to_owned()), None, HashMap::new())
+        .unwrap();
+
+    let consumed = client.consume(topic_id, subscription_id, None, 2).unwrap();
+    assert_eq!(consumed.messages.len(), 2);
+
+    let message = &consumed.messages[0];
+    assert_eq!(message.message_key, "key1");
+    assert_eq!(message.headers, headers);
+    assert_eq!(message.attributes.len(), 2);
+    assert_eq!(message.attributes.get("content_type").unwrap(), "text/plain");
+    assert_eq!(message.attributes.get("order_number").unwrap(), "ABC123");
+
+    // Messages published without headers have none
+    let message = &consumed.messages[1];
+    assert_eq!(message.message_key, "key2");
+    assert_eq!(message.headers, MessageHeaders::default());
+    assert!(message.attributes.is_empty());
+
+    drop(client);
+    app.stop_signal.store(true, Ordering::Relaxed);
+    server_handle.join().unwrap();
+}
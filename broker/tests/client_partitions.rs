@@ -0,0 +1,67 @@
+use pulsar_rust_broker::test_support::{serve, ClusterBuilder};
+use pulsar_rust_client::{blocking::Client, BufferPool};
+use std::{
+    collections::{HashMap, HashSet},
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::{atomic::Ordering, Arc},
+};
+
+const PUBSUB_PORT: u16 = 18111;
+
+#[test]
+fn should_publish_messages_to_partition_chosen_by_key() {
+    let test_cluster = ClusterBuilder::new("127.0.0.1")
+        .ports(18110, PUBSUB_PORT, 18112)
+        .topic("topic1", 4)
+        .subscription("subscription1", true)
+        .build();
+    let topic_id = test_cluster.topics[0].topic.topic_id;
+    let mut expected_partition_ids: Vec<_> = test_cluster.topics[0]
+        .partitions
+        .iter()
+        .map(|partition| partition.partition.partition_id)
+        .collect();
+    expected_partition_ids.sort();
+
+    let app = test_cluster.app();
+
+    let server_handle = serve(&app, SocketAddrV4::new(Ipv4Addr::LOCALHOST, PUBSUB_PORT));
+
+    let buffer_pool = Arc::new(BufferPool::new());
+    let mut client = Client::new(&buffer_pool, &format!("127.0.0.1:{PUBSUB_PORT}"));
+    client.connect().unwrap();
+
+    assert_eq!(
+        client.get_partitions(topic_id).unwrap(),
+        expected_partition_ids
+    );
+
+    // Messages with the same key are always published to the same partition
+    let mut partition_by_key = HashMap::new();
+    for _ in 0..2 {
+        for i in 0..20 {
+            let key = format!("key-{i}");
+            let result = client
+                .publish(topic_id, Some(key.clone()), None, HashMap::new())
+                .unwrap();
+            let partition_id = *partition_by_key
+                .entry(key)
+                .or_insert(result.message_ref.partition_id);
+            assert_eq!(result.message_ref.partition_id, partition_id);
+        }
+    }
+
+    // Different keys are spread across the partitions
+    let used_partitions: HashSet<_> = partition_by_key.values().collect();
+    assert!(used_partitions.len() > 1);
+
+    // Messages without a key are published to a partition chosen from the generated key
+    let result = client
+        .publish(topic_id, None, None, HashMap::new())
+        .unwrap();
+    assert!(expected_partition_ids.contains(&result.message_ref.partition_id));
+
+    drop(client);
+    app.stop_signal.store(true, Ordering::Relaxed);
+    server_handle.join().unwrap();
+}
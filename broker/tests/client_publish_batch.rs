@@ -0,0 +1,93 @@
+use pulsar_rust_broker::{
+    persistence::persisted_entities::QueueOverflowPolicy,
+    test_support::{serve, ClusterBuilder},
+};
+use pulsar_rust_client::{
+    contracts::{ClientError, PublishItem},
+    non_blocking::Client,
+    BufferPool, ERROR_CODE_BACKLOG_FULL,
+};
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::{atomic::Ordering, Arc},
+};
+use tokio::runtime::Runtime;
+
+const PUBSUB_PORT: u16 = 18121;
+
+#[test]
+fn should_publish_batch_with_result_for_each_message() {
+    let test_cluster = ClusterBuilder::new("127.0.0.1")
+        .ports(18120, PUBSUB_PORT, 18122)
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic_id = test_cluster.topics[0].topic.topic_id;
+    let subscription_id = test_cluster.topics[0].subscriptions[0].subscription_id;
+
+    // The subscription only has room for two messages, so the third message in the batch fails
+    test_cluster
+        .data_layer
+        .set_subscription_queue_limit(topic_id, subscription_id, 2, QueueOverflowPolicy::Block)
+        .unwrap();
+
+    let app = test_cluster.app();
+
+    let server_handle = serve(&app, SocketAddrV4::new(Ipv4Addr::LOCALHOST, PUBSUB_PORT));
+
+    let buffer_pool = Arc::new(BufferPool::new());
+    let mut client = Client::new(&buffer_pool, &format!("127.0.0.1:{PUBSUB_PORT}"));
+    client.connect().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    let items = ["key1", "key2", "key3"]
+        .iter()
+        .map(|key| {
+            let mut attributes = HashMap::new();
+            attributes.insert("key".to_owned(), key.to_string());
+            PublishItem {
+                key: Some(key.to_string()),
+                attributes,
+                ..PublishItem::default()
+            }
+        })
+        .collect();
+    let future = client.publish_batch(topic_id, items).unwrap();
+    let results = runtime.block_on(future).unwrap();
+
+    assert_eq!(results.len(), 3);
+    assert!(results[0].is_ok());
+    assert!(results[1].is_ok());
+
+    // Each result says where the message was published
+    for (index, result) in results[0..2].iter().enumerate() {
+        let published = result.as_ref().unwrap();
+        assert_eq!(published.node_id, test_cluster.node.node_id);
+        assert_eq!(published.ledger_message_count, index + 1);
+    }
+
+    match &results[2] {
+        Err(ClientError::Error(_, error_code)) => assert_eq!(*error_code, ERROR_CODE_BACKLOG_FULL),
+        _ => panic!("Publishing to a full subscription should fail"),
+    }
+
+    // The messages that succeeded were published in the order of the batch
+    let consumed = runtime
+        .block_on(
+            client
+                .consume(topic_id, subscription_id, &None, 10)
+                .unwrap(),
+        )
+        .unwrap();
+    let keys: Vec<_> = consumed
+        .messages
+        .iter()
+        .map(|message| message.message_key.as_str())
+        .collect();
+    assert_eq!(keys, vec!["key1", "key2"]);
+
+    drop(client);
+    app.stop_signal.store(true, Ordering::Relaxed);
+    server_handle.join().unwrap();
+}
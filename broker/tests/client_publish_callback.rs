@@ -0,0 +1,66 @@
+use pulsar_rust_broker::test_support::{serve, ClusterBuilder};
+use pulsar_rust_client::{non_blocking::Client, BufferPool, MessageId};
+use std::{
+    collections::{HashMap, HashSet},
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::Duration,
+};
+use tokio::runtime::Runtime;
+
+const PUBSUB_PORT: u16 = 18031;
+const MESSAGE_COUNT: usize = 10;
+
+#[test]
+fn should_invoke_callback_for_each_publish() {
+    let test_cluster = ClusterBuilder::new("127.0.0.1")
+        .ports(18030, PUBSUB_PORT, 18032)
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic_id = test_cluster.topics[0].topic.topic_id;
+
+    let app = test_cluster.app();
+
+    let server_handle = serve(&app, SocketAddrV4::new(Ipv4Addr::LOCALHOST, PUBSUB_PORT));
+
+    let buffer_pool = Arc::new(BufferPool::new());
+    let mut client = Client::new(&buffer_pool, &format!("127.0.0.1:{PUBSUB_PORT}"));
+    client.connect().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    let published: Arc<Mutex<Vec<MessageId>>> = Arc::new(Mutex::new(Vec::new()));
+    let callback_published = published.clone();
+    client.on_publish(move |result| match result {
+        Ok(publish_result) => callback_published
+            .lock()
+            .unwrap()
+            .push(publish_result.message_ref.message_id),
+        Err(_) => panic!("Publish should succeed"),
+    });
+
+    let futures: Vec<_> = (0..MESSAGE_COUNT)
+        .map(|index| {
+            client
+                .publish(topic_id, Some(index.to_string()), None, HashMap::new())
+                .unwrap()
+        })
+        .collect();
+
+    runtime
+        .block_on(client.flush(Duration::from_secs(5)))
+        .unwrap();
+
+    // The callback was invoked once for each publish with the same result as the future
+    let callback_ids: HashSet<MessageId> = published.lock().unwrap().iter().copied().collect();
+    let future_ids: HashSet<MessageId> = futures
+        .into_iter()
+        .map(|future| runtime.block_on(future).unwrap().message_ref.message_id)
+        .collect();
+    assert_eq!(published.lock().unwrap().len(), MESSAGE_COUNT);
+    assert_eq!(callback_ids, future_ids);
+
+    drop(client);
+    app.stop_signal.store(true, Ordering::Relaxed);
+    server_handle.join().unwrap();
+}
@@ -0,0 +1,63 @@
+use pulsar_rust_broker::test_support::{serve, ClusterBuilder};
+use pulsar_rust_client::{blocking::Client, BufferPool};
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::{atomic::Ordering, Arc},
+};
+
+const PUBSUB_PORT: u16 = 18101;
+
+#[test]
+fn should_quarantine_message_from_client() {
+    let test_cluster = ClusterBuilder::new("127.0.0.1")
+        .ports(18100, PUBSUB_PORT, 18102)
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic_id = test_cluster.topics[0].topic.topic_id;
+    let subscription_id = test_cluster.topics[0].subscriptions[0].subscription_id;
+
+    let app = test_cluster.app();
+
+    let server_handle = serve(&app, SocketAddrV4::new(Ipv4Addr::LOCALHOST, PUBSUB_PORT));
+
+    let buffer_pool = Arc::new(BufferPool::new());
+    let mut client = Client::new(&buffer_pool, &format!("127.0.0.1:{PUBSUB_PORT}"));
+    client.connect().unwrap();
+
+    client
+        .publish(topic_id, Some("key1".to_owned()), None, HashMap::new())
+        .unwrap();
+
+    let consumed = client.consume(topic_id, subscription_id, None, 1).unwrap();
+    assert_eq!(consumed.messages.len(), 1);
+    let message_ref_key = consumed.messages[0].message_ref_key.clone();
+
+    let result = client
+        .quarantine(
+            &message_ref_key,
+            subscription_id,
+            consumed.consumer_id,
+            "Payload is not valid JSON",
+        )
+        .unwrap();
+    assert!(result.success);
+
+    // The message has left the subscription, so it is not redelivered
+    let consumed_again = client
+        .consume(topic_id, subscription_id, Some(consumed.consumer_id), 1)
+        .unwrap();
+    assert_eq!(consumed_again.messages.len(), 0);
+
+    let quarantined = app
+        .sub_service
+        .quarantined_messages(topic_id, subscription_id);
+    assert_eq!(quarantined.len(), 1);
+    assert_eq!(quarantined[0].message.message_ref.to_key(), message_ref_key);
+    assert_eq!(quarantined[0].reason, "Payload is not valid JSON");
+
+    drop(client);
+    app.stop_signal.store(true, Ordering::Relaxed);
+    server_handle.join().unwrap();
+}
@@ -0,0 +1,81 @@
+use pulsar_rust_broker::{
+    data::DataLayer,
+    model::cluster::Cluster,
+    persistence::{PersistenceLayer, PersistenceScheme},
+    test_support::{serve, test_app},
+};
+use pulsar_rust_client::{blocking::Client, BufferPool};
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::{atomic::Ordering, Arc},
+};
+
+const PUBSUB_PORT: u16 = 18001;
+
+#[test]
+fn should_resume_as_same_consumer_after_reconnect() {
+    let persistence = Arc::new(PersistenceLayer::new(
+        PersistenceScheme::InMemory,
+        PersistenceScheme::InMemory,
+    ));
+    let data_layer = Arc::new(DataLayer::new("local".to_owned(), &persistence));
+
+    let node = data_layer
+        .add_node("127.0.0.1", 18000, PUBSUB_PORT, 18002)
+        .unwrap();
+    let topic = data_layer.add_topic("topic1").unwrap();
+    let partition = data_layer
+        .add_partition(topic.topic_id, node.node_id)
+        .unwrap();
+    data_layer
+        .add_ledger(topic.topic_id, partition.partition_id, node.node_id)
+        .unwrap();
+    let subscription = data_layer
+        .add_subscription(topic.topic_id, "subscription1", false)
+        .unwrap();
+
+    let cluster = Arc::new(Cluster::new(&data_layer, "127.0.0.1"));
+    let app = test_app(&persistence, &cluster);
+
+    let server_handle = serve(&app, SocketAddrV4::new(Ipv4Addr::LOCALHOST, PUBSUB_PORT));
+
+    let buffer_pool = Arc::new(BufferPool::new());
+    let mut client = Client::new(&buffer_pool, &format!("127.0.0.1:{PUBSUB_PORT}"));
+    client.connect().unwrap();
+
+    for _ in 0..2 {
+        client
+            .publish(topic.topic_id, None, None, HashMap::new())
+            .unwrap();
+    }
+
+    let first = client
+        .consume(topic.topic_id, subscription.subscription_id, None, 1)
+        .unwrap();
+    assert_eq!(first.messages.len(), 1);
+
+    client.reconnect().unwrap();
+
+    let second = client
+        .consume(topic.topic_id, subscription.subscription_id, None, 1)
+        .unwrap();
+    assert_eq!(second.consumer_id, first.consumer_id);
+    assert_eq!(second.messages.len(), 1);
+    assert_ne!(
+        second.messages[0].message_ref_key,
+        first.messages[0].message_ref_key
+    );
+
+    client
+        .ack(
+            &second.messages[0].message_ref_key,
+            subscription.subscription_id,
+            second.consumer_id,
+        )
+        .unwrap();
+
+    client.disconnect();
+    app.stop_signal.store(true, Ordering::Relaxed);
+    server_handle.join().unwrap();
+}
@@ -0,0 +1,171 @@
+use pulsar_rust_broker::{
+    data::DataLayer,
+    model::cluster::Cluster,
+    persistence::{PersistenceLayer, PersistenceScheme},
+    test_support::{serve, test_app},
+    App,
+};
+use pulsar_rust_client::{blocking, contracts::ClientError, non_blocking, BufferPool};
+use pulsar_rust_net::data_types::{SubscriptionId, TopicId};
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::{atomic::Ordering, Arc},
+};
+use tokio::runtime::Runtime;
+
+const WRONG_PUBSUB_PORT: u16 = 18207;
+const OWNER_PUBSUB_PORT: u16 = 18208;
+
+// Nothing listens on this node, so a client that follows a redirect to it fails to connect
+const MOVED_IP: &str = "127.0.0.3";
+const MOVED_PUBSUB_PORT: u16 = 18215;
+
+fn app(persistence: &Arc<PersistenceLayer>, data_layer: &Arc<DataLayer>, ip: &str) -> Arc<App> {
+    let cluster = Arc::new(Cluster::new(data_layer, ip));
+    test_app(persistence, &cluster)
+}
+
+/// The topics that both brokers serve. Topic 1 is owned by the owner node. Topic 2 is owned
+/// by the owner node too, unless it was moved to another node. Brokers with different
+/// metadata disagree about where topic 2 is, like they do while a partition is being moved
+struct Metadata {
+    persistence: Arc<PersistenceLayer>,
+    data_layer: Arc<DataLayer>,
+    topic_id: TopicId,
+    subscription_id: SubscriptionId,
+    topic2_id: TopicId,
+}
+
+fn metadata(topic2_moved: bool) -> Metadata {
+    let persistence = Arc::new(PersistenceLayer::new(
+        PersistenceScheme::InMemory,
+        PersistenceScheme::InMemory,
+    ));
+    let data_layer = Arc::new(DataLayer::new("local".to_owned(), &persistence));
+
+    // Both brokers listen on the loopback address, the wrong node is only registered with
+    // another address so that it does not find itself as the owner when it starts
+    data_layer
+        .add_node("127.0.0.2", 18209, WRONG_PUBSUB_PORT, 18210)
+        .unwrap();
+    let owner = data_layer
+        .add_node("127.0.0.1", 18211, OWNER_PUBSUB_PORT, 18212)
+        .unwrap();
+    let moved = data_layer
+        .add_node(MOVED_IP, 18213, MOVED_PUBSUB_PORT, 18214)
+        .unwrap();
+
+    let topic = data_layer.add_topic("topic1").unwrap();
+    let partition = data_layer
+        .add_partition(topic.topic_id, owner.node_id)
+        .unwrap();
+    data_layer
+        .add_ledger(topic.topic_id, partition.partition_id, owner.node_id)
+        .unwrap();
+    let subscription = data_layer
+        .add_subscription(topic.topic_id, "subscription1", false)
+        .unwrap();
+
+    let topic2_node_id = if topic2_moved {
+        moved.node_id
+    } else {
+        owner.node_id
+    };
+    let topic2 = data_layer.add_topic("topic2").unwrap();
+    let partition2 = data_layer
+        .add_partition(topic2.topic_id, topic2_node_id)
+        .unwrap();
+    data_layer
+        .add_ledger(topic2.topic_id, partition2.partition_id, topic2_node_id)
+        .unwrap();
+    data_layer
+        .add_subscription(topic2.topic_id, "subscription1", false)
+        .unwrap();
+
+    Metadata {
+        persistence,
+        data_layer,
+        topic_id: topic.topic_id,
+        subscription_id: subscription.subscription_id,
+        topic2_id: topic2.topic_id,
+    }
+}
+
+#[test]
+fn should_follow_redirect_to_the_node_that_owns_the_partition() {
+    // The wrong node thinks that topic 2 is on the owner node, but the owner knows that it
+    // moved, so requests for topic 2 are redirected twice
+    let wrong = metadata(false);
+    let owner = metadata(true);
+    let topic = wrong.topic_id;
+    let subscription = wrong.subscription_id;
+    let moved_topic = wrong.topic2_id;
+
+    let wrong_app = app(&wrong.persistence, &wrong.data_layer, "127.0.0.2");
+    let owner_app = app(&owner.persistence, &owner.data_layer, "127.0.0.1");
+    let wrong_handle = serve(
+        &wrong_app,
+        SocketAddrV4::new(Ipv4Addr::LOCALHOST, WRONG_PUBSUB_PORT),
+    );
+    let owner_handle = serve(
+        &owner_app,
+        SocketAddrV4::new(Ipv4Addr::LOCALHOST, OWNER_PUBSUB_PORT),
+    );
+
+    let buffer_pool = Arc::new(BufferPool::new());
+    let authority = format!("127.0.0.1:{WRONG_PUBSUB_PORT}");
+
+    let mut client = blocking::Client::new(&buffer_pool, &authority);
+    client.connect().unwrap();
+    client.publish(topic, None, None, HashMap::new()).unwrap();
+    let consumed = client.consume(topic, subscription, None, 10).unwrap();
+    assert_eq!(consumed.messages.len(), 1);
+
+    // The redirected client is not allowed to redirect again
+    let moved_authority = format!("{MOVED_IP}:{MOVED_PUBSUB_PORT}");
+    match client.publish(moved_topic, None, None, HashMap::new()) {
+        Err(ClientError::IncorrectNode(Some(redirect))) => assert_eq!(redirect, moved_authority),
+        other => panic!("Expected an incorrect node error, got {other:?}"),
+    }
+    client.disconnect();
+
+    // Without redirects the application is told which node owns the partition
+    let mut client = blocking::Client::new(&buffer_pool, &authority).with_max_redirects(0);
+    client.connect().unwrap();
+    match client.publish(topic, None, None, HashMap::new()) {
+        Err(ClientError::IncorrectNode(Some(redirect))) => {
+            assert_eq!(redirect, format!("127.0.0.1:{OWNER_PUBSUB_PORT}"))
+        }
+        other => panic!("Expected an incorrect node error, got {other:?}"),
+    }
+    client.disconnect();
+
+    let runtime = Runtime::new().unwrap();
+    let mut client = non_blocking::Client::new(&buffer_pool, &authority);
+    client.connect().unwrap();
+    runtime.block_on(async {
+        let future = client.publish(topic, None, None, HashMap::new()).unwrap();
+        future.await.unwrap();
+
+        let future = client.consume(topic, subscription, &None, 10).unwrap();
+        let consumed = future.await.unwrap();
+        assert_eq!(consumed.messages.len(), 1);
+
+        let future = client
+            .publish(moved_topic, None, None, HashMap::new())
+            .unwrap();
+        match future.await {
+            Err(ClientError::IncorrectNode(Some(redirect))) => {
+                assert_eq!(redirect, moved_authority)
+            }
+            other => panic!("Expected an incorrect node error, got {other:?}"),
+        }
+    });
+
+    drop(client);
+    wrong_app.stop_signal.store(true, Ordering::Relaxed);
+    owner_app.stop_signal.store(true, Ordering::Relaxed);
+    wrong_handle.join().unwrap();
+    owner_handle.join().unwrap();
+}
@@ -0,0 +1,69 @@
+use pulsar_rust_broker::{
+    api_bin::{self, RequestLimits},
+    test_support::{serve_with, ClusterBuilder},
+};
+use pulsar_rust_client::{
+    contracts::ClientError, non_blocking::Client, BufferPool, ERROR_CODE_REQUEST_TOO_LARGE,
+};
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::{atomic::Ordering, Arc},
+};
+use tokio::runtime::Runtime;
+
+const PUBSUB_PORT: u16 = 18041;
+
+#[test]
+fn should_reject_oversize_publish_requests() {
+    let test_cluster = ClusterBuilder::new("127.0.0.1")
+        .ports(18040, PUBSUB_PORT, 18042)
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic_id = test_cluster.topics[0].topic.topic_id;
+
+    let app = test_cluster.app();
+
+    let request_limits = RequestLimits {
+        max_publish_bytes: 100,
+        ..RequestLimits::default()
+    };
+    let server_handle = serve_with(&app, |app| {
+        api_bin::serve_with_limits(
+            app,
+            SocketAddrV4::new(Ipv4Addr::LOCALHOST, PUBSUB_PORT),
+            request_limits,
+        )
+    });
+
+    let buffer_pool = Arc::new(BufferPool::new());
+    let mut client = Client::new(&buffer_pool, &format!("127.0.0.1:{PUBSUB_PORT}"));
+    client.connect().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    // A request within the limit is accepted
+    let mut attributes = HashMap::new();
+    attributes.insert("data".to_owned(), "x".repeat(50));
+    let future = client
+        .publish(topic_id, Some("key".to_owned()), None, attributes)
+        .unwrap();
+    assert!(runtime.block_on(future).is_ok());
+
+    // A request that fits in a frame but exceeds the limit is rejected
+    let mut attributes = HashMap::new();
+    attributes.insert("data".to_owned(), "x".repeat(300));
+    let future = client
+        .publish(topic_id, Some("key".to_owned()), None, attributes)
+        .unwrap();
+    match runtime.block_on(future) {
+        Err(ClientError::Error(_, error_code)) => {
+            assert_eq!(error_code, ERROR_CODE_REQUEST_TOO_LARGE)
+        }
+        _ => panic!("Oversize publish request should be rejected"),
+    }
+
+    drop(client);
+    app.stop_signal.store(true, Ordering::Relaxed);
+    server_handle.join().unwrap();
+}
@@ -0,0 +1,118 @@
+use pulsar_rust_broker::{
+    data::DataLayer,
+    model::cluster::Cluster,
+    persistence::{PersistenceLayer, PersistenceScheme},
+    test_support::{serve, test_app},
+};
+use pulsar_rust_client::{non_blocking::Client, BufferPool};
+use std::{
+    collections::{HashMap, HashSet},
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::{atomic::Ordering, Arc},
+    thread,
+};
+use tokio::runtime::Runtime;
+
+const PUBSUB_PORT: u16 = 18011;
+const SESSION_COUNT: usize = 4;
+const MESSAGES_PER_SESSION: usize = 5;
+
+#[test]
+fn should_multiplex_sessions_over_one_connection() {
+    let persistence = Arc::new(PersistenceLayer::new(
+        PersistenceScheme::InMemory,
+        PersistenceScheme::InMemory,
+    ));
+    let data_layer = Arc::new(DataLayer::new("local".to_owned(), &persistence));
+
+    let node = data_layer
+        .add_node("127.0.0.1", 18010, PUBSUB_PORT, 18012)
+        .unwrap();
+    let topic = data_layer.add_topic("topic1").unwrap();
+    data_layer
+        .add_partition(topic.topic_id, node.node_id)
+        .unwrap();
+    let subscription = data_layer
+        .add_subscription(topic.topic_id, "subscription1", false)
+        .unwrap();
+
+    let cluster = Arc::new(Cluster::new(&data_layer, "127.0.0.1"));
+    let app = test_app(&persistence, &cluster);
+
+    let server_handle = serve(&app, SocketAddrV4::new(Ipv4Addr::LOCALHOST, PUBSUB_PORT));
+
+    let buffer_pool = Arc::new(BufferPool::new());
+    let mut client = Client::new(&buffer_pool, &format!("127.0.0.1:{PUBSUB_PORT}"));
+    client.connect().unwrap();
+    let client = Arc::new(client);
+    let runtime = Arc::new(Runtime::new().unwrap());
+
+    // Each session publishes concurrently and waits for its own responses
+    let publishers: Vec<_> = (0..SESSION_COUNT)
+        .map(|index| {
+            let session = client.open_session();
+            let runtime = runtime.clone();
+            let topic_id = topic.topic_id;
+            thread::spawn(move || {
+                for message in 0..MESSAGES_PER_SESSION {
+                    let key = format!("{index}-{message}");
+                    let future = session
+                        .publish(topic_id, Some(key), None, HashMap::new())
+                        .unwrap();
+                    let result = runtime.block_on(future).unwrap();
+                    assert_eq!(result.message_ref.topic_id, topic_id);
+                }
+                session.session_id()
+            })
+        })
+        .collect();
+    let session_ids: HashSet<_> = publishers
+        .into_iter()
+        .map(|publisher| publisher.join().unwrap())
+        .collect();
+    assert_eq!(session_ids.len(), SESSION_COUNT);
+
+    // Each session is allocated its own consumer by the broker
+    let consumers: Vec<_> = (0..SESSION_COUNT)
+        .map(|_| {
+            let session = client.open_session();
+            let runtime = runtime.clone();
+            let topic_id = topic.topic_id;
+            let subscription_id = subscription.subscription_id;
+            thread::spawn(move || {
+                let future = session
+                    .consume(topic_id, subscription_id, &None, MESSAGES_PER_SESSION as u8)
+                    .unwrap();
+                let result = runtime.block_on(future).unwrap();
+                for message in &result.messages {
+                    let future = session
+                        .ack(
+                            &message.message_ref_key,
+                            subscription_id,
+                            result.consumer_id,
+                        )
+                        .unwrap();
+                    runtime.block_on(future).unwrap();
+                }
+                (result.consumer_id, result.messages.len())
+            })
+        })
+        .collect();
+    let results: Vec<_> = consumers
+        .into_iter()
+        .map(|consumer| consumer.join().unwrap())
+        .collect();
+
+    let consumer_ids: HashSet<_> = results
+        .iter()
+        .map(|(consumer_id, _)| *consumer_id)
+        .collect();
+    assert_eq!(consumer_ids.len(), SESSION_COUNT);
+
+    let consumed_count: usize = results.iter().map(|(_, count)| *count).sum();
+    assert_eq!(consumed_count, SESSION_COUNT * MESSAGES_PER_SESSION);
+
+    drop(client);
+    app.stop_signal.store(true, Ordering::Relaxed);
+    server_handle.join().unwrap();
+}
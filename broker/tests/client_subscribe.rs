@@ -0,0 +1,85 @@
+use pulsar_rust_broker::test_support::{serve, ClusterBuilder};
+use pulsar_rust_client::{
+    contracts::ClientError,
+    non_blocking::{Client, Subscriber},
+    BufferPool,
+};
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+use tokio::runtime::Runtime;
+
+const PUBSUB_PORT: u16 = 18191;
+
+fn receive(subscriber: &Subscriber, runtime: &Runtime, count: usize) {
+    let mut received = 0;
+    while received < count {
+        let result = subscriber.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(subscriber.consumer_id(), Some(result.consumer_id));
+        for message in &result.messages {
+            let future = subscriber
+                .session()
+                .ack(
+                    &message.message_ref_key,
+                    subscriber.subscription_id(),
+                    result.consumer_id,
+                )
+                .unwrap();
+            runtime.block_on(future).unwrap();
+        }
+        received += result.messages.len();
+    }
+    assert_eq!(received, count);
+}
+
+#[test]
+fn should_push_messages_while_the_subscriber_has_credits() {
+    let test_cluster = ClusterBuilder::new("127.0.0.1")
+        .ports(18190, PUBSUB_PORT, 18192)
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic_id = test_cluster.topics[0].topic.topic_id;
+    let subscription_id = test_cluster.topics[0].subscriptions[0].subscription_id;
+
+    let app = test_cluster.app();
+
+    let server_handle = serve(&app, SocketAddrV4::new(Ipv4Addr::LOCALHOST, PUBSUB_PORT));
+
+    let buffer_pool = Arc::new(BufferPool::new());
+    let mut client = Client::new(&buffer_pool, &format!("127.0.0.1:{PUBSUB_PORT}"));
+    client.connect().unwrap();
+    let client = Arc::new(client);
+    let runtime = Runtime::new().unwrap();
+
+    for i in 0..6 {
+        let future = client
+            .publish(topic_id, Some(format!("key{i}")), None, HashMap::new())
+            .unwrap();
+        runtime.block_on(future).unwrap();
+    }
+
+    let subscriber = client.subscribe(topic_id, subscription_id, 4).unwrap();
+    receive(&subscriber, &runtime, 4);
+
+    // Nothing more is pushed until the subscriber grants more credits
+    assert!(matches!(
+        subscriber.recv_timeout(Duration::from_millis(300)),
+        Err(ClientError::Timeout)
+    ));
+
+    // Every credit was used, so the subscriber has just the credits that it granted
+    let flow = runtime.block_on(subscriber.flow(2).unwrap()).unwrap();
+    assert_eq!(flow.credits, 2);
+    receive(&subscriber, &runtime, 2);
+
+    runtime.block_on(subscriber.close().unwrap()).unwrap();
+
+    drop(subscriber);
+    drop(client);
+    app.stop_signal.store(true, Ordering::Relaxed);
+    server_handle.join().unwrap();
+}
@@ -0,0 +1,60 @@
+use pulsar_rust_broker::test_support::{serve, ClusterBuilder};
+use pulsar_rust_client::{codec::CodecRegistry, non_blocking::Client, BufferPool};
+use serde::{Deserialize, Serialize};
+use std::{
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::{atomic::Ordering, Arc},
+};
+use tokio::runtime::Runtime;
+
+const PUBSUB_PORT: u16 = 18231;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct Order {
+    order_number: String,
+    quantity: u32,
+}
+
+#[test]
+fn should_consume_typed_payloads_with_the_async_client() {
+    let test_cluster = ClusterBuilder::new("127.0.0.1")
+        .ports(18230, PUBSUB_PORT, 18232)
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic_id = test_cluster.topics[0].topic.topic_id;
+    let subscription_id = test_cluster.topics[0].subscriptions[0].subscription_id;
+
+    let app = test_cluster.app();
+
+    let server_handle = serve(&app, SocketAddrV4::new(Ipv4Addr::LOCALHOST, PUBSUB_PORT));
+
+    let buffer_pool = Arc::new(BufferPool::new());
+    let runtime = Runtime::new().unwrap();
+    let mut client = Client::new(&buffer_pool, &format!("127.0.0.1:{PUBSUB_PORT}"));
+    client.connect().unwrap();
+
+    let codecs = CodecRegistry::<Order>::json();
+    let order = Order {
+        order_number: String::from("ABC123"),
+        quantity: 3,
+    };
+    let future = client
+        .publish_typed(topic_id, None, None, &order, &codecs)
+        .unwrap();
+    runtime.block_on(future).unwrap();
+
+    let result = runtime
+        .block_on(client.consume_typed(topic_id, subscription_id, &None, 10, &codecs))
+        .unwrap();
+    assert_eq!(result.messages.len(), 1);
+    assert_eq!(result.messages[0].payload, order);
+    assert_eq!(
+        result.messages[0].message.headers.content_type.as_deref(),
+        Some("application/json")
+    );
+
+    drop(client);
+    app.stop_signal.store(true, Ordering::Relaxed);
+    server_handle.join().unwrap();
+}
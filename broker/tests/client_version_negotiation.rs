@@ -0,0 +1,136 @@
+use pulsar_rust_broker::{
+    api_bin::{self, RequestLimits},
+    test_support::{serve, serve_with, ClusterBuilder},
+};
+use pulsar_rust_client::{
+    blocking, contracts::ClientError, non_blocking, versions::VersionOptions, BufferPool,
+};
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::{atomic::Ordering, Arc},
+};
+use tokio::runtime::Runtime;
+
+const PUBSUB_PORT: u16 = 18081;
+
+#[test]
+fn should_fail_with_typed_error_when_broker_version_is_too_low() {
+    let test_cluster = ClusterBuilder::new("127.0.0.1")
+        .ports(18080, PUBSUB_PORT, 18082)
+        .topic("topic1", 1)
+        .build();
+
+    let app = test_cluster.app();
+
+    let server_handle = serve(&app, SocketAddrV4::new(Ipv4Addr::LOCALHOST, PUBSUB_PORT));
+
+    let buffer_pool = Arc::new(BufferPool::new());
+    let authority = format!("127.0.0.1:{PUBSUB_PORT}");
+
+    // The broker supports versions 1 and 2, so a client that needs version 3 or later
+    // fails cleanly, even after falling back through its range
+    let too_new = VersionOptions {
+        min_version: 3,
+        max_version: 4,
+        fallback: true,
+    };
+
+    let mut client = blocking::Client::new(&buffer_pool, &authority).with_version_options(too_new);
+    match client.connect() {
+        Err(ClientError::IncompatibleVersion) => (),
+        other => panic!("Expected an incompatible version error, got {other:?}"),
+    }
+    assert!(!client.is_connected());
+
+    let mut client =
+        non_blocking::Client::new(&buffer_pool, &authority).with_version_options(too_new);
+    match client.connect() {
+        Err(ClientError::IncompatibleVersion) => (),
+        other => panic!("Expected an incompatible version error, got {other:?}"),
+    }
+    assert!(!client.is_connected());
+
+    // A client whose range overlaps the broker's connects with the highest shared version
+    let overlapping = VersionOptions {
+        min_version: 1,
+        max_version: 3,
+        fallback: true,
+    };
+    let mut client =
+        blocking::Client::new(&buffer_pool, &authority).with_version_options(overlapping);
+    client.connect().unwrap();
+    assert!(client.is_connected());
+    assert_eq!(client.contract_version(), Some(2));
+
+    drop(client);
+    app.stop_signal.store(true, Ordering::Relaxed);
+    server_handle.join().unwrap();
+}
+
+#[test]
+fn should_negotiate_the_highest_version_that_both_ends_support() {
+    let test_cluster = ClusterBuilder::new("127.0.0.1")
+        .ports(18203, 18204, 18205)
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic_id = test_cluster.topics[0].topic.topic_id;
+
+    let app = test_cluster.app();
+
+    // One broker supports version 2, the other is limited to version 1
+    let current_handle = serve(&app, SocketAddrV4::new(Ipv4Addr::LOCALHOST, 18204));
+    let v1_only_limits = RequestLimits {
+        max_contract_version: 1,
+        ..RequestLimits::default()
+    };
+    let v1_only_handle = serve_with(&app, |app| {
+        api_bin::serve_with_limits(
+            app,
+            SocketAddrV4::new(Ipv4Addr::LOCALHOST, 18206),
+            v1_only_limits,
+        )
+    });
+
+    let buffer_pool = Arc::new(BufferPool::new());
+    let runtime = Runtime::new().unwrap();
+
+    // Clients offer versions 1 to 2 by default. Publishing uses the version 2 contract when
+    // it was negotiated, and the version 1 contract otherwise
+    for (port, expected_version) in [(18204, 2), (18206, 1)] {
+        let authority = format!("127.0.0.1:{port}");
+
+        let mut client = blocking::Client::new(&buffer_pool, &authority);
+        client.connect().unwrap();
+        assert_eq!(client.contract_version(), Some(expected_version));
+        client
+            .publish(topic_id, None, None, HashMap::new())
+            .unwrap();
+
+        let mut client = non_blocking::Client::new(&buffer_pool, &authority);
+        client.connect().unwrap();
+        assert_eq!(client.contract_version(), Some(expected_version));
+        let future = client
+            .publish(topic_id, None, None, HashMap::new())
+            .unwrap();
+        runtime.block_on(future).unwrap();
+    }
+
+    // A client that only accepts version 2 can not use the broker that is limited to version 1
+    let v2_only = VersionOptions {
+        min_version: 2,
+        max_version: 2,
+        fallback: false,
+    };
+    let mut client =
+        blocking::Client::new(&buffer_pool, "127.0.0.1:18206").with_version_options(v2_only);
+    match client.connect() {
+        Err(ClientError::IncompatibleVersion) => (),
+        other => panic!("Expected an incompatible version error, got {other:?}"),
+    }
+
+    app.stop_signal.store(true, Ordering::Relaxed);
+    current_handle.join().unwrap();
+    v1_only_handle.join().unwrap();
+}
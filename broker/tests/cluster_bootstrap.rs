@@ -0,0 +1,70 @@
+use pulsar_rust_broker::{
+    data::DataLayer,
+    model::cluster::{BootstrapError, Cluster, NodeBootstrap},
+    persistence::{PersistenceLayer, PersistenceScheme},
+};
+use std::sync::Arc;
+
+fn empty_data_layer() -> Arc<DataLayer> {
+    let persistence = Arc::new(PersistenceLayer::new(
+        PersistenceScheme::InMemory,
+        PersistenceScheme::InMemory,
+    ));
+    Arc::new(DataLayer::new("local".to_owned(), &persistence))
+}
+
+#[test]
+fn should_register_node_on_first_start() {
+    let data_layer = empty_data_layer();
+    let bootstrap = NodeBootstrap {
+        ip_address: "10.0.0.1".to_owned(),
+        admin_port: 9000,
+        pubsub_port: 9001,
+        sync_port: 9002,
+    };
+
+    let cluster = Cluster::bootstrap(&data_layer, &bootstrap).unwrap();
+
+    let my_node = cluster.my_node();
+    assert_eq!(my_node.ip_address(), "10.0.0.1");
+    assert_eq!(my_node.admin_port(), 9000);
+    assert_eq!(my_node.pubsub_port(), 9001);
+    assert_eq!(my_node.sync_port(), 9002);
+    assert_eq!(cluster.topics().values().len(), 0);
+
+    let nodes = data_layer.get_nodes().unwrap();
+    assert_eq!(nodes.len(), 1);
+    assert_eq!(nodes[0].node_id, cluster.my_node_id());
+
+    // Restarting the node finds the existing registration
+    let restarted = Cluster::bootstrap(&data_layer, &bootstrap).unwrap();
+    assert_eq!(restarted.my_node_id(), cluster.my_node_id());
+    assert_eq!(data_layer.get_nodes().unwrap().len(), 1);
+
+    // Another node joining the same cluster registers separately
+    let other = Cluster::bootstrap(&data_layer, &NodeBootstrap::new("10.0.0.2")).unwrap();
+    assert_ne!(other.my_node_id(), cluster.my_node_id());
+    assert_eq!(other.nodes().values().len(), 2);
+}
+
+#[test]
+fn should_explain_missing_bootstrap_information() {
+    let data_layer = empty_data_layer();
+
+    match Cluster::bootstrap(&data_layer, &NodeBootstrap::new("")) {
+        Err(err) => assert_eq!(err, BootstrapError::MissingIpAddress),
+        Ok(_) => panic!("Bootstrapped without an IP address"),
+    }
+
+    match Cluster::bootstrap(&data_layer, &NodeBootstrap::new("my-host")) {
+        Err(err) => assert_eq!(
+            err,
+            BootstrapError::InvalidIpAddress {
+                ip_address: "my-host".to_owned()
+            }
+        ),
+        Ok(_) => panic!("Bootstrapped with an invalid IP address"),
+    }
+
+    assert_eq!(data_layer.get_nodes().unwrap().len(), 0);
+}
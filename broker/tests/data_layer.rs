@@ -1,5 +1,6 @@
 use pulsar_rust_broker::{
     data::{DataLayer, DataReadError},
+    observability::Metrics,
     persistence::{
         entity_persister::{LoadError, LoadResult},
         persisted_entities::{Ledger, Partition, Subscription, Topic},
@@ -247,3 +248,83 @@ fn should_cascade_delete_topic() {
         })
     );
 }
+
+#[test]
+fn should_spread_default_partitions_across_nodes() {
+    let persistence = Arc::new(PersistenceLayer::new(
+        PersistenceScheme::InMemory,
+        PersistenceScheme::InMemory,
+    ));
+    let data_layer =
+        DataLayer::new("local".to_owned(), &persistence).with_default_partition_count(4);
+
+    let node1 = data_layer.add_node("127.0.0.1", 8000, 8001, 8002).unwrap();
+    let node2 = data_layer.add_node("127.0.0.2", 8000, 8001, 8002).unwrap();
+
+    let topic = data_layer
+        .add_topic_with_default_partitions("topic1")
+        .unwrap();
+    assert_eq!(topic.partition_ids.len(), 4);
+
+    let partitions = data_layer.get_partitions(&topic).unwrap();
+    let node_ids: Vec<NodeId> = partitions.iter().map(|p| p.node_id).collect();
+    assert_eq!(
+        node_ids,
+        vec![node1.node_id, node2.node_id, node1.node_id, node2.node_id]
+    );
+
+    for partition in &partitions {
+        assert_eq!(partition.ledger_ids.len(), 1);
+    }
+}
+
+#[test]
+fn should_count_ledger_rollovers_per_partition() {
+    let persistence = Arc::new(PersistenceLayer::new(
+        PersistenceScheme::InMemory,
+        PersistenceScheme::InMemory,
+    ));
+    let metrics = Arc::new(Metrics::new());
+    let data_layer = DataLayer::new("local".to_owned(), &persistence)
+        .with_metrics(&metrics)
+        .with_ledger_rollover_warning(2, 60_000);
+
+    let node = data_layer.add_node("127.0.0.1", 8000, 8001, 8002).unwrap();
+    let topic = data_layer.add_topic("topic1").unwrap();
+    let partition1 = data_layer
+        .add_partition(topic.topic_id, node.node_id)
+        .unwrap();
+    let partition2 = data_layer
+        .add_partition(topic.topic_id, node.node_id)
+        .unwrap();
+
+    let metric = Metrics::METRIC_LEDGER_CREATE_COUNT;
+    let partition1_metric =
+        Metrics::partition_metric(metric, topic.topic_id, partition1.partition_id);
+    let partition2_metric =
+        Metrics::partition_metric(metric, topic.topic_id, partition2.partition_id);
+
+    // Force more rollovers than the warning threshold
+    for _ in 0..4 {
+        data_layer
+            .add_ledger(topic.topic_id, partition1.partition_id, node.node_id)
+            .unwrap();
+    }
+    assert_eq!(metrics.unsent_count(&partition1_metric), 4.0);
+    assert_eq!(metrics.unsent_count(&partition2_metric), 0.0);
+
+    // Only creating a ledger is counted, finding an existing one is not
+    data_layer
+        .add_ledger_if_none(topic.topic_id, partition2.partition_id)
+        .unwrap();
+    data_layer
+        .add_ledger_if_none(topic.topic_id, partition2.partition_id)
+        .unwrap();
+    assert_eq!(metrics.unsent_count(&partition2_metric), 1.0);
+
+    assert_eq!(
+        metrics.unsent_count(&Metrics::topic_metric(metric, topic.topic_id)),
+        5.0
+    );
+    assert_eq!(metrics.unsent_count(metric), 5.0);
+}
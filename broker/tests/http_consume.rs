@@ -0,0 +1,72 @@
+use pulsar_rust_broker::{
+    api_http,
+    test_support::{published_message, ClusterBuilder},
+};
+use pulsar_rust_net::contracts::v1::{
+    requests::{self},
+    responses,
+};
+
+#[tokio::test]
+async fn should_stream_consumed_messages_as_ndjson() {
+    let test_cluster = ClusterBuilder::new("127.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic_id = test_cluster.topics[0].topic.topic_id;
+    let partition_id = test_cluster.topics[0].partitions[0].partition.partition_id;
+    let subscription_id = test_cluster.topics[0].subscriptions[0].subscription_id;
+
+    let app = test_cluster.app();
+
+    for key in ["1", "2", "3"] {
+        if app
+            .pub_service
+            .publish_message(published_message(topic_id, partition_id, key))
+            .is_err()
+        {
+            panic!("Publish request failed");
+        }
+    }
+
+    let response = warp::test::request()
+        .method("POST")
+        .path("/v1/sub/consumer")
+        .header("accept", "application/x-ndjson")
+        .json(&requests::Consume {
+            topic_id,
+            subscription_id,
+            consumer_id: None,
+            max_messages: 10,
+            ack_previous: false,
+            max_bytes: None,
+        })
+        .reply(&api_http::routes(&app))
+        .await;
+
+    assert_eq!(
+        response.headers().get("content-type").unwrap(),
+        "application/x-ndjson"
+    );
+
+    let body = String::from_utf8(response.body().to_vec()).unwrap();
+    let lines: Vec<&str> = body.lines().collect();
+    assert_eq!(lines.len(), 4);
+
+    // The first line is the response without the messages
+    let header: responses::Response<responses::ConsumeResult> =
+        serde_json::from_str(lines[0]).unwrap();
+    let result = header.data.unwrap();
+    assert!(result.messages.is_empty());
+
+    // Each of the following lines is one message
+    let keys: Vec<String> = lines[1..]
+        .iter()
+        .map(|line| {
+            serde_json::from_str::<responses::Message>(line)
+                .unwrap()
+                .message_key
+        })
+        .collect();
+    assert_eq!(keys, vec!["1", "2", "3"]);
+}
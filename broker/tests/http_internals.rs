@@ -0,0 +1,80 @@
+use pulsar_rust_broker::{
+    api_http,
+    test_support::{serve, ClusterBuilder},
+};
+use pulsar_rust_client::{blocking::Client, BufferPool};
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::{atomic::Ordering, Arc},
+};
+
+const PUBSUB_PORT: u16 = 18161;
+
+#[tokio::test]
+async fn should_report_internals_after_traffic() {
+    let test_cluster = ClusterBuilder::new("127.0.0.1")
+        .ports(18160, PUBSUB_PORT, 18162)
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic_id = test_cluster.topics[0].topic.topic_id;
+
+    let app = test_cluster.app();
+
+    let server_handle = serve(&app, SocketAddrV4::new(Ipv4Addr::LOCALHOST, PUBSUB_PORT));
+
+    let buffer_pool = Arc::new(BufferPool::new());
+    let mut client = Client::new(&buffer_pool, &format!("127.0.0.1:{PUBSUB_PORT}"));
+    client.connect().unwrap();
+
+    for i in 0..5 {
+        client
+            .publish(topic_id, Some(format!("key{i}")), None, HashMap::new())
+            .unwrap();
+    }
+
+    let response = warp::test::request()
+        .method("GET")
+        .path("/v1/internals")
+        .reply(&api_http::routes(&app))
+        .await;
+    assert_eq!(response.status(), 200);
+
+    let internals: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert!(internals["buffers_in_use"].is_u64());
+    assert!(internals["buffers_pooled"].is_u64());
+    assert!(internals["buffers_dropped"].is_u64());
+
+    // Each publish serialized a response, so buffers were taken from the pool
+    let hits = internals["buffer_pool_hits"].as_u64().unwrap();
+    let misses = internals["buffer_pool_misses"].as_u64().unwrap();
+    assert!(hits + misses >= 5);
+
+    // Every publish was answered, so nothing is waiting in the processing queues
+    let processing_queue_depths = internals["processing_queue_depths"].as_array().unwrap();
+    assert!(!processing_queue_depths.is_empty());
+    assert!(processing_queue_depths
+        .iter()
+        .all(|depth| depth.as_u64() == Some(0)));
+
+    let connections = internals["connections"].as_array().unwrap();
+    assert_eq!(connections.len(), 1);
+    assert_eq!(connections[0]["connection_id"], 1);
+    assert_eq!(connections[0]["send_queue_depth"], 0);
+    assert_eq!(connections[0]["receive_queue_depth"], 0);
+
+    let threads = &internals["threads"];
+    assert_eq!(threads["listener"], 1);
+    assert_eq!(threads["router"], 1);
+    assert_eq!(threads["connection"], 1);
+    assert_eq!(threads["processing_pool"], 1);
+    assert_eq!(
+        threads["processing"].as_u64().unwrap() as usize,
+        processing_queue_depths.len()
+    );
+
+    drop(client);
+    app.stop_signal.store(true, Ordering::Relaxed);
+    server_handle.join().unwrap();
+}
@@ -0,0 +1,191 @@
+use pulsar_rust_broker::{
+    api_http,
+    model::messages::{MessageRef, PublishedMessage},
+    persistence::{
+        log_entries::LoggedEvent,
+        logged_events::{
+            AckEvent, AdminAckEvent, DeadLetterEvent, DropConsumerEvent, ExpiryEvent,
+            KeyAffinityEvent, NackEvent, NewConsumerEvent, PartitionReassignedEvent, PublishEvent,
+            QuarantineEvent,
+        },
+    },
+    test_support::ClusterBuilder,
+};
+use pulsar_rust_net::contracts::v1::requests::MessageHeaders;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::atomic::Ordering,
+    time::Duration,
+};
+
+#[tokio::test]
+async fn should_tag_log_entry_details_in_json() {
+    let test_cluster = ClusterBuilder::new("127.0.0.1").build();
+    let persistence = &test_cluster.persistence;
+    let app = test_cluster.app();
+
+    let message_ref = MessageRef {
+        topic_id: 1,
+        partition_id: 1,
+        ledger_id: 1,
+        message_id: 1,
+    };
+    let message = PublishedMessage {
+        message_ref,
+        key: "key1".to_owned(),
+        timestamp: 1,
+        published: 1,
+        priority: 0,
+        attributes: HashMap::new(),
+        subscriber_count: 1,
+        ack_count: 0,
+        headers: MessageHeaders::default(),
+        producer_id: None,
+        sequence_number: None,
+        deliver_at: None,
+    };
+
+    // One event of each kind that can be written to the log
+    let events = vec![
+        LoggedEvent::Publish(PublishEvent::new(&message)),
+        LoggedEvent::Ack(AckEvent::new(message_ref, 1, 1)),
+        LoggedEvent::AdminAck(AdminAckEvent::new(message_ref, 1)),
+        LoggedEvent::Nack(NackEvent::new(message_ref, 1, 1)),
+        LoggedEvent::NewConsumer(NewConsumerEvent {
+            topic_id: 1,
+            subscription_id: 1,
+            consumer_id: 1,
+        }),
+        LoggedEvent::DropConsumer(DropConsumerEvent {
+            topic_id: 1,
+            subscription_id: 1,
+            consumer_id: 1,
+        }),
+        LoggedEvent::KeyAffinity(KeyAffinityEvent {
+            topic_id: 1,
+            subscription_id: 1,
+            consumer_id: 1,
+            message_key: "key1".to_owned(),
+        }),
+        LoggedEvent::Quarantine(QuarantineEvent::new(message_ref, 1, 1, "poison")),
+        LoggedEvent::Expiry(ExpiryEvent::new(message_ref, 1)),
+        LoggedEvent::DeadLetter(DeadLetterEvent::new(message_ref, 1, 1, message_ref)),
+        LoggedEvent::PartitionReassigned(PartitionReassignedEvent {
+            topic_id: 1,
+            partition_id: 1,
+            ledger_id: 2,
+            from_node_id: 1,
+            to_node_id: 2,
+        }),
+    ];
+    for event in events.iter() {
+        persistence.log_event(event).unwrap();
+    }
+
+    let response = warp::test::request()
+        .method("GET")
+        .path("/v1/logs?detailed=true&limit=100")
+        .header("Accept", "application/json")
+        .reply(&api_http::routes(&app))
+        .await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        response.headers()["content-type"].to_str().unwrap(),
+        "application/json"
+    );
+
+    let entries: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    let entries = entries.as_array().unwrap();
+    assert_eq!(entries.len(), events.len());
+
+    let mut types = HashSet::new();
+    for entry in entries {
+        let details = &entry["details"];
+        assert!(details["detail"].is_object());
+
+        // The discriminator is the same as the event type of the entry
+        let detail_type = details["type"].as_str().unwrap();
+        assert_eq!(entry["event_type"].as_str().unwrap(), detail_type);
+        types.insert(detail_type.to_owned());
+    }
+
+    let expected: HashSet<String> = [
+        "Publish",
+        "Ack",
+        "AdminAck",
+        "Nack",
+        "NewConsumer",
+        "DropConsumer",
+        "KeyAffinity",
+        "Quarantine",
+        "Expiry",
+        "DeadLetter",
+        "PartitionReassigned",
+    ]
+    .iter()
+    .map(|detail_type| detail_type.to_string())
+    .collect();
+    assert_eq!(types, expected);
+}
+
+#[tokio::test]
+async fn should_stream_new_log_entries_for_a_topic() {
+    let test_cluster = ClusterBuilder::new("127.0.0.1").build();
+    let app = test_cluster.app();
+
+    let message_ref = |topic_id| MessageRef {
+        topic_id,
+        partition_id: 1,
+        ledger_id: 1,
+        message_id: 1,
+    };
+
+    // Only events that are logged after the stream starts are sent
+    test_cluster
+        .persistence
+        .log_event(&LoggedEvent::Ack(AckEvent::new(message_ref(1), 1, 1)))
+        .unwrap();
+
+    let logger = {
+        let app = app.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            for topic_id in [1, 2, 1] {
+                app.peristence
+                    .log_event(&LoggedEvent::Nack(NackEvent::new(
+                        message_ref(topic_id),
+                        1,
+                        1,
+                    )))
+                    .unwrap();
+            }
+
+            // The stream ends when the broker stops
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            app.stop_signal.store(true, Ordering::Relaxed);
+        })
+    };
+
+    let response = warp::test::request()
+        .method("GET")
+        .path("/v1/logs/stream/topic/1")
+        .reply(&api_http::routes(&app))
+        .await;
+    logger.await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        response.headers()["content-type"].to_str().unwrap(),
+        "application/x-ndjson"
+    );
+
+    let lines: Vec<serde_json::Value> = std::str::from_utf8(response.body())
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    for line in lines {
+        assert_eq!(line["event_type"], "Nack");
+        assert_eq!(line["event_key"], "1:1:1:1");
+    }
+}
@@ -0,0 +1,93 @@
+use pulsar_rust_broker::{
+    api_http,
+    test_support::{published_message, ClusterBuilder},
+    App,
+};
+use pulsar_rust_net::contracts::v1::responses::{self, RequestOutcome};
+use serde::de::DeserializeOwned;
+use std::sync::Arc;
+
+#[tokio::test]
+async fn should_return_subscription_stats() {
+    let test_cluster = ClusterBuilder::new("127.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .subscription("subscription2", false)
+        .build();
+    let topic_id = test_cluster.topics[0].topic.topic_id;
+    let partition_id = test_cluster.topics[0].partitions[0].partition.partition_id;
+    let subscription1_id = test_cluster.topics[0].subscriptions[0].subscription_id;
+    let subscription2_id = test_cluster.topics[0].subscriptions[1].subscription_id;
+
+    let app = test_cluster.app();
+
+    for key in ["1", "2", "3"] {
+        if app
+            .pub_service
+            .publish_message(published_message(topic_id, partition_id, key))
+            .is_err()
+        {
+            panic!("Publish request failed");
+        }
+    }
+
+    // One message is delivered to subscription 1 and not acked
+    if app
+        .sub_service
+        .consume_max_messages(topic_id, subscription1_id, None, 1)
+        .is_err()
+    {
+        panic!("Consume request failed");
+    }
+
+    let stats: responses::SubscriptionStats = get_data(
+        &app,
+        &format!("/v1/sub/topic/{topic_id}/subscription/{subscription1_id}/stats"),
+    )
+    .await;
+    assert_eq!(stats.subscription_id, subscription1_id);
+    assert_eq!(stats.queued_count, 2);
+    assert_eq!(stats.unacked_count, 1);
+
+    let stats: responses::TopicSubscriptionStats =
+        get_data(&app, &format!("/v1/sub/topic/{topic_id}/stats")).await;
+    assert_eq!(stats.topic_id, topic_id);
+    assert_eq!(stats.subscriptions.len(), 2);
+    assert_eq!(stats.queued_count, 5);
+    assert_eq!(stats.unacked_count, 1);
+    let subscription2 = stats
+        .subscriptions
+        .iter()
+        .find(|stats| stats.subscription_id == subscription2_id)
+        .unwrap();
+    assert_eq!(subscription2.queued_count, 3);
+    assert_eq!(subscription2.unacked_count, 0);
+
+    let outcome = get_outcome::<responses::SubscriptionStats>(
+        &app,
+        &format!("/v1/sub/topic/{topic_id}/subscription/999/stats"),
+    )
+    .await;
+    assert!(matches!(outcome, RequestOutcome::Warning(_)));
+
+    let outcome =
+        get_outcome::<responses::TopicSubscriptionStats>(&app, "/v1/sub/topic/999/stats").await;
+    assert!(matches!(outcome, RequestOutcome::Warning(_)));
+}
+
+async fn get_response<T: DeserializeOwned>(app: &Arc<App>, path: &str) -> responses::Response<T> {
+    let response = warp::test::request()
+        .method("GET")
+        .path(path)
+        .reply(&api_http::routes(app))
+        .await;
+    serde_json::from_slice(response.body()).unwrap()
+}
+
+async fn get_data<T: DeserializeOwned>(app: &Arc<App>, path: &str) -> T {
+    get_response(app, path).await.data.unwrap()
+}
+
+async fn get_outcome<T: DeserializeOwned>(app: &Arc<App>, path: &str) -> RequestOutcome {
+    get_response::<T>(app, path).await.outcome
+}
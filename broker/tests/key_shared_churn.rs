@@ -0,0 +1,218 @@
+use pulsar_rust_broker::{
+    services::{pub_service::PubService, sub_service::SubService},
+    test_support::{published_message, ClusterBuilder},
+};
+use pulsar_rust_net::data_types::{ConsumerId, MessageCount};
+use std::collections::{HashMap, VecDeque};
+
+const KEY_COUNT: usize = 8;
+const MESSAGES_PER_KEY: usize = 50;
+const CONSUMER_COUNT: usize = 4;
+const MAX_STEPS: usize = 50_000;
+
+#[test]
+fn should_keep_keys_with_one_consumer_under_churn() {
+    for seed in [1, 42, 9001] {
+        churn(seed, false);
+    }
+}
+
+#[test]
+fn should_ack_keys_in_order_under_churn_with_strict_ordering() {
+    for seed in [1, 42, 9001] {
+        churn(seed, true);
+    }
+}
+
+/// Deterministic xorshift generator, so that a failing seed can be replayed
+struct Random(u64);
+
+impl Random {
+    fn below(self: &mut Self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
+
+/// A message that was delivered to a consumer and not yet acked or nacked
+struct InFlight {
+    key: String,
+    sequence: usize,
+    message_ref_key: String,
+}
+
+#[derive(Default)]
+struct Consumer {
+    consumer_id: Option<ConsumerId>,
+    in_flight: VecDeque<InFlight>,
+}
+
+/// Publishes messages with several keys while consumers randomly consume, ack, nack,
+/// disconnect and reconnect. Checks that two messages with the same key are never in-flight
+/// with different consumers, and that every message is eventually acked exactly once. With
+/// strict ordering, also checks that messages with the same key are delivered and acked in
+/// the order that they were published
+fn churn(seed: u64, strict_ordering: bool) {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", true)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let subscription_id = test_cluster.topics[0].subscriptions[0].subscription_id;
+
+    if strict_ordering {
+        test_cluster
+            .data_layer
+            .set_subscription_strict_ordering(topic.topic_id, subscription_id, true)
+            .unwrap();
+    }
+
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+    let subscription = cluster
+        .topics()
+        .get(&topic.topic_id)
+        .unwrap()
+        .subscriptions()
+        .get(&subscription_id)
+        .unwrap();
+
+    let mut random = Random(seed);
+    let mut consumers: Vec<Consumer> = (0..CONSUMER_COUNT).map(|_| Consumer::default()).collect();
+    let mut published = 0;
+
+    // The consumer and number of in-flight messages for each key
+    let mut owners: HashMap<String, (ConsumerId, usize)> = HashMap::new();
+
+    // The sequence numbers of the acked messages for each key, in the order they were acked
+    let mut acked: HashMap<String, Vec<usize>> = HashMap::new();
+
+    let total = KEY_COUNT * MESSAGES_PER_KEY;
+    let mut acked_count = 0;
+    let mut steps = 0;
+    while acked_count < total {
+        steps += 1;
+        assert!(
+            steps < MAX_STEPS,
+            "seed {seed}: messages were not all acked"
+        );
+
+        // Publish in bursts, so that consumers join and leave while keys are in-flight
+        if published < total && random.below(4) == 0 {
+            for _ in 0..random.below(10) + 1 {
+                if published == total {
+                    break;
+                }
+                let key = format!("key{}", published % KEY_COUNT);
+                let sequence = published / KEY_COUNT;
+                let mut message = published_message(topic.topic_id, partition.partition_id, &key);
+                message
+                    .attributes
+                    .insert("sequence".to_owned(), sequence.to_string());
+                if pub_service.publish_message(message).is_err() {
+                    panic!("Publish request failed");
+                }
+                published += 1;
+            }
+        }
+
+        // Once everything is published, stop disconnecting so that the backlog drains
+        let draining = published == total && steps > MAX_STEPS / 2;
+        let consumer = &mut consumers[random.below(CONSUMER_COUNT)];
+        let action = random.below(10);
+
+        if action < 4 {
+            let consumed = match sub_service.consume_max_messages(
+                topic.topic_id,
+                subscription_id,
+                consumer.consumer_id,
+                random.below(5) as MessageCount + 1,
+            ) {
+                Ok(consumed) => consumed,
+                Err(_) => panic!("Consume request failed"),
+            };
+            let consumer_id = consumed.consumer_id;
+            consumer.consumer_id = Some(consumer_id);
+
+            for message in consumed.messages {
+                let key = message.published_message.key.clone();
+                let sequence: usize = message.published_message.attributes["sequence"]
+                    .parse()
+                    .unwrap();
+
+                let owner = owners.entry(key.clone()).or_insert((consumer_id, 0));
+                assert!(
+                    owner.1 == 0 || owner.0 == consumer_id,
+                    "seed {seed}: {key} delivered to consumer {consumer_id} while in-flight with consumer {}",
+                    owner.0
+                );
+                if strict_ordering {
+                    assert_eq!(owner.1, 0, "seed {seed}: two messages with {key} in-flight");
+                    let expected = acked.get(&key).map_or(0, |sequences| sequences.len());
+                    assert_eq!(
+                        sequence, expected,
+                        "seed {seed}: {key} delivered out of order"
+                    );
+                }
+                *owner = (consumer_id, owner.1 + 1);
+
+                consumer.in_flight.push_back(InFlight {
+                    key,
+                    sequence,
+                    message_ref_key: message.subscribed_message.message_ref_key,
+                });
+            }
+        } else if action < 9 || draining {
+            // Consumers process their messages in the order that they were delivered
+            let consumer_id = match consumer.consumer_id {
+                Some(consumer_id) => consumer_id,
+                None => continue,
+            };
+            let message = match consumer.in_flight.pop_front() {
+                Some(message) => message,
+                None => continue,
+            };
+            owners.get_mut(&message.key).unwrap().1 -= 1;
+
+            if random.below(5) == 0 {
+                match sub_service.nack(message.message_ref_key, subscription_id, consumer_id) {
+                    Ok(nacked) => assert!(nacked, "seed {seed}: nack was not applied"),
+                    Err(_) => panic!("Nack request failed"),
+                }
+            } else {
+                match sub_service.ack(message.message_ref_key, subscription_id, consumer_id) {
+                    Ok(acked) => assert!(acked, "seed {seed}: ack was not applied"),
+                    Err(_) => panic!("Ack request failed"),
+                }
+                acked.entry(message.key).or_default().push(message.sequence);
+                acked_count += 1;
+            }
+        } else if let Some(consumer_id) = consumer.consumer_id.take() {
+            // Messages that were not acked are redelivered, possibly to another consumer
+            subscription.disconnect_consumer(consumer_id);
+            for message in consumer.in_flight.drain(..) {
+                owners.get_mut(&message.key).unwrap().1 -= 1;
+            }
+        }
+    }
+
+    for (key, sequences) in acked {
+        let mut sorted = sequences.clone();
+        sorted.sort_unstable();
+        assert_eq!(
+            sorted,
+            (0..MESSAGES_PER_KEY).collect::<Vec<_>>(),
+            "seed {seed}: {key} was not acked exactly once"
+        );
+        if strict_ordering {
+            assert_eq!(
+                sequences, sorted,
+                "seed {seed}: {key} was acked out of order"
+            );
+        }
+    }
+}
@@ -1,9 +1,13 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
 
 use pulsar_rust_broker::{
     model::messages::{MessageRef, PublishedMessage},
     persistence::{
-        entity_persister::{LoadError, LoadResult},
+        entity_persister::{LoadError, LoadResult, SaveError},
         event_logger::EventQueryOptions,
         log_entries::{LogEntry, LoggedEvent},
         logged_events::{AckEvent, NackEvent, PublishEvent},
@@ -11,7 +15,7 @@ use pulsar_rust_broker::{
         Keyed, PersistenceLayer, PersistenceScheme,
     },
 };
-use pulsar_rust_net::data_types::NodeId;
+use pulsar_rust_net::{contracts::v1::requests::MessageHeaders, data_types::NodeId};
 
 #[test]
 fn should_persist_entities_in_memory() {
@@ -64,9 +68,14 @@ fn should_persist_events_in_memory() {
         key: "".to_owned(),
         timestamp: 8773873,
         published: 9839845,
+        priority: 0,
         attributes: HashMap::new(),
         subscriber_count: 1,
         ack_count: 0,
+        headers: MessageHeaders::default(),
+        producer_id: None,
+        sequence_number: None,
+        deliver_at: None,
     };
 
     persistence
@@ -149,9 +158,14 @@ fn should_selectively_delete_events() {
                                 key: String::default(),
                                 timestamp: 0,
                                 published: 0,
+                                priority: 0,
                                 attributes: HashMap::new(),
                                 subscriber_count: 0,
                                 ack_count: 0,
+                                headers: MessageHeaders::default(),
+                                producer_id: None,
+                                sequence_number: None,
+                                deliver_at: None,
                             },
                         )))
                         .unwrap();
@@ -224,3 +238,206 @@ fn should_selectively_delete_events() {
     assert_eq!(entries2.len(), 20);
     assert_eq!(entries3.len(), 40);
 }
+
+fn temp_data_dir(name: &str) -> PathBuf {
+    let data_dir = env::temp_dir().join(format!("pulsar_rust_{name}_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&data_dir);
+    data_dir
+}
+
+fn file_persistence(data_dir: &Path) -> PersistenceLayer {
+    PersistenceLayer::with_data_dir(
+        PersistenceScheme::FileSystem,
+        PersistenceScheme::FileSystem,
+        0,
+        None,
+        data_dir,
+    )
+}
+
+#[test]
+fn should_persist_entities_in_files() {
+    let data_dir = temp_data_dir("entities");
+    let persistence = file_persistence(&data_dir);
+
+    let node_id: NodeId = 99;
+    let node_key = Node::key(node_id);
+    let mut saved_node = Node::new(node_id, "127.0.0.1", 8000, 8001, 8002);
+
+    persistence.save(&mut saved_node).unwrap();
+    assert_eq!(saved_node.version, 1);
+
+    saved_node.ip_address = "10.2.45.6".to_owned();
+    persistence.save(&mut saved_node).unwrap();
+    assert_eq!(saved_node.version, 2);
+
+    let mut stale_node = Node::new(node_id, "127.0.0.1", 8000, 8001, 8002);
+    stale_node.version = 1;
+    assert_eq!(
+        persistence.save(&mut stale_node),
+        Err(SaveError::VersionMissmatch)
+    );
+
+    // Entities are still there after the broker restarts
+    drop(persistence);
+    let persistence = file_persistence(&data_dir);
+
+    let loaded_node: Node = persistence.load(&node_key).unwrap();
+
+    assert_eq!(saved_node.node_id, loaded_node.node_id);
+    assert_eq!(saved_node.version, loaded_node.version);
+    assert_eq!(saved_node.ip_address, loaded_node.ip_address);
+
+    persistence.delete(&node_key).unwrap();
+
+    let not_found_result: LoadResult<Node> = persistence.load(&node_key);
+    assert_eq!(
+        not_found_result,
+        Err(LoadError::NotFound {
+            entity_type: node_key.type_name().to_string(),
+            entity_key: node_key.key()
+        })
+    );
+
+    fs::remove_dir_all(&data_dir).unwrap();
+}
+
+#[test]
+fn should_persist_events_in_files() {
+    let data_dir = temp_data_dir("events");
+    let persistence = file_persistence(&data_dir);
+
+    let message_ref = MessageRef {
+        topic_id: 1,
+        partition_id: 16,
+        ledger_id: 12,
+        message_id: 544,
+    };
+
+    let message = PublishedMessage {
+        message_ref,
+        key: "".to_owned(),
+        timestamp: 8773873,
+        published: 9839845,
+        priority: 0,
+        attributes: HashMap::new(),
+        subscriber_count: 1,
+        ack_count: 0,
+        headers: MessageHeaders::default(),
+        producer_id: None,
+        sequence_number: None,
+        deliver_at: None,
+    };
+
+    persistence
+        .log_with_timestamp(&LoggedEvent::Publish(PublishEvent::new(&message)), 1)
+        .unwrap();
+    persistence
+        .log_with_timestamp(&LoggedEvent::Ack(AckEvent::new(message_ref, 1, 10)), 2)
+        .unwrap();
+    persistence
+        .log_with_timestamp(&LoggedEvent::Nack(NackEvent::new(message_ref, 2, 11)), 3)
+        .unwrap();
+    persistence.flush_events().unwrap();
+
+    // Events are still there after the broker restarts, and new events are appended
+    drop(persistence);
+    let persistence = file_persistence(&data_dir);
+    persistence
+        .log_with_timestamp(&LoggedEvent::Nack(NackEvent::new(message_ref, 3, 12)), 4)
+        .unwrap();
+
+    let prefix =
+        PersistenceLayer::build_partition_prefix(message_ref.topic_id, message_ref.partition_id);
+
+    let options = EventQueryOptions::default();
+    let events: Vec<LogEntry> = persistence
+        .events_by_key_prefix(&prefix, &options)
+        .collect();
+
+    assert_eq!(events.len(), 4);
+    assert_eq!(events[0].timestamp, 4);
+    assert_eq!(events[0].type_name, "Nack");
+    assert_eq!(events[0].key, "1:16:12:544");
+    assert_eq!(events[3].timestamp, 1);
+
+    let options = EventQueryOptions::replay();
+    let events: Vec<LogEntry> = persistence
+        .events_by_key_prefix(&prefix, &options)
+        .collect();
+
+    assert_eq!(events.len(), 4);
+    match events[0].deserialize() {
+        Some(LoggedEvent::Publish(publish)) => assert_eq!(publish.key(), "1:16:12:544"),
+        _ => panic!("Expected a publish event"),
+    }
+
+    persistence.delete_events_before(3).unwrap();
+    let events: Vec<LogEntry> = persistence
+        .events_by_key_prefix(&prefix, &options)
+        .collect();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].timestamp, 3);
+
+    fs::remove_dir_all(&data_dir).unwrap();
+}
+
+fn should_page_through_events_in_time_range(persistence: &PersistenceLayer) {
+    // Events for two topics are interleaved, so the offset must only count the events that
+    // match the prefix
+    for timestamp in 1..=10u64 {
+        let message_ref = MessageRef {
+            topic_id: 1 + (timestamp % 2) as u32,
+            partition_id: 1,
+            ledger_id: 1,
+            message_id: timestamp as u32,
+        };
+        persistence
+            .log_with_timestamp(
+                &LoggedEvent::Ack(AckEvent::new(message_ref, 1, 1)),
+                timestamp,
+            )
+            .unwrap();
+    }
+
+    let prefix = PersistenceLayer::build_topic_prefix(2);
+    let timestamps = |options: &EventQueryOptions| -> Vec<u64> {
+        persistence
+            .events_by_key_prefix(&prefix, options)
+            .map(|entry| entry.timestamp)
+            .collect()
+    };
+
+    let mut options = EventQueryOptions::default();
+    options.from_timestamp = Some(3);
+    options.to_timestamp = Some(9);
+    assert_eq!(timestamps(&options), vec![7, 5, 3]);
+
+    options.skip = 1;
+    options.take = 1;
+    assert_eq!(timestamps(&options), vec![5]);
+
+    options.descending = false;
+    assert_eq!(timestamps(&options), vec![5]);
+
+    options.skip = 2;
+    options.take = 2;
+    assert_eq!(timestamps(&options), vec![7]);
+}
+
+#[test]
+fn should_page_through_events_in_memory() {
+    let persistence =
+        PersistenceLayer::new(PersistenceScheme::InMemory, PersistenceScheme::InMemory);
+    should_page_through_events_in_time_range(&persistence);
+}
+
+#[test]
+fn should_page_through_events_in_files() {
+    let data_dir = temp_data_dir("paged_events");
+    let persistence = file_persistence(&data_dir);
+    should_page_through_events_in_time_range(&persistence);
+
+    drop(persistence);
+    fs::remove_dir_all(&data_dir).unwrap();
+}
@@ -0,0 +1,559 @@
+use pulsar_rust_broker::{
+    data::DataLayer,
+    model::{
+        cluster::Cluster,
+        messages::{MessageRef, PublishedMessage},
+    },
+    persistence::{
+        event_logger::EventQueryOptions, log_entries::LoggedEvent,
+        persisted_entities::QueueOverflowPolicy, PersistenceLayer, PersistenceScheme,
+    },
+    services::{
+        pub_service::{PubError, PubService, IDEMPOTENCY_KEY_ATTRIBUTE},
+        sub_service::SubService,
+    },
+    test_support::ClusterBuilder,
+};
+use pulsar_rust_net::{
+    contracts::v1::requests::{self, MessageHeaders, PublishAckLevel},
+    data_types::{PartitionId, SubscriptionId, TopicId},
+};
+use std::{collections::HashMap, sync::Arc, thread, time::Duration};
+
+fn message(topic_id: TopicId, partition_id: PartitionId, key: &str) -> PublishedMessage {
+    PublishedMessage {
+        message_ref: MessageRef {
+            topic_id,
+            partition_id,
+            ledger_id: 0,
+            message_id: 0,
+        },
+        key: key.to_owned(),
+        timestamp: 0,
+        published: 0,
+        priority: 0,
+        attributes: HashMap::new(),
+        subscriber_count: 0,
+        ack_count: 0,
+        headers: MessageHeaders::default(),
+        producer_id: None,
+        sequence_number: None,
+        deliver_at: None,
+    }
+}
+
+fn consumed_keys(
+    sub_service: &SubService,
+    topic_id: TopicId,
+    subscription_id: SubscriptionId,
+) -> Vec<String> {
+    match sub_service.consume_max_messages(topic_id, subscription_id, None, 10) {
+        Ok(consumed_messages) => consumed_messages
+            .messages
+            .iter()
+            .map(|message| message.published_message.key.clone())
+            .collect(),
+        Err(_) => panic!("Consume request failed"),
+    }
+}
+
+#[test]
+fn should_apply_queue_limit_per_subscription() {
+    let persistence = Arc::new(PersistenceLayer::new(
+        PersistenceScheme::InMemory,
+        PersistenceScheme::InMemory,
+    ));
+    let data_layer = Arc::new(DataLayer::new("local".to_owned(), &persistence));
+
+    let node = data_layer.add_node("10.0.0.1", 8000, 8001, 8002).unwrap();
+    let topic = data_layer.add_topic("topic1").unwrap();
+    let partition = data_layer
+        .add_partition(topic.topic_id, node.node_id)
+        .unwrap();
+    data_layer
+        .add_ledger(topic.topic_id, partition.partition_id, node.node_id)
+        .unwrap();
+
+    let rejecting = data_layer
+        .add_subscription(topic.topic_id, "rejecting", false)
+        .unwrap();
+    let dropping = data_layer
+        .add_subscription(topic.topic_id, "dropping", false)
+        .unwrap();
+    let unlimited = data_layer
+        .add_subscription(topic.topic_id, "unlimited", false)
+        .unwrap();
+
+    data_layer
+        .set_subscription_queue_limit(
+            topic.topic_id,
+            rejecting.subscription_id,
+            2,
+            QueueOverflowPolicy::Reject,
+        )
+        .unwrap();
+    data_layer
+        .set_subscription_queue_limit(
+            topic.topic_id,
+            dropping.subscription_id,
+            2,
+            QueueOverflowPolicy::DropOldest,
+        )
+        .unwrap();
+
+    let cluster = Arc::new(Cluster::new(&data_layer, "10.0.0.1"));
+    let pub_service = PubService::new(&persistence, &cluster);
+    let sub_service = SubService::new(&persistence, &cluster);
+
+    for key in ["1", "2", "3", "4"] {
+        if pub_service
+            .publish_message(message(topic.topic_id, partition.partition_id, key))
+            .is_err()
+        {
+            panic!("Publish should succeed when subscriptions do not block publishers");
+        }
+    }
+
+    assert_eq!(
+        consumed_keys(&sub_service, topic.topic_id, rejecting.subscription_id),
+        vec!["1", "2"]
+    );
+    assert_eq!(
+        consumed_keys(&sub_service, topic.topic_id, dropping.subscription_id),
+        vec!["3", "4"]
+    );
+    assert_eq!(
+        consumed_keys(&sub_service, topic.topic_id, unlimited.subscription_id),
+        vec!["1", "2", "3", "4"]
+    );
+}
+
+#[test]
+fn should_block_publisher_when_subscription_is_full() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("blocking", true)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let subscription = &test_cluster.topics[0].subscriptions[0];
+
+    test_cluster
+        .data_layer
+        .set_subscription_queue_limit(
+            topic.topic_id,
+            subscription.subscription_id,
+            1,
+            QueueOverflowPolicy::Block,
+        )
+        .unwrap();
+
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+
+    if pub_service
+        .publish_message(message(topic.topic_id, partition.partition_id, "1"))
+        .is_err()
+    {
+        panic!("First publish should succeed");
+    }
+
+    match pub_service.publish_message(message(topic.topic_id, partition.partition_id, "2")) {
+        Err(PubError::BacklogCapacityExceeded) => {}
+        _ => panic!("Publish should be blocked while the subscription is full"),
+    }
+
+    assert_eq!(
+        consumed_keys(&sub_service, topic.topic_id, subscription.subscription_id),
+        vec!["1"]
+    );
+
+    if pub_service
+        .publish_message(message(topic.topic_id, partition.partition_id, "3"))
+        .is_err()
+    {
+        panic!("Publish should succeed after the subscription queue is drained");
+    }
+}
+
+#[test]
+fn should_fail_publish_when_no_ledger_is_available() {
+    let persistence = Arc::new(PersistenceLayer::new(
+        PersistenceScheme::InMemory,
+        PersistenceScheme::InMemory,
+    ));
+    let data_layer = Arc::new(DataLayer::new("local".to_owned(), &persistence));
+
+    let node = data_layer.add_node("10.0.0.1", 8000, 8001, 8002).unwrap();
+    let topic = data_layer.add_topic("topic1").unwrap();
+    let partition = data_layer
+        .add_partition(topic.topic_id, node.node_id)
+        .unwrap();
+    data_layer
+        .add_subscription(topic.topic_id, "subscription1", false)
+        .unwrap();
+
+    // Reference a ledger that was never saved, so that obtaining the current ledger
+    // for this partition fails when the cluster is loaded
+    data_layer
+        .update_partition(topic.topic_id, partition.partition_id, |partition| {
+            partition.ledger_ids = vec![5];
+            partition.next_ledger_id = 6;
+            true
+        })
+        .unwrap();
+
+    let cluster = Arc::new(Cluster::new(&data_layer, "10.0.0.1"));
+    let pub_service = PubService::new(&persistence, &cluster);
+
+    match pub_service.publish_message(message(topic.topic_id, partition.partition_id, "1")) {
+        Err(PubError::NoLedger) => {}
+        _ => panic!("Publish should fail when the partition has no ledger"),
+    }
+}
+
+#[test]
+fn should_respond_at_requested_ack_level() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let subscription = &test_cluster.topics[0].subscriptions[0];
+
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+
+    let queued_count = || {
+        cluster
+            .topics()
+            .get(&topic.topic_id)
+            .unwrap()
+            .subscriptions()
+            .get(&subscription.subscription_id)
+            .unwrap()
+            .stats()
+            .queued_count
+    };
+    let logged_count = |message_ref: &MessageRef| {
+        test_cluster
+            .persistence
+            .events_by_key_prefix(&message_ref.to_key(), &EventQueryOptions::default())
+            .count()
+    };
+
+    // With no ack level the response comes before the message is logged or queued
+    let message_ref = match pub_service.publish_message_with_ack_level(
+        message(topic.topic_id, partition.partition_id, "none"),
+        PublishAckLevel::None,
+    ) {
+        Ok(message_ref) => message_ref,
+        Err(_) => panic!("Publish request failed"),
+    };
+    assert_eq!(queued_count(), 0);
+    assert_eq!(logged_count(&message_ref), 0);
+
+    pub_service.flush_publishes();
+    assert_eq!(queued_count(), 1);
+    assert_eq!(logged_count(&message_ref), 1);
+
+    // Queued and persisted levels respond after the message is logged and queued
+    for (level, expected_queued) in [
+        (PublishAckLevel::Queued, 2),
+        (PublishAckLevel::Persisted, 3),
+    ] {
+        let message_ref = match pub_service.publish_message_with_ack_level(
+            message(topic.topic_id, partition.partition_id, "queued"),
+            level,
+        ) {
+            Ok(message_ref) => message_ref,
+            Err(_) => panic!("Publish request failed"),
+        };
+        assert_eq!(queued_count(), expected_queued);
+        assert_eq!(logged_count(&message_ref), 1);
+    }
+}
+
+#[test]
+fn should_complete_deferred_publishes_before_the_next_publish() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let subscription = &test_cluster.topics[0].subscriptions[0];
+
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+
+    for (key, level) in [
+        ("1", PublishAckLevel::None),
+        ("2", PublishAckLevel::None),
+        ("3", PublishAckLevel::Queued),
+    ] {
+        let message = message(topic.topic_id, partition.partition_id, key);
+        if pub_service
+            .publish_message_with_ack_level(message, level)
+            .is_err()
+        {
+            panic!("Publish request failed");
+        }
+    }
+
+    assert_eq!(
+        consumed_keys(&sub_service, topic.topic_id, subscription.subscription_id),
+        vec!["1", "2", "3"]
+    );
+}
+
+#[test]
+fn should_not_log_events_for_ephemeral_topics() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("metrics", 1)
+        .subscription("dashboard", false)
+        .topic("orders", 1)
+        .subscription("fulfilment", false)
+        .build();
+    let ephemeral = &test_cluster.topics[0];
+    let durable = &test_cluster.topics[1];
+
+    test_cluster
+        .data_layer
+        .set_topic_ephemeral(ephemeral.topic.topic_id, true)
+        .unwrap();
+
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+
+    let logged_count = |message_ref: &MessageRef| {
+        test_cluster
+            .persistence
+            .events_by_key_prefix(&message_ref.to_key(), &EventQueryOptions::default())
+            .count()
+    };
+
+    for (test_topic, expected_logged_count) in [(ephemeral, 0), (durable, 2)] {
+        let topic_id = test_topic.topic.topic_id;
+        let partition_id = test_topic.partitions[0].partition.partition_id;
+        let subscription_id = test_topic.subscriptions[0].subscription_id;
+
+        let message_ref = match pub_service.publish_message(message(topic_id, partition_id, "1")) {
+            Ok(message_ref) => message_ref,
+            Err(_) => panic!("Publish request failed"),
+        };
+        let consumed = match sub_service.consume_max_messages(topic_id, subscription_id, None, 1) {
+            Ok(consumed) => consumed,
+            Err(_) => panic!("Consume request failed"),
+        };
+        match sub_service.ack(message_ref.to_key(), subscription_id, consumed.consumer_id) {
+            Ok(true) => {}
+            _ => panic!("Ack request failed"),
+        }
+
+        // The publish and ack are only logged for the durable topic
+        assert_eq!(logged_count(&message_ref), expected_logged_count);
+    }
+}
+
+#[test]
+fn should_not_publish_duplicate_idempotency_keys_across_restart() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("orders", 1)
+        .subscription("fulfilment", false)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+
+    test_cluster
+        .data_layer
+        .set_topic_idempotency_window(topic.topic_id, 60_000)
+        .unwrap();
+
+    let idempotent_message = || {
+        let mut message = message(topic.topic_id, partition.partition_id, "1");
+        message
+            .attributes
+            .insert(IDEMPOTENCY_KEY_ATTRIBUTE.to_owned(), "order-42".to_owned());
+        message
+    };
+
+    let original = {
+        let cluster = test_cluster.cluster();
+        let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+        match pub_service
+            .publish_message_with_ack_level(idempotent_message(), PublishAckLevel::Persisted)
+        {
+            Ok(message_ref) => message_ref,
+            Err(_) => panic!("Publish request failed"),
+        }
+    };
+
+    // After a restart, the duplicate is recognized from the event log
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    match pub_service.publish_message(idempotent_message()) {
+        Ok(message_ref) => assert_eq!(message_ref.to_key(), original.to_key()),
+        Err(_) => panic!("Publish request failed"),
+    }
+
+    // Messages without the key are still published
+    if pub_service
+        .publish_message(message(topic.topic_id, partition.partition_id, "2"))
+        .is_err()
+    {
+        panic!("Publish request failed");
+    }
+
+    let published_count = |key: &str| {
+        test_cluster
+            .persistence
+            .events_by_key_prefix(
+                &PersistenceLayer::build_topic_prefix(topic.topic_id),
+                &EventQueryOptions::replay(),
+            )
+            .filter_map(|log_entry| log_entry.deserialize())
+            .filter(
+                |event| matches!(event, LoggedEvent::Publish(event) if event.message.key == key),
+            )
+            .count()
+    };
+    assert_eq!(published_count("1"), 1);
+    assert_eq!(published_count("2"), 1);
+}
+
+#[test]
+fn should_not_publish_duplicate_producer_sequence_numbers() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("payments", 1)
+        .subscription("ledger", false)
+        .topic("audit", 1)
+        .subscription("archive", false)
+        .build();
+    let deduplicated = &test_cluster.topics[0];
+    let not_deduplicated = &test_cluster.topics[1];
+
+    test_cluster
+        .data_layer
+        .set_topic_sequence_dedup(deduplicated.topic.topic_id, true)
+        .unwrap();
+
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+
+    let numbered_message = |topic_id: TopicId, partition_id: PartitionId, key: &str| {
+        let mut message = message(topic_id, partition_id, key);
+        message.producer_id = Some(99);
+        message.sequence_number = Some(1);
+        message
+    };
+
+    let topic_id = deduplicated.topic.topic_id;
+    let partition_id = deduplicated.partitions[0].partition.partition_id;
+    let original = match pub_service.publish_message(numbered_message(topic_id, partition_id, "1"))
+    {
+        Ok(message_ref) => message_ref,
+        Err(_) => panic!("Publish request failed"),
+    };
+
+    // Sending the same sequence number again returns the original message ref
+    match pub_service.publish_message(numbered_message(topic_id, partition_id, "2")) {
+        Ok(message_ref) => assert_eq!(message_ref.to_key(), original.to_key()),
+        Err(_) => panic!("Publish request failed"),
+    }
+    let subscription_id = deduplicated.subscriptions[0].subscription_id;
+    assert_eq!(
+        consumed_keys(&sub_service, topic_id, subscription_id),
+        vec!["1"]
+    );
+
+    // Topics that do not deduplicate sequence numbers publish both messages
+    let topic_id = not_deduplicated.topic.topic_id;
+    let partition_id = not_deduplicated.partitions[0].partition.partition_id;
+    for key in ["1", "2"] {
+        if pub_service
+            .publish_message(numbered_message(topic_id, partition_id, key))
+            .is_err()
+        {
+            panic!("Publish request failed");
+        }
+    }
+    let subscription_id = not_deduplicated.subscriptions[0].subscription_id;
+    assert_eq!(
+        consumed_keys(&sub_service, topic_id, subscription_id),
+        vec!["1", "2"]
+    );
+}
+
+#[test]
+fn should_hold_delayed_messages_until_their_delivery_time() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("reminders", 1)
+        .subscription("notifier", false)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let subscription = &test_cluster.topics[0].subscriptions[0];
+
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster)
+        .with_delayed_delivery(Duration::from_millis(20));
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+
+    let delayed = requests::Publish {
+        topic_id: topic.topic_id,
+        partition_id: partition.partition_id,
+        key: "later".to_owned(),
+        timestamp: None,
+        priority: None,
+        attributes: HashMap::new(),
+        ack_level: PublishAckLevel::Queued,
+        headers: MessageHeaders::default(),
+        producer_id: None,
+        sequence_number: None,
+        deliver_after_millis: Some(300),
+    };
+    if pub_service.publish_message(delayed.into()).is_err() {
+        panic!("Publish request failed");
+    }
+    if pub_service
+        .publish_message(message(topic.topic_id, partition.partition_id, "now"))
+        .is_err()
+    {
+        panic!("Publish request failed");
+    }
+
+    assert_eq!(
+        consumed_keys(&sub_service, topic.topic_id, subscription.subscription_id),
+        vec!["now"]
+    );
+
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(
+        consumed_keys(&sub_service, topic.topic_id, subscription.subscription_id),
+        vec!["later"]
+    );
+    pub_service.stop();
+
+    // The delayed message is logged when it is published, along with its delivery time
+    let logged = test_cluster
+        .persistence
+        .events_by_key_prefix(
+            &PersistenceLayer::build_topic_prefix(topic.topic_id),
+            &EventQueryOptions::replay(),
+        )
+        .filter_map(|log_entry| log_entry.deserialize())
+        .find_map(|event| match event {
+            LoggedEvent::Publish(event) if event.message.key == "later" => Some(event.message),
+            _ => None,
+        })
+        .expect("The delayed message was not logged");
+    assert!(logged
+        .deliver_at
+        .is_some_and(|deliver_at| deliver_at >= logged.published + 250));
+}
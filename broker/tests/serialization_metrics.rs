@@ -0,0 +1,44 @@
+use pulsar_rust_broker::{
+    observability::Metrics,
+    test_support::{serve, ClusterBuilder},
+};
+use pulsar_rust_net::{bin_serialization::PROTOCOL_VERSION, sockets::MessageLength};
+use std::{
+    io::Write,
+    net::{Ipv4Addr, SocketAddrV4, TcpStream},
+    sync::atomic::Ordering,
+    thread,
+    time::Duration,
+};
+
+const PUBSUB_PORT: u16 = 18071;
+
+#[test]
+fn should_count_requests_that_can_not_be_deserialized() {
+    let test_cluster = ClusterBuilder::new("127.0.0.1")
+        .ports(18070, PUBSUB_PORT, 18072)
+        .topic("topic1", 1)
+        .build();
+
+    let app = test_cluster.app();
+
+    let server_handle = serve(&app, SocketAddrV4::new(Ipv4Addr::LOCALHOST, PUBSUB_PORT));
+
+    let metric = Metrics::METRIC_BIN_DESERIALIZE_ERROR_COUNT;
+    assert_eq!(app.metrics.unsent_count(metric), 0.0);
+
+    // A frame with a valid length prefix, but a body that is not a valid envelope
+    let body = [PROTOCOL_VERSION + 1, 1, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0xc1];
+    let mut stream = TcpStream::connect(("127.0.0.1", PUBSUB_PORT)).unwrap();
+    stream
+        .write_all(&(body.len() as MessageLength).to_le_bytes())
+        .unwrap();
+    stream.write_all(&body).unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    assert_eq!(app.metrics.unsent_count(metric), 1.0);
+
+    drop(stream);
+    app.stop_signal.store(true, Ordering::Relaxed);
+    server_handle.join().unwrap();
+}
@@ -0,0 +1,2277 @@
+use pulsar_rust_broker::{
+    data::DataLayer,
+    model::{
+        cluster::Cluster, ledger::LedgerStats, messages::MessageRef,
+        subscription::SubscriptionStats,
+    },
+    persistence::{
+        event_logger::EventQueryOptions,
+        log_entries::LoggedEvent,
+        persisted_entities::{
+            DeliveryOrder, DeliverySemantics, DeliveryTransform, SubscriptionType,
+        },
+        PersistenceLayer, PersistenceScheme,
+    },
+    services::{
+        pub_service::PubService,
+        sub_service::{
+            ConsumedMessages, ResponseFrame, SerializationErrorPolicy, SubError, SubService,
+            DEAD_LETTER_SOURCE_ATTRIBUTE,
+        },
+    },
+    test_support::{published_message, ClusterBuilder},
+};
+use pulsar_rust_net::{
+    bin_serialization::CompressionScheme, contracts::v1::responses,
+    sockets::tcp_channel::DEFAULT_MAX_MESSAGE_SIZE,
+};
+use std::{sync::Arc, thread, time::Duration};
+
+#[test]
+fn should_direct_consumers_to_remote_partitions() {
+    let persistence = Arc::new(PersistenceLayer::new(
+        PersistenceScheme::InMemory,
+        PersistenceScheme::InMemory,
+    ));
+    let data_layer = Arc::new(DataLayer::new("local".to_owned(), &persistence));
+
+    let local_node = data_layer.add_node("10.0.0.1", 8000, 8001, 8002).unwrap();
+    let remote_node = data_layer.add_node("10.0.0.2", 8000, 8101, 8002).unwrap();
+
+    let topic = data_layer.add_topic("topic1").unwrap();
+    let local_partition = data_layer
+        .add_partition(topic.topic_id, local_node.node_id)
+        .unwrap();
+    let remote_partition = data_layer
+        .add_partition(topic.topic_id, remote_node.node_id)
+        .unwrap();
+    let subscription = data_layer
+        .add_subscription(topic.topic_id, "subscription1", false)
+        .unwrap();
+
+    let cluster = Arc::new(Cluster::new(&data_layer, "10.0.0.1"));
+    assert_eq!(cluster.my_node_id(), local_node.node_id);
+
+    let sub_service = SubService::new(&persistence, &cluster);
+
+    let consumed_messages = match sub_service.consume_max_messages(
+        topic.topic_id,
+        subscription.subscription_id,
+        None,
+        10,
+    ) {
+        Ok(consumed_messages) => consumed_messages,
+        Err(_) => panic!("Consume request failed"),
+    };
+    let response = responses::ConsumeResult::from(&consumed_messages);
+
+    assert_eq!(response.messages.len(), 0);
+    assert_eq!(response.remote_partitions.len(), 1);
+
+    let owner = &response.remote_partitions[0];
+    assert_eq!(owner.topic_id, topic.topic_id);
+    assert_eq!(owner.partition_id, remote_partition.partition_id);
+    assert_ne!(owner.partition_id, local_partition.partition_id);
+    assert_eq!(owner.node_id, remote_node.node_id);
+    assert_eq!(owner.ip_address, "10.0.0.2");
+    assert_eq!(owner.pubsub_port, 8101);
+}
+
+#[test]
+fn should_redirect_consumers_to_partition_owner() {
+    let persistence = Arc::new(PersistenceLayer::new(
+        PersistenceScheme::InMemory,
+        PersistenceScheme::InMemory,
+    ));
+    let data_layer = Arc::new(DataLayer::new("local".to_owned(), &persistence));
+
+    let local_node = data_layer.add_node("10.0.0.1", 8000, 8001, 8002).unwrap();
+    let remote_node = data_layer.add_node("10.0.0.2", 8000, 8001, 8002).unwrap();
+
+    let topic = data_layer.add_topic("topic1").unwrap();
+    data_layer
+        .add_partition(topic.topic_id, local_node.node_id)
+        .unwrap();
+    let remote_partition = data_layer
+        .add_partition(topic.topic_id, remote_node.node_id)
+        .unwrap();
+    let subscription = data_layer
+        .add_subscription(topic.topic_id, "subscription1", false)
+        .unwrap();
+
+    let remote_topic = data_layer.add_topic("topic2").unwrap();
+    data_layer
+        .add_partition(remote_topic.topic_id, remote_node.node_id)
+        .unwrap();
+    let remote_subscription = data_layer
+        .add_subscription(remote_topic.topic_id, "subscription1", false)
+        .unwrap();
+
+    let cluster = Arc::new(Cluster::new(&data_layer, "10.0.0.1"));
+    let sub_service = SubService::new(&persistence, &cluster);
+
+    match sub_service.consume_max_messages(
+        remote_topic.topic_id,
+        remote_subscription.subscription_id,
+        None,
+        10,
+    ) {
+        Err(SubError::WrongNode(node)) => assert_eq!(node.ip_address(), "10.0.0.2"),
+        _ => panic!("Consume should be redirected to the partition owner"),
+    }
+
+    let message_ref_key = MessageRef {
+        topic_id: topic.topic_id,
+        partition_id: remote_partition.partition_id,
+        ledger_id: 1,
+        message_id: 1,
+    }
+    .to_key();
+
+    match sub_service.ack(message_ref_key.clone(), subscription.subscription_id, 1) {
+        Err(SubError::WrongNode(node)) => assert_eq!(node.node_id(), remote_node.node_id),
+        _ => panic!("Ack should be redirected to the partition owner"),
+    }
+
+    match sub_service.nack(message_ref_key, subscription.subscription_id, 1) {
+        Err(SubError::WrongNode(node)) => assert_eq!(node.node_id(), remote_node.node_id),
+        _ => panic!("Nack should be redirected to the partition owner"),
+    }
+}
+
+fn ack_under_load(batch_window: Option<Duration>) -> (SubscriptionStats, LedgerStats) {
+    let persistence = Arc::new(PersistenceLayer::new(
+        PersistenceScheme::InMemory,
+        PersistenceScheme::InMemory,
+    ));
+    let data_layer = Arc::new(DataLayer::new("local".to_owned(), &persistence));
+
+    let node = data_layer.add_node("10.0.0.1", 8000, 8001, 8002).unwrap();
+    let topic = data_layer.add_topic("topic1").unwrap();
+    let partition = data_layer
+        .add_partition(topic.topic_id, node.node_id)
+        .unwrap();
+    let ledger = data_layer
+        .add_ledger(topic.topic_id, partition.partition_id, node.node_id)
+        .unwrap();
+    let subscription = data_layer
+        .add_subscription(topic.topic_id, "subscription1", true)
+        .unwrap();
+
+    let cluster = Arc::new(Cluster::new(&data_layer, "10.0.0.1"));
+    let pub_service = PubService::new(&persistence, &cluster);
+    let sub_service = Arc::new(match batch_window {
+        Some(window) => SubService::with_ack_batching(&persistence, &cluster, window),
+        None => SubService::new(&persistence, &cluster),
+    });
+
+    for index in 0..400 {
+        let message = published_message(
+            topic.topic_id,
+            partition.partition_id,
+            &(index % 7).to_string(),
+        );
+        if pub_service.publish_message(message).is_err() {
+            panic!("Publish request failed");
+        }
+    }
+
+    let consumers: Vec<_> = (0..4)
+        .map(|_| {
+            let sub_service = Arc::clone(&sub_service);
+            let topic_id = topic.topic_id;
+            let subscription_id = subscription.subscription_id;
+            thread::spawn(move || {
+                let mut consumer_id = None;
+                let mut idle = false;
+                loop {
+                    let consumed_messages = match sub_service.consume_max_messages(
+                        topic_id,
+                        subscription_id,
+                        consumer_id,
+                        10,
+                    ) {
+                        Ok(consumed_messages) => consumed_messages,
+                        Err(_) => panic!("Consume request failed"),
+                    };
+                    consumer_id = Some(consumed_messages.consumer_id);
+                    if consumed_messages.messages.is_empty() {
+                        // Batched acks keep the consumer's key affinities until they are applied,
+                        // so messages with those keys can still be assigned to this consumer
+                        if idle {
+                            break;
+                        }
+                        sub_service.flush_acks();
+                        idle = true;
+                        continue;
+                    }
+                    idle = false;
+                    for message in consumed_messages.messages {
+                        let message_ref_key = message.subscribed_message.message_ref_key;
+                        if sub_service
+                            .ack(
+                                message_ref_key,
+                                subscription_id,
+                                consumed_messages.consumer_id,
+                            )
+                            .is_err()
+                        {
+                            panic!("Ack request failed");
+                        }
+                    }
+                }
+            })
+        })
+        .collect();
+    for consumer in consumers {
+        consumer.join().unwrap();
+    }
+    sub_service.flush_acks();
+
+    let topic = cluster.topics().get(&topic.topic_id).unwrap();
+    let subscription_stats = topic
+        .subscriptions()
+        .get(&subscription.subscription_id)
+        .unwrap()
+        .stats();
+    let ledger_stats = topic
+        .partitions()
+        .get(&partition.partition_id)
+        .unwrap()
+        .ledgers()
+        .get(&ledger.ledger_id)
+        .unwrap()
+        .stats();
+    (subscription_stats, ledger_stats)
+}
+
+#[test]
+fn should_apply_batched_acks_like_immediate_acks() {
+    let (immediate_subscription, immediate_ledger) = ack_under_load(None);
+    let (batched_subscription, batched_ledger) = ack_under_load(Some(Duration::from_millis(5)));
+
+    assert_eq!(immediate_ledger.message_count, 0);
+    assert_eq!(immediate_ledger.unacked_count, 0);
+
+    assert_eq!(batched_ledger.message_count, immediate_ledger.message_count);
+    assert_eq!(batched_ledger.unacked_count, immediate_ledger.unacked_count);
+    assert_eq!(
+        batched_subscription.queued_count,
+        immediate_subscription.queued_count
+    );
+    assert_eq!(
+        batched_subscription.unacked_count,
+        immediate_subscription.unacked_count
+    );
+    assert_eq!(
+        batched_subscription.assigned_count,
+        immediate_subscription.assigned_count
+    );
+    assert_eq!(
+        batched_subscription.affinity_count,
+        immediate_subscription.affinity_count
+    );
+}
+
+#[test]
+fn should_report_unknown_and_duplicate_batched_acks() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let subscription = &test_cluster.topics[0].subscriptions[0];
+
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service =
+        SubService::with_ack_batching(&test_cluster.persistence, &cluster, Duration::from_secs(60));
+
+    let message_ref_key = match pub_service.publish_message(published_message(
+        topic.topic_id,
+        partition.partition_id,
+        "a",
+    )) {
+        Ok(message_ref) => message_ref.to_key(),
+        Err(_) => panic!("Publish request failed"),
+    };
+    let ack = |consumer_id| match sub_service.ack(
+        message_ref_key.clone(),
+        subscription.subscription_id,
+        consumer_id,
+    ) {
+        Ok(acked) => acked,
+        Err(_) => panic!("Ack request failed"),
+    };
+
+    // The message has not been delivered yet
+    assert!(!ack(1));
+
+    let consumed = match sub_service.consume_max_messages(
+        topic.topic_id,
+        subscription.subscription_id,
+        None,
+        10,
+    ) {
+        Ok(consumed_messages) => consumed_messages,
+        Err(_) => panic!("Consume request failed"),
+    };
+    assert_eq!(consumed.messages.len(), 1);
+
+    // Only the first ack is queued, even before the batch is applied
+    assert!(ack(consumed.consumer_id));
+    assert!(!ack(consumed.consumer_id));
+    sub_service.flush_acks();
+    assert!(!ack(consumed.consumer_id));
+}
+
+#[test]
+fn should_deliver_newest_first_when_lifo() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let subscription = &test_cluster.topics[0].subscriptions[0];
+
+    test_cluster
+        .data_layer
+        .set_subscription_delivery_order(
+            topic.topic_id,
+            subscription.subscription_id,
+            DeliveryOrder::Lifo,
+        )
+        .unwrap();
+
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+
+    for key in ["1", "2", "3", "4", "5"] {
+        let message = published_message(topic.topic_id, partition.partition_id, key);
+        if pub_service.publish_message(message).is_err() {
+            panic!("Publish request failed");
+        }
+    }
+
+    let consumed_messages = match sub_service.consume_max_messages(
+        topic.topic_id,
+        subscription.subscription_id,
+        None,
+        10,
+    ) {
+        Ok(consumed_messages) => consumed_messages,
+        Err(_) => panic!("Consume request failed"),
+    };
+    let keys: Vec<String> = consumed_messages
+        .messages
+        .iter()
+        .map(|message| message.published_message.key.clone())
+        .collect();
+
+    assert_eq!(keys, vec!["5", "4", "3", "2", "1"]);
+}
+
+#[test]
+fn should_skip_messages_older_than_max_age() {
+    for has_key_affinity in [false, true] {
+        let persistence = Arc::new(PersistenceLayer::new(
+            PersistenceScheme::InMemory,
+            PersistenceScheme::InMemory,
+        ));
+        let data_layer = Arc::new(DataLayer::new("local".to_owned(), &persistence));
+
+        let node = data_layer.add_node("10.0.0.1", 8000, 8001, 8002).unwrap();
+        let topic = data_layer.add_topic("topic1").unwrap();
+        let partition = data_layer
+            .add_partition(topic.topic_id, node.node_id)
+            .unwrap();
+        let ledger = data_layer
+            .add_ledger(topic.topic_id, partition.partition_id, node.node_id)
+            .unwrap();
+        let subscription = data_layer
+            .add_subscription(topic.topic_id, "subscription1", has_key_affinity)
+            .unwrap();
+        data_layer
+            .set_subscription_max_message_age(topic.topic_id, subscription.subscription_id, 200)
+            .unwrap();
+
+        let cluster = Arc::new(Cluster::new(&data_layer, "10.0.0.1"));
+        let pub_service = PubService::new(&persistence, &cluster);
+        let sub_service = SubService::new(&persistence, &cluster);
+
+        for key in ["old1", "old2"] {
+            let message = published_message(topic.topic_id, partition.partition_id, key);
+            if pub_service.publish_message(message).is_err() {
+                panic!("Publish request failed");
+            }
+        }
+
+        thread::sleep(Duration::from_millis(300));
+
+        for key in ["new1", "new2"] {
+            let message = published_message(topic.topic_id, partition.partition_id, key);
+            if pub_service.publish_message(message).is_err() {
+                panic!("Publish request failed");
+            }
+        }
+
+        let consumed_messages = match sub_service.consume_max_messages(
+            topic.topic_id,
+            subscription.subscription_id,
+            None,
+            10,
+        ) {
+            Ok(consumed_messages) => consumed_messages,
+            Err(_) => panic!("Consume request failed"),
+        };
+        let keys: Vec<String> = consumed_messages
+            .messages
+            .iter()
+            .map(|message| message.published_message.key.clone())
+            .collect();
+        assert_eq!(keys, vec!["new1", "new2"]);
+
+        // Stale messages are acked in the ledger, and only the delivered messages are unacked
+        let ledger_stats = cluster
+            .topics()
+            .get(&topic.topic_id)
+            .unwrap()
+            .partitions()
+            .get(&partition.partition_id)
+            .unwrap()
+            .ledgers()
+            .get(&ledger.ledger_id)
+            .unwrap()
+            .stats();
+        assert_eq!(ledger_stats.unacked_count, 2);
+    }
+}
+
+#[test]
+fn should_reassign_messages_from_stalled_consumer() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", true)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let subscription = &test_cluster.topics[0].subscriptions[0];
+
+    test_cluster
+        .data_layer
+        .set_subscription_assignment_timeout(topic.topic_id, subscription.subscription_id, 200)
+        .unwrap();
+
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+
+    let consume = |consumer_id| match sub_service.consume_max_messages(
+        topic.topic_id,
+        subscription.subscription_id,
+        consumer_id,
+        10,
+    ) {
+        Ok(consumed_messages) => consumed_messages,
+        Err(_) => panic!("Consume request failed"),
+    };
+
+    // The first consumer takes a message, creating an affinity for its key
+    if pub_service
+        .publish_message(published_message(
+            topic.topic_id,
+            partition.partition_id,
+            "a",
+        ))
+        .is_err()
+    {
+        panic!("Publish request failed");
+    }
+    let stalled = consume(None);
+    assert_eq!(stalled.messages.len(), 1);
+
+    // The next message with the same key is assigned to the first consumer
+    if pub_service
+        .publish_message(published_message(
+            topic.topic_id,
+            partition.partition_id,
+            "a",
+        ))
+        .is_err()
+    {
+        panic!("Publish request failed");
+    }
+    let other = consume(None);
+    assert_eq!(other.messages.len(), 0);
+
+    // The first consumer acks, then stops consuming
+    if sub_service
+        .ack(
+            stalled.messages[0]
+                .subscribed_message
+                .message_ref_key
+                .clone(),
+            subscription.subscription_id,
+            stalled.consumer_id,
+        )
+        .is_err()
+    {
+        panic!("Ack request failed");
+    }
+    assert_eq!(consume(Some(other.consumer_id)).messages.len(), 0);
+
+    // After the assignment timeout the message is delivered to the other consumer
+    thread::sleep(Duration::from_millis(300));
+    let reassigned = consume(Some(other.consumer_id));
+    assert_eq!(reassigned.messages.len(), 1);
+    assert_eq!(reassigned.messages[0].published_message.key, "a");
+    assert_eq!(reassigned.consumer_id, other.consumer_id);
+}
+
+#[test]
+fn should_flag_redelivered_messages() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let subscription = &test_cluster.topics[0].subscriptions[0];
+
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+
+    if pub_service
+        .publish_message(published_message(
+            topic.topic_id,
+            partition.partition_id,
+            "1",
+        ))
+        .is_err()
+    {
+        panic!("Publish request failed");
+    }
+
+    let consume = || match sub_service.consume_max_messages(
+        topic.topic_id,
+        subscription.subscription_id,
+        None,
+        1,
+    ) {
+        Ok(consumed_messages) => responses::ConsumeResult::from(&consumed_messages),
+        Err(_) => panic!("Consume request failed"),
+    };
+
+    let first = consume();
+    let first_message = &first.messages[0];
+    assert_eq!(first_message.delivery_count, 1);
+    assert!(!first_message.redelivered);
+    assert_eq!(first_message.first_delivered, Some(first_message.delivered));
+
+    thread::sleep(Duration::from_millis(10));
+    if sub_service
+        .nack(
+            first_message.message_ack_key.clone(),
+            subscription.subscription_id,
+            first.consumer_id,
+        )
+        .is_err()
+    {
+        panic!("Nack request failed");
+    }
+
+    let second = consume();
+    let second_message = &second.messages[0];
+    assert_eq!(second_message.delivery_count, 2);
+    assert!(second_message.redelivered);
+    assert_eq!(
+        second_message.first_delivered,
+        first_message.first_delivered
+    );
+    assert!(second_message.delivered > second_message.first_delivered.unwrap());
+}
+
+#[test]
+fn should_flag_the_last_message_in_the_ledger() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let subscription = &test_cluster.topics[0].subscriptions[0];
+
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+
+    for key in ["1", "2", "3"] {
+        if pub_service
+            .publish_message(published_message(
+                topic.topic_id,
+                partition.partition_id,
+                key,
+            ))
+            .is_err()
+        {
+            panic!("Publish request failed");
+        }
+    }
+
+    let consumed = match sub_service.consume_max_messages(
+        topic.topic_id,
+        subscription.subscription_id,
+        None,
+        10,
+    ) {
+        Ok(consumed_messages) => responses::ConsumeResult::from(&consumed_messages),
+        Err(_) => panic!("Consume request failed"),
+    };
+
+    let flags: Vec<bool> = consumed
+        .messages
+        .iter()
+        .map(|message| message.last_in_ledger)
+        .collect();
+    assert_eq!(flags, vec![false, false, true]);
+}
+
+#[test]
+fn should_deliver_higher_priority_messages_first() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let subscription = &test_cluster.topics[0].subscriptions[0];
+
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+
+    for (key, priority) in [("1", 0), ("2", 5), ("3", 0), ("4", 5), ("5", 9), ("6", 0)] {
+        let mut message = published_message(topic.topic_id, partition.partition_id, key);
+        message.priority = priority;
+        if pub_service.publish_message(message).is_err() {
+            panic!("Publish request failed");
+        }
+    }
+
+    let consumed_messages = match sub_service.consume_max_messages(
+        topic.topic_id,
+        subscription.subscription_id,
+        None,
+        10,
+    ) {
+        Ok(consumed_messages) => consumed_messages,
+        Err(_) => panic!("Consume request failed"),
+    };
+    let keys: Vec<String> = consumed_messages
+        .messages
+        .iter()
+        .map(|message| message.published_message.key.clone())
+        .collect();
+
+    assert_eq!(keys, vec!["5", "2", "4", "1", "3", "6"]);
+}
+
+#[test]
+fn should_preserve_key_order_when_consumer_disconnects() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", true)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let subscription = &test_cluster.topics[0].subscriptions[0];
+
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+
+    let publish = |key: &str, sequence: &str| {
+        let mut message = published_message(topic.topic_id, partition.partition_id, key);
+        message
+            .attributes
+            .insert("sequence".to_owned(), sequence.to_owned());
+        if pub_service.publish_message(message).is_err() {
+            panic!("Publish request failed");
+        }
+    };
+    let consume = |consumer_id| match sub_service.consume_max_messages(
+        topic.topic_id,
+        subscription.subscription_id,
+        consumer_id,
+        10,
+    ) {
+        Ok(consumed_messages) => consumed_messages,
+        Err(_) => panic!("Consume request failed"),
+    };
+    let sequences = |consumed_messages: &ConsumedMessages| -> Vec<String> {
+        consumed_messages
+            .messages
+            .iter()
+            .map(|message| message.published_message.attributes["sequence"].clone())
+            .collect()
+    };
+
+    // The first consumer is part way through processing messages with key "a"
+    publish("a", "1");
+    let disconnecting = consume(None);
+    assert_eq!(sequences(&disconnecting), vec!["1"]);
+
+    // More messages with key "a" are assigned to the first consumer
+    publish("a", "2");
+    publish("b", "b");
+    publish("a", "3");
+    let remaining = consume(None);
+    assert_eq!(sequences(&remaining), vec!["b"]);
+
+    cluster
+        .topics()
+        .get(&topic.topic_id)
+        .unwrap()
+        .subscriptions()
+        .get(&subscription.subscription_id)
+        .unwrap()
+        .disconnect_consumer(disconnecting.consumer_id);
+    publish("a", "4");
+
+    // The remaining consumer takes over key "a" in the order that messages were published
+    let taken_over = consume(Some(remaining.consumer_id));
+    assert_eq!(sequences(&taken_over), vec!["1", "2", "3", "4"]);
+    assert_eq!(taken_over.messages[0].subscribed_message.delivery_count, 2);
+}
+
+#[test]
+fn should_deliver_other_messages_when_one_can_not_be_serialized() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let subscription = &test_cluster.topics[0].subscriptions[0];
+
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster)
+        .with_serialization_error_policy(SerializationErrorPolicy::Discard);
+
+    for key in ["1", "2", "3"] {
+        let mut message = published_message(topic.topic_id, partition.partition_id, key);
+        if key == "2" {
+            // Too large to fit into a consume response
+            message
+                .attributes
+                .insert("data".to_owned(), "x".repeat(64 * 1024));
+        }
+        if pub_service.publish_message(message).is_err() {
+            panic!("Publish request failed");
+        }
+    }
+
+    let consume = || match sub_service.consume_into_frame(
+        topic.topic_id,
+        subscription.subscription_id,
+        None,
+        10,
+        None,
+        Some(response_frame(CompressionScheme::None)),
+    ) {
+        Ok(consumed_messages) => consumed_messages,
+        Err(_) => panic!("Consume request failed"),
+    };
+
+    assert_eq!(keys(&consume()), vec!["1", "3"]);
+
+    // The discarded message is not delivered again
+    assert_eq!(consume().messages.len(), 0);
+}
+
+#[test]
+fn should_put_back_messages_that_do_not_fit_the_frame_when_skipped() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let subscription = &test_cluster.topics[0].subscriptions[0];
+
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster)
+        .with_serialization_error_policy(SerializationErrorPolicy::Skip);
+
+    for key in ["1", "2", "3"] {
+        let mut message = published_message(topic.topic_id, partition.partition_id, key);
+        if key == "2" {
+            // Too large to fit into an uncompressed consume response
+            message
+                .attributes
+                .insert("data".to_owned(), "x".repeat(64 * 1024));
+        }
+        if pub_service.publish_message(message).is_err() {
+            panic!("Publish request failed");
+        }
+    }
+
+    let consume = |compression| match sub_service.consume_into_frame(
+        topic.topic_id,
+        subscription.subscription_id,
+        None,
+        10,
+        None,
+        Some(response_frame(compression)),
+    ) {
+        Ok(consumed_messages) => consumed_messages,
+        Err(_) => panic!("Consume request failed"),
+    };
+
+    assert_eq!(keys(&consume(CompressionScheme::None)), vec!["1", "3"]);
+
+    // The skipped message is still queued, and fits the frame once it is compressed
+    assert_eq!(consume(CompressionScheme::None).messages.len(), 0);
+    assert_eq!(keys(&consume(CompressionScheme::Lz4)), vec!["2"]);
+}
+
+fn response_frame(compression: CompressionScheme) -> ResponseFrame {
+    ResponseFrame {
+        max_size: DEFAULT_MAX_MESSAGE_SIZE,
+        compression,
+    }
+}
+
+fn keys(consumed: &ConsumedMessages) -> Vec<String> {
+    consumed
+        .messages
+        .iter()
+        .map(|message| message.published_message.key.clone())
+        .collect()
+}
+
+#[test]
+fn should_transform_delivered_copies_of_messages() {
+    let persistence = Arc::new(PersistenceLayer::new(
+        PersistenceScheme::InMemory,
+        PersistenceScheme::InMemory,
+    ));
+    let data_layer = Arc::new(DataLayer::new("local".to_owned(), &persistence));
+
+    let node = data_layer.add_node("10.0.0.1", 8000, 8001, 8002).unwrap();
+    let topic = data_layer.add_topic("topic1").unwrap();
+    let partition = data_layer
+        .add_partition(topic.topic_id, node.node_id)
+        .unwrap();
+    data_layer
+        .add_ledger(topic.topic_id, partition.partition_id, node.node_id)
+        .unwrap();
+    let transformed = data_layer
+        .add_subscription(topic.topic_id, "transformed", false)
+        .unwrap();
+    let plain = data_layer
+        .add_subscription(topic.topic_id, "plain", false)
+        .unwrap();
+    data_layer
+        .set_subscription_delivery_transforms(
+            topic.topic_id,
+            transformed.subscription_id,
+            vec![
+                DeliveryTransform::SetAttribute {
+                    name: "enriched".to_owned(),
+                    value: "yes".to_owned(),
+                },
+                DeliveryTransform::RemoveAttributes {
+                    prefix: "internal.".to_owned(),
+                },
+            ],
+        )
+        .unwrap();
+
+    let cluster = Arc::new(Cluster::new(&data_layer, "10.0.0.1"));
+    let pub_service = PubService::new(&persistence, &cluster);
+    let sub_service = SubService::new(&persistence, &cluster);
+
+    let mut message = published_message(topic.topic_id, partition.partition_id, "key1");
+    message
+        .attributes
+        .insert("internal.trace".to_owned(), "abc".to_owned());
+    if pub_service.publish_message(message).is_err() {
+        panic!("Publish request failed");
+    }
+
+    let consume = |subscription_id| match sub_service.consume_max_messages(
+        topic.topic_id,
+        subscription_id,
+        None,
+        10,
+    ) {
+        Ok(consumed_messages) => responses::ConsumeResult::from(&consumed_messages),
+        Err(_) => panic!("Consume request failed"),
+    };
+
+    let transformed_messages = consume(transformed.subscription_id).messages;
+    assert_eq!(transformed_messages.len(), 1);
+    let attributes = &transformed_messages[0].attributes;
+    assert_eq!(attributes.get("enriched").map(String::as_str), Some("yes"));
+    assert!(!attributes.contains_key("internal.trace"));
+
+    let plain_messages = consume(plain.subscription_id).messages;
+    assert_eq!(plain_messages.len(), 1);
+    let attributes = &plain_messages[0].attributes;
+    assert!(!attributes.contains_key("enriched"));
+    assert_eq!(attributes.get("internal.trace").map(String::as_str), Some("abc"));
+
+    // The message in the ledger is not changed by the transform
+    let stored = match sub_service.get_message(&transformed_messages[0].message_ack_key) {
+        Ok(message) => message,
+        Err(_) => panic!("Get message request failed"),
+    };
+    assert!(!stored.attributes.contains_key("enriched"));
+    assert!(stored.attributes.contains_key("internal.trace"));
+}
+
+#[test]
+fn should_ack_previous_batch_on_next_consume() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let subscription = &test_cluster.topics[0].subscriptions[0];
+
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+
+    for key in ["1", "2", "3", "4"] {
+        let message = published_message(topic.topic_id, partition.partition_id, key);
+        if pub_service.publish_message(message).is_err() {
+            panic!("Publish request failed");
+        }
+    }
+
+    let consume = |consumer_id| match sub_service.consume_and_ack_previous(
+        topic.topic_id,
+        subscription.subscription_id,
+        consumer_id,
+        2,
+        None,
+        None,
+    ) {
+        Ok(consumed_messages) => consumed_messages,
+        Err(_) => panic!("Consume request failed"),
+    };
+    let unacked_count = || {
+        cluster
+            .topics()
+            .get(&topic.topic_id)
+            .unwrap()
+            .subscriptions()
+            .get(&subscription.subscription_id)
+            .unwrap()
+            .stats()
+            .unacked_count
+    };
+
+    let first_batch = consume(None);
+    assert_eq!(first_batch.messages.len(), 2);
+    assert_eq!(unacked_count(), 2);
+
+    let second_batch = consume(Some(first_batch.consumer_id));
+    assert_eq!(second_batch.messages.len(), 2);
+
+    // Only the second batch is still waiting to be acked
+    assert_eq!(unacked_count(), 2);
+    for message in &first_batch.messages {
+        let ack_result = sub_service.ack(
+            message.subscribed_message.message_ref_key.clone(),
+            subscription.subscription_id,
+            first_batch.consumer_id,
+        );
+        assert!(matches!(ack_result, Ok(false)));
+    }
+
+    let third_batch = consume(Some(first_batch.consumer_id));
+    assert_eq!(third_batch.messages.len(), 0);
+    assert_eq!(unacked_count(), 0);
+}
+
+#[test]
+fn should_quarantine_messages_that_can_not_be_processed() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let subscription = &test_cluster.topics[0].subscriptions[0];
+
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+
+    for key in ["malformed", "good"] {
+        if pub_service
+            .publish_message(published_message(
+                topic.topic_id,
+                partition.partition_id,
+                key,
+            ))
+            .is_err()
+        {
+            panic!("Publish request failed");
+        }
+    }
+
+    let consumed = match sub_service.consume_max_messages(
+        topic.topic_id,
+        subscription.subscription_id,
+        None,
+        1,
+    ) {
+        Ok(consumed_messages) => consumed_messages,
+        Err(_) => panic!("Consume request failed"),
+    };
+    let malformed = &consumed.messages[0].published_message;
+    assert_eq!(malformed.key, "malformed");
+    let message_ref_key = malformed.message_ref.to_key();
+
+    match sub_service.quarantine(
+        message_ref_key.clone(),
+        subscription.subscription_id,
+        consumed.consumer_id,
+        "Missing order number",
+    ) {
+        Ok(quarantined) => assert!(quarantined),
+        Err(_) => panic!("Quarantine request failed"),
+    }
+
+    // The message is no longer delivered, so it can not be nacked and redelivered
+    match sub_service.nack(
+        message_ref_key.clone(),
+        subscription.subscription_id,
+        consumed.consumer_id,
+    ) {
+        Ok(nacked) => assert!(!nacked),
+        Err(_) => panic!("Nack request failed"),
+    }
+    let remaining = match sub_service.consume_max_messages(
+        topic.topic_id,
+        subscription.subscription_id,
+        Some(consumed.consumer_id),
+        10,
+    ) {
+        Ok(consumed_messages) => consumed_messages,
+        Err(_) => panic!("Consume request failed"),
+    };
+    assert_eq!(remaining.messages.len(), 1);
+    assert_eq!(remaining.messages[0].published_message.key, "good");
+
+    let quarantined =
+        sub_service.quarantined_messages(topic.topic_id, subscription.subscription_id);
+    assert_eq!(quarantined.len(), 1);
+    assert_eq!(quarantined[0].message.key, "malformed");
+    assert_eq!(quarantined[0].message.message_ref.to_key(), message_ref_key);
+    assert_eq!(quarantined[0].reason, "Missing order number");
+    assert_eq!(quarantined[0].consumer_id, consumed.consumer_id);
+}
+
+#[test]
+fn should_block_key_until_nacked_message_is_acked() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", true)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let subscription = &test_cluster.topics[0].subscriptions[0];
+
+    test_cluster
+        .data_layer
+        .set_subscription_strict_ordering(topic.topic_id, subscription.subscription_id, true)
+        .unwrap();
+
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+
+    let mut message_ref_keys = Vec::new();
+    for _ in 0..3 {
+        match pub_service.publish_message(published_message(
+            topic.topic_id,
+            partition.partition_id,
+            "a",
+        )) {
+            Ok(message_ref) => message_ref_keys.push(message_ref.to_key()),
+            Err(_) => panic!("Publish request failed"),
+        }
+    }
+
+    let consume = |consumer_id| match sub_service.consume_max_messages(
+        topic.topic_id,
+        subscription.subscription_id,
+        consumer_id,
+        10,
+    ) {
+        Ok(consumed_messages) => consumed_messages,
+        Err(_) => panic!("Consume request failed"),
+    };
+    let delivered_keys = |consumed: &ConsumedMessages| -> Vec<String> {
+        consumed
+            .messages
+            .iter()
+            .map(|message| message.subscribed_message.message_ref_key.clone())
+            .collect()
+    };
+
+    // Only the first message is delivered while it is unacked
+    let consumed = consume(None);
+    let consumer_id = consumed.consumer_id;
+    assert_eq!(delivered_keys(&consumed), vec![message_ref_keys[0].clone()]);
+
+    // After a nack, the first message is delivered again ahead of the later two
+    match sub_service.nack(
+        message_ref_keys[0].clone(),
+        subscription.subscription_id,
+        consumer_id,
+    ) {
+        Ok(nacked) => assert!(nacked),
+        Err(_) => panic!("Nack request failed"),
+    }
+    let consumed = consume(Some(consumer_id));
+    assert_eq!(delivered_keys(&consumed), vec![message_ref_keys[0].clone()]);
+    assert_eq!(consume(Some(consumer_id)).messages.len(), 0);
+
+    // Each ack releases the next message with the same key
+    for index in 0..2 {
+        if sub_service
+            .ack(
+                message_ref_keys[index].clone(),
+                subscription.subscription_id,
+                consumer_id,
+            )
+            .is_err()
+        {
+            panic!("Ack request failed");
+        }
+        let consumed = consume(Some(consumer_id));
+        assert_eq!(
+            delivered_keys(&consumed),
+            vec![message_ref_keys[index + 1].clone()]
+        );
+    }
+}
+
+#[test]
+fn should_limit_the_number_of_consumers() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", true)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let subscription = &test_cluster.topics[0].subscriptions[0];
+
+    test_cluster
+        .data_layer
+        .set_subscription_max_consumers(topic.topic_id, subscription.subscription_id, 2)
+        .unwrap();
+
+    let cluster = test_cluster.cluster();
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+
+    let consume = |consumer_id| {
+        sub_service.consume_max_messages(
+            topic.topic_id,
+            subscription.subscription_id,
+            consumer_id,
+            10,
+        )
+    };
+
+    // Consumers can connect up to the limit
+    let first = match consume(None) {
+        Ok(consumed_messages) => consumed_messages.consumer_id,
+        Err(_) => panic!("First consumer should connect"),
+    };
+    let second = match consume(None) {
+        Ok(consumed_messages) => consumed_messages.consumer_id,
+        Err(_) => panic!("Second consumer should connect"),
+    };
+    assert_ne!(first, second);
+
+    // Connected consumers can keep consuming, but new consumers are rejected
+    assert!(consume(Some(first)).is_ok());
+    match consume(None) {
+        Err(SubError::TooManyConsumers) => {}
+        _ => panic!("Third consumer should be rejected"),
+    }
+
+    // The subscription of a consumer group has the same limit
+    let join = || sub_service.join_group(topic.topic_id, subscription.subscription_id, "group1");
+    let member = match join() {
+        Ok(member) => member,
+        Err(_) => panic!("First group member should connect"),
+    };
+    assert!(join().is_ok());
+    match join() {
+        Err(SubError::TooManyConsumers) => {}
+        _ => panic!("Third consumer should not be able to join a group"),
+    }
+
+    // When a consumer disconnects, another one can take its place
+    match sub_service.leave_group(topic.topic_id, member.subscription_id, member.consumer_id) {
+        Ok(left) => assert!(left),
+        Err(_) => panic!("Leave group request failed"),
+    }
+    assert!(join().is_ok());
+    match sub_service.disconnect_consumer(topic.topic_id, subscription.subscription_id, second) {
+        Ok(_) => {}
+        Err(_) => panic!("Disconnect request failed"),
+    }
+    match consume(None) {
+        Ok(consumed_messages) => assert_ne!(consumed_messages.consumer_id, first),
+        Err(_) => panic!("Consumer should connect after another disconnects"),
+    }
+    match consume(None) {
+        Err(SubError::TooManyConsumers) => {}
+        _ => panic!("Consumers should be rejected once the limit is reached again"),
+    }
+}
+
+#[test]
+fn should_delay_redelivery_of_nacked_messages() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let subscription = &test_cluster.topics[0].subscriptions[0];
+
+    test_cluster
+        .data_layer
+        .set_subscription_nack_redelivery_delay(topic.topic_id, subscription.subscription_id, 200)
+        .unwrap();
+
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+
+    let consume = |consumer_id| match sub_service.consume_max_messages(
+        topic.topic_id,
+        subscription.subscription_id,
+        consumer_id,
+        10,
+    ) {
+        Ok(consumed_messages) => consumed_messages,
+        Err(_) => panic!("Consume request failed"),
+    };
+
+    let message_ref_key = match pub_service.publish_message(published_message(
+        topic.topic_id,
+        partition.partition_id,
+        "a",
+    )) {
+        Ok(message_ref) => message_ref.to_key(),
+        Err(_) => panic!("Publish request failed"),
+    };
+
+    let consumed = consume(None);
+    let consumer_id = consumed.consumer_id;
+    assert_eq!(consumed.messages.len(), 1);
+
+    // The nacked message is not redelivered until the delay has elapsed
+    match sub_service.nack(
+        message_ref_key.clone(),
+        subscription.subscription_id,
+        consumer_id,
+    ) {
+        Ok(nacked) => assert!(nacked),
+        Err(_) => panic!("Nack request failed"),
+    }
+    assert_eq!(consume(Some(consumer_id)).messages.len(), 0);
+
+    thread::sleep(Duration::from_millis(300));
+    let consumed = consume(Some(consumer_id));
+    assert_eq!(consumed.messages.len(), 1);
+    assert_eq!(
+        consumed.messages[0].subscribed_message.message_ref_key,
+        message_ref_key
+    );
+    assert_eq!(consumed.messages[0].subscribed_message.delivery_count, 2);
+}
+
+#[test]
+fn should_hold_key_while_nacked_message_is_delayed() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", true)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let subscription = &test_cluster.topics[0].subscriptions[0];
+
+    test_cluster
+        .data_layer
+        .set_subscription_strict_ordering(topic.topic_id, subscription.subscription_id, true)
+        .unwrap();
+    test_cluster
+        .data_layer
+        .set_subscription_nack_redelivery_delay(topic.topic_id, subscription.subscription_id, 200)
+        .unwrap();
+
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+
+    let publish = |key: &str| match pub_service.publish_message(published_message(
+        topic.topic_id,
+        partition.partition_id,
+        key,
+    )) {
+        Ok(message_ref) => message_ref.to_key(),
+        Err(_) => panic!("Publish request failed"),
+    };
+    let consume = |consumer_id| match sub_service.consume_max_messages(
+        topic.topic_id,
+        subscription.subscription_id,
+        consumer_id,
+        10,
+    ) {
+        Ok(consumed_messages) => consumed_messages,
+        Err(_) => panic!("Consume request failed"),
+    };
+    let delivered_keys = |consumed: &ConsumedMessages| -> Vec<String> {
+        consumed
+            .messages
+            .iter()
+            .map(|message| message.subscribed_message.message_ref_key.clone())
+            .collect()
+    };
+
+    let first = publish("a");
+    let second = publish("a");
+
+    let consumed = consume(None);
+    let consumer_id = consumed.consumer_id;
+    assert_eq!(delivered_keys(&consumed), vec![first.clone()]);
+
+    match sub_service.nack(first.clone(), subscription.subscription_id, consumer_id) {
+        Ok(nacked) => assert!(nacked),
+        Err(_) => panic!("Nack request failed"),
+    }
+
+    // While the nacked message is delayed, later messages with the same key are held back
+    // but messages with other keys are delivered
+    let other = publish("b");
+    assert_eq!(delivered_keys(&consume(Some(consumer_id))), vec![other]);
+
+    // Once the delay has elapsed the nacked message is delivered before the one that follows it
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(
+        delivered_keys(&consume(Some(consumer_id))),
+        vec![first.clone()]
+    );
+    if sub_service
+        .ack(first, subscription.subscription_id, consumer_id)
+        .is_err()
+    {
+        panic!("Ack request failed");
+    }
+    assert_eq!(delivered_keys(&consume(Some(consumer_id))), vec![second]);
+}
+
+#[test]
+fn should_redeliver_messages_that_are_not_acked_within_ack_timeout() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", true)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let subscription = &test_cluster.topics[0].subscriptions[0];
+
+    test_cluster
+        .data_layer
+        .set_subscription_ack_timeout(topic.topic_id, subscription.subscription_id, 100)
+        .unwrap();
+
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster)
+        .with_ack_timeouts(Duration::from_millis(20));
+
+    let publish = |key: &str| match pub_service.publish_message(published_message(
+        topic.topic_id,
+        partition.partition_id,
+        key,
+    )) {
+        Ok(message_ref) => message_ref.to_key(),
+        Err(_) => panic!("Publish request failed"),
+    };
+    let consume = |consumer_id| match sub_service.consume_max_messages(
+        topic.topic_id,
+        subscription.subscription_id,
+        consumer_id,
+        10,
+    ) {
+        Ok(consumed_messages) => consumed_messages,
+        Err(_) => panic!("Consume request failed"),
+    };
+    let delivered_keys = |consumed: &ConsumedMessages| -> Vec<String> {
+        consumed
+            .messages
+            .iter()
+            .map(|message| message.subscribed_message.message_ref_key.clone())
+            .collect()
+    };
+
+    let first = publish("a");
+    let second = publish("a");
+
+    // The first consumer receives both messages and never acks them
+    let abandoned = consume(None);
+    assert_eq!(
+        delivered_keys(&abandoned),
+        vec![first.clone(), second.clone()]
+    );
+
+    // Once the ack timeout has elapsed the key is released and another consumer receives
+    // the messages in the order they were published
+    thread::sleep(Duration::from_millis(300));
+    let consumed = consume(None);
+    assert_ne!(consumed.consumer_id, abandoned.consumer_id);
+    assert_eq!(delivered_keys(&consumed), vec![first, second]);
+    assert!(consumed
+        .messages
+        .iter()
+        .all(|message| message.subscribed_message.delivery_count == 2));
+}
+
+#[test]
+fn should_only_allow_one_consumer_on_exclusive_subscriptions() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let subscription = &test_cluster.topics[0].subscriptions[0];
+
+    test_cluster
+        .data_layer
+        .set_subscription_type(
+            topic.topic_id,
+            subscription.subscription_id,
+            SubscriptionType::Exclusive,
+        )
+        .unwrap();
+
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+
+    for key in ["a", "b"] {
+        if pub_service
+            .publish_message(published_message(
+                topic.topic_id,
+                partition.partition_id,
+                key,
+            ))
+            .is_err()
+        {
+            panic!("Publish request failed");
+        }
+    }
+
+    let consume = |consumer_id| {
+        sub_service.consume_max_messages(
+            topic.topic_id,
+            subscription.subscription_id,
+            consumer_id,
+            10,
+        )
+    };
+
+    let exclusive = match consume(None) {
+        Ok(consumed_messages) => {
+            assert_eq!(consumed_messages.messages.len(), 2);
+            consumed_messages.consumer_id
+        }
+        Err(_) => panic!("First consumer should connect"),
+    };
+    match consume(None) {
+        Err(SubError::TooManyConsumers) => {}
+        _ => panic!("Second consumer should be rejected"),
+    }
+
+    // Once the exclusive consumer disconnects, another consumer can take over
+    if sub_service
+        .disconnect_consumer(topic.topic_id, subscription.subscription_id, exclusive)
+        .is_err()
+    {
+        panic!("Disconnect request failed");
+    }
+    match consume(None) {
+        Ok(consumed_messages) => assert_eq!(consumed_messages.messages.len(), 2),
+        Err(_) => panic!("Consumer should connect after the exclusive consumer disconnects"),
+    }
+}
+
+#[test]
+fn should_deliver_to_first_consumer_on_failover_subscriptions() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let subscription = &test_cluster.topics[0].subscriptions[0];
+
+    test_cluster
+        .data_layer
+        .set_subscription_type(
+            topic.topic_id,
+            subscription.subscription_id,
+            SubscriptionType::Failover,
+        )
+        .unwrap();
+
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+
+    let publish = |key: &str| {
+        if pub_service
+            .publish_message(published_message(
+                topic.topic_id,
+                partition.partition_id,
+                key,
+            ))
+            .is_err()
+        {
+            panic!("Publish request failed");
+        }
+    };
+    let consume = |consumer_id| match sub_service.consume_max_messages(
+        topic.topic_id,
+        subscription.subscription_id,
+        consumer_id,
+        10,
+    ) {
+        Ok(consumed_messages) => consumed_messages,
+        Err(_) => panic!("Consume request failed"),
+    };
+
+    publish("a");
+    let active = consume(None);
+    assert_eq!(active.messages.len(), 1);
+
+    // Standby consumers can connect but do not receive messages
+    publish("b");
+    let standby = consume(None);
+    assert_ne!(standby.consumer_id, active.consumer_id);
+    assert_eq!(standby.messages.len(), 0);
+    assert_eq!(consume(Some(active.consumer_id)).messages.len(), 1);
+
+    // When the active consumer disconnects, the standby takes over and receives the
+    // messages that were not acked
+    if sub_service
+        .disconnect_consumer(
+            topic.topic_id,
+            subscription.subscription_id,
+            active.consumer_id,
+        )
+        .is_err()
+    {
+        panic!("Disconnect request failed");
+    }
+    assert_eq!(consume(Some(standby.consumer_id)).messages.len(), 2);
+}
+
+#[test]
+fn should_expire_queued_messages_but_not_delivered_messages() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let ledger = &test_cluster.topics[0].partitions[0].ledger;
+    let subscription = &test_cluster.topics[0].subscriptions[0];
+
+    test_cluster
+        .data_layer
+        .set_topic_message_ttl(topic.topic_id, 100)
+        .unwrap();
+
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster)
+        .with_message_expiry(Duration::from_millis(20));
+
+    for key in ["a", "b", "c"] {
+        if pub_service
+            .publish_message(published_message(
+                topic.topic_id,
+                partition.partition_id,
+                key,
+            ))
+            .is_err()
+        {
+            panic!("Publish request failed");
+        }
+    }
+
+    // One message is delivered and is still waiting for an ack when the TTL elapses
+    let consumed = match sub_service.consume_max_messages(
+        topic.topic_id,
+        subscription.subscription_id,
+        None,
+        1,
+    ) {
+        Ok(consumed_messages) => consumed_messages,
+        Err(_) => panic!("Consume request failed"),
+    };
+    assert_eq!(consumed.messages.len(), 1);
+    let delivered = consumed.messages[0]
+        .subscribed_message
+        .message_ref_key
+        .clone();
+
+    thread::sleep(Duration::from_millis(300));
+
+    let model_topic = cluster.topics().get(&topic.topic_id).unwrap();
+    let subscription_stats = model_topic
+        .subscriptions()
+        .get(&subscription.subscription_id)
+        .unwrap()
+        .stats();
+    assert_eq!(subscription_stats.queued_count, 0);
+    assert_eq!(subscription_stats.unacked_count, 1);
+    let model_ledger = model_topic
+        .partitions()
+        .get(&partition.partition_id)
+        .unwrap()
+        .ledgers()
+        .get(&ledger.ledger_id)
+        .unwrap();
+    assert_eq!(model_ledger.stats().message_count, 1);
+
+    // The delivered message can still be acked, which removes it from the ledger
+    match sub_service.ack(
+        delivered,
+        subscription.subscription_id,
+        consumed.consumer_id,
+    ) {
+        Ok(true) => (),
+        _ => panic!("Ack request failed"),
+    }
+    assert_eq!(model_ledger.stats().message_count, 0);
+
+    let expired_count = test_cluster
+        .persistence
+        .events_by_key_prefix(
+            &PersistenceLayer::build_topic_prefix(topic.topic_id),
+            &EventQueryOptions::replay(),
+        )
+        .filter_map(|log_entry| log_entry.deserialize())
+        .filter(|event| matches!(event, LoggedEvent::Expiry(_)))
+        .count();
+    assert_eq!(expired_count, 2);
+}
+
+#[test]
+fn should_limit_the_serialized_size_of_consumed_batches() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let subscription = &test_cluster.topics[0].subscriptions[0];
+
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+
+    for key in ["1", "2", "3", "4", "5"] {
+        let message = published_message(topic.topic_id, partition.partition_id, key);
+        if pub_service.publish_message(message).is_err() {
+            panic!("Publish request failed");
+        }
+    }
+
+    let consume = |consumer_id, max_bytes| match sub_service.consume_max_bytes(
+        topic.topic_id,
+        subscription.subscription_id,
+        consumer_id,
+        10,
+        max_bytes,
+    ) {
+        Ok(consumed_messages) => consumed_messages,
+        Err(_) => panic!("Consume request failed"),
+    };
+
+    // The first message is returned even though it is larger than the limit
+    let first_batch = consume(None, Some(1));
+    assert_eq!(first_batch.messages.len(), 1);
+    let consumer_id = Some(first_batch.consumer_id);
+
+    let message_size = rmp_serde::to_vec(&responses::Message::from(
+        &first_batch.messages[0].published_message,
+    ))
+    .unwrap()
+    .len() as u32;
+
+    let second_batch = consume(consumer_id, Some(message_size * 5 / 2));
+    assert_eq!(second_batch.messages.len(), 2);
+
+    // The message that did not fit into the second batch is the next one delivered, and was
+    // not counted as a delivery attempt
+    let third_batch = consume(consumer_id, None);
+    assert_eq!(third_batch.messages.len(), 2);
+    assert_eq!(third_batch.messages[0].published_message.key, "4");
+    assert_eq!(third_batch.messages[0].subscribed_message.delivery_count, 1);
+}
+
+#[test]
+fn should_move_messages_to_dead_letter_topic_after_max_deliveries() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let subscription = &test_cluster.topics[0].subscriptions[0];
+
+    test_cluster
+        .data_layer
+        .set_subscription_max_delivery_count(topic.topic_id, subscription.subscription_id, 2)
+        .unwrap();
+
+    let cluster = test_cluster.cluster();
+    let pub_service = Arc::new(PubService::new(&test_cluster.persistence, &cluster));
+    let sub_service =
+        SubService::new(&test_cluster.persistence, &cluster).with_dead_letters(&pub_service);
+
+    let consume = |topic_id, subscription_id, consumer_id| match sub_service.consume_max_messages(
+        topic_id,
+        subscription_id,
+        consumer_id,
+        10,
+    ) {
+        Ok(consumed_messages) => consumed_messages,
+        Err(_) => panic!("Consume request failed"),
+    };
+
+    let message_ref_key = match pub_service.publish_message(published_message(
+        topic.topic_id,
+        partition.partition_id,
+        "a",
+    )) {
+        Ok(message_ref) => message_ref.to_key(),
+        Err(_) => panic!("Publish request failed"),
+    };
+
+    let nack = |consumer_id| match sub_service.nack(
+        message_ref_key.clone(),
+        subscription.subscription_id,
+        consumer_id,
+    ) {
+        Ok(nacked) => assert!(nacked),
+        Err(_) => panic!("Nack request failed"),
+    };
+
+    // The message is delivered up to the max delivery count
+    let consumed = consume(topic.topic_id, subscription.subscription_id, None);
+    let consumer_id = consumed.consumer_id;
+    assert_eq!(consumed.messages.len(), 1);
+
+    nack(consumer_id);
+    let consumed = consume(
+        topic.topic_id,
+        subscription.subscription_id,
+        Some(consumer_id),
+    );
+    assert_eq!(consumed.messages.len(), 1);
+    assert_eq!(consumed.messages[0].subscribed_message.delivery_count, 2);
+
+    // The next delivery moves it to the dead letter topic instead
+    nack(consumer_id);
+    let consumed = consume(
+        topic.topic_id,
+        subscription.subscription_id,
+        Some(consumer_id),
+    );
+    assert_eq!(consumed.messages.len(), 0);
+
+    let model_topic = cluster.topics().get(&topic.topic_id).unwrap();
+    let model_subscription = model_topic
+        .subscriptions()
+        .get(&subscription.subscription_id)
+        .unwrap();
+    let subscription_stats = model_subscription.stats();
+    assert_eq!(subscription_stats.queued_count, 0);
+    assert_eq!(subscription_stats.unacked_count, 0);
+
+    // The dead letter topic was created with a subscription of the same name
+    let dead_letter_topic_name =
+        sub_service.dead_letter_topic_name(&model_topic, &model_subscription);
+    assert_eq!(dead_letter_topic_name, "topic1-subscription1-DLQ");
+    let dead_letter_subscription =
+        match sub_service.subscription_by_name(&dead_letter_topic_name, "subscription1") {
+            Some(subscription) => subscription,
+            None => panic!("Dead letter subscription was not created"),
+        };
+    let consumed = consume(
+        dead_letter_subscription.topic_id(),
+        dead_letter_subscription.subscription_id(),
+        None,
+    );
+    assert_eq!(consumed.messages.len(), 1);
+    let dead_letter = &consumed.messages[0].published_message;
+    assert_eq!(dead_letter.key, "a");
+    assert_eq!(
+        dead_letter.attributes.get(DEAD_LETTER_SOURCE_ATTRIBUTE),
+        Some(&message_ref_key)
+    );
+
+    let dead_letter_count = test_cluster
+        .persistence
+        .events_by_key_prefix(
+            &PersistenceLayer::build_topic_prefix(topic.topic_id),
+            &EventQueryOptions::replay(),
+        )
+        .filter_map(|log_entry| log_entry.deserialize())
+        .filter(|event| matches!(event, LoggedEvent::DeadLetter(_)))
+        .count();
+    assert_eq!(dead_letter_count, 1);
+}
+
+#[test]
+fn should_replay_unacked_backlog_after_restart() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", true)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let ledger = &test_cluster.topics[0].partitions[0].ledger;
+    let subscription = &test_cluster.topics[0].subscriptions[0];
+
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+
+    let message_ref_keys: Vec<String> = ["a", "b", "c", "d"]
+        .iter()
+        .map(|key| {
+            match pub_service.publish_message(published_message(
+                topic.topic_id,
+                partition.partition_id,
+                key,
+            )) {
+                Ok(message_ref) => message_ref.to_key(),
+                Err(_) => panic!("Publish request failed"),
+            }
+        })
+        .collect();
+
+    let consumed = match sub_service.consume_max_messages(
+        topic.topic_id,
+        subscription.subscription_id,
+        None,
+        10,
+    ) {
+        Ok(consumed_messages) => consumed_messages,
+        Err(_) => panic!("Consume request failed"),
+    };
+    let consumer_id = consumed.consumer_id;
+    assert_eq!(consumed.messages.len(), 4);
+
+    // Ack the first and third messages, and nack the second
+    for index in [0, 2] {
+        match sub_service.ack(
+            message_ref_keys[index].clone(),
+            subscription.subscription_id,
+            consumer_id,
+        ) {
+            Ok(acked) => assert!(acked),
+            Err(_) => panic!("Ack request failed"),
+        }
+    }
+    match sub_service.nack(
+        message_ref_keys[1].clone(),
+        subscription.subscription_id,
+        consumer_id,
+    ) {
+        Ok(nacked) => assert!(nacked),
+        Err(_) => panic!("Nack request failed"),
+    }
+
+    // After a restart, the subscription has the messages that were not acked, in the order that
+    // they were published, including the one that was delivered and never acked or nacked
+    let restarted = test_cluster.cluster();
+    let restarted_subscription = restarted
+        .topics()
+        .get(&topic.topic_id)
+        .unwrap()
+        .subscriptions()
+        .get(&subscription.subscription_id)
+        .unwrap();
+    let stats = restarted_subscription.stats();
+    assert_eq!(stats.queued_count, 2);
+    assert_eq!(stats.unacked_count, 0);
+    assert_eq!(stats.affinity_count, 0);
+
+    // The replayed messages are kept in the ledger, so they can be consumed and acked
+    let restarted_sub_service = SubService::new(&test_cluster.persistence, &restarted);
+    let consumed = match restarted_sub_service.consume_max_messages(
+        topic.topic_id,
+        subscription.subscription_id,
+        None,
+        10,
+    ) {
+        Ok(consumed_messages) => consumed_messages,
+        Err(_) => panic!("Consume request failed"),
+    };
+    assert_eq!(keys(&consumed), vec!["b", "d"]);
+    let delivery_counts: Vec<usize> = consumed
+        .messages
+        .iter()
+        .map(|message| message.subscribed_message.delivery_count)
+        .collect();
+    assert_eq!(delivery_counts, vec![2, 1]);
+
+    for message in &consumed.messages {
+        match restarted_sub_service.ack(
+            message.subscribed_message.message_ref_key.clone(),
+            subscription.subscription_id,
+            consumed.consumer_id,
+        ) {
+            Ok(acked) => assert!(acked),
+            Err(_) => panic!("Ack request failed"),
+        }
+    }
+
+    let stats = restarted_subscription.stats();
+    assert_eq!(stats.queued_count, 0);
+    assert_eq!(stats.unacked_count, 0);
+    let ledger_stats = restarted
+        .topics()
+        .get(&topic.topic_id)
+        .unwrap()
+        .partitions()
+        .get(&partition.partition_id)
+        .unwrap()
+        .ledgers()
+        .get(&ledger.ledger_id)
+        .unwrap()
+        .stats();
+    assert_eq!(ledger_stats.message_count, 0);
+    assert_eq!(ledger_stats.unacked_count, 0);
+}
+
+#[test]
+fn should_log_consumer_and_key_affinity_events() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", true)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let subscription = &test_cluster.topics[0].subscriptions[0];
+
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+
+    for key in ["a", "b", "a"] {
+        let message = published_message(topic.topic_id, partition.partition_id, key);
+        if pub_service.publish_message(message).is_err() {
+            panic!("Publish request failed");
+        }
+    }
+
+    let consumed = match sub_service.consume_max_messages(
+        topic.topic_id,
+        subscription.subscription_id,
+        None,
+        10,
+    ) {
+        Ok(consumed_messages) => consumed_messages,
+        Err(_) => panic!("Consume request failed"),
+    };
+    let consumer_id = consumed.consumer_id;
+    assert_eq!(consumed.messages.len(), 3);
+
+    if sub_service
+        .disconnect_consumer(topic.topic_id, subscription.subscription_id, consumer_id)
+        .is_err()
+    {
+        panic!("Disconnect request failed");
+    }
+
+    let events: Vec<LoggedEvent> = test_cluster
+        .persistence
+        .events_by_key_prefix(
+            &PersistenceLayer::build_topic_prefix(topic.topic_id),
+            &EventQueryOptions::replay(),
+        )
+        .filter_map(|log_entry| log_entry.deserialize())
+        .collect();
+
+    let new_consumers: Vec<_> = events
+        .iter()
+        .filter_map(|event| match event {
+            LoggedEvent::NewConsumer(event) => Some(event.consumer_id),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(new_consumers, vec![consumer_id]);
+
+    // Only the first message with each key creates an affinity
+    let affinity_keys: Vec<_> = events
+        .iter()
+        .filter_map(|event| match event {
+            LoggedEvent::KeyAffinity(event) => {
+                assert_eq!(event.subscription_id, subscription.subscription_id);
+                assert_eq!(event.consumer_id, consumer_id);
+                Some(event.message_key.as_str())
+            }
+            _ => None,
+        })
+        .collect();
+    assert_eq!(affinity_keys, vec!["a", "b"]);
+
+    let dropped_consumers: Vec<_> = events
+        .iter()
+        .filter_map(|event| match event {
+            LoggedEvent::DropConsumer(event) => Some(event.consumer_id),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(dropped_consumers, vec![consumer_id]);
+}
+
+#[test]
+fn should_forget_messages_on_delivery_when_at_most_once() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("shared", false)
+        .subscription("key-shared", true)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let ledger = &test_cluster.topics[0].partitions[0].ledger;
+    let subscriptions = &test_cluster.topics[0].subscriptions;
+
+    for subscription in subscriptions {
+        test_cluster
+            .data_layer
+            .set_subscription_delivery_semantics(
+                topic.topic_id,
+                subscription.subscription_id,
+                DeliverySemantics::AtMostOnce,
+            )
+            .unwrap();
+    }
+
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+
+    for key in ["a", "b", "a"] {
+        let message = published_message(topic.topic_id, partition.partition_id, key);
+        if pub_service.publish_message(message).is_err() {
+            panic!("Publish request failed");
+        }
+    }
+
+    let model_topic = cluster.topics().get(&topic.topic_id).unwrap();
+    let model_ledger = model_topic
+        .partitions()
+        .get(&partition.partition_id)
+        .unwrap()
+        .ledgers()
+        .get(&ledger.ledger_id)
+        .unwrap();
+    assert_eq!(model_ledger.stats().message_count, 3);
+
+    for subscription in subscriptions {
+        let consumed = match sub_service.consume_max_messages(
+            topic.topic_id,
+            subscription.subscription_id,
+            None,
+            10,
+        ) {
+            Ok(consumed_messages) => consumed_messages,
+            Err(_) => panic!("Consume request failed"),
+        };
+        assert_eq!(consumed.messages.len(), 3);
+
+        // Nothing is kept for redelivery, including key affinities
+        let stats = model_topic
+            .subscriptions()
+            .get(&subscription.subscription_id)
+            .unwrap()
+            .stats();
+        assert_eq!(stats.queued_count, 0);
+        assert_eq!(stats.unacked_count, 0);
+        assert_eq!(stats.affinity_count, 0);
+
+        // The messages were already acked, so acking them again does nothing
+        let message_ref_key = consumed.messages[0]
+            .subscribed_message
+            .message_ref_key
+            .clone();
+        match sub_service.ack(
+            message_ref_key,
+            subscription.subscription_id,
+            consumed.consumer_id,
+        ) {
+            Ok(acked) => assert!(!acked),
+            Err(_) => panic!("Ack request failed"),
+        }
+    }
+
+    // Once both subscriptions have had the messages, they are removed from the ledger
+    assert_eq!(model_ledger.stats().message_count, 0);
+
+    // After a restart, the messages are not delivered again
+    let restarted = test_cluster.cluster();
+    for subscription in subscriptions {
+        let stats = restarted
+            .topics()
+            .get(&topic.topic_id)
+            .unwrap()
+            .subscriptions()
+            .get(&subscription.subscription_id)
+            .unwrap()
+            .stats();
+        assert_eq!(stats.queued_count, 0);
+    }
+}
+
+#[test]
+fn should_seek_subscription_to_timestamp() {
+    let test_cluster = ClusterBuilder::new("10.0.0.1")
+        .topic("topic1", 1)
+        .subscription("subscription1", false)
+        .build();
+    let topic = &test_cluster.topics[0].topic;
+    let partition = &test_cluster.topics[0].partitions[0].partition;
+    let ledger = &test_cluster.topics[0].partitions[0].ledger;
+    let subscription = &test_cluster.topics[0].subscriptions[0];
+
+    let cluster = test_cluster.cluster();
+    let pub_service = PubService::new(&test_cluster.persistence, &cluster);
+    let sub_service = SubService::new(&test_cluster.persistence, &cluster);
+
+    for key in ["a", "b", "c"] {
+        let message = published_message(topic.topic_id, partition.partition_id, key);
+        if pub_service.publish_message(message).is_err() {
+            panic!("Publish request failed");
+        }
+        thread::sleep(Duration::from_millis(2));
+    }
+
+    let consume = |consumer_id| match sub_service.consume_max_messages(
+        topic.topic_id,
+        subscription.subscription_id,
+        consumer_id,
+        10,
+    ) {
+        Ok(consumed_messages) => consumed_messages,
+        Err(_) => panic!("Consume request failed"),
+    };
+    let ack = |message_ref_key: &str, consumer_id| match sub_service.ack(
+        message_ref_key.to_owned(),
+        subscription.subscription_id,
+        consumer_id,
+    ) {
+        Ok(acked) => acked,
+        Err(_) => panic!("Ack request failed"),
+    };
+
+    // Ack the first two messages and leave the third in-flight
+    let consumed = consume(None);
+    assert_eq!(consumed.messages.len(), 3);
+    let consumer_id = consumed.consumer_id;
+    let message_ref_keys: Vec<String> = consumed
+        .messages
+        .iter()
+        .map(|message| message.subscribed_message.message_ref_key.clone())
+        .collect();
+    let published: Vec<u64> = consumed
+        .messages
+        .iter()
+        .map(|message| message.published_message.published)
+        .collect();
+    assert!(ack(&message_ref_keys[0], consumer_id));
+    assert!(ack(&message_ref_keys[1], consumer_id));
+
+    let model_topic = cluster.topics().get(&topic.topic_id).unwrap();
+    let model_subscription = model_topic
+        .subscriptions()
+        .get(&subscription.subscription_id)
+        .unwrap();
+    let model_ledger = model_topic
+        .partitions()
+        .get(&partition.partition_id)
+        .unwrap()
+        .ledgers()
+        .get(&ledger.ledger_id)
+        .unwrap();
+    assert_eq!(model_ledger.stats().message_count, 1);
+
+    // Seeking to before the first message queues every message that is available
+    match sub_service.seek(topic.topic_id, subscription.subscription_id, 0) {
+        Ok(seek) => {
+            assert_eq!(seek.queued_count, 3);
+            assert_eq!(seek.earliest_published, Some(published[0]));
+        }
+        Err(_) => panic!("Seek request failed"),
+    }
+    let stats = model_subscription.stats();
+    assert_eq!(stats.queued_count, 3);
+    assert_eq!(stats.unacked_count, 0);
+    assert_eq!(model_ledger.stats().message_count, 3);
+
+    // The in-flight delivery was reset, so its ack is ignored
+    assert!(!ack(&message_ref_keys[2], consumer_id));
+
+    // Seeking forward drops the earlier messages
+    match sub_service.seek(topic.topic_id, subscription.subscription_id, published[1]) {
+        Ok(seek) => {
+            assert_eq!(seek.queued_count, 2);
+            assert_eq!(seek.earliest_published, Some(published[1]));
+        }
+        Err(_) => panic!("Seek request failed"),
+    }
+    assert_eq!(model_ledger.stats().message_count, 2);
+
+    let consumed = consume(Some(consumer_id));
+    let keys: Vec<&str> = consumed
+        .messages
+        .iter()
+        .map(|message| message.published_message.key.as_str())
+        .collect();
+    assert_eq!(keys, vec!["b", "c"]);
+    for message in &consumed.messages {
+        assert!(ack(
+            &message.subscribed_message.message_ref_key,
+            consumer_id
+        ));
+    }
+    assert_eq!(model_ledger.stats().message_count, 0);
+}
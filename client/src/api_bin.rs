@@ -1,6 +1,17 @@
 pub mod async_client;
 mod async_receiver_thread;
+pub mod codec;
+pub mod consume_stream;
+mod consumer_map;
+pub mod cluster_client;
 pub mod blocking_client;
 mod connection;
+mod keepalive;
+mod partition_cache;
+mod redirect;
 pub mod contracts;
 pub mod future_response;
+pub mod metrics;
+pub mod session;
+pub mod subscriber;
+pub mod versions;
@@ -1,35 +1,63 @@
 use super::{
-    connection::Connection,
-    contracts::{AckResult, ClientMessage, ClientResult, ConsumeResult, NackResult, PublishResult},
-    future_response::{FutureResponse, FutureResponseState},
+    codec::{CodecRegistry, TypedConsumeResult},
+    connection::{Connection, DEFAULT_SEND_QUEUE_CAPACITY},
+    consume_stream::ConsumeStream,
+    contracts::{
+        AckResult, BrokerMessage, ClientMessage, ClientResult, ConsumeResult,
+        DisconnectConsumerResult, FlowResult, JoinGroupResult, LeaveGroupResult, Message,
+        MessageHeaders, NackResult, PublishCallback, PublishItem, PublishResult, QuarantineResult,
+        TopicSummary,
+    },
+    future_response::{FlushFuture, FutureResponse, FutureResponseState},
+    partition_cache::choose_partition,
+    redirect::{Redirects, DEFAULT_MAX_REDIRECTS},
+    session::Session,
+    subscriber::Subscriber,
 };
 use crate::api_bin::{
-    async_receiver_thread::AsyncReceiverThread, contracts::ClientError,
+    async_receiver_thread::AsyncReceiverThread,
+    contracts::ClientError,
     future_response::FutureHashMap,
+    keepalive::{Keepalive, KeepaliveOptions},
+    metrics::ClientMetrics,
+    versions::VersionOptions,
 };
-use log::{debug, info};
+use log::{debug, info, warn};
 use pulsar_rust_net::{
     bin_serialization::{
-        ContractSerializer, DeserializeError, Request, RequestId, RequestPayload, ResponsePayload,
+        CompressionScheme, ContractSerializer, DeserializeError, Request, RequestId,
+        RequestPayload, ResponsePayload, SessionId, DEFAULT_SESSION_ID,
+    },
+    contracts::{
+        v1::{self, requests::NegotiateVersion},
+        v2,
     },
-    contracts::v1::{self, requests::NegotiateVersion, responses::RequestOutcome},
     data_types::{
-        ConsumerId, ContractVersionNumber, MessageCount, PartitionId, SubscriptionId, Timestamp,
-        TopicId,
+        ByteCount, ConsumerId, ContractVersionNumber, CreditCount, MessageCount, PartitionId,
+        Priority, SubscriptionId, Timestamp, TopicId,
+    },
+    sockets::{
+        buffer_pool::BufferPool,
+        tcp_channel::{check_message_size, DEFAULT_MAX_MESSAGE_SIZE},
+        tls::ConnectionSecurity,
     },
-    sockets::buffer_pool::BufferPool,
 };
 use std::{
     collections::HashMap,
     sync::{
         atomic::{AtomicBool, Ordering},
-        mpsc::{RecvError, SendError},
+        mpsc::{channel, Receiver, RecvError, SendError},
         Arc, Mutex,
     },
     thread,
+    time::{Duration, Instant},
 };
 use uuid::Uuid;
 
+/// How long to wait for the broker to list the partitions of a topic before a message can be
+/// published to it
+const GET_PARTITIONS_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub struct Client {
     authority: String,
     buffer_pool: Arc<BufferPool>,
@@ -37,8 +65,19 @@ pub struct Client {
     serializer: ContractSerializer,
     connection: Option<Connection>,
     version: Option<ContractVersionNumber>,
+    version_options: VersionOptions,
+    max_message_size: usize,
+    send_queue_capacity: usize,
+    security: ConnectionSecurity,
+    compression: CompressionScheme,
+    consume_max_bytes: Option<ByteCount>,
+    keepalive: Option<KeepaliveOptions>,
+    max_redirects: usize,
+    redirects: Arc<Redirects<Client>>,
     next_request_id: Mutex<RequestId>,
+    next_session_id: Mutex<SessionId>,
     futures: Arc<Mutex<FutureHashMap>>,
+    metrics: Arc<ClientMetrics>,
 }
 
 impl Client {
@@ -52,101 +91,274 @@ impl Client {
             connection: None,
             serializer: ContractSerializer::new(&buffer_pool),
             version: None,
+            version_options: VersionOptions::default(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            send_queue_capacity: DEFAULT_SEND_QUEUE_CAPACITY,
+            security: ConnectionSecurity::Plain,
+            compression: CompressionScheme::None,
+            consume_max_bytes: None,
+            keepalive: None,
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+            redirects: Arc::new(Redirects::none()),
             next_request_id: Mutex::new(1),
+            next_session_id: Mutex::new(DEFAULT_SESSION_ID + 1),
             futures: Arc::new(Mutex::new(FutureHashMap::new())),
+            metrics: Arc::new(ClientMetrics::new()),
         }
     }
 
-    pub fn connect(self: &mut Self) -> Result<(), String> {
-        self.connection = Some(Connection::new(&self.buffer_pool, &self.authority));
+    /// Sets the range of API versions that this client will accept from the broker, and
+    /// whether to try each version in the range on its own when the broker rejects the range
+    pub fn with_version_options(mut self: Self, version_options: VersionOptions) -> Self {
+        self.version_options = version_options;
+        self
+    }
 
-        let payload = NegotiateVersion {
-            min_version: 1,
-            max_version: 1,
-        };
-        let request = Request {
-            request_id: 0,
-            payload: RequestPayload::NegotiateVersion(payload),
-        };
+    /// Sets the maximum length of a serialized request. Requests that are longer than this
+    /// fail with `ClientError::MessageTooLarge` instead of being sent to the broker
+    pub fn with_max_message_size(mut self: Self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
 
-        #[cfg(debug_assertions)]
-        debug!("Client: Sending {:?}", request);
+    /// Sets how many requests can be queued for sending to the broker. When the network can
+    /// not keep up, requests fail with `ClientError::WouldBlock` instead of being queued
+    pub fn with_send_queue_capacity(mut self: Self, send_queue_capacity: usize) -> Self {
+        self.send_queue_capacity = send_queue_capacity;
+        self
+    }
+
+    /// Offers the broker a scheme for compressing requests and responses. The broker uses it
+    /// if it supports compression, otherwise messages are sent without compression
+    pub fn with_compression(mut self: Self, compression: CompressionScheme) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Limits the total serialized size of the messages in each batch that is consumed, so
+    /// that a batch of large messages can not make the response too large to receive. The
+    /// broker still returns the first message that is available when it is larger than this
+    pub fn with_consume_max_bytes(mut self: Self, max_bytes: ByteCount) -> Self {
+        self.consume_max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Sets whether the connection to the broker is encrypted with TLS. This must match the
+    /// security of the broker, otherwise the connection is closed during the TLS handshake
+    pub fn with_security(mut self: Self, security: ConnectionSecurity) -> Self {
+        self.security = security;
+        self
+    }
+
+    /// Pings the broker each time the interval elapses after it answered the last ping. If the
+    /// broker does not answer within the timeout the connection is treated as lost, requests
+    /// that are waiting for a response fail with `ClientError::ConnectionLost`, and so do new
+    /// requests until the client reconnects
+    pub fn with_keepalive(mut self: Self, interval: Duration, timeout: Duration) -> Self {
+        self.keepalive = Some(KeepaliveOptions { interval, timeout });
+        self
+    }
+
+    /// Sets how many times a publish or consume request is sent to another broker, when the
+    /// broker that received it says that the other broker owns the partition. Zero returns
+    /// `ClientError::IncorrectNode` to the application instead. The request is only sent again
+    /// when its future is awaited, and the publish callback is passed the result from the
+    /// first broker
+    pub fn with_max_redirects(mut self: Self, max_redirects: usize) -> Self {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    /// Sets how long the partitions of each topic are cached before they are fetched from the
+    /// broker again. Partitions can be added to a topic while the broker is running
+    pub fn with_partition_cache_duration(self: Self, cache_duration: Duration) -> Self {
+        self.futures
+            .lock()
+            .unwrap()
+            .partitions
+            .set_cache_duration(cache_duration);
+        self
+    }
+
+    /// Connects to the broker and negotiates the API version to use. Fails with
+    /// `ClientError::IncompatibleVersion` if the broker does not support any of the versions
+    /// that this client accepts
+    pub fn connect(self: &mut Self) -> ClientResult<()> {
+        self.stop_signal = Arc::new(AtomicBool::new(false));
+        self.connection = Some(Connection::new(
+            &self.buffer_pool,
+            &self.authority,
+            self.max_message_size,
+            self.send_queue_capacity,
+            &self.security,
+        ));
 
-        let message = self.serializer.serialize_request(&request).unwrap();
-
-        if let Err(e) = self.send(message) {
-            panic!("Client: Error sending API version negotiation request: {e}");
-        }
-
-        match self.recv() {
-            Ok(message) => match self.serializer.deserialize_response(message) {
-                Ok(response) => {
-                    #[cfg(debug_assertions)]
-                    debug!("Client: Received {:?}", &response);
-                    if let ResponsePayload::NegotiateVersion(version_response) = response.payload {
-                        match version_response.outcome {
-                            RequestOutcome::Success => {
-                                if let Some(data) = version_response.data {
-                                    #[cfg(debug_assertions)]
-                                    debug!("Client: Negotiated API version {}", data.version);
-                                    self.version = Some(data.version);
-                                }
-                                if let Some(connection) = &mut self.connection {
-                                    if let Some(receiver) = connection.take_receiver() {
-                                        let thread = AsyncReceiverThread::new(
-                                            &self.buffer_pool,
-                                            &self.stop_signal,
-                                            &self.futures,
-                                            receiver,
-                                        );
-                                        thread::Builder::new()
-                                            .name(String::from("async-rx"))
-                                            .spawn(|| thread.run())
-                                            .unwrap();
-                                    }
-                                }
-                                Ok(())
-                            }
-                            RequestOutcome::Warning(msg) => {
-                                if let Some(data) = version_response.data {
-                                    #[cfg(debug_assertions)]
-                                    debug!("Client: Negotiated API version: {}", data.version);
-                                    self.version = Some(data.version);
-                                }
-                                Err(format!("Client: Warning negotiating API version {}", msg))
-                            }
-                            RequestOutcome::NoData(msg) => {
-                                Err(format!("Client: No data negotiating API version: {}", msg))
-                            }
-                            RequestOutcome::Error(msg, _code) => {
-                                Err(format!("Client: Error negotiating API version: {}", msg))
-                            }
-                        }
-                    } else {
-                        Err(format!(
-                            "Client: Received wrong response type for negotiate version request"
-                        ))
+        // Version negotiation requests are never compressed
+        self.serializer.set_compression(CompressionScheme::None);
+
+        let mut negotiated = Err(ClientError::IncompatibleVersion);
+        for attempt in self.version_options.attempts(self.compression) {
+            negotiated = self.negotiate_version(attempt);
+            if !matches!(negotiated, Err(ClientError::IncompatibleVersion)) {
+                break;
+            }
+        }
+
+        match negotiated {
+            Ok((version, compression)) => {
+                #[cfg(debug_assertions)]
+                debug!("Client: Negotiated API version {version} with {compression:?} compression");
+                self.version = Some(version);
+                self.serializer.set_compression(compression);
+                self.redirects = Arc::new(self.redirects());
+                if let Some(connection) = &mut self.connection {
+                    let keepalive = self
+                        .keepalive
+                        .map(|options| Keepalive::new(options, connection.request_sender()));
+                    if let Some(receiver) = connection.take_receiver() {
+                        let thread = AsyncReceiverThread::new(
+                            &self.buffer_pool,
+                            &self.stop_signal,
+                            &self.futures,
+                            &self.metrics,
+                            receiver,
+                            keepalive,
+                        );
+                        thread::Builder::new()
+                            .name(String::from("async-rx"))
+                            .spawn(|| thread.run())
+                            .unwrap();
                     }
                 }
-                Err(err) => match err {
-                    DeserializeError::Error { msg } => Err(format!("Client: {}", msg)),
-                },
-            },
-            Err(err) => Err(format!(
-                "Client: Error receiving API version negotiation response: {err}"
-            )),
+                Ok(())
+            }
+            Err(err) => {
+                warn!("Client: Failed to negotiate an API version with the broker. {err:?}");
+                self.disconnect();
+                Err(err)
+            }
+        }
+    }
+
+    /// Offers the broker a range of API versions and returns the version that the
+    /// broker chose
+    fn negotiate_version(
+        self: &Self,
+        payload: NegotiateVersion,
+    ) -> ClientResult<(ContractVersionNumber, CompressionScheme)> {
+        let request = Request::new(0, RequestPayload::NegotiateVersion(payload));
+
+        #[cfg(debug_assertions)]
+        debug!("Client: Sending {:?}", request);
+
+        let message = self.serialize(&request)?;
+        self.send(message)?;
+        let message = self.recv().map_err(ClientError::RecvError)?;
+        let response = self
+            .serializer
+            .deserialize_response(message)
+            .map_err(|err| ClientError::DeserializeError(self.count_deserialize_error(err)))?;
+
+        #[cfg(debug_assertions)]
+        debug!("Client: Received {:?}", &response);
+
+        match response.payload {
+            ResponsePayload::NegotiateVersion(version_response) => {
+                let compression = version_response
+                    .data
+                    .as_ref()
+                    .map_or(CompressionScheme::None, |data| data.compression);
+                let version = self.version_options.negotiated_version(version_response)?;
+                Ok((version, compression))
+            }
+            _ => Err(ClientError::IncorrectResponseType),
         }
     }
 
+    /// Requests are redirected by clients with the same options as this one, that are
+    /// connected to the broker that owns the partition
+    fn redirects(self: &Self) -> Redirects<Client> {
+        let buffer_pool = self.buffer_pool.clone();
+        let version_options = self.version_options;
+        let max_message_size = self.max_message_size;
+        let send_queue_capacity = self.send_queue_capacity;
+        let security = self.security.clone();
+        let compression = self.compression;
+        let consume_max_bytes = self.consume_max_bytes;
+        let keepalive = self.keepalive;
+        Redirects::new(self.max_redirects, move |authority: &str, max_redirects| {
+            info!("Client: Redirected to {authority}");
+            let mut client = Client::new(&buffer_pool, authority)
+                .with_version_options(version_options)
+                .with_max_message_size(max_message_size)
+                .with_send_queue_capacity(send_queue_capacity)
+                .with_security(security.clone())
+                .with_compression(compression)
+                .with_max_redirects(max_redirects);
+            client.consume_max_bytes = consume_max_bytes;
+            client.keepalive = keepalive;
+            client.connect()?;
+            Ok(client)
+        })
+    }
+
     pub fn disconnect(self: &mut Self) {
         self.stop_signal.store(true, Ordering::Relaxed);
         if let Some(connection) = self.connection.take() {
             connection.disconnect();
         }
+
+        // Dropping the redirected clients disconnects them
+        self.redirects.drain();
+    }
+
+    /// Counts of errors serializing requests and deserializing responses on this client
+    pub fn metrics(self: &Self) -> &ClientMetrics {
+        &self.metrics
+    }
+
+    /// Drops the connection to the broker and connects again. Subsequent consume requests
+    /// that do not specify a consumer id will continue as the consumer that was previously
+    /// allocated for the subscription
+    pub fn reconnect(self: &mut Self) -> ClientResult<()> {
+        self.disconnect();
+        self.connect()
     }
 
+    /// False after the client disconnects, or when the connection to the broker is lost
     pub fn is_connected(self: &Self) -> bool {
-        self.connection.is_some()
+        self.connection.is_some() && !self.stop_signal.load(Ordering::Relaxed)
+    }
+
+    /// The version of the API contracts that was negotiated with the broker, or None if the
+    /// client has not connected
+    pub fn contract_version(self: &Self) -> Option<ContractVersionNumber> {
+        self.version
+    }
+
+    /// Opens a logical session that shares this client's connection to the broker. Each
+    /// session is treated by the broker as a separate client, and is allocated its own
+    /// consumer ids. Responses are routed back to the session that made the request.
+    pub fn open_session(self: &Arc<Self>) -> Session {
+        let mut next_session_id = self.next_session_id.lock().unwrap();
+        let session_id = *next_session_id;
+        *next_session_id = if session_id == SessionId::MAX {
+            DEFAULT_SESSION_ID + 1
+        } else {
+            session_id + 1
+        };
+        Session::new(self, session_id)
+    }
+
+    /// Opens a stream that consumes from a subscription in a new session. The stream remembers
+    /// the consumer id that the broker allocates in response to its first consume request,
+    /// and consumes as that consumer from then on
+    pub fn consume_stream(
+        self: &Arc<Self>,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+    ) -> ConsumeStream {
+        ConsumeStream::new(self.open_session(), topic_id, subscription_id)
     }
 
     /// Asynchronously publishes a message, returning a future that will complete when a response is received from the broker
@@ -157,16 +369,114 @@ impl Client {
         timestamp: Option<Timestamp>,
         attributes: HashMap<String, String>,
     ) -> ClientResult<FutureResponse<PublishResult>> {
-        let request_id = self.get_next_request_id();
+        self.publish_in_session(
+            DEFAULT_SESSION_ID,
+            topic_id,
+            key,
+            timestamp,
+            Priority::default(),
+            MessageHeaders::default(),
+            attributes,
+        )
+    }
+
+    /// Asynchronously publishes a message that will be delivered to consumers ahead of
+    /// messages with a lower priority
+    pub fn publish_with_priority(
+        self: &Self,
+        topic_id: TopicId,
+        key: Option<String>,
+        timestamp: Option<Timestamp>,
+        priority: Priority,
+        attributes: HashMap<String, String>,
+    ) -> ClientResult<FutureResponse<PublishResult>> {
+        self.publish_in_session(
+            DEFAULT_SESSION_ID,
+            topic_id,
+            key,
+            timestamp,
+            priority,
+            MessageHeaders::default(),
+            attributes,
+        )
+    }
+
+    /// Asynchronously publishes a message with well-known headers in addition to its
+    /// attributes. Headers are delivered to consumers separately from the attributes
+    pub fn publish_with_headers(
+        self: &Self,
+        topic_id: TopicId,
+        key: Option<String>,
+        timestamp: Option<Timestamp>,
+        priority: Priority,
+        headers: MessageHeaders,
+        attributes: HashMap<String, String>,
+    ) -> ClientResult<FutureResponse<PublishResult>> {
+        self.publish_in_session(
+            DEFAULT_SESSION_ID,
+            topic_id,
+            key,
+            timestamp,
+            priority,
+            headers,
+            attributes,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn publish_in_session(
+        self: &Self,
+        session_id: SessionId,
+        topic_id: TopicId,
+        key: Option<String>,
+        timestamp: Option<Timestamp>,
+        priority: Priority,
+        headers: MessageHeaders,
+        attributes: HashMap<String, String>,
+    ) -> ClientResult<FutureResponse<PublishResult>> {
         let key: String = key.unwrap_or_else(|| Uuid::new_v4().to_string());
+        let partition_id = self.get_partition_id(topic_id, &key)?;
+        let request_id = self.get_next_request_id();
+        let retry = self
+            .redirects
+            .enabled()
+            .then(|| (key.clone(), headers.clone(), attributes.clone()));
 
         #[cfg(debug_assertions)]
         debug!("Client: Request {} publish with key {}", request_id, key);
 
-        match self.send_publish(request_id, topic_id, &key, timestamp, attributes) {
+        match self.send_publish(
+            request_id,
+            session_id,
+            topic_id,
+            partition_id,
+            &key,
+            timestamp,
+            priority,
+            headers,
+            attributes,
+        ) {
             Ok(_) => {
                 let state = Arc::new(Mutex::new(FutureResponseState::new()));
-                let future = FutureResponse::new(&state);
+                let mut future =
+                    FutureResponse::new(&state, &self.futures, request_id, |futures| {
+                        &mut futures.publish_futures
+                    })
+                    .with_publish_callback();
+                if let Some((key, headers, attributes)) = retry {
+                    let redirects = self.redirects.clone();
+                    future = future.with_redirect(move |authority| {
+                        redirects.client(authority)?.publish_in_session(
+                            session_id,
+                            topic_id,
+                            Some(key),
+                            timestamp,
+                            priority,
+                            headers,
+                            attributes,
+                        )
+                    });
+                }
                 let mut futures = self.futures.lock().unwrap();
                 futures.publish_futures.insert(request_id, state);
                 Ok(future)
@@ -175,28 +485,258 @@ impl Client {
         }
     }
 
+    /// Asynchronously publishes several messages to a topic in one request, returning a future
+    /// that will complete when a response is received from the broker. Each message is
+    /// published on its own, so the future completes with a result for each message, in the
+    /// order that they were passed
+    pub fn publish_batch(
+        self: &Self,
+        topic_id: TopicId,
+        items: Vec<PublishItem>,
+    ) -> ClientResult<FutureResponse<Vec<ClientResult<PublishResult>>>> {
+        self.publish_batch_in_session(DEFAULT_SESSION_ID, topic_id, items)
+    }
+
+    pub(crate) fn publish_batch_in_session(
+        self: &Self,
+        session_id: SessionId,
+        topic_id: TopicId,
+        items: Vec<PublishItem>,
+    ) -> ClientResult<FutureResponse<Vec<ClientResult<PublishResult>>>> {
+        let request_id = self.get_next_request_id();
+
+        #[cfg(debug_assertions)]
+        debug!(
+            "Client: Request {} publish batch of {} messages",
+            request_id,
+            items.len()
+        );
+
+        match self.send_publish_batch(request_id, session_id, topic_id, items) {
+            Ok(_) => {
+                let state = Arc::new(Mutex::new(FutureResponseState::new()));
+                let future = FutureResponse::new(&state, &self.futures, request_id, |futures| {
+                    &mut futures.publish_batch_futures
+                })
+                .with_publish_callback();
+                let mut futures = self.futures.lock().unwrap();
+                futures.publish_batch_futures.insert(request_id, state);
+                Ok(future)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Asynchronously publishes a strongly typed payload, using the default codec from the registry
+    /// to encode the payload into the message attributes and its content type into the headers.
+    /// Consumers can decode the payloads with `consume_typed`
+    pub fn publish_typed<T>(
+        self: &Self,
+        topic_id: TopicId,
+        key: Option<String>,
+        timestamp: Option<Timestamp>,
+        value: &T,
+        codecs: &CodecRegistry<T>,
+    ) -> ClientResult<FutureResponse<PublishResult>> {
+        let (headers, attributes) = codecs.encode(value)?;
+        self.publish_with_headers(
+            topic_id,
+            key,
+            timestamp,
+            Priority::default(),
+            headers,
+            attributes,
+        )
+    }
+
     /// Asynchronously consumes messages, returning a future that will complete
-    /// when a response is received from the broker
+    /// when a response is received from the broker. If no consumer id is passed, then
+    /// the consumer id previously allocated by the broker for this subscription is used
     pub fn consume(
         self: &Self,
         topic_id: TopicId,
         subscription_id: SubscriptionId,
         consumer_id: &Option<ConsumerId>,
         max_messages: MessageCount,
+    ) -> ClientResult<FutureResponse<ConsumeResult>> {
+        self.consume_in_session(
+            DEFAULT_SESSION_ID,
+            topic_id,
+            subscription_id,
+            consumer_id,
+            max_messages,
+        )
+    }
+
+    /// Asynchronously consumes messages and decodes their payloads using the codec that matches
+    /// the content type of each message
+    pub async fn consume_typed<T>(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        consumer_id: &Option<ConsumerId>,
+        max_messages: MessageCount,
+        codecs: &CodecRegistry<T>,
+    ) -> ClientResult<TypedConsumeResult<T>> {
+        let result = self
+            .consume(topic_id, subscription_id, consumer_id, max_messages)?
+            .await?;
+        TypedConsumeResult::decode(result, codecs)
+    }
+
+    pub(crate) fn consumer_id_in_session(
+        self: &Self,
+        session_id: SessionId,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+    ) -> Option<ConsumerId> {
+        self.futures
+            .lock()
+            .unwrap()
+            .consumers
+            .get(session_id, topic_id, subscription_id)
+    }
+
+    pub(crate) fn consume_in_session(
+        self: &Self,
+        session_id: SessionId,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        consumer_id: &Option<ConsumerId>,
+        max_messages: MessageCount,
     ) -> ClientResult<FutureResponse<ConsumeResult>> {
         let request_id = self.get_next_request_id();
+        let requested_consumer_id = *consumer_id;
+        let consumer_id =
+            match consumer_id {
+                Some(consumer_id) => Some(*consumer_id),
+                None => self.futures.lock().unwrap().consumers.get(
+                    session_id,
+                    topic_id,
+                    subscription_id,
+                ),
+            };
         match self.send_consume(
             request_id,
+            session_id,
             topic_id,
             subscription_id,
-            consumer_id,
+            &consumer_id,
             max_messages,
         ) {
             Ok(_) => {
                 let state = Arc::new(Mutex::new(FutureResponseState::new()));
-                let future = FutureResponse::new(&state);
+                let mut future =
+                    FutureResponse::new(&state, &self.futures, request_id, |futures| {
+                        &mut futures.consume_futures
+                    });
+                if self.redirects.enabled() {
+                    // Consumer ids are allocated by each broker, so the id that was allocated by
+                    // this broker is not passed on to the broker that owns the partition
+                    let redirects = self.redirects.clone();
+                    future = future.with_redirect(move |authority| {
+                        redirects.client(authority)?.consume_in_session(
+                            session_id,
+                            topic_id,
+                            subscription_id,
+                            &requested_consumer_id,
+                            max_messages,
+                        )
+                    });
+                }
                 let mut futures = self.futures.lock().unwrap();
                 futures.consume_futures.insert(request_id, state);
+                futures
+                    .consumers
+                    .expect(request_id, session_id, topic_id, subscription_id);
+                Ok(future)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Subscribes to a subscription in a new session. Instead of waiting for consume requests,
+    /// the broker pushes messages to the subscriber as they become available, until the
+    /// subscriber has used up its credits
+    pub fn subscribe(
+        self: &Arc<Self>,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        credits: CreditCount,
+    ) -> ClientResult<Subscriber> {
+        let session = self.open_session();
+        let deliveries =
+            self.subscribe_in_session(session.session_id(), topic_id, subscription_id, credits)?;
+        Ok(Subscriber::new(
+            session,
+            topic_id,
+            subscription_id,
+            deliveries,
+        ))
+    }
+
+    pub(crate) fn subscribe_in_session(
+        self: &Self,
+        session_id: SessionId,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        credits: CreditCount,
+    ) -> ClientResult<Receiver<ClientResult<ConsumeResult>>> {
+        let request_id = self.get_next_request_id();
+        let consumer_id = self.consumer_id_in_session(session_id, topic_id, subscription_id);
+
+        // The broker can push messages before it responds to the subscribe request, so the
+        // channel is registered before the request is sent
+        let (sender, receiver) = channel();
+        {
+            let mut futures = self.futures.lock().unwrap();
+            futures.deliveries.insert(request_id, sender);
+            futures
+                .consumers
+                .expect(request_id, session_id, topic_id, subscription_id);
+        }
+        match self.send_subscribe(
+            request_id,
+            session_id,
+            topic_id,
+            subscription_id,
+            &consumer_id,
+            credits,
+        ) {
+            Ok(_) => Ok(receiver),
+            Err(err) => {
+                let mut futures = self.futures.lock().unwrap();
+                futures.deliveries.remove(&request_id);
+                futures.consumers.complete(request_id, None);
+                Err(err)
+            }
+        }
+    }
+
+    pub(crate) fn flow_in_session(
+        self: &Self,
+        session_id: SessionId,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+        credits: CreditCount,
+    ) -> ClientResult<FutureResponse<FlowResult>> {
+        let request_id = self.get_next_request_id();
+        match self.send_flow(
+            request_id,
+            session_id,
+            topic_id,
+            subscription_id,
+            consumer_id,
+            credits,
+        ) {
+            Ok(_) => {
+                let state = Arc::new(Mutex::new(FutureResponseState::new()));
+                let future = FutureResponse::new(&state, &self.futures, request_id, |futures| {
+                    &mut futures.flow_futures
+                });
+                let mut futures = self.futures.lock().unwrap();
+                futures.flow_futures.insert(request_id, state);
                 Ok(future)
             }
             Err(err) => Err(err),
@@ -210,12 +750,35 @@ impl Client {
         message_ref_key: &str,
         subscription_id: SubscriptionId,
         consumer_id: ConsumerId,
+    ) -> ClientResult<FutureResponse<AckResult>> {
+        self.ack_in_session(
+            DEFAULT_SESSION_ID,
+            message_ref_key,
+            subscription_id,
+            consumer_id,
+        )
+    }
+
+    pub(crate) fn ack_in_session(
+        self: &Self,
+        session_id: SessionId,
+        message_ref_key: &str,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
     ) -> ClientResult<FutureResponse<AckResult>> {
         let request_id = self.get_next_request_id();
-        match self.send_ack(request_id, message_ref_key, subscription_id, consumer_id) {
+        match self.send_ack(
+            request_id,
+            session_id,
+            message_ref_key,
+            subscription_id,
+            consumer_id,
+        ) {
             Ok(_) => {
                 let state = Arc::new(Mutex::new(FutureResponseState::new()));
-                let future = FutureResponse::new(&state);
+                let future = FutureResponse::new(&state, &self.futures, request_id, |futures| {
+                    &mut futures.ack_futures
+                });
                 let mut futures = self.futures.lock().unwrap();
                 futures.ack_futures.insert(request_id, state);
                 Ok(future)
@@ -231,12 +794,35 @@ impl Client {
         message_ref_key: &str,
         subscription_id: SubscriptionId,
         consumer_id: ConsumerId,
+    ) -> ClientResult<FutureResponse<NackResult>> {
+        self.nack_in_session(
+            DEFAULT_SESSION_ID,
+            message_ref_key,
+            subscription_id,
+            consumer_id,
+        )
+    }
+
+    pub(crate) fn nack_in_session(
+        self: &Self,
+        session_id: SessionId,
+        message_ref_key: &str,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
     ) -> ClientResult<FutureResponse<NackResult>> {
         let request_id = self.get_next_request_id();
-        match self.send_nack(request_id, message_ref_key, subscription_id, consumer_id) {
+        match self.send_nack(
+            request_id,
+            session_id,
+            message_ref_key,
+            subscription_id,
+            consumer_id,
+        ) {
             Ok(_) => {
                 let state = Arc::new(Mutex::new(FutureResponseState::new()));
-                let future = FutureResponse::new(&state);
+                let future = FutureResponse::new(&state, &self.futures, request_id, |futures| {
+                    &mut futures.nack_futures
+                });
                 let mut futures = self.futures.lock().unwrap();
                 futures.nack_futures.insert(request_id, state);
                 Ok(future)
@@ -245,62 +831,417 @@ impl Client {
         }
     }
 
-    fn get_next_request_id(self: &Self) -> RequestId {
-        let mut next_request_id = self.next_request_id.lock().unwrap();
-        let request_id = *next_request_id;
-        *next_request_id = if request_id == RequestId::MAX {
-            0
-        } else {
-            request_id + 1
-        };
-        request_id
+    /// Asynchronously quarantines a message that this consumer can not process. The message is
+    /// removed from the subscription without being redelivered, and is kept by the broker
+    /// with the reason
+    pub fn quarantine(
+        self: &Self,
+        message_ref_key: &str,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+        reason: &str,
+    ) -> ClientResult<FutureResponse<QuarantineResult>> {
+        self.quarantine_in_session(
+            DEFAULT_SESSION_ID,
+            message_ref_key,
+            subscription_id,
+            consumer_id,
+            reason,
+        )
     }
 
-    fn send_publish(
+    pub(crate) fn quarantine_in_session(
         self: &Self,
-        request_id: RequestId,
-        topic_id: TopicId,
-        key: &str,
-        timestamp: Option<Timestamp>,
-        attributes: HashMap<String, String>,
-    ) -> ClientResult<()> {
-        if self.connection.is_none() {
-            return Err(ClientError::NotConnected);
-        }
-        if self.version.is_none() {
-            return Err(ClientError::IncompatibleVersion);
+        session_id: SessionId,
+        message_ref_key: &str,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+        reason: &str,
+    ) -> ClientResult<FutureResponse<QuarantineResult>> {
+        let request_id = self.get_next_request_id();
+        match self.send_quarantine(
+            request_id,
+            session_id,
+            message_ref_key,
+            subscription_id,
+            consumer_id,
+            reason,
+        ) {
+            Ok(_) => {
+                let state = Arc::new(Mutex::new(FutureResponseState::new()));
+                let future = FutureResponse::new(&state, &self.futures, request_id, |futures| {
+                    &mut futures.quarantine_futures
+                });
+                let mut futures = self.futures.lock().unwrap();
+                futures.quarantine_futures.insert(request_id, state);
+                Ok(future)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Asynchronously fetches a message by its ack key. This does not count as a delivery of
+    /// the message, and does not change its state in any subscription
+    pub fn get_message(
+        self: &Self,
+        message_ref_key: &str,
+    ) -> ClientResult<FutureResponse<Message>> {
+        self.get_message_in_session(DEFAULT_SESSION_ID, message_ref_key)
+    }
+
+    pub(crate) fn get_message_in_session(
+        self: &Self,
+        session_id: SessionId,
+        message_ref_key: &str,
+    ) -> ClientResult<FutureResponse<Message>> {
+        let request_id = self.get_next_request_id();
+        match self.send_get_message(request_id, session_id, message_ref_key) {
+            Ok(_) => {
+                let state = Arc::new(Mutex::new(FutureResponseState::new()));
+                let future = FutureResponse::new(&state, &self.futures, request_id, |futures| {
+                    &mut futures.get_message_futures
+                });
+                let mut futures = self.futures.lock().unwrap();
+                futures.get_message_futures.insert(request_id, state);
+                Ok(future)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Asynchronously joins a consumer group on a subscription. Each group has its own
+    /// subscription, whose id is returned, and receives every message published after the group
+    /// was created. Members of the same group share the messages of the group's subscription
+    /// between them, and when a member leaves, the messages that it did not ack are redelivered
+    /// to the other members. After joining, calls to `consume` on the group's subscription with
+    /// no consumer id will consume as this group member
+    pub fn join_group(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        group_name: &str,
+    ) -> ClientResult<FutureResponse<JoinGroupResult>> {
+        self.join_group_in_session(DEFAULT_SESSION_ID, topic_id, subscription_id, group_name)
+    }
+
+    pub(crate) fn join_group_in_session(
+        self: &Self,
+        session_id: SessionId,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        group_name: &str,
+    ) -> ClientResult<FutureResponse<JoinGroupResult>> {
+        let request_id = self.get_next_request_id();
+        match self.send_join_group(
+            request_id,
+            session_id,
+            topic_id,
+            subscription_id,
+            group_name,
+        ) {
+            Ok(_) => {
+                let state = Arc::new(Mutex::new(FutureResponseState::new()));
+                let future = FutureResponse::new(&state, &self.futures, request_id, |futures| {
+                    &mut futures.join_group_futures
+                });
+                let mut futures = self.futures.lock().unwrap();
+                futures.join_group_futures.insert(request_id, state);
+                futures
+                    .consumers
+                    .expect(request_id, session_id, topic_id, subscription_id);
+                Ok(future)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Asynchronously leaves a consumer group. Any messages that were delivered to this
+    /// member and not acked are redelivered to the remaining members of the group
+    pub fn leave_group(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+    ) -> ClientResult<FutureResponse<LeaveGroupResult>> {
+        self.leave_group_in_session(DEFAULT_SESSION_ID, topic_id, subscription_id, consumer_id)
+    }
+
+    pub(crate) fn leave_group_in_session(
+        self: &Self,
+        session_id: SessionId,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+    ) -> ClientResult<FutureResponse<LeaveGroupResult>> {
+        let request_id = self.get_next_request_id();
+        match self.send_leave_group(
+            request_id,
+            session_id,
+            topic_id,
+            subscription_id,
+            consumer_id,
+        ) {
+            Ok(_) => {
+                let state = Arc::new(Mutex::new(FutureResponseState::new()));
+                let future = FutureResponse::new(&state, &self.futures, request_id, |futures| {
+                    &mut futures.leave_group_futures
+                });
+                let mut futures = self.futures.lock().unwrap();
+                futures.leave_group_futures.insert(request_id, state);
+                futures
+                    .consumers
+                    .remove(session_id, topic_id, subscription_id);
+                Ok(future)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Asynchronously disconnects a consumer. Any messages that were delivered to this
+    /// consumer and not acked are redelivered to other consumers of the subscription
+    pub fn disconnect_consumer(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+    ) -> ClientResult<FutureResponse<DisconnectConsumerResult>> {
+        self.disconnect_consumer_in_session(
+            DEFAULT_SESSION_ID,
+            topic_id,
+            subscription_id,
+            consumer_id,
+        )
+    }
+
+    pub(crate) fn disconnect_consumer_in_session(
+        self: &Self,
+        session_id: SessionId,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+    ) -> ClientResult<FutureResponse<DisconnectConsumerResult>> {
+        let request_id = self.get_next_request_id();
+        match self.send_disconnect_consumer(
+            request_id,
+            session_id,
+            topic_id,
+            subscription_id,
+            consumer_id,
+        ) {
+            Ok(_) => {
+                let state = Arc::new(Mutex::new(FutureResponseState::new()));
+                let future = FutureResponse::new(&state, &self.futures, request_id, |futures| {
+                    &mut futures.disconnect_consumer_futures
+                });
+                let mut futures = self.futures.lock().unwrap();
+                futures
+                    .disconnect_consumer_futures
+                    .insert(request_id, state);
+                if futures.consumers.get(session_id, topic_id, subscription_id) == Some(consumer_id)
+                {
+                    futures
+                        .consumers
+                        .remove(session_id, topic_id, subscription_id);
+                }
+                Ok(future)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Asynchronously fetches the ids of the partitions of a topic. The partitions are cached
+    /// when the response is received, and used to choose the partition that each message is
+    /// published to
+    pub fn get_partitions(
+        self: &Self,
+        topic_id: TopicId,
+    ) -> ClientResult<FutureResponse<Vec<PartitionId>>> {
+        let request_id = self.get_next_request_id();
+        match self.send_get_partitions(request_id, DEFAULT_SESSION_ID, topic_id) {
+            Ok(_) => {
+                let state = Arc::new(Mutex::new(FutureResponseState::new()));
+                let future = FutureResponse::new(&state, &self.futures, request_id, |futures| {
+                    &mut futures.get_partitions_futures
+                });
+                let mut futures = self.futures.lock().unwrap();
+                futures.get_partitions_futures.insert(request_id, state);
+                Ok(future)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Asynchronously lists the topics in the cluster, with the number of partitions and
+    /// subscriptions in each
+    pub fn list_topics(self: &Self) -> ClientResult<FutureResponse<Vec<TopicSummary>>> {
+        let request_id = self.get_next_request_id();
+        match self.send_list_topics(request_id, DEFAULT_SESSION_ID) {
+            Ok(_) => {
+                let state = Arc::new(Mutex::new(FutureResponseState::new()));
+                let future = FutureResponse::new(&state, &self.futures, request_id, |futures| {
+                    &mut futures.list_topics_futures
+                });
+                let mut futures = self.futures.lock().unwrap();
+                futures.list_topics_futures.insert(request_id, state);
+                Ok(future)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Returns a future that completes when responses have been received from the broker for
+    /// all outstanding requests, or with a timeout error if this takes longer than the timeout.
+    /// Call this before disconnecting to ensure that all published messages were received
+    pub fn flush(self: &Self, timeout: Duration) -> FlushFuture {
+        FlushFuture::new(&self.futures, Instant::now() + timeout)
+    }
+
+    /// Registers a function that is called with the result of each publish request, in addition
+    /// to completing the future. This allows producers to pipeline publish requests and react
+    /// to the results without awaiting each future. Replaces any previously registered callback
+    pub fn on_publish<F>(self: &Self, callback: F)
+    where
+        F: Fn(&ClientResult<PublishResult>) + Send + Sync + 'static,
+    {
+        let callback: Arc<PublishCallback> = Arc::new(callback);
+        self.futures.lock().unwrap().publish_callback = Some(callback);
+    }
+
+    fn get_next_request_id(self: &Self) -> RequestId {
+        let mut next_request_id = self.next_request_id.lock().unwrap();
+        let request_id = *next_request_id;
+        *next_request_id = if request_id == RequestId::MAX {
+            0
+        } else {
+            request_id + 1
+        };
+        request_id
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn send_publish(
+        self: &Self,
+        request_id: RequestId,
+        session_id: SessionId,
+        topic_id: TopicId,
+        partition_id: PartitionId,
+        key: &str,
+        timestamp: Option<Timestamp>,
+        priority: Priority,
+        headers: MessageHeaders,
+        attributes: HashMap<String, String>,
+    ) -> ClientResult<()> {
+        if self.connection.is_none() {
+            return Err(ClientError::NotConnected);
+        }
+        if self.version.is_none() {
+            return Err(ClientError::IncompatibleVersion);
         }
         let version = self.version.unwrap();
 
         let request = match version {
-            1 => Request {
+            1 => Request::for_session(
                 request_id,
-                payload: RequestPayload::V1Publish(v1::requests::Publish {
+                session_id,
+                RequestPayload::V1Publish(v1::requests::Publish {
                     topic_id,
-                    partition_id: self.get_partition_id(topic_id, &key),
+                    partition_id,
                     key: key.to_owned(),
                     timestamp,
+                    priority: Some(priority),
                     attributes,
+                    ack_level: v1::requests::PublishAckLevel::default(),
+                    headers,
+                    producer_id: None,
+                    sequence_number: None,
+                    deliver_after_millis: None,
                 }),
-            },
+            ),
+            2 => Request::for_session(
+                request_id,
+                session_id,
+                RequestPayload::V2Publish(v2::requests::Publish {
+                    topic_id,
+                    partition_id,
+                    key: key.to_owned(),
+                    timestamp,
+                    priority: Some(priority),
+                    attributes,
+                    ack_level: v1::requests::PublishAckLevel::default(),
+                    headers,
+                    producer_id: None,
+                    sequence_number: None,
+                    deliver_after_millis: None,
+                    deliver_at: None,
+                }),
+            ),
             _ => return Err(ClientError::VersionNotSupported),
         };
 
         #[cfg(debug_assertions)]
         debug!("Client: Sending {:?}", request);
 
-        let message = self.serializer.serialize_request(&request).unwrap();
+        let message = self.serialize(&request)?;
 
-        if let Err(err) = self.send(message) {
-            Err(ClientError::SendError(err))
-        } else {
-            Ok(())
+        self.send(message)
+    }
+
+    fn send_publish_batch(
+        self: &Self,
+        request_id: RequestId,
+        session_id: SessionId,
+        topic_id: TopicId,
+        items: Vec<PublishItem>,
+    ) -> ClientResult<()> {
+        if self.connection.is_none() {
+            return Err(ClientError::NotConnected);
         }
+        if self.version.is_none() {
+            return Err(ClientError::IncompatibleVersion);
+        }
+        let version = self.version.unwrap();
+
+        let request = match version {
+            1 | 2 => {
+                let mut messages = Vec::with_capacity(items.len());
+                for item in items {
+                    let key = item.key.unwrap_or_else(|| Uuid::new_v4().to_string());
+                    let partition_id = self.get_partition_id(topic_id, &key)?;
+                    messages.push(v1::requests::Publish {
+                        topic_id,
+                        partition_id,
+                        key,
+                        timestamp: item.timestamp,
+                        priority: Some(item.priority),
+                        attributes: item.attributes,
+                        ack_level: v1::requests::PublishAckLevel::default(),
+                        headers: item.headers,
+                        producer_id: None,
+                        sequence_number: None,
+                        deliver_after_millis: None,
+                    });
+                }
+                Request::for_session(
+                    request_id,
+                    session_id,
+                    RequestPayload::V1PublishBatch(v1::requests::PublishBatch { messages }),
+                )
+            }
+            _ => return Err(ClientError::VersionNotSupported),
+        };
+
+        #[cfg(debug_assertions)]
+        debug!("Client: Sending {:?}", request);
+
+        let message = self.serialize(&request)?;
+
+        self.send(message)
     }
 
     fn send_consume(
         self: &Self,
         request_id: RequestId,
+        session_id: SessionId,
         topic_id: TopicId,
         subscription_id: SubscriptionId,
         consumer_id: &Option<ConsumerId>,
@@ -315,33 +1256,33 @@ impl Client {
         let version = self.version.unwrap();
 
         let request = match version {
-            1 => Request {
+            1 | 2 => Request::for_session(
                 request_id,
-                payload: RequestPayload::V1Consume(v1::requests::Consume {
+                session_id,
+                RequestPayload::V1Consume(v1::requests::Consume {
                     topic_id,
                     subscription_id,
                     consumer_id: consumer_id.clone(),
                     max_messages,
+                    ack_previous: false,
+                    max_bytes: self.consume_max_bytes,
                 }),
-            },
+            ),
             _ => return Err(ClientError::VersionNotSupported),
         };
 
         #[cfg(debug_assertions)]
         debug!("Client: Sending {:?}", request);
 
-        let message = self.serializer.serialize_request(&request).unwrap();
+        let message = self.serialize(&request)?;
 
-        if let Err(err) = self.send(message) {
-            Err(ClientError::SendError(err))
-        } else {
-            Ok(())
-        }
+        self.send(message)
     }
 
     fn send_ack(
         self: &Self,
         request_id: RequestId,
+        session_id: SessionId,
         message_ref_key: &str,
         subscription_id: SubscriptionId,
         consumer_id: ConsumerId,
@@ -355,32 +1296,30 @@ impl Client {
         let version = self.version.unwrap();
 
         let request = match version {
-            1 => Request {
+            1 | 2 => Request::for_session(
                 request_id,
-                payload: RequestPayload::V1Ack(v1::requests::Ack {
+                session_id,
+                RequestPayload::V1Ack(v1::requests::Ack {
                     message_ref_key: message_ref_key.to_owned(),
                     subscription_id,
                     consumer_id,
                 }),
-            },
+            ),
             _ => return Err(ClientError::VersionNotSupported),
         };
 
         #[cfg(debug_assertions)]
         debug!("Client: Sending {:?}", request);
 
-        let message = self.serializer.serialize_request(&request).unwrap();
+        let message = self.serialize(&request)?;
 
-        if let Err(err) = self.send(message) {
-            Err(ClientError::SendError(err))
-        } else {
-            Ok(())
-        }
+        self.send(message)
     }
 
     fn send_nack(
         self: &Self,
         request_id: RequestId,
+        session_id: SessionId,
         message_ref_key: &str,
         subscription_id: SubscriptionId,
         consumer_id: ConsumerId,
@@ -394,35 +1333,369 @@ impl Client {
         let version = self.version.unwrap();
 
         let request = match version {
-            1 => Request {
+            1 | 2 => Request::for_session(
                 request_id,
-                payload: RequestPayload::V1Nack(v1::requests::Nack {
+                session_id,
+                RequestPayload::V1Nack(v1::requests::Nack {
                     message_ref_key: message_ref_key.to_owned(),
                     subscription_id,
                     consumer_id,
                 }),
-            },
+            ),
             _ => return Err(ClientError::VersionNotSupported),
         };
 
         #[cfg(debug_assertions)]
         debug!("Client: Sending {:?}", request);
 
-        let message = self.serializer.serialize_request(&request).unwrap();
+        let message = self.serialize(&request)?;
 
-        if let Err(err) = self.send(message) {
-            Err(ClientError::SendError(err))
-        } else {
-            Ok(())
+        self.send(message)
+    }
+
+    fn send_quarantine(
+        self: &Self,
+        request_id: RequestId,
+        session_id: SessionId,
+        message_ref_key: &str,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+        reason: &str,
+    ) -> ClientResult<()> {
+        if self.connection.is_none() {
+            return Err(ClientError::NotConnected);
+        }
+        if self.version.is_none() {
+            return Err(ClientError::IncompatibleVersion);
+        }
+        let version = self.version.unwrap();
+
+        let request = match version {
+            1 | 2 => Request::for_session(
+                request_id,
+                session_id,
+                RequestPayload::V1Quarantine(v1::requests::Quarantine {
+                    message_ref_key: message_ref_key.to_owned(),
+                    subscription_id,
+                    consumer_id,
+                    reason: reason.to_owned(),
+                }),
+            ),
+            _ => return Err(ClientError::VersionNotSupported),
+        };
+
+        #[cfg(debug_assertions)]
+        debug!("Client: Sending {:?}", request);
+
+        let message = self.serialize(&request)?;
+
+        self.send(message)
+    }
+
+    fn send_get_message(
+        self: &Self,
+        request_id: RequestId,
+        session_id: SessionId,
+        message_ref_key: &str,
+    ) -> ClientResult<()> {
+        if self.connection.is_none() {
+            return Err(ClientError::NotConnected);
+        }
+        if self.version.is_none() {
+            return Err(ClientError::IncompatibleVersion);
+        }
+        let version = self.version.unwrap();
+
+        let request = match version {
+            1 | 2 => Request::for_session(
+                request_id,
+                session_id,
+                RequestPayload::V1GetMessage(v1::requests::GetMessage {
+                    message_ref_key: message_ref_key.to_owned(),
+                }),
+            ),
+            _ => return Err(ClientError::VersionNotSupported),
+        };
+
+        #[cfg(debug_assertions)]
+        debug!("Client: Sending {:?}", request);
+
+        let message = self.serialize(&request)?;
+
+        self.send(message)
+    }
+
+    fn send_join_group(
+        self: &Self,
+        request_id: RequestId,
+        session_id: SessionId,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        group_name: &str,
+    ) -> ClientResult<()> {
+        if self.connection.is_none() {
+            return Err(ClientError::NotConnected);
+        }
+        if self.version.is_none() {
+            return Err(ClientError::IncompatibleVersion);
+        }
+        let version = self.version.unwrap();
+
+        let request = match version {
+            1 | 2 => Request::for_session(
+                request_id,
+                session_id,
+                RequestPayload::V1JoinGroup(v1::requests::JoinGroup {
+                    topic_id,
+                    subscription_id,
+                    group_name: group_name.to_owned(),
+                }),
+            ),
+            _ => return Err(ClientError::VersionNotSupported),
+        };
+
+        #[cfg(debug_assertions)]
+        debug!("Client: Sending {:?}", request);
+
+        let message = self.serialize(&request)?;
+
+        self.send(message)
+    }
+
+    fn send_leave_group(
+        self: &Self,
+        request_id: RequestId,
+        session_id: SessionId,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+    ) -> ClientResult<()> {
+        if self.connection.is_none() {
+            return Err(ClientError::NotConnected);
+        }
+        if self.version.is_none() {
+            return Err(ClientError::IncompatibleVersion);
+        }
+        let version = self.version.unwrap();
+
+        let request = match version {
+            1 | 2 => Request::for_session(
+                request_id,
+                session_id,
+                RequestPayload::V1LeaveGroup(v1::requests::LeaveGroup {
+                    topic_id,
+                    subscription_id,
+                    consumer_id,
+                }),
+            ),
+            _ => return Err(ClientError::VersionNotSupported),
+        };
+
+        #[cfg(debug_assertions)]
+        debug!("Client: Sending {:?}", request);
+
+        let message = self.serialize(&request)?;
+
+        self.send(message)
+    }
+
+    fn send_disconnect_consumer(
+        self: &Self,
+        request_id: RequestId,
+        session_id: SessionId,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+    ) -> ClientResult<()> {
+        if self.connection.is_none() {
+            return Err(ClientError::NotConnected);
+        }
+        if self.version.is_none() {
+            return Err(ClientError::IncompatibleVersion);
+        }
+        let version = self.version.unwrap();
+
+        let request = match version {
+            1 | 2 => Request::for_session(
+                request_id,
+                session_id,
+                RequestPayload::V1DisconnectConsumer(v1::requests::DisconnectConsumer {
+                    topic_id,
+                    subscription_id,
+                    consumer_id,
+                }),
+            ),
+            _ => return Err(ClientError::VersionNotSupported),
+        };
+
+        #[cfg(debug_assertions)]
+        debug!("Client: Sending {:?}", request);
+
+        let message = self.serialize(&request)?;
+
+        self.send(message)
+    }
+
+    fn send_subscribe(
+        self: &Self,
+        request_id: RequestId,
+        session_id: SessionId,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        consumer_id: &Option<ConsumerId>,
+        credits: CreditCount,
+    ) -> ClientResult<()> {
+        if self.connection.is_none() {
+            return Err(ClientError::NotConnected);
+        }
+        if self.version.is_none() {
+            return Err(ClientError::IncompatibleVersion);
         }
+        let version = self.version.unwrap();
+
+        let request = match version {
+            1 | 2 => Request::for_session(
+                request_id,
+                session_id,
+                RequestPayload::V1Subscribe(v1::requests::Subscribe {
+                    topic_id,
+                    subscription_id,
+                    consumer_id: *consumer_id,
+                    credits,
+                }),
+            ),
+            _ => return Err(ClientError::VersionNotSupported),
+        };
+
+        #[cfg(debug_assertions)]
+        debug!("Client: Sending {:?}", request);
+
+        let message = self.serialize(&request)?;
+
+        self.send(message)
     }
 
-    fn get_partition_id(self: &Self, _topic_id: TopicId, _key: &str) -> PartitionId {
-        // TODO: hash message key to partition id
-        1
+    fn send_flow(
+        self: &Self,
+        request_id: RequestId,
+        session_id: SessionId,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+        credits: CreditCount,
+    ) -> ClientResult<()> {
+        if self.connection.is_none() {
+            return Err(ClientError::NotConnected);
+        }
+        if self.version.is_none() {
+            return Err(ClientError::IncompatibleVersion);
+        }
+        let version = self.version.unwrap();
+
+        let request = match version {
+            1 | 2 => Request::for_session(
+                request_id,
+                session_id,
+                RequestPayload::V1Flow(v1::requests::Flow {
+                    topic_id,
+                    subscription_id,
+                    consumer_id,
+                    credits,
+                }),
+            ),
+            _ => return Err(ClientError::VersionNotSupported),
+        };
+
+        #[cfg(debug_assertions)]
+        debug!("Client: Sending {:?}", request);
+
+        let message = self.serialize(&request)?;
+
+        self.send(message)
     }
 
-    fn recv(self: &Self) -> Result<ClientMessage, RecvError> {
+    fn send_get_partitions(
+        self: &Self,
+        request_id: RequestId,
+        session_id: SessionId,
+        topic_id: TopicId,
+    ) -> ClientResult<()> {
+        if self.connection.is_none() {
+            return Err(ClientError::NotConnected);
+        }
+        if self.version.is_none() {
+            return Err(ClientError::IncompatibleVersion);
+        }
+        let version = self.version.unwrap();
+
+        let request = match version {
+            1 | 2 => Request::for_session(
+                request_id,
+                session_id,
+                RequestPayload::V1GetPartitions(v1::requests::GetPartitions { topic_id }),
+            ),
+            _ => return Err(ClientError::VersionNotSupported),
+        };
+
+        #[cfg(debug_assertions)]
+        debug!("Client: Sending {:?}", request);
+
+        let message = self.serialize(&request)?;
+
+        self.send(message)
+    }
+
+    fn send_list_topics(
+        self: &Self,
+        request_id: RequestId,
+        session_id: SessionId,
+    ) -> ClientResult<()> {
+        if self.connection.is_none() {
+            return Err(ClientError::NotConnected);
+        }
+        if self.version.is_none() {
+            return Err(ClientError::IncompatibleVersion);
+        }
+        let version = self.version.unwrap();
+
+        let request = match version {
+            1 | 2 => Request::for_session(
+                request_id,
+                session_id,
+                RequestPayload::V1ListTopics(v1::requests::ListTopics {}),
+            ),
+            _ => return Err(ClientError::VersionNotSupported),
+        };
+
+        #[cfg(debug_assertions)]
+        debug!("Client: Sending {:?}", request);
+
+        let message = self.serialize(&request)?;
+
+        self.send(message)
+    }
+
+    /// Chooses the partition to publish a message to by hashing its key. If the partitions of
+    /// the topic are not cached, this blocks until they are fetched from the broker
+    fn get_partition_id(self: &Self, topic_id: TopicId, key: &str) -> ClientResult<PartitionId> {
+        let cached = self
+            .futures
+            .lock()
+            .unwrap()
+            .partitions
+            .partition_for_key(topic_id, key);
+        match cached {
+            Some(partition_id) => Ok(partition_id),
+            None => {
+                let partition_ids = self
+                    .get_partitions(topic_id)?
+                    .wait(GET_PARTITIONS_TIMEOUT)?;
+                choose_partition(&partition_ids, key).ok_or(ClientError::NoData)
+            }
+        }
+    }
+
+    fn recv(self: &Self) -> Result<BrokerMessage, RecvError> {
         if let Some(connection) = &self.connection {
             connection.recv()
         } else {
@@ -430,11 +1703,31 @@ impl Client {
         }
     }
 
-    fn send(&self, message: ClientMessage) -> Result<(), SendError<ClientMessage>> {
+    fn serialize(self: &Self, request: &Request) -> ClientResult<ClientMessage> {
+        let message = self.serializer.serialize_request(request).map_err(|err| {
+            self.metrics.incr_serialize_errors();
+            ClientError::SerializeError(err)
+        })?;
+        if let Err(err) = check_message_size(message.len(), self.max_message_size) {
+            self.buffer_pool.reuse(message);
+            return Err(ClientError::MessageTooLarge(err));
+        }
+        Ok(message)
+    }
+
+    fn count_deserialize_error(self: &Self, err: DeserializeError) -> DeserializeError {
+        self.metrics.incr_deserialize_errors();
+        err
+    }
+
+    fn send(&self, message: ClientMessage) -> ClientResult<()> {
+        if self.stop_signal.load(Ordering::Relaxed) {
+            return Err(ClientError::ConnectionLost);
+        }
         if let Some(connection) = &self.connection {
             connection.send(message)
         } else {
-            Err(SendError(message))
+            Err(ClientError::SendError(SendError(message)))
         }
     }
 }
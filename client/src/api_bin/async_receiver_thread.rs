@@ -3,14 +3,24 @@ use super::{
     contracts::ConsumeResult, 
     contracts::AckResult, 
     contracts::NackResult, 
+    contracts::JoinGroupResult, 
+    contracts::LeaveGroupResult, 
+    contracts::DisconnectConsumerResult, 
+    contracts::FlowResult, 
+    contracts::Message, 
+    contracts::QuarantineResult, 
+    contracts::TopicSummary, 
     future_response::FutureHashMap,
+    keepalive::Keepalive,
+};
+use crate::api_bin::{
+    contracts::{BrokerMessage, ClientError},
+    metrics::ClientMetrics,
 };
-use crate::api_bin::contracts::ClientError;
 use log::{debug, info, warn};
 use pulsar_rust_net::{
     bin_serialization::{BrokerResponse, ContractSerializer, ResponsePayload},
     contracts::v1::responses::RequestOutcome,
-    error_codes::ERROR_CODE_INCORRECT_NODE,
     sockets::buffer_pool::BufferPool,
 };
 use std::{
@@ -24,11 +34,13 @@ use std::{
 };
 
 pub(crate) struct AsyncReceiverThread {
-    receiver: Receiver<Vec<u8>>,
+    receiver: Receiver<BrokerMessage>,
     stop_signal: Arc<AtomicBool>,
     futures: Arc<Mutex<FutureHashMap>>,
+    metrics: Arc<ClientMetrics>,
     serializer: ContractSerializer,
     last_message_instant: Instant,
+    keepalive: Option<Keepalive>,
 }
 
 impl AsyncReceiverThread {
@@ -36,14 +48,19 @@ impl AsyncReceiverThread {
         buffer_pool: &Arc<BufferPool>,
         stop_signal: &Arc<AtomicBool>,
         futures: &Arc<Mutex<FutureHashMap>>,
-        receiver: Receiver<Vec<u8>>,
+        metrics: &Arc<ClientMetrics>,
+        receiver: Receiver<BrokerMessage>,
+        keepalive: Option<Keepalive>,
     ) -> Self {
+        futures.lock().unwrap().receiver_count += 1;
         Self {
             stop_signal: stop_signal.clone(),
             futures: futures.clone(),
+            metrics: metrics.clone(),
             serializer: ContractSerializer::new(&buffer_pool),
             receiver,
             last_message_instant: Instant::now(),
+            keepalive,
         }
     }
 
@@ -55,9 +72,21 @@ impl AsyncReceiverThread {
                 self.complete_future(response);
                 self.last_message_instant = Instant::now();
             }
+            if let Some(keepalive) = &mut self.keepalive {
+                if !keepalive.check(&self.serializer) {
+                    self.connection_lost();
+                    break;
+                }
+            }
+            self.futures.lock().unwrap().wake_flushes();
             self.sleep_if_idle();
         }
 
+        // Flushes can not complete once nothing is receiving responses
+        let mut futures = self.futures.lock().unwrap();
+        futures.receiver_count -= 1;
+        futures.wake_flushes();
+        drop(futures);
         info!("ClientReceiverThread: Stopped");
     }
 
@@ -71,6 +100,7 @@ impl AsyncReceiverThread {
                         Some(response)
                     }
                     Err(err) => {
+                        self.metrics.incr_deserialize_errors();
                         warn!("ClientReceiverThread: Failed to deserialize response from broker. {:?}", err);
                         None
                     }
@@ -87,12 +117,33 @@ impl AsyncReceiverThread {
         }
     }
 
-    fn complete_future(self: &Self, response: BrokerResponse) {
+    /// Fails everything that is waiting on the broker, and stops the client from sending more
+    fn connection_lost(self: &Self) {
+        warn!("ClientReceiverThread: The broker did not answer a ping, the connection is lost");
+        self.stop_signal.store(true, Ordering::Relaxed);
+
+        // The callback is invoked without holding the lock so that it can make requests
+        let (publish_results, callback) = {
+            let mut futures = self.futures.lock().unwrap();
+            (futures.fail_pending(), futures.publish_callback.clone())
+        };
+        if let Some(callback) = callback {
+            for result in &publish_results {
+                callback(result);
+            }
+        }
+    }
+
+    fn complete_future(self: &mut Self, response: BrokerResponse) {
         let request_id = response.request_id;
         match response.payload {
             ResponsePayload::V1Publish(response) => {
-                let mut futures = self.futures.lock().unwrap();
-                match futures.publish_futures.remove(&request_id) {
+                // The callback is invoked without holding the lock so that it can make requests
+                let (state, callback) = {
+                    let futures = self.futures.lock().unwrap();
+                    (futures.publish_futures.get(&request_id).cloned(), futures.publish_callback.clone())
+                };
+                match state {
                     Some(state) => {
                         if let RequestOutcome::Warning(ref msg) = response.outcome {
                             warn!("ClientReceiverThread: Warning from broker publishing message {}", msg);
@@ -100,12 +151,51 @@ impl AsyncReceiverThread {
                         let result = if let Some(data) = response.data {
                             Ok(PublishResult::from(&data))
                         } else {
-                            if let RequestOutcome::Error(msg, error_code) = response.outcome {
-                                if error_code == ERROR_CODE_INCORRECT_NODE {
-                                    Err(ClientError::IncorrectNode)
+                            Err(ClientError::from_outcome(response.outcome, response.redirect))
+                        };
+                        if let Some(callback) = callback {
+                            callback(&result);
+                        }
+                        let mut state = state.lock().unwrap();
+                        state.result = Some(result);
+                        if let Some(waker) = state.waker.take() { waker.wake() }
+                        drop(state);
+                        self.futures.lock().unwrap().publish_futures.remove(&request_id);
+                    }
+                    None => warn!("ClientReceiverThread: Publish response received for request {request_id} but there is no corresponding publish future"),
+                }
+            }
+            ResponsePayload::V1PublishBatch(response) => {
+                // The callback is invoked without holding the lock so that it can make requests
+                let (state, callback) = {
+                    let futures = self.futures.lock().unwrap();
+                    (futures.publish_batch_futures.get(&request_id).cloned(), futures.publish_callback.clone())
+                };
+                match state {
+                    Some(state) => {
+                        if let RequestOutcome::Warning(ref msg) = response.outcome {
+                            warn!("ClientReceiverThread: Warning from broker publishing batch {}", msg);
+                        }
+                        let result = if let Some(data) = response.data {
+                            let results: Vec<_> = data.results.into_iter().map(|item_response| {
+                                if let RequestOutcome::Warning(ref msg) = item_response.outcome {
+                                    warn!("ClientReceiverThread: Warning from broker publishing message {}", msg);
+                                }
+                                if let Some(data) = item_response.data {
+                                    Ok(PublishResult::from(&data))
                                 } else {
-                                    Err(ClientError::Error(msg, error_code))
+                                    Err(ClientError::from_outcome(item_response.outcome, item_response.redirect))
+                                }
+                            }).collect();
+                            if let Some(callback) = callback {
+                                for result in &results {
+                                    callback(result);
                                 }
+                            }
+                            Ok(results)
+                        } else {
+                            if let RequestOutcome::Error(msg, error_code) = response.outcome {
+                                Err(ClientError::Error(msg, error_code))
                             } else {
                                 Err(ClientError::BadOutcome(response.outcome))
                             }
@@ -113,12 +203,17 @@ impl AsyncReceiverThread {
                         let mut state = state.lock().unwrap();
                         state.result = Some(result);
                         if let Some(waker) = state.waker.take() { waker.wake() }
+                        drop(state);
+                        self.futures.lock().unwrap().publish_batch_futures.remove(&request_id);
                     }
-                    None => warn!("ClientReceiverThread: Publish response received for request {request_id} but there is no corresponding publish future"),
+                    None => warn!("ClientReceiverThread: Publish batch response received for request {request_id} but there is no corresponding publish batch future"),
                 }
             }
             ResponsePayload::V1Consume(response) => {
                 let mut futures = self.futures.lock().unwrap();
+                futures
+                    .consumers
+                    .complete(request_id, response.data.as_ref().map(|data| data.consumer_id));
                 match futures.consume_futures.remove(&request_id) {
                     Some(state) => {
                         if let RequestOutcome::Warning(ref msg) = response.outcome {
@@ -127,11 +222,7 @@ impl AsyncReceiverThread {
                         let result = if let Some(data) = response.data {
                             Ok(ConsumeResult::from(&data))
                         } else {
-                            if let RequestOutcome::Error(msg, error_code) = response.outcome {
-                                Err(ClientError::Error(msg, error_code))
-                            } else {
-                                Err(ClientError::BadOutcome(response.outcome))
-                            }
+                            Err(ClientError::from_outcome(response.outcome, response.redirect))
                         };
                         let mut state = state.lock().unwrap();
                         state.result = Some(result);
@@ -150,11 +241,7 @@ impl AsyncReceiverThread {
                         let result = if let Some(data) = response.data {
                             Ok(AckResult::from(&data))
                         } else {
-                            if let RequestOutcome::Error(msg, error_code) = response.outcome {
-                                Err(ClientError::Error(msg, error_code))
-                            } else {
-                                Err(ClientError::BadOutcome(response.outcome))
-                            }
+                            Err(ClientError::from_outcome(response.outcome, response.redirect))
                         };
                         let mut state = state.lock().unwrap();
                         state.result = Some(result);
@@ -172,6 +259,151 @@ impl AsyncReceiverThread {
                         }
                         let result = if let Some(data) = response.data {
                             Ok(NackResult::from(&data))
+                        } else {
+                            Err(ClientError::from_outcome(response.outcome, response.redirect))
+                        };
+                        let mut state = state.lock().unwrap();
+                        state.result = Some(result);
+                        if let Some(waker) = state.waker.take() { waker.wake() }
+                    }
+                    None => warn!("ClientReceiverThread: Nack response received for request {request_id} but there is no corresponding nack future"),
+                }
+            }
+            ResponsePayload::V1Quarantine(response) => {
+                let mut futures = self.futures.lock().unwrap();
+                match futures.quarantine_futures.remove(&request_id) {
+                    Some(state) => {
+                        if let RequestOutcome::Warning(ref msg) = response.outcome {
+                            warn!("ClientReceiverThread: Warning from broker quarantine {}", msg);
+                        }
+                        let result = if let Some(data) = response.data {
+                            Ok(QuarantineResult::from(&data))
+                        } else {
+                            Err(ClientError::from_outcome(response.outcome, response.redirect))
+                        };
+                        let mut state = state.lock().unwrap();
+                        state.result = Some(result);
+                        if let Some(waker) = state.waker.take() { waker.wake() }
+                    }
+                    None => warn!("ClientReceiverThread: Quarantine response received for request {request_id} but there is no corresponding quarantine future"),
+                }
+            }
+            ResponsePayload::V1JoinGroup(response) => {
+                let mut futures = self.futures.lock().unwrap();
+                match futures.join_group_futures.remove(&request_id) {
+                    Some(state) => {
+                        if let RequestOutcome::Warning(ref msg) = response.outcome {
+                            warn!("ClientReceiverThread: Warning from broker join group {}", msg);
+                        }
+                        let member = response.data.as_ref().map(|data| (data.subscription_id, data.consumer_id));
+                        futures.consumers.complete_group(request_id, member);
+                        let result = if let Some(data) = response.data {
+                            Ok(JoinGroupResult::from(&data))
+                        } else {
+                            Err(ClientError::from_outcome(response.outcome, response.redirect))
+                        };
+                        let mut state = state.lock().unwrap();
+                        state.result = Some(result);
+                        if let Some(waker) = state.waker.take() { waker.wake() }
+                    }
+                    None => warn!("ClientReceiverThread: Join group response received for request {request_id} but there is no corresponding join group future"),
+                }
+            }
+            ResponsePayload::V1LeaveGroup(response) => {
+                let mut futures = self.futures.lock().unwrap();
+                match futures.leave_group_futures.remove(&request_id) {
+                    Some(state) => {
+                        let result = if let Some(data) = response.data {
+                            Ok(LeaveGroupResult::from(&data))
+                        } else {
+                            match response.outcome {
+                                RequestOutcome::Warning(msg) => {
+                                    warn!("ClientReceiverThread: Warning from broker leave group {}", msg);
+                                    Ok(LeaveGroupResult { success: false })
+                                }
+                                outcome => Err(ClientError::from_outcome(outcome, response.redirect)),
+                            }
+                        };
+                        let mut state = state.lock().unwrap();
+                        state.result = Some(result);
+                        if let Some(waker) = state.waker.take() { waker.wake() }
+                    }
+                    None => warn!("ClientReceiverThread: Leave group response received for request {request_id} but there is no corresponding leave group future"),
+                }
+            }
+            ResponsePayload::V1DisconnectConsumer(response) => {
+                let mut futures = self.futures.lock().unwrap();
+                match futures.disconnect_consumer_futures.remove(&request_id) {
+                    Some(state) => {
+                        let result = if let Some(data) = response.data {
+                            Ok(DisconnectConsumerResult::from(&data))
+                        } else {
+                            match response.outcome {
+                                RequestOutcome::Warning(msg) => {
+                                    warn!("ClientReceiverThread: Warning from broker disconnect consumer {}", msg);
+                                    Ok(DisconnectConsumerResult { success: false })
+                                }
+                                outcome => Err(ClientError::from_outcome(outcome, response.redirect)),
+                            }
+                        };
+                        let mut state = state.lock().unwrap();
+                        state.result = Some(result);
+                        if let Some(waker) = state.waker.take() { waker.wake() }
+                    }
+                    None => warn!("ClientReceiverThread: Disconnect consumer response received for request {request_id} but there is no corresponding disconnect consumer future"),
+                }
+            }
+            ResponsePayload::V1GetMessage(response) => {
+                let mut futures = self.futures.lock().unwrap();
+                match futures.get_message_futures.remove(&request_id) {
+                    Some(state) => {
+                        if let RequestOutcome::Warning(ref msg) = response.outcome {
+                            warn!("ClientReceiverThread: Warning from broker get message {}", msg);
+                        }
+                        let result = if let Some(data) = response.data {
+                            Ok(Message::from(&data))
+                        } else {
+                            Err(ClientError::from_outcome(response.outcome, response.redirect))
+                        };
+                        let mut state = state.lock().unwrap();
+                        state.result = Some(result);
+                        if let Some(waker) = state.waker.take() { waker.wake() }
+                    }
+                    None => warn!("ClientReceiverThread: Get message response received for request {request_id} but there is no corresponding get message future"),
+                }
+            }
+            ResponsePayload::V1GetPartitions(response) => {
+                let mut futures = self.futures.lock().unwrap();
+                match futures.get_partitions_futures.remove(&request_id) {
+                    Some(state) => {
+                        if let RequestOutcome::Warning(ref msg) = response.outcome {
+                            warn!("ClientReceiverThread: Warning from broker get partitions {}", msg);
+                        }
+                        let result = if let Some(data) = response.data {
+                            let partition_ids = data.partitions.iter().map(|partition| partition.partition_id).collect();
+                            match data.partitions.first() {
+                                Some(partition) => Ok(futures.partitions.insert(partition.topic_id, partition_ids)),
+                                None => Ok(partition_ids),
+                            }
+                        } else {
+                            Err(ClientError::from_outcome(response.outcome, response.redirect))
+                        };
+                        let mut state = state.lock().unwrap();
+                        state.result = Some(result);
+                        if let Some(waker) = state.waker.take() { waker.wake() }
+                    }
+                    None => warn!("ClientReceiverThread: Get partitions response received for request {request_id} but there is no corresponding get partitions future"),
+                }
+            }
+            ResponsePayload::V1ListTopics(response) => {
+                let mut futures = self.futures.lock().unwrap();
+                match futures.list_topics_futures.remove(&request_id) {
+                    Some(state) => {
+                        if let RequestOutcome::Warning(ref msg) = response.outcome {
+                            warn!("ClientReceiverThread: Warning from broker list topics {}", msg);
+                        }
+                        let result = if let Some(data) = response.data {
+                            Ok(data.topics.iter().map(TopicSummary::from).collect())
                         } else {
                             if let RequestOutcome::Error(msg, error_code) = response.outcome {
                                 Err(ClientError::Error(msg, error_code))
@@ -183,7 +415,74 @@ impl AsyncReceiverThread {
                         state.result = Some(result);
                         if let Some(waker) = state.waker.take() { waker.wake() }
                     }
-                    None => warn!("ClientReceiverThread: Nack response received for request {request_id} but there is no corresponding nack future"),
+                    None => warn!("ClientReceiverThread: List topics response received for request {request_id} but there is no corresponding list topics future"),
+                }
+            }
+            ResponsePayload::V1Subscribe(response) => {
+                let mut futures = self.futures.lock().unwrap();
+                futures
+                    .consumers
+                    .complete(request_id, response.data.as_ref().map(|data| data.consumer_id));
+                if response.data.is_none() {
+                    // Nothing will be pushed to the subscriber, so it is told why
+                    match futures.deliveries.remove(&request_id) {
+                        Some(sender) => {
+                            let err = ClientError::from_outcome(response.outcome, response.redirect);
+                            let _ = sender.send(Err(err));
+                        }
+                        None => warn!("ClientReceiverThread: Subscribe response received for request {request_id} but there is no corresponding subscriber"),
+                    }
+                }
+            }
+            ResponsePayload::V1Delivery(response) => {
+                let mut futures = self.futures.lock().unwrap();
+                match futures.deliveries.get(&request_id) {
+                    Some(sender) => {
+                        if let RequestOutcome::Warning(ref msg) = response.outcome {
+                            warn!("ClientReceiverThread: Warning from broker delivering messages {}", msg);
+                        }
+                        let result = if let Some(data) = response.data {
+                            Ok(ConsumeResult::from(&data))
+                        } else {
+                            Err(ClientError::from_outcome(response.outcome, response.redirect))
+                        };
+
+                        // The broker stops pushing to a subscriber after an error, and there
+                        // is no point routing messages to a subscriber that was dropped
+                        let stopped = result.is_err();
+                        if sender.send(result).is_err() || stopped {
+                            futures.deliveries.remove(&request_id);
+                        }
+                    }
+                    None => warn!("ClientReceiverThread: Delivery received for request {request_id} but there is no corresponding subscriber"),
+                }
+            }
+            ResponsePayload::V1Flow(response) => {
+                let mut futures = self.futures.lock().unwrap();
+                match futures.flow_futures.remove(&request_id) {
+                    Some(state) => {
+                        if let RequestOutcome::Warning(ref msg) = response.outcome {
+                            warn!("ClientReceiverThread: Warning from broker flow {}", msg);
+                        }
+                        let result = if let Some(data) = response.data {
+                            Ok(FlowResult::from(&data))
+                        } else {
+                            if let RequestOutcome::Error(msg, error_code) = response.outcome {
+                                Err(ClientError::Error(msg, error_code))
+                            } else {
+                                Err(ClientError::BadOutcome(response.outcome))
+                            }
+                        };
+                        let mut state = state.lock().unwrap();
+                        state.result = Some(result);
+                        if let Some(waker) = state.waker.take() { waker.wake() }
+                    }
+                    None => warn!("ClientReceiverThread: Flow response received for request {request_id} but there is no corresponding flow future"),
+                }
+            }
+            ResponsePayload::V1Pong(_) => {
+                if let Some(keepalive) = &mut self.keepalive {
+                    keepalive.pong(request_id);
                 }
             }
             _ => warn!("Received async response to non-async request"),
@@ -2,15 +2,22 @@ use crate::api_bin::contracts::ClientError;
 use log::{debug, info, warn};
 use pulsar_rust_net::{
     bin_serialization::{
-        ContractSerializer, DeserializeError, Request, RequestId, RequestPayload, ResponsePayload,
+        CompressionScheme, ContractSerializer, DeserializeError, Request, RequestId,
+        RequestPayload, ResponsePayload, DEFAULT_SESSION_ID,
+    },
+    contracts::{
+        v1::{self, requests::NegotiateVersion, responses::RequestOutcome},
+        v2,
     },
-    contracts::v1::{self, requests::NegotiateVersion, responses::RequestOutcome},
     data_types::{
-        ConsumerId, ContractVersionNumber, MessageCount, PartitionId, SubscriptionId, Timestamp,
-        TopicId,
+        ByteCount, ConsumerId, ContractVersionNumber, MessageCount, PartitionId, Priority,
+        SubscriptionId, Timestamp, TopicId,
+    },
+    sockets::{
+        buffer_pool::BufferPool,
+        tcp_channel::{check_message_size, DEFAULT_MAX_MESSAGE_SIZE},
+        tls::ConnectionSecurity,
     },
-    error_codes::ERROR_CODE_INCORRECT_NODE,
-    sockets::buffer_pool::BufferPool,
 };
 use std::{
     collections::HashMap,
@@ -18,12 +25,23 @@ use std::{
         mpsc::{RecvError, SendError},
         Arc, Mutex,
     },
+    time::Duration,
 };
 use uuid::Uuid;
 
 use super::{
-    connection::Connection,
-    contracts::{AckResult, ClientMessage, ClientResult, ConsumeResult, NackResult, PublishResult},
+    codec::{CodecRegistry, TypedConsumeResult},
+    connection::{Connection, DEFAULT_SEND_QUEUE_CAPACITY},
+    consumer_map::ConsumerMap,
+    metrics::ClientMetrics,
+    partition_cache::{choose_partition, PartitionCache, DEFAULT_PARTITION_CACHE_DURATION},
+    redirect::{Redirects, DEFAULT_MAX_REDIRECTS},
+    versions::VersionOptions,
+    contracts::{
+        AckResult, BrokerMessage, ClientMessage, ClientResult, ConsumeResult,
+        DisconnectConsumerResult, Message, MessageHeaders, NackResult, PublishResult,
+        QuarantineResult, TopicPartitionMap, TopicSummary,
+    },
 };
 
 pub struct Client {
@@ -32,7 +50,18 @@ pub struct Client {
     serializer: ContractSerializer,
     connection: Option<Connection>,
     version: Option<ContractVersionNumber>,
+    version_options: VersionOptions,
+    max_message_size: usize,
+    send_queue_capacity: usize,
+    security: ConnectionSecurity,
+    compression: CompressionScheme,
+    consume_max_bytes: Option<ByteCount>,
+    max_redirects: usize,
+    redirects: Redirects<Client>,
     next_request_id: Mutex<RequestId>,
+    consumers: Mutex<ConsumerMap>,
+    partitions: Mutex<PartitionCache>,
+    metrics: ClientMetrics,
 }
 
 impl Client {
@@ -45,87 +74,214 @@ impl Client {
             connection: None,
             serializer: ContractSerializer::new(&buffer_pool),
             version: None,
+            version_options: VersionOptions::default(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            send_queue_capacity: DEFAULT_SEND_QUEUE_CAPACITY,
+            security: ConnectionSecurity::Plain,
+            compression: CompressionScheme::None,
+            consume_max_bytes: None,
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+            redirects: Redirects::none(),
             next_request_id: Mutex::new(1),
+            consumers: Mutex::new(ConsumerMap::new()),
+            partitions: Mutex::new(PartitionCache::new(DEFAULT_PARTITION_CACHE_DURATION)),
+            metrics: ClientMetrics::new(),
         }
     }
 
-    pub fn connect(self: &mut Self) -> Result<(), String> {
-        self.connection = Some(Connection::new(&self.buffer_pool, &self.authority));
+    /// Sets the range of API versions that this client will accept from the broker, and
+    /// whether to try each version in the range on its own when the broker rejects the range
+    pub fn with_version_options(mut self: Self, version_options: VersionOptions) -> Self {
+        self.version_options = version_options;
+        self
+    }
 
-        let payload = NegotiateVersion {
-            min_version: 1,
-            max_version: 1,
-        };
-        let request = Request {
-            request_id: 0,
-            payload: RequestPayload::NegotiateVersion(payload),
-        };
+    /// Sets the maximum length of a serialized request. Requests that are longer than this
+    /// fail with `ClientError::MessageTooLarge` instead of being sent to the broker
+    pub fn with_max_message_size(mut self: Self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    /// Sets how many requests can be queued for sending to the broker. When the network can
+    /// not keep up, requests fail with `ClientError::WouldBlock` instead of being queued
+    pub fn with_send_queue_capacity(mut self: Self, send_queue_capacity: usize) -> Self {
+        self.send_queue_capacity = send_queue_capacity;
+        self
+    }
+
+    /// Offers the broker a scheme for compressing requests and responses. The broker uses it
+    /// if it supports compression, otherwise messages are sent without compression
+    pub fn with_compression(mut self: Self, compression: CompressionScheme) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Limits the total serialized size of the messages in each batch that is consumed, so
+    /// that a batch of large messages can not make the response too large to receive. The
+    /// broker still returns the first message that is available when it is larger than this
+    pub fn with_consume_max_bytes(mut self: Self, max_bytes: ByteCount) -> Self {
+        self.consume_max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Sets whether the connection to the broker is encrypted with TLS. This must match the
+    /// security of the broker, otherwise the connection is closed during the TLS handshake
+    pub fn with_security(mut self: Self, security: ConnectionSecurity) -> Self {
+        self.security = security;
+        self
+    }
+
+    /// Sets how many times a publish or consume request is sent to another broker, when the
+    /// broker that received it says that the other broker owns the partition. Zero returns
+    /// `ClientError::IncorrectNode` to the application instead
+    pub fn with_max_redirects(mut self: Self, max_redirects: usize) -> Self {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    /// Sets how long the partitions of each topic are cached before they are fetched from the
+    /// broker again. Partitions can be added to a topic while the broker is running
+    pub fn with_partition_cache_duration(mut self: Self, cache_duration: Duration) -> Self {
+        self.partitions
+            .get_mut()
+            .unwrap()
+            .set_cache_duration(cache_duration);
+        self
+    }
+
+    /// Connects to the broker and negotiates the API version to use. Fails with
+    /// `ClientError::IncompatibleVersion` if the broker does not support any of the versions
+    /// that this client accepts
+    pub fn connect(self: &mut Self) -> ClientResult<()> {
+        self.connection = Some(Connection::new(
+            &self.buffer_pool,
+            &self.authority,
+            self.max_message_size,
+            self.send_queue_capacity,
+            &self.security,
+        ));
+
+        // Version negotiation requests are never compressed
+        self.serializer.set_compression(CompressionScheme::None);
+
+        let mut negotiated = Err(ClientError::IncompatibleVersion);
+        for attempt in self.version_options.attempts(self.compression) {
+            negotiated = self.negotiate_version(attempt);
+            if !matches!(negotiated, Err(ClientError::IncompatibleVersion)) {
+                break;
+            }
+        }
+
+        match negotiated {
+            Ok((version, compression)) => {
+                #[cfg(debug_assertions)]
+                debug!("Client: Negotiated API version {version} with {compression:?} compression");
+                self.version = Some(version);
+                self.serializer.set_compression(compression);
+                self.redirects = self.redirects();
+                Ok(())
+            }
+            Err(err) => {
+                warn!("Client: Failed to negotiate an API version with the broker. {err:?}");
+                self.disconnect();
+                Err(err)
+            }
+        }
+    }
+
+    /// Offers the broker a range of API versions and returns the version that the
+    /// broker chose
+    fn negotiate_version(
+        self: &Self,
+        payload: NegotiateVersion,
+    ) -> ClientResult<(ContractVersionNumber, CompressionScheme)> {
+        let request = Request::new(0, RequestPayload::NegotiateVersion(payload));
 
         #[cfg(debug_assertions)]
         debug!("Client: Sending {:?}", request);
 
-        let message = self.serializer.serialize_request(&request).unwrap();
+        let message = self.serialize(&request)?;
+        self.send(message)?;
+        let message = self.recv().map_err(ClientError::RecvError)?;
+        let response = self
+            .serializer
+            .deserialize_response(message)
+            .map_err(|err| ClientError::DeserializeError(self.count_deserialize_error(err)))?;
 
-        if let Err(e) = self.send(message) {
-            panic!("Client: Error sending API version negotiation request: {e}");
-        }
+        #[cfg(debug_assertions)]
+        debug!("Client: Received {:?}", &response);
 
-        match self.recv() {
-            Ok(message) => match self.serializer.deserialize_response(message) {
-                Ok(response) => {
-                    #[cfg(debug_assertions)]
-                    debug!("Client: Received {:?}", &response);
-                    if let ResponsePayload::NegotiateVersion(version_response) = response.payload {
-                        match version_response.outcome {
-                            RequestOutcome::Success => {
-                                if let Some(data) = version_response.data {
-                                    #[cfg(debug_assertions)]
-                                    debug!("Client: Negotiated API version {}", data.version);
-                                    self.version = Some(data.version);
-                                }
-                                Ok(())
-                            }
-                            RequestOutcome::Warning(msg) => {
-                                if let Some(data) = version_response.data {
-                                    #[cfg(debug_assertions)]
-                                    debug!("Client: Negotiated API version: {}", data.version);
-                                    self.version = Some(data.version);
-                                }
-                                Err(format!("Client: Warning negotiating API version {}", msg))
-                            }
-                            RequestOutcome::NoData(msg) => {
-                                Err(format!("Client: No data negotiating API version: {}", msg))
-                            }
-                            RequestOutcome::Error(msg, _code) => {
-                                Err(format!("Client: Error negotiating API version: {}", msg))
-                            }
-                        }
-                    } else {
-                        Err(format!(
-                            "Client: Received wrong response type for negotiate version request"
-                        ))
-                    }
-                }
-                Err(err) => match err {
-                    DeserializeError::Error { msg } => Err(format!("Client: {}", msg)),
-                },
-            },
-            Err(err) => Err(format!(
-                "Client: Error receiving API version negotiation response: {err}"
-            )),
+        match response.payload {
+            ResponsePayload::NegotiateVersion(version_response) => {
+                let compression = version_response
+                    .data
+                    .as_ref()
+                    .map_or(CompressionScheme::None, |data| data.compression);
+                let version = self.version_options.negotiated_version(version_response)?;
+                Ok((version, compression))
+            }
+            _ => Err(ClientError::IncorrectResponseType),
         }
     }
 
+    /// Requests are redirected by clients with the same options as this one, that are
+    /// connected to the broker that owns the partition
+    fn redirects(self: &Self) -> Redirects<Client> {
+        let buffer_pool = self.buffer_pool.clone();
+        let version_options = self.version_options;
+        let max_message_size = self.max_message_size;
+        let send_queue_capacity = self.send_queue_capacity;
+        let security = self.security.clone();
+        let compression = self.compression;
+        let consume_max_bytes = self.consume_max_bytes;
+        Redirects::new(self.max_redirects, move |authority: &str, max_redirects| {
+            info!("Client: Redirected to {authority}");
+            let mut client = Client::new(&buffer_pool, authority)
+                .with_version_options(version_options)
+                .with_max_message_size(max_message_size)
+                .with_send_queue_capacity(send_queue_capacity)
+                .with_security(security.clone())
+                .with_compression(compression)
+                .with_max_redirects(max_redirects);
+            client.consume_max_bytes = consume_max_bytes;
+            client.connect()?;
+            Ok(client)
+        })
+    }
+
     pub fn disconnect(self: &mut Self) {
         if let Some(connection) = self.connection.take() {
             connection.disconnect();
         }
+        for mut client in self.redirects.drain() {
+            client.disconnect();
+        }
+    }
+
+    /// Counts of errors serializing requests and deserializing responses on this client
+    pub fn metrics(self: &Self) -> &ClientMetrics {
+        &self.metrics
+    }
+
+    /// Drops the connection to the broker and connects again. Subsequent consume requests
+    /// that do not specify a consumer id will continue as the consumer that was previously
+    /// allocated for the subscription
+    pub fn reconnect(self: &mut Self) -> ClientResult<()> {
+        self.disconnect();
+        self.connect()
     }
 
     pub fn is_connected(self: &Self) -> bool {
         self.connection.is_some()
     }
 
+    /// The version of the API contracts that was negotiated with the broker, or None if the
+    /// client has not connected
+    pub fn contract_version(self: &Self) -> Option<ContractVersionNumber> {
+        self.version
+    }
+
     /// Synchronously publishes a message, blocking until a response is received from the broker
     pub fn publish(
         self: &Self,
@@ -134,10 +290,95 @@ impl Client {
         timestamp: Option<Timestamp>,
         attributes: HashMap<String, String>,
     ) -> ClientResult<PublishResult> {
-        let request_id = self.get_next_request_id();
+        self.publish_with_priority(topic_id, key, timestamp, Priority::default(), attributes)
+    }
+
+    /// Synchronously publishes a message that will be delivered to consumers ahead of
+    /// messages with a lower priority
+    pub fn publish_with_priority(
+        self: &Self,
+        topic_id: TopicId,
+        key: Option<String>,
+        timestamp: Option<Timestamp>,
+        priority: Priority,
+        attributes: HashMap<String, String>,
+    ) -> ClientResult<PublishResult> {
+        self.publish_with_headers(
+            topic_id,
+            key,
+            timestamp,
+            priority,
+            MessageHeaders::default(),
+            attributes,
+        )
+    }
+
+    /// Synchronously publishes a message with well-known headers in addition to its
+    /// attributes. Headers are delivered to consumers separately from the attributes
+    pub fn publish_with_headers(
+        self: &Self,
+        topic_id: TopicId,
+        key: Option<String>,
+        timestamp: Option<Timestamp>,
+        priority: Priority,
+        headers: MessageHeaders,
+        attributes: HashMap<String, String>,
+    ) -> ClientResult<PublishResult> {
         let key: String = key.unwrap_or_else(|| Uuid::new_v4().to_string());
+        let retry = self
+            .redirects
+            .enabled()
+            .then(|| (headers.clone(), attributes.clone()));
+        let partition_id = self.get_partition_id(topic_id, &key)?;
+        let result = self.publish_to_partition(
+            topic_id,
+            partition_id,
+            &key,
+            timestamp,
+            priority,
+            headers,
+            attributes,
+        );
+
+        match (self.redirects.target(&result), retry) {
+            (Some(authority), Some((headers, attributes))) => {
+                self.redirects.client(&authority)?.publish_with_headers(
+                    topic_id,
+                    Some(key),
+                    timestamp,
+                    priority,
+                    headers,
+                    attributes,
+                )
+            }
+            _ => result,
+        }
+    }
 
-        match self.send_publish(request_id, topic_id, &key, timestamp, attributes) {
+    /// Publishes a message to a partition that was chosen by the caller, without following
+    /// redirects
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn publish_to_partition(
+        self: &Self,
+        topic_id: TopicId,
+        partition_id: PartitionId,
+        key: &str,
+        timestamp: Option<Timestamp>,
+        priority: Priority,
+        headers: MessageHeaders,
+        attributes: HashMap<String, String>,
+    ) -> ClientResult<PublishResult> {
+        let request_id = self.get_next_request_id();
+        match self.send_publish(
+            request_id,
+            topic_id,
+            partition_id,
+            key,
+            timestamp,
+            priority,
+            headers,
+            attributes,
+        ) {
             Ok(_) => match self.recv() {
                 Ok(message) => match self.serializer.deserialize_response(message) {
                     Ok(response) => {
@@ -151,23 +392,21 @@ impl Client {
                             if let Some(data) = publish_response.data {
                                 Ok(PublishResult::from(&data))
                             } else {
-                                if let RequestOutcome::Error(msg, error_code) =
-                                    publish_response.outcome
-                                {
-                                    if error_code == ERROR_CODE_INCORRECT_NODE {
-                                        Err(ClientError::IncorrectNode)
-                                    } else {
-                                        Err(ClientError::Error(msg, error_code))
-                                    }
-                                } else {
-                                    Err(ClientError::BadOutcome(publish_response.outcome))
+                                let err = ClientError::from_outcome(
+                                    publish_response.outcome,
+                                    publish_response.redirect,
+                                );
+                                if let ClientError::IncorrectNode(_) = err {
+                                    // The partitions of the topic may have changed
+                                    self.partitions.lock().unwrap().invalidate(topic_id);
                                 }
+                                Err(err)
                             }
                         } else {
                             Err(ClientError::IncorrectResponseType)
                         }
                     }
-                    Err(err) => Err(ClientError::DeserializeError(err)),
+                    Err(err) => Err(ClientError::DeserializeError(self.count_deserialize_error(err))),
                 },
                 Err(err) => Err(ClientError::RecvError(err)),
             },
@@ -175,7 +414,9 @@ impl Client {
         }
     }
 
-    /// Synchronously consumes messages, blocking until a response is received from the broker
+    /// Synchronously consumes messages, blocking until a response is received from the broker.
+    /// If no consumer id is passed, then the consumer id previously allocated by the broker for
+    /// this subscription is used
     pub fn consume(
         self: &Self,
         topic_id: TopicId,
@@ -184,7 +425,14 @@ impl Client {
         max_messages: MessageCount,
     ) -> ClientResult<ConsumeResult> {
         let request_id = self.get_next_request_id();
-        match self.send_consume(
+        let requested_consumer_id = consumer_id;
+        let consumer_id = consumer_id.or_else(|| {
+            self.consumers
+                .lock()
+                .unwrap()
+                .get(DEFAULT_SESSION_ID, topic_id, subscription_id)
+        });
+        let result = match self.send_consume(
             request_id,
             topic_id,
             subscription_id,
@@ -202,28 +450,78 @@ impl Client {
                                 warn!("Client: Warning from broker consuming subscription {}", msg);
                             }
                             if let Some(data) = consume_response.data {
+                                self.consumers.lock().unwrap().insert(
+                                    DEFAULT_SESSION_ID,
+                                    topic_id,
+                                    subscription_id,
+                                    data.consumer_id,
+                                );
                                 Ok(ConsumeResult::from(&data))
                             } else {
-                                if let RequestOutcome::Error(msg, error_code) =
-                                    consume_response.outcome
-                                {
-                                    Err(ClientError::Error(msg, error_code))
-                                } else {
-                                    Err(ClientError::BadOutcome(consume_response.outcome))
-                                }
+                                Err(ClientError::from_outcome(
+                                    consume_response.outcome,
+                                    consume_response.redirect,
+                                ))
                             }
                         } else {
                             Err(ClientError::IncorrectResponseType)
                         }
                     }
-                    Err(err) => Err(ClientError::DeserializeError(err)),
+                    Err(err) => Err(ClientError::DeserializeError(self.count_deserialize_error(err))),
                 },
                 Err(err) => Err(ClientError::RecvError(err)),
             },
             Err(err) => Err(err),
+        };
+
+        // Consumer ids are allocated by each broker, so the id that was allocated by this
+        // broker is not passed on to the broker that owns the partition
+        match self.redirects.target(&result) {
+            Some(authority) => self.redirects.client(&authority)?.consume(
+                topic_id,
+                subscription_id,
+                requested_consumer_id,
+                max_messages,
+            ),
+            None => result,
         }
     }
 
+    /// Synchronously publishes a strongly typed payload, using the default codec from the registry
+    /// to encode the payload into the message attributes and its content type into the headers
+    pub fn publish_typed<T>(
+        self: &Self,
+        topic_id: TopicId,
+        key: Option<String>,
+        timestamp: Option<Timestamp>,
+        value: &T,
+        codecs: &CodecRegistry<T>,
+    ) -> ClientResult<PublishResult> {
+        let (headers, attributes) = codecs.encode(value)?;
+        self.publish_with_headers(
+            topic_id,
+            key,
+            timestamp,
+            Priority::default(),
+            headers,
+            attributes,
+        )
+    }
+
+    /// Synchronously consumes messages and decodes their payloads using the codec that matches
+    /// the content type of each message
+    pub fn consume_typed<T>(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        consumer_id: Option<ConsumerId>,
+        max_messages: MessageCount,
+        codecs: &CodecRegistry<T>,
+    ) -> ClientResult<TypedConsumeResult<T>> {
+        let result = self.consume(topic_id, subscription_id, consumer_id, max_messages)?;
+        TypedConsumeResult::decode(result, codecs)
+    }
+
     /// Synchronously acknowledges a message, blocking until a response is received from the broker
     /// This will cause the message to be deleted from the subscription
     pub fn ack(
@@ -247,18 +545,16 @@ impl Client {
                             if let Some(data) = ack_response.data {
                                 Ok(AckResult::from(&data))
                             } else {
-                                if let RequestOutcome::Error(msg, error_code) = ack_response.outcome
-                                {
-                                    Err(ClientError::Error(msg, error_code))
-                                } else {
-                                    Err(ClientError::BadOutcome(ack_response.outcome))
-                                }
+                                Err(ClientError::from_outcome(
+                                    ack_response.outcome,
+                                    ack_response.redirect,
+                                ))
                             }
                         } else {
                             Err(ClientError::IncorrectResponseType)
                         }
                     }
-                    Err(err) => Err(ClientError::DeserializeError(err)),
+                    Err(err) => Err(ClientError::DeserializeError(self.count_deserialize_error(err))),
                 },
                 Err(err) => Err(ClientError::RecvError(err)),
             },
@@ -288,20 +584,277 @@ impl Client {
                             }
                             if let Some(data) = nack_response.data {
                                 Ok(NackResult::from(&data))
+                            } else {
+                                Err(ClientError::from_outcome(
+                                    nack_response.outcome,
+                                    nack_response.redirect,
+                                ))
+                            }
+                        } else {
+                            Err(ClientError::IncorrectResponseType)
+                        }
+                    }
+                    Err(err) => Err(ClientError::DeserializeError(self.count_deserialize_error(err))),
+                },
+                Err(err) => Err(ClientError::RecvError(err)),
+            },
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Synchronously quarantines a message that this consumer can not process, blocking until
+    /// a response is received from the broker. The message is removed from the subscription
+    /// without being redelivered, and is kept by the broker with the reason
+    pub fn quarantine(
+        self: &Self,
+        message_ref_key: &str,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+        reason: &str,
+    ) -> ClientResult<QuarantineResult> {
+        let request_id = self.get_next_request_id();
+        match self.send_quarantine(
+            request_id,
+            message_ref_key,
+            subscription_id,
+            consumer_id,
+            reason,
+        ) {
+            Ok(_) => match self.recv() {
+                Ok(message) => match self.serializer.deserialize_response(message) {
+                    Ok(response) => {
+                        #[cfg(debug_assertions)]
+                        debug!("Client: Received {:?}", &response);
+
+                        if let ResponsePayload::V1Quarantine(quarantine_response) = response.payload
+                        {
+                            if let RequestOutcome::Warning(ref msg) = quarantine_response.outcome {
+                                warn!("Client: Warning from broker quarantining message {}", msg);
+                            }
+                            if let Some(data) = quarantine_response.data {
+                                Ok(QuarantineResult::from(&data))
+                            } else {
+                                Err(ClientError::from_outcome(
+                                    quarantine_response.outcome,
+                                    quarantine_response.redirect,
+                                ))
+                            }
+                        } else {
+                            Err(ClientError::IncorrectResponseType)
+                        }
+                    }
+                    Err(err) => Err(ClientError::DeserializeError(self.count_deserialize_error(err))),
+                },
+                Err(err) => Err(ClientError::RecvError(err)),
+            },
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Synchronously disconnects a consumer, blocking until a response is received from the
+    /// broker. Any messages that were delivered to this consumer and not acked are redelivered
+    /// to other consumers of the subscription
+    pub fn disconnect_consumer(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+    ) -> ClientResult<DisconnectConsumerResult> {
+        let request_id = self.get_next_request_id();
+        match self.send_disconnect_consumer(request_id, topic_id, subscription_id, consumer_id) {
+            Ok(_) => match self.recv() {
+                Ok(message) => match self.serializer.deserialize_response(message) {
+                    Ok(response) => {
+                        #[cfg(debug_assertions)]
+                        debug!("Client: Received {:?}", &response);
+
+                        if let ResponsePayload::V1DisconnectConsumer(disconnect_response) =
+                            response.payload
+                        {
+                            if let RequestOutcome::Warning(ref msg) = disconnect_response.outcome {
+                                warn!("Client: Warning from broker disconnecting consumer {}", msg);
+                            }
+                            if let Some(data) = disconnect_response.data {
+                                let mut consumers = self.consumers.lock().unwrap();
+                                if consumers.get(DEFAULT_SESSION_ID, topic_id, subscription_id)
+                                    == Some(consumer_id)
+                                {
+                                    consumers.remove(DEFAULT_SESSION_ID, topic_id, subscription_id);
+                                }
+                                Ok(DisconnectConsumerResult::from(&data))
+                            } else {
+                                Err(ClientError::from_outcome(
+                                    disconnect_response.outcome,
+                                    disconnect_response.redirect,
+                                ))
+                            }
+                        } else {
+                            Err(ClientError::IncorrectResponseType)
+                        }
+                    }
+                    Err(err) => Err(ClientError::DeserializeError(self.count_deserialize_error(err))),
+                },
+                Err(err) => Err(ClientError::RecvError(err)),
+            },
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Synchronously fetches a message by its ack key, blocking until a response is received
+    /// from the broker. This does not count as a delivery of the message, and does not change
+    /// its state in any subscription
+    pub fn get_message(self: &Self, message_ref_key: &str) -> ClientResult<Message> {
+        let request_id = self.get_next_request_id();
+        match self.send_get_message(request_id, message_ref_key) {
+            Ok(_) => match self.recv() {
+                Ok(message) => match self.serializer.deserialize_response(message) {
+                    Ok(response) => {
+                        #[cfg(debug_assertions)]
+                        debug!("Client: Received {:?}", &response);
+
+                        if let ResponsePayload::V1GetMessage(get_message_response) =
+                            response.payload
+                        {
+                            if let RequestOutcome::Warning(ref msg) = get_message_response.outcome {
+                                warn!("Client: Warning from broker getting message {}", msg);
+                            }
+                            if let Some(data) = get_message_response.data {
+                                Ok(Message::from(&data))
+                            } else {
+                                Err(ClientError::from_outcome(
+                                    get_message_response.outcome,
+                                    get_message_response.redirect,
+                                ))
+                            }
+                        } else {
+                            Err(ClientError::IncorrectResponseType)
+                        }
+                    }
+                    Err(err) => Err(ClientError::DeserializeError(self.count_deserialize_error(err))),
+                },
+                Err(err) => Err(ClientError::RecvError(err)),
+            },
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Synchronously fetches the ids of the partitions of a topic, blocking until a response is
+    /// received from the broker. The partitions are cached, and used to choose the partition
+    /// that each message is published to
+    pub fn get_partitions(self: &Self, topic_id: TopicId) -> ClientResult<Vec<PartitionId>> {
+        let request_id = self.get_next_request_id();
+        match self.send_get_partitions(request_id, topic_id) {
+            Ok(_) => match self.recv() {
+                Ok(message) => match self.serializer.deserialize_response(message) {
+                    Ok(response) => {
+                        #[cfg(debug_assertions)]
+                        debug!("Client: Received {:?}", &response);
+
+                        if let ResponsePayload::V1GetPartitions(partitions_response) =
+                            response.payload
+                        {
+                            if let RequestOutcome::Warning(ref msg) = partitions_response.outcome {
+                                warn!("Client: Warning from broker getting partitions {}", msg);
+                            }
+                            if let Some(data) = partitions_response.data {
+                                let partition_ids = data
+                                    .partitions
+                                    .iter()
+                                    .map(|partition| partition.partition_id)
+                                    .collect();
+                                let mut partitions = self.partitions.lock().unwrap();
+                                Ok(partitions.insert(topic_id, partition_ids))
+                            } else {
+                                Err(ClientError::from_outcome(
+                                    partitions_response.outcome,
+                                    partitions_response.redirect,
+                                ))
+                            }
+                        } else {
+                            Err(ClientError::IncorrectResponseType)
+                        }
+                    }
+                    Err(err) => Err(ClientError::DeserializeError(self.count_deserialize_error(err))),
+                },
+                Err(err) => Err(ClientError::RecvError(err)),
+            },
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Synchronously lists the topics in the cluster, with the number of partitions and
+    /// subscriptions in each, blocking until a response is received from the broker
+    pub fn list_topics(self: &Self) -> ClientResult<Vec<TopicSummary>> {
+        let request_id = self.get_next_request_id();
+        match self.send_list_topics(request_id) {
+            Ok(_) => match self.recv() {
+                Ok(message) => match self.serializer.deserialize_response(message) {
+                    Ok(response) => {
+                        #[cfg(debug_assertions)]
+                        debug!("Client: Received {:?}", &response);
+
+                        if let ResponsePayload::V1ListTopics(topics_response) = response.payload {
+                            if let RequestOutcome::Warning(ref msg) = topics_response.outcome {
+                                warn!("Client: Warning from broker listing topics {}", msg);
+                            }
+                            if let Some(data) = topics_response.data {
+                                Ok(data.topics.iter().map(TopicSummary::from).collect())
                             } else {
                                 if let RequestOutcome::Error(msg, error_code) =
-                                    nack_response.outcome
+                                    topics_response.outcome
+                                {
+                                    Err(ClientError::Error(msg, error_code))
+                                } else {
+                                    Err(ClientError::BadOutcome(topics_response.outcome))
+                                }
+                            }
+                        } else {
+                            Err(ClientError::IncorrectResponseType)
+                        }
+                    }
+                    Err(err) => Err(ClientError::DeserializeError(self.count_deserialize_error(err))),
+                },
+                Err(err) => Err(ClientError::RecvError(err)),
+            },
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Synchronously gets the partitions of a topic and the broker that owns each of them,
+    /// blocking until a response is received from the broker
+    pub fn get_topic_partition_map(
+        self: &Self,
+        topic_id: TopicId,
+    ) -> ClientResult<TopicPartitionMap> {
+        let request_id = self.get_next_request_id();
+        match self.send_get_topic_partition_map(request_id, topic_id) {
+            Ok(_) => match self.recv() {
+                Ok(message) => match self.serializer.deserialize_response(message) {
+                    Ok(response) => {
+                        #[cfg(debug_assertions)]
+                        debug!("Client: Received {:?}", &response);
+
+                        if let ResponsePayload::V1GetTopicPartitionMap(map_response) =
+                            response.payload
+                        {
+                            if let RequestOutcome::Warning(ref msg) = map_response.outcome {
+                                warn!("Client: Warning from broker getting partition map {}", msg);
+                            }
+                            if let Some(data) = map_response.data {
+                                Ok(TopicPartitionMap::from(&data))
+                            } else {
+                                if let RequestOutcome::Error(msg, error_code) = map_response.outcome
                                 {
                                     Err(ClientError::Error(msg, error_code))
                                 } else {
-                                    Err(ClientError::BadOutcome(nack_response.outcome))
+                                    Err(ClientError::BadOutcome(map_response.outcome))
                                 }
                             }
                         } else {
                             Err(ClientError::IncorrectResponseType)
                         }
                     }
-                    Err(err) => Err(ClientError::DeserializeError(err)),
+                    Err(err) => Err(ClientError::DeserializeError(self.count_deserialize_error(err))),
                 },
                 Err(err) => Err(ClientError::RecvError(err)),
             },
@@ -320,12 +873,16 @@ impl Client {
         request_id
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn send_publish(
         self: &Self,
         request_id: RequestId,
         topic_id: TopicId,
+        partition_id: PartitionId,
         key: &str,
         timestamp: Option<Timestamp>,
+        priority: Priority,
+        headers: MessageHeaders,
         attributes: HashMap<String, String>,
     ) -> ClientResult<()> {
         if self.connection.is_none() {
@@ -337,29 +894,48 @@ impl Client {
         let version = self.version.unwrap();
 
         let request = match version {
-            1 => Request {
+            1 => Request::new(
                 request_id,
-                payload: RequestPayload::V1Publish(v1::requests::Publish {
+                RequestPayload::V1Publish(v1::requests::Publish {
                     topic_id,
-                    partition_id: self.get_partition_id(topic_id, &key),
+                    partition_id,
                     key: key.to_owned(),
                     timestamp,
+                    priority: Some(priority),
                     attributes,
+                    ack_level: v1::requests::PublishAckLevel::default(),
+                    headers,
+                    producer_id: None,
+                    sequence_number: None,
+                    deliver_after_millis: None,
                 }),
-            },
+            ),
+            2 => Request::new(
+                request_id,
+                RequestPayload::V2Publish(v2::requests::Publish {
+                    topic_id,
+                    partition_id,
+                    key: key.to_owned(),
+                    timestamp,
+                    priority: Some(priority),
+                    attributes,
+                    ack_level: v1::requests::PublishAckLevel::default(),
+                    headers,
+                    producer_id: None,
+                    sequence_number: None,
+                    deliver_after_millis: None,
+                    deliver_at: None,
+                }),
+            ),
             _ => return Err(ClientError::VersionNotSupported),
         };
 
         #[cfg(debug_assertions)]
         debug!("Client: Sending {:?}", request);
 
-        let message = self.serializer.serialize_request(&request).unwrap();
+        let message = self.serialize(&request)?;
 
-        if let Err(err) = self.send(message) {
-            Err(ClientError::SendError(err))
-        } else {
-            Ok(())
-        }
+        self.send(message)
     }
 
     fn send_consume(
@@ -379,28 +955,26 @@ impl Client {
         let version = self.version.unwrap();
 
         let request = match version {
-            1 => Request {
+            1 | 2 => Request::new(
                 request_id,
-                payload: RequestPayload::V1Consume(v1::requests::Consume {
+                RequestPayload::V1Consume(v1::requests::Consume {
                     topic_id,
                     subscription_id,
                     consumer_id,
                     max_messages,
+                    ack_previous: false,
+                    max_bytes: self.consume_max_bytes,
                 }),
-            },
+            ),
             _ => return Err(ClientError::VersionNotSupported),
         };
 
         #[cfg(debug_assertions)]
         debug!("Client: Sending {:?}", request);
 
-        let message = self.serializer.serialize_request(&request).unwrap();
+        let message = self.serialize(&request)?;
 
-        if let Err(err) = self.send(message) {
-            Err(ClientError::SendError(err))
-        } else {
-            Ok(())
-        }
+        self.send(message)
     }
 
     fn send_ack(
@@ -419,27 +993,23 @@ impl Client {
         let version = self.version.unwrap();
 
         let request = match version {
-            1 => Request {
+            1 | 2 => Request::new(
                 request_id,
-                payload: RequestPayload::V1Ack(v1::requests::Ack {
+                RequestPayload::V1Ack(v1::requests::Ack {
                     message_ref_key: message_ref_key.to_owned(),
                     subscription_id,
                     consumer_id,
                 }),
-            },
+            ),
             _ => return Err(ClientError::VersionNotSupported),
         };
 
         #[cfg(debug_assertions)]
         debug!("Client: Sending {:?}", request);
 
-        let message = self.serializer.serialize_request(&request).unwrap();
+        let message = self.serialize(&request)?;
 
-        if let Err(err) = self.send(message) {
-            Err(ClientError::SendError(err))
-        } else {
-            Ok(())
-        }
+        self.send(message)
     }
 
     fn send_nack(
@@ -458,35 +1028,231 @@ impl Client {
         let version = self.version.unwrap();
 
         let request = match version {
-            1 => Request {
+            1 | 2 => Request::new(
                 request_id,
-                payload: RequestPayload::V1Nack(v1::requests::Nack {
+                RequestPayload::V1Nack(v1::requests::Nack {
                     message_ref_key: message_ref_key.to_owned(),
                     subscription_id,
                     consumer_id,
                 }),
-            },
+            ),
             _ => return Err(ClientError::VersionNotSupported),
         };
 
         #[cfg(debug_assertions)]
         debug!("Client: Sending {:?}", request);
 
-        let message = self.serializer.serialize_request(&request).unwrap();
+        let message = self.serialize(&request)?;
 
-        if let Err(err) = self.send(message) {
-            Err(ClientError::SendError(err))
-        } else {
-            Ok(())
+        self.send(message)
+    }
+
+    fn send_quarantine(
+        self: &Self,
+        request_id: RequestId,
+        message_ref_key: &str,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+        reason: &str,
+    ) -> ClientResult<()> {
+        if self.connection.is_none() {
+            return Err(ClientError::NotConnected);
+        }
+        if self.version.is_none() {
+            return Err(ClientError::IncompatibleVersion);
+        }
+        let version = self.version.unwrap();
+
+        let request = match version {
+            1 | 2 => Request::new(
+                request_id,
+                RequestPayload::V1Quarantine(v1::requests::Quarantine {
+                    message_ref_key: message_ref_key.to_owned(),
+                    subscription_id,
+                    consumer_id,
+                    reason: reason.to_owned(),
+                }),
+            ),
+            _ => return Err(ClientError::VersionNotSupported),
+        };
+
+        #[cfg(debug_assertions)]
+        debug!("Client: Sending {:?}", request);
+
+        let message = self.serialize(&request)?;
+
+        self.send(message)
+    }
+
+    fn send_disconnect_consumer(
+        self: &Self,
+        request_id: RequestId,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+    ) -> ClientResult<()> {
+        if self.connection.is_none() {
+            return Err(ClientError::NotConnected);
+        }
+        if self.version.is_none() {
+            return Err(ClientError::IncompatibleVersion);
+        }
+        let version = self.version.unwrap();
+
+        let request = match version {
+            1 | 2 => Request::new(
+                request_id,
+                RequestPayload::V1DisconnectConsumer(v1::requests::DisconnectConsumer {
+                    topic_id,
+                    subscription_id,
+                    consumer_id,
+                }),
+            ),
+            _ => return Err(ClientError::VersionNotSupported),
+        };
+
+        #[cfg(debug_assertions)]
+        debug!("Client: Sending {:?}", request);
+
+        let message = self.serialize(&request)?;
+
+        self.send(message)
+    }
+
+    fn send_get_message(
+        self: &Self,
+        request_id: RequestId,
+        message_ref_key: &str,
+    ) -> ClientResult<()> {
+        if self.connection.is_none() {
+            return Err(ClientError::NotConnected);
+        }
+        if self.version.is_none() {
+            return Err(ClientError::IncompatibleVersion);
+        }
+        let version = self.version.unwrap();
+
+        let request = match version {
+            1 | 2 => Request::new(
+                request_id,
+                RequestPayload::V1GetMessage(v1::requests::GetMessage {
+                    message_ref_key: message_ref_key.to_owned(),
+                }),
+            ),
+            _ => return Err(ClientError::VersionNotSupported),
+        };
+
+        #[cfg(debug_assertions)]
+        debug!("Client: Sending {:?}", request);
+
+        let message = self.serialize(&request)?;
+
+        self.send(message)
+    }
+
+    fn send_list_topics(self: &Self, request_id: RequestId) -> ClientResult<()> {
+        if self.connection.is_none() {
+            return Err(ClientError::NotConnected);
+        }
+        if self.version.is_none() {
+            return Err(ClientError::IncompatibleVersion);
+        }
+        let version = self.version.unwrap();
+
+        let request = match version {
+            1 | 2 => Request::new(
+                request_id,
+                RequestPayload::V1ListTopics(v1::requests::ListTopics {}),
+            ),
+            _ => return Err(ClientError::VersionNotSupported),
+        };
+
+        #[cfg(debug_assertions)]
+        debug!("Client: Sending {:?}", request);
+
+        let message = self.serialize(&request)?;
+
+        self.send(message)
+    }
+
+    fn send_get_topic_partition_map(
+        self: &Self,
+        request_id: RequestId,
+        topic_id: TopicId,
+    ) -> ClientResult<()> {
+        if self.connection.is_none() {
+            return Err(ClientError::NotConnected);
+        }
+        if self.version.is_none() {
+            return Err(ClientError::IncompatibleVersion);
+        }
+        let version = self.version.unwrap();
+
+        let request = match version {
+            1 | 2 => Request::new(
+                request_id,
+                RequestPayload::V1GetTopicPartitionMap(v1::requests::GetTopicPartitionMap {
+                    topic_id,
+                }),
+            ),
+            _ => return Err(ClientError::VersionNotSupported),
+        };
+
+        #[cfg(debug_assertions)]
+        debug!("Client: Sending {:?}", request);
+
+        let message = self.serialize(&request)?;
+
+        self.send(message)
+    }
+
+    fn send_get_partitions(
+        self: &Self,
+        request_id: RequestId,
+        topic_id: TopicId,
+    ) -> ClientResult<()> {
+        if self.connection.is_none() {
+            return Err(ClientError::NotConnected);
         }
+        if self.version.is_none() {
+            return Err(ClientError::IncompatibleVersion);
+        }
+        let version = self.version.unwrap();
+
+        let request = match version {
+            1 | 2 => Request::new(
+                request_id,
+                RequestPayload::V1GetPartitions(v1::requests::GetPartitions { topic_id }),
+            ),
+            _ => return Err(ClientError::VersionNotSupported),
+        };
+
+        #[cfg(debug_assertions)]
+        debug!("Client: Sending {:?}", request);
+
+        let message = self.serialize(&request)?;
+
+        self.send(message)
     }
 
-    fn get_partition_id(self: &Self, _topic_id: TopicId, _key: &str) -> PartitionId {
-        // TODO: hash message key to partition id
-        1
+    /// Chooses the partition to publish a message to by hashing its key, fetching the
+    /// partitions of the topic from the broker if they are not cached
+    fn get_partition_id(self: &Self, topic_id: TopicId, key: &str) -> ClientResult<PartitionId> {
+        let cached = self
+            .partitions
+            .lock()
+            .unwrap()
+            .partition_for_key(topic_id, key);
+        match cached {
+            Some(partition_id) => Ok(partition_id),
+            None => {
+                let partition_ids = self.get_partitions(topic_id)?;
+                choose_partition(&partition_ids, key).ok_or(ClientError::NoData)
+            }
+        }
     }
 
-    fn recv(self: &Self) -> Result<ClientMessage, RecvError> {
+    fn recv(self: &Self) -> Result<BrokerMessage, RecvError> {
         if let Some(connection) = &self.connection {
             connection.recv()
         } else {
@@ -494,11 +1260,28 @@ impl Client {
         }
     }
 
-    fn send(&self, message: ClientMessage) -> Result<(), SendError<ClientMessage>> {
+    fn serialize(self: &Self, request: &Request) -> ClientResult<ClientMessage> {
+        let message = self.serializer.serialize_request(request).map_err(|err| {
+            self.metrics.incr_serialize_errors();
+            ClientError::SerializeError(err)
+        })?;
+        if let Err(err) = check_message_size(message.len(), self.max_message_size) {
+            self.buffer_pool.reuse(message);
+            return Err(ClientError::MessageTooLarge(err));
+        }
+        Ok(message)
+    }
+
+    fn count_deserialize_error(self: &Self, err: DeserializeError) -> DeserializeError {
+        self.metrics.incr_deserialize_errors();
+        err
+    }
+
+    fn send(&self, message: ClientMessage) -> ClientResult<()> {
         if let Some(connection) = &self.connection {
             connection.send(message)
         } else {
-            Err(SendError(message))
+            Err(ClientError::SendError(SendError(message)))
         }
     }
 }
@@ -0,0 +1,335 @@
+/*
+A client for a cluster of brokers. It is seeded with the authorities of one or more brokers,
+and asks whichever broker it can reach for the partitions of each topic and the node that
+owns each partition. Requests are sent straight to the owning node over a connection that is
+kept for each node, so a message is published in one round trip instead of being rejected by
+a broker that does not own the partition first.
+
+Messages are published to the partition that is chosen by hashing the message key, the same
+way that the `Client` chooses it, so messages with the same key are published to the same
+partition whichever client publishes them. Consuming takes messages from each node that owns
+partitions of the topic in turn.
+
+The map of each topic is kept until a request shows that it is out of date. When a node says
+that it does not own a partition, or a node can not be reached, the map is fetched again and
+the request is sent once more. Connections to nodes that can not be reached are dropped, and
+a new connection is made if the node is still in the map.
+*/
+
+use super::{
+    blocking_client::Client,
+    contracts::{
+        AckResult, ClientError, ClientResult, ConsumeResult, Message, MessageHeaders,
+        PartitionOwner, PublishResult, TopicPartitionMap,
+    },
+    partition_cache::choose_partition,
+};
+use log::{info, warn};
+use pulsar_rust_net::{
+    data_types::{
+        ConsumerId, MessageCount, PartitionId, Priority, SubscriptionId, Timestamp, TopicId,
+    },
+    sockets::buffer_pool::BufferPool,
+};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+use uuid::Uuid;
+
+/// Applies the options of the application to each client that connects to a node
+type ClientOptions = Box<dyn Fn(Client) -> Client + Send + Sync>;
+
+pub struct ClusterClient {
+    buffer_pool: Arc<BufferPool>,
+    seeds: Vec<String>,
+    client_options: Option<ClientOptions>,
+
+    /// Connected clients keyed by the authority of the node
+    clients: Mutex<HashMap<String, Arc<Client>>>,
+    maps: Mutex<HashMap<TopicId, Arc<TopicPartitionMap>>>,
+    next_consume: AtomicUsize,
+}
+
+impl ClusterClient {
+    pub fn new(buffer_pool: &Arc<BufferPool>, seeds: &[&str]) -> Self {
+        info!("ClusterClient: Constructed for {}", seeds.join(", "));
+
+        Self {
+            buffer_pool: buffer_pool.clone(),
+            seeds: seeds.iter().map(|seed| String::from(*seed)).collect(),
+            client_options: None,
+            clients: Mutex::new(HashMap::new()),
+            maps: Mutex::new(HashMap::new()),
+            next_consume: AtomicUsize::new(0),
+        }
+    }
+
+    /// Sets the options of the client that connects to each node, for example its security or
+    /// compression. The cluster client sends requests to the owning node itself, so redirects
+    /// are turned off on these clients
+    pub fn with_client_options<F>(mut self: Self, client_options: F) -> Self
+    where
+        F: Fn(Client) -> Client + Send + Sync + 'static,
+    {
+        self.client_options = Some(Box::new(client_options));
+        self
+    }
+
+    /// Connects to the first seed broker that accepts the connection. Fails with the error
+    /// from the last seed if none of them can be connected to
+    pub fn connect(self: &mut Self) -> ClientResult<()> {
+        let mut result = Err(ClientError::NotConnected);
+        for seed in self.seeds.iter() {
+            result = self.client(seed).map(|_| ());
+            if result.is_ok() {
+                break;
+            }
+            warn!("ClusterClient: Failed to connect to seed {seed}. {result:?}");
+        }
+        result
+    }
+
+    pub fn disconnect(self: &mut Self) {
+        for (_, client) in self.clients.get_mut().unwrap().drain() {
+            if let Ok(mut client) = Arc::try_unwrap(client) {
+                client.disconnect();
+            }
+        }
+        self.maps.get_mut().unwrap().clear();
+    }
+
+    pub fn is_connected(self: &Self) -> bool {
+        !self.clients.lock().unwrap().is_empty()
+    }
+
+    /// Returns the partitions of a topic and the nodes that own them, fetching them from the
+    /// cluster the first time
+    pub fn topic_partition_map(
+        self: &Self,
+        topic_id: TopicId,
+    ) -> ClientResult<Arc<TopicPartitionMap>> {
+        if let Some(map) = self.maps.lock().unwrap().get(&topic_id) {
+            return Ok(map.clone());
+        }
+
+        // Any node can answer, so the nodes that are already connected are asked first
+        let mut authorities: Vec<String> = self.clients.lock().unwrap().keys().cloned().collect();
+        for seed in self.seeds.iter() {
+            if !authorities.contains(seed) {
+                authorities.push(seed.clone());
+            }
+        }
+
+        let mut result = Err(ClientError::NotConnected);
+        for authority in authorities.iter() {
+            result = self
+                .client(authority)
+                .and_then(|client| client.get_topic_partition_map(topic_id));
+            match &result {
+                Ok(_) => break,
+                Err(err) if is_unreachable(err) => self.forget_node(authority),
+                Err(_) => return result.map(Arc::new),
+            }
+        }
+
+        let map = Arc::new(result?);
+        self.maps.lock().unwrap().insert(topic_id, map.clone());
+        Ok(map)
+    }
+
+    /// Publishes a message to the node that owns the partition that the key hashes to
+    pub fn publish(
+        self: &Self,
+        topic_id: TopicId,
+        key: Option<String>,
+        timestamp: Option<Timestamp>,
+        attributes: HashMap<String, String>,
+    ) -> ClientResult<PublishResult> {
+        let key: String = key.unwrap_or_else(|| Uuid::new_v4().to_string());
+        let result = self.publish_once(topic_id, &key, timestamp, attributes.clone());
+        match &result {
+            Err(err) if is_stale(err) => self.publish_once(topic_id, &key, timestamp, attributes),
+            _ => result,
+        }
+    }
+
+    /// Consumes messages from the next node that owns partitions of the topic and has
+    /// messages available. Each node allocates its own consumer id, so acks must be sent
+    /// with the consumer id from the result that the message was in
+    pub fn consume(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        max_messages: MessageCount,
+    ) -> ClientResult<ConsumeResult> {
+        let result = self.consume_once(topic_id, subscription_id, max_messages);
+        match &result {
+            Err(err) if is_stale(err) => self.consume_once(topic_id, subscription_id, max_messages),
+            _ => result,
+        }
+    }
+
+    /// Acks a message on the node that owns the partition that it was delivered from
+    pub fn ack(
+        self: &Self,
+        message: &Message,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+    ) -> ClientResult<AckResult> {
+        let topic_id = message.message_ref.topic_id;
+        let partition_id = message.message_ref.partition_id;
+        let authority = self.owner_authority(topic_id, partition_id)?;
+        let result = self
+            .client(&authority)
+            .and_then(|client| client.ack(&message.message_ref_key, subscription_id, consumer_id));
+        if let Err(err) = &result {
+            self.forget(topic_id, &authority, err);
+        }
+        result
+    }
+
+    fn publish_once(
+        self: &Self,
+        topic_id: TopicId,
+        key: &str,
+        timestamp: Option<Timestamp>,
+        attributes: HashMap<String, String>,
+    ) -> ClientResult<PublishResult> {
+        let map = self.topic_partition_map(topic_id)?;
+        let partition_ids: Vec<PartitionId> = map
+            .partitions
+            .iter()
+            .map(|owner| owner.partition_id)
+            .collect();
+        let partition_id = choose_partition(&partition_ids, key).ok_or(ClientError::NoData)?;
+        let authority = self.owner_authority(topic_id, partition_id)?;
+        let result = self.client(&authority).and_then(|client| {
+            client.publish_to_partition(
+                topic_id,
+                partition_id,
+                key,
+                timestamp,
+                Priority::default(),
+                MessageHeaders::default(),
+                attributes,
+            )
+        });
+        if let Err(err) = &result {
+            self.forget(topic_id, &authority, err);
+        }
+        result
+    }
+
+    fn consume_once(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        max_messages: MessageCount,
+    ) -> ClientResult<ConsumeResult> {
+        let map = self.topic_partition_map(topic_id)?;
+        let mut authorities: Vec<String> = map.partitions.iter().map(authority).collect();
+        authorities.sort_unstable();
+        authorities.dedup();
+        if authorities.is_empty() {
+            return Err(ClientError::NoData);
+        }
+
+        // Each call starts with the next node, so that one busy node does not starve the others
+        let start = self.next_consume.fetch_add(1, Ordering::Relaxed);
+        let mut result = Err(ClientError::NoData);
+        for index in 0..authorities.len() {
+            let authority = &authorities[(start + index) % authorities.len()];
+            let node_result = self
+                .client(authority)
+                .and_then(|client| client.consume(topic_id, subscription_id, None, max_messages));
+            match node_result {
+                Ok(consumed) if !consumed.messages.is_empty() => return Ok(consumed),
+                Ok(consumed) => result = Ok(consumed),
+                Err(err) => {
+                    self.forget(topic_id, authority, &err);
+                    if result.is_err() {
+                        result = Err(err);
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// The authority of the node that owns a partition
+    fn owner_authority(
+        self: &Self,
+        topic_id: TopicId,
+        partition_id: PartitionId,
+    ) -> ClientResult<String> {
+        let map = self.topic_partition_map(topic_id)?;
+        map.partitions
+            .iter()
+            .find(|owner| owner.partition_id == partition_id)
+            .map(authority)
+            .ok_or(ClientError::NoData)
+    }
+
+    /// Returns the client that is connected to a node, connecting to it the first time
+    fn client(self: &Self, authority: &str) -> ClientResult<Arc<Client>> {
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(client) = clients.get(authority) {
+            return Ok(client.clone());
+        }
+        let mut client = Client::new(&self.buffer_pool, authority);
+        if let Some(client_options) = &self.client_options {
+            client = client_options(client);
+        }
+        let mut client = client.with_max_redirects(0);
+        client.connect()?;
+        let client = Arc::new(client);
+        clients.insert(authority.to_owned(), client.clone());
+        Ok(client)
+    }
+
+    /// Drops the map of the topic when a request fails because it is out of date, and the
+    /// connection to the node when it could not be reached
+    fn forget(self: &Self, topic_id: TopicId, authority: &str, err: &ClientError) {
+        if is_stale(err) {
+            warn!("ClusterClient: Refreshing the partitions of topic {topic_id}. {err:?}");
+            self.maps.lock().unwrap().remove(&topic_id);
+        }
+        if is_unreachable(err) {
+            self.forget_node(authority);
+        }
+    }
+
+    fn forget_node(self: &Self, authority: &str) {
+        warn!("ClusterClient: Dropping the connection to {authority}");
+        if let Some(client) = self.clients.lock().unwrap().remove(authority) {
+            if let Ok(mut client) = Arc::try_unwrap(client) {
+                client.disconnect();
+            }
+        }
+    }
+}
+
+fn authority(owner: &PartitionOwner) -> String {
+    format!("{}:{}", owner.ip_address, owner.pubsub_port)
+}
+
+/// The node can not be reached, so it may have left the cluster
+fn is_unreachable(err: &ClientError) -> bool {
+    matches!(
+        err,
+        ClientError::NotConnected
+            | ClientError::SendError(_)
+            | ClientError::RecvError(_)
+            | ClientError::ConnectionLost
+    )
+}
+
+/// The request may succeed if it is sent to the node that the cluster now says owns the
+/// partition
+fn is_stale(err: &ClientError) -> bool {
+    matches!(err, ClientError::IncorrectNode(_)) || is_unreachable(err)
+}
@@ -0,0 +1,292 @@
+/*
+The broker treats messages as a key plus a set of string attributes. Applications that
+want to publish strongly typed payloads can use a codec to serialize the payload into the
+message attributes, and to deserialize it again when the message is consumed. The content
+type is stored in the content type header so that consumers can choose the matching codec.
+*/
+
+use std::collections::HashMap;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use pulsar_rust_net::data_types::ConsumerId;
+
+use super::contracts::{ClientError, ClientResult, ConsumeResult, Message, MessageHeaders};
+
+/// The message attribute that identified the codec before the content type header was
+/// added. Only read from messages that have no content type header
+pub const CONTENT_TYPE_ATTRIBUTE: &str = "content-type";
+
+/// The message attribute that contains the encoded payload
+pub const PAYLOAD_ATTRIBUTE: &str = "payload";
+
+/// Implement this trait to add support for a payload serialization format
+pub trait Codec<T> {
+    /// The value written to the content type header of messages encoded by this codec
+    fn content_type(self: &Self) -> &str;
+
+    fn encode(self: &Self, value: &T) -> ClientResult<String>;
+
+    fn decode(self: &Self, payload: &str) -> ClientResult<T>;
+}
+
+/// Serializes payloads as JSON
+pub struct JsonCodec;
+
+impl JsonCodec {
+    pub const CONTENT_TYPE: &'static str = "application/json";
+}
+
+impl<T: Serialize + DeserializeOwned> Codec<T> for JsonCodec {
+    fn content_type(self: &Self) -> &str {
+        JsonCodec::CONTENT_TYPE
+    }
+
+    fn encode(self: &Self, value: &T) -> ClientResult<String> {
+        serde_json::to_string(value).map_err(|err| ClientError::CodecError(err.to_string()))
+    }
+
+    fn decode(self: &Self, payload: &str) -> ClientResult<T> {
+        serde_json::from_str(payload).map_err(|err| ClientError::CodecError(err.to_string()))
+    }
+}
+
+/// A set of codecs that can encode and decode payloads of type T. Messages are always
+/// encoded with the default codec, which is the first one registered. Messages are decoded
+/// with the codec that matches the content type header of the message.
+pub struct CodecRegistry<T> {
+    default_content_type: Option<String>,
+    codecs: HashMap<String, Box<dyn Codec<T> + Send + Sync>>,
+}
+
+impl<T> CodecRegistry<T> {
+    pub fn new() -> Self {
+        Self {
+            default_content_type: None,
+            codecs: HashMap::new(),
+        }
+    }
+
+    pub fn register(self: &mut Self, codec: impl Codec<T> + Send + Sync + 'static) {
+        let content_type = codec.content_type().to_owned();
+        if self.default_content_type.is_none() {
+            self.default_content_type = Some(content_type.clone());
+        }
+        self.codecs.insert(content_type, Box::new(codec));
+    }
+
+    pub fn set_default(self: &mut Self, content_type: &str) {
+        self.default_content_type = Some(content_type.to_owned());
+    }
+
+    /// Encodes the value with the default codec, returning message headers that contain the
+    /// content type, and message attributes that contain the encoded payload
+    pub fn encode(
+        self: &Self,
+        value: &T,
+    ) -> ClientResult<(MessageHeaders, HashMap<String, String>)> {
+        let content_type = match &self.default_content_type {
+            Some(content_type) => content_type,
+            None => {
+                return Err(ClientError::CodecError(String::from(
+                    "No codecs are registered",
+                )))
+            }
+        };
+        let codec = self.codec(content_type)?;
+
+        let headers = MessageHeaders {
+            content_type: Some(content_type.clone()),
+            ..MessageHeaders::default()
+        };
+        let mut attributes = HashMap::new();
+        attributes.insert(PAYLOAD_ATTRIBUTE.to_owned(), codec.encode(value)?);
+        Ok((headers, attributes))
+    }
+
+    /// Decodes the payload from message attributes using the codec for the content type in
+    /// the message headers. Messages that were published before the content type header was
+    /// added have their content type in an attribute instead
+    pub fn decode(
+        self: &Self,
+        headers: &MessageHeaders,
+        attributes: &HashMap<String, String>,
+    ) -> ClientResult<T> {
+        let content_type = match headers
+            .content_type
+            .as_ref()
+            .or_else(|| attributes.get(CONTENT_TYPE_ATTRIBUTE))
+        {
+            Some(content_type) => content_type,
+            None => {
+                return Err(ClientError::CodecError(String::from(
+                    "Message has no content type",
+                )))
+            }
+        };
+        let payload = match attributes.get(PAYLOAD_ATTRIBUTE) {
+            Some(payload) => payload,
+            None => {
+                return Err(ClientError::CodecError(String::from(
+                    "Message has no payload",
+                )))
+            }
+        };
+        self.codec(content_type)?.decode(payload)
+    }
+
+    fn codec(self: &Self, content_type: &str) -> ClientResult<&(dyn Codec<T> + Send + Sync)> {
+        match self.codecs.get(content_type) {
+            Some(codec) => Ok(codec.as_ref()),
+            None => Err(ClientError::CodecError(format!(
+                "No codec registered for content type {content_type}"
+            ))),
+        }
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> CodecRegistry<T> {
+    /// Constructs a registry that encodes payloads as JSON
+    pub fn json() -> Self {
+        let mut registry = Self::new();
+        registry.register(JsonCodec);
+        registry
+    }
+}
+
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct TypedMessage<T> {
+    pub message: Message,
+    pub payload: T,
+}
+
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct TypedConsumeResult<T> {
+    pub consumer_id: ConsumerId,
+    pub messages: Vec<TypedMessage<T>>,
+}
+
+impl<T> TypedConsumeResult<T> {
+    /// Decodes the payloads of all the messages in a consume result
+    pub fn decode(result: ConsumeResult, codecs: &CodecRegistry<T>) -> ClientResult<Self> {
+        let mut messages = Vec::with_capacity(result.messages.len());
+        for message in result.messages {
+            let payload = codecs.decode(&message.headers, &message.attributes)?;
+            messages.push(TypedMessage { message, payload });
+        }
+        Ok(Self {
+            consumer_id: result.consumer_id,
+            messages,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CodecRegistry, TypedConsumeResult, CONTENT_TYPE_ATTRIBUTE, PAYLOAD_ATTRIBUTE};
+    use crate::api_bin::contracts::{ConsumeResult, Message, MessageHeaders, MessageRef};
+    use serde::{Deserialize, Serialize};
+    use std::collections::HashMap;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Order {
+        order_id: u32,
+        customer: String,
+        lines: Vec<String>,
+    }
+
+    #[test]
+    fn should_round_trip_typed_payload() {
+        let codecs = CodecRegistry::<Order>::json();
+        let order = Order {
+            order_id: 42,
+            customer: String::from("Acme"),
+            lines: vec![String::from("widget"), String::from("sprocket")],
+        };
+
+        let (headers, attributes) = codecs.encode(&order).unwrap();
+        assert_eq!(headers.content_type.as_deref(), Some("application/json"));
+        assert!(!attributes.contains_key(CONTENT_TYPE_ATTRIBUTE));
+
+        let result = ConsumeResult {
+            consumer_id: 7,
+            messages: vec![Message {
+                message_ref: MessageRef {
+                    topic_id: 1,
+                    partition_id: 1,
+                    ledger_id: 1,
+                    message_id: 1,
+                },
+                message_key: String::from("42"),
+                message_ref_key: String::from("1:1:1:1"),
+                published: 0,
+                delivered: 0,
+                first_delivered: None,
+                delivery_count: 1,
+                redelivered: false,
+                attributes,
+                headers,
+                last_in_ledger: false,
+            }],
+            remote_partitions: Vec::new(),
+        };
+
+        let typed_result = TypedConsumeResult::decode(result, &codecs).unwrap();
+        assert_eq!(typed_result.consumer_id, 7);
+        assert_eq!(typed_result.messages.len(), 1);
+        assert_eq!(typed_result.messages[0].payload, order);
+    }
+
+    #[test]
+    fn should_reject_unknown_content_type() {
+        let codecs = CodecRegistry::<Order>::json();
+        let (mut headers, attributes) = codecs
+            .encode(&Order {
+                order_id: 1,
+                customer: String::new(),
+                lines: Vec::new(),
+            })
+            .unwrap();
+        headers.content_type = Some(String::from("text/xml"));
+
+        assert!(codecs.decode(&headers, &attributes).is_err());
+    }
+
+    #[test]
+    fn should_ignore_content_type_attribute_when_header_is_present() {
+        let codecs = CodecRegistry::<Order>::json();
+        let order = Order {
+            order_id: 3,
+            customer: String::from("Acme"),
+            lines: Vec::new(),
+        };
+        let (headers, mut attributes) = codecs.encode(&order).unwrap();
+
+        // Applications are free to use an attribute with this name for their own purposes
+        attributes.insert(
+            CONTENT_TYPE_ATTRIBUTE.to_owned(),
+            String::from("text/plain"),
+        );
+
+        assert_eq!(codecs.decode(&headers, &attributes).unwrap(), order);
+    }
+
+    #[test]
+    fn should_decode_content_type_attribute_when_header_is_missing() {
+        let codecs = CodecRegistry::<Order>::json();
+        let mut attributes = HashMap::new();
+        attributes.insert(
+            CONTENT_TYPE_ATTRIBUTE.to_owned(),
+            String::from("application/json"),
+        );
+        attributes.insert(
+            PAYLOAD_ATTRIBUTE.to_owned(),
+            String::from(r#"{"order_id":4,"customer":"Acme","lines":[]}"#),
+        );
+
+        let order = codecs
+            .decode(&MessageHeaders::default(), &attributes)
+            .unwrap();
+        assert_eq!(order.order_id, 4);
+    }
+}
@@ -1,30 +1,43 @@
-use log::{error, info};
-use pulsar_rust_net::sockets::{buffer_pool::BufferPool, tcp_channel::TcpChannel};
+use log::info;
+use pulsar_rust_net::sockets::{
+    buffer_pool::BufferPool,
+    tcp_channel::{check_message_size, TcpChannel},
+    tls::ConnectionSecurity,
+};
 use std::{
     net::TcpStream,
     sync::{
         atomic::{AtomicBool, Ordering},
-        mpsc::{channel, Receiver, RecvError, SendError, Sender},
-        Arc,
+        mpsc::{channel, sync_channel, Receiver, RecvError, SendError, SyncSender, TrySendError},
+        Arc, Mutex,
     },
     time::Duration,
 };
 
-use super::contracts::ClientMessage;
+use super::contracts::{BrokerMessage, ClientError, ClientMessage, ClientResult};
 
-const MAX_MESSAGE_LENGTH: usize = 512;
+/// The number of requests that can be queued for sending to the broker before sending fails
+/// with `ClientError::WouldBlock`
+pub(crate) const DEFAULT_SEND_QUEUE_CAPACITY: usize = 10000;
 
 /// Opens a connection to a host endpoint and owns a thread that sends requests and
 /// receives replies into mpsc channels
 pub struct Connection {
     stop_signal: Arc<AtomicBool>,
-    request_sender: Sender<ClientMessage>,
-    response_receiver: Option<Receiver<ClientMessage>>,
+    request_sender: SyncSender<ClientMessage>,
+    response_receiver: Mutex<Option<Receiver<BrokerMessage>>>,
     tcp_channel: TcpChannel,
+    max_message_size: usize,
 }
 
 impl Connection {
-    pub fn new(buffer_pool: &Arc<BufferPool>, authority: &str) -> Self {
+    pub fn new(
+        buffer_pool: &Arc<BufferPool>,
+        authority: &str,
+        max_message_size: usize,
+        send_queue_capacity: usize,
+        security: &ConnectionSecurity,
+    ) -> Self {
         let stream = TcpStream::connect(&authority)
             .expect(&format!("Connection: Failed to connect to {}", authority));
         info!("Connection: Connected to {}", authority);
@@ -34,9 +47,16 @@ impl Connection {
             .set_read_timeout(Some(Duration::from_millis(5)))
             .unwrap();
 
+        // The TLS handshake is completed by the channel before any requests are sent
+        let stream = security
+            .wrap(stream)
+            .unwrap_or_else(|err| panic!("Connection: Failed to secure connection. {err}"));
+
         let stop_signal = Arc::new(AtomicBool::new(false));
-        let (request_sender, request_receiver) = channel::<ClientMessage>();
-        let (response_sender, response_receiver) = channel::<ClientMessage>();
+        // The request queue is bounded so that the application can slow down when requests are
+        // produced faster than the network can send them
+        let (request_sender, request_receiver) = sync_channel::<ClientMessage>(send_queue_capacity);
+        let (response_sender, response_receiver) = channel::<BrokerMessage>();
 
         let tcp_channel = TcpChannel::new(
             request_receiver,
@@ -44,13 +64,15 @@ impl Connection {
             stream,
             &buffer_pool,
             &stop_signal,
+            max_message_size,
         );
 
         Self {
             stop_signal,
             request_sender,
-            response_receiver: Some(response_receiver),
+            response_receiver: Mutex::new(Some(response_receiver)),
             tcp_channel,
+            max_message_size,
         }
     }
 
@@ -61,8 +83,8 @@ impl Connection {
     }
 
     /// Blocking call that waits until there is a response from the host
-    pub fn recv(self: &Self) -> Result<ClientMessage, RecvError> {
-        if let Some(receiver) = &self.response_receiver {
+    pub fn recv(self: &Self) -> Result<BrokerMessage, RecvError> {
+        if let Some(receiver) = &*self.response_receiver.lock().unwrap() {
             receiver.recv()
         } else {
             Err(RecvError)
@@ -71,21 +93,64 @@ impl Connection {
 
     /// Allows you to take over the receiving half of the connection. After
     /// calling this method, you can no longer use the recv function.
-    pub fn take_receiver(self: &mut Self) -> Option<Receiver<Vec<u8>>> {
-        self.response_receiver.take()
+    pub fn take_receiver(self: &mut Self) -> Option<Receiver<BrokerMessage>> {
+        self.response_receiver.get_mut().unwrap().take()
     }
 
-    /// Non-blocking call that queues a message to send to the host
-    pub fn send(&self, message: ClientMessage) -> Result<(), SendError<ClientMessage>> {
-        if message.len() > MAX_MESSAGE_LENGTH {
-            error!(
-                "Connection: Message length {} exceeds maximum length of {}",
-                message.len(),
-                MAX_MESSAGE_LENGTH
-            );
-            Err(SendError(message))
-        } else {
-            self.request_sender.send(message)
+    /// Another handle to the queue of messages waiting to be sent to the host, for threads
+    /// that send messages of their own
+    pub(crate) fn request_sender(self: &Self) -> SyncSender<ClientMessage> {
+        self.request_sender.clone()
+    }
+
+    /// Non-blocking call that queues a message to send to the host. Fails with
+    /// `ClientError::WouldBlock` when the queue is full
+    pub fn send(&self, message: ClientMessage) -> ClientResult<()> {
+        check_message_size(message.len(), self.max_message_size)
+            .map_err(ClientError::MessageTooLarge)?;
+        match self.request_sender.try_send(message) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(ClientError::WouldBlock),
+            Err(TrySendError::Disconnected(message)) => {
+                Err(ClientError::SendError(SendError(message)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Connection;
+    use crate::api_bin::contracts::ClientError;
+    use pulsar_rust_net::sockets::{
+        buffer_pool::BufferPool, tcp_channel::DEFAULT_MAX_MESSAGE_SIZE, tls::ConnectionSecurity,
+    };
+    use std::{net::TcpListener, sync::Arc, thread};
+
+    #[test]
+    fn should_fail_with_would_block_when_the_send_queue_is_full() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let authority = listener.local_addr().unwrap().to_string();
+
+        // The peer accepts the connection but never reads from it, so sending stalls
+        let peer = thread::spawn(move || listener.accept().unwrap());
+
+        let connection = Connection::new(
+            &Arc::new(BufferPool::new()),
+            &authority,
+            DEFAULT_MAX_MESSAGE_SIZE,
+            2,
+            &ConnectionSecurity::Plain,
+        );
+        let _peer = peer.join().unwrap();
+
+        let mut result = Ok(());
+        for _ in 0..1000 {
+            result = connection.send(vec![0u8; 30000]);
+            if result.is_err() {
+                break;
+            }
         }
+        assert!(matches!(result, Err(ClientError::WouldBlock)));
     }
 }
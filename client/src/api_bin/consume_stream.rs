@@ -0,0 +1,70 @@
+/*
+A consume stream repeatedly consumes from one subscription as the same consumer. Each stream
+has its own session, so the consumer id that the broker allocates in response to the first
+consume request is remembered for the stream and sent with every request after that. This
+preserves the key affinity and assigned messages of the consumer between polls, and lets
+several streams consume from the same subscription as different consumers.
+*/
+
+use super::{
+    contracts::{ClientResult, ConsumeResult},
+    future_response::FutureResponse,
+    session::Session,
+};
+use pulsar_rust_net::data_types::{ConsumerId, MessageCount, SubscriptionId, TopicId};
+
+#[derive(Clone)]
+pub struct ConsumeStream {
+    session: Session,
+    topic_id: TopicId,
+    subscription_id: SubscriptionId,
+}
+
+impl ConsumeStream {
+    pub(crate) fn new(
+        session: Session,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+    ) -> Self {
+        Self {
+            session,
+            topic_id,
+            subscription_id,
+        }
+    }
+
+    /// The session that requests from this stream are made in. Use the session to ack and
+    /// nack the messages that the stream consumed
+    pub fn session(self: &Self) -> &Session {
+        &self.session
+    }
+
+    pub fn topic_id(self: &Self) -> TopicId {
+        self.topic_id
+    }
+
+    pub fn subscription_id(self: &Self) -> SubscriptionId {
+        self.subscription_id
+    }
+
+    /// The consumer id that the broker allocated to this stream. This is None until the
+    /// response to the first consume request is received
+    pub fn consumer_id(self: &Self) -> Option<ConsumerId> {
+        self.session
+            .consumer_id(self.topic_id, self.subscription_id)
+    }
+
+    /// Asynchronously consumes the next messages, as the consumer that was allocated to this
+    /// stream by the first response
+    pub fn consume(
+        self: &Self,
+        max_messages: MessageCount,
+    ) -> ClientResult<FutureResponse<ConsumeResult>> {
+        self.session.consume(
+            self.topic_id,
+            self.subscription_id,
+            &self.consumer_id(),
+            max_messages,
+        )
+    }
+}
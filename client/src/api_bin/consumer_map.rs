@@ -0,0 +1,97 @@
+use pulsar_rust_net::{
+    bin_serialization::{RequestId, SessionId},
+    data_types::{ConsumerId, SubscriptionId, TopicId},
+};
+use std::collections::HashMap;
+
+/// Remembers the consumer id that the broker allocated for each subscription in each
+/// session, so that the
+/// client continues as the same logical consumer after reconnecting to the broker. This
+/// preserves any key affinity and assigned messages that the broker has for the consumer.
+pub(crate) struct ConsumerMap {
+    consumers: HashMap<(SessionId, TopicId, SubscriptionId), ConsumerId>,
+    pending: HashMap<RequestId, (SessionId, TopicId, SubscriptionId)>,
+}
+
+impl ConsumerMap {
+    pub(crate) fn new() -> Self {
+        Self {
+            consumers: HashMap::new(),
+            pending: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn get(
+        self: &Self,
+        session_id: SessionId,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+    ) -> Option<ConsumerId> {
+        self.consumers
+            .get(&(session_id, topic_id, subscription_id))
+            .copied()
+    }
+
+    pub(crate) fn insert(
+        self: &mut Self,
+        session_id: SessionId,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+    ) {
+        self.consumers
+            .insert((session_id, topic_id, subscription_id), consumer_id);
+    }
+
+    /// Forgets the consumer id for a subscription, so that the broker allocates a new
+    /// consumer the next time this session consumes from it
+    pub(crate) fn remove(
+        self: &mut Self,
+        session_id: SessionId,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+    ) -> Option<ConsumerId> {
+        self.consumers
+            .remove(&(session_id, topic_id, subscription_id))
+    }
+
+    /// Records the subscription that a consume request was for, so that the consumer id
+    /// can be saved when the response arrives
+    pub(crate) fn expect(
+        self: &mut Self,
+        request_id: RequestId,
+        session_id: SessionId,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+    ) {
+        self.pending
+            .insert(request_id, (session_id, topic_id, subscription_id));
+    }
+
+    /// Saves the consumer id from the response to a consume request
+    pub(crate) fn complete(
+        self: &mut Self,
+        request_id: RequestId,
+        consumer_id: Option<ConsumerId>,
+    ) {
+        if let Some((session_id, topic_id, subscription_id)) = self.pending.remove(&request_id) {
+            if let Some(consumer_id) = consumer_id {
+                self.insert(session_id, topic_id, subscription_id, consumer_id);
+            }
+        }
+    }
+
+    /// Saves the consumer id from the response to a join group request. The consumer is saved
+    /// against the subscription of the group rather than the subscription that was joined
+    pub(crate) fn complete_group(
+        self: &mut Self,
+        request_id: RequestId,
+        member: Option<(SubscriptionId, ConsumerId)>,
+    ) {
+        if let Some((session_id, topic_id, _)) = self.pending.remove(&request_id) {
+            if let Some((subscription_id, consumer_id)) = member {
+                self.insert(session_id, topic_id, subscription_id, consumer_id);
+            }
+        }
+    }
+}
@@ -3,14 +3,25 @@ use std::{
     sync::mpsc::{RecvError, SendError},
 };
 
+pub use pulsar_rust_net::contracts::v1::requests::MessageHeaders;
+
 use pulsar_rust_net::{
-    bin_serialization::DeserializeError,
+    bin_serialization::{DeserializeError, SerializeError},
     contracts::v1::{self, responses::RequestOutcome},
-    data_types::{ConsumerId, ErrorCode, LedgerId, MessageId, PartitionId, Timestamp, TopicId},
+    data_types::{
+        ConsumerId, CreditCount, ErrorCode, LedgerId, MessageId, NodeId, PartitionId, PortNumber,
+        Priority, SubscriptionId, Timestamp, TopicId,
+    },
+    error_codes::ERROR_CODE_INCORRECT_NODE,
+    sockets::tcp_channel::{MessageTooLarge, ReceivedMessage},
 };
 
 pub(crate) type ClientMessage = Vec<u8>;
 
+/// A message that was received from the broker, without being copied out of the buffer that
+/// it was received into
+pub(crate) type BrokerMessage = ReceivedMessage;
+
 #[derive(Debug)]
 pub enum ClientError {
     /// The client is not connected to a broker
@@ -25,6 +36,10 @@ pub enum ClientError {
     /// An error occurred sending the request to the broker
     SendError(SendError<Vec<u8>>),
 
+    /// The queue of requests waiting to be sent to the broker is full. Requests are being
+    /// made faster than they can be sent, so the application should slow down and try again
+    WouldBlock,
+
     /// The broker retuened an unsuccesfull outcome for the request
     BadOutcome(RequestOutcome),
 
@@ -35,12 +50,30 @@ pub enum ClientError {
     /// happens if you mix sync and async calls on the same connection
     IncorrectResponseType,
 
-    /// The request was sent to the wrong broker, it does not currently own this partition
-    IncorrectNode,
+    /// The request was sent to the wrong broker, it does not currently own this partition. This
+    /// is the authority of the broker that does own it, when the broker knows
+    IncorrectNode(Option<String>),
+
+    /// The request could not be serialized for sending to the broker
+    SerializeError(SerializeError),
+
+    /// The serialized request is longer than the maximum message size of the connection
+    MessageTooLarge(MessageTooLarge),
 
     /// The response from the broker could not be deserialized
     DeserializeError(DeserializeError),
 
+    /// A message payload could not be encoded or decoded by the codec
+    CodecError(String),
+
+    /// The broker did not respond within the time allowed
+    Timeout,
+
+    /// The broker stopped answering pings, so the connection is assumed to be broken. Requests
+    /// that were waiting for a response fail with this error, and so do new requests until
+    /// the client reconnects
+    ConnectionLost,
+
     /// There was an error receiving the response from the broker. Most likely the broker
     /// was shutting down and closed the connection
     RecvError(RecvError),
@@ -52,6 +85,9 @@ pub enum ClientError {
 
 pub type ClientResult<T> = Result<T, ClientError>;
 
+/// A function that is called with the result of each publish request
+pub type PublishCallback = dyn Fn(&ClientResult<PublishResult>) + Send + Sync;
+
 #[cfg_attr(debug_assertions, derive(Debug))]
 pub struct MessageRef {
     pub topic_id: TopicId,
@@ -67,19 +103,59 @@ pub struct Message {
     pub message_ref_key: String,
     pub published: Timestamp,
     pub delivered: Timestamp,
+
+    /// When the message was first delivered to a consumer of the subscription. This is None
+    /// for messages that were fetched without being delivered
+    pub first_delivered: Option<Timestamp>,
     pub delivery_count: usize,
+    pub redelivered: bool,
     pub attributes: HashMap<String, String>,
+    pub headers: MessageHeaders,
+
+    /// No later message had been published to the ledger when this message was delivered.
+    /// Replay tools can use this to move on to the next ledger
+    pub last_in_ledger: bool,
 }
 
 #[cfg_attr(debug_assertions, derive(Debug))]
 pub struct PublishResult {
     pub message_ref: MessageRef,
+
+    /// The broker node that owns the ledger that the message was published to
+    pub node_id: NodeId,
+
+    /// The number of messages in the ledger when the broker acknowledged the publish
+    pub ledger_message_count: usize,
+}
+
+/// One message in a batch publish request. Each message is published to the partition that
+/// is chosen by hashing its key, as it would be if it was published on its own
+#[derive(Default)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct PublishItem {
+    pub key: Option<String>,
+    pub timestamp: Option<Timestamp>,
+    pub priority: Priority,
+    pub headers: MessageHeaders,
+    pub attributes: HashMap<String, String>,
 }
 
 #[cfg_attr(debug_assertions, derive(Debug))]
 pub struct ConsumeResult {
     pub consumer_id: ConsumerId,
     pub messages: Vec<Message>,
+    pub remote_partitions: Vec<PartitionOwner>,
+}
+
+/// A partition of the topic that is owned by another broker. To consume messages from
+/// this partition, send consume requests to the broker at this address.
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct PartitionOwner {
+    pub topic_id: TopicId,
+    pub partition_id: PartitionId,
+    pub node_id: NodeId,
+    pub ip_address: String,
+    pub pubsub_port: PortNumber,
 }
 
 #[cfg_attr(debug_assertions, derive(Debug))]
@@ -92,6 +168,68 @@ pub struct NackResult {
     pub success: bool,
 }
 
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct QuarantineResult {
+    pub success: bool,
+}
+
+/// The consumer id that the broker allocated to this member of the consumer group, and the
+/// subscription of the group that the member consumes from and acks to
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct JoinGroupResult {
+    pub subscription_id: SubscriptionId,
+    pub consumer_id: ConsumerId,
+}
+
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct LeaveGroupResult {
+    pub success: bool,
+}
+
+/// The result of disconnecting a consumer from a subscription
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct DisconnectConsumerResult {
+    pub success: bool,
+}
+
+/// The number of credits that a subscriber has after granting it more
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct FlowResult {
+    pub credits: CreditCount,
+}
+
+/// A topic in the cluster, with the number of partitions and subscriptions that it has
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct TopicSummary {
+    pub topic_id: TopicId,
+    pub name: String,
+    pub partition_count: usize,
+    pub subscription_count: usize,
+}
+
+/// The partitions of a topic and the broker that owns each of them
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct TopicPartitionMap {
+    pub topic: TopicSummary,
+
+    /// Sorted by partition id, which is the order used to choose the partition for a key
+    pub partitions: Vec<PartitionOwner>,
+}
+
+impl ClientError {
+    /// The error for a request that the broker answered without any data. A broker that does
+    /// not own the partition redirects the client to the broker that does, when it knows
+    pub(crate) fn from_outcome(outcome: RequestOutcome, redirect: Option<String>) -> Self {
+        match outcome {
+            RequestOutcome::Error(_, ERROR_CODE_INCORRECT_NODE) => {
+                ClientError::IncorrectNode(redirect)
+            }
+            RequestOutcome::Error(msg, error_code) => ClientError::Error(msg, error_code),
+            outcome => ClientError::BadOutcome(outcome),
+        }
+    }
+}
+
 impl From<&v1::responses::MessageRef> for MessageRef {
     fn from(message_ref: &v1::responses::MessageRef) -> Self {
         Self {
@@ -107,6 +245,8 @@ impl From<&v1::responses::PublishResult> for PublishResult {
     fn from(result: &v1::responses::PublishResult) -> Self {
         Self {
             message_ref: MessageRef::from(&result.message_ref),
+            node_id: result.node_id,
+            ledger_message_count: result.ledger_message_count,
         }
     }
 }
@@ -119,8 +259,12 @@ impl From<&v1::responses::Message> for Message {
             message_ref_key: message.message_ack_key.clone(),
             published: message.published,
             delivered: message.delivered,
+            first_delivered: message.first_delivered,
             delivery_count: message.delivery_count,
+            redelivered: message.redelivered,
             attributes: message.attributes.clone(),
+            headers: message.headers.clone(),
+            last_in_ledger: message.last_in_ledger,
         }
     }
 }
@@ -130,6 +274,23 @@ impl From<&v1::responses::ConsumeResult> for ConsumeResult {
         Self {
             consumer_id: result.consumer_id,
             messages: result.messages.iter().map(|m| Message::from(m)).collect(),
+            remote_partitions: result
+                .remote_partitions
+                .iter()
+                .map(|p| PartitionOwner::from(p))
+                .collect(),
+        }
+    }
+}
+
+impl From<&v1::responses::PartitionOwner> for PartitionOwner {
+    fn from(owner: &v1::responses::PartitionOwner) -> Self {
+        Self {
+            topic_id: owner.topic_id,
+            partition_id: owner.partition_id,
+            node_id: owner.node_id,
+            ip_address: owner.ip_address.clone(),
+            pubsub_port: owner.pubsub_port,
         }
     }
 }
@@ -149,3 +310,83 @@ impl From<&v1::responses::NackResult> for NackResult {
         }
     }
 }
+
+impl From<&v1::responses::QuarantineResult> for QuarantineResult {
+    fn from(result: &v1::responses::QuarantineResult) -> Self {
+        QuarantineResult {
+            success: result.success,
+        }
+    }
+}
+
+impl From<&v1::responses::JoinGroupResult> for JoinGroupResult {
+    fn from(result: &v1::responses::JoinGroupResult) -> Self {
+        JoinGroupResult {
+            subscription_id: result.subscription_id,
+            consumer_id: result.consumer_id,
+        }
+    }
+}
+
+impl From<&v1::responses::LeaveGroupResult> for LeaveGroupResult {
+    fn from(result: &v1::responses::LeaveGroupResult) -> Self {
+        LeaveGroupResult {
+            success: result.success,
+        }
+    }
+}
+
+impl From<&v1::responses::DisconnectConsumerResult> for DisconnectConsumerResult {
+    fn from(result: &v1::responses::DisconnectConsumerResult) -> Self {
+        DisconnectConsumerResult {
+            success: result.success,
+        }
+    }
+}
+
+impl From<&v1::responses::FlowResult> for FlowResult {
+    fn from(result: &v1::responses::FlowResult) -> Self {
+        FlowResult {
+            credits: result.credits,
+        }
+    }
+}
+
+impl From<&v1::responses::TopicSummary> for TopicSummary {
+    fn from(topic: &v1::responses::TopicSummary) -> Self {
+        TopicSummary {
+            topic_id: topic.topic_id,
+            name: topic.name.clone(),
+            partition_count: topic.partition_count,
+            subscription_count: topic.subscription_count,
+        }
+    }
+}
+
+impl From<&v1::responses::TopicPartitionMap> for TopicPartitionMap {
+    /// Partitions that are owned by a node that the broker did not include are left out,
+    /// because there is no way to send requests to them
+    fn from(map: &v1::responses::TopicPartitionMap) -> Self {
+        let mut partitions: Vec<PartitionOwner> = map
+            .partitions
+            .iter()
+            .filter_map(|partition| {
+                map.nodes
+                    .iter()
+                    .find(|node| node.node_id == partition.node_id)
+                    .map(|node| PartitionOwner {
+                        topic_id: partition.topic_id,
+                        partition_id: partition.partition_id,
+                        node_id: node.node_id,
+                        ip_address: node.ip_address.clone(),
+                        pubsub_port: node.pubsub_port,
+                    })
+            })
+            .collect();
+        partitions.sort_unstable_by_key(|owner| owner.partition_id);
+        TopicPartitionMap {
+            topic: TopicSummary::from(&map.topic),
+            partitions,
+        }
+    }
+}
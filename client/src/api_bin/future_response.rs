@@ -1,11 +1,21 @@
-use super::contracts::{AckResult, ClientResult, ConsumeResult, NackResult, PublishResult};
-use pulsar_rust_net::bin_serialization::RequestId;
+use super::{
+    consumer_map::ConsumerMap,
+    contracts::{
+        AckResult, ClientError, ClientResult, ConsumeResult, DisconnectConsumerResult, FlowResult,
+        JoinGroupResult, LeaveGroupResult, Message, NackResult, PublishCallback, PublishResult,
+        QuarantineResult, TopicSummary,
+    },
+    partition_cache::{PartitionCache, DEFAULT_PARTITION_CACHE_DURATION},
+};
+use pulsar_rust_net::{bin_serialization::RequestId, data_types::PartitionId};
 use std::{
     collections::HashMap,
     future::Future,
     pin::Pin,
-    sync::{Arc, Mutex},
+    sync::{mpsc::Sender, Arc, Mutex},
     task::{Context, Poll, Waker},
+    thread,
+    time::{Duration, Instant},
 };
 
 pub(crate) struct FutureResponseState<T> {
@@ -13,15 +23,78 @@ pub(crate) struct FutureResponseState<T> {
     pub(crate) waker: Option<Waker>,
 }
 
+type FutureMap<T> = HashMap<RequestId, Arc<Mutex<FutureResponseState<T>>>>;
+
+/// Finds the map in the `FutureHashMap` that holds the state of one type of request
+pub(crate) type SelectFutureMap<T> = fn(&mut FutureHashMap) -> &mut FutureMap<T>;
+
+/// Sends the request again to the broker with the authority passed
+type Retry<T> = Box<dyn FnOnce(&str) -> ClientResult<FutureResponse<T>> + Send>;
+
+/// Completes when the broker responds to a request. Dropping this future before it completes,
+/// for example when it is wrapped in a timeout, cancels the request so that its state is not
+/// kept forever if the broker never responds
 pub struct FutureResponse<T> {
     state: Arc<Mutex<FutureResponseState<T>>>,
+
+    /// This is None once the response has been taken
+    pending: Option<PendingRequest<T>>,
+
+    /// Sends the request to the broker that owns the partition, when the broker that the
+    /// request was sent to does not own it
+    retry: Option<Retry<T>>,
+
+    /// The response from the broker that the request was redirected to
+    redirected: Option<Box<FutureResponse<T>>>,
+}
+
+/// Identifies the entry in the `FutureHashMap` that the response will be delivered to
+struct PendingRequest<T> {
+    futures: Arc<Mutex<FutureHashMap>>,
+    request_id: RequestId,
+    select: SelectFutureMap<T>,
+
+    /// Publish requests are not cancelled when there is a publish callback, because the callback
+    /// is still waiting for the response
+    has_callback: bool,
 }
 
 pub(crate) struct FutureHashMap {
     pub publish_futures: HashMap<RequestId, Arc<Mutex<FutureResponseState<PublishResult>>>>,
+    pub publish_batch_futures:
+        HashMap<RequestId, Arc<Mutex<FutureResponseState<Vec<ClientResult<PublishResult>>>>>>,
     pub consume_futures: HashMap<RequestId, Arc<Mutex<FutureResponseState<ConsumeResult>>>>,
     pub ack_futures: HashMap<RequestId, Arc<Mutex<FutureResponseState<AckResult>>>>,
     pub nack_futures: HashMap<RequestId, Arc<Mutex<FutureResponseState<NackResult>>>>,
+    pub join_group_futures: HashMap<RequestId, Arc<Mutex<FutureResponseState<JoinGroupResult>>>>,
+    pub leave_group_futures: HashMap<RequestId, Arc<Mutex<FutureResponseState<LeaveGroupResult>>>>,
+    pub disconnect_consumer_futures:
+        HashMap<RequestId, Arc<Mutex<FutureResponseState<DisconnectConsumerResult>>>>,
+    pub get_message_futures: HashMap<RequestId, Arc<Mutex<FutureResponseState<Message>>>>,
+    pub quarantine_futures: HashMap<RequestId, Arc<Mutex<FutureResponseState<QuarantineResult>>>>,
+    pub get_partitions_futures:
+        HashMap<RequestId, Arc<Mutex<FutureResponseState<Vec<PartitionId>>>>>,
+    pub list_topics_futures: HashMap<RequestId, Arc<Mutex<FutureResponseState<Vec<TopicSummary>>>>>,
+    pub flow_futures: HashMap<RequestId, Arc<Mutex<FutureResponseState<FlowResult>>>>,
+
+    /// Messages that the broker pushes to subscribers, keyed by the id of the subscribe
+    /// request. These are not pending requests, so flushing does not wait for them
+    pub deliveries: HashMap<RequestId, Sender<ClientResult<ConsumeResult>>>,
+    pub consumers: ConsumerMap,
+    pub partitions: PartitionCache,
+    pub flush_wakers: Vec<Waker>,
+    pub publish_callback: Option<Arc<PublishCallback>>,
+
+    /// The number of receiver threads that are running. When there are none, no more
+    /// responses will arrive, so pending requests can not be flushed
+    pub receiver_count: usize,
+}
+
+/// Completes when there are no more requests waiting for a response from the broker, or with
+/// an error if the client stops receiving responses before then
+pub struct FlushFuture {
+    futures: Arc<Mutex<FutureHashMap>>,
+    deadline: Instant,
 }
 
 impl<T> FutureResponseState<T> {
@@ -34,9 +107,86 @@ impl<T> FutureResponseState<T> {
 }
 
 impl<T> FutureResponse<T> {
-    pub(crate) fn new(state: &Arc<Mutex<FutureResponseState<T>>>) -> Self {
+    pub(crate) fn new(
+        state: &Arc<Mutex<FutureResponseState<T>>>,
+        futures: &Arc<Mutex<FutureHashMap>>,
+        request_id: RequestId,
+        select: SelectFutureMap<T>,
+    ) -> Self {
         Self {
             state: state.clone(),
+            pending: Some(PendingRequest {
+                futures: futures.clone(),
+                request_id,
+                select,
+                has_callback: false,
+            }),
+            retry: None,
+            redirected: None,
+        }
+    }
+
+    /// Keeps the request when this future is dropped if a publish callback is registered
+    pub(crate) fn with_publish_callback(mut self: Self) -> Self {
+        if let Some(pending) = &mut self.pending {
+            pending.has_callback = true;
+        }
+        self
+    }
+
+    /// Sends the request again when the broker responds that another broker owns the
+    /// partition. The response to the retried request completes this future instead
+    pub(crate) fn with_redirect<F>(mut self: Self, retry: F) -> Self
+    where
+        F: FnOnce(&str) -> ClientResult<FutureResponse<T>> + Send + 'static,
+    {
+        self.retry = Some(Box::new(retry));
+        self
+    }
+
+    /// Returns the future for the redirected request if the result says that another broker
+    /// owns the partition, otherwise returns the result
+    fn follow(
+        self: &mut Self,
+        result: ClientResult<T>,
+    ) -> Result<FutureResponse<T>, ClientResult<T>> {
+        match (self.retry.take(), result) {
+            (Some(retry), Err(ClientError::IncorrectNode(Some(authority)))) => {
+                retry(&authority).map_err(Err)
+            }
+            (_, result) => Err(result),
+        }
+    }
+}
+
+impl<T> FutureResponse<T> {
+    /// Returns true if a response has been received, and awaiting this future will not block
+    pub fn is_ready(self: &Self) -> bool {
+        match &self.redirected {
+            Some(redirected) => redirected.is_ready(),
+            None => self.state.lock().unwrap().result.is_some(),
+        }
+    }
+
+    /// Blocks the current thread until a response is received, or the timeout elapses. This
+    /// is for requests that the client must complete before it can continue, without awaiting
+    pub(crate) fn wait(mut self: Self, timeout: Duration) -> ClientResult<T> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let result = self.state.lock().unwrap().result.take();
+            if let Some(result) = result {
+                self.pending = None;
+                return match self.follow(result) {
+                    Ok(redirected) => {
+                        redirected.wait(deadline.saturating_duration_since(Instant::now()))
+                    }
+                    Err(result) => result,
+                };
+            }
+            if Instant::now() >= deadline {
+                return Err(ClientError::Timeout);
+            }
+            thread::sleep(Duration::from_millis(1));
         }
     }
 }
@@ -45,12 +195,45 @@ impl<T> Future for FutureResponse<T> {
     type Output = ClientResult<T>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut state = self.state.lock().unwrap();
-        if state.result.is_some() {
-            Poll::Ready(state.result.take().unwrap())
-        } else {
-            state.waker = Some(cx.waker().clone());
-            Poll::Pending
+        let this = self.get_mut();
+        if let Some(redirected) = &mut this.redirected {
+            return Pin::new(redirected.as_mut()).poll(cx);
+        }
+        let mut state = this.state.lock().unwrap();
+        match state.result.take() {
+            Some(result) => {
+                drop(state);
+                this.pending = None;
+                match this.follow(result) {
+                    Ok(redirected) => {
+                        let redirected = this.redirected.insert(Box::new(redirected));
+                        Pin::new(redirected.as_mut()).poll(cx)
+                    }
+                    Err(result) => Poll::Ready(result),
+                }
+            }
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> Drop for FutureResponse<T> {
+    fn drop(self: &mut Self) {
+        if let Some(pending) = self.pending.take() {
+            if let Ok(mut futures) = pending.futures.lock() {
+                if pending.has_callback && futures.publish_callback.is_some() {
+                    return;
+                }
+                if (pending.select)(&mut futures)
+                    .remove(&pending.request_id)
+                    .is_some()
+                {
+                    futures.wake_flushes();
+                }
+            }
         }
     }
 }
@@ -59,9 +242,198 @@ impl FutureHashMap {
     pub(crate) fn new() -> Self {
         Self {
             publish_futures: HashMap::new(),
+            publish_batch_futures: HashMap::new(),
             consume_futures: HashMap::new(),
             ack_futures: HashMap::new(),
             nack_futures: HashMap::new(),
+            join_group_futures: HashMap::new(),
+            leave_group_futures: HashMap::new(),
+            disconnect_consumer_futures: HashMap::new(),
+            get_message_futures: HashMap::new(),
+            quarantine_futures: HashMap::new(),
+            get_partitions_futures: HashMap::new(),
+            list_topics_futures: HashMap::new(),
+            flow_futures: HashMap::new(),
+            deliveries: HashMap::new(),
+            consumers: ConsumerMap::new(),
+            partitions: PartitionCache::new(DEFAULT_PARTITION_CACHE_DURATION),
+            flush_wakers: Vec::new(),
+            publish_callback: None,
+            receiver_count: 0,
+        }
+    }
+
+    /// The number of requests that are waiting for a response from the broker
+    pub(crate) fn pending_count(self: &Self) -> usize {
+        self.publish_futures.len()
+            + self.publish_batch_futures.len()
+            + self.consume_futures.len()
+            + self.ack_futures.len()
+            + self.nack_futures.len()
+            + self.join_group_futures.len()
+            + self.leave_group_futures.len()
+            + self.disconnect_consumer_futures.len()
+            + self.get_message_futures.len()
+            + self.quarantine_futures.len()
+            + self.get_partitions_futures.len()
+            + self.list_topics_futures.len()
+            + self.flow_futures.len()
+    }
+
+    /// Fails every request that is waiting for a response, and tells subscribers that nothing
+    /// more will be pushed to them. Returns the results of the failed publish requests, so
+    /// that they can be passed to the publish callback
+    pub(crate) fn fail_pending(self: &mut Self) -> Vec<ClientResult<PublishResult>> {
+        let publish_results = self
+            .publish_futures
+            .keys()
+            .map(|_| Err(ClientError::ConnectionLost))
+            .collect();
+        fail(&mut self.publish_futures);
+        fail(&mut self.publish_batch_futures);
+        fail(&mut self.consume_futures);
+        fail(&mut self.ack_futures);
+        fail(&mut self.nack_futures);
+        fail(&mut self.join_group_futures);
+        fail(&mut self.leave_group_futures);
+        fail(&mut self.disconnect_consumer_futures);
+        fail(&mut self.get_message_futures);
+        fail(&mut self.quarantine_futures);
+        fail(&mut self.get_partitions_futures);
+        fail(&mut self.list_topics_futures);
+        fail(&mut self.flow_futures);
+        for (_, sender) in self.deliveries.drain() {
+            let _ = sender.send(Err(ClientError::ConnectionLost));
+        }
+        self.wake_flushes();
+        publish_results
+    }
+
+    /// Wakes any flush futures so that they can check if they are complete
+    pub(crate) fn wake_flushes(self: &mut Self) {
+        for waker in self.flush_wakers.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+fn fail<T>(futures: &mut FutureMap<T>) {
+    for (_, state) in futures.drain() {
+        let mut state = state.lock().unwrap();
+        state.result = Some(Err(ClientError::ConnectionLost));
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl FlushFuture {
+    pub(crate) fn new(futures: &Arc<Mutex<FutureHashMap>>, deadline: Instant) -> Self {
+        Self {
+            futures: futures.clone(),
+            deadline,
+        }
+    }
+}
+
+impl Future for FlushFuture {
+    type Output = ClientResult<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut futures = self.futures.lock().unwrap();
+        if futures.pending_count() == 0 {
+            Poll::Ready(Ok(()))
+        } else if futures.receiver_count == 0 {
+            Poll::Ready(Err(ClientError::NotConnected))
+        } else if Instant::now() >= self.deadline {
+            Poll::Ready(Err(ClientError::Timeout))
+        } else {
+            futures.flush_wakers.push(cx.waker().clone());
+            Poll::Pending
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{FlushFuture, FutureHashMap, FutureResponse, FutureResponseState};
+    use crate::api_bin::contracts::{ClientError, ClientResult, PublishResult};
+    use pulsar_rust_net::bin_serialization::RequestId;
+    use std::{
+        future::Future,
+        pin::Pin,
+        sync::{Arc, Mutex},
+        task::{Context, Poll, Waker},
+        time::{Duration, Instant},
+    };
+
+    fn publish_future(
+        futures: &Arc<Mutex<FutureHashMap>>,
+        request_id: RequestId,
+    ) -> FutureResponse<PublishResult> {
+        let state = Arc::new(Mutex::new(FutureResponseState::new()));
+        let future = FutureResponse::new(&state, futures, request_id, |futures| {
+            &mut futures.publish_futures
+        })
+        .with_publish_callback();
+        futures
+            .lock()
+            .unwrap()
+            .publish_futures
+            .insert(request_id, state);
+        future
+    }
+
+    #[test]
+    fn should_remove_request_when_future_times_out() {
+        let futures = Arc::new(Mutex::new(FutureHashMap::new()));
+        let timed_out = publish_future(&futures, 1);
+        let _waiting = publish_future(&futures, 2);
+
+        match timed_out.wait(Duration::from_millis(10)) {
+            Err(ClientError::Timeout) => {}
+            _ => panic!("Publish should time out"),
+        }
+
+        let futures = futures.lock().unwrap();
+        assert!(!futures.publish_futures.contains_key(&1));
+        assert!(futures.publish_futures.contains_key(&2));
+    }
+
+    #[test]
+    fn should_fail_flush_when_no_responses_can_arrive() {
+        let futures = Arc::new(Mutex::new(FutureHashMap::new()));
+        let _waiting = publish_future(&futures, 1);
+        let flush = || FlushFuture::new(&futures, Instant::now() + Duration::from_secs(60));
+
+        futures.lock().unwrap().receiver_count = 1;
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(Pin::new(&mut flush()).poll(&mut cx).is_pending());
+
+        futures.lock().unwrap().receiver_count = 0;
+        match Pin::new(&mut flush()).poll(&mut cx) {
+            Poll::Ready(Err(ClientError::NotConnected)) => {}
+            _ => panic!("Flush should fail once the receiver has stopped"),
+        }
+    }
+
+    #[test]
+    fn should_remove_request_when_future_is_dropped() {
+        let futures = Arc::new(Mutex::new(FutureHashMap::new()));
+        let future = publish_future(&futures, 1);
+        assert_eq!(futures.lock().unwrap().pending_count(), 1);
+
+        drop(future);
+        assert_eq!(futures.lock().unwrap().pending_count(), 0);
+    }
+
+    #[test]
+    fn should_keep_publish_request_for_callback_when_future_is_dropped() {
+        let futures = Arc::new(Mutex::new(FutureHashMap::new()));
+        futures.lock().unwrap().publish_callback =
+            Some(Arc::new(|_: &ClientResult<PublishResult>| {}));
+
+        drop(publish_future(&futures, 1));
+        assert!(futures.lock().unwrap().publish_futures.contains_key(&1));
+    }
+}
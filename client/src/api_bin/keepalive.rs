@@ -0,0 +1,150 @@
+/*
+Detects connections to the broker that stopped working without being closed, for example when
+the broker's host loses power or a network device drops the connection without telling either
+end. The TCP connection looks healthy, so without this the client only finds out when requests
+never get a response.
+
+The receiver thread pings the broker each time the interval elapses, and considers the
+connection lost if the broker does not answer within the timeout. The broker answers pings
+without doing any other work, so a busy broker still answers them. A ping that can not be sent
+because the send queue is full is treated as unanswered, because a queue that stays full for
+the whole timeout is not being sent to the broker.
+*/
+
+use std::{
+    sync::mpsc::SyncSender,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use log::warn;
+use pulsar_rust_net::{
+    bin_serialization::{ContractSerializer, Request, RequestId, RequestPayload},
+    contracts::v1,
+};
+
+use super::contracts::ClientMessage;
+
+#[derive(Clone, Copy)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub(crate) struct KeepaliveOptions {
+    /// How long to wait after the broker answers a ping before pinging it again
+    pub interval: Duration,
+
+    /// How long to wait for the broker to answer a ping
+    pub timeout: Duration,
+}
+
+pub(crate) struct Keepalive {
+    options: KeepaliveOptions,
+    sender: SyncSender<ClientMessage>,
+    next_request_id: RequestId,
+    last_pong: Instant,
+
+    /// The ping that the broker has not answered yet, and when it was sent
+    awaiting: Option<(RequestId, Instant)>,
+}
+
+impl Keepalive {
+    pub(crate) fn new(options: KeepaliveOptions, sender: SyncSender<ClientMessage>) -> Self {
+        Self {
+            options,
+            sender,
+            next_request_id: 1,
+            last_pong: Instant::now(),
+            awaiting: None,
+        }
+    }
+
+    /// Pings the broker if the interval elapsed since it last answered. Returns false if the
+    /// broker did not answer a ping within the timeout
+    pub(crate) fn check(self: &mut Self, serializer: &ContractSerializer) -> bool {
+        let now = Instant::now();
+        match self.awaiting {
+            Some((_, sent)) => now.duration_since(sent) < self.options.timeout,
+            None => {
+                if now.duration_since(self.last_pong) >= self.options.interval {
+                    self.ping(serializer, now);
+                }
+                true
+            }
+        }
+    }
+
+    /// Records the broker's answer to a ping
+    pub(crate) fn pong(self: &mut Self, request_id: RequestId) {
+        if matches!(self.awaiting, Some((awaiting_id, _)) if awaiting_id == request_id) {
+            self.awaiting = None;
+            self.last_pong = Instant::now();
+        }
+    }
+
+    fn ping(self: &mut Self, serializer: &ContractSerializer, now: Instant) {
+        let request_id = self.next_request_id;
+        self.next_request_id = request_id.wrapping_add(1);
+        self.awaiting = Some((request_id, now));
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let request = Request::new(
+            request_id,
+            RequestPayload::V1Ping(v1::requests::Ping { timestamp }),
+        );
+        // A ping that could not be queued is left to time out like one that was not answered
+        match serializer.serialize_request(&request) {
+            Ok(message) => {
+                let _ = self.sender.try_send(message);
+            }
+            Err(err) => warn!("Keepalive: Failed to serialize ping. {err:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Keepalive, KeepaliveOptions};
+    use pulsar_rust_net::{
+        bin_serialization::{ContractSerializer, RequestPayload},
+        sockets::buffer_pool::BufferPool,
+    };
+    use std::{
+        sync::{mpsc::sync_channel, Arc},
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn should_fail_when_a_ping_is_not_answered_within_the_timeout() {
+        let serializer = ContractSerializer::new(&Arc::new(BufferPool::new()));
+        let (sender, receiver) = sync_channel(10);
+        let mut keepalive = Keepalive::new(
+            KeepaliveOptions {
+                interval: Duration::from_millis(20),
+                timeout: Duration::from_millis(50),
+            },
+            sender,
+        );
+
+        // Nothing is sent until the interval elapses
+        assert!(keepalive.check(&serializer));
+        assert!(receiver.try_recv().is_err());
+
+        thread::sleep(Duration::from_millis(30));
+        assert!(keepalive.check(&serializer));
+        let ping = serializer
+            .deserialize_request(receiver.try_recv().unwrap())
+            .unwrap();
+        assert!(matches!(ping.payload, RequestPayload::V1Ping(_)));
+
+        // Answering the ping keeps the connection alive
+        keepalive.pong(ping.request_id);
+        thread::sleep(Duration::from_millis(60));
+        assert!(keepalive.check(&serializer));
+
+        // The next ping is not answered
+        assert!(receiver.try_recv().is_ok());
+        thread::sleep(Duration::from_millis(60));
+        assert!(!keepalive.check(&serializer));
+    }
+}
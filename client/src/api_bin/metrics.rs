@@ -0,0 +1,37 @@
+/*
+Counts events in the client library that applications may want to report on their own
+dashboards. The client does not send these anywhere itself.
+*/
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(Default)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct ClientMetrics {
+    serialize_error_count: AtomicUsize,
+    deserialize_error_count: AtomicUsize,
+}
+
+impl ClientMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of requests that could not be serialized for sending to the broker
+    pub fn serialize_error_count(self: &Self) -> usize {
+        self.serialize_error_count.load(Ordering::Relaxed)
+    }
+
+    /// The number of responses received from the broker that could not be deserialized
+    pub fn deserialize_error_count(self: &Self) -> usize {
+        self.deserialize_error_count.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn incr_serialize_errors(self: &Self) {
+        self.serialize_error_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn incr_deserialize_errors(self: &Self) {
+        self.deserialize_error_count.fetch_add(1, Ordering::Relaxed);
+    }
+}
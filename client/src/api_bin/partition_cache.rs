@@ -0,0 +1,154 @@
+/*
+Chooses the partition to publish each message to by hashing the message key, so that all
+of the messages with the same key are published to the same partition and are delivered in
+order. The hash is FNV-1a, which is stable across processes, platforms and versions of this
+library, so that different publishers agree on the partition for each key.
+
+The partitions of each topic are fetched from the broker the first time they are needed, and
+cached. Partitions can be added to a topic while the broker is running, so cached partitions
+expire after a while and are fetched again. The blocking client also drops them straight
+away when the broker rejects a message because it does not own the partition it was
+published to.
+*/
+
+use pulsar_rust_net::data_types::{PartitionId, TopicId};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// How long the partitions of a topic are cached before they are fetched from the broker again
+pub(crate) const DEFAULT_PARTITION_CACHE_DURATION: Duration = Duration::from_secs(60);
+
+struct CachedPartitions {
+    partition_ids: Vec<PartitionId>,
+    expires: Instant,
+}
+
+pub(crate) struct PartitionCache {
+    topics: HashMap<TopicId, CachedPartitions>,
+    cache_duration: Duration,
+}
+
+impl PartitionCache {
+    pub(crate) fn new(cache_duration: Duration) -> Self {
+        Self {
+            topics: HashMap::new(),
+            cache_duration,
+        }
+    }
+
+    pub(crate) fn set_cache_duration(self: &mut Self, cache_duration: Duration) {
+        self.cache_duration = cache_duration;
+    }
+
+    /// Returns the partition that a message with this key should be published to, or `None`
+    /// if the partitions of the topic have not been fetched from the broker, or have expired
+    pub(crate) fn partition_for_key(
+        self: &Self,
+        topic_id: TopicId,
+        key: &str,
+    ) -> Option<PartitionId> {
+        let cached = self.topics.get(&topic_id)?;
+        if cached.expires <= Instant::now() {
+            return None;
+        }
+        choose_partition(&cached.partition_ids, key)
+    }
+
+    /// Caches the partitions of a topic, replacing any that were cached before, and returns
+    /// the partition ids in the order that is used to choose partitions
+    pub(crate) fn insert(
+        self: &mut Self,
+        topic_id: TopicId,
+        mut partition_ids: Vec<PartitionId>,
+    ) -> Vec<PartitionId> {
+        partition_ids.sort_unstable();
+        partition_ids.dedup();
+        self.topics.insert(
+            topic_id,
+            CachedPartitions {
+                partition_ids: partition_ids.clone(),
+                expires: Instant::now() + self.cache_duration,
+            },
+        );
+        partition_ids
+    }
+
+    /// Drops the cached partitions of a topic, so that they are fetched again before the next
+    /// message is published to the topic
+    pub(crate) fn invalidate(self: &mut Self, topic_id: TopicId) {
+        self.topics.remove(&topic_id);
+    }
+}
+
+/// Chooses a partition for a message key. The partition ids must be sorted so that every
+/// publisher chooses the same partition for the same key
+pub(crate) fn choose_partition(partition_ids: &[PartitionId], key: &str) -> Option<PartitionId> {
+    if partition_ids.is_empty() {
+        return None;
+    }
+    let index = hash_key(key) % partition_ids.len() as u64;
+    Some(partition_ids[index as usize])
+}
+
+/// 64 bit FNV-1a hash of the bytes of the message key
+fn hash_key(key: &str) -> u64 {
+    key.bytes().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{choose_partition, hash_key, PartitionCache};
+    use std::{collections::HashSet, thread, time::Duration};
+
+    #[test]
+    fn should_hash_keys_with_fnv1a() {
+        assert_eq!(hash_key(""), 0xcbf29ce484222325);
+        assert_eq!(hash_key("a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(hash_key("foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn should_choose_the_same_partition_for_the_same_key() {
+        let partition_ids = vec![1, 2, 3, 4];
+        for key in ["order-1", "order-2", "customer-99", ""] {
+            assert_eq!(
+                choose_partition(&partition_ids, key),
+                choose_partition(&partition_ids, key)
+            );
+        }
+        assert_eq!(choose_partition(&[], "order-1"), None);
+    }
+
+    #[test]
+    fn should_spread_keys_across_partitions() {
+        let partition_ids = vec![1, 2, 3, 4];
+        let chosen: HashSet<_> = (0..100)
+            .filter_map(|i| choose_partition(&partition_ids, &format!("key-{i}")))
+            .collect();
+        assert_eq!(chosen.len(), 4);
+    }
+
+    #[test]
+    fn should_expire_cached_partitions() {
+        let mut cache = PartitionCache::new(Duration::from_millis(50));
+        assert_eq!(cache.partition_for_key(1, "key"), None);
+
+        assert_eq!(cache.insert(1, vec![3, 1, 2]), vec![1, 2, 3]);
+        let partition_id = cache.partition_for_key(1, "key");
+        assert_eq!(partition_id, choose_partition(&[1, 2, 3], "key"));
+
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(cache.partition_for_key(1, "key"), None);
+
+        cache.insert(1, vec![1, 2]);
+        cache.invalidate(1);
+        assert_eq!(cache.partition_for_key(1, "key"), None);
+    }
+}
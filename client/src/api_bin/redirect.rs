@@ -0,0 +1,146 @@
+/*
+When a request is sent to a broker that does not own the partition, the broker responds with
+the authority of the broker that does. The client opens a connection to that broker and sends
+the request again. These connections are kept, so that the next request for the same partition
+only pays for the failed attempt, not for connecting again.
+
+Each client that is created for a redirect is allowed one less redirect than the client that
+created it. Two brokers that both claim the other owns a partition would otherwise bounce the
+request between them forever. With the default of one redirect, the client connected to the
+second broker returns the error instead of following it.
+*/
+
+use super::contracts::{ClientError, ClientResult};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// The number of times a request is sent to another broker when the broker that received it
+/// does not own the partition
+pub(crate) const DEFAULT_MAX_REDIRECTS: usize = 1;
+
+/// Creates and connects a client for a broker, allowing it the number of redirects passed
+type Connector<C> = Box<dyn Fn(&str, usize) -> ClientResult<C> + Send + Sync>;
+
+pub(crate) struct Redirects<C> {
+    max_redirects: usize,
+    connector: Option<Connector<C>>,
+    clients: Mutex<HashMap<String, Arc<C>>>,
+}
+
+impl<C> Redirects<C> {
+    /// Redirects that are never followed, for clients that have not connected yet
+    pub(crate) fn none() -> Self {
+        Self {
+            max_redirects: 0,
+            connector: None,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn new<F>(max_redirects: usize, connector: F) -> Self
+    where
+        F: Fn(&str, usize) -> ClientResult<C> + Send + Sync + 'static,
+    {
+        Self {
+            max_redirects,
+            connector: Some(Box::new(connector)),
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// True if requests that fail with `ClientError::IncorrectNode` are sent again
+    pub(crate) fn enabled(self: &Self) -> bool {
+        self.max_redirects > 0 && self.connector.is_some()
+    }
+
+    /// Returns the authority of the broker to send the request to, if the result is an
+    /// incorrect node error that should be followed
+    pub(crate) fn target<T>(self: &Self, result: &ClientResult<T>) -> Option<String> {
+        match result {
+            Err(ClientError::IncorrectNode(Some(authority))) if self.enabled() => {
+                Some(authority.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the client that is connected to a broker, connecting to it the first time
+    pub(crate) fn client(self: &Self, authority: &str) -> ClientResult<Arc<C>> {
+        let connector = match &self.connector {
+            Some(connector) => connector,
+            None => return Err(ClientError::NotConnected),
+        };
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(client) = clients.get(authority) {
+            return Ok(client.clone());
+        }
+        let client = Arc::new(connector(authority, self.max_redirects.saturating_sub(1))?);
+        clients.insert(authority.to_owned(), client.clone());
+        Ok(client)
+    }
+
+    /// Removes the clients for all of the brokers that requests were redirected to, returning
+    /// the ones that are not in use elsewhere so that they can be disconnected
+    pub(crate) fn drain(self: &Self) -> Vec<C> {
+        self.clients
+            .lock()
+            .unwrap()
+            .drain()
+            .filter_map(|(_, client)| Arc::try_unwrap(client).ok())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Redirects;
+    use crate::api_bin::contracts::{ClientError, ClientResult};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    fn incorrect_node(authority: &str) -> ClientResult<()> {
+        Err(ClientError::IncorrectNode(Some(authority.to_owned())))
+    }
+
+    #[test]
+    fn should_connect_once_to_each_broker_with_one_less_redirect() {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+        let redirects = Redirects::new(2, move |authority: &str, max_redirects| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            Ok((authority.to_owned(), max_redirects))
+        });
+
+        let target = redirects.target(&incorrect_node("127.0.0.1:8001")).unwrap();
+        assert_eq!(target, "127.0.0.1:8001");
+
+        let client = redirects.client(&target).unwrap();
+        assert_eq!(*client, ("127.0.0.1:8001".to_owned(), 1));
+        redirects.client(&target).unwrap();
+        redirects.client("127.0.0.2:8001").unwrap();
+        assert_eq!(connections.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn should_not_follow_redirects_when_none_are_left() {
+        let redirects = Redirects::new(0, |authority: &str, _| Ok(authority.to_owned()));
+        assert!(redirects
+            .target(&incorrect_node("127.0.0.1:8001"))
+            .is_none());
+
+        let redirects = Redirects::<String>::none();
+        assert!(redirects
+            .target(&incorrect_node("127.0.0.1:8001"))
+            .is_none());
+
+        // Brokers that do not say which node owns the partition can not be followed
+        let redirects = Redirects::new(1, |authority: &str, _| Ok(authority.to_owned()));
+        assert!(redirects
+            .target(&Err::<(), _>(ClientError::IncorrectNode(None)))
+            .is_none());
+    }
+}
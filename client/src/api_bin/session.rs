@@ -0,0 +1,235 @@
+/*
+A session is a lightweight logical client that shares the connection of an async client.
+Applications with many producers and consumers can open a session for each one rather than
+opening a connection for each. Requests are tagged with the session id so that the broker
+can attribute them to the session, and responses are routed back to the future that was
+returned to the session using the request id.
+*/
+
+use super::{
+    async_client::Client,
+    contracts::{
+        AckResult, ClientResult, ConsumeResult, DisconnectConsumerResult, FlowResult,
+        JoinGroupResult, LeaveGroupResult, Message, MessageHeaders, NackResult, PublishItem,
+        PublishResult, QuarantineResult,
+    },
+    future_response::FutureResponse,
+};
+use pulsar_rust_net::{
+    bin_serialization::SessionId,
+    data_types::{
+        ConsumerId, CreditCount, MessageCount, Priority, SubscriptionId, Timestamp, TopicId,
+    },
+};
+use std::{collections::HashMap, sync::Arc};
+
+#[derive(Clone)]
+pub struct Session {
+    client: Arc<Client>,
+    session_id: SessionId,
+}
+
+impl Session {
+    pub(crate) fn new(client: &Arc<Client>, session_id: SessionId) -> Self {
+        Self {
+            client: client.clone(),
+            session_id,
+        }
+    }
+
+    pub fn session_id(self: &Self) -> SessionId {
+        self.session_id
+    }
+
+    /// Asynchronously publishes a message, returning a future that will complete when a response is received from the broker
+    pub fn publish(
+        self: &Self,
+        topic_id: TopicId,
+        key: Option<String>,
+        timestamp: Option<Timestamp>,
+        attributes: HashMap<String, String>,
+    ) -> ClientResult<FutureResponse<PublishResult>> {
+        self.client.publish_in_session(
+            self.session_id,
+            topic_id,
+            key,
+            timestamp,
+            Priority::default(),
+            MessageHeaders::default(),
+            attributes,
+        )
+    }
+
+    /// Asynchronously publishes a message that will be delivered to consumers ahead of
+    /// messages with a lower priority
+    pub fn publish_with_priority(
+        self: &Self,
+        topic_id: TopicId,
+        key: Option<String>,
+        timestamp: Option<Timestamp>,
+        priority: Priority,
+        attributes: HashMap<String, String>,
+    ) -> ClientResult<FutureResponse<PublishResult>> {
+        self.client.publish_in_session(
+            self.session_id,
+            topic_id,
+            key,
+            timestamp,
+            priority,
+            MessageHeaders::default(),
+            attributes,
+        )
+    }
+
+    /// Asynchronously publishes several messages in one request. The future completes with a
+    /// result for each message, in the order that they were passed
+    pub fn publish_batch(
+        self: &Self,
+        topic_id: TopicId,
+        items: Vec<PublishItem>,
+    ) -> ClientResult<FutureResponse<Vec<ClientResult<PublishResult>>>> {
+        self.client
+            .publish_batch_in_session(self.session_id, topic_id, items)
+    }
+
+    /// Asynchronously consumes messages. If no consumer id is passed, then the consumer
+    /// id previously allocated by the broker for this subscription in this session is used
+    pub fn consume(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        consumer_id: &Option<ConsumerId>,
+        max_messages: MessageCount,
+    ) -> ClientResult<FutureResponse<ConsumeResult>> {
+        self.client.consume_in_session(
+            self.session_id,
+            topic_id,
+            subscription_id,
+            consumer_id,
+            max_messages,
+        )
+    }
+
+    /// The consumer id that the broker allocated for a subscription in this session, if any
+    pub fn consumer_id(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+    ) -> Option<ConsumerId> {
+        self.client
+            .consumer_id_in_session(self.session_id, topic_id, subscription_id)
+    }
+
+    /// Asynchronously grants more credits to a consumer that subscribed in this session, so
+    /// that the broker can push more messages to it
+    pub fn flow(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+        credits: CreditCount,
+    ) -> ClientResult<FutureResponse<FlowResult>> {
+        self.client.flow_in_session(
+            self.session_id,
+            topic_id,
+            subscription_id,
+            consumer_id,
+            credits,
+        )
+    }
+
+    /// Asynchronously acknowledges a message
+    pub fn ack(
+        self: &Self,
+        message_ref_key: &str,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+    ) -> ClientResult<FutureResponse<AckResult>> {
+        self.client.ack_in_session(
+            self.session_id,
+            message_ref_key,
+            subscription_id,
+            consumer_id,
+        )
+    }
+
+    /// Asynchronously negatively acknowledges a message
+    pub fn nack(
+        self: &Self,
+        message_ref_key: &str,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+    ) -> ClientResult<FutureResponse<NackResult>> {
+        self.client.nack_in_session(
+            self.session_id,
+            message_ref_key,
+            subscription_id,
+            consumer_id,
+        )
+    }
+
+    /// Asynchronously quarantines a message that can not be processed, so that it is not
+    /// redelivered
+    pub fn quarantine(
+        self: &Self,
+        message_ref_key: &str,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+        reason: &str,
+    ) -> ClientResult<FutureResponse<QuarantineResult>> {
+        self.client.quarantine_in_session(
+            self.session_id,
+            message_ref_key,
+            subscription_id,
+            consumer_id,
+            reason,
+        )
+    }
+
+    /// Asynchronously fetches a message by its ack key without affecting its delivery
+    pub fn get_message(
+        self: &Self,
+        message_ref_key: &str,
+    ) -> ClientResult<FutureResponse<Message>> {
+        self.client
+            .get_message_in_session(self.session_id, message_ref_key)
+    }
+
+    /// Asynchronously joins a consumer group. Subsequent calls to `consume` on the group's
+    /// subscription in this session will consume as the group member that the broker allocated
+    pub fn join_group(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        group_name: &str,
+    ) -> ClientResult<FutureResponse<JoinGroupResult>> {
+        self.client
+            .join_group_in_session(self.session_id, topic_id, subscription_id, group_name)
+    }
+
+    /// Asynchronously leaves a consumer group
+    pub fn leave_group(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+    ) -> ClientResult<FutureResponse<LeaveGroupResult>> {
+        self.client
+            .leave_group_in_session(self.session_id, topic_id, subscription_id, consumer_id)
+    }
+
+    /// Asynchronously disconnects a consumer from a subscription
+    pub fn disconnect_consumer(
+        self: &Self,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        consumer_id: ConsumerId,
+    ) -> ClientResult<FutureResponse<DisconnectConsumerResult>> {
+        self.client.disconnect_consumer_in_session(
+            self.session_id,
+            topic_id,
+            subscription_id,
+            consumer_id,
+        )
+    }
+}
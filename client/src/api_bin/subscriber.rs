@@ -0,0 +1,97 @@
+/*
+A subscriber has messages pushed to it by the broker as they become available, instead of
+polling for them with consume requests. The subscriber starts with a number of credits, and
+each message that the broker pushes uses one of them. The broker stops pushing messages when
+the credits run out, so the subscriber grants more with `flow` as it processes the messages
+that it was sent. This keeps latency low without letting a slow subscriber be flooded.
+
+Each subscriber has its own session, so that the consumer id that the broker allocates is
+remembered for the subscriber, and is used to ack the messages that were pushed to it.
+*/
+
+use super::{
+    contracts::{ClientError, ClientResult, ConsumeResult, DisconnectConsumerResult, FlowResult},
+    future_response::FutureResponse,
+    session::Session,
+};
+use pulsar_rust_net::data_types::{ConsumerId, CreditCount, SubscriptionId, TopicId};
+use std::{
+    sync::mpsc::{Receiver, RecvTimeoutError},
+    time::Duration,
+};
+
+pub struct Subscriber {
+    session: Session,
+    topic_id: TopicId,
+    subscription_id: SubscriptionId,
+    deliveries: Receiver<ClientResult<ConsumeResult>>,
+}
+
+impl Subscriber {
+    pub(crate) fn new(
+        session: Session,
+        topic_id: TopicId,
+        subscription_id: SubscriptionId,
+        deliveries: Receiver<ClientResult<ConsumeResult>>,
+    ) -> Self {
+        Self {
+            session,
+            topic_id,
+            subscription_id,
+            deliveries,
+        }
+    }
+
+    /// The session that the subscriber was registered in. Use the session to ack and nack
+    /// the messages that were pushed to the subscriber
+    pub fn session(self: &Self) -> &Session {
+        &self.session
+    }
+
+    pub fn topic_id(self: &Self) -> TopicId {
+        self.topic_id
+    }
+
+    pub fn subscription_id(self: &Self) -> SubscriptionId {
+        self.subscription_id
+    }
+
+    /// The consumer id that the broker allocated to this subscriber. This is None until the
+    /// broker responds to the subscribe request
+    pub fn consumer_id(self: &Self) -> Option<ConsumerId> {
+        self.session
+            .consumer_id(self.topic_id, self.subscription_id)
+    }
+
+    /// The channel that messages pushed by the broker are received on. An error is received
+    /// if the broker could not subscribe, or stopped pushing messages to this subscriber
+    pub fn deliveries(self: &Self) -> &Receiver<ClientResult<ConsumeResult>> {
+        &self.deliveries
+    }
+
+    /// Blocks until the broker pushes more messages, or the timeout elapses
+    pub fn recv_timeout(self: &Self, timeout: Duration) -> ClientResult<ConsumeResult> {
+        match self.deliveries.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => Err(ClientError::Timeout),
+            Err(RecvTimeoutError::Disconnected) => Err(ClientError::NotConnected),
+        }
+    }
+
+    /// Asynchronously grants the subscriber more credits, so that the broker can push more
+    /// messages to it. Fails with `ClientError::NoData` until the broker has responded to the
+    /// subscribe request
+    pub fn flow(self: &Self, credits: CreditCount) -> ClientResult<FutureResponse<FlowResult>> {
+        let consumer_id = self.consumer_id().ok_or(ClientError::NoData)?;
+        self.session
+            .flow(self.topic_id, self.subscription_id, consumer_id, credits)
+    }
+
+    /// Asynchronously disconnects the subscriber, so that the broker stops pushing messages
+    /// to it, and redelivers any that were not acked to other consumers
+    pub fn close(self: &Self) -> ClientResult<FutureResponse<DisconnectConsumerResult>> {
+        let consumer_id = self.consumer_id().ok_or(ClientError::NoData)?;
+        self.session
+            .disconnect_consumer(self.topic_id, self.subscription_id, consumer_id)
+    }
+}
@@ -0,0 +1,99 @@
+/*
+Chooses which versions of the API contracts to offer the broker when connecting. The broker
+replies with the version it will use for the rest of the connection, or an error if it does
+not support any of the versions offered.
+
+Older brokers only accept a range whose minimum version is one that they support, so with
+fallback enabled the client offers each version in its range on its own, highest first,
+before giving up.
+*/
+
+use pulsar_rust_net::{
+    bin_serialization::CompressionScheme,
+    contracts::v1::{
+        requests::NegotiateVersion,
+        responses::{NegotiateVersionResult, RequestOutcome, Response},
+    },
+    data_types::ContractVersionNumber,
+    error_codes::ERROR_CODE_NO_COMPATIBLE_VERSION,
+};
+
+use super::contracts::{ClientError, ClientResult};
+
+/// The lowest version of the API contracts that this client library can use
+pub const MIN_SUPPORTED_VERSION: ContractVersionNumber = 1;
+
+/// The highest version of the API contracts that this client library can use
+pub const MAX_SUPPORTED_VERSION: ContractVersionNumber = 2;
+
+#[derive(Clone, Copy)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct VersionOptions {
+    /// The lowest API version the client will accept
+    pub min_version: ContractVersionNumber,
+
+    /// The highest API version the client will accept
+    pub max_version: ContractVersionNumber,
+
+    /// When the broker rejects the whole range, offer each version in the range on its own
+    /// from highest to lowest before failing with `ClientError::IncompatibleVersion`
+    pub fallback: bool,
+}
+
+impl Default for VersionOptions {
+    fn default() -> Self {
+        Self {
+            min_version: MIN_SUPPORTED_VERSION,
+            max_version: MAX_SUPPORTED_VERSION,
+            fallback: false,
+        }
+    }
+}
+
+impl VersionOptions {
+    /// The version negotiation requests to send to the broker, in the order they are tried.
+    /// Each request also offers the compression scheme, unless it is `None`
+    pub(crate) fn attempts(self: &Self, compression: CompressionScheme) -> Vec<NegotiateVersion> {
+        let offered_compression = match compression {
+            CompressionScheme::None => Vec::new(),
+            compression => vec![compression],
+        };
+        let mut attempts = vec![NegotiateVersion {
+            min_version: self.min_version,
+            max_version: self.max_version,
+            compression: offered_compression.clone(),
+        }];
+        if self.fallback && self.max_version > self.min_version {
+            for version in (self.min_version..=self.max_version).rev() {
+                attempts.push(NegotiateVersion {
+                    min_version: version,
+                    max_version: version,
+                    compression: offered_compression.clone(),
+                });
+            }
+        }
+        attempts
+    }
+
+    /// Interprets the broker's response to a version negotiation request
+    pub(crate) fn negotiated_version(
+        self: &Self,
+        response: Response<NegotiateVersionResult>,
+    ) -> ClientResult<ContractVersionNumber> {
+        match response.outcome {
+            RequestOutcome::Success => match response.data {
+                Some(data)
+                    if data.version >= self.min_version && data.version <= self.max_version =>
+                {
+                    Ok(data.version)
+                }
+                Some(_) => Err(ClientError::IncompatibleVersion),
+                None => Err(ClientError::NoData),
+            },
+            RequestOutcome::Error(_, ERROR_CODE_NO_COMPATIBLE_VERSION) => {
+                Err(ClientError::IncompatibleVersion)
+            }
+            outcome => Err(ClientError::BadOutcome(outcome)),
+        }
+    }
+}
@@ -1,16 +1,37 @@
 mod api_bin;
 
-pub use pulsar_rust_net::{data_types::*, error_codes::*, sockets::buffer_pool::BufferPool};
+pub use pulsar_rust_net::{
+    bin_serialization::CompressionScheme,
+    data_types::*,
+    error_codes::*,
+    sockets::{buffer_pool::BufferPool, tls::ConnectionSecurity},
+};
 
 pub mod contracts {
     pub use crate::api_bin::contracts::*;
 }
 
+pub mod metrics {
+    pub use crate::api_bin::metrics::*;
+}
+
+pub mod versions {
+    pub use crate::api_bin::versions::*;
+}
+
+pub mod codec {
+    pub use crate::api_bin::codec::*;
+}
+
 pub mod non_blocking {
-    pub use crate::api_bin::future_response::FutureResponse;
+    pub use crate::api_bin::future_response::{FlushFuture, FutureResponse};
     pub use crate::api_bin::async_client::*;
+    pub use crate::api_bin::consume_stream::ConsumeStream;
+    pub use crate::api_bin::session::Session;
+    pub use crate::api_bin::subscriber::Subscriber;
 }
 
 pub mod blocking {
     pub use crate::api_bin::blocking_client::*;
+    pub use crate::api_bin::cluster_client::ClusterClient;
 }
@@ -1,9 +1,19 @@
 /*
 For APIs that use binary serialization, wraps the request and response in an envelope that contains
+- The version of the envelope layout, so that future changes to the framing can be detected
 - The type that was serialized
 - The version of the data contract
 - A unique ID for the request so that responses can be matched up with requests
+- For requests only, the ID of the logical client session that made the request. This allows
+  many lightweight clients to share one connection. Responses are routed back to the session
+  using the request ID
 Serializes and deserilizes these messages to byte arrays for transmission over Tcp
+
+The body that follows the envelope header can be compressed. A compressed body starts with a
+marker byte that is never written by MessagePack, followed by the compression scheme, so an
+uncompressed body is unchanged from earlier versions. Peers agree on a compression scheme
+when they negotiate the API version, and neither end compresses messages until then, so
+peers that do not support compression can still communicate.
 */
 
 use rmp_serde::{Deserializer, Serializer};
@@ -11,7 +21,7 @@ use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
 use crate::{
-    contracts::v1::{self},
+    contracts::{v1, v2},
     sockets::{buffer_pool::BufferPool, MessageLength},
 };
 
@@ -26,6 +36,9 @@ pub enum SerializeError {
 #[derive(Debug, PartialEq)]
 pub enum DeserializeError {
     Error { msg: String },
+
+    /// The envelope was written with a version of the protocol that this end does not understand
+    UnsupportedProtocolVersion { version: ProtocolVersion },
 }
 
 #[derive(Deserialize, Serialize)]
@@ -36,11 +49,25 @@ pub enum RequestPayload {
     V1Consume(v1::requests::Consume),
     V1Ack(v1::requests::Ack),
     V1Nack(v1::requests::Nack),
+    V1JoinGroup(v1::requests::JoinGroup),
+    V1LeaveGroup(v1::requests::LeaveGroup),
+    V1GetMessage(v1::requests::GetMessage),
+    V1Quarantine(v1::requests::Quarantine),
+    V1GetPartitions(v1::requests::GetPartitions),
+    V1PublishBatch(v1::requests::PublishBatch),
+    V1ListTopics(v1::requests::ListTopics),
+    V1DisconnectConsumer(v1::requests::DisconnectConsumer),
+    V1Subscribe(v1::requests::Subscribe),
+    V1Flow(v1::requests::Flow),
+    V1Ping(v1::requests::Ping),
+    V2Publish(v2::requests::Publish),
+    V1GetTopicPartitionMap(v1::requests::GetTopicPartitionMap),
 }
 
 #[cfg_attr(debug_assertions, derive(Debug))]
 pub struct Request {
     pub request_id: RequestId,
+    pub session_id: SessionId,
     pub payload: RequestPayload,
 }
 
@@ -52,6 +79,23 @@ pub enum ResponsePayload {
     V1Consume(v1::responses::Response<v1::responses::ConsumeResult>),
     V1Ack(v1::responses::Response<v1::responses::AckResult>),
     V1Nack(v1::responses::Response<v1::responses::NackResult>),
+    V1JoinGroup(v1::responses::Response<v1::responses::JoinGroupResult>),
+    V1LeaveGroup(v1::responses::Response<v1::responses::LeaveGroupResult>),
+    V1GetMessage(v1::responses::Response<v1::responses::Message>),
+    V1Quarantine(v1::responses::Response<v1::responses::QuarantineResult>),
+    V1GetPartitions(v1::responses::Response<v1::responses::PartitionList>),
+    V1PublishBatch(v1::responses::Response<v1::responses::PublishBatchResult>),
+    V1ListTopics(v1::responses::Response<v1::responses::TopicList>),
+    V1DisconnectConsumer(v1::responses::Response<v1::responses::DisconnectConsumerResult>),
+    V1Subscribe(v1::responses::Response<v1::responses::SubscribeResult>),
+    V1Flow(v1::responses::Response<v1::responses::FlowResult>),
+
+    /// Messages that were pushed to a consumer without a request. The request id is the id
+    /// of the subscribe request that registered the consumer
+    V1Delivery(v1::responses::Response<v1::responses::ConsumeResult>),
+
+    V1Pong(v1::responses::Response<v1::responses::Pong>),
+    V1GetTopicPartitionMap(v1::responses::Response<v1::responses::TopicPartitionMap>),
 }
 
 #[cfg_attr(debug_assertions, derive(Debug))]
@@ -62,20 +106,121 @@ pub struct BrokerResponse {
 
 pub struct ContractSerializer {
     buffer_pool: Arc<BufferPool>,
+    compression: CompressionScheme,
+}
+
+/// How the body of a message is compressed
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub enum CompressionScheme {
+    #[default]
+    None,
+    Lz4,
 }
 
+impl CompressionScheme {
+    /// Chooses the compression to use from the schemes that the peer offered, in the order
+    /// of the peer's preference
+    pub fn choose(offered: &[CompressionScheme]) -> CompressionScheme {
+        offered.first().copied().unwrap_or_default()
+    }
+
+    /// The length that a message body takes up once it is compressed with this scheme
+    pub fn compressed_size(self: Self, body: &[u8]) -> usize {
+        match self {
+            CompressionScheme::None => body.len(),
+            CompressionScheme::Lz4 => {
+                COMPRESSED_BODY_HEADER_SIZE + lz4_flex::compress_prepend_size(body).len()
+            }
+        }
+    }
+
+    fn id(self: Self) -> u8 {
+        match self {
+            CompressionScheme::None => 0,
+            CompressionScheme::Lz4 => 1,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(CompressionScheme::None),
+            1 => Some(CompressionScheme::Lz4),
+            _ => None,
+        }
+    }
+}
+
+pub type ProtocolVersion = u8;
 type MessageTypeId = u16;
 pub type RequestId = u32;
+pub type SessionId = u32;
+
+/// The session used by clients that do not multiplex sessions over their connection
+pub const DEFAULT_SESSION_ID: SessionId = 0;
+
+/// The version of the envelope layout written by this build. Envelopes with any other
+/// version are rejected rather than guessed at
+pub const PROTOCOL_VERSION: ProtocolVersion = 1;
 
 const BUFFER_CAPACITY: MessageLength = 2048;
+const PROTOCOL_VERSION_SIZE: usize = size_of::<ProtocolVersion>();
 const MESSAGE_TYPE_SIZE: usize = size_of::<MessageTypeId>();
 const REQUEST_ID_SIZE: usize = size_of::<RequestId>();
+const SESSION_ID_SIZE: usize = size_of::<SessionId>();
+const RESPONSE_HEADER_SIZE: usize = PROTOCOL_VERSION_SIZE + MESSAGE_TYPE_SIZE + REQUEST_ID_SIZE;
+const REQUEST_HEADER_SIZE: usize = RESPONSE_HEADER_SIZE + SESSION_ID_SIZE;
+
+/// Marks a compressed body. MessagePack never uses this byte, so it can not be confused with
+/// the first byte of an uncompressed body
+const COMPRESSED_BODY_MARKER: u8 = 0xc1;
+const COMPRESSED_BODY_HEADER_SIZE: usize = 2;
+const UNCOMPRESSED_SIZE_SIZE: usize = size_of::<u32>();
+
+/// Compressed bodies that claim to be larger than this are rejected without allocating memory
+const MAX_UNCOMPRESSED_SIZE: usize = 16 * 1024 * 1024;
 
 const NEGOTIATE_VERSION_MESSAGE_TYPE_ID: MessageTypeId = 1;
 const V1_PUBLISH_MESSAGE_TYPE_ID: MessageTypeId = 2;
 const V1_CONSUMER_MESSAGE_TYPE_ID: MessageTypeId = 3;
 const V1_ACK_MESSAGE_TYPE_ID: MessageTypeId = 4;
 const V1_NACK_MESSAGE_TYPE_ID: MessageTypeId = 5;
+const V1_JOIN_GROUP_MESSAGE_TYPE_ID: MessageTypeId = 6;
+const V1_LEAVE_GROUP_MESSAGE_TYPE_ID: MessageTypeId = 7;
+const V1_GET_MESSAGE_MESSAGE_TYPE_ID: MessageTypeId = 8;
+const V1_QUARANTINE_MESSAGE_TYPE_ID: MessageTypeId = 9;
+const V1_GET_PARTITIONS_MESSAGE_TYPE_ID: MessageTypeId = 10;
+const V1_PUBLISH_BATCH_MESSAGE_TYPE_ID: MessageTypeId = 11;
+const V1_LIST_TOPICS_MESSAGE_TYPE_ID: MessageTypeId = 12;
+const V1_DISCONNECT_CONSUMER_MESSAGE_TYPE_ID: MessageTypeId = 13;
+const V1_SUBSCRIBE_MESSAGE_TYPE_ID: MessageTypeId = 14;
+const V1_FLOW_MESSAGE_TYPE_ID: MessageTypeId = 15;
+const V1_DELIVERY_MESSAGE_TYPE_ID: MessageTypeId = 16;
+const V1_PING_MESSAGE_TYPE_ID: MessageTypeId = 17;
+const V2_PUBLISH_MESSAGE_TYPE_ID: MessageTypeId = 18;
+const V1_GET_TOPIC_PARTITION_MAP_MESSAGE_TYPE_ID: MessageTypeId = 19;
+
+impl Request {
+    pub fn new(request_id: RequestId, payload: RequestPayload) -> Self {
+        Self {
+            request_id,
+            session_id: DEFAULT_SESSION_ID,
+            payload,
+        }
+    }
+
+    pub fn for_session(
+        request_id: RequestId,
+        session_id: SessionId,
+        payload: RequestPayload,
+    ) -> Self {
+        Self {
+            request_id,
+            session_id,
+            payload,
+        }
+    }
+}
 
 impl BrokerResponse {
     pub fn new(request_id: RequestId, payload: ResponsePayload) -> Self {
@@ -97,134 +242,584 @@ impl ContractSerializer {
     pub fn new(buffer_pool: &Arc<BufferPool>) -> Self {
         Self {
             buffer_pool: buffer_pool.clone(),
+            compression: CompressionScheme::None,
         }
     }
 
+    /// Sets the compression of requests and responses that are serialized after this. Only
+    /// set this to a scheme that was agreed with the peer when negotiating the API version
+    pub fn set_compression(self: &mut Self, compression: CompressionScheme) {
+        self.compression = compression;
+    }
+
     pub fn serialize_request(self: &Self, request: &Request) -> SerializeResult {
+        let buffer = self.serialize_request_body(request)?;
+        self.compress(buffer, REQUEST_HEADER_SIZE, self.compression)
+    }
+
+    pub fn serialize_response(self: &Self, response: &BrokerResponse) -> SerializeResult {
+        self.serialize_response_with_compression(response, self.compression)
+    }
+
+    /// Serializes a response with the compression that the request was sent with, so that
+    /// a server can reply to each peer with the compression that was agreed with that peer
+    pub fn serialize_response_with_compression(
+        self: &Self,
+        response: &BrokerResponse,
+        compression: CompressionScheme,
+    ) -> SerializeResult {
+        let buffer = self.serialize_response_body(response)?;
+        self.compress(buffer, RESPONSE_HEADER_SIZE, compression)
+    }
+
+    /// Returns the compression scheme of a serialized request without deserializing it
+    pub fn request_compression(self: &Self, buffer: &[u8]) -> CompressionScheme {
+        match buffer.get(REQUEST_HEADER_SIZE..REQUEST_HEADER_SIZE + COMPRESSED_BODY_HEADER_SIZE) {
+            Some(&[COMPRESSED_BODY_MARKER, id]) => {
+                CompressionScheme::from_id(id).unwrap_or_default()
+            }
+            _ => CompressionScheme::None,
+        }
+    }
+
+    fn serialize_request_body(self: &Self, request: &Request) -> SerializeResult {
         match &request.payload {
             RequestPayload::NegotiateVersion(negotiate_version) => self.serialize_entity(
                 negotiate_version,
                 NEGOTIATE_VERSION_MESSAGE_TYPE_ID,
                 request.request_id,
+                Some(request.session_id),
+            ),
+            RequestPayload::V1Publish(publish) => self.serialize_entity(
+                publish,
+                V1_PUBLISH_MESSAGE_TYPE_ID,
+                request.request_id,
+                Some(request.session_id),
+            ),
+            RequestPayload::V1Consume(consumer) => self.serialize_entity(
+                consumer,
+                V1_CONSUMER_MESSAGE_TYPE_ID,
+                request.request_id,
+                Some(request.session_id),
+            ),
+            RequestPayload::V1Ack(ack) => self.serialize_entity(
+                ack,
+                V1_ACK_MESSAGE_TYPE_ID,
+                request.request_id,
+                Some(request.session_id),
+            ),
+            RequestPayload::V1Nack(nack) => self.serialize_entity(
+                nack,
+                V1_NACK_MESSAGE_TYPE_ID,
+                request.request_id,
+                Some(request.session_id),
+            ),
+            RequestPayload::V1JoinGroup(join_group) => self.serialize_entity(
+                join_group,
+                V1_JOIN_GROUP_MESSAGE_TYPE_ID,
+                request.request_id,
+                Some(request.session_id),
+            ),
+            RequestPayload::V1LeaveGroup(leave_group) => self.serialize_entity(
+                leave_group,
+                V1_LEAVE_GROUP_MESSAGE_TYPE_ID,
+                request.request_id,
+                Some(request.session_id),
+            ),
+            RequestPayload::V1GetMessage(get_message) => self.serialize_entity(
+                get_message,
+                V1_GET_MESSAGE_MESSAGE_TYPE_ID,
+                request.request_id,
+                Some(request.session_id),
+            ),
+            RequestPayload::V1Quarantine(quarantine) => self.serialize_entity(
+                quarantine,
+                V1_QUARANTINE_MESSAGE_TYPE_ID,
+                request.request_id,
+                Some(request.session_id),
+            ),
+            RequestPayload::V1GetPartitions(get_partitions) => self.serialize_entity(
+                get_partitions,
+                V1_GET_PARTITIONS_MESSAGE_TYPE_ID,
+                request.request_id,
+                Some(request.session_id),
+            ),
+            RequestPayload::V1PublishBatch(publish_batch) => self.serialize_entity(
+                publish_batch,
+                V1_PUBLISH_BATCH_MESSAGE_TYPE_ID,
+                request.request_id,
+                Some(request.session_id),
+            ),
+            RequestPayload::V1ListTopics(list_topics) => self.serialize_entity(
+                list_topics,
+                V1_LIST_TOPICS_MESSAGE_TYPE_ID,
+                request.request_id,
+                Some(request.session_id),
+            ),
+            RequestPayload::V1DisconnectConsumer(disconnect_consumer) => self.serialize_entity(
+                disconnect_consumer,
+                V1_DISCONNECT_CONSUMER_MESSAGE_TYPE_ID,
+                request.request_id,
+                Some(request.session_id),
+            ),
+            RequestPayload::V1Subscribe(subscribe) => self.serialize_entity(
+                subscribe,
+                V1_SUBSCRIBE_MESSAGE_TYPE_ID,
+                request.request_id,
+                Some(request.session_id),
+            ),
+            RequestPayload::V1Flow(flow) => self.serialize_entity(
+                flow,
+                V1_FLOW_MESSAGE_TYPE_ID,
+                request.request_id,
+                Some(request.session_id),
+            ),
+            RequestPayload::V1Ping(ping) => self.serialize_entity(
+                ping,
+                V1_PING_MESSAGE_TYPE_ID,
+                request.request_id,
+                Some(request.session_id),
+            ),
+            RequestPayload::V2Publish(publish) => self.serialize_entity(
+                publish,
+                V2_PUBLISH_MESSAGE_TYPE_ID,
+                request.request_id,
+                Some(request.session_id),
+            ),
+            RequestPayload::V1GetTopicPartitionMap(get_map) => self.serialize_entity(
+                get_map,
+                V1_GET_TOPIC_PARTITION_MAP_MESSAGE_TYPE_ID,
+                request.request_id,
+                Some(request.session_id),
             ),
-            RequestPayload::V1Publish(publish) => {
-                self.serialize_entity(publish, V1_PUBLISH_MESSAGE_TYPE_ID, request.request_id)
-            }
-            RequestPayload::V1Consume(consumer) => {
-                self.serialize_entity(consumer, V1_CONSUMER_MESSAGE_TYPE_ID, request.request_id)
-            }
-            RequestPayload::V1Ack(ack) => {
-                self.serialize_entity(ack, V1_ACK_MESSAGE_TYPE_ID, request.request_id)
-            }
-            RequestPayload::V1Nack(nack) => {
-                self.serialize_entity(nack, V1_NACK_MESSAGE_TYPE_ID, request.request_id)
-            }
         }
     }
 
-    pub fn serialize_response(self: &Self, response: &BrokerResponse) -> SerializeResult {
+    fn serialize_response_body(self: &Self, response: &BrokerResponse) -> SerializeResult {
         match &response.payload {
             ResponsePayload::NegotiateVersion(negotiate_version) => self.serialize_entity(
                 negotiate_version,
                 NEGOTIATE_VERSION_MESSAGE_TYPE_ID,
                 response.request_id,
+                None,
+            ),
+            ResponsePayload::V1Publish(publish) => self.serialize_entity(
+                publish,
+                V1_PUBLISH_MESSAGE_TYPE_ID,
+                response.request_id,
+                None,
+            ),
+            ResponsePayload::V1Consume(consumer) => self.serialize_entity(
+                consumer,
+                V1_CONSUMER_MESSAGE_TYPE_ID,
+                response.request_id,
+                None,
             ),
-            ResponsePayload::V1Publish(publish) => {
-                self.serialize_entity(publish, V1_PUBLISH_MESSAGE_TYPE_ID, response.request_id)
-            }
-            ResponsePayload::V1Consume(consumer) => {
-                self.serialize_entity(consumer, V1_CONSUMER_MESSAGE_TYPE_ID, response.request_id)
-            }
             ResponsePayload::V1Ack(ack) => {
-                self.serialize_entity(ack, V1_ACK_MESSAGE_TYPE_ID, response.request_id)
+                self.serialize_entity(ack, V1_ACK_MESSAGE_TYPE_ID, response.request_id, None)
             }
             ResponsePayload::V1Nack(nack) => {
-                self.serialize_entity(nack, V1_NACK_MESSAGE_TYPE_ID, response.request_id)
+                self.serialize_entity(nack, V1_NACK_MESSAGE_TYPE_ID, response.request_id, None)
             }
+            ResponsePayload::V1JoinGroup(join_group) => self.serialize_entity(
+                join_group,
+                V1_JOIN_GROUP_MESSAGE_TYPE_ID,
+                response.request_id,
+                None,
+            ),
+            ResponsePayload::V1LeaveGroup(leave_group) => self.serialize_entity(
+                leave_group,
+                V1_LEAVE_GROUP_MESSAGE_TYPE_ID,
+                response.request_id,
+                None,
+            ),
+            ResponsePayload::V1GetMessage(message) => self.serialize_entity(
+                message,
+                V1_GET_MESSAGE_MESSAGE_TYPE_ID,
+                response.request_id,
+                None,
+            ),
+            ResponsePayload::V1Quarantine(quarantine) => self.serialize_entity(
+                quarantine,
+                V1_QUARANTINE_MESSAGE_TYPE_ID,
+                response.request_id,
+                None,
+            ),
+            ResponsePayload::V1GetPartitions(partitions) => self.serialize_entity(
+                partitions,
+                V1_GET_PARTITIONS_MESSAGE_TYPE_ID,
+                response.request_id,
+                None,
+            ),
+            ResponsePayload::V1PublishBatch(publish_batch) => self.serialize_entity(
+                publish_batch,
+                V1_PUBLISH_BATCH_MESSAGE_TYPE_ID,
+                response.request_id,
+                None,
+            ),
+            ResponsePayload::V1ListTopics(topics) => self.serialize_entity(
+                topics,
+                V1_LIST_TOPICS_MESSAGE_TYPE_ID,
+                response.request_id,
+                None,
+            ),
+            ResponsePayload::V1DisconnectConsumer(disconnect_consumer) => self.serialize_entity(
+                disconnect_consumer,
+                V1_DISCONNECT_CONSUMER_MESSAGE_TYPE_ID,
+                response.request_id,
+                None,
+            ),
+            ResponsePayload::V1Subscribe(subscribe) => self.serialize_entity(
+                subscribe,
+                V1_SUBSCRIBE_MESSAGE_TYPE_ID,
+                response.request_id,
+                None,
+            ),
+            ResponsePayload::V1Flow(flow) => {
+                self.serialize_entity(flow, V1_FLOW_MESSAGE_TYPE_ID, response.request_id, None)
+            }
+            ResponsePayload::V1Delivery(delivery) => self.serialize_entity(
+                delivery,
+                V1_DELIVERY_MESSAGE_TYPE_ID,
+                response.request_id,
+                None,
+            ),
+            ResponsePayload::V1Pong(pong) => {
+                self.serialize_entity(pong, V1_PING_MESSAGE_TYPE_ID, response.request_id, None)
+            }
+            ResponsePayload::V1GetTopicPartitionMap(map) => self.serialize_entity(
+                map,
+                V1_GET_TOPIC_PARTITION_MAP_MESSAGE_TYPE_ID,
+                response.request_id,
+                None,
+            ),
         }
     }
 
-    pub fn deserialize_request(self: &Self, buffer: Vec<u8>) -> DeserializeResult<Request> {
-        let (message_type, request_id) = self.extract_metadata(&buffer);
+    pub fn deserialize_request(
+        self: &Self,
+        buffer: impl AsRef<[u8]>,
+    ) -> DeserializeResult<Request> {
+        let buffer = buffer.as_ref();
+        self.check_header(buffer, REQUEST_HEADER_SIZE)?;
+        let (message_type, request_id) = self.extract_metadata(buffer);
+        let session_id = self.extract_session_id(buffer);
 
         match message_type {
             NEGOTIATE_VERSION_MESSAGE_TYPE_ID => {
-                match self.deserialize_entity::<v1::requests::NegotiateVersion>(buffer) {
+                match self.deserialize_entity::<v1::requests::NegotiateVersion>(
+                    buffer,
+                    REQUEST_HEADER_SIZE,
+                ) {
                     Ok(negotiate_version) => Ok(Request {
                         request_id,
+                        session_id,
                         payload: RequestPayload::NegotiateVersion(negotiate_version),
                     }),
                     Err(err) => Err(err),
                 }
             }
             V1_PUBLISH_MESSAGE_TYPE_ID => {
-                match self.deserialize_entity::<v1::requests::Publish>(buffer) {
+                match self.deserialize_entity::<v1::requests::Publish>(buffer, REQUEST_HEADER_SIZE)
+                {
                     Ok(publish) => Ok(Request {
                         request_id,
+                        session_id,
                         payload: RequestPayload::V1Publish(publish),
                     }),
                     Err(err) => Err(err),
                 }
             }
             V1_CONSUMER_MESSAGE_TYPE_ID => {
-                match self.deserialize_entity::<v1::requests::Consume>(buffer) {
+                match self.deserialize_entity::<v1::requests::Consume>(buffer, REQUEST_HEADER_SIZE)
+                {
                     Ok(consumer) => Ok(Request {
                         request_id,
+                        session_id,
                         payload: RequestPayload::V1Consume(consumer),
                     }),
                     Err(err) => Err(err),
                 }
             }
-            V1_ACK_MESSAGE_TYPE_ID => match self.deserialize_entity::<v1::requests::Ack>(buffer) {
-                Ok(ack) => Ok(Request {
-                    request_id,
-                    payload: RequestPayload::V1Ack(ack),
-                }),
-                Err(err) => Err(err),
-            },
+            V1_ACK_MESSAGE_TYPE_ID => {
+                match self.deserialize_entity::<v1::requests::Ack>(buffer, REQUEST_HEADER_SIZE) {
+                    Ok(ack) => Ok(Request {
+                        request_id,
+                        session_id,
+                        payload: RequestPayload::V1Ack(ack),
+                    }),
+                    Err(err) => Err(err),
+                }
+            }
             V1_NACK_MESSAGE_TYPE_ID => {
-                match self.deserialize_entity::<v1::requests::Nack>(buffer) {
+                match self.deserialize_entity::<v1::requests::Nack>(buffer, REQUEST_HEADER_SIZE) {
                     Ok(nack) => Ok(Request {
                         request_id,
+                        session_id,
                         payload: RequestPayload::V1Nack(nack),
                     }),
                     Err(err) => Err(err),
                 }
             }
-            _ => panic!("Unsupported message type {message_type} in request"),
+            V1_JOIN_GROUP_MESSAGE_TYPE_ID => {
+                match self
+                    .deserialize_entity::<v1::requests::JoinGroup>(buffer, REQUEST_HEADER_SIZE)
+                {
+                    Ok(join_group) => Ok(Request {
+                        request_id,
+                        session_id,
+                        payload: RequestPayload::V1JoinGroup(join_group),
+                    }),
+                    Err(err) => Err(err),
+                }
+            }
+            V1_LEAVE_GROUP_MESSAGE_TYPE_ID => {
+                match self
+                    .deserialize_entity::<v1::requests::LeaveGroup>(buffer, REQUEST_HEADER_SIZE)
+                {
+                    Ok(leave_group) => Ok(Request {
+                        request_id,
+                        session_id,
+                        payload: RequestPayload::V1LeaveGroup(leave_group),
+                    }),
+                    Err(err) => Err(err),
+                }
+            }
+            V1_GET_MESSAGE_MESSAGE_TYPE_ID => {
+                match self
+                    .deserialize_entity::<v1::requests::GetMessage>(buffer, REQUEST_HEADER_SIZE)
+                {
+                    Ok(get_message) => Ok(Request {
+                        request_id,
+                        session_id,
+                        payload: RequestPayload::V1GetMessage(get_message),
+                    }),
+                    Err(err) => Err(err),
+                }
+            }
+            V1_QUARANTINE_MESSAGE_TYPE_ID => {
+                match self
+                    .deserialize_entity::<v1::requests::Quarantine>(buffer, REQUEST_HEADER_SIZE)
+                {
+                    Ok(quarantine) => Ok(Request {
+                        request_id,
+                        session_id,
+                        payload: RequestPayload::V1Quarantine(quarantine),
+                    }),
+                    Err(err) => Err(err),
+                }
+            }
+            V1_GET_PARTITIONS_MESSAGE_TYPE_ID => {
+                match self
+                    .deserialize_entity::<v1::requests::GetPartitions>(buffer, REQUEST_HEADER_SIZE)
+                {
+                    Ok(get_partitions) => Ok(Request {
+                        request_id,
+                        session_id,
+                        payload: RequestPayload::V1GetPartitions(get_partitions),
+                    }),
+                    Err(err) => Err(err),
+                }
+            }
+            V1_PUBLISH_BATCH_MESSAGE_TYPE_ID => {
+                match self
+                    .deserialize_entity::<v1::requests::PublishBatch>(buffer, REQUEST_HEADER_SIZE)
+                {
+                    Ok(publish_batch) => Ok(Request {
+                        request_id,
+                        session_id,
+                        payload: RequestPayload::V1PublishBatch(publish_batch),
+                    }),
+                    Err(err) => Err(err),
+                }
+            }
+            V1_LIST_TOPICS_MESSAGE_TYPE_ID => {
+                match self
+                    .deserialize_entity::<v1::requests::ListTopics>(buffer, REQUEST_HEADER_SIZE)
+                {
+                    Ok(list_topics) => Ok(Request {
+                        request_id,
+                        session_id,
+                        payload: RequestPayload::V1ListTopics(list_topics),
+                    }),
+                    Err(err) => Err(err),
+                }
+            }
+            V1_DISCONNECT_CONSUMER_MESSAGE_TYPE_ID => {
+                match self.deserialize_entity::<v1::requests::DisconnectConsumer>(
+                    buffer,
+                    REQUEST_HEADER_SIZE,
+                ) {
+                    Ok(disconnect_consumer) => Ok(Request {
+                        request_id,
+                        session_id,
+                        payload: RequestPayload::V1DisconnectConsumer(disconnect_consumer),
+                    }),
+                    Err(err) => Err(err),
+                }
+            }
+            V1_SUBSCRIBE_MESSAGE_TYPE_ID => {
+                match self
+                    .deserialize_entity::<v1::requests::Subscribe>(buffer, REQUEST_HEADER_SIZE)
+                {
+                    Ok(subscribe) => Ok(Request {
+                        request_id,
+                        session_id,
+                        payload: RequestPayload::V1Subscribe(subscribe),
+                    }),
+                    Err(err) => Err(err),
+                }
+            }
+            V1_FLOW_MESSAGE_TYPE_ID => {
+                match self.deserialize_entity::<v1::requests::Flow>(buffer, REQUEST_HEADER_SIZE) {
+                    Ok(flow) => Ok(Request {
+                        request_id,
+                        session_id,
+                        payload: RequestPayload::V1Flow(flow),
+                    }),
+                    Err(err) => Err(err),
+                }
+            }
+            V1_PING_MESSAGE_TYPE_ID => {
+                match self.deserialize_entity::<v1::requests::Ping>(buffer, REQUEST_HEADER_SIZE) {
+                    Ok(ping) => Ok(Request {
+                        request_id,
+                        session_id,
+                        payload: RequestPayload::V1Ping(ping),
+                    }),
+                    Err(err) => Err(err),
+                }
+            }
+            V2_PUBLISH_MESSAGE_TYPE_ID => {
+                match self.deserialize_entity::<v2::requests::Publish>(buffer, REQUEST_HEADER_SIZE)
+                {
+                    Ok(publish) => Ok(Request {
+                        request_id,
+                        session_id,
+                        payload: RequestPayload::V2Publish(publish),
+                    }),
+                    Err(err) => Err(err),
+                }
+            }
+            V1_GET_TOPIC_PARTITION_MAP_MESSAGE_TYPE_ID => {
+                match self.deserialize_entity::<v1::requests::GetTopicPartitionMap>(
+                    buffer,
+                    REQUEST_HEADER_SIZE,
+                ) {
+                    Ok(get_map) => Ok(Request {
+                        request_id,
+                        session_id,
+                        payload: RequestPayload::V1GetTopicPartitionMap(get_map),
+                    }),
+                    Err(err) => Err(err),
+                }
+            }
+            _ => Err(DeserializeError::Error {
+                msg: format!("Unsupported message type {message_type} in request"),
+            }),
         }
     }
 
-    pub fn deserialize_response(self: &Self, buffer: Vec<u8>) -> DeserializeResult<BrokerResponse> {
-        let (message_type, request_id) = self.extract_metadata(&buffer);
+    pub fn deserialize_response(
+        self: &Self,
+        buffer: impl AsRef<[u8]>,
+    ) -> DeserializeResult<BrokerResponse> {
+        let buffer = buffer.as_ref();
+        self.check_header(buffer, RESPONSE_HEADER_SIZE)?;
+        let (message_type, request_id) = self.extract_metadata(buffer);
 
         match message_type {
             NEGOTIATE_VERSION_MESSAGE_TYPE_ID =>
-                match self.deserialize_entity::<v1::responses::Response<v1::responses::NegotiateVersionResult>>(buffer) {
+                match self.deserialize_entity::<v1::responses::Response<v1::responses::NegotiateVersionResult>>(buffer, RESPONSE_HEADER_SIZE) {
                     Ok(response) => Ok(BrokerResponse{ request_id, payload: ResponsePayload::NegotiateVersion(response) }),
                     Err(err) => Err(err),
                 }
             V1_PUBLISH_MESSAGE_TYPE_ID =>
-                match self.deserialize_entity::<v1::responses::Response<v1::responses::PublishResult>>(buffer) {
+                match self.deserialize_entity::<v1::responses::Response<v1::responses::PublishResult>>(buffer, RESPONSE_HEADER_SIZE) {
                     Ok(response) => Ok(BrokerResponse{ request_id, payload: ResponsePayload::V1Publish(response) }),
                     Err(err) => Err(err),
                 }
             V1_CONSUMER_MESSAGE_TYPE_ID =>
-                match self.deserialize_entity::<v1::responses::Response<v1::responses::ConsumeResult>>(buffer) {
+                match self.deserialize_entity::<v1::responses::Response<v1::responses::ConsumeResult>>(buffer, RESPONSE_HEADER_SIZE) {
                     Ok(response) => Ok(BrokerResponse{ request_id, payload: ResponsePayload::V1Consume(response) }),
                     Err(err) => Err(err),
                 }
             V1_ACK_MESSAGE_TYPE_ID =>
-                match self.deserialize_entity::<v1::responses::Response<v1::responses::AckResult>>(buffer) {
+                match self.deserialize_entity::<v1::responses::Response<v1::responses::AckResult>>(buffer, RESPONSE_HEADER_SIZE) {
                     Ok(response) => Ok(BrokerResponse{ request_id, payload: ResponsePayload::V1Ack(response) }),
                     Err(err) => Err(err),
                 }
             V1_NACK_MESSAGE_TYPE_ID =>
-                match self.deserialize_entity::<v1::responses::Response<v1::responses::NackResult>>(buffer) {
+                match self.deserialize_entity::<v1::responses::Response<v1::responses::NackResult>>(buffer, RESPONSE_HEADER_SIZE) {
                     Ok(response) => Ok(BrokerResponse{ request_id, payload: ResponsePayload::V1Nack(response) }),
                     Err(err) => Err(err),
                 }
-            _ => panic!("Unsupported message type {message_type} in response")
+            V1_JOIN_GROUP_MESSAGE_TYPE_ID =>
+                match self.deserialize_entity::<v1::responses::Response<v1::responses::JoinGroupResult>>(buffer, RESPONSE_HEADER_SIZE) {
+                    Ok(response) => Ok(BrokerResponse{ request_id, payload: ResponsePayload::V1JoinGroup(response) }),
+                    Err(err) => Err(err),
+                }
+            V1_LEAVE_GROUP_MESSAGE_TYPE_ID =>
+                match self.deserialize_entity::<v1::responses::Response<v1::responses::LeaveGroupResult>>(buffer, RESPONSE_HEADER_SIZE) {
+                    Ok(response) => Ok(BrokerResponse{ request_id, payload: ResponsePayload::V1LeaveGroup(response) }),
+                    Err(err) => Err(err),
+                }
+            V1_GET_MESSAGE_MESSAGE_TYPE_ID =>
+                match self.deserialize_entity::<v1::responses::Response<v1::responses::Message>>(buffer, RESPONSE_HEADER_SIZE) {
+                    Ok(response) => Ok(BrokerResponse{ request_id, payload: ResponsePayload::V1GetMessage(response) }),
+                    Err(err) => Err(err),
+                }
+            V1_QUARANTINE_MESSAGE_TYPE_ID =>
+                match self.deserialize_entity::<v1::responses::Response<v1::responses::QuarantineResult>>(buffer, RESPONSE_HEADER_SIZE) {
+                    Ok(response) => Ok(BrokerResponse{ request_id, payload: ResponsePayload::V1Quarantine(response) }),
+                    Err(err) => Err(err),
+                }
+            V1_GET_PARTITIONS_MESSAGE_TYPE_ID =>
+                match self.deserialize_entity::<v1::responses::Response<v1::responses::PartitionList>>(buffer, RESPONSE_HEADER_SIZE) {
+                    Ok(response) => Ok(BrokerResponse{ request_id, payload: ResponsePayload::V1GetPartitions(response) }),
+                    Err(err) => Err(err),
+                }
+            V1_PUBLISH_BATCH_MESSAGE_TYPE_ID =>
+                match self.deserialize_entity::<v1::responses::Response<v1::responses::PublishBatchResult>>(buffer, RESPONSE_HEADER_SIZE) {
+                    Ok(response) => Ok(BrokerResponse{ request_id, payload: ResponsePayload::V1PublishBatch(response) }),
+                    Err(err) => Err(err),
+                }
+            V1_LIST_TOPICS_MESSAGE_TYPE_ID =>
+                match self.deserialize_entity::<v1::responses::Response<v1::responses::TopicList>>(buffer, RESPONSE_HEADER_SIZE) {
+                    Ok(response) => Ok(BrokerResponse{ request_id, payload: ResponsePayload::V1ListTopics(response) }),
+                    Err(err) => Err(err),
+                }
+            V1_DISCONNECT_CONSUMER_MESSAGE_TYPE_ID =>
+                match self.deserialize_entity::<v1::responses::Response<v1::responses::DisconnectConsumerResult>>(buffer, RESPONSE_HEADER_SIZE) {
+                    Ok(response) => Ok(BrokerResponse{ request_id, payload: ResponsePayload::V1DisconnectConsumer(response) }),
+                    Err(err) => Err(err),
+                }
+            V1_SUBSCRIBE_MESSAGE_TYPE_ID =>
+                match self.deserialize_entity::<v1::responses::Response<v1::responses::SubscribeResult>>(buffer, RESPONSE_HEADER_SIZE) {
+                    Ok(response) => Ok(BrokerResponse{ request_id, payload: ResponsePayload::V1Subscribe(response) }),
+                    Err(err) => Err(err),
+                }
+            V1_FLOW_MESSAGE_TYPE_ID =>
+                match self.deserialize_entity::<v1::responses::Response<v1::responses::FlowResult>>(buffer, RESPONSE_HEADER_SIZE) {
+                    Ok(response) => Ok(BrokerResponse{ request_id, payload: ResponsePayload::V1Flow(response) }),
+                    Err(err) => Err(err),
+                }
+            V1_DELIVERY_MESSAGE_TYPE_ID =>
+                match self.deserialize_entity::<v1::responses::Response<v1::responses::ConsumeResult>>(buffer, RESPONSE_HEADER_SIZE) {
+                    Ok(response) => Ok(BrokerResponse{ request_id, payload: ResponsePayload::V1Delivery(response) }),
+                    Err(err) => Err(err),
+                }
+            V1_PING_MESSAGE_TYPE_ID =>
+                match self.deserialize_entity::<v1::responses::Response<v1::responses::Pong>>(buffer, RESPONSE_HEADER_SIZE) {
+                    Ok(response) => Ok(BrokerResponse{ request_id, payload: ResponsePayload::V1Pong(response) }),
+                    Err(err) => Err(err),
+                }
+            V1_GET_TOPIC_PARTITION_MAP_MESSAGE_TYPE_ID =>
+                match self.deserialize_entity::<v1::responses::Response<v1::responses::TopicPartitionMap>>(buffer, RESPONSE_HEADER_SIZE) {
+                    Ok(response) => Ok(BrokerResponse{ request_id, payload: ResponsePayload::V1GetTopicPartitionMap(response) }),
+                    Err(err) => Err(err),
+                }
+            _ => Err(DeserializeError::Error { msg: format!("Unsupported message type {message_type} in response") }),
         }
     }
 
@@ -233,10 +828,15 @@ impl ContractSerializer {
         entity: &T,
         message_type_id: MessageTypeId,
         request_id: RequestId,
+        session_id: Option<SessionId>,
     ) -> SerializeResult {
         let mut buffer = self.buffer_pool.get_with_capacity(0, BUFFER_CAPACITY);
+        buffer.extend_from_slice(&PROTOCOL_VERSION.to_le_bytes());
         buffer.extend_from_slice(&message_type_id.to_le_bytes());
         buffer.extend_from_slice(&request_id.to_le_bytes());
+        if let Some(session_id) = session_id {
+            buffer.extend_from_slice(&session_id.to_le_bytes());
+        }
         let mut serializer = Serializer::new(&mut buffer);
         match entity.serialize(&mut serializer) {
             Ok(_) => Ok(buffer),
@@ -246,36 +846,143 @@ impl ContractSerializer {
         }
     }
 
-    fn extract_metadata(self: &Self, buffer: &Vec<u8>) -> (MessageTypeId, RequestId) {
-        let message_type_id: MessageTypeId =
-            MessageTypeId::from_le_bytes(buffer[0..MESSAGE_TYPE_SIZE].try_into().unwrap());
+    /// Replaces the body that follows the header with a compressed body
+    fn compress(
+        self: &Self,
+        buffer: Vec<u8>,
+        header_size: usize,
+        compression: CompressionScheme,
+    ) -> SerializeResult {
+        let compressed_body = match compression {
+            CompressionScheme::None => return Ok(buffer),
+            CompressionScheme::Lz4 => lz4_flex::compress_prepend_size(&buffer[header_size..]),
+        };
+        let mut compressed = self.buffer_pool.get_with_capacity(0, BUFFER_CAPACITY);
+        compressed.extend_from_slice(&buffer[..header_size]);
+        compressed.push(COMPRESSED_BODY_MARKER);
+        compressed.push(compression.id());
+        compressed.extend_from_slice(&compressed_body);
+        self.buffer_pool.reuse(buffer);
+        Ok(compressed)
+    }
+
+    /// Reverses `compress`, returning the uncompressed body
+    fn decompress(self: &Self, body: &[u8]) -> DeserializeResult<Vec<u8>> {
+        if body.len() < COMPRESSED_BODY_HEADER_SIZE + UNCOMPRESSED_SIZE_SIZE {
+            return Err(DeserializeError::Error {
+                msg: String::from("Compressed body is truncated"),
+            });
+        }
+        let id = body[1];
+        let body = &body[COMPRESSED_BODY_HEADER_SIZE..];
+        match CompressionScheme::from_id(id) {
+            Some(CompressionScheme::Lz4) => {
+                let size =
+                    u32::from_le_bytes(body[..UNCOMPRESSED_SIZE_SIZE].try_into().unwrap()) as usize;
+                if size > MAX_UNCOMPRESSED_SIZE {
+                    return Err(DeserializeError::Error {
+                        msg: format!("Compressed body claims to expand to {size} bytes"),
+                    });
+                }
+                lz4_flex::decompress(&body[UNCOMPRESSED_SIZE_SIZE..], size).map_err(|err| {
+                    DeserializeError::Error {
+                        msg: format!("Failed to decompress body. {err}"),
+                    }
+                })
+            }
+            Some(CompressionScheme::None) => Ok(body.to_vec()),
+            None => Err(DeserializeError::Error {
+                msg: format!("Unsupported compression scheme {id}"),
+            }),
+        }
+    }
+
+    /// Checks that the envelope is complete and was written with a protocol version that
+    /// this end understands, before any other part of the header is interpreted
+    fn check_header(self: &Self, buffer: &[u8], header_size: usize) -> DeserializeResult<()> {
+        if buffer.len() < PROTOCOL_VERSION_SIZE {
+            return Err(DeserializeError::Error {
+                msg: String::from("Empty message has no protocol version"),
+            });
+        }
+        let version = ProtocolVersion::from_le_bytes(
+            buffer[0..PROTOCOL_VERSION_SIZE].try_into().unwrap(),
+        );
+        if version != PROTOCOL_VERSION {
+            return Err(DeserializeError::UnsupportedProtocolVersion { version });
+        }
+        if buffer.len() < header_size {
+            return Err(DeserializeError::Error {
+                msg: format!(
+                    "Message of {} bytes is shorter than the {header_size} byte header",
+                    buffer.len()
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// Reads the message type and request id. The header must have passed `check_header`,
+    /// which guarantees that the buffer is long enough
+    fn extract_metadata(self: &Self, buffer: &[u8]) -> (MessageTypeId, RequestId) {
+        let message_type_start = PROTOCOL_VERSION_SIZE;
+        let request_id_start = message_type_start + MESSAGE_TYPE_SIZE;
+        let message_type_id: MessageTypeId = MessageTypeId::from_le_bytes(
+            buffer[message_type_start..request_id_start]
+                .try_into()
+                .unwrap(),
+        );
         let request_id: RequestId = RequestId::from_le_bytes(
-            buffer[MESSAGE_TYPE_SIZE..MESSAGE_TYPE_SIZE + REQUEST_ID_SIZE]
+            buffer[request_id_start..request_id_start + REQUEST_ID_SIZE]
                 .try_into()
                 .unwrap(),
         );
         (message_type_id, request_id)
     }
 
-    fn deserialize_entity<'a, T>(self: &Self, buffer: Vec<u8>) -> DeserializeResult<T>
+    fn extract_session_id(self: &Self, buffer: &[u8]) -> SessionId {
+        SessionId::from_le_bytes(
+            buffer[RESPONSE_HEADER_SIZE..REQUEST_HEADER_SIZE]
+                .try_into()
+                .unwrap(),
+        )
+    }
+
+    fn deserialize_entity<'a, T>(
+        self: &Self,
+        buffer: &[u8],
+        header_size: usize,
+    ) -> DeserializeResult<T>
     where
         T: Deserialize<'a>,
     {
-        let mut deserializer = Deserializer::new(&buffer[MESSAGE_TYPE_SIZE + REQUEST_ID_SIZE..]);
-        let result = match Deserialize::deserialize(&mut deserializer) {
-            Ok(entity) => DeserializeResult::Ok(entity),
-            Err(err) => Err(DeserializeError::Error {
-                msg: format!("{err:?}"),
-            }),
-        };
-        self.buffer_pool.reuse(buffer);
-        result
+        let body = &buffer[header_size..];
+        if body.first() == Some(&COMPRESSED_BODY_MARKER) {
+            self.decompress(body)
+                .and_then(|body| deserialize_body(&body))
+        } else {
+            deserialize_body(body)
+        }
+    }
+}
+
+fn deserialize_body<'a, T>(body: &[u8]) -> DeserializeResult<T>
+where
+    T: Deserialize<'a>,
+{
+    let mut deserializer = Deserializer::new(body);
+    match Deserialize::deserialize(&mut deserializer) {
+        Ok(entity) => DeserializeResult::Ok(entity),
+        Err(err) => Err(DeserializeError::Error {
+            msg: format!("{err:?}"),
+        }),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
 
     #[test]
     fn should_serialize_request() {
@@ -284,10 +991,11 @@ mod tests {
         let request = v1::requests::NegotiateVersion {
             min_version,
             max_version,
+            compression: Vec::new(),
         };
 
         let mut buffer = Vec::new();
-        buffer.resize(MESSAGE_TYPE_SIZE + REQUEST_ID_SIZE, 0);
+        buffer.resize(REQUEST_HEADER_SIZE, 0);
         let mut serializer = Serializer::new(&mut buffer);
         request.serialize(&mut serializer).unwrap();
     }
@@ -295,10 +1003,13 @@ mod tests {
     #[test]
     fn should_serialize_response() {
         let version = 5;
-        let result = v1::responses::NegotiateVersionResult { version };
+        let result = v1::responses::NegotiateVersionResult {
+            version,
+            compression: CompressionScheme::None,
+        };
 
         let mut buffer = Vec::new();
-        buffer.resize(MESSAGE_TYPE_SIZE + REQUEST_ID_SIZE, 0);
+        buffer.resize(RESPONSE_HEADER_SIZE, 0);
         let mut serializer = Serializer::new(&mut buffer);
         result.serialize(&mut serializer).unwrap();
     }
@@ -309,28 +1020,36 @@ mod tests {
         let serializer = ContractSerializer::new(&Arc::new(buffer_pool));
 
         let request_id = 99;
+        let session_id = 7;
         let min_version = 4;
         let max_version = 10;
 
         let original_payload = v1::requests::NegotiateVersion {
             min_version,
             max_version,
+            compression: vec![CompressionScheme::Lz4],
         };
-        let original_request = Request {
+        let original_request = Request::for_session(
             request_id,
-            payload: RequestPayload::NegotiateVersion(original_payload),
-        };
+            session_id,
+            RequestPayload::NegotiateVersion(original_payload),
+        );
 
         let buffer = serializer.serialize_request(&original_request).unwrap();
 
         let deserialized_request = serializer.deserialize_request(buffer).unwrap();
 
         assert_eq!(original_request.request_id, deserialized_request.request_id);
+        assert_eq!(original_request.session_id, deserialized_request.session_id);
 
         if let RequestPayload::NegotiateVersion(deserialized_payload) = deserialized_request.payload
         {
             assert_eq!(min_version, deserialized_payload.min_version);
             assert_eq!(max_version, deserialized_payload.max_version);
+            assert_eq!(
+                vec![CompressionScheme::Lz4],
+                deserialized_payload.compression
+            );
         } else {
             panic!("Wrong type of payload")
         }
@@ -345,10 +1064,14 @@ mod tests {
         let version = 6;
         let outcome = v1::responses::RequestOutcome::Success;
 
-        let original_payload = v1::responses::NegotiateVersionResult { version };
+        let original_payload = v1::responses::NegotiateVersionResult {
+            version,
+            compression: CompressionScheme::Lz4,
+        };
         let original_payload_response = v1::responses::Response {
             outcome,
             data: Some(original_payload),
+            redirect: None,
         };
         let original_response = BrokerResponse {
             request_id,
@@ -375,6 +1098,7 @@ mod tests {
             }
             if let Some(data) = deserialized_payload.data {
                 assert_eq!(version, data.version);
+                assert_eq!(CompressionScheme::Lz4, data.compression);
             } else {
                 panic!("No data")
             }
@@ -382,4 +1106,298 @@ mod tests {
             panic!("Wrong type of payload")
         }
     }
+
+    #[test]
+    fn roundtrip_subscribe_and_delivery() {
+        let buffer_pool = BufferPool::new();
+        let serializer = ContractSerializer::new(&Arc::new(buffer_pool));
+
+        let request = Request::for_session(
+            21,
+            4,
+            RequestPayload::V1Subscribe(v1::requests::Subscribe {
+                topic_id: 1,
+                subscription_id: 2,
+                consumer_id: None,
+                credits: 500,
+            }),
+        );
+        let buffer = serializer.serialize_request(&request).unwrap();
+        match serializer.deserialize_request(buffer).unwrap().payload {
+            RequestPayload::V1Subscribe(subscribe) => {
+                assert_eq!(subscribe.credits, 500);
+                assert_eq!(subscribe.consumer_id, None);
+            }
+            _ => panic!("Wrong type of payload"),
+        }
+
+        // Deliveries are sent with the request id of the subscribe request
+        let delivery = BrokerResponse::new(
+            21,
+            ResponsePayload::V1Delivery(v1::responses::Response::success(
+                v1::responses::ConsumeResult {
+                    consumer_id: 3,
+                    messages: Vec::new(),
+                    remote_partitions: Vec::new(),
+                },
+            )),
+        );
+        let buffer = serializer.serialize_response(&delivery).unwrap();
+        let deserialized = serializer.deserialize_response(buffer).unwrap();
+        assert_eq!(deserialized.request_id, 21);
+        match deserialized.payload {
+            ResponsePayload::V1Delivery(response) => {
+                assert_eq!(response.data.unwrap().consumer_id, 3)
+            }
+            _ => panic!("Wrong type of payload"),
+        }
+    }
+
+    #[test]
+    fn roundtrip_ping_and_pong() {
+        let buffer_pool = BufferPool::new();
+        let serializer = ContractSerializer::new(&Arc::new(buffer_pool));
+
+        let request = Request::new(
+            22,
+            RequestPayload::V1Ping(v1::requests::Ping { timestamp: 1234 }),
+        );
+        let buffer = serializer.serialize_request(&request).unwrap();
+        match serializer.deserialize_request(buffer).unwrap().payload {
+            RequestPayload::V1Ping(ping) => assert_eq!(ping.timestamp, 1234),
+            _ => panic!("Wrong type of payload"),
+        }
+
+        let response = BrokerResponse::new(
+            22,
+            ResponsePayload::V1Pong(v1::responses::Response::success(v1::responses::Pong {
+                timestamp: 1234,
+            })),
+        );
+        let buffer = serializer.serialize_response(&response).unwrap();
+        match serializer.deserialize_response(buffer).unwrap().payload {
+            ResponsePayload::V1Pong(response) => assert_eq!(response.data.unwrap().timestamp, 1234),
+            _ => panic!("Wrong type of payload"),
+        }
+    }
+
+    #[test]
+    fn roundtrip_topic_partition_map() {
+        let buffer_pool = BufferPool::new();
+        let serializer = ContractSerializer::new(&Arc::new(buffer_pool));
+
+        let request = Request::new(
+            23,
+            RequestPayload::V1GetTopicPartitionMap(v1::requests::GetTopicPartitionMap {
+                topic_id: 7,
+            }),
+        );
+        let buffer = serializer.serialize_request(&request).unwrap();
+        match serializer.deserialize_request(buffer).unwrap().payload {
+            RequestPayload::V1GetTopicPartitionMap(get_map) => assert_eq!(get_map.topic_id, 7),
+            _ => panic!("Wrong type of payload"),
+        }
+
+        let map = v1::responses::TopicPartitionMap {
+            topic: v1::responses::TopicSummary {
+                topic_id: 7,
+                name: String::from("topic7"),
+                partition_count: 1,
+                subscription_count: 0,
+            },
+            partitions: vec![v1::responses::PartitionDetail {
+                topic_id: 7,
+                partition_id: 3,
+                ledgers: Vec::new(),
+                node_id: 2,
+            }],
+            nodes: vec![v1::responses::NodeDetail {
+                node_id: 2,
+                ip_address: String::from("10.0.0.2"),
+                admin_port: 8000,
+                pubsub_port: 8001,
+                sync_port: 8002,
+                ledgers: Vec::new(),
+            }],
+        };
+        let response = BrokerResponse::new(
+            23,
+            ResponsePayload::V1GetTopicPartitionMap(v1::responses::Response::success(map)),
+        );
+        let buffer = serializer.serialize_response(&response).unwrap();
+        match serializer.deserialize_response(buffer).unwrap().payload {
+            ResponsePayload::V1GetTopicPartitionMap(response) => {
+                let map = response.data.unwrap();
+                assert_eq!(map.partitions[0].node_id, 2);
+                assert_eq!(map.nodes[0].pubsub_port, 8001);
+            }
+            _ => panic!("Wrong type of payload"),
+        }
+    }
+
+    #[test]
+    fn roundtrip_v2_publish() {
+        let buffer_pool = BufferPool::new();
+        let serializer = ContractSerializer::new(&Arc::new(buffer_pool));
+
+        let request = Request::new(
+            24,
+            RequestPayload::V2Publish(v2::requests::Publish {
+                topic_id: 1,
+                partition_id: 2,
+                key: String::from("key"),
+                timestamp: None,
+                priority: None,
+                attributes: HashMap::new(),
+                ack_level: v1::requests::PublishAckLevel::default(),
+                headers: v1::requests::MessageHeaders::default(),
+                producer_id: None,
+                sequence_number: None,
+                deliver_after_millis: None,
+                deliver_at: Some(5678),
+            }),
+        );
+        let buffer = serializer.serialize_request(&request).unwrap();
+        match serializer.deserialize_request(buffer).unwrap().payload {
+            RequestPayload::V2Publish(publish) => {
+                assert_eq!(publish.key, "key");
+                assert_eq!(publish.deliver_at, Some(5678));
+            }
+            _ => panic!("Wrong type of payload"),
+        }
+    }
+
+    #[test]
+    fn should_reject_future_protocol_version() {
+        let buffer_pool = BufferPool::new();
+        let serializer = ContractSerializer::new(&Arc::new(buffer_pool));
+
+        let request = Request::new(
+            12,
+            RequestPayload::NegotiateVersion(v1::requests::NegotiateVersion {
+                min_version: 1,
+                max_version: 1,
+                compression: Vec::new(),
+            }),
+        );
+        let mut buffer = serializer.serialize_request(&request).unwrap();
+        assert_eq!(buffer[0], PROTOCOL_VERSION);
+
+        let future_version = PROTOCOL_VERSION + 1;
+        buffer[0] = future_version;
+
+        match serializer.deserialize_request(buffer) {
+            Err(DeserializeError::UnsupportedProtocolVersion { version }) => {
+                assert_eq!(version, future_version)
+            }
+            Err(err) => panic!("Wrong error {err:?}"),
+            Ok(_) => panic!("Request with a future protocol version was accepted"),
+        }
+    }
+
+    #[test]
+    fn should_reject_truncated_header() {
+        let buffer_pool = BufferPool::new();
+        let serializer = ContractSerializer::new(&Arc::new(buffer_pool));
+
+        let buffer = vec![PROTOCOL_VERSION, 0, 0];
+        assert!(matches!(
+            serializer.deserialize_response(buffer),
+            Err(DeserializeError::Error { .. })
+        ));
+
+        let buffer = vec![PROTOCOL_VERSION, 0, 0];
+        assert!(matches!(
+            serializer.deserialize_request(buffer),
+            Err(DeserializeError::Error { .. })
+        ));
+    }
+
+    #[test]
+    fn should_reject_unknown_message_type() {
+        let buffer_pool = BufferPool::new();
+        let serializer = ContractSerializer::new(&Arc::new(buffer_pool));
+        let unknown_type = MessageTypeId::MAX.to_le_bytes();
+        let message_type_range = PROTOCOL_VERSION_SIZE..PROTOCOL_VERSION_SIZE + MESSAGE_TYPE_SIZE;
+
+        let request = Request::new(
+            23,
+            RequestPayload::V1Ping(v1::requests::Ping { timestamp: 1234 }),
+        );
+        let mut buffer = serializer.serialize_request(&request).unwrap();
+        buffer[message_type_range.clone()].copy_from_slice(&unknown_type);
+        assert!(matches!(
+            serializer.deserialize_request(buffer),
+            Err(DeserializeError::Error { .. })
+        ));
+
+        let response = BrokerResponse::new(
+            23,
+            ResponsePayload::V1Pong(v1::responses::Response::success(v1::responses::Pong {
+                timestamp: 1234,
+            })),
+        );
+        let mut buffer = serializer.serialize_response(&response).unwrap();
+        buffer[message_type_range].copy_from_slice(&unknown_type);
+        assert!(matches!(
+            serializer.deserialize_response(buffer),
+            Err(DeserializeError::Error { .. })
+        ));
+    }
+
+    #[test]
+    fn should_roundtrip_compressed_request() {
+        let buffer_pool = Arc::new(BufferPool::new());
+        let mut compressing_serializer = ContractSerializer::new(&buffer_pool);
+        compressing_serializer.set_compression(CompressionScheme::Lz4);
+        let serializer = ContractSerializer::new(&buffer_pool);
+
+        let attributes: HashMap<String, String> = (0..100)
+            .map(|i| (format!("attribute-{i}"), String::from("repeated value")))
+            .collect();
+        let request = Request::for_session(
+            42,
+            3,
+            RequestPayload::V1Publish(v1::requests::Publish {
+                topic_id: 1,
+                partition_id: 2,
+                key: String::from("key"),
+                timestamp: None,
+                priority: None,
+                attributes: attributes.clone(),
+                ack_level: v1::requests::PublishAckLevel::default(),
+                headers: v1::requests::MessageHeaders::default(),
+                producer_id: None,
+                sequence_number: None,
+                deliver_after_millis: None,
+            }),
+        );
+
+        let uncompressed = serializer.serialize_request(&request).unwrap();
+        let compressed = compressing_serializer.serialize_request(&request).unwrap();
+        assert!(compressed.len() < uncompressed.len());
+        assert_eq!(
+            serializer.request_compression(&compressed),
+            CompressionScheme::Lz4
+        );
+        assert_eq!(
+            serializer.request_compression(&uncompressed),
+            CompressionScheme::None
+        );
+
+        // The header is not compressed
+        assert_eq!(
+            serializer.extract_metadata(&compressed),
+            serializer.extract_metadata(&uncompressed)
+        );
+        assert_eq!(serializer.extract_session_id(&compressed), 3);
+
+        // Compressed bodies are recognized whatever the compression of the deserializer
+        let deserialized = serializer.deserialize_request(compressed).unwrap();
+        assert_eq!(deserialized.request_id, 42);
+        match deserialized.payload {
+            RequestPayload::V1Publish(publish) => assert_eq!(publish.attributes, attributes),
+            _ => panic!("Wrong type of payload"),
+        }
+    }
 }
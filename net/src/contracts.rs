@@ -1 +1,3 @@
+pub mod sorted_map;
 pub mod v1;
+pub mod v2;
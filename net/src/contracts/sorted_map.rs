@@ -0,0 +1,65 @@
+/*
+Serializes maps with their entries sorted by key. Message attributes are held in a `HashMap`,
+which iterates in a different order in each process, so serializing the map directly gives
+different bytes for messages with identical attributes. Sorting the entries makes the
+serialized bytes depend only on the content of the map, which checksums and reproducible
+tests rely on. The serialized form is still an ordinary map, so deserialization is unchanged.
+*/
+
+use serde::{Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
+
+/// For use with `#[serde(serialize_with = "crate::contracts::sorted_map::serialize")]`
+pub fn serialize<S>(map: &HashMap<String, String>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    map.iter().collect::<BTreeMap<_, _>>().serialize(serializer)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::contracts::v1::requests::{MessageHeaders, Publish, PublishAckLevel};
+    use std::collections::HashMap;
+
+    fn publish(attributes: HashMap<String, String>) -> Publish {
+        Publish {
+            topic_id: 1,
+            partition_id: 1,
+            key: "key1".to_owned(),
+            timestamp: None,
+            priority: None,
+            attributes,
+            ack_level: PublishAckLevel::Queued,
+            headers: MessageHeaders::default(),
+            producer_id: None,
+            sequence_number: None,
+            deliver_after_millis: None,
+        }
+    }
+
+    #[test]
+    fn should_serialize_same_attributes_to_identical_bytes() {
+        let names: Vec<String> = (0..20).map(|index| format!("attribute{index}")).collect();
+
+        let mut forwards = HashMap::new();
+        for name in names.iter() {
+            forwards.insert(name.clone(), name.to_uppercase());
+        }
+        let mut backwards = HashMap::with_capacity(100);
+        for name in names.iter().rev() {
+            backwards.insert(name.clone(), name.to_uppercase());
+        }
+
+        let forwards_bytes = rmp_serde::to_vec(&publish(forwards)).unwrap();
+        let backwards_bytes = rmp_serde::to_vec(&publish(backwards)).unwrap();
+        assert_eq!(forwards_bytes, backwards_bytes);
+
+        let roundtrip: Publish = rmp_serde::from_slice(&forwards_bytes).unwrap();
+        assert_eq!(roundtrip.attributes.len(), 20);
+        assert_eq!(
+            roundtrip.attributes.get("attribute7").unwrap(),
+            "ATTRIBUTE7"
+        );
+    }
+}
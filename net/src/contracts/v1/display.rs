@@ -1,6 +1,8 @@
 use super::responses::{
-    AckLogEntry, DropConsumerLogEntry, KeyAffinityLogEntry, LogEntry, LogEntryDetail,
-    LogEntrySummary, Message, MessageRef, NackLogEntry, NewConsumerLogEntry, PublishLogEntry,
+    AckLogEntry, AdminAckLogEntry, DeadLetterLogEntry, DropConsumerLogEntry, ExpiryLogEntry,
+    KeyAffinityLogEntry, LogEntry, LogEntryDetail, LogEntrySummary, Message, MessageRef,
+    NackLogEntry, NewConsumerLogEntry, PartitionReassignedLogEntry, PublishLogEntry,
+    QuarantineLogEntry,
 };
 use crate::display::JoinableToString;
 use std::fmt::Display;
@@ -69,6 +71,46 @@ impl Display for NackLogEntry {
     }
 }
 
+impl Display for QuarantineLogEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} subscription:{} consumer:{} reason:{}",
+            self.message_ref, self.subscription_id, self.consumer_id, self.reason
+        )
+    }
+}
+
+impl Display for ExpiryLogEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} subscription:{}",
+            self.message_ref, self.subscription_id
+        )
+    }
+}
+
+impl Display for DeadLetterLogEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} subscription:{} consumer:{} dead-letter:{}",
+            self.message_ref, self.subscription_id, self.consumer_id, self.dead_letter_ref
+        )
+    }
+}
+
+impl Display for AdminAckLogEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} subscription:{}",
+            self.message_ref, self.subscription_id
+        )
+    }
+}
+
 impl Display for NewConsumerLogEntry {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -99,15 +141,30 @@ impl Display for KeyAffinityLogEntry {
     }
 }
 
+impl Display for PartitionReassignedLogEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "topic:{} partition:{} ledger:{} from-node:{} to-node:{}",
+            self.topic_id, self.partition_id, self.ledger_id, self.from_node_id, self.to_node_id
+        )
+    }
+}
+
 impl Display for LogEntryDetail {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             LogEntryDetail::Publish(entry) => write!(f, "{}", entry),
             LogEntryDetail::Ack(entry) => write!(f, "{}", entry),
             LogEntryDetail::Nack(entry) => write!(f, "{}", entry),
+            LogEntryDetail::AdminAck(entry) => write!(f, "{}", entry),
             LogEntryDetail::NewConsumer(entry) => write!(f, "{}", entry),
             LogEntryDetail::DropConsumer(entry) => write!(f, "{}", entry),
             LogEntryDetail::KeyAffinity(entry) => write!(f, "{}", entry),
+            LogEntryDetail::Quarantine(entry) => write!(f, "{}", entry),
+            LogEntryDetail::Expiry(entry) => write!(f, "{}", entry),
+            LogEntryDetail::DeadLetter(entry) => write!(f, "{}", entry),
+            LogEntryDetail::PartitionReassigned(entry) => write!(f, "{}", entry),
         }
     }
 }
@@ -2,13 +2,64 @@
 Version 1 data contracts for serializing request body
 */
 
+use crate::bin_serialization::CompressionScheme;
 use crate::data_types::{
-    ConsumerId, ContractVersionNumber, MessageCount, PartitionId, SubscriptionId, Timestamp,
-    TopicId,
+    ByteCount, ConsumerId, ContractVersionNumber, CreditCount, MessageCount, NodeId, PartitionId,
+    Priority, ProducerId, SequenceNumber, SubscriptionId, Timestamp, TopicId,
 };
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+/// Determines how much of the work of publishing a message is complete before the
+/// broker responds to the publisher
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub enum PublishAckLevel {
+    /// The broker responds as soon as a message id is allocated. The message is logged and
+    /// added to subscriptions afterwards, so it can be lost if the broker stops first
+    None,
+
+    /// The broker responds after the message is logged and added to all subscriptions
+    #[default]
+    Queued,
+
+    /// As for `Queued`, but the event log is also flushed to durable storage first
+    Persisted,
+}
+
+/// Well-known message headers. These are kept separate from the free-form attributes so
+/// that they can not collide with application attribute names
+#[derive(Serialize, Deserialize, Clone, PartialEq, Default)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct MessageHeaders {
+    /// Identifies how the message content is encoded, for example `application/json`
+    pub content_type: Option<String>,
+
+    /// Relates a reply to the request that it answers
+    pub correlation_id: Option<String>,
+
+    /// The topic that replies to this message should be published to
+    pub reply_to: Option<String>,
+
+    /// Identifies the distributed trace that this message is part of
+    pub trace_id: Option<String>,
+}
+
+impl MessageHeaders {
+    /// The total length of the header values
+    pub fn byte_count(self: &Self) -> usize {
+        [
+            &self.content_type,
+            &self.correlation_id,
+            &self.reply_to,
+            &self.trace_id,
+        ]
+        .iter()
+        .map(|header| header.as_ref().map_or(0, |value| value.len()))
+        .sum()
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 #[cfg_attr(debug_assertions, derive(Debug))]
 pub struct Publish {
@@ -16,7 +67,41 @@ pub struct Publish {
     pub partition_id: PartitionId,
     pub key: String,
     pub timestamp: Option<Timestamp>,
+    pub priority: Option<Priority>,
+    #[serde(serialize_with = "crate::contracts::sorted_map::serialize")]
     pub attributes: HashMap<String, String>,
+
+    /// Defaults to `Queued` when omitted
+    #[serde(default)]
+    pub ack_level: PublishAckLevel,
+
+    /// Defaults to no headers when omitted
+    #[serde(default)]
+    pub headers: MessageHeaders,
+
+    /// Identifies a producer that numbers the messages it publishes to each partition, so
+    /// that topics with sequence deduplication enabled discard messages that it sends more
+    /// than once. Defaults to no producer when omitted
+    #[serde(default)]
+    pub producer_id: Option<ProducerId>,
+
+    /// Must increase with each message that the producer publishes to the partition. Ignored
+    /// unless there is also a producer id
+    #[serde(default)]
+    pub sequence_number: Option<SequenceNumber>,
+
+    /// Holds the message back from subscribers until this many milliseconds after it was
+    /// published. Defaults to delivering the message immediately when omitted
+    #[serde(default)]
+    pub deliver_after_millis: Option<u64>,
+}
+
+/// Publishes several messages in one request. Each message is published on its own, so some
+/// messages in the batch can fail while others succeed
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct PublishBatch {
+    pub messages: Vec<Publish>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -26,6 +111,49 @@ pub struct Consume {
     pub subscription_id: SubscriptionId,
     pub consumer_id: Option<ConsumerId>,
     pub max_messages: MessageCount,
+
+    /// Acks the batch that was returned by this consumer's previous consume request before
+    /// returning the next batch. Defaults to false when omitted
+    #[serde(default)]
+    pub ack_previous: bool,
+
+    /// The maximum total serialized size of the messages in the batch. The first message is
+    /// always returned if there is one, even when it is larger than this. Defaults to no limit
+    /// when omitted
+    #[serde(default)]
+    pub max_bytes: Option<ByteCount>,
+}
+
+/// Registers a consumer that the broker pushes messages to as they become available, instead
+/// of waiting for consume requests. Each message that is pushed uses one credit, and nothing
+/// more is pushed once the consumer runs out of credits
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct Subscribe {
+    pub topic_id: TopicId,
+    pub subscription_id: SubscriptionId,
+    pub consumer_id: Option<ConsumerId>,
+    pub credits: CreditCount,
+}
+
+/// Grants more credits to a consumer that was registered by a subscribe request on the same
+/// connection
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct Flow {
+    pub topic_id: TopicId,
+    pub subscription_id: SubscriptionId,
+    pub consumer_id: ConsumerId,
+    pub credits: CreditCount,
+}
+
+/// Checks that the connection to the broker is still working. The broker answers without doing
+/// any other work, so that a slow broker is not mistaken for a broken connection
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct Ping {
+    /// When the ping was sent. The broker returns this in the pong
+    pub timestamp: Timestamp,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -44,9 +172,111 @@ pub struct Nack {
     pub consumer_id: ConsumerId,
 }
 
+/// Removes a message that the consumer can not process from the subscription straight away,
+/// without waiting for it to be redelivered, and keeps it in quarantine with the reason
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct Quarantine {
+    pub message_ref_key: String,
+    pub subscription_id: SubscriptionId,
+    pub consumer_id: ConsumerId,
+    pub reason: String,
+}
+
+/// Fetches a message by its ack key without changing its delivery state
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct GetMessage {
+    pub message_ref_key: String,
+}
+
+/// Lists the partitions of a topic, so that publishers can choose a partition by hashing
+/// the message key
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct GetPartitions {
+    pub topic_id: TopicId,
+}
+
+/// Lists the topics in the cluster with the number of partitions and subscriptions in each
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct ListTopics {}
+
+/// Gets the partitions of a topic and the nodes that own them, so that clients can send
+/// requests for each partition straight to the node that owns it
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct GetTopicPartitionMap {
+    pub topic_id: TopicId,
+}
+
+/// Adds a consumer to a named group of consumers that share the messages of a subscription
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct JoinGroup {
+    pub topic_id: TopicId,
+    pub subscription_id: SubscriptionId,
+    pub group_name: String,
+}
+
+/// Removes a consumer from its group, passing its messages to the remaining members
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct LeaveGroup {
+    pub topic_id: TopicId,
+    pub subscription_id: SubscriptionId,
+    pub consumer_id: ConsumerId,
+}
+
+/// Disconnects a consumer from a subscription when the consumer shuts down, so that messages
+/// that were delivered or assigned to it are redelivered to other consumers straight away
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct DisconnectConsumer {
+    pub topic_id: TopicId,
+    pub subscription_id: SubscriptionId,
+    pub consumer_id: ConsumerId,
+}
+
 #[derive(Serialize, Deserialize)]
 #[cfg_attr(debug_assertions, derive(Debug))]
 pub struct NegotiateVersion {
     pub min_version: ContractVersionNumber,
     pub max_version: ContractVersionNumber,
+
+    /// The compression schemes that the client supports, in order of preference. Messages
+    /// are not compressed if this is empty
+    #[serde(default)]
+    pub compression: Vec<CompressionScheme>,
+}
+
+/// Adds a topic to the cluster. The topic has no partitions or subscriptions until they
+/// are added
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct CreateTopic {
+    pub name: String,
+}
+
+/// Adds a partition to a topic. The partition is owned by the node that receives the
+/// request when no node is specified
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct CreatePartition {
+    #[serde(default)]
+    pub node_id: Option<NodeId>,
+}
+
+/// Adds a subscription to a topic. Messages published before the subscription was added
+/// are not delivered to it
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct CreateSubscription {
+    pub name: String,
+
+    /// Messages with the same key are delivered to the same consumer. Defaults to false
+    /// when omitted
+    #[serde(default)]
+    pub has_key_affinity: bool,
 }
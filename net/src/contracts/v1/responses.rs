@@ -6,10 +6,13 @@ use std::collections::HashMap;
 
 use serde::{Deserialize, Serialize};
 
+use super::requests::MessageHeaders;
+use crate::bin_serialization::CompressionScheme;
 use crate::data_types::{
-    ConsumerId, ContractVersionNumber, ErrorCode, LedgerId, MessageId, NodeId, PartitionId,
-    PortNumber, SubscriptionId, Timestamp, TopicId,
+    ConsumerId, ContractVersionNumber, CreditCount, ErrorCode, LedgerId, MessageId, NodeId,
+    PartitionId, PortNumber, SubscriptionId, Timestamp, TopicId,
 };
+use crate::error_codes::ERROR_CODE_INCORRECT_NODE;
 
 #[derive(Deserialize, Serialize, Clone)]
 #[cfg_attr(debug_assertions, derive(Debug))]
@@ -47,6 +50,9 @@ pub struct NodeDetail {
 #[cfg_attr(debug_assertions, derive(Debug))]
 pub struct TopicSummary {
     pub topic_id: TopicId,
+    pub name: String,
+    pub partition_count: usize,
+    pub subscription_count: usize,
 }
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -70,6 +76,10 @@ pub struct PartitionDetail {
     pub topic_id: TopicId,
     pub partition_id: PartitionId,
     pub ledgers: Vec<LedgerSummary>,
+
+    /// The node that currently owns the partition
+    #[serde(default)]
+    pub node_id: NodeId,
 }
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -130,12 +140,32 @@ pub struct LedgerList {
 #[cfg_attr(debug_assertions, derive(Debug))]
 pub struct NegotiateVersionResult {
     pub version: ContractVersionNumber,
+
+    /// The compression that both ends will use for the rest of the connection
+    #[serde(default)]
+    pub compression: CompressionScheme,
 }
 
 #[derive(Deserialize, Serialize, Clone)]
 #[cfg_attr(debug_assertions, derive(Debug))]
 pub struct PublishResult {
     pub message_ref: MessageRef,
+
+    /// The node that owns the ledger that the message was published to
+    #[serde(default)]
+    pub node_id: NodeId,
+
+    /// The number of messages in the ledger when the publish was acknowledged. Messages that
+    /// were published with no ack level are not always counted yet
+    #[serde(default)]
+    pub ledger_message_count: usize,
+}
+
+/// The outcome of publishing each message in a batch, in the order that they were in the batch
+#[derive(Deserialize, Serialize, Clone)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct PublishBatchResult {
+    pub results: Vec<Response<PublishResult>>,
 }
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -143,6 +173,44 @@ pub struct PublishResult {
 pub struct ConsumeResult {
     pub consumer_id: ConsumerId,
     pub messages: Vec<Message>,
+
+    /// The partitions of the topic that are owned by other nodes. Empty when omitted by
+    /// brokers that do not report them
+    #[serde(default)]
+    pub remote_partitions: Vec<PartitionOwner>,
+}
+
+/// Identifies a partition that is owned by another node in the cluster. Consumers must
+/// send consume requests for these partitions to the owning node instead.
+#[derive(Deserialize, Serialize, Clone)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct PartitionOwner {
+    pub topic_id: TopicId,
+    pub partition_id: PartitionId,
+    pub node_id: NodeId,
+    pub ip_address: String,
+    pub pubsub_port: PortNumber,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct SubscribeResult {
+    pub consumer_id: ConsumerId,
+}
+
+/// The credits that the consumer has after the grant, including any that were not used yet
+#[derive(Deserialize, Serialize, Clone)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct FlowResult {
+    pub credits: CreditCount,
+}
+
+/// The answer to a ping, with the timestamp from the ping so that the round trip time can be
+/// measured
+#[derive(Deserialize, Serialize, Clone)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct Pong {
+    pub timestamp: Timestamp,
 }
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -157,6 +225,32 @@ pub struct NackResult {
     pub success: bool,
 }
 
+#[derive(Deserialize, Serialize, Clone)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct QuarantineResult {
+    pub success: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct JoinGroupResult {
+    /// The subscription of the group, which members consume from and ack to
+    pub subscription_id: SubscriptionId,
+    pub consumer_id: ConsumerId,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct LeaveGroupResult {
+    pub success: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct DisconnectConsumerResult {
+    pub success: bool,
+}
+
 #[derive(Deserialize, Serialize, Clone, Default)]
 #[cfg_attr(debug_assertions, derive(Debug))]
 pub struct MessageRef {
@@ -174,8 +268,113 @@ pub struct Message {
     pub message_ack_key: String,
     pub published: Timestamp,
     pub delivered: Timestamp,
+
+    /// When the message was first delivered to a consumer of the subscription. This is None
+    /// for messages that were fetched without being delivered
+    pub first_delivered: Option<Timestamp>,
     pub delivery_count: usize,
+    pub redelivered: bool,
+    #[serde(serialize_with = "crate::contracts::sorted_map::serialize")]
     pub attributes: HashMap<String, String>,
+
+    #[serde(default)]
+    pub headers: MessageHeaders,
+
+    /// No later message had been published to the ledger when this message was delivered.
+    /// Once a ledger is full, this is only set on its final message
+    #[serde(default)]
+    pub last_in_ledger: bool,
+}
+
+/// A message that a consumer removed from a subscription because it could not be processed
+#[derive(Deserialize, Serialize, Clone)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct QuarantinedMessage {
+    pub message: Message,
+    pub subscription_id: SubscriptionId,
+    pub consumer_id: ConsumerId,
+    pub reason: String,
+    pub quarantined: Timestamp,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct QuarantinedMessageList {
+    pub messages: Vec<QuarantinedMessage>,
+}
+
+/// The number of messages in each state for one subscription
+#[derive(Deserialize, Serialize, Clone)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct SubscriptionStats {
+    pub subscription_id: SubscriptionId,
+    pub queued_count: usize,
+    pub unacked_count: usize,
+    pub assigned_count: usize,
+    pub affinity_count: usize,
+}
+
+/// The stats of every subscription to a topic, with the counts totalled over all of them
+#[derive(Deserialize, Serialize, Clone)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct TopicSubscriptionStats {
+    pub topic_id: TopicId,
+    pub queued_count: usize,
+    pub unacked_count: usize,
+    pub assigned_count: usize,
+    pub affinity_count: usize,
+    pub subscriptions: Vec<SubscriptionStats>,
+}
+
+/// The differences that were found when a subscription was reconciled with the event log
+#[derive(Deserialize, Serialize, Clone)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct SubscriptionRepair {
+    pub unacked_count: usize,
+    pub restored: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+/// The messages that were queued again when a subscription was moved to an earlier time
+#[derive(Deserialize, Serialize, Clone)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct SubscriptionSeek {
+    pub queued_count: usize,
+    pub earliest_published: Option<Timestamp>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct CreateTopicResult {
+    pub topic_id: TopicId,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct CreatePartitionResult {
+    pub topic_id: TopicId,
+    pub partition_id: PartitionId,
+    pub node_id: NodeId,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct CreateSubscriptionResult {
+    pub topic_id: TopicId,
+    pub subscription_id: SubscriptionId,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct DeleteTopicResult {
+    pub topic_id: TopicId,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct DeleteSubscriptionResult {
+    pub topic_id: TopicId,
+    pub subscription_id: SubscriptionId,
 }
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -208,6 +407,42 @@ pub struct NackLogEntry {
     pub consumer_id: ConsumerId,
 }
 
+#[derive(Deserialize, Serialize, Clone)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct QuarantineLogEntry {
+    pub message_ref: MessageRef,
+    pub subscription_id: SubscriptionId,
+    pub consumer_id: ConsumerId,
+    pub reason: String,
+}
+
+/// A message that was removed from a subscription because it was not delivered within the
+/// message TTL of its topic
+#[derive(Deserialize, Serialize, Clone)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct ExpiryLogEntry {
+    pub message_ref: MessageRef,
+    pub subscription_id: SubscriptionId,
+}
+
+/// A message that was delivered too many times, and was moved to the dead letter topic of its
+/// subscription. `dead_letter_ref` is the copy of the message in the dead letter topic
+#[derive(Deserialize, Serialize, Clone)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct DeadLetterLogEntry {
+    pub message_ref: MessageRef,
+    pub subscription_id: SubscriptionId,
+    pub consumer_id: ConsumerId,
+    pub dead_letter_ref: MessageRef,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct AdminAckLogEntry {
+    pub message_ref: MessageRef,
+    pub subscription_id: SubscriptionId,
+}
+
 #[derive(Deserialize, Serialize, Clone)]
 #[cfg_attr(debug_assertions, derive(Debug))]
 pub struct NewConsumerLogEntry {
@@ -233,15 +468,34 @@ pub struct KeyAffinityLogEntry {
     pub message_key: String,
 }
 
+/// A partition that was moved to another node, with the ledger that was started on that node
 #[derive(Deserialize, Serialize, Clone)]
 #[cfg_attr(debug_assertions, derive(Debug))]
+pub struct PartitionReassignedLogEntry {
+    pub topic_id: TopicId,
+    pub partition_id: PartitionId,
+    pub ledger_id: LedgerId,
+    pub from_node_id: NodeId,
+    pub to_node_id: NodeId,
+}
+
+/// Serialized with the name of the variant in `type` and its fields in `detail`, so that JSON
+/// consumers of the event log can tell the kinds of entry apart without guessing from the shape
+#[derive(Deserialize, Serialize, Clone)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+#[serde(tag = "type", content = "detail")]
 pub enum LogEntryDetail {
     Publish(PublishLogEntry),
     Ack(AckLogEntry),
     Nack(NackLogEntry),
+    AdminAck(AdminAckLogEntry),
     NewConsumer(NewConsumerLogEntry),
     DropConsumer(DropConsumerLogEntry),
     KeyAffinity(KeyAffinityLogEntry),
+    Quarantine(QuarantineLogEntry),
+    Expiry(ExpiryLogEntry),
+    DeadLetter(DeadLetterLogEntry),
+    PartitionReassigned(PartitionReassignedLogEntry),
 }
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -266,6 +520,10 @@ pub enum RequestOutcome {
 pub struct Response<T> {
     pub outcome: RequestOutcome,
     pub data: Option<T>,
+    /// When the request was sent to a node that does not own the partition, this is the
+    /// authority (host:port) of the node that does, so that clients can retry there
+    #[serde(default)]
+    pub redirect: Option<String>,
 }
 
 impl<T> Response<T> {
@@ -273,24 +531,35 @@ impl<T> Response<T> {
         Self {
             outcome: RequestOutcome::Success,
             data: Some(data),
+            redirect: None,
         }
     }
     pub fn no_data(msg: &str) -> Self {
         Self {
             outcome: RequestOutcome::NoData(msg.to_owned()),
             data: None,
+            redirect: None,
         }
     }
     pub fn warning(msg: &str) -> Self {
         Self {
             outcome: RequestOutcome::Warning(msg.to_owned()),
             data: None,
+            redirect: None,
         }
     }
     pub fn error(msg: &str, code: ErrorCode) -> Self {
         Self {
             outcome: RequestOutcome::Error(msg.to_owned(), code),
             data: None,
+            redirect: None,
+        }
+    }
+    pub fn incorrect_node(msg: &str, authority: String) -> Self {
+        Self {
+            outcome: RequestOutcome::Error(msg.to_owned(), ERROR_CODE_INCORRECT_NODE),
+            data: None,
+            redirect: Some(authority),
         }
     }
 }
@@ -0,0 +1,53 @@
+/*
+Version 2 data contracts for serializing request body. Only the requests that changed in
+version 2 are defined here, clients that negotiate version 2 send the version 1 contracts
+for everything else.
+*/
+
+use crate::contracts::v1::requests::{MessageHeaders, PublishAckLevel};
+use crate::data_types::{PartitionId, Priority, ProducerId, SequenceNumber, Timestamp, TopicId};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// As for the version 1 publish request, with the addition of an absolute delivery time
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct Publish {
+    pub topic_id: TopicId,
+    pub partition_id: PartitionId,
+    pub key: String,
+    pub timestamp: Option<Timestamp>,
+    pub priority: Option<Priority>,
+    #[serde(serialize_with = "crate::contracts::sorted_map::serialize")]
+    pub attributes: HashMap<String, String>,
+
+    /// Defaults to `Queued` when omitted
+    #[serde(default)]
+    pub ack_level: PublishAckLevel,
+
+    /// Defaults to no headers when omitted
+    #[serde(default)]
+    pub headers: MessageHeaders,
+
+    /// Identifies a producer that numbers the messages it publishes to each partition, so
+    /// that topics with sequence deduplication enabled discard messages that it sends more
+    /// than once. Defaults to no producer when omitted
+    #[serde(default)]
+    pub producer_id: Option<ProducerId>,
+
+    /// Must increase with each message that the producer publishes to the partition. Ignored
+    /// unless there is also a producer id
+    #[serde(default)]
+    pub sequence_number: Option<SequenceNumber>,
+
+    /// Holds the message back from subscribers until this many milliseconds after it was
+    /// published. Defaults to delivering the message immediately when omitted
+    #[serde(default)]
+    pub deliver_after_millis: Option<u64>,
+
+    /// Holds the message back from subscribers until this time, in milliseconds since the
+    /// epoch. Unlike `deliver_after_millis` this does not depend on when the broker receives
+    /// the request. Takes precedence over `deliver_after_millis` when both are present
+    #[serde(default)]
+    pub deliver_at: Option<Timestamp>,
+}
@@ -7,7 +7,11 @@ pub type Timestamp = u64; // Compatible with Rust time library
 pub type VersionNumber = u32; // Allows 4 billion updates to each database record
 pub type PortNumber = u16; // Conforms to TCP/IP port numbering
 pub type MessageCount = u8; // The number of messages to consume
+pub type CreditCount = u32; // The number of messages that can be pushed to a consumer before it grants more
 pub type ErrorCode = u16; // Numeric value returned with error responses to identify the specific error
+pub type Priority = u8; // Messages with higher priority are delivered to consumers first
+pub type SequenceNumber = u64; // Numbers the messages that a producer publishes to each partition
+pub type ByteCount = u32; // The size of a batch of messages, up to 4 gigabytes
 
 pub type NodeId = u16; // Maximum of 65 thousand nodes in a cluster
 pub type TopicId = u32; // Up to 4 billion topics per cluster
@@ -16,3 +20,4 @@ pub type LedgerId = u32; // Up to 4 billion ledgers per partition
 pub type MessageId = u32; // // Up to 4 billion messages per ledger
 pub type SubscriptionId = u32; // // Up to 4 billion subscriptions per topic
 pub type ConsumerId = u64; // Allows us to create 1 million consumers per second for about half a million years
+pub type ProducerId = u64; // Chosen by the producer, so needs to be large enough to pick at random
@@ -4,3 +4,7 @@ pub const ERROR_CODE_GENERAL_FAILURE: ErrorCode = 0;
 pub const ERROR_CODE_INCORRECT_NODE: ErrorCode = 1;
 pub const ERROR_CODE_NO_COMPATIBLE_VERSION: ErrorCode = 2;
 pub const ERROR_CODE_BACKLOG_FULL: ErrorCode = 3;
+pub const ERROR_CODE_REQUEST_TOO_LARGE: ErrorCode = 4;
+pub const ERROR_CODE_TIMEOUT: ErrorCode = 5;
+pub const ERROR_CODE_TOO_MANY_CONSUMERS: ErrorCode = 6;
+pub const ERROR_CODE_DUPLICATE_SEQUENCE: ErrorCode = 7;
@@ -2,7 +2,9 @@
 Thin wrapper around sockets implemenation in the standard library.
 Includes thread pooling and scheduling request handling
 */
+pub mod backoff;
 pub mod buffer_pool;
 pub mod tcp_channel;
+pub mod tls;
 
 pub type MessageLength = u16;
@@ -0,0 +1,102 @@
+/*
+Threads that poll for work back off when there is nothing to do. Straight after doing some
+work the thread spins, because more work is likely to arrive within microseconds, for example
+the next request from a client that is waiting for each response. If nothing arrives the
+thread yields to other threads, and then sleeps for longer and longer, up to a maximum, so
+that idle threads use very little CPU. Doing work resets the backoff.
+*/
+
+use std::{hint, thread, time::Duration};
+
+/// The longest that an idle thread sleeps between polls, unless another limit is set
+pub const DEFAULT_MAX_SLEEP: Duration = Duration::from_millis(10);
+
+/// The first sleep after spinning and yielding, which doubles each time there is still no work
+const MIN_SLEEP: Duration = Duration::from_micros(50);
+
+/// The number of polls that spin, each spinning for twice as long as the one before
+const SPIN_LIMIT: u32 = 6;
+
+/// The number of polls, including the spins, before the thread starts to sleep
+const YIELD_LIMIT: u32 = 10;
+
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct Backoff {
+    step: u32,
+    sleep: Duration,
+    max_sleep: Duration,
+}
+
+impl Backoff {
+    pub fn new() -> Self {
+        Self::with_max_sleep(DEFAULT_MAX_SLEEP)
+    }
+
+    pub fn with_max_sleep(max_sleep: Duration) -> Self {
+        Self {
+            step: 0,
+            sleep: MIN_SLEEP.min(max_sleep),
+            max_sleep,
+        }
+    }
+
+    /// Call this when the thread found some work to do, so that it polls again straight away
+    pub fn reset(self: &mut Self) {
+        self.step = 0;
+        self.sleep = MIN_SLEEP.min(self.max_sleep);
+    }
+
+    /// Call this when the thread found nothing to do. Waits before the next poll, for longer
+    /// each time that this is called without a reset
+    pub fn snooze(self: &mut Self) {
+        if self.step <= SPIN_LIMIT {
+            for _ in 0..1 << self.step {
+                hint::spin_loop();
+            }
+        } else if self.step <= YIELD_LIMIT {
+            thread::yield_now();
+        } else {
+            thread::sleep(self.sleep);
+            self.sleep = (self.sleep * 2).min(self.max_sleep);
+        }
+
+        if self.step <= YIELD_LIMIT {
+            self.step += 1;
+        }
+    }
+
+    /// True once the thread has been idle long enough to sleep between polls
+    pub fn is_sleeping(self: &Self) -> bool {
+        self.step > YIELD_LIMIT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Backoff, MIN_SLEEP, YIELD_LIMIT};
+    use std::time::Duration;
+
+    #[test]
+    fn should_sleep_longer_until_the_limit_then_reset() {
+        let max_sleep = Duration::from_micros(400);
+        let mut backoff = Backoff::with_max_sleep(max_sleep);
+
+        for _ in 0..=YIELD_LIMIT {
+            assert!(!backoff.is_sleeping());
+            backoff.snooze();
+        }
+        assert!(backoff.is_sleeping());
+        assert_eq!(backoff.sleep, MIN_SLEEP);
+
+        let mut sleeps = Vec::new();
+        for _ in 0..5 {
+            backoff.snooze();
+            sleeps.push(backoff.sleep.as_micros());
+        }
+        assert_eq!(sleeps, vec![100, 200, 400, 400, 400]);
+
+        backoff.reset();
+        assert!(!backoff.is_sleeping());
+        assert_eq!(backoff.sleep, MIN_SLEEP);
+    }
+}
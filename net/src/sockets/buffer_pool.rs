@@ -1,4 +1,7 @@
-use std::sync::RwLock;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    RwLock,
+};
 
 use super::MessageLength;
 
@@ -7,75 +10,194 @@ const M_CAPACICY: MessageLength = 512;
 const L_CAPACICY: MessageLength = 4096;
 const XL_CAPACICY: MessageLength = 16384;
 
+/// The number of buffers of each size that are kept for reuse, unless another limit is set
+pub const DEFAULT_MAX_POOLED_BUFFERS: usize = 1024;
+
+/// Buffers with more capacity than this are dropped instead of being kept for reuse, unless
+/// another limit is set. This is big enough for a buffer that grew to hold the largest message
+pub const DEFAULT_MAX_BUFFER_CAPACITY: usize = 128 * 1024;
+
 pub struct BufferPool {
     s: RwLock<Vec<Vec<u8>>>,
     m: RwLock<Vec<Vec<u8>>>,
     l: RwLock<Vec<Vec<u8>>>,
     xl: RwLock<Vec<Vec<u8>>>,
+
+    max_pooled: usize,
+    max_capacity: usize,
+
+    /// The number of buffers that were handed out and not returned to the pool yet
+    in_use: AtomicUsize,
+
+    /// The number of buffers that were handed out from the pool, and that had to be allocated
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+
+    /// The number of buffers that were returned but not kept, because the pool was full or
+    /// the buffer was too big
+    dropped: AtomicUsize,
 }
 
 impl BufferPool {
     pub fn new() -> Self {
+        Self::with_limits(DEFAULT_MAX_POOLED_BUFFERS, DEFAULT_MAX_BUFFER_CAPACITY)
+    }
+
+    /// Constructs a pool that keeps at most `max_pooled` buffers of each size for reuse, and
+    /// never keeps buffers with a capacity of more than `max_capacity` bytes. This stops a
+    /// burst of traffic from permanently inflating the memory that the pool holds on to
+    pub fn with_limits(max_pooled: usize, max_capacity: usize) -> Self {
         Self {
             s: RwLock::new(Vec::new()),
             m: RwLock::new(Vec::new()),
             l: RwLock::new(Vec::new()),
             xl: RwLock::new(Vec::new()),
+            max_pooled,
+            max_capacity,
+            in_use: AtomicUsize::new(0),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
         }
     }
 
+    /// The number of buffers that were taken from the pool and not returned for reuse yet
+    pub fn in_use(self: &Self) -> usize {
+        self.in_use.load(Ordering::Relaxed)
+    }
+
+    /// The number of buffers that are waiting in the pool to be reused
+    pub fn pooled(self: &Self) -> usize {
+        [&self.s, &self.m, &self.l, &self.xl]
+            .iter()
+            .map(|pool| pool.read().unwrap().len())
+            .sum()
+    }
+
+    /// The number of buffers that were reused from the pool
+    pub fn hits(self: &Self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// The number of buffers that were allocated because there were none in the pool
+    pub fn misses(self: &Self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// The number of buffers that were returned to the pool and dropped instead of being kept
+    pub fn dropped(self: &Self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
     pub fn get_with_capacity(self: &Self, size: MessageLength, capacity: MessageLength) -> Vec<u8> {
+        self.in_use.fetch_add(1, Ordering::Relaxed);
         if capacity <= S_CAPACICY {
-            BufferPool::get_internal(&self.s, S_CAPACICY, size)
+            self.get_internal(&self.s, S_CAPACICY, size)
         } else if capacity <= M_CAPACICY {
-            BufferPool::get_internal(&self.m, M_CAPACICY, size)
+            self.get_internal(&self.m, M_CAPACICY, size)
         } else if capacity <= L_CAPACICY {
-            BufferPool::get_internal(&self.l, L_CAPACICY, size)
+            self.get_internal(&self.l, L_CAPACICY, size)
         } else {
-            BufferPool::get_internal(&self.xl, capacity, size)
+            self.get_internal(&self.xl, capacity, size)
         }
     }
 
     pub fn get(self: &Self, size: MessageLength) -> Vec<u8> {
+        self.in_use.fetch_add(1, Ordering::Relaxed);
         if size <= S_CAPACICY {
-            BufferPool::get_internal(&self.s, S_CAPACICY, size)
+            self.get_internal(&self.s, S_CAPACICY, size)
         } else if size <= M_CAPACICY {
-            BufferPool::get_internal(&self.m, M_CAPACICY, size)
+            self.get_internal(&self.m, M_CAPACICY, size)
         } else if size <= L_CAPACICY {
-            BufferPool::get_internal(&self.l, L_CAPACICY, size)
+            self.get_internal(&self.l, L_CAPACICY, size)
         } else {
-            BufferPool::get_internal(&self.xl, XL_CAPACICY, size)
+            self.get_internal(&self.xl, XL_CAPACICY, size)
         }
     }
 
     pub fn reuse(self: &Self, buffer: Vec<u8>) {
-        let capacity = buffer.capacity() as MessageLength;
-        if capacity <= S_CAPACICY {
-            BufferPool::reuse_internal(&self.s, buffer)
-        } else if capacity <= M_CAPACICY {
-            BufferPool::reuse_internal(&self.m, buffer)
-        } else if capacity <= L_CAPACICY {
-            BufferPool::reuse_internal(&self.l, buffer)
+        // Buffers that were not allocated by the pool can also be reused
+        let _ = self
+            .in_use
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |in_use| {
+                in_use.checked_sub(1)
+            });
+        let capacity = buffer.capacity();
+        if capacity > self.max_capacity {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        } else if capacity <= S_CAPACICY as usize {
+            self.reuse_internal(&self.s, buffer)
+        } else if capacity <= M_CAPACICY as usize {
+            self.reuse_internal(&self.m, buffer)
+        } else if capacity <= L_CAPACICY as usize {
+            self.reuse_internal(&self.l, buffer)
         } else {
-            BufferPool::reuse_internal(&self.xl, buffer);
+            self.reuse_internal(&self.xl, buffer);
         }
     }
 
-    fn reuse_internal(pool: &RwLock<Vec<Vec<u8>>>, buffer: Vec<u8>) {
-        pool.write().unwrap().push(buffer);
+    fn reuse_internal(self: &Self, pool: &RwLock<Vec<Vec<u8>>>, buffer: Vec<u8>) {
+        let mut pool = pool.write().unwrap();
+        if pool.len() < self.max_pooled {
+            pool.push(buffer);
+        } else {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
     }
 
     fn get_internal(
+        self: &Self,
         pool: &RwLock<Vec<Vec<u8>>>,
         capacity: MessageLength,
         size: MessageLength,
     ) -> Vec<u8> {
         let mut pool = pool.write().unwrap();
         let mut buffer = match pool.pop() {
-            Some(buffer) => buffer,
-            None => Vec::with_capacity(capacity as usize),
+            Some(buffer) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                buffer
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                Vec::with_capacity(capacity as usize)
+            }
         };
         buffer.resize(size as usize, 0);
         buffer
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::BufferPool;
+
+    #[test]
+    fn should_drop_oversized_buffers_instead_of_pooling_them() {
+        let pool = BufferPool::with_limits(10, 1024);
+
+        pool.reuse(Vec::with_capacity(4096));
+        assert_eq!(pool.pooled(), 0);
+        assert_eq!(pool.dropped(), 1);
+
+        let buffer = pool.get(100);
+        assert_eq!(pool.misses(), 1);
+        pool.reuse(buffer);
+        assert_eq!(pool.pooled(), 1);
+
+        pool.get(100);
+        assert_eq!(pool.hits(), 1);
+    }
+
+    #[test]
+    fn should_keep_no_more_than_the_maximum_number_of_buffers() {
+        let pool = BufferPool::with_limits(2, 1024);
+
+        let buffers: Vec<Vec<u8>> = (0..3).map(|_| pool.get(10)).collect();
+        for buffer in buffers {
+            pool.reuse(buffer);
+        }
+        assert_eq!(pool.pooled(), 2);
+        assert_eq!(pool.dropped(), 1);
+        assert_eq!(pool.in_use(), 0);
+    }
+}
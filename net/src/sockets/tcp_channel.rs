@@ -1,8 +1,8 @@
-use super::{buffer_pool::BufferPool, MessageLength};
+use super::{backoff::Backoff, buffer_pool::BufferPool, tls::ChannelStream, MessageLength};
+use bytes::{Buf, Bytes, BytesMut};
 use log::{error, info, warn};
 use std::{
     io::{ErrorKind, Read, Write},
-    net::TcpStream,
     sync::{
         atomic::{AtomicBool, Ordering},
         mpsc::{Receiver, Sender, TryRecvError},
@@ -15,15 +15,66 @@ use std::{
 #[cfg(debug_assertions)]
 use log::debug;
 
-const IDLE_SLEEP_LIMIT: Duration = Duration::from_millis(50);
 const IDLE_SLEEP_DURATION: Duration = Duration::from_millis(10);
 const DISCONNECT_IDLE_TIME: Duration = Duration::from_secs(60);
 const MESSAGE_LENGTH_SIZE: usize = size_of::<MessageLength>();
-const MAX_MESSAGE_SIZE: usize = 32 * 1024;
-const RECEIVE_BUFFER_SIZE: usize = MAX_MESSAGE_SIZE << 2;
-const MAX_TX_RETRY_COUNT: usize = 5;
 const TX_RETRY_INTERVAL: Duration = Duration::from_millis(10);
 
+/// The maximum length of a serialized message when no other limit is configured
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 32 * 1024;
+
+/// The largest message size that can be configured. The length of each message is sent
+/// ahead of it, and must fit into the `MessageLength` type
+pub const MAX_MESSAGE_SIZE_LIMIT: usize = MessageLength::MAX as usize;
+
+/// A message that was received by a channel. Received messages share the buffer that they
+/// were read into from the stream, so that they do not have to be copied out of it
+pub type ReceivedMessage = Bytes;
+
+/// A message was not sent because it is longer than the maximum message size of the channel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MessageTooLarge {
+    pub length: usize,
+    pub max_message_size: usize,
+}
+
+/// Checks that a serialized message fits within the maximum message size of a channel. Call
+/// this before posting a message to the channel, because the channel can only log and discard
+/// messages that are too large
+pub fn check_message_size(length: usize, max_message_size: usize) -> Result<(), MessageTooLarge> {
+    if length > max_message_size {
+        Err(MessageTooLarge {
+            length,
+            max_message_size,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Limits how long the channel waits for stalled I/O before closing the connection. The
+/// stream is non-blocking, so these are enforced by the channel rather than the socket.
+/// This detects half-open connections where the peer has gone away without resetting
+/// the connection
+#[derive(Clone, Copy)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub struct TcpTimeouts {
+    /// How long to wait for the rest of a message after part of it was received
+    pub read: Duration,
+
+    /// How long to wait for the peer to accept a message that is being sent
+    pub write: Duration,
+}
+
+impl Default for TcpTimeouts {
+    fn default() -> Self {
+        Self {
+            read: Duration::from_secs(10),
+            write: Duration::from_secs(1),
+        }
+    }
+}
+
 pub struct TcpChannel {
     stop_signal: Arc<AtomicBool>,
 }
@@ -31,14 +82,51 @@ pub struct TcpChannel {
 impl TcpChannel {
     pub fn new(
         receiver: Receiver<Vec<u8>>,
-        sender: Sender<Vec<u8>>,
-        stream: TcpStream,
+        sender: Sender<ReceivedMessage>,
+        stream: impl Into<ChannelStream>,
         buffer_pool: &Arc<BufferPool>,
         stop_signal: &Arc<AtomicBool>,
+        max_message_size: usize,
     ) -> Self {
+        Self::with_timeouts(
+            receiver,
+            sender,
+            stream,
+            buffer_pool,
+            stop_signal,
+            max_message_size,
+            TcpTimeouts::default(),
+        )
+    }
+
+    /// Messages that are longer than `max_message_size` are not sent, and a connection that
+    /// receives one is closed. The maximum message size can not exceed `MAX_MESSAGE_SIZE_LIMIT`.
+    /// The stream can be plain or wrapped in TLS, in which case the TLS handshake is completed
+    /// before any messages are sent or received
+    pub fn with_timeouts(
+        receiver: Receiver<Vec<u8>>,
+        sender: Sender<ReceivedMessage>,
+        stream: impl Into<ChannelStream>,
+        buffer_pool: &Arc<BufferPool>,
+        stop_signal: &Arc<AtomicBool>,
+        max_message_size: usize,
+        timeouts: TcpTimeouts,
+    ) -> Self {
+        assert!(
+            max_message_size <= MAX_MESSAGE_SIZE_LIMIT,
+            "TcpChannel: Maximum message size {max_message_size} exceeds the limit of {MAX_MESSAGE_SIZE_LIMIT}"
+        );
         info!("TcpChannel: Created");
 
-        let thread = TcpThread::new(receiver, sender, stream, buffer_pool, stop_signal);
+        let thread = TcpThread::new(
+            receiver,
+            sender,
+            stream.into(),
+            buffer_pool,
+            stop_signal,
+            max_message_size,
+            timeouts,
+        );
         thread::Builder::new()
             .name(String::from("tcp-channel"))
             .spawn(move || thread.run())
@@ -65,53 +153,90 @@ impl Drop for TcpChannel {
 }
 
 struct TcpThread {
-    stream: TcpStream,
+    stream: ChannelStream,
     buffer_pool: Arc<BufferPool>,
     stop_signal: Arc<AtomicBool>,
+    timeouts: TcpTimeouts,
+    max_message_size: usize,
     last_message_instant: Instant,
+    last_receive_instant: Instant,
+    backoff: Backoff,
 
     channel_rx: Receiver<Vec<u8>>,
-    channel_tx: Sender<Vec<u8>>,
+    channel_tx: Sender<ReceivedMessage>,
 
-    receive_buffer: Box<[u8; RECEIVE_BUFFER_SIZE]>,
+    /// Messages are split off the front of this buffer as they are received, and the unused
+    /// space that follows is where the next bytes from the stream are read into
+    receive_buffer: BytesMut,
+    receive_buffer_size: usize,
     receive_buffer_count: usize,
-    consumed_count: usize,
 }
 
 impl TcpThread {
     fn new(
         receiver: Receiver<Vec<u8>>,
-        sender: Sender<Vec<u8>>,
-        stream: TcpStream,
+        sender: Sender<ReceivedMessage>,
+        stream: ChannelStream,
         buffer_pool: &Arc<BufferPool>,
         stop_signal: &Arc<AtomicBool>,
+        max_message_size: usize,
+        timeouts: TcpTimeouts,
     ) -> Self {
+        // The buffer holds several messages so that it does not have to be replaced often
+        let receive_buffer_size = (max_message_size + MESSAGE_LENGTH_SIZE) << 2;
         Self {
             stream,
             buffer_pool: buffer_pool.clone(),
             stop_signal: stop_signal.clone(),
+            timeouts,
+            max_message_size,
             last_message_instant: Instant::now(),
+            last_receive_instant: Instant::now(),
+            backoff: Backoff::new(),
 
             channel_rx: receiver,
             channel_tx: sender,
 
-            receive_buffer: Box::new([0u8; RECEIVE_BUFFER_SIZE]),
-            consumed_count: 0,
+            receive_buffer: BytesMut::zeroed(receive_buffer_size),
+            receive_buffer_size,
             receive_buffer_count: 0,
         }
     }
 
     fn run(mut self: Self) {
         info!("TcpThread: Started");
+        self.handshake();
         while !self.stop_signal.load(Ordering::Relaxed) {
             self.try_send();
             self.try_receive();
             self.try_extract_received();
+            self.stop_if_stalled();
             self.stop_if_idle();
         }
         info!("TcpThread: Stopped");
     }
 
+    /// Completes the TLS handshake before any messages are sent or received. Plain streams
+    /// have no handshake
+    fn handshake(self: &mut Self) {
+        let deadline = Instant::now() + self.timeouts.read;
+        while self.stream.is_handshaking() && !self.stop_signal.load(Ordering::Relaxed) {
+            match self.stream.handshake() {
+                Ok(()) => {}
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                Err(err) => {
+                    self.fatal(&format!("TLS handshake failed. {err}"));
+                    return;
+                }
+            }
+            if Instant::now() > deadline {
+                self.fatal("TLS handshake timeout, the other party did not respond");
+                return;
+            }
+            thread::sleep(IDLE_SLEEP_DURATION);
+        }
+    }
+
     fn stop(self: &mut Self) {
         self.stop_signal.store(true, Ordering::Relaxed);
         self.last_message_instant = Instant::now();
@@ -135,16 +260,18 @@ impl TcpThread {
         };
 
         self.last_message_instant = Instant::now();
+        self.backoff.reset();
 
         let len = message.len();
-        let length: MessageLength = len
-            .try_into()
-            .expect("TcpThread Tx: Message length must fit into MessageLength type");
-        if len + MESSAGE_LENGTH_SIZE > MAX_MESSAGE_SIZE {
-            error!("TcpThread Tx: {len} exceeds maximum message length and cannot be sent");
+        if let Err(err) = check_message_size(len, self.max_message_size) {
+            error!(
+                "TcpThread Tx: {} exceeds maximum message length of {} and cannot be sent",
+                err.length, err.max_message_size
+            );
             self.buffer_pool.reuse(message);
             return;
         }
+        let length = len as MessageLength;
 
         let length_bytes = length.to_le_bytes();
         if self.send(&length_bytes) {
@@ -154,19 +281,24 @@ impl TcpThread {
     }
 
     fn send(self: &mut Self, buf: &[u8]) -> bool {
-        let mut retry_count = 0;
+        let deadline = Instant::now() + self.timeouts.write;
+        let mut sent_count = 0;
         loop {
             #[cfg(debug_assertions)]
-            debug!("TcpThread Tx: Sending {buf:?}");
+            debug!("TcpThread Tx: Sending {:?}", &buf[sent_count..]);
 
-            match self.stream.write(buf) {
-                Ok(_) => {
-                    return true;
+            match self.stream.write(&buf[sent_count..]) {
+                Ok(byte_count) => {
+                    sent_count += byte_count;
+                    if sent_count == buf.len() {
+                        return true;
+                    }
                 }
                 Err(e) => match e.kind() {
                     ErrorKind::ConnectionReset
                     | ErrorKind::ConnectionAborted
-                    | ErrorKind::NotConnected => {
+                    | ErrorKind::NotConnected
+                    | ErrorKind::BrokenPipe => {
                         self.fatal("Tx stream closed by other party");
                         return false;
                     }
@@ -181,9 +313,8 @@ impl TcpThread {
                     _ => {}
                 },
             }
-            retry_count += 1;
-            if retry_count > MAX_TX_RETRY_COUNT {
-                self.fatal("Retry count exceeded sending message to TcpStream");
+            if Instant::now() > deadline {
+                self.fatal("Write timeout, the other party is not accepting data");
                 return false;
             } else {
                 thread::sleep(TX_RETRY_INTERVAL);
@@ -201,6 +332,8 @@ impl TcpThread {
                 #[cfg(debug_assertions)]
                 debug!("TcpThread Rx: Received {byte_count} bytes");
                 self.receive_buffer_count += byte_count;
+                self.last_receive_instant = Instant::now();
+                self.backoff.reset();
             }
             Err(err) => match err.kind() {
                 ErrorKind::ConnectionReset
@@ -221,21 +354,14 @@ impl TcpThread {
     }
 
     fn try_extract_received(self: &mut Self) {
-        loop {
-            let residual_byte_count = self.receive_buffer_count - self.consumed_count;
-            if residual_byte_count < MESSAGE_LENGTH_SIZE {
-                break;
-            }
-
+        while self.receive_buffer_count >= MESSAGE_LENGTH_SIZE {
             #[cfg(debug_assertions)]
             debug!(
-                "TcpThread Rx: residual_byte_count:{residual_byte_count}. consumed_count:{} ",
-                self.consumed_count
+                "TcpThread Rx: receive_buffer_count:{}",
+                self.receive_buffer_count
             );
 
-            let length_start_index = self.consumed_count;
-            let length_end_index = length_start_index + MESSAGE_LENGTH_SIZE;
-            let length_bytes = self.receive_buffer[length_start_index..length_end_index]
+            let length_bytes = self.receive_buffer[..MESSAGE_LENGTH_SIZE]
                 .try_into()
                 .unwrap();
             let message_length = MessageLength::from_le_bytes(length_bytes);
@@ -243,21 +369,31 @@ impl TcpThread {
             #[cfg(debug_assertions)]
             debug!("TcpThread Rx: Next message is {message_length} bytes");
 
+            // The rest of a message this long would never fit into the receive buffer
+            if message_length as usize > self.max_message_size {
+                self.fatal(&format!(
+                    "Rx message length {message_length} exceeds maximum message length of {}",
+                    self.max_message_size
+                ));
+                return;
+            }
+
             let entire_length = MESSAGE_LENGTH_SIZE + message_length as usize;
-            if residual_byte_count < entire_length {
+            if self.receive_buffer_count < entire_length {
                 break;
             }
 
-            let mut message = self.buffer_pool.get(message_length);
-            let message_start_index = self.consumed_count + MESSAGE_LENGTH_SIZE;
-            let message_end_index = message_start_index + (message_length as usize);
-            message.copy_from_slice(&self.receive_buffer[message_start_index..message_end_index]);
+            // The message is split off the receive buffer without copying it
+            self.receive_buffer.advance(MESSAGE_LENGTH_SIZE);
+            let message = self
+                .receive_buffer
+                .split_to(message_length as usize)
+                .freeze();
+            self.receive_buffer_count -= entire_length;
 
             #[cfg(debug_assertions)]
             debug!("TcpThread Rx: Extracted message {message:?}");
 
-            self.consumed_count += entire_length;
-
             match self.channel_tx.send(message) {
                 Ok(_) => {
                     #[cfg(debug_assertions)]
@@ -270,34 +406,230 @@ impl TcpThread {
             }
         }
 
-        let residual_byte_count = self.receive_buffer_count - self.consumed_count;
-        if residual_byte_count == 0 {
-            self.receive_buffer_count = 0;
-            self.consumed_count = 0;
-        } else {
-            let space_remaining = RECEIVE_BUFFER_SIZE - self.receive_buffer_count;
-            if space_remaining < MAX_MESSAGE_SIZE {
-                #[cfg(debug_assertions)]
-                debug!(
-                    "TcpThread Rx: Making room in buffer. consumed:{} space:{}",
-                    self.consumed_count, space_remaining
-                );
-                self.receive_buffer.copy_within(self.consumed_count.., 0);
-                self.receive_buffer_count -= self.consumed_count;
-                self.consumed_count = 0;
-            }
+        let space_remaining = self.receive_buffer.len() - self.receive_buffer_count;
+        if space_remaining < self.max_message_size + MESSAGE_LENGTH_SIZE {
+            self.replace_receive_buffer();
         }
     }
 
+    /// The messages that were split off the receive buffer still refer to it, so it can not
+    /// be compacted in place when it runs out of space. Any part of a message that straddles
+    /// the end of the buffer is copied into a new buffer instead, and the old buffer is freed
+    /// once all of the messages that were received into it have been dropped
+    fn replace_receive_buffer(self: &mut Self) {
+        #[cfg(debug_assertions)]
+        debug!(
+            "TcpThread Rx: Making room in buffer. residual:{} space:{}",
+            self.receive_buffer_count,
+            self.receive_buffer.len() - self.receive_buffer_count
+        );
+
+        let mut receive_buffer = BytesMut::zeroed(self.receive_buffer_size);
+        receive_buffer[..self.receive_buffer_count]
+            .copy_from_slice(&self.receive_buffer[..self.receive_buffer_count]);
+        self.receive_buffer = receive_buffer;
+    }
+
+    /// Closes the connection if part of a message was received and the rest of it did not
+    /// arrive within the read timeout
+    fn stop_if_stalled(self: &mut Self) {
+        if self.receive_buffer_count > 0 && self.last_receive_instant.elapsed() > self.timeouts.read
+        {
+            self.fatal("Read timeout, the other party stopped sending part way through a message");
+        }
+    }
+
+    /// Backs off when there was nothing to send or receive, so that the thread responds
+    /// quickly while messages are flowing, but uses little CPU when the connection is idle
     fn stop_if_idle(self: &mut Self) {
-        let idle_duration = self.last_message_instant.elapsed();
-        if idle_duration > IDLE_SLEEP_LIMIT {
-            if idle_duration > DISCONNECT_IDLE_TIME {
-                info!("TcpThread: Idle for too long, disconnecting");
-                self.stop();
-            } else {
-                thread::sleep(IDLE_SLEEP_DURATION);
+        if self.last_message_instant.elapsed() > DISCONNECT_IDLE_TIME {
+            info!("TcpThread: Idle for too long, disconnecting");
+            self.stop();
+        } else {
+            self.backoff.snooze();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        net::{TcpListener, TcpStream},
+        sync::mpsc::{channel, RecvTimeoutError},
+    };
+
+    const TEST_TIMEOUTS: TcpTimeouts = TcpTimeouts {
+        read: Duration::from_millis(200),
+        write: Duration::from_millis(200),
+    };
+
+    /// Returns a connected pair of streams, the first of which is non-blocking like the
+    /// streams that channels are created with
+    fn connected_streams() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (peer, _) = listener.accept().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        (stream, peer)
+    }
+
+    #[test]
+    fn should_disconnect_when_peer_stops_reading() {
+        let (stream, _peer) = connected_streams();
+        let (request_sender, request_receiver) = channel();
+        let (response_sender, response_receiver) = channel::<ReceivedMessage>();
+        let stop_signal = Arc::new(AtomicBool::new(false));
+        let _channel = TcpChannel::with_timeouts(
+            request_receiver,
+            response_sender,
+            stream,
+            &Arc::new(BufferPool::new()),
+            &stop_signal,
+            DEFAULT_MAX_MESSAGE_SIZE,
+            TEST_TIMEOUTS,
+        );
+
+        // The peer never reads, so the socket buffers fill up and writes stall
+        for _ in 0..1000 {
+            if request_sender.send(vec![0u8; 30000]).is_err() {
+                break;
             }
         }
+
+        assert_eq!(
+            response_receiver.recv_timeout(Duration::from_secs(10)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+        assert!(stop_signal.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn should_disconnect_when_peer_stops_sending_part_way_through_a_message() {
+        let (stream, mut peer) = connected_streams();
+        let (_request_sender, request_receiver) = channel();
+        let (response_sender, response_receiver) = channel::<ReceivedMessage>();
+        let stop_signal = Arc::new(AtomicBool::new(false));
+        let _channel = TcpChannel::with_timeouts(
+            request_receiver,
+            response_sender,
+            stream,
+            &Arc::new(BufferPool::new()),
+            &stop_signal,
+            DEFAULT_MAX_MESSAGE_SIZE,
+            TEST_TIMEOUTS,
+        );
+
+        // Send the length of a message and part of the message, then stall
+        let length: MessageLength = 100;
+        peer.write_all(&length.to_le_bytes()).unwrap();
+        peer.write_all(&[0u8; 10]).unwrap();
+
+        assert_eq!(
+            response_receiver.recv_timeout(Duration::from_secs(2)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+        assert!(stop_signal.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn should_receive_messages_up_to_the_configured_maximum_size() {
+        let (stream, mut peer) = connected_streams();
+        let (_request_sender, request_receiver) = channel();
+        let (response_sender, response_receiver) = channel::<ReceivedMessage>();
+        let stop_signal = Arc::new(AtomicBool::new(false));
+        let _channel = TcpChannel::with_timeouts(
+            request_receiver,
+            response_sender,
+            stream,
+            &Arc::new(BufferPool::new()),
+            &stop_signal,
+            60000,
+            TEST_TIMEOUTS,
+        );
+
+        let length: MessageLength = 50000;
+        peer.write_all(&length.to_le_bytes()).unwrap();
+        peer.write_all(&vec![7u8; length as usize]).unwrap();
+
+        let message = response_receiver
+            .recv_timeout(Duration::from_secs(2))
+            .unwrap();
+        assert_eq!(message.len(), length as usize);
+        assert!(message.iter().all(|&byte| byte == 7));
+    }
+
+    #[test]
+    fn should_receive_messages_that_straddle_the_end_of_the_receive_buffer() {
+        let (stream, mut peer) = connected_streams();
+        let (_request_sender, request_receiver) = channel();
+        let (response_sender, response_receiver) = channel::<ReceivedMessage>();
+        let stop_signal = Arc::new(AtomicBool::new(false));
+        let _channel = TcpChannel::with_timeouts(
+            request_receiver,
+            response_sender,
+            stream,
+            &Arc::new(BufferPool::new()),
+            &stop_signal,
+            1000,
+            TEST_TIMEOUTS,
+        );
+
+        // Messages of different lengths, so that some of them are only partly received when
+        // the receive buffer runs out of space
+        let lengths: Vec<usize> = (0..50).map(|index| 300 + index * 13).collect();
+        let mut data = Vec::new();
+        for (index, &length) in lengths.iter().enumerate() {
+            data.extend_from_slice(&(length as MessageLength).to_le_bytes());
+            data.extend(std::iter::repeat(index as u8).take(length));
+        }
+        peer.write_all(&data).unwrap();
+
+        // Messages are kept until the end, so that the buffers they were received into are
+        // still in use when later messages are received
+        let messages: Vec<ReceivedMessage> = lengths
+            .iter()
+            .map(|_| {
+                response_receiver
+                    .recv_timeout(Duration::from_secs(2))
+                    .unwrap()
+            })
+            .collect();
+        for (index, message) in messages.iter().enumerate() {
+            assert_eq!(message.len(), lengths[index]);
+            assert!(message.iter().all(|&byte| byte == index as u8));
+        }
+    }
+
+    #[test]
+    fn should_disconnect_when_peer_sends_a_message_that_is_too_large() {
+        let (stream, mut peer) = connected_streams();
+        let (_request_sender, request_receiver) = channel();
+        let (response_sender, response_receiver) = channel::<ReceivedMessage>();
+        let stop_signal = Arc::new(AtomicBool::new(false));
+        let _channel = TcpChannel::with_timeouts(
+            request_receiver,
+            response_sender,
+            stream,
+            &Arc::new(BufferPool::new()),
+            &stop_signal,
+            100,
+            TEST_TIMEOUTS,
+        );
+
+        let length: MessageLength = 200;
+        peer.write_all(&length.to_le_bytes()).unwrap();
+
+        assert_eq!(
+            response_receiver.recv_timeout(Duration::from_secs(2)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+        assert_eq!(
+            check_message_size(200, 100),
+            Err(MessageTooLarge {
+                length: 200,
+                max_message_size: 100
+            })
+        );
     }
 }
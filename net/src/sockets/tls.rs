@@ -0,0 +1,392 @@
+/*
+Optional TLS encryption of the connections between clients and the broker. Connections are
+plain TCP unless both ends are configured with a `ConnectionSecurity` that uses TLS. The TLS
+handshake is completed by the channel thread before any messages are sent or received, so
+it happens before the client negotiates the API version with the broker.
+
+The broker always presents a certificate. It can optionally require clients to present a
+certificate that is signed by a trusted certificate authority.
+*/
+
+use rustls::{
+    pki_types::{CertificateDer, PrivateKeyDer, ServerName},
+    server::{VerifierBuilderError, WebPkiClientVerifier},
+    ClientConfig, ClientConnection, RootCertStore, ServerConfig, ServerConnection, StreamOwned,
+};
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{self, BufReader, Read, Write},
+    net::TcpStream,
+    sync::Arc,
+};
+
+#[derive(Debug)]
+pub enum TlsError {
+    /// A certificate or key file could not be read
+    File {
+        path: String,
+        err: io::Error,
+    },
+
+    /// The key file does not contain a private key
+    NoPrivateKey {
+        path: String,
+    },
+
+    /// The name that the client expects in the broker certificate is not a valid DNS name or IP address
+    InvalidServerName {
+        server_name: String,
+    },
+
+    /// The certificates could not be used to verify client certificates
+    ClientVerifier(VerifierBuilderError),
+
+    Rustls(rustls::Error),
+}
+
+impl Display for TlsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TlsError::File { path, err } => write!(f, "Failed to read {path}. {err}"),
+            TlsError::NoPrivateKey { path } => write!(f, "There is no private key in {path}"),
+            TlsError::InvalidServerName { server_name } => {
+                write!(f, "{server_name} is not a valid server name")
+            }
+            TlsError::ClientVerifier(err) => {
+                write!(f, "Invalid client certificate authority. {err}")
+            }
+            TlsError::Rustls(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl From<rustls::Error> for TlsError {
+    fn from(err: rustls::Error) -> Self {
+        TlsError::Rustls(err)
+    }
+}
+
+/// Whether the connections between clients and the broker are encrypted. Both ends of a
+/// connection must agree, otherwise the connection is closed during the TLS handshake
+#[derive(Clone, Default)]
+#[cfg_attr(debug_assertions, derive(Debug))]
+pub enum ConnectionSecurity {
+    /// Messages are sent over the TCP connection without encryption
+    #[default]
+    Plain,
+
+    /// The broker encrypts connections that it accepts from clients
+    TlsServer(Arc<ServerConfig>),
+
+    /// The client encrypts its connection to the broker, and checks that the broker's
+    /// certificate is valid for the server name
+    TlsClient {
+        config: Arc<ClientConfig>,
+        server_name: ServerName<'static>,
+    },
+}
+
+impl ConnectionSecurity {
+    /// Encrypts connections accepted by the broker with the certificate chain and private key in
+    /// PEM files. When a certificate authority file is passed, clients must present a certificate
+    /// that is signed by one of the certificate authorities in it
+    pub fn tls_server(
+        cert_file: &str,
+        key_file: &str,
+        client_ca_file: Option<&str>,
+    ) -> Result<Self, TlsError> {
+        let builder = match client_ca_file {
+            Some(client_ca_file) => {
+                let verifier = WebPkiClientVerifier::builder(Arc::new(load_roots(client_ca_file)?))
+                    .build()
+                    .map_err(TlsError::ClientVerifier)?;
+                ServerConfig::builder().with_client_cert_verifier(verifier)
+            }
+            None => ServerConfig::builder().with_no_client_auth(),
+        };
+        let config = builder.with_single_cert(load_certs(cert_file)?, load_key(key_file)?)?;
+        Ok(Self::TlsServer(Arc::new(config)))
+    }
+
+    /// Encrypts the connection to the broker, trusting broker certificates that are signed by
+    /// one of the certificate authorities in a PEM file. The client presents its own certificate
+    /// chain and private key if the broker requires client certificates
+    pub fn tls_client(
+        ca_file: &str,
+        server_name: &str,
+        client_cert: Option<(&str, &str)>,
+    ) -> Result<Self, TlsError> {
+        let builder = ClientConfig::builder().with_root_certificates(load_roots(ca_file)?);
+        let config = match client_cert {
+            Some((cert_file, key_file)) => {
+                builder.with_client_auth_cert(load_certs(cert_file)?, load_key(key_file)?)?
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let server_name = ServerName::try_from(server_name.to_owned()).map_err(|_| {
+            TlsError::InvalidServerName {
+                server_name: server_name.to_owned(),
+            }
+        })?;
+        Ok(Self::TlsClient {
+            config: Arc::new(config),
+            server_name,
+        })
+    }
+
+    /// Wraps a connected stream so that it can be passed to a `TcpChannel`. No data is
+    /// exchanged until the channel performs the TLS handshake
+    pub fn wrap(self: &Self, stream: TcpStream) -> Result<ChannelStream, TlsError> {
+        Ok(match self {
+            ConnectionSecurity::Plain => ChannelStream::Plain(stream),
+            ConnectionSecurity::TlsServer(config) => ChannelStream::TlsServer(Box::new(
+                StreamOwned::new(ServerConnection::new(config.clone())?, stream),
+            )),
+            ConnectionSecurity::TlsClient {
+                config,
+                server_name,
+            } => ChannelStream::TlsClient(Box::new(StreamOwned::new(
+                ClientConnection::new(config.clone(), server_name.clone())?,
+                stream,
+            ))),
+        })
+    }
+}
+
+/// A TCP stream that is either plain or encrypted with TLS
+pub enum ChannelStream {
+    Plain(TcpStream),
+    TlsServer(Box<StreamOwned<ServerConnection, TcpStream>>),
+    TlsClient(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl ChannelStream {
+    /// The underlying TCP stream, for setting socket options
+    pub fn tcp_stream(self: &Self) -> &TcpStream {
+        match self {
+            ChannelStream::Plain(stream) => stream,
+            ChannelStream::TlsServer(stream) => &stream.sock,
+            ChannelStream::TlsClient(stream) => &stream.sock,
+        }
+    }
+
+    pub fn is_handshaking(self: &Self) -> bool {
+        match self {
+            ChannelStream::Plain(_) => false,
+            ChannelStream::TlsServer(stream) => stream.conn.is_handshaking(),
+            ChannelStream::TlsClient(stream) => stream.conn.is_handshaking(),
+        }
+    }
+
+    /// Exchanges TLS handshake messages with the peer. The stream is non-blocking, so this
+    /// returns `ErrorKind::WouldBlock` when it is waiting for the peer
+    pub fn handshake(self: &mut Self) -> io::Result<()> {
+        match self {
+            ChannelStream::Plain(_) => Ok((0, 0)),
+            ChannelStream::TlsServer(stream) => stream.conn.complete_io(&mut stream.sock),
+            ChannelStream::TlsClient(stream) => stream.conn.complete_io(&mut stream.sock),
+        }
+        .map(|_| ())
+    }
+}
+
+impl From<TcpStream> for ChannelStream {
+    fn from(stream: TcpStream) -> Self {
+        ChannelStream::Plain(stream)
+    }
+}
+
+impl Read for ChannelStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ChannelStream::Plain(stream) => stream.read(buf),
+            ChannelStream::TlsServer(stream) => stream.read(buf),
+            ChannelStream::TlsClient(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for ChannelStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ChannelStream::Plain(stream) => stream.write(buf),
+            ChannelStream::TlsServer(stream) => stream.write(buf),
+            ChannelStream::TlsClient(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ChannelStream::Plain(stream) => stream.flush(),
+            ChannelStream::TlsServer(stream) => stream.flush(),
+            ChannelStream::TlsClient(stream) => stream.flush(),
+        }
+    }
+}
+
+fn open(path: &str) -> Result<BufReader<File>, TlsError> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|err| TlsError::File {
+            path: path.to_owned(),
+            err,
+        })
+}
+
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    rustls_pemfile::certs(&mut open(path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| TlsError::File {
+            path: path.to_owned(),
+            err,
+        })
+}
+
+fn load_key(path: &str) -> Result<PrivateKeyDer<'static>, TlsError> {
+    rustls_pemfile::private_key(&mut open(path)?)
+        .map_err(|err| TlsError::File {
+            path: path.to_owned(),
+            err,
+        })?
+        .ok_or_else(|| TlsError::NoPrivateKey {
+            path: path.to_owned(),
+        })
+}
+
+fn load_roots(path: &str) -> Result<RootCertStore, TlsError> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots.add(cert)?;
+    }
+    Ok(roots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConnectionSecurity;
+    use crate::sockets::{
+        buffer_pool::BufferPool,
+        tcp_channel::{ReceivedMessage, TcpChannel, DEFAULT_MAX_MESSAGE_SIZE},
+    };
+    use rcgen::{BasicConstraints, CertificateParams, CertifiedIssuer, IsCa, KeyPair};
+    use std::{
+        env, fs,
+        net::{TcpListener, TcpStream},
+        path::PathBuf,
+        process,
+        sync::{atomic::AtomicBool, mpsc::channel, Arc},
+        time::Duration,
+    };
+
+    /// Writes a certificate authority, and broker and client certificates signed by it, to
+    /// PEM files in a temporary folder
+    fn write_certificates(test_name: &str) -> PathBuf {
+        let folder = env::temp_dir().join(format!("pulsar-tls-{test_name}-{}", process::id()));
+        fs::create_dir_all(&folder).unwrap();
+
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = CertifiedIssuer::self_signed(ca_params, KeyPair::generate().unwrap()).unwrap();
+        fs::write(folder.join("ca.pem"), ca.pem()).unwrap();
+
+        for name in ["broker", "client"] {
+            let params = CertificateParams::new(vec![String::from("localhost")]).unwrap();
+            let key = KeyPair::generate().unwrap();
+            let cert = params.signed_by(&key, &ca).unwrap();
+            fs::write(folder.join(format!("{name}.pem")), cert.pem()).unwrap();
+            fs::write(folder.join(format!("{name}.key")), key.serialize_pem()).unwrap();
+        }
+        folder
+    }
+
+    fn path(folder: &PathBuf, file_name: &str) -> String {
+        folder.join(file_name).to_str().unwrap().to_owned()
+    }
+
+    /// Connects a channel with the client security to a channel with the broker security,
+    /// and returns the message that the broker receives from the client
+    fn send_to_broker(
+        broker_security: &ConnectionSecurity,
+        client_security: &ConnectionSecurity,
+    ) -> Option<Vec<u8>> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client_stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (broker_stream, _) = listener.accept().unwrap();
+        client_stream.set_nonblocking(true).unwrap();
+        broker_stream.set_nonblocking(true).unwrap();
+
+        let buffer_pool = Arc::new(BufferPool::new());
+
+        let (_broker_sender, broker_receiver) = channel();
+        let (broker_response_sender, broker_response_receiver) = channel::<ReceivedMessage>();
+        let _broker_channel = TcpChannel::new(
+            broker_receiver,
+            broker_response_sender,
+            broker_security.wrap(broker_stream).unwrap(),
+            &buffer_pool,
+            &Arc::new(AtomicBool::new(false)),
+            DEFAULT_MAX_MESSAGE_SIZE,
+        );
+
+        let (client_sender, client_receiver) = channel();
+        let (client_response_sender, _client_response_receiver) = channel::<ReceivedMessage>();
+        let _client_channel = TcpChannel::new(
+            client_receiver,
+            client_response_sender,
+            client_security.wrap(client_stream).unwrap(),
+            &buffer_pool,
+            &Arc::new(AtomicBool::new(false)),
+            DEFAULT_MAX_MESSAGE_SIZE,
+        );
+
+        client_sender.send(vec![1u8, 2, 3, 4]).unwrap();
+        broker_response_receiver
+            .recv_timeout(Duration::from_secs(5))
+            .ok()
+            .map(|message| message.to_vec())
+    }
+
+    #[test]
+    fn should_exchange_messages_over_tls() {
+        let folder = write_certificates("exchange");
+        let broker_security = ConnectionSecurity::tls_server(
+            &path(&folder, "broker.pem"),
+            &path(&folder, "broker.key"),
+            Some(&path(&folder, "ca.pem")),
+        )
+        .unwrap();
+        let client_security = ConnectionSecurity::tls_client(
+            &path(&folder, "ca.pem"),
+            "localhost",
+            Some((&path(&folder, "client.pem"), &path(&folder, "client.key"))),
+        )
+        .unwrap();
+
+        assert_eq!(
+            send_to_broker(&broker_security, &client_security),
+            Some(vec![1u8, 2, 3, 4])
+        );
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn should_reject_clients_without_a_certificate_when_required() {
+        let folder = write_certificates("reject");
+        let broker_security = ConnectionSecurity::tls_server(
+            &path(&folder, "broker.pem"),
+            &path(&folder, "broker.key"),
+            Some(&path(&folder, "ca.pem")),
+        )
+        .unwrap();
+        let client_security =
+            ConnectionSecurity::tls_client(&path(&folder, "ca.pem"), "localhost", None).unwrap();
+
+        assert_eq!(send_to_broker(&broker_security, &client_security), None);
+        assert_eq!(
+            send_to_broker(&broker_security, &ConnectionSecurity::Plain),
+            None
+        );
+        fs::remove_dir_all(folder).unwrap();
+    }
+}
@@ -3,9 +3,9 @@ use pulsar_rust_client::{blocking::Client, BufferPool, SubscriptionId, TopicId};
 use std::thread::available_parallelism;
 use std::{
     collections::HashMap,
-    sync::{Arc, Barrier, RwLock},
+    sync::{Arc, Barrier, Mutex, RwLock},
     thread::{self},
-    time::Instant,
+    time::{Duration, Instant},
 };
 
 pub fn run_test() {
@@ -29,6 +29,7 @@ pub fn run_test() {
     let buffer_pool = Arc::new(BufferPool::new());
 
     let start = Arc::new(RwLock::new(Instant::now()));
+    let latencies = Arc::new(Mutex::new(Vec::with_capacity(concurrency * repeat_count)));
     let barrier = Arc::new(Barrier::new(concurrency));
 
     let mut threads: Vec<thread::JoinHandle<()>> = Vec::with_capacity(concurrency);
@@ -36,6 +37,7 @@ pub fn run_test() {
     for _ in 0..concurrency {
         let buffer_pool = buffer_pool.clone();
         let start = start.clone();
+        let latencies = latencies.clone();
         let barrier = Arc::clone(&barrier);
         threads.push(thread::spawn(move || {
             let mut client = Client::new(&buffer_pool, authority);
@@ -50,7 +52,10 @@ pub fn run_test() {
                 let topic_id: TopicId = 1;
                 let mut attributes = HashMap::new();
                 attributes.insert(String::from("order_number"), String::from("ABC123"));
-                match client.publish(topic_id, None, None, attributes) {
+                let request_start = Instant::now();
+                let result = client.publish(topic_id, None, None, attributes);
+                latencies.lock().unwrap().push(request_start.elapsed());
+                match result {
                     Ok(publish_result) => {
                         #[cfg(debug_assertions)]
                         println!("Published {:?}", publish_result.message_ref.message_id);
@@ -109,6 +114,20 @@ pub fn run_test() {
                 .to_string()
         )
     );
+    println!(
+        "P99 latency {} µs",
+        thousands(&percentile(&mut latencies.lock().unwrap(), 99).as_micros().to_string())
+    );
+}
+
+/// Returns the latency that the given percentage of requests completed within
+fn percentile(latencies: &mut [Duration], percent: usize) -> Duration {
+    if latencies.is_empty() {
+        return Duration::ZERO;
+    }
+    latencies.sort_unstable();
+    let index = (latencies.len() * percent).div_ceil(100).max(1) - 1;
+    latencies[index]
 }
 
 fn thousands(number: &str) -> String {